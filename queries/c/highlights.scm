@@ -0,0 +1,62 @@
+; Patterns that come first win when they capture the same node
+
+(function_declarator declarator: (identifier) @function)
+(call_expression function: (identifier) @function)
+(call_expression function: (field_expression field: (field_identifier) @function))
+(preproc_function_def name: (identifier) @function.macro)
+
+((identifier) @constant
+ (#match? @constant "^[A-Z][A-Z\\d_]*$"))
+
+(type_identifier) @type
+(primitive_type) @type.builtin
+(sized_type_specifier) @type.builtin
+(field_identifier) @property
+(statement_identifier) @label
+
+(comment) @comment
+
+(string_literal) @string
+(system_lib_string) @string
+(char_literal) @string
+(escape_sequence) @escape
+
+(number_literal) @number
+(null) @constant.builtin
+
+[
+  "#define"
+  "#elif"
+  "#else"
+  "#endif"
+  "#if"
+  "#ifdef"
+  "#ifndef"
+  "#include"
+  (preproc_directive)
+] @keyword
+
+[
+  "break"
+  "case"
+  "const"
+  "continue"
+  "default"
+  "do"
+  "else"
+  "enum"
+  "extern"
+  "for"
+  "goto"
+  "if"
+  "inline"
+  "return"
+  "sizeof"
+  "static"
+  "struct"
+  "switch"
+  "typedef"
+  "union"
+  "volatile"
+  "while"
+] @keyword
@@ -0,0 +1,76 @@
+; Patterns that come first win when they capture the same node
+
+(method_declaration name: (identifier) @function.method)
+(local_function_statement name: (identifier) @function)
+(invocation_expression function: (identifier) @function)
+(invocation_expression function: (member_access_expression name: (identifier) @function.method))
+(constructor_declaration name: (identifier) @constructor)
+
+(class_declaration name: (identifier) @type)
+(struct_declaration name: (identifier) @type)
+(interface_declaration name: (identifier) @type)
+(enum_declaration name: (identifier) @type)
+(attribute name: (identifier) @attribute)
+
+((identifier) @type
+ (#match? @type "^[A-Z]"))
+
+(predefined_type) @type.builtin
+
+(comment) @comment
+
+(string_literal) @string
+(verbatim_string_literal) @string
+(interpolated_string_expression) @string
+(character_literal) @string
+(escape_sequence) @escape
+
+(integer_literal) @number
+(real_literal) @number
+(boolean_literal) @constant.builtin
+(null_literal) @constant.builtin
+
+[
+  "abstract"
+  "as"
+  "async"
+  "await"
+  "base"
+  "break"
+  "case"
+  "catch"
+  "class"
+  "const"
+  "continue"
+  "default"
+  "do"
+  "else"
+  "enum"
+  "finally"
+  "for"
+  "foreach"
+  "if"
+  "in"
+  "interface"
+  "internal"
+  "is"
+  "namespace"
+  "new"
+  "override"
+  "private"
+  "protected"
+  "public"
+  "readonly"
+  "return"
+  "sealed"
+  "static"
+  "struct"
+  "switch"
+  "this"
+  "throw"
+  "try"
+  "typeof"
+  "using"
+  "virtual"
+  "while"
+] @keyword
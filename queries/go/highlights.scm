@@ -0,0 +1,54 @@
+; Patterns that come first win when they capture the same node
+
+(function_declaration name: (identifier) @function)
+(method_declaration name: (field_identifier) @function.method)
+(call_expression function: (identifier) @function)
+(call_expression function: (selector_expression field: (field_identifier) @function.method))
+
+(type_identifier) @type
+(field_identifier) @property
+(package_identifier) @namespace
+(label_name) @label
+
+(comment) @comment
+
+(interpreted_string_literal) @string
+(raw_string_literal) @string
+(rune_literal) @string
+(escape_sequence) @escape
+
+(int_literal) @number
+(float_literal) @number
+(imaginary_literal) @number
+(true) @constant.builtin
+(false) @constant.builtin
+(nil) @constant.builtin
+(iota) @constant.builtin
+
+[
+  "break"
+  "case"
+  "chan"
+  "const"
+  "continue"
+  "default"
+  "defer"
+  "else"
+  "fallthrough"
+  "for"
+  "func"
+  "go"
+  "goto"
+  "if"
+  "import"
+  "interface"
+  "map"
+  "package"
+  "range"
+  "return"
+  "select"
+  "struct"
+  "switch"
+  "type"
+  "var"
+] @keyword
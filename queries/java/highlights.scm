@@ -0,0 +1,81 @@
+; Patterns that come first win when they capture the same node
+
+(method_declaration name: (identifier) @function.method)
+(method_invocation name: (identifier) @function.method)
+(constructor_declaration name: (identifier) @constructor)
+
+(annotation name: (identifier) @attribute)
+(marker_annotation name: (identifier) @attribute)
+
+(class_declaration name: (identifier) @type)
+(interface_declaration name: (identifier) @type)
+(enum_declaration name: (identifier) @type)
+
+((identifier) @constant
+ (#match? @constant "^_*[A-Z][A-Z\\d_]+$"))
+((identifier) @type
+ (#match? @type "^[A-Z]"))
+
+(type_identifier) @type
+(boolean_type) @type.builtin
+(integral_type) @type.builtin
+(floating_point_type) @type.builtin
+(void_type) @type.builtin
+
+(this) @variable.builtin
+(super) @variable.builtin
+
+[(line_comment) (block_comment)] @comment
+
+(string_literal) @string
+(character_literal) @string
+
+(hex_integer_literal) @number
+(decimal_integer_literal) @number
+(octal_integer_literal) @number
+(binary_integer_literal) @number
+(decimal_floating_point_literal) @number
+(hex_floating_point_literal) @number
+(true) @constant.builtin
+(false) @constant.builtin
+(null_literal) @constant.builtin
+
+[
+  "abstract"
+  "assert"
+  "break"
+  "case"
+  "catch"
+  "class"
+  "continue"
+  "default"
+  "do"
+  "else"
+  "enum"
+  "extends"
+  "final"
+  "finally"
+  "for"
+  "if"
+  "implements"
+  "import"
+  "instanceof"
+  "interface"
+  "native"
+  "new"
+  "package"
+  "private"
+  "protected"
+  "public"
+  "return"
+  "static"
+  "strictfp"
+  "switch"
+  "synchronized"
+  "throw"
+  "throws"
+  "transient"
+  "try"
+  "volatile"
+  "while"
+] @keyword
@@ -0,0 +1,72 @@
+; Patterns that come first win when they capture the same node
+
+(function_declaration name: (identifier) @function)
+(function_expression name: (identifier) @function)
+(method_definition name: (property_identifier) @function.method)
+(call_expression function: (identifier) @function)
+(call_expression function: (member_expression property: (property_identifier) @function.method))
+
+((identifier) @constant
+ (#match? @constant "^[A-Z_][A-Z\\d_]+$"))
+((identifier) @constructor
+ (#match? @constructor "^[A-Z]"))
+
+(property_identifier) @property
+(this) @variable.builtin
+(super) @variable.builtin
+
+(comment) @comment
+
+(string) @string
+(template_string) @string
+(regex) @string
+(escape_sequence) @escape
+
+(number) @number
+(true) @constant.builtin
+(false) @constant.builtin
+(null) @constant.builtin
+(undefined) @constant.builtin
+
+[
+  "as"
+  "async"
+  "await"
+  "break"
+  "case"
+  "catch"
+  "class"
+  "const"
+  "continue"
+  "debugger"
+  "default"
+  "delete"
+  "do"
+  "else"
+  "export"
+  "extends"
+  "finally"
+  "for"
+  "from"
+  "function"
+  "get"
+  "if"
+  "import"
+  "in"
+  "instanceof"
+  "let"
+  "new"
+  "of"
+  "return"
+  "set"
+  "static"
+  "switch"
+  "throw"
+  "try"
+  "typeof"
+  "var"
+  "void"
+  "while"
+  "with"
+  "yield"
+] @keyword
@@ -0,0 +1,65 @@
+; Patterns that come first win when they capture the same node
+
+(decorator) @attribute
+(decorator (identifier) @attribute)
+
+(function_definition name: (identifier) @function)
+(call function: (attribute attribute: (identifier) @function.method))
+(call function: (identifier) @function)
+(class_definition name: (identifier) @type)
+
+((identifier) @constant
+ (#match? @constant "^[A-Z][A-Z\\d_]+$"))
+((identifier) @constructor
+ (#match? @constructor "^[A-Z]"))
+((identifier) @variable.builtin
+ (#match? @variable.builtin "^(self|cls)$"))
+
+(attribute attribute: (identifier) @property)
+(type (identifier) @type)
+
+(comment) @comment
+
+(string) @string
+(escape_sequence) @escape
+
+(integer) @number
+(float) @number
+(none) @constant.builtin
+(true) @constant.builtin
+(false) @constant.builtin
+
+[
+  "and"
+  "as"
+  "assert"
+  "async"
+  "await"
+  "break"
+  "class"
+  "continue"
+  "def"
+  "del"
+  "elif"
+  "else"
+  "except"
+  "finally"
+  "for"
+  "from"
+  "global"
+  "if"
+  "import"
+  "in"
+  "is"
+  "lambda"
+  "nonlocal"
+  "not"
+  "or"
+  "pass"
+  "raise"
+  "return"
+  "try"
+  "while"
+  "with"
+  "yield"
+] @keyword
@@ -0,0 +1,78 @@
+; Patterns that come first win when they capture the same node
+
+(function_item name: (identifier) @function)
+(function_signature_item name: (identifier) @function)
+(call_expression function: (identifier) @function)
+(call_expression function: (field_expression field: (field_identifier) @function.method))
+(call_expression function: (scoped_identifier name: (identifier) @function))
+(generic_function function: (identifier) @function)
+(macro_invocation macro: (identifier) @function.macro "!" @function.macro)
+(macro_definition name: (identifier) @function.macro)
+
+((identifier) @constant
+ (#match? @constant "^[A-Z][A-Z\\d_]+$"))
+((identifier) @constructor
+ (#match? @constructor "^[A-Z]"))
+
+(type_identifier) @type
+(primitive_type) @type.builtin
+(field_identifier) @property
+(lifetime (identifier) @label)
+(lifetime "'" @label)
+
+(line_comment) @comment
+(block_comment) @comment
+
+(string_literal) @string
+(raw_string_literal) @string
+(char_literal) @string
+(escape_sequence) @escape
+
+(integer_literal) @number
+(float_literal) @number
+(boolean_literal) @constant.builtin
+
+(attribute_item) @attribute
+(inner_attribute_item) @attribute
+
+(self) @variable.builtin
+(crate) @keyword
+(super) @keyword
+(mutable_specifier) @keyword
+
+[
+  "as"
+  "async"
+  "await"
+  "break"
+  "const"
+  "continue"
+  "default"
+  "dyn"
+  "else"
+  "enum"
+  "extern"
+  "fn"
+  "for"
+  "if"
+  "impl"
+  "in"
+  "let"
+  "loop"
+  "macro_rules!"
+  "match"
+  "mod"
+  "move"
+  "pub"
+  "ref"
+  "return"
+  "static"
+  "struct"
+  "trait"
+  "type"
+  "union"
+  "unsafe"
+  "use"
+  "where"
+  "while"
+] @keyword
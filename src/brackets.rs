@@ -0,0 +1,69 @@
+use crate::buffer::Buffer;
+
+
+/// The brackets that get matched, as (open, close).
+const PAIRS: [(char, char); 3] = [('(', ')'), ('[', ']'), ('{', '}')];
+
+
+pub fn is_bracket(c: char) -> bool {
+    PAIRS.iter().any(|(open, close)| *open == c || *close == c)
+}
+
+fn row_chars(buffer: &Buffer, row: usize) -> Option<Vec<char>> {
+    let line = buffer.get_row(row, 0, usize::MAX)?.to_string();
+    Some(line.trim_end_matches(['\n', '\r']).chars().collect())
+}
+
+/// The column of the first bracket at or after the cursor on its line, which is where `%` starts from.
+pub fn bracket_from(buffer: &Buffer, (x, y): (usize, usize)) -> Option<usize> {
+    let line = row_chars(buffer, y)?;
+    line.iter().skip(x).position(|c| is_bracket(*c)).map(|offset| x + offset)
+}
+
+/// Finds the bracket that matches the one at a (column, row) position by counting the brackets in between.
+/// This knows nothing about strings or comments so it is only used when there is no syntax tree.
+pub fn find_match(buffer: &Buffer, (x, y): (usize, usize)) -> Option<(usize, usize)> {
+    let mut chars = row_chars(buffer, y)?;
+    let c = *chars.get(x)?;
+
+    if let Some((open, close)) = PAIRS.iter().find(|(open, _)| *open == c) {
+        let mut depth = 0;
+        let (mut row, mut start) = (y, x);
+        loop {
+            for (col, c) in chars.iter().enumerate().skip(start) {
+                if c == open {
+                    depth += 1;
+                }
+                else if c == close {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Some((col, row));
+                    }
+                }
+            }
+            row += 1;
+            start = 0;
+            chars = row_chars(buffer, row)?;
+        }
+    }
+
+    let (open, close) = PAIRS.iter().find(|(_, close)| *close == c)?;
+    let mut depth = 0;
+    let (mut row, mut end) = (y, x + 1);
+    loop {
+        for (col, c) in chars[..end].iter().enumerate().rev() {
+            if c == close {
+                depth += 1;
+            }
+            else if c == open {
+                depth -= 1;
+                if depth == 0 {
+                    return Some((col, row));
+                }
+            }
+        }
+        row = row.checked_sub(1)?;
+        chars = row_chars(buffer, row)?;
+        end = chars.len();
+    }
+}
@@ -2,16 +2,38 @@ use std::{cmp, cell::RefCell, rc::Rc};
 
 use crop::{Rope, RopeSlice};
 
-use crate::settings::Settings;
+use crate::{settings::Settings, undo::UndoStep};
 
 
 
 
 
+/// The part of the text that changed between two versions of the buffer.
+/// Positions are (column, row) and the ends are exclusive.
+/// The old end is in the version before and the new end in the version after.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Change {
+    pub start_byte: usize,
+    pub old_end_byte: usize,
+    pub new_end_byte: usize,
+    pub start: (usize, usize),
+    pub old_end: (usize, usize),
+    pub new_end: (usize, usize),
+}
+
+/// The text of a pane along with its undo history.
+///
+/// Every version of the text is kept and undo steps back to the one before.
+/// The plain edits like `insert` and `delete` each make a new version, so each one is its own undo step.
+/// The `_current` edits change the latest version in place and only become an undo step at the next `add_new_rope`.
+/// While a group is open every edit goes into the same version, which is how a whole insert session is undone at once.
 pub struct Buffer {
     current: usize,
     buffers: Vec<Rope>,
     settings: Rc<RefCell<Settings>>,
+    grouping: bool,
+    /// Counts every edit so that callers can tell whether something changed the text.
+    edits: usize,
 }
 
 
@@ -21,6 +43,8 @@ impl Buffer {
             current: 0,
             buffers: vec![Rope::new()],
             settings,
+            grouping: false,
+            edits: 0,
         }
     }
 
@@ -28,16 +52,73 @@ impl Buffer {
         self.settings = settings;
     }
 
-    pub fn undo(&mut self) {
-        if self.current > 0 {
-            self.current -= 1;
+    /// Goes back to the version before the last change.
+    /// Returns what the undo changed so the cursor and syntax tree can follow it.
+    pub fn undo(&mut self) -> Option<Change> {
+        if self.current == 0 {
+            return None;
         }
+        self.current -= 1;
+
+        Some(change_between(&self.buffers[self.current + 1], &self.buffers[self.current]))
     }
 
-    pub fn redo(&mut self) {
-        if self.current < self.buffers.len() - 1 {
-            self.current += 1;
+    /// Goes forward to the version after the change that was undone.
+    /// Returns what the redo changed so the cursor and syntax tree can follow it.
+    pub fn redo(&mut self) -> Option<Change> {
+        if self.current + 1 >= self.buffers.len() {
+            return None;
+        }
+        self.current += 1;
+
+        Some(change_between(&self.buffers[self.current - 1], &self.buffers[self.current]))
+    }
+
+    /// The changes between every version as steps along with how many of them lead to the current text.
+    pub fn history(&self) -> (Vec<UndoStep>, usize) {
+        let steps = self.buffers.windows(2).map(|versions| {
+            let change = change_between(&versions[0], &versions[1]);
+            UndoStep {
+                start: change.start_byte,
+                removed: versions[0].byte_slice(change.start_byte..change.old_end_byte).to_string(),
+                inserted: versions[1].byte_slice(change.start_byte..change.new_end_byte).to_string(),
+            }
+        }).collect();
+
+        (steps, self.current)
+    }
+
+    /// Rebuilds the versions around the current text from the steps given by `history`.
+    /// The history is left alone and false is returned if the steps don't fit the text.
+    pub fn restore_history(&mut self, steps: &[UndoStep], current: usize) -> bool {
+        if current > steps.len() {
+            return false;
+        }
+        let text = self.buffers[self.current].clone();
+
+        let mut buffers = Vec::with_capacity(steps.len() + 1);
+        let mut rope = text.clone();
+        for step in steps[..current].iter().rev() {
+            if !replace_step(&mut rope, step.start, &step.inserted, &step.removed) {
+                return false;
+            }
+            buffers.push(rope.clone());
         }
+        buffers.reverse();
+        buffers.push(text.clone());
+
+        let mut rope = text;
+        for step in &steps[current..] {
+            if !replace_step(&mut rope, step.start, &step.removed, &step.inserted) {
+                return false;
+            }
+            buffers.push(rope.clone());
+        }
+
+        self.buffers = buffers;
+        self.current = current;
+        self.grouping = false;
+        true
     }
 
     pub fn line_len(&self, row: usize) -> Option<usize> {
@@ -107,7 +188,52 @@ impl Buffer {
         Some(line_byte + col_byte)
     }
 
+    /// The (column, row) of a byte offset, clamped to the end of the text.
+    pub fn position_of_byte(&self, byte: usize) -> (usize, usize) {
+        position_of_byte(&self.buffers[self.current], byte)
+    }
+
+    /// Starts an undo step that every edit goes into until `end_group` is called.
+    pub fn begin_group(&mut self) {
+        self.add_new_rope();
+        self.grouping = true;
+    }
+
+    /// Ends the undo step started by `begin_group`.
+    /// A group that made no edits is dropped so it doesn't take an undo to get past.
+    pub fn end_group(&mut self) {
+        if !self.grouping {
+            return;
+        }
+        self.grouping = false;
+
+        if self.current > 0 && self.current + 1 == self.buffers.len() && self.buffers[self.current - 1] == self.buffers[self.current] {
+            self.buffers.pop();
+            self.current -= 1;
+        }
+    }
+
+    /// How many edits have been made, undo and redo don't count.
+    pub fn edit_count(&self) -> usize {
+        self.edits
+    }
+
+    /// Changes whenever the text does, unlike `edit_count` this includes undo and redo.
+    pub fn revision(&self) -> (usize, usize) {
+        (self.edits, self.current)
+    }
+
+    /// The text as it is now, which is cheap to copy since ropes share their chunks.
+    pub fn rope(&self) -> Rope {
+        self.buffers[self.current].clone()
+    }
+
     fn get_new_rope(&mut self) -> &mut Rope {
+        self.edits += 1;
+        if self.grouping {
+            return &mut self.buffers[self.current];
+        }
+
         let buffer = self.buffers[self.current].clone();
         if self.current < self.buffers.len() - 1 {
             self.buffers.truncate(self.current + 1);
@@ -132,14 +258,17 @@ impl Buffer {
     }
 
     pub fn insert_current<T>(&mut self, byte_offset: usize, text: T) where T: AsRef<str> {
+        self.edits += 1;
         self.buffers[self.current].insert(byte_offset, text.as_ref());
     }
 
     pub fn delete_current<R>(&mut self, range: R) where R: std::ops::RangeBounds<usize> {
+        self.edits += 1;
         self.buffers[self.current].delete(range);
     }
 
     pub fn replace_current<R, T>(&mut self, range: R, text: T) where R: std::ops::RangeBounds<usize>, T: AsRef<str> {
+        self.edits += 1;
         self.buffers[self.current].replace(range, text.as_ref());
     }
 
@@ -212,6 +341,8 @@ impl From<&str> for Buffer {
             current: 0,
             buffers: vec![Rope::from(s)],
             settings: Rc::new(RefCell::new(Settings::default())),
+            grouping: false,
+            edits: 0,
         }
     }
 }
@@ -222,6 +353,8 @@ impl From<String> for Buffer {
             current: 0,
             buffers: vec![Rope::from(s)],
             settings: Rc::new(RefCell::new(Settings::default())),
+            grouping: false,
+            edits: 0,
         }
     }
 }
@@ -232,6 +365,173 @@ impl From<&String> for Buffer {
             current: 0,
             buffers: vec![Rope::from(s.as_str())],
             settings: Rc::new(RefCell::new(Settings::default())),
+            grouping: false,
+            edits: 0,
         }
     }
 }
+
+
+/// Finds the part of the text that differs between `old` and `new`.
+/// Everything before and after the change is the same in both versions.
+fn change_between(old: &Rope, new: &Rope) -> Change {
+    let mut start = old.bytes().zip(new.bytes()).take_while(|(a, b)| a == b).count();
+    while start > 0 && !new.is_char_boundary(start) {
+        start -= 1;
+    }
+
+    // The end can't go before the start when the text around the change repeats
+    let room = cmp::min(old.byte_len(), new.byte_len()) - start;
+    let mut same_end = old.bytes().rev().zip(new.bytes().rev()).take(room).take_while(|(a, b)| a == b).count();
+    while same_end > 0 && !new.is_char_boundary(new.byte_len() - same_end) {
+        same_end -= 1;
+    }
+    let old_end = old.byte_len() - same_end;
+    let new_end = new.byte_len() - same_end;
+
+    Change {
+        start_byte: start,
+        old_end_byte: old_end,
+        new_end_byte: new_end,
+        start: position_of_byte(new, start),
+        old_end: position_of_byte(old, old_end),
+        new_end: position_of_byte(new, new_end),
+    }
+}
+
+/// Replaces `old` at `start` with `new`.
+/// Nothing is changed when the text at `start` isn't `old`, like when an undo file is for some other text.
+fn replace_step(rope: &mut Rope, start: usize, old: &str, new: &str) -> bool {
+    let end = match start.checked_add(old.len()) {
+        Some(end) if end <= rope.byte_len() => end,
+        _ => return false,
+    };
+    if !rope.is_char_boundary(start) || !rope.is_char_boundary(end) || !rope.byte_slice(start..end).chars().eq(old.chars()) {
+        return false;
+    }
+
+    rope.replace(start..end, new);
+    true
+}
+
+/// Turns a byte offset into a (column, row) position.
+fn position_of_byte(rope: &Rope, byte: usize) -> (usize, usize) {
+    let byte = cmp::min(byte, rope.byte_len());
+    let row = rope.line_of_byte(byte);
+    let col = rope.byte_slice(rope.byte_of_line(row)..byte).chars().count();
+    (col, row)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_undo_insert_session() {
+        let mut buffer = Buffer::from("");
+
+        buffer.begin_group();
+        for (i, c) in "hello".chars().enumerate() {
+            buffer.insert_current(i, c.to_string());
+        }
+        buffer.end_group();
+
+        assert_eq!(buffer.to_string(), "hello");
+        buffer.undo();
+        assert_eq!(buffer.to_string(), "");
+    }
+
+    #[test]
+    fn test_undo_insert_session_with_backspace() {
+        let mut buffer = Buffer::from("");
+
+        buffer.begin_group();
+        buffer.insert_current(0, "hellp");
+        buffer.delete(4..5);
+        buffer.insert_current(4, "o");
+        buffer.end_group();
+
+        assert_eq!(buffer.to_string(), "hello");
+        buffer.undo();
+        assert_eq!(buffer.to_string(), "");
+        buffer.redo();
+        assert_eq!(buffer.to_string(), "hello");
+    }
+
+    #[test]
+    fn test_restore_history() {
+        let mut buffer = Buffer::from("one\ntwo");
+        buffer.insert(7, "\nthree");
+        buffer.delete(0..4);
+        buffer.replace(0..3, "TWO");
+        buffer.undo();
+
+        let (steps, current) = buffer.history();
+        assert_eq!(current, 2);
+        assert_eq!(steps[1], UndoStep { start: 0, removed: "one\n".to_string(), inserted: String::new() });
+
+        let mut restored = Buffer::from(buffer.to_string());
+        assert!(restored.restore_history(&steps, current));
+        assert_eq!(restored.to_string(), "two\nthree");
+        restored.redo();
+        assert_eq!(restored.to_string(), "TWO\nthree");
+        restored.undo();
+        restored.undo();
+        restored.undo();
+        assert_eq!(restored.to_string(), "one\ntwo");
+        assert!(restored.undo().is_none());
+
+        // Steps for some other text are ignored
+        let mut other = Buffer::from("something else");
+        assert!(!other.restore_history(&steps, current));
+        assert_eq!(other.to_string(), "something else");
+        assert!(other.undo().is_none());
+    }
+
+    #[test]
+    fn test_empty_group_is_dropped() {
+        let mut buffer = Buffer::from("");
+        buffer.insert(0, "hello");
+
+        buffer.begin_group();
+        buffer.end_group();
+        buffer.insert(5, " world");
+
+        buffer.undo();
+        assert_eq!(buffer.to_string(), "hello");
+        buffer.undo();
+        assert_eq!(buffer.to_string(), "");
+    }
+
+    #[test]
+    fn test_undo_returns_change_position() {
+        let mut buffer = Buffer::from("one\ntwo\nthree");
+
+        buffer.delete(5..6);
+        assert_eq!(buffer.undo().map(|change| change.start), Some((1, 1)));
+        assert_eq!(buffer.redo().map(|change| change.new_end), Some((1, 1)));
+
+        buffer.insert(7, "xx");
+        assert_eq!(buffer.to_string(), "one\nto\nxxthree");
+        let change = buffer.undo().unwrap();
+        assert_eq!((change.start_byte, change.old_end_byte, change.new_end_byte), (7, 9, 7));
+        assert_eq!((change.start, change.old_end, change.new_end), ((0, 2), (2, 2), (0, 2)));
+        assert_eq!(buffer.redo().map(|change| change.new_end), Some((2, 2)));
+
+        assert_eq!(buffer.redo(), None);
+    }
+
+    #[test]
+    fn test_undo_separate_deletes() {
+        let mut buffer = Buffer::from("hello");
+
+        buffer.delete(4..5);
+        buffer.delete(3..4);
+
+        assert_eq!(buffer.to_string(), "hel");
+        buffer.undo();
+        assert_eq!(buffer.to_string(), "hell");
+        buffer.undo();
+        assert_eq!(buffer.to_string(), "hello");
+    }
+}
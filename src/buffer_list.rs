@@ -0,0 +1,157 @@
+use std::{cell::RefCell, path::PathBuf, rc::Rc};
+
+use crate::pane::Pane;
+
+
+/// A file that was opened in a window, numbered in the order they were opened.
+pub struct BufferEntry {
+    pub number: usize,
+    pub pane: Rc<RefCell<dyn Pane>>,
+}
+
+impl BufferEntry {
+    pub fn path(&self) -> Option<PathBuf> {
+        self.pane.borrow().get_filename().clone()
+    }
+
+    pub fn name(&self) -> String {
+        match self.path() {
+            None => "[No Name]".to_string(),
+            Some(path) => path.to_string_lossy().to_string(),
+        }
+    }
+}
+
+/// The buffers of a window, which stay around after their pane is closed until they are deleted.
+/// The pane of a buffer keeps its own cursor, so going back to it puts you where you were.
+pub struct BufferList {
+    entries: Vec<BufferEntry>,
+    next_number: usize,
+    /// The buffer that was shown before the current one, for Ctrl-^.
+    alternate: Option<usize>,
+}
+
+impl Default for BufferList {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BufferList {
+    pub fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+            next_number: 1,
+            alternate: None,
+        }
+    }
+
+    /// Adds the pane of a file that was opened, giving back its number.
+    pub fn add(&mut self, pane: Rc<RefCell<dyn Pane>>) -> usize {
+        if let Some(number) = self.number_of(&pane) {
+            return number;
+        }
+
+        let number = self.next_number;
+        self.next_number += 1;
+        self.entries.push(BufferEntry { number, pane });
+        number
+    }
+
+    pub fn remove(&mut self, number: usize) -> Option<Rc<RefCell<dyn Pane>>> {
+        let index = self.entries.iter().position(|entry| entry.number == number)?;
+        if self.alternate == Some(number) {
+            self.alternate = None;
+        }
+        Some(self.entries.remove(index).pane)
+    }
+
+    pub fn get(&self, number: usize) -> Option<Rc<RefCell<dyn Pane>>> {
+        self.entries.iter()
+            .find(|entry| entry.number == number)
+            .map(|entry| entry.pane.clone())
+    }
+
+    pub fn number_of(&self, pane: &Rc<RefCell<dyn Pane>>) -> Option<usize> {
+        self.entries.iter()
+            .find(|entry| Rc::ptr_eq(&entry.pane, pane))
+            .map(|entry| entry.number)
+    }
+
+    pub fn entries(&self) -> &[BufferEntry] {
+        &self.entries
+    }
+
+    /// Remembers the pane that was switched away from, if it is a buffer.
+    pub fn set_alternate(&mut self, pane: &Rc<RefCell<dyn Pane>>) {
+        if let Some(number) = self.number_of(pane) {
+            self.alternate = Some(number);
+        }
+    }
+
+    pub fn alternate(&self) -> Option<usize> {
+        self.alternate
+    }
+
+    /// Finds a buffer by its number or by part of its name.
+    pub fn find(&self, query: &str) -> Result<usize, String> {
+        let names = self.entries.iter()
+            .map(|entry| (entry.number, entry.name()))
+            .collect::<Vec<_>>();
+        match_buffer(query, &names)
+    }
+}
+
+/// Picks the buffer a query means out of the numbers and names of the buffers.
+/// A number has to match exactly while a name only has to contain the query, as long as only one does.
+pub fn match_buffer(query: &str, buffers: &[(usize, String)]) -> Result<usize, String> {
+    let query = query.trim();
+    if query.is_empty() {
+        return Err("No buffer given".to_string());
+    }
+
+    if let Ok(number) = query.parse::<usize>() {
+        return match buffers.iter().find(|(n, _)| *n == number) {
+            Some((n, _)) => Ok(*n),
+            None => Err(format!("No buffer {}", number)),
+        };
+    }
+
+    // A whole name wins over the longer names that contain it
+    if let Some((n, _)) = buffers.iter().find(|(_, name)| name == query) {
+        return Ok(*n);
+    }
+
+    let found = buffers.iter()
+        .filter(|(_, name)| name.contains(query))
+        .collect::<Vec<_>>();
+    match found.as_slice() {
+        [] => Err(format!("No buffer matching {}", query)),
+        [(n, _)] => Ok(*n),
+        _ => Err(format!("More than one buffer matches {}", query)),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_match_buffer() {
+        let buffers = vec![
+            (1, "src/main.rs".to_string()),
+            (3, "src/window.rs".to_string()),
+            (4, "src/pane/window.rs".to_string()),
+        ];
+
+        assert_eq!(match_buffer("3", &buffers), Ok(3));
+        assert!(match_buffer("2", &buffers).is_err());
+        assert_eq!(match_buffer("main", &buffers), Ok(1));
+        assert_eq!(match_buffer("pane/win", &buffers), Ok(4));
+        assert_eq!(match_buffer("src/window.rs", &buffers), Ok(3));
+        assert!(match_buffer("window", &buffers).is_err());
+        assert!(match_buffer("lib", &buffers).is_err());
+        assert!(match_buffer(" ", &buffers).is_err());
+    }
+}
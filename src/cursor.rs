@@ -12,6 +12,13 @@ pub enum CursorMove {
     ToBottom,
 }
 
+/// Where `zz`, `zt` and `zb` put the line of the cursor.
+pub enum ScreenLine {
+    Center,
+    Top,
+    Bottom,
+}
+
 pub enum Direction {
     Up,
     Down,
@@ -29,6 +36,8 @@ pub enum Direction {
 pub struct Cursor {
     x: usize,
     y: usize,
+    /// The column the cursor goes back to when moving onto a line that is long enough.
+    want_x: usize,
     draw_x: usize,
     draw_y: usize,
     went_down: bool,
@@ -50,6 +59,7 @@ impl Cursor {
         Cursor {
             x: 0,
             y: 0,
+            want_x: 0,
             draw_x: 0,
             draw_y: 0,
             went_down: false,
@@ -141,13 +151,9 @@ impl Cursor {
         self.scrolled
     }
 
-    pub fn scroll(&mut self, pane: &PaneContainer) {
-
-        //eprintln!("PaneContainer: {:?}", pane.get_size());
-        
+    pub fn scroll(&mut self, pane: &dyn Pane, container: &PaneContainer) {
         self.jumped = false;
-        let (pane_x, pane_y) = pane.get_size();
-
+        let (pane_x, pane_y) = container.get_text_size();
 
         if self.went_right && pane_x != 0 && ((self.number_line_size + self.x) - self.col_offset) >= pane_x {
             self.col_offset = (self.number_line_size + self.x).saturating_sub(pane_x) + 1;
@@ -158,41 +164,46 @@ impl Cursor {
             self.scrolled = true;
         }
 
-        /*if self.x >= pane_x && self.went_right {
-            self.col_offset = self.x - pane_x + 1;
+        if pane_y == 0 {
+            return;
         }
-        else if self.x < self.col_offset && !self.went_right {
-            self.x = self.col_offset.saturating_sub(1);
-            self.col_offset = self.col_offset.saturating_sub(1);
-        }*/
-
-        //eprintln!("row offset: {}, y: {}, pane y: {}", self.row_offset, self.y, pane_y);
 
-        if self.went_down && pane_y != 0 && (self.y - self.row_offset) >= pane_y {
-            
-            let new_offset = self.y.saturating_sub(pane_y) + 1;
-            self.row_offset = new_offset;
+        // Keep the margin on both sides, except where the file starts or ends
+        let scroll_off = Self::scroll_off(pane_y, pane);
+        let top = self.y.saturating_sub(scroll_off);
+        let bottom = (self.y + scroll_off + 1).saturating_sub(pane_y)
+            .min(pane.get_line_count().saturating_sub(pane_y))
+            .max((self.y + 1).saturating_sub(pane_y));
 
+        if self.row_offset > top {
+            self.row_offset = top;
             self.scrolled = true;
-            //eprintln!("1row offset: {}, 1y: {}, 1pane y: {}", self.row_offset, self.y, pane_y);
         }
-        else if !self.went_down && (self.y.saturating_sub(self.row_offset)) == 0 {
-            self.row_offset = self.y;
+        else if self.row_offset < bottom {
+            self.row_offset = bottom;
             self.scrolled = true;
-            //eprintln!("2row offset: {}, 2y: {}, 2pane y: {}", self.row_offset, self.y, pane_y);
         }
-        /*else {
-            let new_offset = self.y - pane_y + 1;
-            self.row_offset = new_offset;
-            eprintln!("3row offset: {}, 3y: {}, 3pane y: {}", self.row_offset, self.y, pane_y);
-    }*/
+    }
 
-        /*let ((_, y), _) = pane.get_corners();
+    /// The lines to keep around the cursor, which can't be more than half of the rows.
+    fn scroll_off(rows: usize, pane: &dyn Pane) -> usize {
+        let scroll_off = pane.get_settings().borrow().editor_settings.scroll_off;
+        scroll_off.min(rows.saturating_sub(1) / 2)
+    }
 
-        if y > 0 {
-            self.row_offset += 1;
-        }*/
+    /// Scrolls so that the line of the cursor is at the given place in the rows.
+    /// The view stops at the start and end of the file.
+    pub fn place_line(&mut self, place: ScreenLine, rows: usize, pane: &dyn Pane) {
+        let scroll_off = Self::scroll_off(rows, pane);
+        let row_offset = match place {
+            ScreenLine::Top => self.y.saturating_sub(scroll_off),
+            ScreenLine::Center => self.y.saturating_sub(rows / 2),
+            ScreenLine::Bottom => (self.y + scroll_off + 1).saturating_sub(rows),
+        };
 
+        self.row_offset = row_offset.min(pane.get_line_count().saturating_sub(1));
+        self.scrolled = true;
+        self.moved = true;
     }
 
     pub fn set_cursor(&mut self, x: CursorMove, y: CursorMove, pane: &dyn Pane, (x_offset, y_offset): (usize, usize)) {
@@ -228,6 +239,7 @@ impl Cursor {
             },
             CursorMove::Nothing => {},
         }
+        self.want_x = self.x;
         match y {
             CursorMove::Amount(n) => {
                 self.y = n % (number_of_lines + 1);
@@ -257,6 +269,84 @@ impl Cursor {
         self.moved = true;
     }
 
+    /// Moves the cursor straight to a position in the buffer.
+    /// The position gets clamped to the buffer and the direction is recorded so that scrolling follows it.
+    pub fn jump_to(&mut self, x: usize, y: usize, pane: &dyn Pane) {
+        self.jumped = false;
+        let number_of_lines = pane.get_line_count();
+
+        let y = y.min(number_of_lines.saturating_sub(1));
+        let number_of_cols = pane.get_row_len(y).unwrap_or(0);
+        let x = x.min(number_of_cols);
+
+        self.went_down = y >= self.y;
+        self.went_right = x >= self.x;
+
+        self.x = x;
+        self.y = y;
+        self.want_x = self.x;
+        self.draw_x = self.x;
+        self.draw_y = self.y;
+
+        self.moved = true;
+    }
+
+    /// Puts the cursor back in the column it wants to be in after it moved to another line.
+    /// Without virtual editing the column gets clamped to the length of the line.
+    fn restore_column(&mut self, pane: &dyn Pane, virtual_edit: bool) {
+        let number_of_cols = pane.get_row_len(self.y).unwrap_or(0);
+
+        self.x = if virtual_edit {
+            self.want_x
+        }
+        else {
+            self.want_x.min(number_of_cols)
+        };
+        self.draw_x = self.x;
+    }
+
+    /// Scrolls the view by some rows and takes the cursor along so it stays in the same place on screen.
+    pub fn scroll_by(&mut self, delta: isize, number_of_lines: usize) {
+        let max = number_of_lines.saturating_sub(1);
+
+        let offset = (self.row_offset as isize + delta).clamp(0, max as isize) as usize;
+        let delta = offset as isize - self.row_offset as isize;
+
+        self.row_offset = offset;
+        self.y = ((self.y as isize + delta).max(0) as usize).min(max);
+        self.draw_y = self.y;
+
+        self.scrolled = true;
+        self.moved = true;
+    }
+
+    /// Scrolls the view by some rows, like the mouse wheel does.
+    /// The cursor stays where it is unless it would go off screen, then it moves to the nearest row outside of `scroll_off`.
+    pub fn scroll_view(&mut self, delta: isize, rows: usize, pane: &dyn Pane) {
+        let max = pane.get_line_count().saturating_sub(1);
+
+        self.row_offset = (self.row_offset as isize + delta).clamp(0, max as isize) as usize;
+
+        let scroll_off = Self::scroll_off(rows, pane);
+        let first = if self.row_offset == 0 { 0 } else { self.row_offset + scroll_off };
+        let last = if self.row_offset + rows > max {
+            max
+        }
+        else {
+            (self.row_offset + rows.saturating_sub(1)).saturating_sub(scroll_off).max(first)
+        };
+        let y = self.y.max(first).min(last);
+        if y != self.y {
+            let virtual_edit = pane.get_settings().borrow().editor_settings.virtualedit.allows(pane.selecting_block());
+            self.y = y;
+            self.draw_y = y;
+            self.restore_column(pane, virtual_edit);
+        }
+
+        self.scrolled = true;
+        self.moved = true;
+    }
+
     pub fn move_cursor(&mut self, direction: Direction, mut n: usize, pane: &dyn Pane) {
 
         //eprintln!("{:?}", self);
@@ -274,6 +364,8 @@ impl Cursor {
 
         let buffer = pane.borrow_buffer();
         let settings = pane.get_settings();
+        // Block mode only goes past the end of lines while selecting a block
+        let virtual_edit = settings.borrow().editor_settings.virtualedit.allows(pane.selecting_block());
 
         let number_of_cols = if let Some('\t') = buffer.get_nth_char(self.x) {
             n += settings.borrow().editor_settings.tab_size - 1;
@@ -308,6 +400,12 @@ impl Cursor {
                 self.went_right = false;
                 self.draw_x = self.x;
             },
+            Direction::Right if virtual_edit => {
+                self.x = self.x.saturating_add(n);
+                self.went_right = true;
+
+                self.draw_x = self.x;
+            },
             Direction::Right => {
                 if self.x < number_of_cols {
                     let new_x = (self.x + n) % (number_of_cols + 1);
@@ -369,6 +467,13 @@ impl Cursor {
             },
         }
 
+        match direction {
+            Direction::Left | Direction::Right | Direction::LineStart | Direction::LineEnd => {
+                self.want_x = self.x;
+            },
+            _ => self.restore_column(pane, virtual_edit),
+        }
+
         self.moved = true;
     }
 
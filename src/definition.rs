@@ -0,0 +1,98 @@
+
+/// Words that usually come right before the name of something being defined.
+const DEFINITION_KEYWORDS: [&str; 20] = [
+    "let", "mut", "fn", "def", "const", "static", "struct", "enum", "union", "type",
+    "trait", "mod", "class", "var", "function", "local", "macro_rules!", "alias", "export", "set",
+];
+
+
+pub fn is_identifier_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Returns the identifier under the given column and the column it starts at.
+pub fn word_at(line: &str, col: usize) -> Option<(usize, String)> {
+    let chars = line.chars().collect::<Vec<char>>();
+
+    if !chars.get(col).map(|c| is_identifier_char(*c)).unwrap_or(false) {
+        return None;
+    }
+
+    let start = chars[..col].iter().rev().take_while(|c| is_identifier_char(**c)).count();
+    let start = col - start;
+    let end = col + chars[col..].iter().take_while(|c| is_identifier_char(**c)).count();
+
+    Some((start, chars[start..end].iter().collect()))
+}
+
+/// Looks at the text around a name to guess if this is where it gets defined.
+/// We accept things like `let x`, `fn x`, `def x`, `x = ...` and `x: ...` at the start of a line.
+fn defines(chars: &[char], start: usize, end: usize) -> bool {
+    let before = chars[..start].iter().collect::<String>();
+    let after = chars[end..].iter().collect::<String>();
+    let after = after.trim_start();
+
+    let previous = before.trim_end()
+        .rsplit(|c: char| !is_identifier_char(c) && c != '!')
+        .next()
+        .unwrap_or("");
+
+    if DEFINITION_KEYWORDS.contains(&previous) {
+        return true;
+    }
+
+    if after.starts_with('=') && !after.starts_with("==") && !after.starts_with("=>") {
+        return true;
+    }
+
+    before.trim().is_empty() && after.starts_with(':') && !after.starts_with("::")
+}
+
+/// Finds every place in the text that looks like it defines the name.
+pub fn find_candidates(text: &str, name: &str) -> Vec<(usize, usize)> {
+    let name = name.chars().collect::<Vec<char>>();
+    let mut candidates = Vec::new();
+
+    if name.is_empty() {
+        return candidates;
+    }
+
+    for (row, line) in text.lines().enumerate() {
+        let chars = line.chars().collect::<Vec<char>>();
+        let mut col = 0;
+
+        while col + name.len() <= chars.len() {
+            let end = col + name.len();
+
+            let whole_word = (col == 0 || !is_identifier_char(chars[col - 1]))
+                && (end == chars.len() || !is_identifier_char(chars[end]));
+
+            if whole_word && chars[col..end] == name[..] && defines(&chars, col, end) {
+                candidates.push((col, row));
+                col = end;
+            }
+            else {
+                col += 1;
+            }
+        }
+    }
+
+    candidates
+}
+
+/// Picks where to jump to out of some candidates.
+/// When `from_top` is set we take the first one in the file like `gD`,
+/// otherwise we take the closest one before the cursor like `gd` and only look past the cursor if there is none.
+pub fn pick_candidate(mut candidates: Vec<(usize, usize)>, (x, y): (usize, usize), from_top: bool) -> Option<(usize, usize)> {
+    candidates.sort_by_key(|(x, y)| (*y, *x));
+
+    if from_top {
+        return candidates.first().copied();
+    }
+
+    candidates.iter()
+        .rev()
+        .find(|(cx, cy)| (*cy, *cx) <= (y, x))
+        .or(candidates.first())
+        .copied()
+}
@@ -0,0 +1,440 @@
+use std::{fs, io::{self, Write}, path::{Path, PathBuf}, process, time::{Duration, Instant, SystemTime}};
+
+use crate::settings::Settings;
+
+
+/// How often a pane looks at its file to see if another program changed it.
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How many unchanged lines are shown around the changed ones in a diff.
+const DIFF_CONTEXT: usize = 3;
+
+/// Past this many lines times lines, the changed part of a diff isn't matched up line by line.
+const MAX_DIFF_CELLS: usize = 4_000_000;
+
+
+/// The modification time and size of a file, which change when anything writes to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileStamp {
+    modified: Option<SystemTime>,
+    len: u64,
+}
+
+impl FileStamp {
+    /// `None` when the file doesn't exist or can't be looked at.
+    pub fn read(path: &Path) -> Option<Self> {
+        let metadata = fs::metadata(path).ok()?;
+        Some(Self {
+            modified: metadata.modified().ok(),
+            len: metadata.len(),
+        })
+    }
+}
+
+/// Remembers what a file looked like when we last read or wrote it.
+pub struct DiskWatch {
+    stamp: Option<FileStamp>,
+    /// The last change that was reported, so each change is only reported once.
+    reported: Option<FileStamp>,
+    last_check: Option<Instant>,
+}
+
+impl Default for DiskWatch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DiskWatch {
+    pub fn new() -> Self {
+        Self {
+            stamp: None,
+            reported: None,
+            last_check: None,
+        }
+    }
+
+    /// Call after reading or writing the file.
+    pub fn update(&mut self, path: &Path) {
+        self.stamp = FileStamp::read(path);
+        self.reported = None;
+    }
+
+    /// The stamp of the file if something else wrote to it since we last did.
+    /// A file that was deleted doesn't count, since writing it again loses nothing.
+    fn current_change(&self, path: &Path) -> Option<FileStamp> {
+        match (self.stamp, FileStamp::read(path)) {
+            (Some(stamp), Some(now)) if stamp != now => Some(now),
+            _ => None,
+        }
+    }
+
+    /// Whether the file changed on disk since we last read or wrote it.
+    pub fn changed(&self, path: &Path) -> bool {
+        self.current_change(path).is_some()
+    }
+
+    /// Looks at the file at most once every `CHECK_INTERVAL`.
+    /// Returns true the first time a change to the file is seen.
+    pub fn poll(&mut self, path: &Path) -> bool {
+        if self.last_check.is_some_and(|last| last.elapsed() < CHECK_INTERVAL) {
+            return false;
+        }
+        self.last_check = Some(Instant::now());
+
+        match self.current_change(path) {
+            Some(now) if self.reported != Some(now) => {
+                self.reported = Some(now);
+                true
+            },
+            _ => false,
+        }
+    }
+}
+
+/// How a file gets written.
+pub struct SaveOptions {
+    /// Whether to keep what the file had before in a copy with a `~` after its name.
+    pub backup: bool,
+    /// Where the copies go, next to the file when `None`.
+    pub backup_dir: Option<PathBuf>,
+    /// Whether to write over a read only file and go on when the copy can't be made, like `:w!` does.
+    pub force: bool,
+}
+
+impl SaveOptions {
+    pub fn new(settings: &Settings, force: bool) -> Self {
+        Self {
+            backup: settings.editor_settings.backup,
+            backup_dir: settings.backup_dir.clone(),
+            force,
+        }
+    }
+}
+
+/// Writes a file so that a crash can't leave it half written.
+/// The text goes to a file next to it first, which then takes its place with the permissions of the old one.
+/// When that can't be done, like when the directory can't be written to, the file is written directly.
+/// Returns a warning when something didn't go as planned but the file was still saved.
+pub fn save_file(path: &Path, bytes: &[u8], options: &SaveOptions) -> io::Result<Option<String>> {
+    // Saving through a link writes the file it points to instead of replacing the link
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let permissions = fs::metadata(&path).ok().map(|metadata| metadata.permissions());
+
+    if permissions.as_ref().is_some_and(|permissions| permissions.readonly()) && !options.force {
+        return Err(io::Error::new(io::ErrorKind::PermissionDenied, "The file is read only (add ! to override)"));
+    }
+
+    if options.backup && permissions.is_some() {
+        if let Err(error) = write_backup(&path, options.backup_dir.as_deref()) {
+            if !options.force {
+                return Err(io::Error::new(error.kind(), format!("Failed to write the backup file (add ! to override): {}", error)));
+            }
+        }
+    }
+
+    let error = match replace_file(&path, bytes, permissions.as_ref()) {
+        Ok(()) => return Ok(None),
+        Err(error) => error,
+    };
+
+    match &permissions {
+        // The owner has to be able to write to a read only file for a moment
+        Some(permissions) if permissions.readonly() => {
+            fs::set_permissions(&path, writable(permissions))?;
+            let written = write_directly(&path, bytes);
+            fs::set_permissions(&path, permissions.clone())?;
+            written?;
+        },
+        _ => write_directly(&path, bytes)?,
+    }
+    Ok(Some(format!("Wrote the file in place since it couldn't be replaced: {}", error)))
+}
+
+/// Writes to a file in the same directory and moves it over the file.
+fn replace_file(path: &Path, bytes: &[u8], permissions: Option<&fs::Permissions>) -> io::Result<()> {
+    let name = path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+    let temp = path.with_file_name(format!(".{}.{}.tmp", name, process::id()));
+
+    let result = write_directly(&temp, bytes)
+        .and_then(|_| match permissions {
+            None => Ok(()),
+            Some(permissions) => fs::set_permissions(&temp, permissions.clone()),
+        })
+        .and_then(|_| fs::rename(&temp, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
+
+fn write_directly(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let mut file = fs::File::create(path)?;
+    file.write_all(bytes)?;
+    file.sync_all()
+}
+
+/// Copies what the file has now to `name~`, in the backup directory if there is one.
+fn write_backup(path: &Path, backup_dir: Option<&Path>) -> io::Result<()> {
+    let name = match path.file_name() {
+        None => return Ok(()),
+        Some(name) => format!("{}~", name.to_string_lossy()),
+    };
+    let backup = match backup_dir {
+        None => path.with_file_name(name),
+        Some(dir) => {
+            fs::create_dir_all(dir)?;
+            dir.join(name)
+        },
+    };
+    // An old copy of a read only file would be read only too
+    let _ = fs::remove_file(&backup);
+    fs::copy(path, backup).map(|_| ())
+}
+
+/// The permissions with the owner allowed to write.
+fn writable(permissions: &fs::Permissions) -> fs::Permissions {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::Permissions::from_mode(permissions.mode() | 0o200)
+    }
+    #[cfg(not(unix))]
+    {
+        let mut permissions = permissions.clone();
+        permissions.set_readonly(false);
+        permissions
+    }
+}
+
+/// Keeps track of when the unsaved changes of a buffer were last added to, for auto-save.
+pub struct EditTimer {
+    revision: (usize, usize),
+    edited: Option<Instant>,
+}
+
+impl Default for EditTimer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EditTimer {
+    pub fn new() -> Self {
+        Self {
+            revision: (0, 0),
+            edited: None,
+        }
+    }
+
+    /// Call with the revision of the buffer and whether it has unsaved changes.
+    /// The time starts over whenever the revision moves.
+    pub fn update(&mut self, revision: (usize, usize), modified: bool, now: Instant) {
+        if !modified {
+            self.edited = None;
+        }
+        else if revision != self.revision {
+            self.edited = Some(now);
+        }
+        self.revision = revision;
+    }
+
+    /// When the buffer was last changed, `None` when there is nothing for auto-save to do.
+    pub fn edited(&self) -> Option<Instant> {
+        self.edited
+    }
+
+    /// Call when auto-save tried to save, so a save that failed isn't tried again until the next change.
+    pub fn saved(&mut self) {
+        self.edited = None;
+    }
+}
+
+/// The time of day as `14:32:07`.
+/// The standard library knows nothing about time zones, so this is in UTC.
+pub fn clock_time(time: SystemTime) -> String {
+    let seconds = time.duration_since(SystemTime::UNIX_EPOCH)
+        .map(|since| since.as_secs())
+        .unwrap_or(0) % (24 * 60 * 60);
+    format!("{:02}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+}
+
+/// Compares two texts line by line.
+/// Lines only in `old` start with `-`, lines only in `new` with `+` and the lines around them with a space.
+/// Runs of unchanged lines farther away from a change are left out and marked with `...`.
+pub fn diff_lines(old: &str, new: &str) -> Vec<String> {
+    let old = old.lines().collect::<Vec<_>>();
+    let new = new.lines().collect::<Vec<_>>();
+
+    let prefix = old.iter().zip(new.iter()).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..].iter().rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old_middle = &old[prefix..old.len() - suffix];
+    let new_middle = &new[prefix..new.len() - suffix];
+
+    let mut lines = old[..prefix].iter().map(|line| (' ', *line)).collect::<Vec<_>>();
+    if old_middle.len() * new_middle.len() > MAX_DIFF_CELLS {
+        lines.extend(old_middle.iter().map(|line| ('-', *line)));
+        lines.extend(new_middle.iter().map(|line| ('+', *line)));
+    }
+    else {
+        // The length of the longest common run of lines between the rest of each side
+        let (n, m) = (old_middle.len(), new_middle.len());
+        let mut common = vec![vec![0; m + 1]; n + 1];
+        for i in (0..n).rev() {
+            for j in (0..m).rev() {
+                common[i][j] = if old_middle[i] == new_middle[j] {
+                    common[i + 1][j + 1] + 1
+                }
+                else {
+                    common[i + 1][j].max(common[i][j + 1])
+                };
+            }
+        }
+
+        let (mut i, mut j) = (0, 0);
+        while i < n || j < m {
+            if i < n && j < m && old_middle[i] == new_middle[j] {
+                lines.push((' ', old_middle[i]));
+                i += 1;
+                j += 1;
+            }
+            else if i < n && (j == m || common[i + 1][j] >= common[i][j + 1]) {
+                lines.push(('-', old_middle[i]));
+                i += 1;
+            }
+            else {
+                lines.push(('+', new_middle[j]));
+                j += 1;
+            }
+        }
+    }
+    lines.extend(old[old.len() - suffix..].iter().map(|line| (' ', *line)));
+
+    let mut keep = vec![false; lines.len()];
+    for (index, (mark, _)) in lines.iter().enumerate() {
+        if *mark != ' ' {
+            let start = index.saturating_sub(DIFF_CONTEXT);
+            let end = (index + DIFF_CONTEXT + 1).min(lines.len());
+            keep[start..end].iter_mut().for_each(|keep| *keep = true);
+        }
+    }
+
+    let mut output = Vec::new();
+    for (index, (mark, line)) in lines.iter().enumerate() {
+        if keep[index] {
+            output.push(format!("{} {}", mark, line));
+        }
+        else if index == 0 || keep[index - 1] {
+            output.push("...".to_string());
+        }
+    }
+    output
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_lines() {
+        assert!(diff_lines("a\nb\n", "a\nb\n").iter().all(|line| line == "..."));
+
+        let old = "1\n2\n3\n4\n5\n6\n7\n8\n9\n";
+        let new = "1\n2\n3\n4\n5\nsix\n7\n8\n9\n";
+        assert_eq!(diff_lines(old, new), vec!["...", "  3", "  4", "  5", "- 6", "+ six", "  7", "  8", "  9"]);
+
+        assert_eq!(diff_lines("a\nc\n", "a\nb\nc\n"), vec!["  a", "+ b", "  c"]);
+        assert_eq!(diff_lines("a\nb\n", ""), vec!["- a", "- b"]);
+    }
+
+    #[test]
+    fn test_edit_timer() {
+        let start = Instant::now();
+        let later = start + Duration::from_secs(5);
+        let mut timer = EditTimer::new();
+
+        timer.update((0, 0), false, start);
+        assert_eq!(timer.edited(), None);
+        timer.update((1, 1), true, start);
+        assert_eq!(timer.edited(), Some(start));
+        timer.update((1, 1), true, later);
+        assert_eq!(timer.edited(), Some(start));
+        timer.update((2, 2), true, later);
+        assert_eq!(timer.edited(), Some(later));
+
+        timer.saved();
+        timer.update((2, 2), true, later);
+        assert_eq!(timer.edited(), None);
+        timer.update((3, 3), false, later);
+        assert_eq!(timer.edited(), None);
+    }
+
+    #[test]
+    fn test_clock_time() {
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(3 * 24 * 60 * 60 + 14 * 3600 + 32 * 60 + 7);
+        assert_eq!(clock_time(time), "14:32:07");
+        assert_eq!(clock_time(SystemTime::UNIX_EPOCH), "00:00:00");
+    }
+
+    #[test]
+    fn test_save_file() {
+        let dir = std::env::temp_dir().join("vi_save_file_test");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("file.txt");
+        let backup_dir = dir.join("backups");
+
+        let options = SaveOptions { backup: true, backup_dir: Some(backup_dir.clone()), force: false };
+        assert_eq!(save_file(&path, b"one", &options).unwrap(), None);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "one");
+        // There was nothing to back up yet
+        assert!(!backup_dir.join("file.txt~").exists());
+
+        assert_eq!(save_file(&path, b"two", &options).unwrap(), None);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "two");
+        assert_eq!(fs::read_to_string(backup_dir.join("file.txt~")).unwrap(), "one");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+
+        let mut permissions = fs::metadata(&path).unwrap().permissions();
+        permissions.set_readonly(true);
+        fs::set_permissions(&path, permissions).unwrap();
+
+        let options = SaveOptions { backup: false, backup_dir: None, force: false };
+        assert_eq!(save_file(&path, b"three", &options).unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "two");
+
+        let options = SaveOptions { force: true, ..options };
+        save_file(&path, b"three", &options).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "three");
+        assert!(fs::metadata(&path).unwrap().permissions().readonly());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_disk_watch() {
+        let path = std::env::temp_dir().join("vi_disk_watch_test");
+        fs::write(&path, "one").unwrap();
+
+        let mut watch = DiskWatch::new();
+        watch.update(&path);
+        assert!(!watch.changed(&path));
+        assert!(!watch.poll(&path));
+
+        // The size changes even when the modification time is too coarse to
+        fs::write(&path, "one two").unwrap();
+        assert!(watch.changed(&path));
+        watch.last_check = None;
+        assert!(watch.poll(&path));
+        watch.last_check = None;
+        assert!(!watch.poll(&path));
+
+        watch.update(&path);
+        assert!(!watch.changed(&path));
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,8 +1,8 @@
-use std::{io, sync::mpsc::{Receiver, Sender}, cell::RefCell, rc::Rc, thread};
+use std::{io, sync::mpsc::{Receiver, Sender}, cell::RefCell, rc::Rc, path::{Path, PathBuf}};
 
-use crossterm::{terminal, execute, cursor::{SetCursorStyle, MoveTo}};
+use crossterm::{terminal, execute, cursor::{SetCursorStyle, MoveTo}, event::{KeyEvent, EnableFocusChange, DisableFocusChange, DisableMouseCapture}};
 
-use crate::{window::{Window, Message}, pane::Pane, lsp::{ControllerMessage, LspController}, registers::{Registers, RegisterUtils}};
+use crate::{window::{Window, Message, MessageKind}, pane::Pane, settings::Settings, lsp::ControllerMessage, registers::{Registers, RegisterUtils, RegisterValue}, recent::RecentFiles, quickfix::{QuickFix, QuickFixEntry}};
 
 
 
@@ -12,18 +12,70 @@ pub enum EditorMessage {
     NewWindow(Option<Rc<RefCell<dyn Pane>>>),
     CloseWindow,
     Quit,
+    /// Quit, or ask what to do first when any window has unsaved changes.
+    QuitAll,
+    /// Save every file with unsaved changes in every window, then quit if they all saved.
+    SaveAllAndQuit,
     NthWindow(usize),
-    Paste(RegisterType),
-    Copy(RegisterType, String),
+    /// The bool is whether to paste after the cursor.
+    Paste(RegisterType, bool),
+    Copy(RegisterType, RegisterValue),
+    AddRecentFile(PathBuf),
+    RecentFiles,
+    /// Store the keys of a macro in a register.
+    SetMacro(char, Vec<KeyEvent>),
+    /// Send the keys of a macro back to the window to be played some number of times.
+    GetMacro(char, usize),
+    /// Move the cursor of the active pane in the active window.
+    SetCursorLocation((usize, usize)),
+    /// Remember a file that was closed and where its cursor was.
+    ClosedBuffer(PathBuf, (usize, usize)),
+    /// Open the most recently closed file again.
+    Reopen,
+    /// Search the files under the directory and fill the quickfix list with the matches.
+    Grep(String, PathBuf),
+    /// Stop the search that is filling the quickfix list.
+    CancelGrep,
+    NextQuickFix,
+    PreviousQuickFix,
+    /// Send the contents of every register to the window to be listed.
+    Registers,
+    /// A message from a language server to show in the active window.
+    LspMessage(String, MessageKind),
+    /// Read the config files again and use them in every window.
+    ReloadConfig,
+    /// Bind keys in a mode to a command in every window, or take the binding away without a command.
+    MapKeys(String, String, Option<String>),
 }
 
 #[derive(Clone, Debug)]
 pub enum RegisterType {
     Number(usize),
     Name(String),
+    /// The system clipboard, selected with `"+` or `"*`.
+    Clipboard,
     None,
 }
 
+impl RegisterType {
+    /// Turns a register name as typed by the user into a register.
+    pub fn from_name(name: &str) -> Self {
+        match name {
+            "+" | "*" => RegisterType::Clipboard,
+            name => match name.parse::<usize>() {
+                Ok(number) => RegisterType::Number(number),
+                Err(_) => RegisterType::Name(name.to_string()),
+            },
+        }
+    }
+}
+
+/// How many closed files we remember for reopening.
+const MAX_CLOSED_BUFFERS: usize = 20;
+
+/// The register that is used when no register is given.
+const UNNAMED_REGISTER: &str = "\"";
+
 pub struct Editor {
     windows: Vec<Window>,
     window_senders: Vec<Sender<Message>>,
@@ -34,6 +86,13 @@ pub struct Editor {
     lsp_responder: Sender<ControllerMessage>,
 
     registers: Registers,
+    recent_files: RecentFiles,
+    /// The files that were closed with the most recent last.
+    closed_buffers: Vec<(PathBuf, (usize, usize))>,
+    /// Shared with the panes that list the matches.
+    quickfix: Rc<RefCell<QuickFix>>,
+    /// The settings from the config files, which new windows start with.
+    settings: Settings,
 }
 
 
@@ -42,6 +101,7 @@ impl Editor {
         terminal::enable_raw_mode().expect("Failed to enable raw mode");
         execute!(std::io::stdout(), terminal::EnterAlternateScreen).expect("Failed to enter alternate screen");
         execute!(io::stdout(), SetCursorStyle::BlinkingBlock).expect("Could not set cursor style");
+        execute!(io::stdout(), EnableFocusChange).expect("Could not enable focus events");
 
 
         let (sender, reciever) = std::sync::mpsc::channel();
@@ -50,9 +110,12 @@ impl Editor {
         //eprintln!("Editor created");
         //let lsp_listener = Rc::new(lsp_controller_reciever);
 
-        let window = Window::new(sender.clone(), lsp_sender.clone(), lsp_listener.clone());
+        let (settings, errors) = Settings::load();
+        let mut window = Window::new(sender.clone(), lsp_sender.clone(), lsp_listener.clone());
+        window.apply_settings(settings.clone());
 
         let window_sender = window.get_sender();
+        report_config_errors(&window_sender, errors);
         
         Self {
             windows: vec![window],
@@ -63,11 +126,62 @@ impl Editor {
             lsp_listener,
             lsp_responder: lsp_sender,
             registers: Registers::new(),
+            recent_files: RecentFiles::new(),
+            closed_buffers: Vec::new(),
+            quickfix: Rc::new(RefCell::new(QuickFix::new())),
+            settings,
         }
     }
 
-    pub fn open_file(&mut self, path: &str) -> io::Result<()> {
-        self.windows[self.active_window].open_file_start(path)
+    /// Opens a file from the command line the same way `:e` does, in place of the empty pane the editor starts with.
+    pub fn open_file(&mut self, argument: &str) -> io::Result<()> {
+        let (path, position) = parse_file_argument(argument);
+
+        self.window_senders[self.active_window].send(Message::OpenFileStart(path, position)).expect("Failed to send file");
+        Ok(())
+    }
+
+    /// Adds the matches a running search found to the quickfix list.
+    fn check_quickfix(&mut self) {
+        if !self.quickfix.borrow().is_searching() || self.windows.is_empty() {
+            return;
+        }
+
+        let (finished, found) = {
+            let mut quickfix = self.quickfix.borrow_mut();
+            (quickfix.poll(), quickfix.len())
+        };
+
+        if finished {
+            let message = match found {
+                0 => "No matches found".to_string(),
+                1 => "1 match found".to_string(),
+                found => format!("{} matches found", found),
+            };
+            self.window_senders[self.active_window].send(Message::SetStatus(message)).expect("Failed to send status");
+        }
+    }
+
+    fn open_quickfix_entry(&self, entry: &QuickFixEntry) {
+        let sender = &self.window_senders[self.active_window];
+        let path = entry.path.to_string_lossy().to_string();
+        let status = {
+            let quickfix = self.quickfix.borrow();
+            format!("({} of {}) {}", quickfix.current_index() + 1, quickfix.len(), entry.text)
+        };
+
+        sender.send(Message::OpenFile(path, Some((entry.col, entry.line)))).expect("Failed to send file");
+        sender.send(Message::SetStatus(status)).expect("Failed to send status");
+    }
+
+    fn step_quickfix(&self, entry: Option<QuickFixEntry>) {
+        match entry {
+            Some(entry) => self.open_quickfix_entry(&entry),
+            None => {
+                let message = Message::SetStatus("No more matches".to_string());
+                self.window_senders[self.active_window].send(message).expect("Failed to send status");
+            },
+        }
     }
 
     fn check_messages(&mut self) -> io::Result<()> {
@@ -85,7 +199,10 @@ impl Editor {
                         Ok(())
                     },
                     EditorMessage::NewWindow(pane) => {
-                        self.windows.push(Window::new(self.sender.clone(), self.lsp_responder.clone(), self.lsp_listener.clone()));
+                        let mut window = Window::new(self.sender.clone(), self.lsp_responder.clone(), self.lsp_listener.clone());
+                        window.apply_settings(self.settings.clone());
+                        self.window_senders.push(window.get_sender());
+                        self.windows.push(window);
                         self.active_window = self.windows.len() - 1;
                         if let Some(pane) = pane {
                             self.windows[self.active_window].replace_pane(0, pane);
@@ -98,12 +215,41 @@ impl Editor {
                     EditorMessage::CloseWindow => {
                         //eprintln!("Close window");
                         self.windows.remove(self.active_window);
+                        self.window_senders.remove(self.active_window);
                         self.active_window = self.active_window.saturating_sub(1);
                         Ok(())
                    },
                     EditorMessage::Quit => {
                         //eprintln!("Quit");
                         self.windows.clear();
+                        self.window_senders.clear();
+                        Ok(())
+                    },
+                    EditorMessage::QuitAll => {
+                        let files = self.windows.iter()
+                            .flat_map(|window| window.modified_files())
+                            .collect::<Vec<_>>();
+                        if files.is_empty() {
+                            self.windows.clear();
+                            self.window_senders.clear();
+                            return Ok(());
+                        }
+
+                        self.window_senders[self.active_window].send(Message::ConfirmQuit(files)).expect("Failed to send message");
+                        Ok(())
+                    },
+                    EditorMessage::SaveAllAndQuit => {
+                        let failed = self.windows.iter_mut()
+                            .flat_map(|window| window.save_all())
+                            .collect::<Vec<_>>();
+                        if failed.is_empty() {
+                            self.windows.clear();
+                            self.window_senders.clear();
+                            return Ok(());
+                        }
+
+                        let message = format!("Failed to save {}", failed.join(", "));
+                        self.window_senders[self.active_window].send(Message::SetMessage(message, MessageKind::Error)).expect("Failed to send message");
                         Ok(())
                     },
                     EditorMessage::NthWindow(n) => {
@@ -113,66 +259,171 @@ impl Editor {
                         self.windows[self.active_window].force_refresh_screen()?;
                         Ok(())
                     },
-                    EditorMessage::Paste(ty) => {
-                        match ty {
-                            RegisterType::None => {
-
+                    EditorMessage::Paste(ty, after) => {
+                        let value = match ty {
+                            RegisterType::Clipboard => {
                                 eprintln!("Pasting from clipboard");
+                                self.registers.get_clipboard().map(RegisterValue::from_text)
+                            },
+                            RegisterType::None => self.registers.get(UNNAMED_REGISTER.to_string()).cloned(),
+                            RegisterType::Number(n) => self.registers.get(n).cloned(),
+                            RegisterType::Name(name) => self.registers.get(name.to_lowercase()).cloned(),
+                        };
 
-                                let text = self.registers.get_clipboard();
-
-                                eprintln!("{:?}", text);
-
-                                let response = text.and_then(|text| Some(text.into_boxed_str()));
-
-                                let response = Message::PasteResponse(response);
+                        let response = Message::PasteResponse(value, after);
 
-                                self.window_senders[self.active_window].send(response).expect("Failed to send paste response");
+                        self.window_senders[self.active_window].send(response).expect("Failed to send paste response");
 
+                        Ok(())
+                    },
+                    EditorMessage::Copy(ty, value) => {
+                        match ty {
+                            RegisterType::Clipboard => {
+                                self.registers.set_clipboard(value.text);
+                                Ok(())
+                            },
+                            RegisterType::None => {
+                                self.registers.set(UNNAMED_REGISTER.to_string(), value);
                                 Ok(())
                             },
                             RegisterType::Number(n) => {
-
-                                let text = self.registers.get(n);
-
-                                let response = text.and_then(|text| Some(text.clone().into_boxed_str()));
-
-                                let response = Message::PasteResponse(response);
-
-                                self.window_senders[self.active_window].send(response).expect("Failed to send paste response");
-
+                                self.registers.set(n, value);
                                 Ok(())
                             },
                             RegisterType::Name(name) => {
+                                // Like vim the unnamed register always has what was yanked last
+                                let value = self.registers.set_named(&name, value);
+                                self.registers.set(UNNAMED_REGISTER.to_string(), value);
+                                Ok(())
+                            },
+                        }
+                    },
+                    EditorMessage::ReloadConfig => {
+                        let (settings, errors) = Settings::load();
+                        for window in self.windows.iter_mut() {
+                            window.apply_settings(settings.clone());
+                        }
+                        self.settings = settings;
 
-                                let text = self.registers.get(name);
+                        let sender = &self.window_senders[self.active_window];
+                        if errors.is_empty() {
+                            sender.send(Message::SetStatus("Reloaded the config".to_string())).expect("Failed to send message");
+                        }
+                        report_config_errors(sender, errors);
+                        sender.send(Message::ForceRedraw).expect("Failed to send message");
+                        Ok(())
+                    },
+                    EditorMessage::MapKeys(mode, keys, command) => {
+                        // Every window has the same bindings, so the settings new windows get tell whether it works
+                        let sender = &self.window_senders[self.active_window];
+                        match self.settings.map_keys(&mode, &keys, command.as_deref()) {
+                            Ok(message) => {
+                                let mut message = Message::SetStatus(message);
+                                for window in self.windows.iter_mut() {
+                                    if let Err(error) = window.map_keys(&mode, &keys, command.as_deref()) {
+                                        message = Message::SetMessage(format!("Failed to map keys in a window: {}", error), MessageKind::Error);
+                                    }
+                                }
+                                sender.send(message).expect("Failed to send message");
+                            },
+                            Err(error) => {
+                                sender.send(Message::SetMessage(error, MessageKind::Error)).expect("Failed to send message");
+                            },
+                        }
+                        Ok(())
+                    },
+                    EditorMessage::Registers => {
+                        let registers = self.registers.list();
 
-                                let response = text.and_then(|text| Some(text.clone().into_boxed_str()));
+                        self.window_senders[self.active_window].send(Message::Registers(registers)).expect("Failed to send registers");
 
-                                let response = Message::PasteResponse(response);
+                        Ok(())
+                    },
+                    EditorMessage::AddRecentFile(path) => {
+                        if let Err(err) = self.recent_files.push(path) {
+                            let message = format!("Failed to save recent files: {}", err);
+                            self.window_senders[self.active_window].send(Message::SetMessage(message, MessageKind::Error)).expect("Failed to send message");
+                        }
+                        Ok(())
+                    },
+                    EditorMessage::RecentFiles => {
+                        let files = self.recent_files.get_files().to_vec();
 
-                                self.window_senders[self.active_window].send(response).expect("Failed to send paste response");
+                        self.window_senders[self.active_window].send(Message::RecentFiles(files)).expect("Failed to send recent files");
 
-                                Ok(())
-                            },
-                                               
+                        Ok(())
+                    },
+                    EditorMessage::ClosedBuffer(path, location) => {
+                        self.closed_buffers.push((path, location));
+                        if self.closed_buffers.len() > MAX_CLOSED_BUFFERS {
+                            self.closed_buffers.remove(0);
                         }
+                        Ok(())
                     },
-                    EditorMessage::Copy(ty, text) => {
-                        match ty {
-                            RegisterType::None => {
-                                self.registers.set_clipboard(text);
-                                Ok(())
+                    EditorMessage::Reopen => {
+                        let sender = &self.window_senders[self.active_window];
+                        match self.closed_buffers.pop() {
+                            None => {
+                                sender.send(Message::SetStatus("No closed files to reopen".to_string())).expect("Failed to send status");
                             },
-                            RegisterType::Number(n) => {
-                                self.registers.set(n, text);
-                                Ok(())
-                            },
-                            RegisterType::Name(name) => {
-                                self.registers.set(name, text);
-                                Ok(())
+                            Some((path, location)) => {
+                                let path = path.to_string_lossy().to_string();
+
+                                sender.send(Message::HorizontalSplit).expect("Failed to send split");
+                                sender.send(Message::OpenFile(path, Some(location))).expect("Failed to send file");
                             },
                         }
+                        Ok(())
+                    },
+                    EditorMessage::Grep(pattern, root) => {
+                        self.quickfix.borrow_mut().grep(pattern, root);
+
+                        let message = Message::ShowQuickFix(self.quickfix.clone());
+                        self.window_senders[self.active_window].send(message).expect("Failed to send quickfix");
+                        Ok(())
+                    },
+                    EditorMessage::CancelGrep => {
+                        let message = if self.quickfix.borrow().is_searching() {
+                            self.quickfix.borrow_mut().cancel();
+                            "Search stopped"
+                        }
+                        else {
+                            "No search is running"
+                        };
+                        self.window_senders[self.active_window].send(Message::SetStatus(message.to_string())).expect("Failed to send status");
+                        Ok(())
+                    },
+                    EditorMessage::NextQuickFix => {
+                        let entry = self.quickfix.borrow_mut().next_entry().cloned();
+                        self.step_quickfix(entry);
+                        Ok(())
+                    },
+                    EditorMessage::PreviousQuickFix => {
+                        let entry = self.quickfix.borrow_mut().previous_entry().cloned();
+                        self.step_quickfix(entry);
+                        Ok(())
+                    },
+                    EditorMessage::SetMacro(name, keys) => {
+                        self.registers.set_macro(name, keys);
+                        Ok(())
+                    },
+                    EditorMessage::GetMacro(name, count) => {
+                        if let Some(keys) = self.registers.get_macro(name) {
+                            let message = Message::MacroKeys(keys.clone(), count);
+
+                            self.window_senders[self.active_window].send(message).expect("Failed to send macro");
+                        }
+                        Ok(())
+                    },
+                    EditorMessage::LspMessage(text, kind) => {
+                        self.window_senders[self.active_window].send(Message::SetMessage(text, kind)).expect("Failed to send message");
+                        Ok(())
+                    },
+                    EditorMessage::SetCursorLocation(location) => {
+                        let message = Message::SetCursorLocation(location);
+
+                        self.window_senders[self.active_window].send(message).expect("Failed to send cursor location");
+                        Ok(())
                     },
                 }
             },
@@ -180,13 +431,27 @@ impl Editor {
         }
     }
 
+    /// Passes on the messages the language servers want shown.
+    /// A window that is waiting on the controller passes them on itself.
+    fn check_lsp_messages(&mut self) -> io::Result<()> {
+        while let Ok(message) = self.lsp_listener.try_recv() {
+            if let ControllerMessage::ShowMessage(language, kind, text) = message {
+                let message = format!("{}: {}", language, text);
+                self.sender.send(EditorMessage::LspMessage(message, MessageKind::from_lsp(kind))).expect("Failed to send message");
+            }
+        }
+        Ok(())
+    }
+
     pub fn run(&mut self) -> io::Result<bool> {
         self.check_messages()?;
+        self.check_quickfix();
 
         if self.windows.is_empty() {
             //eprintln!("No windows left, quitting");
             return Ok(false);
         }
+        self.check_lsp_messages()?;
         
         self.windows[self.active_window].run()?;
         Ok(true)
@@ -196,6 +461,43 @@ impl Editor {
 }
 
 
+/// Shows what was wrong with the config files on the message line, falling back on the defaults for those parts.
+fn report_config_errors(sender: &Sender<Message>, errors: Vec<String>) {
+    if errors.is_empty() {
+        return;
+    }
+
+    let message = format!("Config error: {}", errors.join("; "));
+    sender.send(Message::SetMessage(message, MessageKind::Error)).expect("Failed to send message");
+}
+
+/// Splits a `path:line` or `path:line:col` argument into the path and the column and row to start at.
+/// A file that exists with that name is opened as is.
+pub fn parse_file_argument(argument: &str) -> (String, Option<(usize, usize)>) {
+    if Path::new(argument).exists() {
+        return (argument.to_string(), None);
+    }
+
+    let number = |part: &str| part.parse::<usize>().ok().filter(|number| *number > 0);
+
+    let (rest, last) = match argument.rsplit_once(':') {
+        Some((rest, last)) if !rest.is_empty() => (rest, last),
+        _ => return (argument.to_string(), None),
+    };
+    let last = match number(last) {
+        None => return (argument.to_string(), None),
+        Some(last) => last,
+    };
+
+    match rest.rsplit_once(':') {
+        Some((path, line)) if !path.is_empty() => match number(line) {
+            Some(line) => (path.to_string(), Some((last - 1, line - 1))),
+            None => (rest.to_string(), Some((0, last - 1))),
+        },
+        _ => (rest.to_string(), Some((0, last - 1))),
+    }
+}
+
 impl Drop for Editor {
     fn drop(&mut self) {
         terminal::disable_raw_mode().expect("Failed to disable raw mode");
@@ -203,5 +505,91 @@ impl Drop for Editor {
         execute!(std::io::stdout(), MoveTo(0, 0)).expect("Failed to move cursor to 0, 0");
         execute!(std::io::stdout(), terminal::LeaveAlternateScreen).expect("Failed to leave alternate screen");
         execute!(io::stdout(), SetCursorStyle::DefaultUserShape).expect("Could not reset cursor style");
+        execute!(io::stdout(), DisableFocusChange).expect("Could not disable focus events");
+        // The windows turn mouse capture on when the `mouse` setting is on
+        execute!(io::stdout(), DisableMouseCapture).expect("Could not disable mouse capture");
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::{mem::ManuallyDrop, sync::mpsc};
+
+    use super::*;
+
+    /// An editor with some windows that never touches the terminal, not even when it goes away.
+    fn test_editor(windows: usize) -> ManuallyDrop<Editor> {
+        let (sender, reciever) = mpsc::channel();
+        let (lsp_responder, _) = mpsc::channel();
+        let (_, lsp_listener) = mpsc::channel();
+        let lsp_listener = Rc::new(lsp_listener);
+
+        let windows = (0..windows)
+            .map(|_| Window::new(sender.clone(), lsp_responder.clone(), lsp_listener.clone()))
+            .collect::<Vec<_>>();
+
+        ManuallyDrop::new(Editor {
+            window_senders: windows.iter().map(Window::get_sender).collect(),
+            windows,
+            active_window: 0,
+            reciever,
+            sender,
+            lsp_listener,
+            lsp_responder,
+            registers: Registers::new(),
+            recent_files: RecentFiles::new(),
+            closed_buffers: Vec::new(),
+            quickfix: Rc::new(RefCell::new(QuickFix::new())),
+            settings: Settings::default(),
+        })
+    }
+
+    #[test]
+    fn test_send_to_window_after_close() {
+        let mut editor = test_editor(2);
+        editor.active_window = 1;
+
+        editor.sender.send(EditorMessage::CloseWindow).unwrap();
+        editor.check_messages().unwrap();
+        assert_eq!(editor.windows.len(), 1);
+        assert_eq!(editor.window_senders.len(), 1);
+
+        // This goes to the window that is left, not the one that closed
+        editor.sender.send(EditorMessage::RecentFiles).unwrap();
+        editor.check_messages().unwrap();
+
+        editor.sender.send(EditorMessage::CloseWindow).unwrap();
+        assert!(!editor.run().unwrap());
+    }
+
+    #[test]
+    fn test_close_last_pane() {
+        let mut editor = test_editor(2);
+        editor.active_window = 1;
+
+        // The window closes once its last pane is gone
+        editor.window_senders[1].send(Message::ClosePane(false, None)).unwrap();
+        assert!(!editor.windows[1].run().unwrap());
+        editor.check_messages().unwrap();
+        assert_eq!(editor.windows.len(), 1);
+        assert_eq!(editor.window_senders.len(), 1);
+        assert_eq!(editor.active_window, 0);
+
+        // Every sender left still has its window
+        for sender in editor.window_senders.iter() {
+            assert!(sender.send(Message::ForceRedraw).is_ok());
+        }
+        editor.sender.send(EditorMessage::RecentFiles).unwrap();
+        editor.check_messages().unwrap();
+    }
+
+    #[test]
+    fn test_parse_file_argument() {
+        assert_eq!(parse_file_argument("missing.rs"), ("missing.rs".to_string(), None));
+        assert_eq!(parse_file_argument("missing.rs:10"), ("missing.rs".to_string(), Some((0, 9))));
+        assert_eq!(parse_file_argument("missing.rs:10:5"), ("missing.rs".to_string(), Some((4, 9))));
+        assert_eq!(parse_file_argument("missing.rs:0"), ("missing.rs:0".to_string(), None));
+        assert_eq!(parse_file_argument("Cargo.toml"), ("Cargo.toml".to_string(), None));
     }
 }
@@ -0,0 +1,266 @@
+use std::{fs, path::{Path, PathBuf}};
+
+use crate::settings::{EditorSettings, FinalNewline, LineEnding};
+
+
+/// The properties an `.editorconfig` file can set for a file.
+/// Anything left as `None` keeps the editor's own setting.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EditorConfig {
+    pub use_spaces: Option<bool>,
+    pub indent_size: Option<usize>,
+    pub tab_width: Option<usize>,
+    pub line_ending: Option<LineEnding>,
+    pub trim_trailing_whitespace: Option<bool>,
+    pub insert_final_newline: Option<bool>,
+}
+
+impl EditorConfig {
+    /// Reads every `.editorconfig` from the file's directory up to the one marked as root.
+    /// Files closer to the file win over the ones further up.
+    pub fn find(path: &Path) -> Self {
+        let path = absolute(path);
+        let mut config = Self::default();
+
+        let mut configs = Vec::new();
+        let mut dir = path.parent();
+        while let Some(current) = dir {
+            if let Ok(text) = fs::read_to_string(current.join(".editorconfig")) {
+                let root = is_root(&text);
+                configs.push((current.to_path_buf(), text));
+                if root {
+                    break;
+                }
+            }
+            dir = current.parent();
+        }
+
+        for (dir, text) in configs.iter().rev() {
+            let relative = match path.strip_prefix(dir) {
+                Ok(relative) => relative.to_string_lossy().replace('\\', "/"),
+                Err(_) => continue,
+            };
+            config.read(text, &relative);
+        }
+
+        config
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Applies the properties of the sections in a file that match the path.
+    /// The path is relative to the directory of the file.
+    pub fn read(&mut self, text: &str, path: &str) {
+        let mut matches = false;
+
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+
+            if let Some(section) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+                matches = section_matches(section, path);
+                continue;
+            }
+
+            if !matches {
+                continue;
+            }
+
+            if let Some((key, value)) = line.split_once('=') {
+                self.set(&key.trim().to_lowercase(), &value.trim().to_lowercase());
+            }
+        }
+    }
+
+    fn set(&mut self, key: &str, value: &str) {
+        let flag = match value {
+            "true" => Some(true),
+            "false" => Some(false),
+            _ => None,
+        };
+
+        match key {
+            "indent_style" => self.use_spaces = match value {
+                "space" => Some(true),
+                "tab" => Some(false),
+                _ => None,
+            },
+            "indent_size" => self.indent_size = match value {
+                // Indenting by a tab means the indent is as wide as a tab
+                "tab" => None,
+                value => value.parse().ok(),
+            },
+            "tab_width" => self.tab_width = value.parse().ok(),
+            "end_of_line" => self.line_ending = match value {
+                "lf" => Some(LineEnding::Lf),
+                "crlf" => Some(LineEnding::Crlf),
+                "cr" => Some(LineEnding::Cr),
+                _ => None,
+            },
+            "trim_trailing_whitespace" => self.trim_trailing_whitespace = flag,
+            "insert_final_newline" => self.insert_final_newline = flag,
+            _ => {},
+        }
+    }
+
+    /// Layers the properties over the editor settings.
+    pub fn apply(&self, settings: &mut EditorSettings) {
+        if let Some(use_spaces) = self.use_spaces {
+            settings.use_spaces = use_spaces;
+        }
+        if let Some(tab_size) = self.indent_size.or(self.tab_width) {
+            settings.tab_size = tab_size;
+        }
+        if let Some(line_ending) = self.line_ending {
+            settings.line_ending = line_ending;
+        }
+        if let Some(trim) = self.trim_trailing_whitespace {
+            settings.trim_trailing_whitespace = trim;
+        }
+        match self.insert_final_newline {
+            Some(true) => settings.final_newline = FinalNewline::Ensure,
+            Some(false) => settings.final_newline = FinalNewline::Trim,
+            None => {},
+        }
+    }
+}
+
+fn absolute(path: &Path) -> PathBuf {
+    if let Ok(path) = fs::canonicalize(path) {
+        return path;
+    }
+    match std::env::current_dir() {
+        Ok(dir) => dir.join(path),
+        Err(_) => path.to_path_buf(),
+    }
+}
+
+/// Whether the file says to stop looking in the directories above it.
+fn is_root(text: &str) -> bool {
+    for line in text.lines() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            return false;
+        }
+        if let Some((key, value)) = line.split_once('=') {
+            if key.trim().eq_ignore_ascii_case("root") && value.trim().eq_ignore_ascii_case("true") {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+/// Whether a section header like `*.rs` or `src/**.{c,h}` covers the path.
+/// Headers without a `/` only look at the file name.
+fn section_matches(section: &str, path: &str) -> bool {
+    let target = if section.contains('/') {
+        path
+    }
+    else {
+        path.rsplit('/').next().unwrap_or(path)
+    };
+    let section = section.trim_start_matches('/');
+
+    expand_braces(section).iter().any(|pattern| {
+        let pattern = pattern.chars().collect::<Vec<char>>();
+        let target = target.chars().collect::<Vec<char>>();
+        glob_match(&pattern, &target)
+    })
+}
+
+/// Turns `{a,b}` into a pattern for each choice.
+fn expand_braces(pattern: &str) -> Vec<String> {
+    let chars = pattern.chars().collect::<Vec<char>>();
+
+    let start = match chars.iter().position(|c| *c == '{') {
+        None => return vec![pattern.to_string()],
+        Some(start) => start,
+    };
+
+    let mut depth = 0;
+    let mut choices = vec![String::new()];
+    for (i, c) in chars.iter().enumerate().skip(start + 1) {
+        match c {
+            '{' => depth += 1,
+            '}' if depth > 0 => depth -= 1,
+            '}' => {
+                if choices.len() == 1 {
+                    break;
+                }
+
+                let prefix = chars[..start].iter().collect::<String>();
+                let suffix = chars[i + 1..].iter().collect::<String>();
+                return choices.iter()
+                    .flat_map(|choice| expand_braces(&format!("{}{}{}", prefix, choice, suffix)))
+                    .collect();
+            },
+            ',' if depth == 0 => {
+                choices.push(String::new());
+                continue;
+            },
+            _ => {},
+        }
+        choices.last_mut().unwrap().push(*c);
+    }
+
+    // Braces without any choices are just characters
+    vec![pattern.to_string()]
+}
+
+fn glob_match(pattern: &[char], text: &[char]) -> bool {
+    match pattern.first() {
+        None => text.is_empty(),
+        Some('*') if pattern.get(1) == Some(&'*') => {
+            let rest = &pattern[2..];
+            (0..=text.len()).any(|i| glob_match(rest, &text[i..]))
+        },
+        Some('*') => {
+            let rest = &pattern[1..];
+            for i in 0..=text.len() {
+                if glob_match(rest, &text[i..]) {
+                    return true;
+                }
+                if i < text.len() && text[i] == '/' {
+                    break;
+                }
+            }
+            false
+        },
+        Some('?') => !text.is_empty() && text[0] != '/' && glob_match(&pattern[1..], &text[1..]),
+        Some('[') => match pattern.iter().position(|c| *c == ']') {
+            Some(end) if end > 1 && !text.is_empty() => {
+                let class = &pattern[1..end];
+                let (negate, class) = match class.first() {
+                    Some('!') => (true, &class[1..]),
+                    _ => (false, class),
+                };
+
+                let c = text[0];
+                let mut found = false;
+                let mut i = 0;
+                while i < class.len() {
+                    if i + 2 < class.len() && class[i + 1] == '-' {
+                        found |= class[i] <= c && c <= class[i + 2];
+                        i += 3;
+                    }
+                    else {
+                        found |= class[i] == c;
+                        i += 1;
+                    }
+                }
+
+                found != negate && glob_match(&pattern[end + 1..], &text[1..])
+            },
+            _ => !text.is_empty() && text[0] == '[' && glob_match(&pattern[1..], &text[1..]),
+        },
+        Some('\\') if pattern.len() > 1 => {
+            !text.is_empty() && text[0] == pattern[1] && glob_match(&pattern[2..], &text[1..])
+        },
+        Some(c) => !text.is_empty() && text[0] == *c && glob_match(&pattern[1..], &text[1..]),
+    }
+}
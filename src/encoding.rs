@@ -0,0 +1,80 @@
+use std::{fs, io, path::Path};
+
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8};
+
+
+/// The encoding a file was read with so it can be written back the same way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileEncoding {
+    pub encoding: &'static Encoding,
+    /// Whether the file started with a byte order mark.
+    pub bom: bool,
+}
+
+impl Default for FileEncoding {
+    fn default() -> Self {
+        Self {
+            encoding: UTF_8,
+            bom: false,
+        }
+    }
+}
+
+impl FileEncoding {
+    /// Decodes the bytes of a file.
+    /// A byte order mark wins over the given encoding and files without either are read as UTF-8.
+    /// Also returns whether any bytes couldn't be decoded and were replaced.
+    pub fn decode(bytes: &[u8], encoding: Option<&'static Encoding>) -> (String, Self, bool) {
+        if let Some((encoding, bom_length)) = Encoding::for_bom(bytes) {
+            let (text, had_errors) = encoding.decode_without_bom_handling(&bytes[bom_length..]);
+            return (text.into_owned(), Self { encoding, bom: true }, had_errors);
+        }
+
+        let encoding = encoding.unwrap_or(UTF_8);
+        let (text, had_errors) = encoding.decode_without_bom_handling(bytes);
+        (text.into_owned(), Self { encoding, bom: false }, had_errors)
+    }
+
+    pub fn read(path: &Path, encoding: Option<&'static Encoding>) -> io::Result<(String, Self, bool)> {
+        let bytes = fs::read(path)?;
+        Ok(Self::decode(&bytes, encoding))
+    }
+
+    /// Encodes the text to be written to the file.
+    /// Also returns whether any characters can't be written in the encoding.
+    pub fn encode(&self, text: &str) -> (Vec<u8>, bool) {
+        // encoding_rs can only decode UTF-16 so we write it ourselves
+        if self.encoding == UTF_16LE || self.encoding == UTF_16BE {
+            let little_endian = self.encoding == UTF_16LE;
+            let bom = if self.bom { Some(0xFEFF) } else { None };
+
+            let bytes = bom.into_iter().chain(text.encode_utf16()).flat_map(|unit| {
+                if little_endian {
+                    unit.to_le_bytes()
+                }
+                else {
+                    unit.to_be_bytes()
+                }
+            }).collect();
+            return (bytes, false);
+        }
+
+        let mut bytes = Vec::new();
+        if self.bom && self.encoding == UTF_8 {
+            bytes.extend_from_slice(b"\xEF\xBB\xBF");
+        }
+
+        let (encoded, _, had_errors) = self.encoding.encode(text);
+        bytes.extend_from_slice(&encoded);
+        (bytes, had_errors)
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.encoding.name()
+    }
+}
+
+/// Looks up an encoding by a name like `utf-8`, `latin1` or `shift_jis`.
+pub fn parse_encoding(label: &str) -> Result<&'static Encoding, String> {
+    Encoding::for_label(label.trim().as_bytes()).ok_or_else(|| format!("Invalid encoding: {}", label))
+}
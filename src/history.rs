@@ -0,0 +1,148 @@
+use std::{cell::RefCell, env, fs, io, path::PathBuf};
+
+use crate::settings::EditorSettings;
+
+
+/// The commands run in the command prompt, oldest first.
+pub struct CommandHistory {
+    commands: Vec<String>,
+    save_path: Option<PathBuf>,
+}
+
+thread_local! {
+    /// Every command prompt goes through the same history.
+    /// It is loaded the first time it is used.
+    static HISTORY: RefCell<Option<CommandHistory>> = const { RefCell::new(None) };
+}
+
+impl CommandHistory {
+    pub fn new(save_path: Option<PathBuf>) -> Self {
+        let commands = match &save_path {
+            None => Vec::new(),
+            Some(path) => fs::read_to_string(path)
+                .map(|contents| contents.lines().filter(|line| !line.is_empty()).map(str::to_string).collect())
+                .unwrap_or_default(),
+        };
+
+        Self {
+            commands,
+            save_path,
+        }
+    }
+
+    /// The file lives in `$XDG_DATA_HOME/vi` or `~/.local/share/vi` if that isn't set.
+    fn data_path() -> Option<PathBuf> {
+        let data_dir = match env::var_os("XDG_DATA_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(env::var_os("HOME")?).join(".local").join("share"),
+        };
+
+        Some(data_dir.join("vi").join("command_history"))
+    }
+
+    /// Moves a command to the end, dropping the oldest ones past `size`.
+    pub fn push(&mut self, command: &str, size: usize) {
+        let command = command.trim();
+        if command.is_empty() {
+            return;
+        }
+
+        self.commands.retain(|old| old != command);
+        self.commands.push(command.to_string());
+        let extra = self.commands.len().saturating_sub(size);
+        self.commands.drain(..extra);
+    }
+
+    /// The commands that start with the prefix, oldest first.
+    pub fn matching(&self, prefix: &str) -> Vec<String> {
+        self.commands.iter()
+            .filter(|command| command.starts_with(prefix))
+            .cloned()
+            .collect()
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let path = match &self.save_path {
+            None => return Ok(()),
+            Some(path) => path,
+        };
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        fs::write(path, self.commands.join("\n"))
+    }
+}
+
+/// Remembers a command that was run, saving the history when the settings say to.
+pub fn add_command(command: &str, settings: &EditorSettings) -> io::Result<()> {
+    with_history(|history| {
+        history.push(command, settings.history_size);
+        match settings.save_history {
+            true => history.save(),
+            false => Ok(()),
+        }
+    })
+}
+
+/// The commands run before that start with the prefix, oldest first.
+pub fn matching_commands(prefix: &str) -> Vec<String> {
+    with_history(|history| history.matching(prefix))
+}
+
+fn with_history<T>(f: impl FnOnce(&mut CommandHistory) -> T) -> T {
+    HISTORY.with(|history| {
+        let mut history = history.borrow_mut();
+        let history = history.get_or_insert_with(|| CommandHistory::new(CommandHistory::data_path()));
+        f(history)
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_history() {
+        let mut history = CommandHistory::new(None);
+        history.push("e src/main.rs", 3);
+        history.push("w", 3);
+        history.push("e src/lib.rs", 3);
+        history.push("  ", 3);
+        assert_eq!(history.matching("e "), vec!["e src/main.rs", "e src/lib.rs"]);
+
+        // Running a command again moves it to the end and the oldest fall off
+        history.push("e src/main.rs", 3);
+        history.push("set nu", 3);
+        assert_eq!(history.matching(""), vec!["e src/lib.rs", "e src/main.rs", "set nu"]);
+    }
+
+    #[test]
+    fn test_saved_history() {
+        let path = std::env::temp_dir().join("vi_command_history_test");
+        let _ = fs::remove_file(&path);
+
+        let mut history = CommandHistory::new(Some(path.clone()));
+        history.push("vertical_split", 10);
+        history.push("grep todo", 10);
+        history.save().unwrap();
+
+        let history = CommandHistory::new(Some(path.clone()));
+        assert_eq!(history.matching(""), vec!["vertical_split", "grep todo"]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_save_failure() {
+        // A file where the directory should be can't be saved under
+        let file = std::env::temp_dir().join(format!("vi_history_not_a_dir_{}", std::process::id()));
+        fs::write(&file, "").unwrap();
+
+        let mut history = CommandHistory::new(Some(file.join("history")));
+        history.push("w", 10);
+        assert!(history.save().is_err());
+        fs::remove_file(&file).unwrap();
+    }
+}
@@ -0,0 +1,142 @@
+use std::ops::Bound;
+
+
+/// The byte range of some indentation and what to replace it with.
+pub type IndentEdit = ((Bound<usize>, Bound<usize>), String);
+
+/// Returns the whitespace at the start of a line.
+pub fn leading_whitespace(line: &str) -> &str {
+    let len = line.len() - line.trim_start_matches([' ', '\t']).len();
+    &line[..len]
+}
+
+/// Builds the indentation that takes up as many columns as the given one but only uses the right whitespace.
+/// Without `use_spaces` any spaces left over after the tabs are kept for alignment.
+pub fn normalize_indent(indent: &str, use_spaces: bool, tab_size: usize) -> String {
+    let tab_size = tab_size.max(1);
+
+    let width = indent.chars().fold(0, |width, c| match c {
+        '\t' => (width / tab_size + 1) * tab_size,
+        _ => width + 1,
+    });
+
+    if use_spaces {
+        " ".repeat(width)
+    }
+    else {
+        "\t".repeat(width / tab_size) + &" ".repeat(width % tab_size)
+    }
+}
+
+/// Whether a line is indented with the wrong kind of whitespace.
+pub fn is_bad_indent(line: &str, use_spaces: bool, tab_size: usize) -> bool {
+    let indent = leading_whitespace(line);
+    indent != normalize_indent(indent, use_spaces, tab_size)
+}
+
+/// Finds every line with the wrong kind of indentation.
+/// Returns the byte range of the old indentation in the text along with what it should be replaced by.
+pub fn fix_indent(text: &str, use_spaces: bool, tab_size: usize) -> Vec<IndentEdit> {
+    let mut edits = Vec::new();
+    let mut offset = 0;
+
+    for line in text.split_inclusive('\n') {
+        let indent = leading_whitespace(line);
+        let fixed = normalize_indent(indent, use_spaces, tab_size);
+
+        if indent != fixed {
+            edits.push(((Bound::Included(offset), Bound::Excluded(offset + indent.len())), fixed));
+        }
+
+        offset += line.len();
+    }
+
+    edits
+}
+
+/// One level of indentation.
+pub fn indent_unit(use_spaces: bool, tab_size: usize) -> String {
+    if use_spaces {
+        " ".repeat(tab_size.max(1))
+    }
+    else {
+        "\t".to_string()
+    }
+}
+
+/// Adds one level to an indentation, going up to the next level when it is between levels.
+pub fn indent(indent: &str, use_spaces: bool, tab_size: usize) -> String {
+    let tab_size = tab_size.max(1);
+    let width = normalize_indent(indent, true, tab_size).len();
+    let width = (width / tab_size + 1) * tab_size;
+
+    normalize_indent(&" ".repeat(width), use_spaces, tab_size)
+}
+
+/// Takes one level off of an indentation, going back to the level before when it is between levels.
+pub fn dedent(indent: &str, use_spaces: bool, tab_size: usize) -> String {
+    let tab_size = tab_size.max(1);
+    let width = normalize_indent(indent, true, tab_size).len();
+    let width = width.saturating_sub(1) / tab_size * tab_size;
+
+    normalize_indent(&" ".repeat(width), use_spaces, tab_size)
+}
+
+/// The indentation a line should have going by the closest line above it with text.
+/// It gets one more level when the line above opens a block and one less when the line itself closes one.
+pub fn line_indent(above: &str, opened: bool, closes: bool, use_spaces: bool, tab_size: usize) -> String {
+    let base = normalize_indent(leading_whitespace(above), use_spaces, tab_size);
+    match (opened, closes) {
+        (true, false) => indent(&base, use_spaces, tab_size),
+        (false, true) => dedent(&base, use_spaces, tab_size),
+        _ => base,
+    }
+}
+
+/// Whether a line starts by closing a block.
+pub fn starts_with_closer(line: &str) -> bool {
+    line.trim_start().starts_with(['}', ')', ']'])
+}
+
+/// The bracket that the text before the cursor ends with, which is what makes the next line indented.
+/// Python blocks are opened with a `:` instead.
+pub fn block_opener(before: &str, language: Option<&str>) -> Option<(usize, char)> {
+    let (index, c) = before.char_indices().rev().find(|(_, c)| !c.is_whitespace())?;
+    match c {
+        '{' | '(' | '[' => Some((index, c)),
+        ':' if language == Some("python") => Some((index, c)),
+        _ => None,
+    }
+}
+
+/// The bracket that closes an opening one.
+pub fn closing_bracket(open: char) -> Option<char> {
+    match open {
+        '{' => Some('}'),
+        '(' => Some(')'),
+        '[' => Some(']'),
+        _ => None,
+    }
+}
+
+/// Builds the text that goes in when a line is split in two at the cursor.
+/// The new line keeps the indentation of the line it was split from, with one more level after a block opener.
+/// When the text after the cursor starts with the bracket that closes the block it goes on a line of its own.
+/// Returns the text along with the column the cursor ends up at on the new line.
+pub fn split_line(before: &str, after: &str, opener: Option<char>, use_spaces: bool, tab_size: usize) -> (String, usize) {
+    let indent = leading_whitespace(before);
+    if opener.is_none() {
+        return (format!("\n{}", indent), indent.chars().count());
+    }
+
+    let inner = format!("{}{}", indent, indent_unit(use_spaces, tab_size));
+    let column = inner.chars().count();
+
+    let closed = opener.and_then(closing_bracket).is_some_and(|close| after.trim_start().starts_with(close));
+    if closed {
+        (format!("\n{}\n{}", inner, indent), column)
+    }
+    else {
+        (format!("\n{}", inner), column)
+    }
+}
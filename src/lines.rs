@@ -0,0 +1,134 @@
+use std::ops::Range;
+
+
+/// What a line command does to its lines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineOperation {
+    /// Sort the lines, dropping any duplicates when `unique` is set.
+    Sort { unique: bool },
+    /// Drop every line that is the same as the one before it.
+    Uniq,
+    /// Remove the spaces and tabs at the end of every line.
+    TrimWhitespace,
+}
+
+/// A command that rewrites whole lines like `sort u`, `5,10uniq` or `trim_whitespace`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineCommand {
+    /// The first and last line to change, counting from 1. `None` means the whole buffer.
+    pub range: Option<(usize, usize)>,
+    pub operation: LineOperation,
+}
+
+impl LineCommand {
+    pub fn parse(command: &str) -> Option<Self> {
+        let (range, rest) = parse_range(command.trim())?;
+        let mut args = rest.split_whitespace();
+
+        let operation = match args.next()? {
+            "sort" => {
+                let mut unique = false;
+                for arg in args {
+                    match arg {
+                        "u" => unique = true,
+                        _ => return None,
+                    }
+                }
+                LineOperation::Sort { unique }
+            },
+            "uniq" => {
+                if args.next().is_some() {
+                    return None;
+                }
+                LineOperation::Uniq
+            },
+            "trim_whitespace" => {
+                if args.next().is_some() {
+                    return None;
+                }
+                LineOperation::TrimWhitespace
+            },
+            _ => return None,
+        };
+
+        Some(Self {
+            range,
+            operation,
+        })
+    }
+
+    /// Works out the edit the command makes to the text.
+    /// Returns the byte range of the lines that get replaced, the text that replaces them and how many lines were removed.
+    pub fn apply(&self, text: &str) -> Option<(Range<usize>, String, usize)> {
+        let lines = text.split_inclusive('\n').collect::<Vec<&str>>();
+
+        let (start, end) = match self.range {
+            None => (0, lines.len()),
+            Some((start, end)) => (start - 1, end.min(lines.len())),
+        };
+        if start >= end {
+            return None;
+        }
+
+        let byte_start = lines[..start].iter().map(|line| line.len()).sum::<usize>();
+        let byte_end = byte_start + lines[start..end].iter().map(|line| line.len()).sum::<usize>();
+        let ends_with_newline = text[..byte_end].ends_with('\n');
+
+        // The last line might not have a newline so we compare the lines without them
+        let mut selected = lines[start..end].iter()
+            .map(|line| line.strip_suffix('\n').unwrap_or(line))
+            .collect::<Vec<&str>>();
+
+        match self.operation {
+            LineOperation::Sort { unique } => {
+                selected.sort();
+                if unique {
+                    selected.dedup();
+                }
+            },
+            LineOperation::Uniq => selected.dedup(),
+            LineOperation::TrimWhitespace => {
+                for line in selected.iter_mut() {
+                    *line = line.trim_end_matches([' ', '\t']);
+                }
+            },
+        }
+
+        let removed = (end - start) - selected.len();
+
+        let mut replacement = selected.join("\n");
+        if ends_with_newline {
+            replacement.push('\n');
+        }
+
+        Some((byte_start..byte_end, replacement, removed))
+    }
+}
+
+/// Splits a leading `%`, `5` or `5,10` off of a command.
+fn parse_range(command: &str) -> Option<(Option<(usize, usize)>, &str)> {
+    if let Some(rest) = command.strip_prefix('%') {
+        return Some((None, rest));
+    }
+
+    let split = command.find(|c: char| !c.is_ascii_digit() && c != ',').unwrap_or(command.len());
+    let (range, rest) = command.split_at(split);
+
+    if range.is_empty() {
+        return Some((None, rest));
+    }
+
+    let (start, end) = match range.split_once(',') {
+        Some((start, end)) => (start.parse::<usize>().ok()?, end.parse::<usize>().ok()?),
+        None => {
+            let line = range.parse::<usize>().ok()?;
+            (line, line)
+        },
+    };
+
+    if start == 0 || end == 0 {
+        return None;
+    }
+
+    Some((Some((start.min(end), start.max(end))), rest))
+}
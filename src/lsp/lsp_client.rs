@@ -1,119 +1,233 @@
-
+use std::{time::Duration, path::{Path, PathBuf}, sync::mpsc::Sender};
 
 use futures::executor::block_on;
-use tokio::{io::{BufReader, AsyncBufReadExt, AsyncWriteExt, AsyncReadExt, BufWriter, self}, process::{ChildStdout, ChildStdin, Child}};
+use tokio::{io::{BufReader, AsyncRead, AsyncWrite, AsyncWriteExt, AsyncReadExt, BufWriter, self}, process::{ChildStdout, ChildStdin, Child}, net::TcpStream, sync::oneshot, task::JoinHandle, time::timeout};
 
+use super::{ControllerMessage, lsp_utils::{MessageBuffer, FormattingOptions, Diagnostic, LspCommand, TextChange, path_to_uri}};
 
 
 
-unsafe impl Send for Client {}
 
-pub struct Client {
-    child: Child,
-    input: BufWriter<ChildStdin>,
-    output: BufReader<ChildStdout>,
+/// How long a server gets to answer `shutdown`, and then to exit, before it is killed.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(2);
+/// The id of the `shutdown` request, so the reader can tell when it is answered.
+const SHUTDOWN_ID: u64 = 14;
+
+pub type Reader = Box<dyn AsyncRead + Send + Unpin>;
+pub type Writer = Box<dyn AsyncWrite + Send + Unpin>;
+
+/// How messages get to and from a server.
+/// Every transport frames its messages the same way, with a `Content-Length` header.
+pub trait Transport {
+    /// Splits the connection into where the server's messages are read from and where messages to it are written.
+    fn split(self) -> (Reader, Writer);
 }
 
-impl Client {
-    pub fn new(mut child: Child) -> Self {
-        let input = child.stdin.take().expect("Failed to get stdin");
-        let output = child.stdout.take().expect("Failed to get stdout");
-        let input = BufWriter::new(input);
-        let output = BufReader::new(output);
+/// Talks to a server over its stdin and stdout.
+pub struct StdioTransport {
+    pub stdin: ChildStdin,
+    pub stdout: ChildStdout,
+}
 
-        Client {
-            child,
-            input,
-            output,
-        }
+impl Transport for StdioTransport {
+    fn split(self) -> (Reader, Writer) {
+        (Box::new(self.stdout), Box::new(self.stdin))
+    }
+}
 
+impl Transport for TcpStream {
+    fn split(self) -> (Reader, Writer) {
+        let (reader, writer) = self.into_split();
+        (Box::new(reader), Box::new(writer))
     }
 }
 
+fn workspace_folder(folder: &Path) -> serde_json::Value {
+    let name = folder.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| folder.display().to_string());
+    serde_json::json!({
+        "uri": path_to_uri(folder),
+        "name": name,
+    })
+}
 
-impl Drop for Client {
-    fn drop(&mut self) {
-        self.send_shutdown().expect("Failed to send shutdown");
-        self.send_exit().expect("Failed to send exit");
-        let future = async {
-            self.child.wait().await.expect("Failed to wait for child");
-        };
-        block_on(future);
+/// Reads the messages of a server as they come in and hands them to the controller.
+/// This runs on the tokio runtime so the controller can wait on its channel instead of checking every server in a loop.
+/// The answer to `shutdown` goes to `shutdown` instead.
+async fn read_messages(mut output: BufReader<Reader>, language: Box<str>, sender: Sender<ControllerMessage>, shutdown: oneshot::Sender<()>) {
+    let mut messages = MessageBuffer::new();
+    let mut chunk = [0; 4096];
+    let mut shutdown = Some(shutdown);
+
+    loop {
+        while let Some(body) = messages.next_message() {
+            let json: serde_json::Value = match serde_json::from_str(&body) {
+                Ok(json) => json,
+                Err(_) => continue,
+            };
+            // Requests from the server have their own ids, only answers have no method
+            if json["id"] == SHUTDOWN_ID && json.get("method").is_none() {
+                if let Some(shutdown) = shutdown.take() {
+                    let _ = shutdown.send(());
+                }
+                continue;
+            }
+            if sender.send(ControllerMessage::ServerMessage(language.clone(), json)).is_err() {
+                return;
+            }
+        }
+
+        // The server closed its output so it has exited
+        match output.read(&mut chunk).await {
+            Ok(0) | Err(_) => return,
+            Ok(bytes_read) => messages.extend(&chunk[..bytes_read]),
+        }
     }
 }
 
+pub struct Client {
+    /// `None` when connecting to a server the editor didn't start.
+    child: Option<Child>,
+    /// Where the server was connected to when it talks over tcp.
+    address: Option<String>,
+    /// Taken when the server is shut down so it sees the end of its input.
+    input: Option<BufWriter<Writer>>,
+    /// Told by the reader when the server answers `shutdown`.
+    shutdown: Option<oneshot::Receiver<()>>,
+    /// Whether the server takes just the part of a file that changed instead of all of it.
+    incremental_sync: bool,
+}
+
 impl Client {
+    /// Talks to a server that was started with piped stdin and stdout.
+    /// Messages from the server go to `sender` tagged with the language.
+    /// Has to be called from inside of a tokio runtime.
+    pub fn new(mut child: Child, language: &str, sender: Sender<ControllerMessage>) -> Self {
+        let transport = StdioTransport {
+            stdin: child.stdin.take().expect("Failed to get stdin"),
+            stdout: child.stdout.take().expect("Failed to get stdout"),
+        };
+        Self::with_transport(Some(child), None, transport, language, sender)
+    }
 
-    pub async fn process_messages(&mut self) -> io::Result<serde_json::Value> {
+    /// Talks to a server listening at `address`, `child` is the server if the editor started it.
+    /// Has to be called from inside of a tokio runtime.
+    pub fn connect(child: Option<Child>, address: &str, stream: TcpStream, language: &str, sender: Sender<ControllerMessage>) -> Self {
+        Self::with_transport(child, Some(address.to_string()), stream, language, sender)
+    }
 
-        let value = async {
-            let mut header = String::new();
-            let mut content_length = 0;
-            let mut content_type = String::new();
-            while let Ok(bytes_read) = self.output.read_line(&mut header).await {
-                if bytes_read == 0 {
-                    break;
-                }
-                if header.starts_with("Content-Length: ") {
-                    content_length = header[16..].trim().parse::<usize>().expect("Failed to parse content length");
-                    header.clear();
-                }
-                if header.starts_with("Content-Type: ") {
-                    content_type = header[14..].trim().to_string();
-                    header.clear();
-                }
-                if header == "\r\n" {
-                    break;
-                }
-            }
+    fn with_transport<T: Transport>(child: Option<Child>, address: Option<String>, transport: T, language: &str, sender: Sender<ControllerMessage>) -> Self {
+        let (output, input) = transport.split();
+        let (answered, shutdown) = oneshot::channel();
 
-            let mut body = vec![0; content_length];
-            self.output.read_exact(&mut body).await?;
+        tokio::spawn(read_messages(BufReader::new(output), language.into(), sender, answered));
 
+        Client {
+            child,
+            address,
+            input: Some(BufWriter::new(input)),
+            shutdown: Some(shutdown),
+            incremental_sync: false,
+        }
+    }
 
-            let body = match content_type {
-                _ => {
-                    String::from_utf8(body).expect("Failed to parse body as utf8")
-                },
-            };
+    /// Reads what the server said it can do in its answer to `initialize`.
+    pub fn read_capabilities(&mut self, capabilities: &serde_json::Value) {
+        let sync = &capabilities["textDocumentSync"];
+        let kind = sync.get("change").unwrap_or(sync).as_u64();
+        self.incremental_sync = kind == Some(2);
+    }
 
-            //eprintln!("Body: {}", body);
+    /// How the server is running, for listing the servers.
+    pub fn status(&self) -> String {
+        match (&self.child, &self.address) {
+            (Some(child), _) => match child.id() {
+                Some(id) => format!("pid {}", id),
+                None => "exited".to_string(),
+            },
+            (None, Some(address)) => format!("tcp {}", address),
+            (None, None) => String::new(),
+        }
+    }
+}
 
 
-            serde_json::from_str(&body).and_then(|json_data| {
-                Ok(json_data)
-            }).map_err(|err| {
-                io::Error::new(io::ErrorKind::Other, err)
-            })
-        };
-        value.await
+impl Drop for Client {
+    fn drop(&mut self) {
+        self.shut_down();
     }
+}
 
-    pub async fn figure_out_capabilities(&mut self) -> io::Result<()> {
-        self.process_messages().await?;
-        Ok(())
+/// Tells a server to exit once it has answered `shutdown`, then waits for it to exit.
+/// A server that doesn't answer or exit in time is killed.
+async fn stop_server(mut input: BufWriter<Writer>, child: Option<Child>, answered: Option<oneshot::Receiver<()>>) {
+    let answered = match answered {
+        Some(answered) => matches!(timeout(SHUTDOWN_TIMEOUT, answered).await, Ok(Ok(()))),
+        None => false,
+    };
+    if answered {
+        let exit = serde_json::json!({
+            "jsonrpc": "2.0",
+            "method": "exit",
+        });
+        let _ = write_message(&mut input, &exit).await;
     }
+    drop(input);
+
+    // The editor didn't start the server so it isn't the one to stop it
+    let mut child = match child {
+        Some(child) => child,
+        None => return,
+    };
+    if timeout(SHUTDOWN_TIMEOUT, child.wait()).await.is_err() {
+        let _ = child.kill().await;
+    }
+}
 
+/// Writes a message with the `Content-Length` header every transport uses.
+async fn write_message(input: &mut BufWriter<Writer>, message: &serde_json::Value) -> io::Result<()> {
+    let message = serde_json::to_string(message).expect("Failed to serialize json");
+    let message = format!("Content-Length: {}\r\n\r\n{}", message.len(), message);
+    // The server might have exited already, which the caller needs to hear about
+    input.write_all(message.as_bytes()).await?;
+    match input.flush().await {
+        Ok(_) => {},
+        Err(err) => {
+            eprintln!("Failed to flush: {}", err);
+        },
+    }
+    Ok(())
+}
+
+impl Client {
 
     pub fn send_message(&mut self, message: serde_json::Value) -> io::Result<()> {
         //eprintln!("Sending messag");
-        let future = async {
-            let message = serde_json::to_string(&message).expect("Failed to serialize json");
-            let message = format!("Content-Length: {}\r\n\r\n{}", message.len(), message);
-            self.input.write_all(message.as_bytes()).await.expect("Failed to write");
-            match self.input.flush().await {
-                Ok(_) => {},
-                Err(err) => {
-                    eprintln!("Failed to flush: {}", err);
-                },
-            }
-            
+        let input = match self.input.as_mut() {
+            Some(input) => input,
+            None => return Err(io::Error::new(io::ErrorKind::BrokenPipe, "Language server was shut down")),
         };
-        block_on(future);
-        Ok(())
+        block_on(write_message(input, &message))
     }
-    pub fn initialize(&mut self) -> io::Result<()> {
-        let message = serde_json::json!({
+
+    /// Asks the server to shut down and leaves the waiting for it to a task, so the controller isn't held up.
+    /// Only the first call starts the task.
+    /// Has to be called from inside of a tokio runtime.
+    pub fn shut_down(&mut self) -> Option<JoinHandle<()>> {
+        // A server that already exited can't be told to shut down
+        let told = self.send_shutdown().is_ok();
+        let input = self.input.take()?;
+        let answered = self.shutdown.take().filter(|_| told);
+        Some(tokio::spawn(stop_server(input, self.child.take(), answered)))
+    }
+    /// The first of the workspace folders is the root of the project.
+    pub fn initialize(&mut self, initialization_options: Option<serde_json::Value>, workspace_folders: &[PathBuf]) -> io::Result<()> {
+        let root_uri = workspace_folders.first().map(|root| path_to_uri(root));
+        let folders = workspace_folders.iter()
+            .map(|folder| workspace_folder(folder))
+            .collect::<Vec<_>>();
+        let mut message = serde_json::json!({
             "jsonrpc": "2.0",
             "id": 1,
             "method": "initialize",
@@ -122,11 +236,45 @@ impl Client {
                     "name": "vi",
                     "version": "0.0.1",
                 },
+                "rootUri": root_uri,
+                "workspaceFolders": if folders.is_empty() { serde_json::Value::Null } else { folders.into() },
                 "capabilities": {
                     "diagnostics": {
                         "refreshSupport": true,
                     },
+                    "workspace": {
+                        "workspaceFolders": true,
+                        "applyEdit": true,
+                        "configuration": true,
+                    },
                     "textDocument": {
+                        "rename": {
+                            "prepareSupport": false,
+                        },
+                        "formatting": {},
+                        "rangeFormatting": {},
+                        "codeAction": {
+                            "codeActionLiteralSupport": {
+                                "codeActionKind": {
+                                    "valueSet": [
+                                        "",
+                                        "quickfix",
+                                        "refactor",
+                                        "refactor.extract",
+                                        "refactor.inline",
+                                        "refactor.rewrite",
+                                        "source",
+                                        "source.organizeImports",
+                                    ],
+                                },
+                            },
+                        },
+                        "hover": {
+                            "contentFormat": [
+                                "markdown",
+                                "plaintext",
+                            ],
+                        },
                         "completion": {
                             "snippetSupport": true,
                             "insertReplaceSupport": true,
@@ -168,6 +316,9 @@ impl Client {
                 },
             },
         });
+        if let Some(options) = initialization_options {
+            message["params"]["initializationOptions"] = options;
+        }
         self.send_message(message)?;
 
         self.send_inialized()?;
@@ -175,7 +326,22 @@ impl Client {
         Ok(())
     }
 
-    pub fn send_did_open(&mut self, language_id: &str, uri: &str, text: &str) -> io::Result<()> {
+    pub fn add_workspace_folder(&mut self, folder: &Path) -> io::Result<()> {
+        let message = serde_json::json!({
+            "jsonrpc": "2.0",
+            "method": "workspace/didChangeWorkspaceFolders",
+            "params": {
+                "event": {
+                    "added": [workspace_folder(folder)],
+                    "removed": [],
+                },
+            },
+        });
+        self.send_message(message)?;
+        Ok(())
+    }
+
+    pub fn send_did_open(&mut self, language_id: &str, uri: &str, version: usize, text: &str) -> io::Result<()> {
         let message = serde_json::json!({
             "jsonrpc": "2.0",
             "method": "textDocument/didOpen",
@@ -183,7 +349,7 @@ impl Client {
                 "textDocument": {
                     "uri": uri,
                     "languageId": language_id,
-                    "version": 1,
+                    "version": version,
                     "text": text,
                 },
             },
@@ -192,7 +358,14 @@ impl Client {
         Ok(())
     }
 
-    pub fn did_change_text(&mut self, uri: &str, version: usize, text: &str) -> io::Result<()> {
+    /// Sends just the changes if the server takes them and the whole text otherwise.
+    pub fn did_change_text(&mut self, uri: &str, version: usize, changes: &[TextChange], text: &str) -> io::Result<()> {
+        let content_changes = if self.incremental_sync {
+            changes.iter().map(TextChange::to_json).collect::<Vec<_>>()
+        }
+        else {
+            vec![serde_json::json!({ "text": text })]
+        };
         let message = serde_json::json!({
             "jsonrpc": "2.0",
             "method": "textDocument/didChange",
@@ -201,11 +374,7 @@ impl Client {
                     "uri": uri,
                     "version": version,
                 },
-                "contentChanges": [
-                    {
-                        "text": text,
-                    },
-                ],
+                "contentChanges": content_changes,
             },
         });
         self.send_message(message)?;
@@ -366,6 +535,186 @@ impl Client {
         Ok(())
     }
 
+    pub fn request_hover(&mut self, uri: Box<str>, (x, y): (usize, usize)) -> io::Result<()> {
+        let message = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 7,
+            "method": "textDocument/hover",
+            "params": {
+                "textDocument": {
+                    "uri": uri,
+                },
+                "position": {
+                    "line": y,
+                    "character": x,
+                },
+            },
+        });
+        self.send_message(message)?;
+        Ok(())
+    }
+
+    pub fn find_references(&mut self, uri: Box<str>, (x, y): (usize, usize), include_declaration: bool) -> io::Result<()> {
+        let message = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 8,
+            "method": "textDocument/references",
+            "params": {
+                "textDocument": {
+                    "uri": uri,
+                },
+                "position": {
+                    "line": y,
+                    "character": x,
+                },
+                "context": {
+                    "includeDeclaration": include_declaration,
+                },
+            },
+        });
+        self.send_message(message)?;
+        Ok(())
+    }
+
+    pub fn rename(&mut self, uri: Box<str>, (x, y): (usize, usize), new_name: Box<str>) -> io::Result<()> {
+        let message = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 9,
+            "method": "textDocument/rename",
+            "params": {
+                "textDocument": {
+                    "uri": uri,
+                },
+                "position": {
+                    "line": y,
+                    "character": x,
+                },
+                "newName": new_name,
+            },
+        });
+        self.send_message(message)?;
+        Ok(())
+    }
+
+    pub fn range_formatting(&mut self, uri: Box<str>, ((x1, y1), (x2, y2)): ((usize, usize), (usize, usize)), options: FormattingOptions) -> io::Result<()> {
+        let message = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 11,
+            "method": "textDocument/rangeFormatting",
+            "params": {
+                "textDocument": {
+                    "uri": uri,
+                },
+                "range": {
+                    "start": {
+                        "line": y1,
+                        "character": x1,
+                    },
+                    "end": {
+                        "line": y2,
+                        "character": x2,
+                    },
+                },
+                "options": {
+                    "tabSize": options.tab_size,
+                    "insertSpaces": options.insert_spaces,
+                },
+            },
+        });
+        self.send_message(message)?;
+        Ok(())
+    }
+
+    pub fn code_action(&mut self, uri: Box<str>, ((x1, y1), (x2, y2)): ((usize, usize), (usize, usize)), diagnostics: &[Diagnostic]) -> io::Result<()> {
+        let diagnostics = diagnostics.iter().map(Diagnostic::to_json).collect::<Vec<_>>();
+        let message = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 12,
+            "method": "textDocument/codeAction",
+            "params": {
+                "textDocument": {
+                    "uri": uri,
+                },
+                "range": {
+                    "start": {
+                        "line": y1,
+                        "character": x1,
+                    },
+                    "end": {
+                        "line": y2,
+                        "character": x2,
+                    },
+                },
+                "context": {
+                    "diagnostics": diagnostics,
+                    "triggerKind": 1,
+                },
+            },
+        });
+        self.send_message(message)?;
+        Ok(())
+    }
+
+    pub fn execute_command(&mut self, command: &LspCommand) -> io::Result<()> {
+        let message = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 13,
+            "method": "workspace/executeCommand",
+            "params": {
+                "command": command.command,
+                "arguments": command.arguments.clone().unwrap_or_default(),
+            },
+        });
+        self.send_message(message)?;
+        Ok(())
+    }
+
+    pub fn formatting(&mut self, uri: Box<str>, options: FormattingOptions) -> io::Result<()> {
+        let message = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 10,
+            "method": "textDocument/formatting",
+            "params": {
+                "textDocument": {
+                    "uri": uri,
+                },
+                "options": {
+                    "tabSize": options.tab_size,
+                    "insertSpaces": options.insert_spaces,
+                },
+            },
+        });
+        self.send_message(message)?;
+        Ok(())
+    }
+
+
+    /// Answers a `workspace/configuration` from the server with one value for each item it asked for.
+    pub fn configuration_response(&mut self, id: serde_json::Value, values: Vec<serde_json::Value>) -> io::Result<()> {
+        let message = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "result": values,
+        });
+        self.send_message(message)?;
+        Ok(())
+    }
+
+    /// Answers a `workspace/applyEdit` from the server with whether the edit was made.
+    pub fn apply_edit_response(&mut self, id: serde_json::Value, applied: bool) -> io::Result<()> {
+        let mut message = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "result": {
+                "applied": applied,
+            },
+        });
+        if !applied {
+            message["result"]["failureReason"] = "The edit was cancelled".into();
+        }
+        self.send_message(message)?;
+        Ok(())
+    }
 
     pub fn send_inialized(&mut self) -> io::Result<()> {
         let message = serde_json::json!({
@@ -380,7 +729,7 @@ impl Client {
     pub fn send_shutdown(&mut self) -> io::Result<()> {
         let message = serde_json::json!({
             "jsonrpc": "2.0",
-            "id": 1,
+            "id": SHUTDOWN_ID,
             "method": "shutdown",
         });
         self.send_message(message)?;
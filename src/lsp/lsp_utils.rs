@@ -1,4 +1,4 @@
-use std::io;
+use std::{io, path::{Path, PathBuf}};
 
 use serde::Deserialize;
 use serde_json::Value;
@@ -12,7 +12,19 @@ pub enum LSPMessage {
     Diagnostics(Diagnostics),
     Completions(CompletionList),
     Location(LocationResponse),
-    
+    /// The text of a hover, empty when there is nothing to show.
+    Hover(String),
+    WorkspaceEdit(WorkspaceEdit),
+    Formatting(Option<Vec<TextEdit>>),
+    CodeActions(Vec<CodeAction>),
+    /// An edit the server wants made and the id of its request, which has to be answered.
+    ApplyEdit(WorkspaceEdit, Value),
+    /// A message for the user with its type, from 1 for errors to 4 for logs.
+    ShowMessage(u64, String),
+    /// A message for the log with its type.
+    LogMessage(u64, String),
+    /// The server asks for its settings, with the section of each item and the id of the request.
+    Configuration(Vec<Option<String>>, Value),
 }
 
 #[derive(Debug, Deserialize, PartialEq)]
@@ -43,23 +55,44 @@ impl Diagnostics {
     }
 
     pub fn get_diagnostic(&self, line: usize, character: usize) -> Option<&Diagnostic> {
-        ////eprintln!("{:?}", self.diagnostics);
-        for diagnostic in &self.diagnostics {
-            let start_line = diagnostic.range.start.line;
-            let end_line = diagnostic.range.end.line;
-            let start_character = diagnostic.range.start.character;
-            let end_character = diagnostic.range.end.character;
-            if line >= start_line && line <= end_line {
-                if character >= start_character && character <= end_character {
-                    return Some(diagnostic);
-                }
-            }
+        self.get_diagnostics(line, character).into_iter().next()
+    }
+
+    /// All the diagnostics whose range covers a position, in the order the server sent them.
+    pub fn get_diagnostics(&self, line: usize, character: usize) -> Vec<&Diagnostic> {
+        self.diagnostics.iter().filter(|diagnostic| {
+            let start = (diagnostic.range.start.line, diagnostic.range.start.character);
+            let end = (diagnostic.range.end.line, diagnostic.range.end.character);
+            start <= (line, character) && (line, character) <= end
+        }).collect()
+    }
+
+    /// The closest diagnostic that starts after a (line, character) position, or before it when going backwards.
+    /// This wraps around at the ends of the file and skips diagnostics of other severities if one is given.
+    pub fn next_diagnostic(&self, position: (usize, usize), forward: bool, severity: Option<usize>) -> Option<&Diagnostic> {
+        let mut diagnostics = self.diagnostics.iter()
+            .filter(|diagnostic| severity.is_none() || severity == Some(diagnostic.severity))
+            .collect::<Vec<&Diagnostic>>();
+        diagnostics.sort_by_key(|diagnostic| (diagnostic.range.start.line, diagnostic.range.start.character));
+
+        let start = |diagnostic: &&&Diagnostic| (diagnostic.range.start.line, diagnostic.range.start.character);
+        if forward {
+            diagnostics.iter().find(|diagnostic| start(diagnostic) > position).or(diagnostics.first()).copied()
+        }
+        else {
+            diagnostics.iter().rev().find(|diagnostic| start(diagnostic) < position).or(diagnostics.last()).copied()
         }
-        None
     }
+}
 
-    pub fn merge(&mut self, other: Diagnostics) {
-        self.diagnostics.extend(other.diagnostics);
+/// Reads the name of a severity into the number the server uses for it.
+pub fn parse_severity(name: &str) -> Option<usize> {
+    match name.to_lowercase().as_str() {
+        "error" | "e" => Some(1),
+        "warning" | "warn" | "w" => Some(2),
+        "information" | "info" | "i" => Some(3),
+        "hint" | "h" => Some(4),
+        _ => None,
     }
 }
 
@@ -91,6 +124,104 @@ pub struct Diagnostic {
     pub source: Option<String>,
 }
 
+impl Diagnostic {
+    /// The diagnostic as the server sent it, for sending it back as context.
+    pub fn to_json(&self) -> Value {
+        let mut json = serde_json::json!({
+            "range": {
+                "start": { "line": self.range.start.line, "character": self.range.start.character },
+                "end": { "line": self.range.end.line, "character": self.range.end.character },
+            },
+            "severity": self.severity,
+            "message": self.message,
+        });
+        if let Some(code) = &self.code {
+            json["code"] = Value::from(code.clone());
+        }
+        if let Some(source) = &self.source {
+            json["source"] = Value::from(source.clone());
+        }
+        json
+    }
+
+    pub fn severity_name(&self) -> &'static str {
+        match self.severity {
+            1 => "Error",
+            2 => "Warning",
+            3 => "Information",
+            _ => "Hint",
+        }
+    }
+
+    /// The severity, source and code followed by the message wrapped to fit in a width.
+    pub fn popup_lines(&self, width: usize) -> Vec<String> {
+        let mut header = self.severity_name().to_string();
+        let label = [&self.source, &self.code].into_iter()
+            .flatten()
+            .map(|s| s.as_str())
+            .collect::<Vec<&str>>()
+            .join(" ");
+        if !label.is_empty() {
+            header.push_str(&format!(" [{}]", label));
+        }
+        header.push(':');
+
+        let mut lines = vec![header];
+        for line in self.message.lines() {
+            lines.extend(wrap_text(line, width));
+        }
+        lines
+    }
+}
+
+/// Shortens text to the width, ending it with `…` when some of it was cut off.
+fn truncate_chars(text: &[char], width: usize) -> String {
+    if text.len() <= width {
+        return text.iter().collect();
+    }
+    let mut output = text[..width.saturating_sub(1)].iter().collect::<String>();
+    if width > 0 {
+        output.push('…');
+    }
+    output
+}
+
+/// Breaks a line at spaces so no piece is wider than the width.
+/// Words that are too long by themselves get split.
+pub fn wrap_text(text: &str, width: usize) -> Vec<String> {
+    let width = width.max(1);
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    let mut line_len = 0;
+
+    for word in text.split_whitespace() {
+        let mut word = word.chars().collect::<Vec<char>>();
+
+        if line_len > 0 && line_len + 1 + word.len() > width {
+            lines.push(std::mem::take(&mut line));
+            line_len = 0;
+        }
+
+        while word.len() > width {
+            let rest = word.split_off(width);
+            lines.push(word.into_iter().collect());
+            word = rest;
+        }
+
+        if line_len > 0 {
+            line.push(' ');
+            line_len += 1;
+        }
+        line_len += word.len();
+        line.extend(word);
+    }
+
+    if line_len > 0 || lines.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
 #[derive(Debug, Deserialize, PartialEq, Hash, Eq, Clone, Copy)]
 pub struct LSPRange {
     pub start: Position,
@@ -111,6 +242,9 @@ pub struct Position {
     pub character: usize,
 }
 
+/// The `insertTextFormat` of a completion whose text is a snippet.
+pub const SNIPPET_FORMAT: usize = 2;
+
 #[allow(non_snake_case)]
 #[derive(Debug, Deserialize, PartialEq, Clone)]
 pub struct CompletionList {
@@ -119,6 +253,36 @@ pub struct CompletionList {
 }
 
 impl CompletionList {
+    /// A list of plain words, for completing without a language server.
+    pub fn from_words(words: Vec<String>) -> Self {
+        CompletionList {
+            isIncomplete: false,
+            items: words.into_iter().map(CompletionItem::word).collect(),
+        }
+    }
+
+    /// A list of the names of files, each replacing the columns `start..end` of the row.
+    pub fn from_paths(names: Vec<String>, row: usize, (start, end): (usize, usize)) -> Self {
+        let items = names.into_iter()
+            .map(|name| {
+                let mut item = CompletionItem::word(name.clone());
+                item.kind = if name.ends_with('/') { Some(19) } else { Some(17) };
+                item.textEdit = Some(serde_json::json!({
+                    "range": {
+                        "start": { "line": row, "character": start },
+                        "end": { "line": row, "character": end },
+                    },
+                    "newText": name,
+                }));
+                item
+            })
+            .collect();
+        CompletionList {
+            isIncomplete: false,
+            items,
+        }
+    }
+
     pub fn generate_text(&self) -> Vec<(String, String)> {
         let mut result = Vec::new();
         for item in &self.items {
@@ -144,17 +308,19 @@ impl CompletionList {
     pub fn generate_buttons(&self, max_len: usize) -> PromptType {
         let mut buttons = Vec::new();
         for (i, item) in self.items.iter().enumerate() {
-            let (text, info) = item.generate_text();
-
-            let other_half = max_len - text.chars().count() - 1;
-            
-            let label = format!("{} {:>remain$}", text, info, remain=other_half);
-
-            buttons.push((label, item.generate_button(i)));
+            buttons.push((item.dropdown_row(max_len), item.generate_button(i)));
         }
 
         PromptType::Button(buttons, 0)
     }
+
+    /// How wide the rows of the dropdown need to be to fit every label and detail.
+    pub fn row_width(&self) -> usize {
+        self.items.iter()
+            .map(|item| item.label.chars().count() + 1 + item.row_detail().chars().count())
+            .max()
+            .unwrap_or(0)
+    }
 }
 
 #[allow(non_snake_case)]
@@ -162,7 +328,7 @@ impl CompletionList {
 pub struct CompletionItem {
     pub label: String,
     pub labelDetails: Option<CompletionItemLabelDetails>,
-    pub kind: usize,
+    pub kind: Option<usize>,
     pub score: Option<f64>,
     pub tags: Option<Vec<usize>>,
     pub detail: Option<String>,
@@ -183,44 +349,44 @@ pub struct CompletionItem {
 }
 
 impl CompletionItem {
-    pub fn generate_text(&self) -> (String, String) {
+    /// A completion that is only a word, shown as text.
+    pub fn word(label: String) -> Self {
+        CompletionItem {
+            label,
+            labelDetails: None,
+            kind: Some(1),
+            score: None,
+            tags: None,
+            detail: None,
+            documentation: None,
+            deprecated: None,
+            preselect: None,
+            sortText: None,
+            filterText: None,
+            insertText: None,
+            insertTextFormat: None,
+            insertTextMode: None,
+            textEdit: None,
+            textEditText: None,
+            additionalTextEdits: None,
+            commitCharacters: None,
+            command: None,
+            data: None,
+        }
+    }
 
-        let kind = match self.kind {
-            1 => "Text",
-            2 => "Method",
-            3 => "Function",
-            4 => "Constructor",
-            5 => "Field",
-            6 => "Variable",
-            7 => "Class",
-            8 => "Interface",
-            9 => "Module",
-            10 => "Property",
-            11 => "Unit",
-            12 => "Value",
-            13 => "Enum",
-            14 => "Keyword",
-            15 => "Snippet",
-            16 => "Color",
-            17 => "File",
-            18 => "Reference",
-            19 => "Folder",
-            20 => "EnumMember",
-            21 => "Constant",
-            22 => "Struct",
-            23 => "Event",
-            24 => "Operator",
-            25 => "TypeParameter",
-            _ => "Unknown",
+    pub fn generate_text(&self) -> (String, String) {
+        let info = match self.kind {
+            None => self.label.clone(),
+            Some(_) => format!("({}) {}", self.kind_name(), self.label),
         };
-        
-        let info = format!("({}) {}", kind, self.label);
-
 
         let mut insert_text = String::new();
-        if let Some(text_edit) = &self.textEdit {
-            let text_edit: TextEdit = serde_json::from_value(text_edit.clone()).unwrap();
-            insert_text = text_edit.newText.clone();
+        if let Some(text_edit) = self.get_edit_text() {
+            insert_text = match text_edit {
+                TextEditType::TextEdit(text_edit) => text_edit.newText,
+                TextEditType::InsertReplaceEdit(text_edit) => text_edit.new_text,
+            };
         } else if let Some(text_edit) = &self.textEditText {
             insert_text = text_edit.clone();
         } else if let Some(text_edit) = &self.insertText {
@@ -229,6 +395,75 @@ impl CompletionItem {
         (insert_text, info)
     }
 
+    pub fn kind_name(&self) -> &'static str {
+        match self.kind {
+            Some(1) => "Text",
+            Some(2) => "Method",
+            Some(3) => "Function",
+            Some(4) => "Constructor",
+            Some(5) => "Field",
+            Some(6) => "Variable",
+            Some(7) => "Class",
+            Some(8) => "Interface",
+            Some(9) => "Module",
+            Some(10) => "Property",
+            Some(11) => "Unit",
+            Some(12) => "Value",
+            Some(13) => "Enum",
+            Some(14) => "Keyword",
+            Some(15) => "Snippet",
+            Some(16) => "Color",
+            Some(17) => "File",
+            Some(18) => "Reference",
+            Some(19) => "Folder",
+            Some(20) => "EnumMember",
+            Some(21) => "Constant",
+            Some(22) => "Struct",
+            Some(23) => "Event",
+            Some(24) => "Operator",
+            Some(25) => "TypeParameter",
+            _ => "Unknown",
+        }
+    }
+
+    /// What goes on the right of the label in the dropdown, the type of the item if the server gave it.
+    fn row_detail(&self) -> String {
+        match &self.detail {
+            Some(detail) if !detail.trim().is_empty() => detail.lines().next().unwrap_or("").trim().to_string(),
+            _ if self.kind.is_none() => String::new(),
+            _ => format!("({})", self.kind_name()),
+        }
+    }
+
+    /// The label with the detail right aligned after it, cut down to fit in the width.
+    pub fn dropdown_row(&self, width: usize) -> String {
+        let label = self.label.chars().collect::<Vec<char>>();
+        let detail = self.row_detail().chars().collect::<Vec<char>>();
+
+        if label.len() >= width {
+            return truncate_chars(&label, width);
+        }
+        let room = width - label.len();
+        let detail = if detail.len() < room {
+            detail.iter().collect::<String>()
+        }
+        else {
+            truncate_chars(&detail, room.saturating_sub(1))
+        };
+        let label = label.iter().collect::<String>();
+        format!("{}{:>room$}", label, detail, room = room)
+    }
+
+    /// The documentation of the item as plain text.
+    pub fn documentation_text(&self) -> Option<String> {
+        let text = match self.documentation.as_ref()? {
+            // A string by itself is plain text and not markdown
+            Value::String(text) => text.clone(),
+            documentation => hover_text(documentation),
+        };
+        (!text.trim().is_empty()).then_some(text)
+    }
+
     pub fn generate_button(&self, index: usize) -> Box<dyn Fn(&dyn Promptable) -> String> {
         /*let mut insert_text = String::new();
         if let Some(text_edit) = &self.textEdit {
@@ -300,10 +535,241 @@ impl TextEdit {
     }
 }
 
+/// A change to a document for `textDocument/didChange`.
+/// Positions are (column, row) and a change without a range replaces the whole text.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextChange {
+    pub range: Option<((usize, usize), (usize, usize))>,
+    pub text: String,
+}
+
+impl TextChange {
+    pub fn to_json(&self) -> Value {
+        match self.range {
+            None => serde_json::json!({ "text": self.text }),
+            Some(((x1, y1), (x2, y2))) => serde_json::json!({
+                "range": {
+                    "start": { "line": y1, "character": x1 },
+                    "end": { "line": y2, "character": x2 },
+                },
+                "text": self.text,
+            }),
+        }
+    }
+}
+
+/// Orders edits from the bottom of the file up so making one doesn't move the ones still to come.
+pub fn edits_from_bottom(edits: &[TextEdit]) -> Vec<&TextEdit> {
+    let mut edits = edits.iter().collect::<Vec<_>>();
+    // Stable so edits inserting at the same spot keep their order
+    edits.sort_by_key(|edit| (edit.range.start.line, edit.range.start.character));
+    edits.reverse();
+    edits
+}
+
+/// Makes the edits to some text, for files that aren't open in a pane.
+pub fn apply_text_edits(text: &str, edits: &[TextEdit]) -> String {
+    let mut text = text.to_string();
+    for edit in edits_from_bottom(edits) {
+        let start = byte_offset(&text, edit.range.start);
+        let end = byte_offset(&text, edit.range.end).max(start);
+        text.replace_range(start..end, &edit.newText);
+    }
+    text
+}
+
+/// Where a position ends up once the edits are made.
+/// A position inside of a replaced range moves to the start of the new text.
+pub fn position_after_edits(mut position: Position, edits: &[TextEdit]) -> Position {
+    for edit in edits_from_bottom(edits) {
+        let LSPRange { start, end } = edit.range;
+        if (position.line, position.character) < (start.line, start.character) {
+            continue;
+        }
+        if (position.line, position.character) < (end.line, end.character) {
+            position = start;
+            continue;
+        }
+
+        let new_lines = edit.newText.matches('\n').count();
+        let new_end_character = match edit.newText.rsplit_once('\n') {
+            None => start.character + edit.newText.chars().count(),
+            Some((_, last)) => last.chars().count(),
+        };
+
+        if position.line == end.line {
+            position.character = new_end_character + (position.character - end.character);
+        }
+        position.line = position.line - (end.line - start.line) + new_lines;
+    }
+    position
+}
+
+/// A command the server runs itself through `workspace/executeCommand`.
+#[derive(Debug, Deserialize, PartialEq, Clone)]
+pub struct LspCommand {
+    pub title: String,
+    pub command: String,
+    pub arguments: Option<Vec<Value>>,
+}
+
+/// Something the server can do to the code, like a quick fix for a diagnostic.
+/// The edit is made first and the command gets run after it.
+#[derive(Debug, PartialEq, Clone)]
+pub struct CodeAction {
+    pub title: String,
+    pub edit: Option<WorkspaceEdit>,
+    pub command: Option<LspCommand>,
+}
+
+/// Reads the result of a code action request, which mixes code actions and bare commands.
+/// Actions that the server marked as disabled are left out.
+pub fn code_actions(result: &Value) -> Vec<CodeAction> {
+    let mut actions = Vec::new();
+    for value in result.as_array().into_iter().flatten() {
+        let title = match value["title"].as_str() {
+            None => continue,
+            Some(title) => title.to_string(),
+        };
+        if !value["disabled"].is_null() {
+            continue;
+        }
+
+        // A bare command has the name of the command where a code action has an object
+        if value["command"].is_string() {
+            if let Ok(command) = serde_json::from_value::<LspCommand>(value.clone()) {
+                actions.push(CodeAction { title, edit: None, command: Some(command) });
+            }
+            continue;
+        }
+
+        let edit = value.get("edit").map(WorkspaceEdit::from_value);
+        let command = serde_json::from_value::<LspCommand>(value["command"].clone()).ok();
+        actions.push(CodeAction { title, edit, command });
+    }
+    actions
+}
+
+/// The options sent along with a request to format a file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FormattingOptions {
+    pub tab_size: usize,
+    pub insert_spaces: bool,
+}
+
+/// Files that mark the top directory of a project.
+pub const ROOT_MARKERS: [&str; 5] = ["Cargo.toml", ".git", "compile_commands.json", "go.mod", "pyproject.toml"];
+
+/// The path of a file from the root of the filesystem.
+/// Files that don't exist yet are taken to be in the current directory.
+pub fn absolute_path(path: &Path) -> PathBuf {
+    if let Ok(path) = path.canonicalize() {
+        return path;
+    }
+    match std::env::current_dir() {
+        Ok(dir) => dir.join(path),
+        Err(_) => path.to_path_buf(),
+    }
+}
+
+/// The closest directory above the file that holds one of the `ROOT_MARKERS`.
+/// Falls back to the directory of the file when none of them are found.
+pub fn find_root(file: &Path) -> PathBuf {
+    let file = absolute_path(file);
+    let directory = file.parent().map(Path::to_path_buf).unwrap_or(file);
+    directory.ancestors()
+        .find(|dir| ROOT_MARKERS.iter().any(|marker| dir.join(marker).exists()))
+        .unwrap_or(directory.as_path())
+        .to_path_buf()
+}
+
+/// A `file://` URI for the path, with the characters a URI can't hold escaped.
+pub fn path_to_uri(path: &Path) -> String {
+    let path = absolute_path(path);
+    let mut uri = String::from("file://");
+    for byte in path.to_string_lossy().bytes() {
+        match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'/' | b'-' | b'.' | b'_' | b'~' => uri.push(byte as char),
+            _ => uri.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    uri
+}
+
+/// The path of a `file://` URI with the escaped characters turned back.
+pub fn uri_to_path(uri: &str) -> PathBuf {
+    let path = uri.strip_prefix("file://").unwrap_or(uri).as_bytes();
+    let mut bytes = Vec::with_capacity(path.len());
+    let mut i = 0;
+    while i < path.len() {
+        let escaped = path.get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (path[i], escaped) {
+            (b'%', Some(byte)) => {
+                bytes.push(byte);
+                i += 3;
+            },
+            (byte, _) => {
+                bytes.push(byte);
+                i += 1;
+            },
+        }
+    }
+    PathBuf::from(String::from_utf8_lossy(&bytes).to_string())
+}
+
+/// The byte offset of a position, clamped to the end of its line or of the text.
+fn byte_offset(text: &str, position: Position) -> usize {
+    let mut offset = 0;
+    for (row, line) in text.split_inclusive('\n').enumerate() {
+        if row == position.line {
+            let line = line.trim_end_matches(['\n', '\r']);
+            return offset + line.char_indices()
+                .nth(position.character)
+                .map_or(line.len(), |(index, _)| index);
+        }
+        offset += line.len();
+    }
+    text.len()
+}
+
+/// The edits a server wants made across the workspace, grouped by the URI of each file.
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct WorkspaceEdit {
+    pub changes: Vec<(String, Vec<TextEdit>)>,
+}
+
+impl WorkspaceEdit {
+    /// Reads either `documentChanges` or `changes`.
+    /// Creating, renaming, and deleting files isn't supported so those operations are skipped.
+    pub fn from_value(value: &Value) -> Self {
+        let mut changes = Vec::new();
+        if let Some(document_changes) = value.get("documentChanges").and_then(Value::as_array) {
+            for change in document_changes {
+                let uri = change["textDocument"]["uri"].as_str();
+                let edits = serde_json::from_value::<Vec<TextEdit>>(change["edits"].clone());
+                if let (Some(uri), Ok(edits)) = (uri, edits) {
+                    changes.push((uri.to_string(), edits));
+                }
+            }
+        }
+        else if let Some(map) = value.get("changes").and_then(Value::as_object) {
+            for (uri, edits) in map {
+                if let Ok(edits) = serde_json::from_value::<Vec<TextEdit>>(edits.clone()) {
+                    changes.push((uri.clone(), edits));
+                }
+            }
+        }
+        WorkspaceEdit { changes }
+    }
+}
+
 #[derive(Debug, Deserialize, PartialEq, Hash, Eq, Clone)]
 pub struct InsertReplaceEdit {
     pub insert: LSPRange,
     pub replace: LSPRange,
+    #[serde(rename = "newText")]
     pub new_text: String,
 }
 
@@ -338,6 +804,216 @@ pub struct LocationLink {
     pub targetSelectionRange: LSPRange,
 }
 
+/// The name of the type of a `window/showMessage` or `window/logMessage`.
+pub fn message_type_name(kind: u64) -> &'static str {
+    match kind {
+        1 => "Error",
+        2 => "Warning",
+        3 => "Info",
+        _ => "Log",
+    }
+}
+
+/// Picks the part of the settings of a server that a `workspace/configuration` item asks for.
+/// The section is a dotted path, and anything that isn't set is an empty object so the server falls back on its defaults.
+pub fn configuration_section(settings: &Value, section: Option<&str>) -> Value {
+    let mut value = settings;
+    for part in section.into_iter().flat_map(|section| section.split('.')) {
+        value = match value.get(part) {
+            Some(value) => value,
+            None => return serde_json::json!({}),
+        };
+    }
+    if value.is_null() {
+        serde_json::json!({})
+    }
+    else {
+        value.clone()
+    }
+}
+
+/// Turns the contents of a hover into plain text.
+/// The contents can be a MarkupContent, a MarkedString or a list of MarkedStrings.
+pub fn hover_text(contents: &Value) -> String {
+    match contents {
+        Value::String(markdown) => strip_markdown(markdown),
+        Value::Array(items) => items.iter()
+            .map(hover_text)
+            .filter(|text| !text.trim().is_empty())
+            .collect::<Vec<String>>()
+            .join("\n\n"),
+        Value::Object(object) => {
+            let value = object.get("value").and_then(|value| value.as_str()).unwrap_or("");
+            match object.get("kind").and_then(|kind| kind.as_str()) {
+                Some("markdown") => strip_markdown(value),
+                // Plain text and the code blocks of MarkedStrings are shown as they are
+                _ => value.to_string(),
+            }
+        },
+        _ => String::new(),
+    }
+}
+
+/// Takes the markup out of markdown so it reads well as plain text.
+/// Code blocks keep their text but lose their fences.
+pub fn strip_markdown(markdown: &str) -> String {
+    let mut lines = Vec::new();
+    let mut in_code = false;
+
+    for line in markdown.lines() {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") {
+            in_code = !in_code;
+            continue;
+        }
+
+        if in_code {
+            lines.push(line.to_string());
+        }
+        else if trimmed.chars().all(|c| c == '-' || c == '_' || c == '*') && trimmed.len() >= 3 {
+            // A rule between sections
+            lines.push(String::new());
+        }
+        else {
+            let line = trimmed.trim_start_matches('#').trim_start();
+            lines.push(strip_inline_markdown(line));
+        }
+    }
+
+    lines.dedup_by(|line, previous| line.is_empty() && previous.is_empty());
+    while lines.last().is_some_and(|line| line.is_empty()) {
+        lines.pop();
+    }
+    lines.join("\n")
+}
+
+/// Removes emphasis, code spans, escapes and link targets from a line.
+fn strip_inline_markdown(line: &str) -> String {
+    let mut output = String::new();
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                if let Some(next) = chars.next() {
+                    output.push(next);
+                }
+            },
+            '`' => {},
+            '*' => {},
+            '[' => {
+                let mut text = String::new();
+                let mut closed = false;
+                for c in chars.by_ref() {
+                    if c == ']' {
+                        closed = true;
+                        break;
+                    }
+                    text.push(c);
+                }
+                output.push_str(&strip_inline_markdown(&text));
+                if !closed {
+                    continue;
+                }
+
+                // The target of the link isn't worth showing
+                if chars.peek() == Some(&'(') {
+                    for c in chars.by_ref() {
+                        if c == ')' {
+                            break;
+                        }
+                    }
+                }
+            },
+            c => output.push(c),
+        }
+    }
+
+    output
+}
+
+/// Splits the output of a language server into the bodies of its messages.
+/// Bytes are kept between calls so that a message split across several reads gets put back together.
+#[derive(Debug, Default)]
+pub struct MessageBuffer {
+    buffer: Vec<u8>,
+}
+
+impl MessageBuffer {
+    pub fn new() -> Self {
+        Self {
+            buffer: Vec::new(),
+        }
+    }
+
+    pub fn extend(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Returns the next whole message if there is one.
+    /// Every header up to the blank line is read but only `Content-Length` is used.
+    /// A frame with broken headers gets thrown away up to the next `Content-Length`.
+    pub fn next_message(&mut self) -> Option<String> {
+        loop {
+            let header_end = find_bytes(&self.buffer, b"\r\n\r\n")?;
+            let body_start = header_end + 4;
+
+            let content_length = String::from_utf8(self.buffer[..header_end].to_vec())
+                .ok()
+                .and_then(|headers| parse_content_length(&headers));
+
+            let content_length = match content_length {
+                Some(content_length) => content_length,
+                None => {
+                    match find_bytes(&self.buffer[1..], b"Content-Length:") {
+                        Some(index) => self.buffer.drain(..index + 1),
+                        None => self.buffer.drain(..body_start),
+                    };
+                    continue;
+                },
+            };
+
+            if self.buffer.len() < body_start + content_length {
+                return None;
+            }
+
+            let body = self.buffer[body_start..body_start + content_length].to_vec();
+            self.buffer.drain(..body_start + content_length);
+
+            match String::from_utf8(body) {
+                Ok(body) => return Some(body),
+                Err(_) => continue,
+            }
+        }
+    }
+}
+
+/// Gets the content length out of a block of headers.
+/// Returns `None` if any of the lines isn't a header since that means we are out of sync.
+fn parse_content_length(headers: &str) -> Option<usize> {
+    let mut content_length = None;
+
+    for line in headers.split("\r\n") {
+        let (name, value) = line.split_once(':')?;
+        let name = name.trim();
+
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+            return None;
+        }
+
+        if name.eq_ignore_ascii_case("Content-Length") {
+            content_length = Some(value.trim().parse::<usize>().ok()?);
+        }
+    }
+
+    content_length
+}
+
+fn find_bytes(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+
 pub fn process_json(json: Value) -> io::Result<LSPMessage> {
 
 
@@ -358,6 +1034,26 @@ pub fn process_json(json: Value) -> io::Result<LSPMessage> {
                 };
                 Ok(LSPMessage::Diagnostics(diagnostics))
             },
+            "window/showMessage" | "window/logMessage" => {
+                let kind = json["params"]["type"].as_u64().unwrap_or(4);
+                let message = json["params"]["message"].as_str().unwrap_or("").to_string();
+                if method == "window/showMessage" {
+                    Ok(LSPMessage::ShowMessage(kind, message))
+                }
+                else {
+                    Ok(LSPMessage::LogMessage(kind, message))
+                }
+            },
+            "workspace/configuration" => {
+                let sections = json["params"]["items"].as_array().into_iter().flatten()
+                    .map(|item| item["section"].as_str().map(|section| section.to_string()))
+                    .collect::<Vec<_>>();
+                Ok(LSPMessage::Configuration(sections, json["id"].clone()))
+            },
+            "workspace/applyEdit" => {
+                let edit = WorkspaceEdit::from_value(&json["params"]["edit"]);
+                Ok(LSPMessage::ApplyEdit(edit, json["id"].clone()))
+            },
 
             _ => {
                 println!("Unknown method: {}", method);
@@ -387,7 +1083,7 @@ pub fn process_json(json: Value) -> io::Result<LSPMessage> {
                 };
                 Ok(LSPMessage::Completions(completion_list))
             },
-            3 | 4 | 5 | 6 => {
+            3 | 4 | 5 | 6 | 8 => {
                 let obj = json["result"].clone();
 
                 if obj.is_array() {
@@ -404,7 +1100,7 @@ pub fn process_json(json: Value) -> io::Result<LSPMessage> {
                     Ok(LSPMessage::Location(locations))
                 }
                 else if obj.is_object() {
-                    if json.get("uri").is_some() {
+                    if obj.get("uri").is_some() {
                         let location: Location = match serde_json::from_value(obj) {
                             Ok(value) => value,
                             Err(e) => {
@@ -417,7 +1113,7 @@ pub fn process_json(json: Value) -> io::Result<LSPMessage> {
 
                         Ok(LSPMessage::Location(location))
                     }
-                    else if json.get("targetUri").is_some() {
+                    else if obj.get("targetUri").is_some() {
                         let location_link: LocationLink = match serde_json::from_value(obj) {
                             Ok(value) => value,
                             Err(e) => {
@@ -438,10 +1134,30 @@ pub fn process_json(json: Value) -> io::Result<LSPMessage> {
 
                 }
                 else {
-                    Ok(LSPMessage::None)
+                    // Nothing was found but whoever asked is still waiting for an answer
+                    Ok(LSPMessage::Location(LocationResponse::Null))
                 }
                 
                 
+            },
+            7 => {
+                // A null result means there is nothing under the cursor
+                let text = hover_text(&json["result"]["contents"]);
+                Ok(LSPMessage::Hover(text))
+            },
+            9 => {
+                // An error or a null result leaves nothing to change
+                let edit = WorkspaceEdit::from_value(&json["result"]);
+                Ok(LSPMessage::WorkspaceEdit(edit))
+            },
+            12 => {
+                // An error or a null result means there is nothing to do
+                Ok(LSPMessage::CodeActions(code_actions(&json["result"])))
+            },
+            10 | 11 => {
+                // Null or an error means the server can't format the file
+                let edits = serde_json::from_value::<Vec<TextEdit>>(json["result"].clone()).ok();
+                Ok(LSPMessage::Formatting(edits))
             },
             _ => {
                 //eprintln!("Unknown id: {}", id);
@@ -508,7 +1224,9 @@ mod tests {
         assert_eq!(value, LSPMessage::Diagnostics(Diagnostics {
             diagnostics: vec![
                 Diagnostic {
-                    code: "E0001".to_string(),
+                    code: Some("E0001".to_string()),
+                    code_description: None,
+                    data: None,
                     message: "this is a test".to_string(),
                     range: LSPRange {
                         start: Position {
@@ -521,14 +1239,390 @@ mod tests {
                         },
                     },
                     severity: 1,
-                    source: "rustc".to_string(),
+                    source: Some("rustc".to_string()),
                 }
             ],
             uri: "file:///home/username/project/src/main.rs".to_string(),
-            version: 0,
+            version: Some(0),
         }));
         
     }
 
+    #[test]
+    fn test_message_split_across_reads() {
+        let body = r#"{"jsonrpc":"2.0","id":1,"result":null}"#;
+        let message = format!("Content-Length: {}\r\n\r\n{}", body.len(), body);
+
+        let mut buffer = MessageBuffer::new();
+        let (first, rest) = message.as_bytes().split_at(10);
+        let (second, third) = rest.split_at(20);
+
+        buffer.extend(first);
+        assert_eq!(buffer.next_message(), None);
+        buffer.extend(second);
+        assert_eq!(buffer.next_message(), None);
+        buffer.extend(third);
+        assert_eq!(buffer.next_message(), Some(body.to_string()));
+        assert_eq!(buffer.next_message(), None);
+    }
+
+    #[test]
+    fn test_message_with_content_type() {
+        let body = r#"{"jsonrpc":"2.0","method":"initialized","params":{}}"#;
+        let message = format!("Content-Type: application/vscode-jsonrpc; charset=utf-8\r\nContent-Length: {}\r\n\r\n{}",
+                              body.len(),
+                              body);
+
+        let mut buffer = MessageBuffer::new();
+        buffer.extend(message.as_bytes());
+        buffer.extend(message.as_bytes());
+
+        assert_eq!(buffer.next_message(), Some(body.to_string()));
+        assert_eq!(buffer.next_message(), Some(body.to_string()));
+        assert_eq!(buffer.next_message(), None);
+    }
+
+    #[test]
+    fn test_message_resync() {
+        let body = r#"{"jsonrpc":"2.0","id":2,"result":[]}"#;
+        let message = format!("garbage}}\r\nContent-Length: {}\r\n\r\n{}", body.len(), body);
+
+        let mut buffer = MessageBuffer::new();
+        buffer.extend(message.as_bytes());
+
+        assert_eq!(buffer.next_message(), Some(body.to_string()));
+    }
+
+    fn diagnostic(start: (usize, usize), end: (usize, usize), message: &str) -> Diagnostic {
+        Diagnostic {
+            code: Some("E0308".to_string()),
+            code_description: None,
+            data: None,
+            message: message.to_string(),
+            range: LSPRange {
+                start: Position { line: start.0, character: start.1 },
+                end: Position { line: end.0, character: end.1 },
+            },
+            severity: 1,
+            source: Some("rustc".to_string()),
+        }
+    }
 
+    #[test]
+    fn test_get_diagnostics() {
+        let mut diagnostics = Diagnostics::new();
+        diagnostics.diagnostics.push(diagnostic((1, 10), (3, 2), "spans lines"));
+        diagnostics.diagnostics.push(diagnostic((2, 0), (2, 4), "inside"));
+
+        // The columns only bound the first and last line of a range
+        assert_eq!(diagnostics.get_diagnostics(2, 30).len(), 1);
+        assert_eq!(diagnostics.get_diagnostics(2, 3).len(), 2);
+        assert!(diagnostics.get_diagnostics(1, 5).is_empty());
+        assert!(diagnostics.get_diagnostics(3, 5).is_empty());
+    }
+
+    #[test]
+    fn test_next_diagnostic() {
+        let mut diagnostics = Diagnostics::new();
+        diagnostics.diagnostics.push(diagnostic((5, 0), (5, 3), "later"));
+        diagnostics.diagnostics.push(diagnostic((1, 4), (1, 8), "earlier"));
+        let mut warning = diagnostic((3, 0), (3, 1), "warning");
+        warning.severity = 2;
+        diagnostics.diagnostics.push(warning);
+
+        let message = |diagnostic: Option<&Diagnostic>| diagnostic.map(|diagnostic| diagnostic.message.clone());
+
+        assert_eq!(message(diagnostics.next_diagnostic((1, 4), true, None)), Some("warning".to_string()));
+        assert_eq!(message(diagnostics.next_diagnostic((1, 4), false, None)), Some("later".to_string()));
+        assert_eq!(message(diagnostics.next_diagnostic((6, 0), true, None)), Some("earlier".to_string()));
+        assert_eq!(message(diagnostics.next_diagnostic((1, 4), true, parse_severity("error"))), Some("later".to_string()));
+        assert_eq!(message(diagnostics.next_diagnostic((0, 0), false, Some(2))), Some("warning".to_string()));
+        assert_eq!(diagnostics.next_diagnostic((0, 0), true, Some(4)), None);
+    }
+
+    #[test]
+    fn test_hover_text() {
+        let markup = serde_json::json!({
+            "kind": "markdown",
+            "value": "```rust\nfn main()\n```\n\n---\n\nThe **entry** point, see [`std`](https://doc.rust-lang.org/std)\\_",
+        });
+        assert_eq!(hover_text(&markup), "fn main()\n\nThe entry point, see std_");
+
+        let marked = serde_json::json!([
+            { "language": "python", "value": "def f(x):  pass" },
+            "`f` returns nothing",
+        ]);
+        assert_eq!(hover_text(&marked), "def f(x):  pass\n\nf returns nothing");
+
+        let null = serde_json::json!({ "jsonrpc": "2.0", "id": 7, "result": null });
+        assert_eq!(process_json(null).unwrap(), LSPMessage::Hover(String::new()));
+    }
+
+    #[test]
+    fn test_completion_rows() {
+        let item = |value: serde_json::Value| serde_json::from_value::<CompletionItem>(value).unwrap();
+
+        let with_detail = item(serde_json::json!({
+            "label": "len",
+            "kind": 2,
+            "detail": "fn(&self) -> usize",
+            "documentation": { "kind": "markdown", "value": "Returns the **length**.\n\n```rust\nlet n = v.len();\n```" },
+        }));
+        assert_eq!(with_detail.dropdown_row(30), "len         fn(&self) -> usize");
+        assert_eq!(with_detail.dropdown_row(12), "len fn(&sel…");
+        assert_eq!(with_detail.dropdown_row(2), "l…");
+        assert_eq!(with_detail.documentation_text().unwrap(), "Returns the length.\n\nlet n = v.len();");
+
+        // Without a detail the kind is shown, and plain documentation isn't stripped
+        let without = item(serde_json::json!({ "label": "x", "kind": 6, "documentation": "a *b*" }));
+        assert_eq!(without.dropdown_row(12), "x (Variable)");
+        assert_eq!(without.documentation_text().unwrap(), "a *b*");
+
+        let list = CompletionList { isIncomplete: false, items: vec![with_detail, without] };
+        assert_eq!(list.row_width(), 22);
+    }
+
+    #[test]
+    fn test_references() {
+        let references = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 8,
+            "result": [
+                { "uri": "file:///src/main.rs", "range": { "start": { "line": 1, "character": 4 }, "end": { "line": 1, "character": 8 } } },
+                { "uri": "file:///src/lib.rs", "range": { "start": { "line": 9, "character": 0 }, "end": { "line": 9, "character": 4 } } },
+            ],
+        });
+        match process_json(references).unwrap() {
+            LSPMessage::Location(LocationResponse::Locations(locations)) => {
+                assert_eq!(locations.len(), 2);
+                assert_eq!(locations[1].uri, "file:///src/lib.rs");
+                assert_eq!(locations[1].range.get_positions().0, (0, 9));
+            },
+            message => panic!("Expected locations, got {:?}", message),
+        }
+
+        let none = serde_json::json!({ "jsonrpc": "2.0", "id": 8, "result": null });
+        assert_eq!(process_json(none).unwrap(), LSPMessage::Location(LocationResponse::Null));
+    }
+
+    #[test]
+    fn test_diagnostic_popup_lines() {
+        let diagnostic = diagnostic((0, 0), (0, 1), "mismatched types\nexpected `usize`, found `String`");
+
+        assert_eq!(diagnostic.popup_lines(16), vec![
+            "Error [rustc E0308]:".to_string(),
+            "mismatched types".to_string(),
+            "expected".to_string(),
+            "`usize`, found".to_string(),
+            "`String`".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn test_apply_text_edits() {
+        let edit = |start: (usize, usize), end: (usize, usize), text: &str| TextEdit {
+            range: LSPRange {
+                start: Position { line: start.0, character: start.1 },
+                end: Position { line: end.0, character: end.1 },
+            },
+            newText: text.to_string(),
+        };
+        let text = "let foo = 1;\nlet bar = foo + föo;\nfoo";
+
+        let edits = vec![
+            edit((1, 10), (1, 13), "value"),
+            edit((0, 4), (0, 7), "value"),
+            edit((1, 16), (1, 19), "x"),
+            edit((2, 0), (2, 3), "value"),
+        ];
+        assert_eq!(apply_text_edits(text, &edits), "let value = 1;\nlet bar = value + x;\nvalue");
+
+        // Positions past the end of a line or the text are clamped
+        let edits = vec![edit((0, 20), (0, 20), " // one"), edit((5, 0), (5, 0), "\n")];
+        assert_eq!(apply_text_edits("one\ntwo", &edits), "one // one\ntwo\n");
+    }
+
+    #[test]
+    fn test_server_requests() {
+        let show = serde_json::json!({ "jsonrpc": "2.0", "method": "window/showMessage", "params": { "type": 2, "message": "Failed to load workspace" } });
+        assert_eq!(process_json(show).unwrap(), LSPMessage::ShowMessage(2, "Failed to load workspace".to_string()));
+
+        let log = serde_json::json!({ "jsonrpc": "2.0", "method": "window/logMessage", "params": { "type": 4, "message": "indexing" } });
+        assert_eq!(process_json(log).unwrap(), LSPMessage::LogMessage(4, "indexing".to_string()));
+
+        let configuration = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 3,
+            "method": "workspace/configuration",
+            "params": { "items": [{ "section": "rust-analyzer.cargo" }, { "scopeUri": "file:///src" }] },
+        });
+        assert_eq!(process_json(configuration).unwrap(),
+                   LSPMessage::Configuration(vec![Some("rust-analyzer.cargo".to_string()), None], serde_json::json!(3)));
+
+        let settings = serde_json::json!({ "rust-analyzer": { "cargo": { "features": "all" } } });
+        assert_eq!(configuration_section(&settings, Some("rust-analyzer.cargo")), serde_json::json!({ "features": "all" }));
+        assert_eq!(configuration_section(&settings, Some("rust-analyzer.check")), serde_json::json!({}));
+        assert_eq!(configuration_section(&settings, None), settings);
+        assert_eq!(configuration_section(&Value::Null, Some("pylsp")), serde_json::json!({}));
+    }
+
+    #[test]
+    fn test_workspace_edit() {
+        let range = serde_json::json!({
+            "start": { "line": 0, "character": 4 },
+            "end": { "line": 0, "character": 7 },
+        });
+        let changes = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 9,
+            "result": {
+                "changes": {
+                    "file:///src/main.rs": [{ "range": range, "newText": "bar" }],
+                },
+            },
+        });
+        match process_json(changes).unwrap() {
+            LSPMessage::WorkspaceEdit(edit) => {
+                assert_eq!(edit.changes.len(), 1);
+                assert_eq!(edit.changes[0].0, "file:///src/main.rs");
+                assert_eq!(edit.changes[0].1[0].newText, "bar");
+            },
+            message => panic!("Expected a workspace edit, got {:?}", message),
+        }
+
+        let document_changes = serde_json::json!({
+            "documentChanges": [
+                {
+                    "textDocument": { "uri": "file:///src/lib.rs", "version": 3 },
+                    "edits": [{ "range": range, "newText": "bar", "annotationId": "rename" }],
+                },
+                { "kind": "create", "uri": "file:///src/new.rs" },
+            ],
+        });
+        let edit = WorkspaceEdit::from_value(&document_changes);
+        assert_eq!(edit.changes.len(), 1);
+        assert_eq!(edit.changes[0].1[0].get_range(), ((4, 0), (7, 0)));
+
+        let error = serde_json::json!({ "jsonrpc": "2.0", "id": 9, "error": { "code": -32602, "message": "No references found" } });
+        assert_eq!(process_json(error).unwrap(), LSPMessage::WorkspaceEdit(WorkspaceEdit::default()));
+
+        // A request from the server has its own id, which the answer has to use
+        let apply = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": "apply-3",
+            "method": "workspace/applyEdit",
+            "params": { "label": "Inline variable", "edit": document_changes },
+        });
+        match process_json(apply).unwrap() {
+            LSPMessage::ApplyEdit(edit, id) => {
+                assert_eq!(edit.changes[0].0, "file:///src/lib.rs");
+                assert_eq!(id, "apply-3");
+            },
+            message => panic!("Expected an edit to apply, got {:?}", message),
+        }
+    }
+
+    #[test]
+    fn test_position_after_edits() {
+        let edit = |start: (usize, usize), end: (usize, usize), text: &str| TextEdit {
+            range: LSPRange {
+                start: Position { line: start.0, character: start.1 },
+                end: Position { line: end.0, character: end.1 },
+            },
+            newText: text.to_string(),
+        };
+        let position = |line: usize, character: usize| Position { line, character };
+
+        // "fn main(){\nlet x=1;\n}" formatted into "fn main() {\n    let x = 1;\n}"
+        let edits = vec![
+            edit((0, 9), (0, 9), " "),
+            edit((1, 0), (1, 0), "    "),
+            edit((1, 5), (1, 6), " = "),
+        ];
+        assert_eq!(position_after_edits(position(1, 6), &edits), position(1, 12));
+        assert_eq!(position_after_edits(position(1, 4), &edits), position(1, 8));
+        assert_eq!(position_after_edits(position(0, 3), &edits), position(0, 3));
+        assert_eq!(position_after_edits(position(2, 0), &edits), position(2, 0));
+
+        // Joining lines moves the rows below up and the rest of the joined line over
+        let edits = vec![edit((0, 3), (2, 1), " ")];
+        assert_eq!(position_after_edits(position(2, 4), &edits), position(0, 7));
+        assert_eq!(position_after_edits(position(3, 2), &edits), position(1, 2));
+        assert_eq!(position_after_edits(position(1, 0), &edits), position(0, 3));
+    }
+
+    #[test]
+    fn test_code_actions() {
+        let json = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 12,
+            "result": [
+                {
+                    "title": "Remove unused import",
+                    "kind": "quickfix",
+                    "edit": {
+                        "changes": {
+                            "file:///src/main.rs": [{
+                                "range": {
+                                    "start": { "line": 0, "character": 0 },
+                                    "end": { "line": 1, "character": 0 },
+                                },
+                                "newText": "",
+                            }],
+                        },
+                    },
+                },
+                { "title": "Run test", "command": "rust-analyzer.runSingle", "arguments": [1] },
+                { "title": "Extract function", "disabled": { "reason": "Nothing selected" } },
+                {
+                    "title": "Organize imports",
+                    "command": { "title": "Organize imports", "command": "organize" },
+                },
+            ],
+        });
+
+        let actions = match process_json(json).unwrap() {
+            LSPMessage::CodeActions(actions) => actions,
+            message => panic!("Expected code actions, got {:?}", message),
+        };
+        assert_eq!(actions.len(), 3);
+
+        assert_eq!(actions[0].title, "Remove unused import");
+        assert_eq!(actions[0].edit.as_ref().map(|edit| edit.changes.len()), Some(1));
+        assert!(actions[0].command.is_none());
+
+        assert!(actions[1].edit.is_none());
+        assert_eq!(actions[1].command.as_ref().map(|command| command.command.as_str()), Some("rust-analyzer.runSingle"));
+        assert_eq!(actions[1].command.as_ref().and_then(|command| command.arguments.clone()), Some(vec![Value::from(1)]));
+
+        assert_eq!(actions[2].command.as_ref().map(|command| command.command.as_str()), Some("organize"));
+
+        let none = serde_json::json!({ "jsonrpc": "2.0", "id": 12, "result": null });
+        assert_eq!(process_json(none).unwrap(), LSPMessage::CodeActions(Vec::new()));
+    }
+
+    #[test]
+    fn test_uri_round_trip() {
+        let path = PathBuf::from("/home/user/my project/src/main.rs");
+        let uri = path_to_uri(&path);
+        assert_eq!(uri, "file:///home/user/my%20project/src/main.rs");
+        assert_eq!(uri_to_path(&uri), path);
+
+        // A `%` that isn't followed by a byte is left alone
+        assert_eq!(uri_to_path("file:///tmp/100%"), PathBuf::from("/tmp/100%"));
+    }
+
+    #[test]
+    fn test_find_root() {
+        let project = std::env::temp_dir().join("vi_find_root_test");
+        let source = project.join("src").join("nested");
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::write(project.join("Cargo.toml"), "").unwrap();
+        std::fs::write(source.join("main.rs"), "").unwrap();
+
+        let root = find_root(&source.join("main.rs"));
+        assert_eq!(root, project.canonicalize().unwrap());
+
+        std::fs::remove_dir_all(&project).unwrap();
+    }
 }
@@ -1,18 +1,49 @@
-use std::{collections::HashMap, sync::{mpsc::{Sender, Receiver}, Arc}, io, process::Stdio, fmt::Display, };
-use futures::executor::block_on;
-use futures::FutureExt;
+use std::{collections::HashMap, sync::{mpsc::{Sender, Receiver}, Arc, Mutex}, io, process::Stdio, fmt::Display, path::PathBuf, time::{Duration, Instant}};
+use futures::{executor::block_on, future::join_all};
 use serde_json::Value;
-use tokio::process::Command;
+use tokio::{process::Command, net::TcpStream};
 
-use crate::lsp::lsp_utils::{process_json, LSPMessage};
+use crate::lsp::lsp_utils::{process_json, LSPMessage, configuration_section, message_type_name};
+use crate::settings::{LspServerConfig, LspTransport};
 
-use self::{lsp_client::Client, lsp_utils::{Diagnostics, CompletionList, LocationResponse}};
+use self::{lsp_client::Client, lsp_utils::{Diagnostics, CompletionList, LocationResponse, WorkspaceEdit, FormattingOptions, TextEdit, Diagnostic, CodeAction, LspCommand, TextChange}};
 
 pub mod lsp_client;
 pub mod lsp_utils;
 
+/// How many lines of what the servers logged are kept.
+const MAX_LOG_LINES: usize = 1000;
+
+/// How long a server the editor started gets to start listening on its port.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The answers of the server of a language, shared by all of the panes of the language.
+/// It is made on the thread of the controller, so it needs the mutex to be sent to the editor.
+pub type SharedReceiver = Arc<Mutex<Receiver<ControllerMessage>>>;
+
+/// What the server of a language said about itself in its answer to `initialize`.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct LspInfo {
+    /// Typing one of these in Insert mode asks the server for completions.
+    pub trigger_characters: Vec<String>,
+}
+
+impl LspInfo {
+    pub fn from_capabilities(capabilities: &Value) -> Self {
+        let trigger_characters = capabilities["completionProvider"]["triggerCharacters"].as_array()
+            .map(|characters| characters.iter().filter_map(|c| c.as_str()).map(str::to_string).collect())
+            .unwrap_or_default();
+        LspInfo {
+            trigger_characters,
+        }
+    }
+}
+
+/// The info of the server of a language, shared by all of the panes of the language.
+/// It stays empty until the server answers `initialize`.
+pub type SharedInfo = Arc<Mutex<LspInfo>>;
+
 
-unsafe impl Send for LspRequest {}
 pub enum LspRequest {
     /// Tells the server to shutdown
     Shutdown,
@@ -30,27 +61,49 @@ pub enum LspRequest {
     GotoTypeDefinition(Box<str>, (usize, usize)),
     /// Requires a URI and a position
     GotoImplementation(Box<str>, (usize, usize)),
+    /// Requires a URI and a position
+    Hover(Box<str>, (usize, usize)),
+    /// Requires a URI, a position, and whether to include the declaration
+    References(Box<str>, (usize, usize), bool),
+    /// Requires a URI, a position, and the new name
+    Rename(Box<str>, (usize, usize), Box<str>),
+    /// Requires a URI and the options to format with
+    Formatting(Box<str>, FormattingOptions),
+    /// Requires a URI, the start and end of the range, and the options to format with
+    RangeFormatting(Box<str>, ((usize, usize), (usize, usize)), FormattingOptions),
+    /// Requires a URI, the start and end of the range, and the diagnostics in it
+    CodeAction(Box<str>, ((usize, usize), (usize, usize)), Vec<Diagnostic>),
+    /// Requires the command to run
+    ExecuteCommand(LspCommand),
 
 }
 
-unsafe impl Send for LspResponse {}
-
 pub enum LspResponse {
     PublishDiagnostics(Diagnostics),
     Completion(CompletionList),
     Location(LocationResponse),
-
+    /// The text of a hover, empty when there is nothing to show.
+    Hover(String),
+    /// The edits to make, empty when the server couldn't make any.
+    WorkspaceEdit(WorkspaceEdit),
+    /// The edits that format a file, `None` when the server can't format it.
+    Formatting(Option<Vec<TextEdit>>),
+    /// The actions that can be taken, empty when there are none.
+    CodeActions(Vec<CodeAction>),
+    /// An edit the server wants made, any pane of the language makes it and answers the server with the id.
+    ApplyEdit(WorkspaceEdit, Value),
 }
 
-unsafe impl Send for LspNotification {}
 pub enum LspNotification {
     /// 0 is the uri
     /// 1 is the version
-    /// 2 is the text
-    ChangeText(Box<str>, usize, Box<str>),
+    /// 2 is the changes since the last version
+    /// 3 is the whole text, for servers that don't take just the changes
+    ChangeText(Box<str>, usize, Vec<TextChange>, Box<str>),
     /// 0 is the uri
-    /// 1 is the text
-    Open(Box<str>, Box<str>),
+    /// 1 is the version
+    /// 2 is the text
+    Open(Box<str>, usize, Box<str>),
     /// 0 is the uri
     Close(Box<str>),
     /// 0 is the uri
@@ -59,13 +112,15 @@ pub enum LspNotification {
     /// 0 is the uri
     /// 1 is the reason
     WillSave(Box<str>, Box<str>),
+    /// The answer to a `workspace/applyEdit`
+    /// 0 is the id of the request
+    /// 1 is whether the edit was made
+    EditApplied(Value, bool),
 
 
 }
 
 
-unsafe impl Send for ControllerMessage {}
-
 pub enum ControllerMessage {
     /// String is the language id
     Request(Box<str>, LspRequest),
@@ -73,13 +128,38 @@ pub enum ControllerMessage {
     Response(LspResponse),
     /// Box<str> is the language id
     Notification(Box<str>, LspNotification),
-    /// String is the language id
-    CreateClient(Box<str>),
+    /// String is the language id, the config is the server the settings give for it
+    /// and the path is the root of the project of the file being opened.
+    /// The sender goes to the reader of the server so what it says comes in with everything else.
+    CreateClient(Box<str>, Option<LspServerConfig>, Option<PathBuf>, Sender<ControllerMessage>),
     /// Notification to tell the caller how to recieve responses
-    /// The receiver is for the language server side
-    ClientCreated(Arc<Receiver<ControllerMessage>>),
+    /// The receiver is for the language server side and the info is what the server can do
+    ClientCreated(SharedReceiver, SharedInfo),
     /// Notification to tell the caller that there is no client for the language
-    NoClient,
+    /// Holds the reason if a server was found but couldn't be started
+    NoClient(Option<String>),
+    /// Stops the server of the language and starts it again with the config or the built in one
+    /// The sender goes to the reader of the new server
+    RestartClient(Box<str>, Option<LspServerConfig>, Sender<ControllerMessage>),
+    /// Stops the server of the language, the panes of the language keep their channel
+    StopClient(Box<str>),
+    /// Tells the caller that the server was stopped
+    ClientStopped,
+    /// Asks which servers are running
+    Status,
+    /// The language of every running server and how it is running
+    ClientStatus(Vec<(String, String)>),
+    /// A message the server of the language sent
+    ServerMessage(Box<str>, Value),
+    /// A message a server wants the user to see
+    /// 0 is the language
+    /// 1 is the type of the message, from 1 for errors to 4 for logs
+    /// 2 is the text
+    ShowMessage(Box<str>, u64, String),
+    /// Asks for what the servers logged
+    Log,
+    /// The lines the servers logged, oldest first
+    LogLines(Vec<String>),
     Resend(Box<str>, LspResponse),
     Exit,
 
@@ -97,14 +177,24 @@ impl Drop for LspController {
     }
 }
 
-unsafe impl Send for LspController {}
-
 pub struct LspController {
     clients: HashMap<String, Client>,
     //channels: (Sender<ControllerMessage>, Receiver<ControllerMessage>),
+    /// Where the editor and the readers of the servers' output send to.
+    /// The controller holds no sender of its own so it stops once all of them are gone.
     listen: Option<Receiver<ControllerMessage>>,
     response: Option<Sender<ControllerMessage>>,
-    server_channels: HashMap<String, (Sender<ControllerMessage>, Arc<Receiver<ControllerMessage>>)>,
+    server_channels: HashMap<String, (Sender<ControllerMessage>, SharedReceiver)>,
+    /// What the server of each language can do, kept when the server is restarted
+    infos: HashMap<String, SharedInfo>,
+    /// The project roots the server of each language has been told about
+    workspace_folders: HashMap<String, Vec<PathBuf>>,
+    /// What the settings give each server when it asks for its configuration
+    configurations: HashMap<String, Value>,
+    /// What the servers logged and showed, oldest first
+    log: Vec<String>,
+    /// How many times the controller woke up for a message.
+    wakeups: usize,
     exit: bool,
 }
 
@@ -119,6 +209,11 @@ impl LspController {
             listen: None,
             response: None,
             server_channels: HashMap::new(),
+            infos: HashMap::new(),
+            workspace_folders: HashMap::new(),
+            configurations: HashMap::new(),
+            log: Vec::new(),
+            wakeups: 0,
             exit: false,
             
         }
@@ -137,122 +232,145 @@ impl LspController {
     pub fn run(&mut self) -> io::Result<()> {
         //eprintln!("Running lsp controller");
         while !self.exit {
-            self.check_messages()?;
-
-            
-            let future = self.check_clients();
-            let _ = block_on(future);
-                        
-
+            self.handle_next()?;
         }
         Ok(())
     }
 
-    async fn check_client(client: &mut Client) -> io::Result<Value> {
-        let future = client.process_messages();
-        let val = future.await;
-        let json = val?;
-        Ok(json)
+    /// Shuts every server down and waits for them to exit or be killed, for when the editor is closing.
+    pub fn stop_servers(&mut self) {
+        let stopping = self.clients.drain()
+            .filter_map(|(_, mut client)| client.shut_down())
+            .collect::<Vec<_>>();
+        block_on(join_all(stopping));
     }
 
-    async fn check_clients(&mut self) -> io::Result<()> {
-
-        for (language, client) in self.clients.iter_mut() {
-
-            let json;
-            let mut future = Self::check_client(client).boxed();
-
-            if let Some(value) = (&mut future).now_or_never() {
-                json = value?;
-                ////eprintln!("Got json");
-            } else {
-                continue;
-                //json = future.await?;
+    /// Passes what a server sent on to the panes of its language.
+    fn handle_server_message(&mut self, language: &str, json: Value) {
+        // The answer to `initialize` says how the server wants changes sent
+        if json["id"] == 1 {
+            if let Some(capabilities) = json["result"].get("capabilities") {
+                if let Some(client) = self.clients.get_mut(language) {
+                    client.read_capabilities(capabilities);
+                }
+                if let Some(info) = self.infos.get(language) {
+                    *info.lock().expect("Lsp info poisoned") = LspInfo::from_capabilities(capabilities);
+                }
             }
+        }
 
-            //eprintln!("Json for: {} \n{:#?}", language, json);
-
-            match process_json(json).expect("Failed to process json") {
-                LSPMessage::Diagnostics(diagnostics) => {
-                    ////eprintln!("Got diagnostics");
-                    let sender = self.server_channels.get(language).unwrap().0.clone();
-
-                    let message = ControllerMessage::Response(
-                        LspResponse::PublishDiagnostics(diagnostics)
-                    );
-
-                    sender.send(message).expect("Failed to send diagnostics");
-                },
-                LSPMessage::Completions(completion) => {
-                    //eprintln!("Got completion");
-                    let sender = self.server_channels.get(language).unwrap().0.clone();
-
-                    let message = ControllerMessage::Response(
-                        LspResponse::Completion(completion)
-                    );
-
-                    sender.send(message).expect("Failed to send completions");
-                },
-                LSPMessage::Location(location) => {
-                    //eprintln!("Got location");
-
-                    let sender = self.server_channels.get(language).unwrap().0.clone();
-
-                    let message = ControllerMessage::Response(
-                        LspResponse::Location(location)
-                    );
-
-                    sender.send(message).expect("Failed to send location");
-                },
-                LSPMessage::None => {
-                    ////eprintln!("Got none");
-                    continue;
+        //eprintln!("Json for: {} \n{:#?}", language, json);
+
+        let response = match process_json(json).expect("Failed to process json") {
+            LSPMessage::Diagnostics(diagnostics) => LspResponse::PublishDiagnostics(diagnostics),
+            LSPMessage::Completions(completion) => LspResponse::Completion(completion),
+            LSPMessage::Location(location) => LspResponse::Location(location),
+            LSPMessage::Hover(text) => LspResponse::Hover(text),
+            LSPMessage::WorkspaceEdit(edit) => LspResponse::WorkspaceEdit(edit),
+            LSPMessage::Formatting(edits) => LspResponse::Formatting(edits),
+            LSPMessage::CodeActions(actions) => LspResponse::CodeActions(actions),
+            LSPMessage::ApplyEdit(edit, id) => LspResponse::ApplyEdit(edit, id),
+            LSPMessage::ShowMessage(kind, message) => {
+                self.add_to_log(language, kind, &message);
+                let message = ControllerMessage::ShowMessage(language.into(), kind, message);
+                self.response.as_ref().unwrap().send(message).expect("Failed to send message");
+                return;
+            },
+            LSPMessage::LogMessage(kind, message) => {
+                self.add_to_log(language, kind, &message);
+                return;
+            },
+            LSPMessage::Configuration(sections, id) => {
+                // The server waits for the answer so it is given right away
+                let settings = self.configurations.get(language).unwrap_or(&Value::Null);
+                let values = sections.iter()
+                    .map(|section| configuration_section(settings, section.as_deref()))
+                    .collect();
+                if let Some(client) = self.clients.get_mut(language) {
+                    let _ = client.configuration_response(id, values);
                 }
-            }
+                return;
+            },
+            LSPMessage::None => return,
+        };
+
+        if let Some((sender, _)) = self.server_channels.get(language) {
+            sender.send(ControllerMessage::Response(response)).expect("Failed to send response");
         }
+    }
 
-        Ok(())
+    fn add_to_log(&mut self, language: &str, kind: u64, message: &str) {
+        for line in message.lines() {
+            self.log.push(format!("[{}] {}: {}", language, message_type_name(kind), line));
+        }
+        if self.log.len() > MAX_LOG_LINES {
+            self.log.drain(..self.log.len() - MAX_LOG_LINES);
+        }
     }
 
+    /// Sleeps until a message comes in from the editor or a server and handles it.
+    fn handle_next(&mut self) -> io::Result<()> {
+        let message = match self.listen.as_ref().unwrap().recv() {
+            Ok(message) => message,
+            // Nothing is left to send anything
+            Err(_) => {
+                self.exit = true;
+                return Ok(());
+            },
+        };
+        self.wakeups += 1;
+
+        self.handle_message(message)
+    }
 
-    fn check_messages(&mut self) -> io::Result<()> {
-       
-        match self.listen.as_ref().unwrap().try_recv() {
-            Ok(ControllerMessage::CreateClient(lang)) => {
-                self.create_client(lang)
+    fn handle_message(&mut self, message: ControllerMessage) -> io::Result<()> {
+        match message {
+            ControllerMessage::ServerMessage(language, json) => {
+                self.handle_server_message(&language, json);
             },
-            Ok(ControllerMessage::Request(lang, req)) => {
-                self.check_request(lang, req)
+            ControllerMessage::CreateClient(lang, config, root, sender) => {
+                self.create_client(lang, config, root, sender)?;
             },
-            Ok(ControllerMessage::Notification(lang, notif)) => {
-                self.check_notification(lang, notif)
+            ControllerMessage::RestartClient(lang, config, sender) => {
+                self.restart_client(lang, config, sender)?;
             },
-            Ok(ControllerMessage::Exit) => {
-                self.exit = true;
-                return Ok(());
+            ControllerMessage::StopClient(lang) => {
+                self.stop_client(&lang)?;
             },
-            Err(std::sync::mpsc::TryRecvError::Disconnected) => {
-                return Err(io::Error::new(io::ErrorKind::Other, "Channel disconnected"));
-            }
-            Err(_) => {
-                return Ok(());
+            ControllerMessage::Status => {
+                let mut status = self.clients.iter()
+                    .map(|(lang, client)| (lang.clone(), client.status()))
+                    .collect::<Vec<_>>();
+                status.sort();
+                self.response.as_ref().unwrap().send(ControllerMessage::ClientStatus(status)).unwrap();
             },
-            _ => {
-                return Ok(());
-            }
+            ControllerMessage::Log => {
+                self.response.as_ref().unwrap().send(ControllerMessage::LogLines(self.log.clone())).unwrap();
+            },
+            // A server that died or was stopped shouldn't stop the others from being talked to
+            ControllerMessage::Request(lang, req) => {
+                let _ = self.check_request(lang, req);
+            },
+            ControllerMessage::Notification(lang, notif) => {
+                let _ = self.check_notification(lang, notif);
+            },
+            ControllerMessage::Exit => {
+                self.exit = true;
+            },
+            _ => {},
         }
-
+        Ok(())
     }
 
     fn check_notification<R>(&mut self, lang: R, notif: LspNotification) -> io::Result<()> where R: AsRef<str> + Display {
         match self.clients.get_mut(&lang.to_string()) {
             Some(client) => {
                 match notif {
-                    LspNotification::ChangeText(uri, version, text) => {
-                        client.did_change_text(uri.as_ref(), version, text.as_ref())?;
+                    LspNotification::ChangeText(uri, version, changes, text) => {
+                        client.did_change_text(uri.as_ref(), version, &changes, text.as_ref())?;
                     },
-                    LspNotification::Open(uri, text) => {
-                        client.send_did_open(&lang.to_string(),uri.as_ref(), text.as_ref())?;
+                    LspNotification::Open(uri, version, text) => {
+                        client.send_did_open(lang.as_ref(),uri.as_ref(), version, text.as_ref())?;
                     },
                     LspNotification::Close(uri) => {
                         client.did_close(uri.as_ref())?;
@@ -271,6 +389,9 @@ impl LspController {
                         };
                         client.will_save_text(uri.as_ref(), reason)?;
                     },
+                    LspNotification::EditApplied(id, applied) => {
+                        client.apply_edit_response(id, applied)?;
+                    },
                 }
             },
             None => {
@@ -318,6 +439,27 @@ impl LspController {
                     LspRequest::GotoImplementation(uri, pos) => {
                         client.goto_implementation(uri, pos)?;
                     },
+                    LspRequest::Hover(uri, pos) => {
+                        client.request_hover(uri, pos)?;
+                    },
+                    LspRequest::References(uri, pos, include_declaration) => {
+                        client.find_references(uri, pos, include_declaration)?;
+                    },
+                    LspRequest::Rename(uri, pos, new_name) => {
+                        client.rename(uri, pos, new_name)?;
+                    },
+                    LspRequest::Formatting(uri, options) => {
+                        client.formatting(uri, options)?;
+                    },
+                    LspRequest::RangeFormatting(uri, range, options) => {
+                        client.range_formatting(uri, range, options)?;
+                    },
+                    LspRequest::CodeAction(uri, range, diagnostics) => {
+                        client.code_action(uri, range, &diagnostics)?;
+                    },
+                    LspRequest::ExecuteCommand(command) => {
+                        client.execute_command(&command)?;
+                    },
                 }
             },
             None => {
@@ -328,128 +470,454 @@ impl LspController {
         Ok(())
     }
 
-    fn create_client<R>(&mut self, lang: R) -> io::Result<()> where R: AsRef<str> {
-        let client = match lang.as_ref() {
-            "rust" => {
-                if let Some((_, recv)) = self.server_channels.get("rust") {
-                    self.response.as_ref().unwrap().send(ControllerMessage::ClientCreated(recv.clone())).unwrap();
-                    return Ok(());
-                }
-                let rust_analyzer = Command::new("rust-analyzer")
+    /// The servers that get started when the settings don't name one for the language.
+    fn builtin_server(lang: &str) -> Option<LspServerConfig> {
+        let command = match lang {
+            "rust" => "rust-analyzer",
+            "c" | "cpp" => "clangd",
+            "python" => "python-lsp-server",
+            "swift" => "sourcekit-lsp",
+            "go" => "gopls",
+            "bash" => "bash-language-server",
+            _ => return None,
+        };
+        Some(LspServerConfig {
+            command: command.to_string(),
+            ..Default::default()
+        })
+    }
+
+    fn start_client(&self, lang: &str, config: &LspServerConfig, workspace_folders: &[PathBuf], sender: Sender<ControllerMessage>) -> io::Result<Client> {
+        let mut lsp_client = match config.transport {
+            LspTransport::Stdio => {
+                let server = Command::new(&config.command)
+                    .args(&config.args)
+                    .envs(&config.env)
                     .stdin(Stdio::piped())
                     .stdout(Stdio::piped())
                     .spawn()?;
+                lsp_client::Client::new(server, lang, sender)
+            },
+            LspTransport::Tcp => {
+                // Without a command the server is expected to be running already
+                let server = if config.command.is_empty() {
+                    None
+                }
+                else {
+                    let server = Command::new(&config.command)
+                        .args(&config.args)
+                        .envs(&config.env)
+                        .stdin(Stdio::null())
+                        .stdout(Stdio::null())
+                        .kill_on_drop(true)
+                        .spawn()?;
+                    Some(server)
+                };
+                let address = format!("{}:{}", config.host.as_deref().unwrap_or("127.0.0.1"), config.port.unwrap_or_default());
+                let stream = Self::connect(&address, server.is_some())?;
+                lsp_client::Client::connect(server, &address, stream, lang, sender)
+            },
+        };
 
-                let mut lsp_client = lsp_client::Client::new(rust_analyzer);
+        lsp_client.initialize(config.initialization_options.clone(), workspace_folders)?;
 
-                lsp_client.initialize()?;
+        Ok(lsp_client)
+    }
 
-                lsp_client
-            },
-            "c" | "cpp" => {
+    /// Connects to a server listening at `address`.
+    /// A server that was just started gets some time to start listening.
+    fn connect(address: &str, just_started: bool) -> io::Result<TcpStream> {
+        let deadline = Instant::now() + CONNECT_TIMEOUT;
+        loop {
+            match std::net::TcpStream::connect(address) {
+                Ok(stream) => {
+                    stream.set_nonblocking(true)?;
+                    return TcpStream::from_std(stream);
+                },
+                Err(_) if just_started && Instant::now() < deadline => std::thread::sleep(Duration::from_millis(50)),
+                Err(err) => return Err(io::Error::new(err.kind(), format!("{}: {}", address, err))),
+            }
+        }
+    }
 
-                if let Some((_, recv)) = self.server_channels.get(lang.as_ref()) {
-                    self.response.as_ref().unwrap().send(ControllerMessage::ClientCreated(recv.clone())).unwrap();
-                    return Ok(());
+    /// Starts the server for the language from `config` or the built in one.
+    /// If the server can't be started the caller gets told why instead of the controller stopping.
+    fn create_client<R>(&mut self, lang: R, config: Option<LspServerConfig>, root: Option<PathBuf>, sender: Sender<ControllerMessage>) -> io::Result<()> where R: AsRef<str> {
+        if let Some(root) = root {
+            let folders = self.workspace_folders.entry(lang.as_ref().to_string()).or_default();
+            if !folders.contains(&root) {
+                folders.push(root.clone());
+                // A server that is already running gets told about the new project
+                if let Some(client) = self.clients.get_mut(lang.as_ref()) {
+                    let _ = client.add_workspace_folder(&root);
                 }
+            }
+        }
 
-                let clangd = Command::new("clangd")
-                    .stdin(Stdio::piped())
-                    .stdout(Stdio::piped())
-                    .spawn()?;
+        if let (Some((_, recv)), Some(info)) = (self.server_channels.get(lang.as_ref()), self.infos.get(lang.as_ref())) {
+            self.response.as_ref().unwrap().send(ControllerMessage::ClientCreated(recv.clone(), info.clone())).unwrap();
+            return Ok(());
+        }
 
-                let mut lsp_client = lsp_client::Client::new(clangd);
+        self.start_server(lang.as_ref(), config, sender);
+        Ok(())
+    }
 
-                lsp_client.initialize()?;
+    /// Replaces the server of the language with a new one.
+    /// The channel of the language is kept so the panes that are open keep working.
+    fn restart_client(&mut self, lang: Box<str>, config: Option<LspServerConfig>, sender: Sender<ControllerMessage>) -> io::Result<()> {
+        // Dropping the client shuts the old server down
+        self.clients.remove(&*lang);
 
-                lsp_client
-            },
-            "python" => {
-                if let Some((_, recv)) = self.server_channels.get(lang.as_ref()) {
-                    self.response.as_ref().unwrap().send(ControllerMessage::ClientCreated(recv.clone())).unwrap();
-                    return Ok(());
-                }
-                let python_lsp = Command::new("python-lsp-server")
-                    .stdin(Stdio::piped())
-                    .stdout(Stdio::piped())
-                    .spawn()?;
+        self.start_server(&lang, config, sender);
+        Ok(())
+    }
 
-                let mut lsp_client = lsp_client::Client::new(python_lsp);
+    fn start_server(&mut self, lang: &str, config: Option<LspServerConfig>, sender: Sender<ControllerMessage>) {
+        let config = match config.or_else(|| Self::builtin_server(lang)) {
+            Some(config) => config,
+            None => {
+                self.response.as_ref().unwrap().send(ControllerMessage::NoClient(None)).unwrap();
+                return;
+            }
+        };
 
-                lsp_client.initialize()?;
+        match &config.configuration {
+            Some(configuration) => self.configurations.insert(lang.to_string(), configuration.clone()),
+            None => self.configurations.remove(lang),
+        };
 
-                lsp_client
-            },
-            "swift" => {
-                if let Some((_, recv)) = self.server_channels.get(lang.as_ref()) {
-                    self.response.as_ref().unwrap().send(ControllerMessage::ClientCreated(recv.clone())).unwrap();
-                    return Ok(());
-                }
-                let apple_swift = Command::new("sourcekit-lsp")
-                    .stdin(Stdio::piped())
-                    .stdout(Stdio::piped())
-                    .spawn()?;
+        let folders = self.workspace_folders.get(lang).cloned().unwrap_or_default();
+        let client = match self.start_client(lang, &config, &folders, sender) {
+            Ok(client) => client,
+            Err(err) => {
+                let reason = match config.transport {
+                    LspTransport::Stdio => format!("Failed to start {} for {}: {}", config.command, lang, err),
+                    LspTransport::Tcp => format!("Failed to connect to the server for {}: {}", lang, err),
+                };
+                self.response.as_ref().unwrap().send(ControllerMessage::NoClient(Some(reason))).unwrap();
+                return;
+            }
+        };
 
-                let mut lsp_client = lsp_client::Client::new(apple_swift);
+        //let client = Arc::new(Mutex::new(client));
 
-                lsp_client.initialize()?;
+        self.clients.insert(lang.to_string(), client);
 
-                lsp_client
-            },
-            "go" => {
-                if let Some((_, recv)) = self.server_channels.get(lang.as_ref()) {
-                    self.response.as_ref().unwrap().send(ControllerMessage::ClientCreated(recv.clone())).unwrap();
-                    return Ok(());
-                }
-                let gopls = Command::new("gopls")
-                    .stdin(Stdio::piped())
-                    .stdout(Stdio::piped())
-                    .spawn()?;
+        let (_, recv) = self.server_channels.entry(lang.to_string())
+            .or_insert_with(|| {
+                let (tx, rx) = std::sync::mpsc::channel();
+                (tx, Arc::new(Mutex::new(rx)))
+            });
 
-                let mut lsp_client = lsp_client::Client::new(gopls);
+        // A new server says again what it can do
+        let info = self.infos.entry(lang.to_string()).or_default();
+        *info.lock().expect("Lsp info poisoned") = LspInfo::default();
 
-                lsp_client.initialize()?;
+        self.response.as_ref().unwrap().send(ControllerMessage::ClientCreated(recv.clone(), info.clone())).unwrap();
+    }
 
-                lsp_client
-            },
-            "bash" => {
-                if let Some((_, recv)) = self.server_channels.get(lang.as_ref()) {
-                    self.response.as_ref().unwrap().send(ControllerMessage::ClientCreated(recv.clone())).unwrap();
-                    return Ok(());
-                }
-                let bash_lsp = Command::new("bash-language-server")
-                    .stdin(Stdio::piped())
-                    .stdout(Stdio::piped())
-                    .spawn()?;
+    fn stop_client(&mut self, lang: &str) -> io::Result<()> {
+        let message = match self.clients.remove(lang) {
+            Some(_) => ControllerMessage::ClientStopped,
+            None => ControllerMessage::NoClient(None),
+        };
+        self.response.as_ref().unwrap().send(message).unwrap();
+        Ok(())
+    }
 
-                let mut lsp_client = lsp_client::Client::new(bash_lsp);
+    
 
-                lsp_client.initialize()?;
 
-                lsp_client
-            },
-            _ => {
-                self.response.as_ref().unwrap().send(ControllerMessage::NoClient).unwrap();
-                return Ok(());
-            }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::{Read, Write}, net::TcpListener};
+
+    use super::*;
+
+    fn assert_send<T: Send>() {}
+
+    #[test]
+    fn test_messages_are_send() {
+        assert_send::<ControllerMessage>();
+        assert_send::<LspController>();
+    }
+
+    /// Handles what is already waiting for the controller without sleeping.
+    /// Returns how many messages there were.
+    fn handle_waiting(controller: &mut LspController) -> usize {
+        let mut handled = 0;
+        while let Ok(message) = controller.listen.as_ref().unwrap().try_recv() {
+            controller.handle_message(message).unwrap();
+            handled += 1;
+        }
+        handled
+    }
+
+    #[test]
+    fn test_controller_waits_for_messages() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let _guard = runtime.enter();
+
+        let (sender, listen) = std::sync::mpsc::channel();
+        let (response, responses) = std::sync::mpsc::channel();
+        let mut controller = LspController::new();
+        controller.set_listen(listen);
+        controller.set_response(response);
+
+        // `cat` sends back everything it gets, which is enough of a server to talk to
+        let config = LspServerConfig {
+            command: "cat".to_string(),
+            ..Default::default()
         };
+        sender.send(ControllerMessage::CreateClient("fake".into(), Some(config), None, sender.clone())).unwrap();
+        controller.handle_next().unwrap();
+        assert!(matches!(responses.try_recv(), Ok(ControllerMessage::ClientCreated(..))));
+
+        // The echoed initialize messages come in from the reader of the server on the same channel
+        std::thread::sleep(Duration::from_millis(200));
+        assert!(handle_waiting(&mut controller) > 0);
+
+        // With nothing to do the controller sleeps until something comes in, without waking up to check
+        let wakeups = controller.wakeups;
+        let waker = sender.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(200));
+            waker.send(ControllerMessage::Status).unwrap();
+        });
+        let start = Instant::now();
+        controller.handle_next().unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(200));
+        assert_eq!(controller.wakeups, wakeups + 1);
+        assert!(matches!(responses.try_recv(), Ok(ControllerMessage::ClientStatus(status)) if status.len() == 1));
+    }
 
-        let (tx, rx) = std::sync::mpsc::channel();
+    #[test]
+    fn test_controller_stops_on_exit() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let _guard = runtime.enter();
 
-        let rx = Arc::new(rx);
+        let (sender, listen) = std::sync::mpsc::channel();
+        let (response, responses) = std::sync::mpsc::channel();
+        let mut controller = LspController::new();
+        controller.set_listen(listen);
+        controller.set_response(response);
 
-        self.server_channels.insert(lang.as_ref().to_string(), (tx, rx.clone()));
+        let config = LspServerConfig {
+            command: "cat".to_string(),
+            ..Default::default()
+        };
+        sender.send(ControllerMessage::CreateClient("fake".into(), Some(config), None, sender.clone())).unwrap();
+        controller.handle_next().unwrap();
+        assert!(matches!(responses.try_recv(), Ok(ControllerMessage::ClientCreated(..))));
+
+        // The reader of the running server still holds a sender, so the controller is told to stop
+        sender.send(ControllerMessage::Exit).unwrap();
+        controller.run().unwrap();
+        assert!(controller.exit);
+    }
 
-        //let client = Arc::new(Mutex::new(client));
+    #[test]
+    fn test_controller_stops_without_senders() {
+        let (sender, listen) = std::sync::mpsc::channel();
+        let (response, _responses) = std::sync::mpsc::channel();
+        let mut controller = LspController::new();
+        controller.set_listen(listen);
+        controller.set_response(response);
+
+        sender.send(ControllerMessage::Log).unwrap();
+        drop(sender);
+
+        // What was sent is still handled before the controller stops
+        controller.run().unwrap();
+        assert!(controller.exit);
+        assert_eq!(controller.wakeups, 1);
+    }
 
-        self.clients.insert(lang.as_ref().to_string(), client);
+    #[test]
+    fn test_server_messages() {
+        let (response, responses) = std::sync::mpsc::channel();
+        let mut controller = LspController::new();
+        controller.set_response(response);
+
+        controller.handle_server_message("rust", serde_json::json!({
+            "jsonrpc": "2.0",
+            "method": "window/showMessage",
+            "params": { "type": 1, "message": "Failed to load workspace" },
+        }));
+        controller.handle_server_message("rust", serde_json::json!({
+            "jsonrpc": "2.0",
+            "method": "window/logMessage",
+            "params": { "type": 3, "message": "one\ntwo" },
+        }));
+
+        assert!(matches!(responses.try_recv(),
+                         Ok(ControllerMessage::ShowMessage(lang, 1, text)) if &*lang == "rust" && text == "Failed to load workspace"));
+        assert!(responses.try_recv().is_err());
+        assert_eq!(controller.log, vec![
+            "[rust] Error: Failed to load workspace".to_string(),
+            "[rust] Info: one".to_string(),
+            "[rust] Info: two".to_string(),
+        ]);
+    }
 
-        self.response.as_ref().unwrap().send(ControllerMessage::ClientCreated(rx)).unwrap();
+    fn tcp_config(port: u16) -> LspServerConfig {
+        LspServerConfig {
+            transport: LspTransport::Tcp,
+            port: Some(port),
+            ..Default::default()
+        }
+    }
 
-        Ok(())
+    #[test]
+    fn test_tcp_server() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let _guard = runtime.enter();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let (sender, listen) = std::sync::mpsc::channel();
+        let (response, responses) = std::sync::mpsc::channel();
+        let mut controller = LspController::new();
+        controller.set_listen(listen);
+        controller.set_response(response);
+
+        sender.send(ControllerMessage::CreateClient("fake".into(), Some(tcp_config(port)), None, sender.clone())).unwrap();
+        controller.handle_next().unwrap();
+        assert!(matches!(responses.try_recv(), Ok(ControllerMessage::ClientCreated(..))));
+
+        // The server gets the same framing as over stdio
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut header = [0; 16];
+        stream.read_exact(&mut header).unwrap();
+        assert_eq!(&header, b"Content-Length: ");
+
+        sender.send(ControllerMessage::Status).unwrap();
+        controller.handle_next().unwrap();
+        let status = match responses.try_recv() {
+            Ok(ControllerMessage::ClientStatus(status)) => status,
+            _ => panic!("Expected the status of the servers"),
+        };
+        assert_eq!(status, vec![("fake".to_string(), format!("tcp 127.0.0.1:{}", port))]);
     }
 
-    
+    /// Reads the next message the editor sent to a server over tcp.
+    fn read_message(stream: &mut std::net::TcpStream) -> Value {
+        let mut header = Vec::new();
+        while !header.ends_with(b"\r\n\r\n") {
+            let mut byte = [0];
+            stream.read_exact(&mut byte).unwrap();
+            header.push(byte[0]);
+        }
+        let length = String::from_utf8(header).unwrap()
+            .trim()
+            .trim_start_matches("Content-Length: ")
+            .parse::<usize>()
+            .unwrap();
+        let mut body = vec![0; length];
+        stream.read_exact(&mut body).unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
 
+    #[test]
+    fn test_stop_waits_for_shutdown_answer() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let _guard = runtime.enter();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let (sender, listen) = std::sync::mpsc::channel();
+        let (response, responses) = std::sync::mpsc::channel();
+        let mut controller = LspController::new();
+        controller.set_listen(listen);
+        controller.set_response(response);
+
+        sender.send(ControllerMessage::CreateClient("fake".into(), Some(tcp_config(port)), None, sender.clone())).unwrap();
+        controller.handle_next().unwrap();
+        assert!(matches!(responses.try_recv(), Ok(ControllerMessage::ClientCreated(..))));
+        let (mut stream, _) = listener.accept().unwrap();
+
+        // The controller goes on without waiting for the server to answer
+        sender.send(ControllerMessage::StopClient("fake".into())).unwrap();
+        let start = Instant::now();
+        controller.handle_next().unwrap();
+        assert!(start.elapsed() < Duration::from_millis(500));
+        assert!(matches!(responses.try_recv(), Ok(ControllerMessage::ClientStopped)));
+
+        let shutdown = loop {
+            let message = read_message(&mut stream);
+            if message["method"] == "shutdown" {
+                break message;
+            }
+        };
+
+        // Nothing else is sent until the server answers
+        stream.set_read_timeout(Some(Duration::from_millis(300))).unwrap();
+        assert!(stream.read(&mut [0]).is_err());
+        stream.set_read_timeout(None).unwrap();
+
+        let answer = serde_json::json!({ "jsonrpc": "2.0", "id": shutdown["id"], "result": null }).to_string();
+        stream.write_all(format!("Content-Length: {}\r\n\r\n{}", answer.len(), answer).as_bytes()).unwrap();
+        assert_eq!(read_message(&mut stream)["method"], "exit");
+
+        // And then the server sees the end of its input
+        assert_eq!(stream.read(&mut [0]).unwrap(), 0);
+    }
 
+    #[test]
+    fn test_trigger_characters() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let _guard = runtime.enter();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let (sender, listen) = std::sync::mpsc::channel();
+        let (response, responses) = std::sync::mpsc::channel();
+        let mut controller = LspController::new();
+        controller.set_listen(listen);
+        controller.set_response(response);
+
+        sender.send(ControllerMessage::CreateClient("fake".into(), Some(tcp_config(port)), None, sender.clone())).unwrap();
+        controller.handle_next().unwrap();
+        let info = match responses.try_recv() {
+            Ok(ControllerMessage::ClientCreated(_, info)) => info,
+            _ => panic!("Expected the client to be created"),
+        };
+        assert!(info.lock().unwrap().trigger_characters.is_empty());
+
+        // The panes see the characters as soon as the server answers
+        controller.handle_server_message("fake", serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "result": { "capabilities": { "completionProvider": { "triggerCharacters": [".", "::"] } } },
+        }));
+        assert_eq!(info.lock().unwrap().trigger_characters, vec![".".to_string(), "::".to_string()]);
+    }
+
+    #[test]
+    fn test_tcp_refused() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let _guard = runtime.enter();
+
+        // Nothing listens on the port once the listener is gone
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+
+        let (sender, listen) = std::sync::mpsc::channel();
+        let (response, responses) = std::sync::mpsc::channel();
+        let mut controller = LspController::new();
+        controller.set_listen(listen);
+        controller.set_response(response);
+
+        sender.send(ControllerMessage::CreateClient("fake".into(), Some(tcp_config(port)), None, sender.clone())).unwrap();
+        controller.handle_next().unwrap();
+        match responses.try_recv() {
+            Ok(ControllerMessage::NoClient(Some(reason))) => assert!(reason.contains("Failed to connect")),
+            _ => panic!("Expected no client"),
+        }
+    }
 }
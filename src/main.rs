@@ -13,6 +13,24 @@ pub mod treesitter;
 pub mod editor;
 pub mod lsp;
 pub mod registers;
+pub mod spell;
+pub mod recent;
+pub mod definition;
+pub mod substitute;
+pub mod indent;
+pub mod lines;
+pub mod editorconfig;
+pub mod encoding;
+pub mod quickfix;
+pub mod search;
+pub mod word;
+pub mod undo;
+pub mod brackets;
+pub mod snippet;
+pub mod path_completion;
+pub mod history;
+pub mod buffer_list;
+pub mod disk;
 
 //const EDITOR_NAME: &str = "vi";
 
@@ -43,6 +61,7 @@ fn main() -> io::Result<()> {
         let tokio_handle = tokio_runtime.spawn_blocking(move || {
             eprintln!("Starting Tokio thread");
             let _ = controller.run();
+            controller.stop_servers();
             drop(controller);
         });
         eprintln!("Starting Tokio runtime");
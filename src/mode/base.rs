@@ -2,13 +2,16 @@ use std::{io, collections::HashMap, rc::Rc, cell::RefCell, time::Instant};
 
 use crossterm::{event::{KeyEvent, KeyCode, KeyModifiers}, execute, cursor::{SetCursorStyle, MoveTo}, terminal};
 
-use crate::{pane::{Pane, PaneContainer}, cursor::{Direction, Cursor}, settings::{Keys, Key}};
+use crate::{pane::{Pane, PaneContainer}, cursor::{Direction, Cursor}, settings::{Keys, Key, KeyAction, take_key_actions}, indent, path_completion::complete_path, history, window::MessageKind};
 
-use crate::mode::Mode;
+use crate::mode::{Mode, show_keys};
 
 
 pub struct Normal {
     number_buffer: String,
+    /// The register picked with `"` for the next yank or paste.
+    register: Option<char>,
+    waiting_for_register: bool,
     keybindings: Rc<RefCell<HashMap<Keys, String>>>,
     key_buffer: Vec<Key>,
     timeout: u64,
@@ -20,12 +23,36 @@ impl Normal {
 
         Self {
             number_buffer: String::new(),
+            register: None,
+            waiting_for_register: false,
             keybindings: Rc::new(RefCell::new(HashMap::new())),
             key_buffer: Vec::new(),
             timeout: 1000,
             time: Instant::now(),
         }
     }
+
+    /// Takes the register that was picked with `"`.
+    fn take_register(&mut self) -> String {
+        self.register.take().map(|register| register.to_string()).unwrap_or_default()
+    }
+
+    /// Takes the count typed before the command, which is 1 when there isn't one.
+    fn take_count(&mut self) -> usize {
+        let count = self.number_buffer.parse::<usize>().unwrap_or(1).max(1);
+        self.number_buffer.clear();
+        count
+    }
+
+    /// Runs the bindings in the key buffer, falling back on shorter ones when the key timeout ran out.
+    fn run_key_buffer(&mut self, timed_out: bool, pane: &mut dyn Pane, container: &mut PaneContainer) {
+        let actions = take_key_actions(&self.keybindings.borrow(), &mut self.key_buffer, timed_out);
+        for action in actions {
+            if let KeyAction::Run(command) = action {
+                self.execute_command(&command, pane, container);
+            }
+        }
+    }
 }
 
 impl Mode for Normal {
@@ -38,6 +65,10 @@ impl Mode for Normal {
         self.keybindings.borrow_mut().extend(bindings);
     }
 
+    fn set_keybindings(&mut self, bindings: HashMap<Keys, String>) {
+        *self.keybindings.borrow_mut() = bindings;
+    }
+
     fn set_key_timeout(&mut self, timeout: u64) {
         self.timeout = timeout;
     }
@@ -46,9 +77,9 @@ impl Mode for Normal {
         self.key_buffer.clear();
     }
 
-    fn refresh(&mut self) {
-        if self.time.elapsed().as_millis() >= self.timeout as u128 {
-            self.flush_key_buffer();
+    fn refresh(&mut self, pane: &mut dyn Pane, container: &mut PaneContainer) {
+        if !self.key_buffer.is_empty() && self.time.elapsed().as_millis() >= self.timeout as u128 {
+            self.run_key_buffer(true, pane, container);
             self.time = Instant::now();
         }
     }
@@ -75,11 +106,19 @@ impl Mode for Normal {
                 self.number_buffer.clear();
             },
             "line_start" => {
-                pane.run_command("move line_start", container);
+                if pane.get_settings().borrow().editor_settings.smarthome {
+                    pane.run_command("smart_home", container);
+                }
+                else {
+                    pane.run_command("move line_start", container);
+                }
             },
             "line_end" => {
                 pane.run_command("move line_end", container);
             },
+            "first_non_blank" => {
+                pane.run_command("first_non_blank", container);
+            },
             "file_top" => {
                 pane.run_command("move file_top", container);
             },
@@ -113,15 +152,44 @@ impl Mode for Normal {
                 pane.run_command("move line_end", container);
                 self.change_mode("Insert", pane, container);
             },
+            "insert_bellow" => {
+                execute!(io::stdout(),SetCursorStyle::BlinkingBar).unwrap();
+                self.change_mode("Insert", pane, container);
+                pane.run_command("open_line below", container);
+            },
+            "insert_above" => {
+                execute!(io::stdout(),SetCursorStyle::BlinkingBar).unwrap();
+                self.change_mode("Insert", pane, container);
+                pane.run_command("open_line above", container);
+            },
             "start_command" => {
                 self.change_mode("Command", pane, container);
             },
+            "start_visual" => {
+                self.number_buffer.clear();
+                self.change_mode("Visual", pane, container);
+            },
+            "start_visual_line" => {
+                self.number_buffer.clear();
+                self.change_mode("Visual Line", pane, container);
+            },
+            "start_visual_block" => {
+                self.number_buffer.clear();
+                self.change_mode("Visual Block", pane, container);
+            },
+            "reselect" => {
+                self.number_buffer.clear();
+                pane.run_command("reselect", container);
+            },
             "paste_after" => {
-                eprintln!("paste after");
-                pane.run_command(&format!("paste {}", self.number_buffer), container);
+                self.number_buffer.clear();
+                let register = self.take_register();
+                pane.run_command(&format!("paste after {}", register), container);
             },
             "paste_before" => {
-                pane.run_command(&format!("paste {}", self.number_buffer), container);
+                self.number_buffer.clear();
+                let register = self.take_register();
+                pane.run_command(&format!("paste before {}", register), container);
             },
             "insert_text" => {
                 let text = command_args.collect::<Vec<&str>>().join(" ");
@@ -130,10 +198,69 @@ impl Mode for Normal {
                 
             },
             "copy_line" => {
-                pane.run_command(&format!("copy line {}", self.number_buffer), container);
+                let count = self.take_count();
+                let register = self.take_register();
+                pane.run_command(&format!("copy line {} {}", count, register), container);
+            },
+            "delete_line" => {
+                let count = self.take_count();
+                let register = self.take_register();
+                pane.run_command(&format!("delete_line {} {}", count, register), container);
+            },
+            "jump_match" => {
+                self.number_buffer.clear();
+                pane.run_command("jump_match", container);
+            },
+            "indent_line" => {
+                let count = self.take_count();
+                pane.run_command(&format!("shift_lines right {}", count), container);
+            },
+            "dedent_line" => {
+                let count = self.take_count();
+                pane.run_command(&format!("shift_lines left {}", count), container);
+            },
+            "delete_word" => {
+                let count = self.take_count();
+                let register = self.take_register();
+                pane.run_command(&format!("delete_word {} {}", count, register), container);
+            },
+            "change_word" => {
+                let count = self.take_count();
+                let register = self.take_register();
+                pane.run_command(&format!("change_word {} {}", count, register), container);
+                execute!(io::stdout(),SetCursorStyle::BlinkingBar).unwrap();
+                self.change_mode("Insert", pane, container);
+            },
+            "delete_inner_word" => {
+                self.number_buffer.clear();
+                let register = self.take_register();
+                pane.run_command(&format!("delete_inner_word {}", register), container);
+            },
+            "change_inner_word" => {
+                self.number_buffer.clear();
+                let register = self.take_register();
+                pane.run_command(&format!("delete_inner_word {}", register), container);
+                execute!(io::stdout(),SetCursorStyle::BlinkingBar).unwrap();
+                self.change_mode("Insert", pane, container);
+            },
+            "delete_char" => {
+                for _ in 0..self.take_count() {
+                    pane.delete_char();
+                }
+            },
+            "play_macro" => {
+                pane.run_command(&format!("play_macro {}", self.number_buffer), container);
+                self.number_buffer.clear();
+            },
+            "repeat_change" => {
+                let count = self.take_count();
+                pane.run_command(&format!("repeat_change {}", count), container);
             },
             command => {
-                pane.run_command(command, container);
+                // A count runs the command that many times
+                for _ in 0..self.take_count() {
+                    pane.run_command(command, container);
+                }
             }
 
         }
@@ -141,9 +268,24 @@ impl Mode for Normal {
     }
 
     fn process_keypress(&mut self, key: KeyEvent, pane: &mut dyn Pane, container: &mut PaneContainer) -> io::Result<bool> {
-        self.refresh();
+        self.refresh(pane, container);
+
+        if self.waiting_for_register {
+            self.waiting_for_register = false;
+            if let KeyCode::Char(c) = key.code {
+                self.register = Some(c);
+            }
+            return Ok(true);
+        }
 
         match key {
+            KeyEvent {
+                code: KeyCode::Char('"'),
+                ..
+            } if self.key_buffer.is_empty() => {
+                self.waiting_for_register = true;
+                Ok(true)
+            },
             KeyEvent {
                 code: KeyCode::Char('1'),
                 modifiers: KeyModifiers::NONE,
@@ -224,18 +366,14 @@ impl Mode for Normal {
                     return Ok(true);
                 }
 
-                let mut flush = false;
-                if key.key == KeyCode::Esc {
-                    flush = true;
-                }
-                self.key_buffer.push(key);
-                if let Some(command) = self.keybindings.clone().borrow().get(&self.key_buffer) {
-                    self.execute_command(command.as_str(), pane, container);
-                    flush = true;
-                }
-                if flush {
+                // Escape gives up on the keys typed so far
+                if key.key == KeyCode::Esc && !self.key_buffer.is_empty() {
                     self.flush_key_buffer();
+                    return Ok(true);
                 }
+                self.key_buffer.push(key);
+                self.time = Instant::now();
+                self.run_key_buffer(false, pane, container);
 
                 Ok(true)
             }
@@ -248,24 +386,55 @@ impl Mode for Normal {
 
     }
 
-    fn update_status(&mut self, pane: &dyn Pane, _container: &PaneContainer) -> (String, String, String){
-        let (row, col) = pane.get_cursor().borrow().get_cursor();
+    fn get_pending_bindings(&self) -> Option<(Keys, Vec<(Keys, String)>)> {
+        if self.key_buffer.is_empty() {
+            return None;
+        }
 
+        let keybindings = self.keybindings.borrow();
+        let bindings = keybindings.iter()
+            .filter(|(keys, _)| keys.len() > self.key_buffer.len() && keys.starts_with(&self.key_buffer))
+            .map(|(keys, command)| (keys[self.key_buffer.len()..].to_vec(), command.clone()))
+            .collect::<Vec<(Keys, String)>>();
 
-        let mut first = format!("{}:{}", col + 1, row + 1);
+        if bindings.is_empty() {
+            None
+        }
+        else {
+            Some((self.key_buffer.clone(), bindings))
+        }
+    }
+
+    fn get_pending_keys(&self) -> String {
+        let mut pending = Vec::new();
 
-        
-            
         if !self.number_buffer.is_empty() {
-            first.push_str(&format!(" {}", self.number_buffer));
+            pending.push(self.number_buffer.clone());
+        }
+        match self.register {
+            Some(register) => pending.push(format!("\"{}", register)),
+            None if self.waiting_for_register => pending.push("\"".to_string()),
+            None => {},
         }
-        
-        let mut second = String::new();
         if !self.key_buffer.is_empty() {
-            for key in &self.key_buffer {
-                second.push_str(&format!("{} ", key));
-            }
+            pending.push(show_keys(&self.key_buffer));
+        }
+
+        pending.join(" ")
+    }
+
+    fn update_status(&mut self, pane: &dyn Pane, _container: &PaneContainer) -> (String, String, String){
+        let (row, col) = pane.get_cursor().borrow().get_cursor();
+
+
+        let first = format!("{}:{}", col + 1, row + 1);
+
+        let second = if pane.get_settings().borrow().editor_settings.showcmd {
+            self.get_pending_keys()
         }
+        else {
+            String::new()
+        };
 
         /*let corners = pane.get_corners();
 
@@ -323,12 +492,140 @@ impl Insert {
         Ok(true)
     }
     fn backspace(&self, pane: &mut dyn Pane) -> io::Result<bool> {
+        // Deleting the opening half of an empty pair takes the closing half with it
+        if let Some((before, after)) = Self::chars_around_cursor(pane) {
+            if Self::get_auto_pairs(pane).contains(&(before, after)) {
+                pane.delete_char();
+            }
+        }
+
         pane.backspace_char();
         pane.changed();
         Ok(true)
     }
+
+    /// Takes a level of indentation off the cursor's line wherever the cursor is on it.
+    fn dedent_line(&self, pane: &mut dyn Pane) -> io::Result<bool> {
+        let (_, y) = pane.get_cursor().borrow().get_cursor();
+        let line = match pane.borrow_buffer().get_row(y, 0, usize::MAX) {
+            Some(line) => line.to_string(),
+            None => return Ok(true),
+        };
+
+        let indent = {
+            let settings = pane.get_settings();
+            let settings = settings.borrow();
+            indent::dedent(indent::leading_whitespace(&line), settings.editor_settings.use_spaces, settings.editor_settings.tab_size)
+        };
+        pane.set_indent(y, &indent);
+        pane.changed();
+        Ok(true)
+    }
+
+    /// The pairs to close for the pane's language, which is none when autopairs is off.
+    fn get_auto_pairs(pane: &dyn Pane) -> Vec<(char, char)> {
+        let settings = pane.get_settings();
+        let settings = settings.borrow();
+
+        if !settings.editor_settings.autopairs {
+            return Vec::new();
+        }
+        settings.get_auto_pairs(pane.get_language()).to_vec()
+    }
+
+    /// The characters right before and right at the cursor on its line.
+    fn chars_around_cursor(pane: &dyn Pane) -> Option<(char, char)> {
+        let (x, y) = pane.get_cursor().borrow().get_cursor();
+        let line = pane.borrow_buffer().get_row(y, 0, usize::MAX)?.to_string();
+
+        let before = line.chars().nth(x.checked_sub(1)?)?;
+        let after = line.chars().nth(x)?;
+        Some((before, after))
+    }
+
+    /// Types the closing half of a pair or steps over it if it is already there.
+    /// Returns false when the character should just be inserted.
+    fn insert_pair(&self, pane: &mut dyn Pane, c: char) -> bool {
+        let pairs = Self::get_auto_pairs(pane);
+        if pairs.is_empty() {
+            return false;
+        }
+
+        let (x, y) = pane.get_cursor().borrow().get_cursor();
+        let line = match pane.borrow_buffer().get_row(y, 0, usize::MAX) {
+            Some(line) => line.to_string(),
+            None => String::new(),
+        };
+        let before = x.checked_sub(1).and_then(|x| line.chars().nth(x));
+        let after = line.chars().nth(x);
+
+        if after == Some(c) && pairs.iter().any(|(_, close)| *close == c) {
+            let cursor = pane.get_cursor();
+            cursor.borrow_mut().move_cursor(Direction::Right, 1, &*pane);
+            return true;
+        }
+
+        let close = match pairs.iter().find(|(open, _)| *open == c) {
+            Some((_, close)) => *close,
+            None => return false,
+        };
+
+        // Quotes in the middle of a word are usually apostrophes
+        if c == close && before.is_some_and(|before| before.is_alphanumeric()) {
+            return false;
+        }
+
+        pane.insert_str(&format!("{}{}", c, close));
+
+        let cursor = pane.get_cursor();
+        cursor.borrow_mut().move_cursor(Direction::Right, 1, &*pane);
+        true
+    }
+
+    /// Fills the space between the end of the line and the cursor with spaces when the cursor is past the end.
+    fn fill_virtual_space(pane: &mut dyn Pane) {
+        let (x, y) = pane.get_cursor().borrow().get_cursor();
+        let len = match pane.borrow_buffer().get_row(y, 0, usize::MAX) {
+            Some(line) => line.chars().filter(|c| *c != '\n').count(),
+            None => 0,
+        };
+
+        if x > len {
+            pane.insert_str(&" ".repeat(x - len));
+        }
+    }
+
+    /// Takes a level of indentation off the line when a closing bracket is the first thing typed on it.
+    fn dedent_closing(pane: &mut dyn Pane) {
+        let (x, y) = pane.get_cursor().borrow().get_cursor();
+        let before = match pane.borrow_buffer().get_row(y, 0, usize::MAX) {
+            Some(line) => line.to_string().chars().take(x).collect::<String>(),
+            None => return,
+        };
+        if before.is_empty() || !before.chars().all(|c| c == ' ' || c == '\t') {
+            return;
+        }
+
+        let indent = {
+            let settings = pane.get_settings();
+            let settings = settings.borrow();
+            indent::dedent(&before, settings.editor_settings.use_spaces, settings.editor_settings.tab_size)
+        };
+        pane.set_indent(y, &indent);
+    }
+
     fn insert_char(&self, pane: &mut dyn Pane, c: char) -> io::Result<bool> {
         pane.changed();
+        Self::fill_virtual_space(pane);
+
+        if matches!(c, '}' | ')' | ']') {
+            Self::dedent_closing(pane);
+        }
+
+        if self.insert_pair(pane, c) {
+            return Ok(true);
+        }
+
         if pane.get_settings().borrow().editor_settings.use_spaces && c == '\t' {
             pane.insert_str(&" ".repeat(pane.get_settings().borrow().editor_settings.tab_size));
         } else {
@@ -345,6 +642,22 @@ impl Insert {
         }
         Ok(true)
     }
+
+    /// Runs the bindings in the key buffer, falling back on shorter ones when the key timeout ran out.
+    /// A character that turned out not to start a binding gets typed after all.
+    fn run_key_buffer(&mut self, timed_out: bool, pane: &mut dyn Pane, container: &mut PaneContainer) -> io::Result<()> {
+        let actions = take_key_actions(&self.keybindings.borrow(), &mut self.key_buffer, timed_out);
+        for action in actions {
+            match action {
+                KeyAction::Run(command) => self.execute_command(&command, pane, container),
+                KeyAction::Unbound(Key { key: KeyCode::Char(c), modifier: KeyModifiers::NONE | KeyModifiers::SHIFT }) => {
+                    self.insert_char(pane, c)?;
+                },
+                KeyAction::Unbound(_) => {},
+            }
+        }
+        Ok(())
+    }
 }
 
 impl Mode for Insert {
@@ -353,10 +666,18 @@ impl Mode for Insert {
         "Insert".to_string()
     }
 
+    fn get_pending_keys(&self) -> String {
+        show_keys(&self.key_buffer)
+    }
+
     fn add_keybindings(&mut self, keybindings: HashMap<Keys, String>) {
         self.keybindings.borrow_mut().extend(keybindings);
     }
 
+    fn set_keybindings(&mut self, keybindings: HashMap<Keys, String>) {
+        *self.keybindings.borrow_mut() = keybindings;
+    }
+
     fn set_key_timeout(&mut self, timeout: u64) {
         self.timeout = timeout;
     }
@@ -365,9 +686,9 @@ impl Mode for Insert {
         self.key_buffer.clear();
     }
 
-    fn refresh(&mut self) {
-        if self.time.elapsed().as_millis() >= self.timeout as u128 {
-            self.flush_key_buffer();
+    fn refresh(&mut self, pane: &mut dyn Pane, container: &mut PaneContainer) {
+        if !self.key_buffer.is_empty() && self.time.elapsed().as_millis() >= self.timeout as u128 {
+            self.run_key_buffer(true, pane, container).expect("Failed to type the pending keys");
             self.time = Instant::now();
         }
     }
@@ -411,7 +732,7 @@ impl Mode for Insert {
     }
     
     fn process_keypress(&mut self, key: KeyEvent, pane: &mut dyn Pane, container: &mut PaneContainer) -> io::Result<bool> {
-        self.refresh();
+        self.refresh(pane, container);
 
         if self.key_buffer.is_empty() {
             match key {
@@ -430,6 +751,18 @@ impl Mode for Insert {
                     modifiers: KeyModifiers::NONE,
                     ..
                 } => self.backspace(pane),
+                KeyEvent {
+                    code: KeyCode::Tab | KeyCode::BackTab,
+                    ..
+                } if pane.in_snippet() => {
+                    let command = if key.code == KeyCode::Tab { "snippet_next" } else { "snippet_previous" };
+                    pane.run_command(command, container);
+                    Ok(true)
+                },
+                KeyEvent {
+                    code: KeyCode::BackTab,
+                    ..
+                } => self.dedent_line(pane),
                 KeyEvent {
                     code: code @ (KeyCode::Char(..) | KeyCode::Tab),
                     modifiers: KeyModifiers::NONE | KeyModifiers::SHIFT,
@@ -442,18 +775,9 @@ impl Mode for Insert {
                 key_event => {
                     let key = Key::from(key_event);
 
-                    let mut flush = false;
-                    if key.key == KeyCode::Esc {
-                        flush = true;
-                    }
                     self.key_buffer.push(key);
-                    if let Some(command) = self.keybindings.clone().borrow().get(&self.key_buffer) {
-                        self.execute_command(command.as_str(), pane, container);
-                        flush = true;
-                    }
-                    if flush {
-                        self.flush_key_buffer();
-                    }
+                    self.time = Instant::now();
+                    self.run_key_buffer(false, pane, container)?;
 
                     Ok(true)
                 }
@@ -464,18 +788,14 @@ impl Mode for Insert {
                 key_event => {
                     let key = Key::from(key_event);
 
-                    let mut flush = false;
+                    // Escape gives up on the keys typed so far
                     if key.key == KeyCode::Esc {
-                        flush = true;
-                    }
-                    self.key_buffer.push(key);
-                    if let Some(command) = self.keybindings.clone().borrow().get(&self.key_buffer) {
-                        self.execute_command(command.as_str(), pane, container);
-                        flush = true;
-                    }
-                    if flush {
                         self.flush_key_buffer();
+                        return Ok(true);
                     }
+                    self.key_buffer.push(key);
+                    self.time = Instant::now();
+                    self.run_key_buffer(false, pane, container)?;
 
                     Ok(true)
                 }
@@ -495,16 +815,15 @@ impl Mode for Insert {
         let first = format!("{}:{}", col + 1, row + 1);
         
 
-        let mut second = String::new();
-
         //second.push_str(&format!("{:?} {}", &pane.borrow_buffer().chars().collect::<String>(), pane.borrow_buffer().line_len()));
         //second.push_str(&format!("{:?}", pane.get_cursor().borrow()));
 
-        if !self.key_buffer.is_empty() {
-            for key in &self.key_buffer {
-                second.push_str(&format!("{} ", key));
-            }
+        let second = if pane.get_settings().borrow().editor_settings.showcmd {
+            self.get_pending_keys()
         }
+        else {
+            String::new()
+        };
 
 
         (self.get_name(), first, second)
@@ -513,6 +832,30 @@ impl Mode for Insert {
 }
 
 
+/// The commands whose argument Tab completes as a path.
+const PATH_COMMANDS: [&str; 3] = ["e", "w", "w!"];
+
+/// The command names or paths Tab goes through in the command prompt.
+struct TabCycle {
+    candidates: Vec<String>,
+    /// The candidate showing, `None` for what was typed.
+    index: Option<usize>,
+    typed: String,
+    /// Where what is being completed starts in the command.
+    start: usize,
+    /// The command as the last Tab left it, so any other edit starts over.
+    shown: String,
+}
+
+/// The earlier commands Up and Down go through, which start with what was typed.
+struct HistoryWalk {
+    commands: Vec<String>,
+    /// The command showing counting back from the newest, `None` for what was typed.
+    index: Option<usize>,
+    typed: String,
+    shown: String,
+}
+
 pub struct Command {
     command: String,
     edit_pos: usize,
@@ -521,6 +864,8 @@ pub struct Command {
     timeout: u64,
     time: Instant,
     cursor_location: Option<Cursor>,
+    tab_cycle: Option<TabCycle>,
+    history_walk: Option<HistoryWalk>,
 }
 
 impl Command {
@@ -533,6 +878,8 @@ impl Command {
             timeout: 1000,
             time: Instant::now(),
             cursor_location: None,
+            tab_cycle: None,
+            history_walk: None,
         }
     }
 
@@ -543,6 +890,115 @@ impl Command {
         }
     }
 
+    /// What Tab completes at the cursor: the name of the command, or a path for commands that take one.
+    /// Returns where that starts and the candidates.
+    fn completions(&self, pane: &dyn Pane) -> Option<(usize, Vec<String>)> {
+        let before = &self.command[..self.edit_pos];
+        let start = match before.rfind(char::is_whitespace) {
+            None => {
+                let mut names = pane.command_names().into_iter()
+                    .filter(|name| name.starts_with(before))
+                    .map(str::to_string)
+                    .collect::<Vec<_>>();
+                names.sort();
+                names.dedup();
+                return Some((0, names));
+            },
+            Some(space) => space + 1,
+        };
+
+        let name = before.split_whitespace().next().unwrap_or("");
+        if !PATH_COMMANDS.contains(&name) {
+            return None;
+        }
+        let hidden = pane.get_settings().borrow().editor_settings.show_hidden_files;
+        Some((start, complete_path(&before[start..], hidden)))
+    }
+
+    /// Puts the next or previous candidate that completes what is before the cursor in its place.
+    /// Going past either end shows what was typed again.
+    /// A candidate that is the only way to finish is taken right away so Tab can carry on into a directory.
+    fn cycle_completion(&mut self, forward: bool, pane: &mut dyn Pane) {
+        if self.tab_cycle.as_ref().is_some_and(|cycle| cycle.shown != self.command) {
+            self.tab_cycle = None;
+        }
+
+        if self.tab_cycle.is_none() {
+            let (start, candidates) = match self.completions(pane) {
+                Some((start, candidates)) if !candidates.is_empty() => (start, candidates),
+                _ => return,
+            };
+            self.tab_cycle = Some(TabCycle {
+                candidates,
+                index: None,
+                typed: self.command[start..self.edit_pos].to_string(),
+                start,
+                shown: String::new(),
+            });
+        }
+
+        let cycle = self.tab_cycle.as_mut().expect("Just made the cycle");
+        let count = cycle.candidates.len();
+        cycle.index = match (cycle.index, forward) {
+            (None, true) => Some(0),
+            (None, false) => Some(count - 1),
+            (Some(index), true) => (index + 1 < count).then_some(index + 1),
+            (Some(index), false) => index.checked_sub(1),
+        };
+        let text = match cycle.index {
+            None => &cycle.typed,
+            Some(index) => &cycle.candidates[index],
+        };
+
+        self.command.replace_range(cycle.start..self.edit_pos, text);
+        self.edit_pos = cycle.start + text.len();
+        cycle.shown = self.command.clone();
+        if count == 1 {
+            self.tab_cycle = None;
+        }
+        pane.preview_command(Some(&self.command));
+    }
+
+    /// Shows the command run before or after the one showing, out of those that start with what was typed.
+    /// Going past the newest shows what was typed again.
+    fn walk_history(&mut self, back: bool, pane: &mut dyn Pane) {
+        if self.history_walk.as_ref().is_some_and(|walk| walk.shown != self.command) {
+            self.history_walk = None;
+        }
+
+        let walk = self.history_walk.get_or_insert_with(|| HistoryWalk {
+            commands: history::matching_commands(&self.command),
+            index: None,
+            typed: self.command.clone(),
+            shown: String::new(),
+        });
+        let count = walk.commands.len();
+        walk.index = match (walk.index, back) {
+            (None, true) if count > 0 => Some(0),
+            (None, _) => None,
+            (Some(index), true) => Some((index + 1).min(count - 1)),
+            (Some(index), false) => index.checked_sub(1),
+        };
+
+        self.command = match walk.index {
+            None => walk.typed.clone(),
+            Some(index) => walk.commands[count - 1 - index].clone(),
+        };
+        self.edit_pos = self.command.len();
+        walk.shown = self.command.clone();
+        pane.preview_command(Some(&self.command));
+    }
+
+    /// Runs the bindings in the key buffer, falling back on shorter ones when the key timeout ran out.
+    fn run_key_buffer(&mut self, timed_out: bool, pane: &mut dyn Pane, container: &mut PaneContainer) {
+        let actions = take_key_actions(&self.keybindings.borrow(), &mut self.key_buffer, timed_out);
+        for action in actions {
+            if let KeyAction::Run(command) = action {
+                self.execute_command(&command, pane, container);
+            }
+        }
+    }
+
 }
 
 impl Mode for Command {
@@ -551,6 +1007,10 @@ impl Mode for Command {
         "Command".to_string()
     }
 
+    fn get_pending_keys(&self) -> String {
+        show_keys(&self.key_buffer)
+    }
+
     fn update_status(&mut self, pane: &dyn Pane, _container: &PaneContainer) -> (String, String, String) {
 
 
@@ -571,8 +1031,15 @@ impl Mode for Command {
         cursor.set_draw_cursor(offset + self.edit_pos, terminal::size().unwrap().1 as usize);
         
         let first = format!(":{}", self.command);
-        
-        let second = String::new();
+
+        // Which of the candidates Tab went to is showing
+        let second = match &self.tab_cycle {
+            Some(TabCycle { index: Some(index), candidates, shown, .. }) if *shown == self.command => {
+                format!("{} of {}", index + 1, candidates.len())
+            },
+            _ if pane.get_settings().borrow().editor_settings.showcmd => self.get_pending_keys(),
+            _ => String::new(),
+        };
         
 
         (self.get_name(), first, second)
@@ -581,6 +1048,9 @@ impl Mode for Command {
     fn change_mode(&mut self, name: &str, pane: &mut dyn Pane, container: &mut PaneContainer) {
         self.command.clear();
         self.edit_pos = 0;
+        self.tab_cycle = None;
+        self.history_walk = None;
+        pane.preview_command(None);
         pane.change_mode(name);
 
         let mut cursor = self.cursor_location.take().unwrap();
@@ -590,6 +1060,13 @@ impl Mode for Command {
 
         if !pane.get_cursor().borrow().jumped {
             *pane.get_cursor().borrow_mut() = cursor;
+
+            // The command might have shortened or removed the line the cursor was on
+            let (col, row) = cursor.get_cursor();
+            if row >= pane.get_line_count() || col > pane.get_row_len(row).unwrap_or(0) {
+                let pane_cursor = pane.get_cursor();
+                pane_cursor.borrow_mut().jump_to(col, row, &*pane);
+            }
         }
 
         pane.get_cursor().borrow_mut().ignore_offset = false;
@@ -602,6 +1079,10 @@ impl Mode for Command {
         self.keybindings.borrow_mut().extend(keybindings);
     }
 
+    fn set_keybindings(&mut self, keybindings: HashMap<Keys, String>) {
+        *self.keybindings.borrow_mut() = keybindings;
+    }
+
     fn set_key_timeout(&mut self, timeout: u64) {
         self.timeout = timeout;
     }
@@ -610,9 +1091,9 @@ impl Mode for Command {
         self.key_buffer.clear();
     }
 
-    fn refresh(&mut self) {
-        if self.time.elapsed().as_millis() >= self.timeout as u128 {
-            self.flush_key_buffer();
+    fn refresh(&mut self, pane: &mut dyn Pane, container: &mut PaneContainer) {
+        if !self.key_buffer.is_empty() && self.time.elapsed().as_millis() >= self.timeout as u128 {
+            self.run_key_buffer(true, pane, container);
             self.time = Instant::now();
         }
     }
@@ -634,6 +1115,10 @@ impl Mode for Command {
             "end" => {
                 self.edit_pos = self.command.len();
             },
+            "complete_next" => self.cycle_completion(true, pane),
+            "complete_previous" => self.cycle_completion(false, pane),
+            "history_previous" => self.walk_history(true, pane),
+            "history_next" => self.walk_history(false, pane),
             "leave" => {
                 self.change_mode("Normal", pane, container);
             },
@@ -646,7 +1131,7 @@ impl Mode for Command {
     }
 
     fn process_keypress(&mut self, key: KeyEvent, pane: &mut dyn Pane, container: &mut PaneContainer) -> io::Result<bool> {
-        self.refresh();
+        self.refresh(pane, container);
 
 
         match key {
@@ -656,6 +1141,9 @@ impl Mode for Command {
                 ..
             } => {
                 pane.run_command(&self.command, container);
+                if let Err(err) = history::add_command(&self.command, &pane.get_settings().borrow().editor_settings) {
+                    pane.show_message(format!("Failed to save command history: {}", err), MessageKind::Error);
+                }
 
                 self.change_mode("Normal", pane,container);
                 Ok(true)
@@ -668,6 +1156,7 @@ impl Mode for Command {
                 if self.edit_pos < self.command.len() {
                     self.command.remove(self.edit_pos);
                 }
+                pane.preview_command(Some(&self.command));
                 Ok(true)
             },
             KeyEvent {
@@ -679,6 +1168,7 @@ impl Mode for Command {
                     self.edit_pos -= 1;
                     self.command.remove(self.edit_pos);
                 }
+                pane.preview_command(Some(&self.command));
                 Ok(true)
             },
             KeyEvent {
@@ -694,27 +1184,270 @@ impl Mode for Command {
 
                 self.command.insert(self.edit_pos, c);
                 self.edit_pos += 1;
+                pane.preview_command(Some(&self.command));
                 Ok(true)
             },
             key_event => {
                 let key = Key::from(key_event);
 
-                let mut flush = false;
-                if key.key == KeyCode::Esc {
-                    flush = true;
+                // Escape gives up on the keys typed so far
+                if key.key == KeyCode::Esc && !self.key_buffer.is_empty() {
+                    self.flush_key_buffer();
+                    return Ok(true);
                 }
                 self.key_buffer.push(key);
-                if let Some(command) = self.keybindings.clone().borrow().get(&self.key_buffer) {
-                    self.execute_command(command.as_str(), pane, container);
-                    flush = true;
+                self.time = Instant::now();
+                self.run_key_buffer(false, pane, container);
+
+                Ok(true)
+            }
+
+        }
+    }
+}
+
+
+/// What a visual mode selects between where it was entered and the cursor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VisualKind {
+    /// The characters in between, which is `v`.
+    Chars,
+    /// Whole lines, which is `V`.
+    Lines,
+    /// The same columns of every line in between, which is `Ctrl-v`.
+    Block,
+}
+
+impl VisualKind {
+    pub fn mode_name(&self) -> &'static str {
+        match self {
+            VisualKind::Chars => "Visual",
+            VisualKind::Lines => "Visual Line",
+            VisualKind::Block => "Visual Block",
+        }
+    }
+}
+
+/// Selects the text between where the mode was entered and the cursor.
+/// The pane keeps the anchor so it can draw the selection.
+pub struct Visual {
+    kind: VisualKind,
+    number_buffer: String,
+    /// The register picked with `"` for the next yank or delete.
+    register: Option<char>,
+    waiting_for_register: bool,
+    keybindings: Rc<RefCell<HashMap<Keys, String>>>,
+    key_buffer: Vec<Key>,
+    timeout: u64,
+    time: Instant,
+}
+
+impl Visual {
+    pub fn new(kind: VisualKind) -> Self {
+        Self {
+            kind,
+            number_buffer: String::new(),
+            register: None,
+            waiting_for_register: false,
+            keybindings: Rc::new(RefCell::new(HashMap::new())),
+            key_buffer: Vec::new(),
+            timeout: 1000,
+            time: Instant::now(),
+        }
+    }
+
+    fn take_register(&mut self) -> String {
+        self.number_buffer.clear();
+        self.register.take().map(|register| register.to_string()).unwrap_or_default()
+    }
+
+    /// Runs the bindings in the key buffer, falling back on shorter ones when the key timeout ran out.
+    fn run_key_buffer(&mut self, timed_out: bool, pane: &mut dyn Pane, container: &mut PaneContainer) {
+        let actions = take_key_actions(&self.keybindings.borrow(), &mut self.key_buffer, timed_out);
+        for action in actions {
+            if let KeyAction::Run(command) = action {
+                self.execute_command(&command, pane, container);
+            }
+        }
+    }
+}
+
+impl Mode for Visual {
+
+    fn get_name(&self) -> String {
+        self.kind.mode_name().to_string()
+    }
+
+    fn add_keybindings(&mut self, bindings: HashMap<Keys, String>) {
+        self.keybindings.borrow_mut().extend(bindings);
+    }
+
+    fn set_keybindings(&mut self, bindings: HashMap<Keys, String>) {
+        *self.keybindings.borrow_mut() = bindings;
+    }
+
+    fn set_key_timeout(&mut self, timeout: u64) {
+        self.timeout = timeout;
+    }
+
+    fn flush_key_buffer(&mut self) {
+        self.key_buffer.clear();
+    }
+
+    fn refresh(&mut self, pane: &mut dyn Pane, container: &mut PaneContainer) {
+        if !self.key_buffer.is_empty() && self.time.elapsed().as_millis() >= self.timeout as u128 {
+            self.run_key_buffer(true, pane, container);
+            self.time = Instant::now();
+        }
+    }
+
+    fn execute_command(&mut self, command: &str, pane: &mut dyn Pane, container: &mut PaneContainer) {
+        match command {
+            "left" | "right" | "up" | "down" | "page_up" | "page_down" => {
+                pane.run_command(&format!("move {} {}", command, self.number_buffer), container);
+                self.number_buffer.clear();
+            },
+            "line_start" | "line_end" | "file_top" | "file_bottom" => {
+                pane.run_command(&format!("move {}", command), container);
+            },
+            "word_start_forward" | "word_start_backward" | "word_end_forward" => {
+                pane.run_command(&format!("{} {}", command, self.number_buffer), container);
+                self.number_buffer.clear();
+            },
+            "delete" => {
+                let register = self.take_register();
+                pane.run_command(&format!("delete_selection {}", register), container);
+                self.change_mode("Normal", pane, container);
+            },
+            "copy" => {
+                let register = self.take_register();
+                pane.run_command(&format!("copy selection {}", register), container);
+                self.change_mode("Normal", pane, container);
+            },
+            "indent" | "dedent" => {
+                let direction = if command == "indent" { "right" } else { "left" };
+                pane.run_command(&format!("shift_lines {} selection", direction), container);
+                self.change_mode("Normal", pane, container);
+            },
+            "format_selection" => {
+                pane.run_command(command, container);
+                self.change_mode("Normal", pane, container);
+            },
+            "trim_whitespace" => {
+                pane.run_command("trim_whitespace selection", container);
+                self.change_mode("Normal", pane, container);
+            },
+            "change" => {
+                let register = self.take_register();
+                pane.run_command(&format!("delete_selection {}", register), container);
+                execute!(io::stdout(),SetCursorStyle::BlinkingBar).unwrap();
+                self.change_mode("Insert", pane, container);
+            },
+            "start_visual" | "start_visual_line" | "start_visual_block" => {
+                // Typing the key of the current mode again leaves it
+                let kind = match command {
+                    "start_visual_line" => VisualKind::Lines,
+                    "start_visual_block" => VisualKind::Block,
+                    _ => VisualKind::Chars,
+                };
+                self.number_buffer.clear();
+                self.register = None;
+                if kind == self.kind {
+                    self.change_mode("Normal", pane, container);
                 }
-                if flush {
+                else {
+                    self.change_mode(kind.mode_name(), pane, container);
+                }
+            },
+            "leave" => {
+                self.number_buffer.clear();
+                self.register = None;
+                self.change_mode("Normal", pane, container);
+            },
+            command => {
+                pane.run_command(command, container);
+            }
+        }
+    }
+
+    fn process_keypress(&mut self, key: KeyEvent, pane: &mut dyn Pane, container: &mut PaneContainer) -> io::Result<bool> {
+        self.refresh(pane, container);
+
+        if self.waiting_for_register {
+            self.waiting_for_register = false;
+            if let KeyCode::Char(c) = key.code {
+                self.register = Some(c);
+            }
+            return Ok(true);
+        }
+
+        match key {
+            KeyEvent {
+                code: KeyCode::Char('"'),
+                ..
+            } if self.key_buffer.is_empty() => {
+                self.waiting_for_register = true;
+                Ok(true)
+            },
+            KeyEvent {
+                code: KeyCode::Char(c),
+                modifiers: KeyModifiers::NONE,
+                ..
+            } if c.is_ascii_digit() && (c != '0' || !self.number_buffer.is_empty()) => {
+                self.number_buffer.push(c);
+                Ok(true)
+            },
+            key_event => {
+                let key = Key::from(key_event);
+
+                // Escape gives up on the keys typed so far
+                if key.key == KeyCode::Esc && !self.key_buffer.is_empty() {
                     self.flush_key_buffer();
+                    return Ok(true);
                 }
+                self.key_buffer.push(key);
+                self.time = Instant::now();
+                self.run_key_buffer(false, pane, container);
 
                 Ok(true)
             }
+        }
+    }
 
+    fn change_mode(&mut self, name: &str, pane: &mut dyn Pane, _container: &mut PaneContainer) {
+        pane.change_mode(name);
+    }
+
+    fn get_pending_keys(&self) -> String {
+        let mut pending = Vec::new();
+
+        if !self.number_buffer.is_empty() {
+            pending.push(self.number_buffer.clone());
+        }
+        match self.register {
+            Some(register) => pending.push(format!("\"{}", register)),
+            None if self.waiting_for_register => pending.push("\"".to_string()),
+            None => {},
         }
+        if !self.key_buffer.is_empty() {
+            pending.push(show_keys(&self.key_buffer));
+        }
+
+        pending.join(" ")
+    }
+
+    fn update_status(&mut self, pane: &dyn Pane, _container: &PaneContainer) -> (String, String, String) {
+        let (row, col) = pane.get_cursor().borrow().get_cursor();
+
+        let first = format!("{}:{}", col + 1, row + 1);
+
+        let second = if pane.get_settings().borrow().editor_settings.showcmd {
+            self.get_pending_keys()
+        }
+        else {
+            String::new()
+        };
+
+        (self.get_name(), first, second)
     }
 }
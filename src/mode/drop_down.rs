@@ -2,7 +2,7 @@ use std::{cell::RefCell, rc::Rc, collections::HashMap};
 
 use crossterm::{style::Attribute, event::{KeyEvent, KeyModifiers, KeyCode}};
 
-use crate::{settings::{Keys, Key}, pane::PaneContainer, window::StyledChar};
+use crate::{settings::{Keys, Key, ColorScheme}, pane::PaneContainer, window::StyledChar};
 
 use super::{PromptType, Promptable, Mode};
 
@@ -11,45 +11,76 @@ use super::{PromptType, Promptable, Mode};
 
 
 
+/// Text shown next to the list for the selected button, like the documentation of a completion.
+pub struct SidePanel {
+    /// The lines for each button, already wrapped to fit in the panel with a space before them.
+    pub lines: Vec<Vec<String>>,
+    /// The width of the panel including the line that separates it from the list.
+    pub width: usize,
+    /// Whether the panel is on the left of the list instead of the right.
+    pub left: bool,
+}
+
 pub struct DropDown {
     buttons: Rc<RefCell<PromptType>>,
     keybindings: Rc<RefCell<HashMap<Keys, String>>>,
+    side_panel: Option<SidePanel>,
 }
 
 impl DropDown {
-    pub fn new(buttons: PromptType) -> Self {
+    pub fn new(buttons: PromptType, side_panel: Option<SidePanel>) -> Self {
         Self {
             buttons: Rc::new(RefCell::new(buttons)),
             keybindings: Rc::new(RefCell::new(HashMap::new())),
+            side_panel,
         }
     }
+
+    /// The row of the side panel for the selected button.
+    fn draw_panel(&self, panel: &SidePanel, row: usize, selected: usize, color_settings: &ColorScheme) -> Vec<Option<StyledChar>> {
+        let text = panel.lines.get(selected)
+            .and_then(|lines| lines.get(row))
+            .map(|line| line.as_str())
+            .unwrap_or("");
+        // A space keeps the text off of the separator
+        let text = std::iter::once(' ')
+            .chain(text.chars())
+            .chain(std::iter::repeat(' '))
+            .take(panel.width.saturating_sub(1));
+
+        let mut output = Vec::new();
+        if !panel.left {
+            output.push(Some(StyledChar::new('│', color_settings.clone())));
+        }
+        output.extend(text.map(|c| Some(StyledChar::new(c, color_settings.clone()))));
+        if panel.left {
+            output.push(Some(StyledChar::new('│', color_settings.clone())));
+        }
+        output
+    }
 }
 
 
 impl Promptable for DropDown {
     fn draw_prompt(&mut self, row: usize, container: &PaneContainer) -> Vec<Option<StyledChar>> {
         let mut output = Vec::new();
-        let width = container.get_size().0;
+        let width = match &self.side_panel {
+            None => container.get_size().0,
+            Some(panel) => container.get_size().0.saturating_sub(panel.width),
+        };
 
-        let mut buttons = self.buttons.borrow_mut();
+        let buttons = self.buttons.borrow();
 
         let color_settings = container.settings.borrow().colors.popup.clone();
 
-        let button_str = match buttons.draw_button(row) {
-            Some(s) => s,
-            None =>  {
-                " ".repeat(width)
-                .chars()
-                .for_each(|c|
-                          output.push(Some(StyledChar::new(c, color_settings.clone()))));
-                return output;
-            },
+        let selected = match &*buttons {
+            PromptType::Button(_, selected) => *selected,
+            _ => panic!("Buttons were not buttons"),
         };
 
-        match &mut*buttons {
-            PromptType::Button(_, selected) => {
-                
-                let color_settings = if *selected == row {
+        match buttons.draw_button(row) {
+            Some(button_str) => {
+                let color_settings = if selected == row {
                     color_settings.add_attribute(Attribute::Reverse)
                 }
                 else {
@@ -60,16 +91,44 @@ impl Promptable for DropDown {
                     output.push(Some(StyledChar::new(c, color_settings.clone())));
                 }
             },
-            _ => panic!("Buttons were not buttons"),
+            None =>  {
+                " ".repeat(width)
+                .chars()
+                .for_each(|c|
+                          output.push(Some(StyledChar::new(c, color_settings.clone()))));
+            },
         }
 
-        output
+        match &self.side_panel {
+            None => output,
+            Some(panel) => {
+                // The panel goes right up against the list
+                output.resize(width, Some(StyledChar::new(' ', color_settings.clone())));
+                let side = self.draw_panel(panel, row, selected, &color_settings);
+                if panel.left {
+                    side.into_iter().chain(output).collect()
+                }
+                else {
+                    output.extend(side);
+                    output
+                }
+            },
+        }
     }
 
     fn max_width(&self) -> usize {
         todo!()
     }
 
+    /// Clicking a button picks it like Space does.
+    fn click(&mut self, row: usize, pane: &mut dyn crate::pane::Pane, container: &mut PaneContainer) {
+        match &mut *self.buttons.borrow_mut() {
+            PromptType::Button(buttons, selected) if row < buttons.len() => *selected = row,
+            _ => return,
+        }
+        self.execute_command("submit", pane, container);
+    }
+
 }
 
 
@@ -171,7 +230,7 @@ impl Mode for DropDown {
         }
     }
 
-    fn refresh(&mut self) {
+    fn refresh(&mut self, _pane: &mut dyn crate::pane::Pane, _container: &mut PaneContainer) {
     }
 
 }
@@ -41,7 +41,7 @@ impl Promptable for Info {
                     output.push(Some(StyledChar::new(chr, color_settings.clone())));
                 }
 
-                let gap = " ".repeat(width - text.len());
+                let gap = " ".repeat(width.saturating_sub(text.chars().count()));
 
                 for chr in gap.chars() {
                     output.push(Some(StyledChar::new(chr, color_settings.clone())));
@@ -54,18 +54,11 @@ impl Promptable for Info {
     }
 
     fn max_width(&self) -> usize {
-        let mut max = 0;
-        for line in &self.body {
-            match line {
-                None => {},
-                Some(ref text) => {
-                    if text.len() > max {
-                        max = text.len();
-                    }
-                }
-            }
-        }
-        max
+        self.body.iter()
+            .flatten()
+            .map(|text| text.chars().count())
+            .max()
+            .unwrap_or(0)
     }
 }
 
@@ -102,7 +95,7 @@ impl Mode for Info {
         // Do nothing
     }
 
-    fn refresh(&mut self) {
+    fn refresh(&mut self, _pane: &mut dyn Pane, _container: &mut PaneContainer) {
         // Do nothing
     }
 }
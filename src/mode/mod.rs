@@ -5,11 +5,11 @@ pub mod info;
 pub mod drop_down;
 
 
-use std::{io, collections::HashMap};
+use std::{io, collections::HashMap, rc::Rc, cell::RefCell};
 
 use crossterm::event::KeyEvent;
 
-use crate::{pane::{Pane, PaneContainer}, settings::Keys, window::StyledChar};
+use crate::{pane::{Pane, PaneContainer}, settings::{Keys, Settings}, window::StyledChar};
 
 use self::prompt::Prompt;
 
@@ -28,21 +28,62 @@ pub trait Mode {
 
     fn add_keybindings(&mut self, bindings: HashMap<Keys, String>);
 
+    /// Swaps the bindings of the mode for new ones, dropping the bindings that aren't in them.
+    fn set_keybindings(&mut self, bindings: HashMap<Keys, String>) {
+        self.add_keybindings(bindings);
+    }
+
     fn set_key_timeout(&mut self, timeout: u64);
 
     fn flush_key_buffer(&mut self);
 
     fn execute_command(&mut self, command: &str, pane: &mut dyn Pane, pane: &mut PaneContainer);
 
-    fn refresh(&mut self);
+    /// Runs what the keys typed so far come to once the key timeout runs out.
+    fn refresh(&mut self, pane: &mut dyn Pane, container: &mut PaneContainer);
+
+    /// Returns the keys typed so far and the rest of each binding that they could turn into.
+    /// Modes that don't have multi-key bindings have nothing pending.
+    fn get_pending_bindings(&self) -> Option<(Keys, Vec<(Keys, String)>)> {
+        None
+    }
+
+    /// The keys of a command that is still being typed, like a count or the start of a binding.
+    fn get_pending_keys(&self) -> String {
+        String::new()
+    }
+}
+
+/// Gives the modes of a pane the bindings and key timeout from the settings again, after they were read again.
+/// Visual Line and Visual Block mode have the same bindings as Visual mode.
+pub fn reload_keybindings(modes: &HashMap<String, Rc<RefCell<dyn Mode>>>, settings: &Settings) {
+    for (name, mode) in modes.iter() {
+        let name = if name == "Visual Line" || name == "Visual Block" { "Visual" } else { name.as_str() };
+        let mut mode = mode.borrow_mut();
+        if let Some(bindings) = settings.mode_keybindings.get(name) {
+            mode.set_keybindings(bindings.clone());
+        }
+        mode.set_key_timeout(settings.editor_settings.key_timeout);
+    }
+}
+
+/// Joins the pending keys of a mode so the status bar can show them.
+pub fn show_keys(keys: &Keys) -> String {
+    keys.iter().map(|key| key.to_string()).collect::<Vec<String>>().join(" ")
 }
 
 pub trait Promptable: Mode {
     fn draw_prompt(&mut self, row: usize, container: &PaneContainer) -> Vec<Option<StyledChar>>;
 
     fn max_width(&self) -> usize;
+
+    /// Handles a click on a row of the prompts, counting from the first one.
+    fn click(&mut self, _row: usize, _pane: &mut dyn Pane, _container: &mut PaneContainer) {}
 }
 
+/// What a button does when it is pressed
+pub type ButtonFunction = Box<dyn Fn(&dyn Promptable) -> String>;
+
 
 
 
@@ -53,7 +94,7 @@ pub enum PromptType {
     Text(String, Option<usize>,bool),
     /// A button that has a label and a function to call when pressed
     /// The usize is the index of the currently selected button
-    Button(Vec<(String, Box<dyn Fn(&dyn Promptable) -> String>)>, usize),
+    Button(Vec<(String, ButtonFunction)>, usize),
     /// A checkbox that has a label and a bool indicating whether or not it is checked
     /// The usize is the index of the currently selected checkbox
     Checkbox(Vec<(String, bool)>, usize),
@@ -31,6 +31,16 @@ impl Prompt {
             current_prompt: 0,
         }
     }
+
+    /// Lets the pane that opened the prompt see the text as it is typed.
+    fn send_preview(&self, pane: &mut dyn Pane, container: &mut PaneContainer) {
+        let text = match &self.prompts.borrow()[self.current_prompt] {
+            PromptType::Text(text, _, _) => text.clone(),
+            _ => return,
+        };
+
+        pane.run_command(&format!("preview text {}", text), container);
+    }
 }
 
 
@@ -219,6 +229,19 @@ impl Promptable for Prompt {
 
         max
     }
+
+    /// Clicking a prompt goes to it, and pushes it like Space does if it isn't text.
+    fn click(&mut self, row: usize, pane: &mut dyn Pane, container: &mut PaneContainer) {
+        let is_text = match self.prompts.borrow().get(row) {
+            None => return,
+            Some(prompt) => prompt.is_text(),
+        };
+
+        self.current_prompt = row;
+        if !is_text {
+            self.execute_command("toggle", pane, container);
+        }
+    }
 }
 
 
@@ -239,7 +262,7 @@ impl Mode for Prompt {
         //self.key_buffer.clear();
     }
 
-    fn refresh(&mut self) {
+    fn refresh(&mut self, _pane: &mut dyn Pane, _container: &mut PaneContainer) {
     }
 
     fn change_mode(&mut self, mode: &str, pane: &mut dyn Pane, container: &mut PaneContainer) {
@@ -394,6 +417,7 @@ impl Mode for Prompt {
                         _ => {},
                     }
                 }
+                self.send_preview(pane, container);
                 return Ok(true);
             },
             KeyEvent {
@@ -408,6 +432,7 @@ impl Mode for Prompt {
                     },
                     _ => {},
                 }
+                self.send_preview(pane, container);
                 return Ok(true);
             },
             key_event => {
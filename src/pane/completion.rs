@@ -0,0 +1,229 @@
+use std::{rc::Rc, cell::RefCell, sync::mpsc::{Sender, Receiver}, collections::HashSet};
+
+use crate::{window::Message, settings::Settings, definition::is_identifier_char, path_completion::{path_before, complete_names, name_len}, mode::drop_down::SidePanel, lsp::lsp_utils::{CompletionList, CompletionItem, TextEditType, wrap_text}};
+use super::{PaneMessage, PaneContainer, popup::PopUpPane};
+
+
+/// The widest the list of completions gets.
+const COMPLETION_WIDTH: usize = 60;
+/// The widest the documentation beside the completions gets.
+const DOCUMENTATION_WIDTH: usize = 50;
+/// The documentation isn't shown in less room than this.
+const MIN_DOCUMENTATION_WIDTH: usize = 16;
+/// How many lines of documentation the completion popup grows to fit.
+const DOCUMENTATION_ROWS: usize = 12;
+/// The most words the buffers offer at once.
+const MAX_WORDS: usize = 50;
+
+/// Where the documentation of a completion list at column `x` fits, as its width and whether it goes on the left.
+/// It goes on the right of the list unless there is more room on the left.
+fn place_side_panel(x: usize, list_width: usize, cols: usize) -> Option<(usize, bool)> {
+    let right = cols.saturating_sub(x + list_width);
+    let (room, left) = if right >= DOCUMENTATION_WIDTH || right >= x {
+        (right, false)
+    }
+    else {
+        (x, true)
+    };
+    let width = room.min(DOCUMENTATION_WIDTH);
+    (width >= MIN_DOCUMENTATION_WIDTH).then_some((width, left))
+}
+
+/// Wraps documentation to the width.
+/// Short lines keep their indentation since they are often code.
+fn wrap_documentation(text: &str, width: usize) -> Vec<String> {
+    text.lines()
+        .flat_map(|line| {
+            if line.chars().count() <= width {
+                vec![line.to_string()]
+            }
+            else {
+                wrap_text(line, width)
+            }
+        })
+        .collect()
+}
+
+/// Makes the dropdown for the completions at the cursor, which is at `(x, y)` on the screen.
+/// The documentation of the selected completion goes beside the list where there is room for it.
+/// `below` moves the list a row further down, for when it shouldn't cover what is being typed.
+/// Returns the popup and the channels to talk to it.
+pub fn completion_popup(completion_list: &CompletionList,
+                        (x, y): (usize, usize),
+                        below: bool,
+                        container: &PaneContainer,
+                        settings: &Rc<RefCell<Settings>>,
+                        sender: &Sender<Message>) -> (PaneContainer, (Sender<PaneMessage>, Receiver<PaneMessage>)) {
+    let max_size = container.get_size();
+    let list_width = completion_list.row_width().min(COMPLETION_WIDTH).min(max_size.0);
+    let buttons = completion_list.generate_buttons(list_width);
+
+    let documentation = completion_list.items.iter()
+        .map(|item| item.documentation_text())
+        .collect::<Vec<_>>();
+    let side_panel = match place_side_panel(x, list_width, max_size.0) {
+        Some((width, left)) if documentation.iter().any(Option::is_some) => Some(SidePanel {
+            lines: documentation.iter()
+                .map(|text| text.as_deref().map(|text| wrap_documentation(text, width - 2)).unwrap_or_default())
+                .collect(),
+            width,
+            left,
+        }),
+        _ => None,
+    };
+
+    let mut rows = completion_list.items.len();
+    if let Some(panel) = &side_panel {
+        let longest = panel.lines.iter().map(|lines| lines.len()).max().unwrap_or(0);
+        rows = rows.max(longest.min(DOCUMENTATION_ROWS));
+    }
+    let size = (list_width + side_panel.as_ref().map_or(0, |panel| panel.width), rows + 2);
+    let y = if below { y + 1 } else { y };
+    // A panel on the left pushes the popup over so the list stays under the cursor
+    let pos = match &side_panel {
+        Some(panel) if panel.left => (x - panel.width, y),
+        _ => (x, y),
+    };
+
+    let (send, recv) = std::sync::mpsc::channel();
+    let (send2, recv2) = std::sync::mpsc::channel();
+
+    let pane = PopUpPane::new_dropdown_with_panel(
+        settings.clone(),
+        Vec::new(),
+        sender.clone(),
+        send,
+        recv2,
+        buttons,
+        side_panel,
+        false,
+    );
+
+    let pane = Rc::new(RefCell::new(pane));
+
+    let mut popup = PaneContainer::new(max_size, size, pane, settings.clone());
+    popup.set_position(pos);
+    popup.set_size(size);
+
+    (popup, (send2, recv))
+}
+
+/// What inserting a completion replaces, as the start and end (column, row) and the text to put there.
+/// Without a range from the server the completion replaces the part of the word before the cursor,
+/// which is at `(x, y)` on `line`.
+pub fn completion_edit(completion: &CompletionItem, line: &str, (x, y): (usize, usize)) -> ((usize, usize), (usize, usize), String) {
+    match completion.get_edit_text() {
+        Some(TextEditType::TextEdit(edit)) => {
+            let (start, end) = edit.get_range();
+            (start, end, edit.newText)
+        },
+        Some(TextEditType::InsertReplaceEdit(edit)) => {
+            let start = (edit.insert.start.character, edit.insert.start.line);
+            let end = (edit.insert.end.character, edit.insert.end.line);
+            (start, end, edit.new_text)
+        },
+        None => {
+            let typed = word_prefix(line, x).chars().count();
+            let text = completion.insertText.clone().unwrap_or_else(|| completion.label.clone());
+            ((x - typed, y), (x, y), text)
+        },
+    }
+}
+
+/// The files that complete the path before column `x` of row `y`, if the text there looks like a path.
+/// Only the name after the last `/` gets replaced.
+pub fn path_completions(line: &str, (x, y): (usize, usize), hidden: bool) -> Option<CompletionList> {
+    let before = line.chars().take(x).collect::<String>();
+    let partial = path_before(&before)?;
+    let names = complete_names(partial, hidden);
+    Some(CompletionList::from_paths(names, y, (x - name_len(partial), x)))
+}
+
+/// The part of the identifier before column `x` of the line.
+pub fn word_prefix(line: &str, x: usize) -> String {
+    let before = line.chars().take(x).collect::<Vec<_>>();
+    let typed = before.iter().rev().take_while(|c| is_identifier_char(**c)).count();
+    before[before.len() - typed..].iter().collect()
+}
+
+/// The identifiers of the text with the byte each starts at.
+fn words(text: &str) -> Vec<(usize, &str)> {
+    let mut words = Vec::new();
+    let mut start = None;
+    for (i, c) in text.char_indices() {
+        match (is_identifier_char(c), start) {
+            (true, None) => start = Some(i),
+            (false, Some(word_start)) => {
+                words.push((word_start, &text[word_start..i]));
+                start = None;
+            },
+            _ => {},
+        }
+    }
+    if let Some(word_start) = start {
+        words.push((word_start, &text[word_start..]));
+    }
+    words
+}
+
+/// The words of the open buffers that start with `prefix`, for completing without a language server.
+/// The words of `text`, the buffer with the cursor at byte `cursor`, come first with the nearest ones first,
+/// then the words of the `others` in the order they appear.
+/// Numbers and the word being typed are left out.
+pub fn buffer_words(prefix: &str, text: &str, cursor: usize, others: &[String]) -> Vec<String> {
+    let wanted = |word: &str| word.len() > prefix.len()
+        && word.starts_with(prefix)
+        && !word.starts_with(|c: char| c.is_numeric());
+
+    let mut nearby = words(text).into_iter()
+        .filter(|(start, word)| !(*start < cursor && cursor <= start + word.len()) && wanted(word))
+        .map(|(start, word)| {
+            let distance = if start >= cursor { start - cursor } else { cursor - (start + word.len()) };
+            (distance, word)
+        })
+        .collect::<Vec<_>>();
+    nearby.sort_by_key(|(distance, _)| *distance);
+
+    let elsewhere = others.iter()
+        .flat_map(|text| words(text))
+        .map(|(_, word)| word)
+        .filter(|word| wanted(word));
+
+    let mut seen = HashSet::new();
+    nearby.into_iter()
+        .map(|(_, word)| word)
+        .chain(elsewhere)
+        .filter(|word| seen.insert(*word))
+        .take(MAX_WORDS)
+        .map(str::to_string)
+        .collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_place_side_panel() {
+        assert_eq!(place_side_panel(0, 30, 120), Some((DOCUMENTATION_WIDTH, false)));
+        // Near the right edge the panel flips over to the left of the list
+        assert_eq!(place_side_panel(70, 30, 120), Some((DOCUMENTATION_WIDTH, true)));
+        assert_eq!(place_side_panel(10, 30, 60), Some((20, false)));
+        assert_eq!(place_side_panel(5, 70, 80), None);
+    }
+
+    #[test]
+    fn test_buffer_words() {
+        let text = "counter count_all 12counts\ncou\nlet counted = country;";
+        let cursor = text.find("cou\n").unwrap() + 3;
+        let others = vec!["count_all county".to_string()];
+
+        // The nearest come first and each word only once
+        assert_eq!(buffer_words("cou", text, cursor, &others),
+                   vec!["counted", "count_all", "country", "counter", "county"]);
+        assert_eq!(buffer_words("county", text, cursor, &others), Vec::<String>::new());
+        assert_eq!(word_prefix("let x = cou", 11), "cou");
+        assert_eq!(word_prefix("a.b", 2), "");
+    }
+}
@@ -0,0 +1,280 @@
+use std::{rc::Rc, cell::RefCell, sync::mpsc::Sender, path::PathBuf, io};
+
+use crossterm::event::{KeyEvent, KeyCode};
+
+use crate::{cursor::{Cursor, Direction}, window::{StyledChar, Message, TextRow}, settings::Settings, buffer::Buffer, lsp::lsp_utils::Diagnostics};
+use super::{PaneMessage, PaneContainer, Pane};
+
+
+
+/// Lists the diagnostics of a buffer, one per row.
+/// Enter sends the position of the diagnostic under the cursor back to the pane of the buffer.
+pub struct DiagnosticsPane {
+    diagnostics: Rc<RefCell<Diagnostics>>,
+    /// The (column, row) of the diagnostic on each row of the list.
+    positions: Vec<(usize, usize)>,
+    contents: Buffer,
+    cursor: Rc<RefCell<Cursor>>,
+    settings: Rc<RefCell<Settings>>,
+    sender: Sender<Message>,
+    pane_sender: Sender<PaneMessage>,
+}
+
+impl DiagnosticsPane {
+    pub fn new(settings: Rc<RefCell<Settings>>,
+               diagnostics: Rc<RefCell<Diagnostics>>,
+               sender: Sender<Message>,
+               pane_sender: Sender<PaneMessage>) -> Self {
+        let mut pane = Self {
+            diagnostics,
+            positions: Vec::new(),
+            contents: Buffer::new(settings.clone()),
+            cursor: Rc::new(RefCell::new(Cursor::new((0, 0)))),
+            settings,
+            sender,
+            pane_sender,
+        };
+        pane.update_list();
+        pane
+    }
+
+    /// Rebuilds the rows from the diagnostics, which change whenever the server publishes new ones.
+    fn update_list(&mut self) {
+        let (rows, positions) = {
+            let diagnostics = self.diagnostics.borrow();
+            let mut entries = diagnostics.diagnostics.iter().collect::<Vec<_>>();
+            entries.sort_by_key(|diagnostic| (diagnostic.range.start.line, diagnostic.range.start.character));
+
+            let rows = entries.iter()
+                .map(|diagnostic| format!("{}:{} {} {}",
+                                          diagnostic.range.start.line + 1,
+                                          diagnostic.range.start.character + 1,
+                                          diagnostic.severity_name(),
+                                          diagnostic.message.lines().next().unwrap_or("")))
+                .collect::<Vec<String>>();
+            let positions = entries.iter()
+                .map(|diagnostic| (diagnostic.range.start.character, diagnostic.range.start.line))
+                .collect::<Vec<_>>();
+            (rows, positions)
+        };
+
+        let text = if rows.is_empty() {
+            "No diagnostics".to_string()
+        }
+        else {
+            rows.join("\n")
+        };
+
+        if positions == self.positions && text == self.contents.to_string() {
+            return;
+        }
+
+        self.positions = positions;
+        self.contents = Buffer::from(text);
+        self.contents.set_settings(self.settings.clone());
+
+        let (_, y) = self.cursor.borrow().get_cursor();
+        let last = self.positions.len().saturating_sub(1);
+        let cursor = self.cursor.clone();
+        cursor.borrow_mut().jump_to(0, y.min(last), self);
+    }
+
+    fn select(&mut self) {
+        let (_, y) = self.cursor.borrow().get_cursor();
+        let (col, row) = match self.positions.get(y) {
+            None => return,
+            Some(position) => *position,
+        };
+
+        // The pane of the buffer might have been closed already
+        if self.pane_sender.send(PaneMessage::String(format!("{} {}", col, row))).is_ok() {
+            self.sender.send(Message::PaneUp).expect("Failed to send message");
+        }
+    }
+}
+
+
+impl Pane for DiagnosticsPane {
+    fn draw_row(&self, mut index: usize, container: &PaneContainer, output: &mut TextRow) {
+        let (mut cols, rows) = container.get_size();
+        if cols == 0 || rows == 0 {
+            return;
+        }
+
+        let settings = self.settings.borrow();
+        let ((x1, y1), _) = container.get_corners();
+
+        if container.has_border() {
+            if y1 != 0 {
+                if index == 0 {
+                    for _ in 0..cols {
+                        output.push(Some(Some(StyledChar::new('-', settings.colors.ui.clone()))));
+                    }
+                    return;
+                }
+                index -= 1;
+            }
+
+            if x1 != 0 {
+                output.push(Some(Some(StyledChar::new('|', settings.colors.ui.clone()))));
+                cols = cols.saturating_sub(1);
+            }
+        }
+
+        let start = output.len();
+        let (row_offset, cursor_row) = {
+            let cursor = self.cursor.borrow();
+            (cursor.row_offset, cursor.get_cursor().1)
+        };
+        let row = row_offset + index;
+
+        let mut text = match self.contents.get_row(row, 0, usize::MAX) {
+            None => Vec::new(),
+            Some(line) => line.chars().filter(|c| *c != '\n').collect::<Vec<char>>(),
+        };
+        if text.len() > cols {
+            text.truncate(cols.saturating_sub(1));
+            text.push('…');
+        }
+
+        for c in text.iter() {
+            output.push(Some(Some(StyledChar::new(*c, settings.colors.pane.clone()))));
+        }
+        for _ in text.len()..cols {
+            output.push(Some(Some(StyledChar::new(' ', settings.colors.pane.clone()))));
+        }
+
+        if row == cursor_row && !self.positions.is_empty() {
+            for i in start..start + cols {
+                output.set_background(i, settings.colors.selection.background_color);
+            }
+        }
+    }
+
+    fn refresh(&mut self, _container: &mut PaneContainer) {
+        self.update_list();
+    }
+
+    fn process_keypress(&mut self, key: KeyEvent, _container: &mut PaneContainer) -> io::Result<bool> {
+        let cursor = self.cursor.clone();
+        match key.code {
+            KeyCode::Char('j') | KeyCode::Down => cursor.borrow_mut().move_cursor(Direction::Down, 1, self),
+            KeyCode::Char('k') | KeyCode::Up => cursor.borrow_mut().move_cursor(Direction::Up, 1, self),
+            KeyCode::Char('g') | KeyCode::Home => cursor.borrow_mut().move_cursor(Direction::FileTop, 1, self),
+            KeyCode::Char('G') | KeyCode::End => cursor.borrow_mut().move_cursor(Direction::FileBottom, 1, self),
+            KeyCode::Enter => self.select(),
+            KeyCode::Char('q') | KeyCode::Esc => self.sender.send(Message::ClosePane(false, None)).expect("Failed to send message"),
+            _ => {},
+        }
+        Ok(true)
+    }
+
+    fn scroll_cursor(&mut self, container: &PaneContainer) {
+        let cursor = self.cursor.clone();
+        cursor.borrow_mut().scroll(self, container);
+    }
+
+    fn get_status(&self, _container: &PaneContainer) -> (String, String, String) {
+        let (_, y) = self.cursor.borrow().get_cursor();
+        let position = if self.positions.is_empty() {
+            String::new()
+        }
+        else {
+            format!("{} of {}", y + 1, self.positions.len())
+        };
+        ("Diagnostics".to_string(), position, String::new())
+    }
+
+    fn run_command(&mut self, command: &str, _container: &PaneContainer) {
+        match command.trim() {
+            "select" => self.select(),
+            "q" | "q!" => self.sender.send(Message::ClosePane(false, None)).expect("Failed to send message"),
+            _ => {},
+        }
+    }
+
+    fn execute_command(&mut self, command: &str, container: &mut PaneContainer) {
+        self.run_command(command, container);
+    }
+
+    fn change_mode(&mut self, _mode_name: &str) {}
+
+    fn get_settings(&self) -> Rc<RefCell<Settings>> {
+        self.settings.clone()
+    }
+
+    fn set_sender(&mut self, sender: Sender<Message>) {
+        self.sender = sender;
+    }
+
+    fn reset(&mut self) {
+        self.cursor.borrow_mut().reset_move();
+    }
+
+    fn changed(&mut self) {
+        self.cursor.borrow_mut().set_moved();
+    }
+
+    fn get_cursor(&self) -> Rc<RefCell<Cursor>> {
+        self.cursor.clone()
+    }
+
+    fn save_buffer(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn open_file(&mut self, _filename: &PathBuf) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn insert_newline(&mut self) {}
+
+    fn insert_char(&mut self, _c: char) {}
+
+    fn insert_str(&mut self, _s: &str) {}
+
+    fn delete_char(&mut self) {}
+
+    fn backspace_char(&mut self) {}
+
+    fn get_line_count(&self) -> usize {
+        self.contents.get_line_count()
+    }
+
+    fn buffer_to_string(&self) -> String {
+        self.contents.to_string()
+    }
+
+    fn get_row_len(&self, row: usize) -> Option<usize> {
+        self.contents.line_len(row)
+    }
+
+    fn get_filename(&self) -> &Option<PathBuf> {
+        &None
+    }
+
+    fn get_name(&self) -> String {
+        "[Diagnostics]".to_string()
+    }
+
+    fn resize_cursor(&mut self, size: (usize, usize)) {
+        self.cursor.borrow_mut().resize(size);
+    }
+
+    fn set_cursor_size(&mut self, size: (usize, usize)) {
+        self.cursor.borrow_mut().set_size(size);
+    }
+
+    fn set_location(&mut self, (x, y): (usize, usize)) {
+        let cursor = self.cursor.clone();
+        cursor.borrow_mut().jump_to(x, y, self);
+    }
+
+    fn borrow_buffer(&self) -> &Buffer {
+        &self.contents
+    }
+
+    fn borrow_mut_buffer(&mut self) -> &mut Buffer {
+        &mut self.contents
+    }
+}
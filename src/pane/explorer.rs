@@ -0,0 +1,642 @@
+use std::{rc::Rc, cell::RefCell, sync::mpsc::{Sender, Receiver, TryRecvError}, path::{Path, PathBuf}, collections::HashSet, fs, io};
+
+use crossterm::event::{KeyEvent, KeyCode, KeyModifiers};
+
+use crate::{cursor::{Cursor, Direction}, window::{StyledChar, Message, MessageKind, TextRow}, settings::Settings, buffer::Buffer, mode::{PromptType, ButtonFunction}};
+use super::{PaneMessage, PaneContainer, Pane, popup::PopUpPane};
+
+
+/// A file or directory on a row of the tree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TreeRow {
+    pub path: PathBuf,
+    pub depth: usize,
+    pub is_dir: bool,
+}
+
+/// The path of an entry of a directory.
+/// The entries of the current directory don't get a `./` in front so they match the names files are opened with.
+fn child_path(dir: &Path, name: &str) -> PathBuf {
+    if dir == Path::new(".") {
+        PathBuf::from(name)
+    }
+    else {
+        dir.join(name)
+    }
+}
+
+/// The rows of the tree under `dir`, with the entries of the expanded directories below them.
+/// Directories come before files and each are sorted by name.
+pub fn tree_rows(dir: &Path, depth: usize, expanded: &HashSet<PathBuf>, hidden: bool) -> Vec<TreeRow> {
+    let entries = match fs::read_dir(dir) {
+        Err(_) => return Vec::new(),
+        Ok(entries) => entries,
+    };
+
+    let mut entries = entries.filter_map(|entry| {
+        let entry = entry.ok()?;
+        let name = entry.file_name().into_string().ok()?;
+        if name.starts_with('.') && !hidden {
+            return None;
+        }
+        // Links to directories are followed like directories
+        let is_dir = fs::metadata(entry.path()).is_ok_and(|metadata| metadata.is_dir());
+        Some((!is_dir, name))
+    })
+    .collect::<Vec<_>>();
+    entries.sort();
+
+    let mut rows = Vec::new();
+    for (is_file, name) in entries {
+        let path = child_path(dir, &name);
+        let open = !is_file && expanded.contains(&path);
+        rows.push(TreeRow {
+            path: path.clone(),
+            depth,
+            is_dir: !is_file,
+        });
+        if open {
+            rows.extend(tree_rows(&path, depth + 1, expanded, hidden));
+        }
+    }
+    rows
+}
+
+/// What the answer of the popup of the explorer is for.
+enum PendingAction {
+    CreateFile(PathBuf),
+    CreateDir(PathBuf),
+    Rename(PathBuf),
+    Delete(PathBuf),
+}
+
+/// Shows a directory as a tree that directories can be opened and closed in.
+/// Files get opened in the place of the explorer or in a split,
+/// and can be created, renamed and deleted from the tree.
+pub struct ExplorerPane {
+    root: PathBuf,
+    expanded: HashSet<PathBuf>,
+    hidden: bool,
+    rows: Vec<TreeRow>,
+    /// What the open popup asks about and where its answer comes from.
+    pending: Option<(PendingAction, Receiver<PaneMessage>)>,
+    /// Whether the last key was `ctrl-w`, so the next one moves between panes.
+    window_key: bool,
+    contents: Buffer,
+    cursor: Rc<RefCell<Cursor>>,
+    settings: Rc<RefCell<Settings>>,
+    sender: Sender<Message>,
+}
+
+impl ExplorerPane {
+    pub fn new(settings: Rc<RefCell<Settings>>, root: PathBuf, sender: Sender<Message>) -> Self {
+        let hidden = settings.borrow().editor_settings.show_hidden_files;
+        let mut pane = Self {
+            root,
+            expanded: HashSet::new(),
+            hidden,
+            rows: Vec::new(),
+            pending: None,
+            window_key: false,
+            contents: Buffer::new(settings.clone()),
+            cursor: Rc::new(RefCell::new(Cursor::new((0, 0)))),
+            settings,
+            sender,
+        };
+        pane.update_tree(None);
+        pane
+    }
+
+    /// Reads the tree from the disk again, putting the cursor on `select` if it is still there.
+    fn update_tree(&mut self, select: Option<&Path>) {
+        let (_, y) = self.cursor.borrow().get_cursor();
+        let selected = select.map(Path::to_path_buf)
+            .or_else(|| self.rows.get(y).map(|row| row.path.clone()));
+
+        // Directories that were deleted or renamed don't stay open
+        self.expanded.retain(|dir| dir.is_dir());
+        self.rows = tree_rows(&self.root, 0, &self.expanded, self.hidden);
+
+        let text = if self.rows.is_empty() {
+            "Empty directory".to_string()
+        }
+        else {
+            self.rows.iter()
+                .map(|row| {
+                    let name = row.path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+                    let indent = "  ".repeat(row.depth);
+                    match (row.is_dir, self.expanded.contains(&row.path)) {
+                        (true, true) => format!("{}▾ {}/", indent, name),
+                        (true, false) => format!("{}▸ {}/", indent, name),
+                        (false, _) => format!("{}  {}", indent, name),
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join("\n")
+        };
+        self.contents = Buffer::from(text);
+        self.contents.set_settings(self.settings.clone());
+
+        let y = selected.and_then(|path| self.rows.iter().position(|row| row.path == path))
+            .unwrap_or(y)
+            .min(self.rows.len().saturating_sub(1));
+        let cursor = self.cursor.clone();
+        cursor.borrow_mut().jump_to(0, y, self);
+    }
+
+    fn selected(&self) -> Option<TreeRow> {
+        let (_, y) = self.cursor.borrow().get_cursor();
+        self.rows.get(y).cloned()
+    }
+
+    /// The directory that new files go in, which is the selected directory or the one of the selected file.
+    fn target_dir(&self) -> PathBuf {
+        match self.selected() {
+            None => self.root.clone(),
+            Some(row) if row.is_dir => row.path,
+            Some(row) => row.path.parent()
+                .filter(|parent| !parent.as_os_str().is_empty())
+                .map(Path::to_path_buf)
+                .unwrap_or_else(|| self.root.clone()),
+        }
+    }
+
+    /// Opens or closes the selected directory, or opens the selected file.
+    fn activate(&mut self, open: Option<bool>) {
+        let row = match self.selected() {
+            None => return,
+            Some(row) => row,
+        };
+
+        if !row.is_dir {
+            self.open_path(&row.path, None);
+            return;
+        }
+
+        let is_open = self.expanded.contains(&row.path);
+        match open {
+            Some(false) | None if is_open => {
+                self.expanded.remove(&row.path);
+            },
+            Some(true) | None if !is_open => {
+                self.expanded.insert(row.path.clone());
+            },
+            _ => return,
+        }
+        self.update_tree(None);
+    }
+
+    /// Closes the selected directory or goes up to the directory the selected row is in.
+    fn collapse(&mut self) {
+        let row = match self.selected() {
+            None => return,
+            Some(row) => row,
+        };
+
+        if row.is_dir && self.expanded.contains(&row.path) {
+            self.activate(Some(false));
+            return;
+        }
+
+        let parent = match row.path.parent() {
+            Some(parent) if self.expanded.contains(parent) => parent.to_path_buf(),
+            _ => return,
+        };
+        self.expanded.remove(&parent);
+        self.update_tree(Some(&parent));
+    }
+
+    /// Shows the directory above the root instead.
+    fn go_up(&mut self) {
+        let root = match self.root.canonicalize() {
+            Ok(root) => root,
+            Err(_) => return,
+        };
+        let parent = match root.parent() {
+            None => return,
+            Some(parent) => parent.to_path_buf(),
+        };
+
+        self.expanded.clear();
+        self.expanded.insert(root.clone());
+        self.root = parent;
+        self.update_tree(Some(&root));
+    }
+
+    /// Opens a file in the place of the explorer, or in a split when `vertical` says which way.
+    fn open_path(&self, path: &Path, vertical: Option<bool>) {
+        let path = path.to_string_lossy().to_string();
+        let message = match vertical {
+            None => Message::OpenFile(path, None),
+            Some(vertical) => Message::SplitOpenFile(path, vertical),
+        };
+        self.sender.send(message).expect("Failed to send message");
+    }
+
+    fn show_error(&self, action: &str, err: io::Error) {
+        self.sender.send(Message::SetMessage(format!("Failed to {}: {}", action, err), MessageKind::Error)).expect("Failed to send message");
+    }
+
+    /// Asks for something with a popup in the middle of the explorer.
+    /// The answer gets handled by `check_popup`.
+    fn ask(&mut self, action: PendingAction, title: String, prompt: PromptType, container: &PaneContainer) {
+        let (send, recv) = std::sync::mpsc::channel();
+        let (_, recv2) = std::sync::mpsc::channel();
+
+        let width = title.chars().count().max(30) + 2;
+        let pane = PopUpPane::new_prompt(
+            self.settings.clone(),
+            vec![title],
+            self.sender.clone(),
+            send,
+            recv2,
+            vec![prompt],
+            true
+        );
+        let pane = Rc::new(RefCell::new(pane));
+
+        let ((x1, y1), _) = container.get_corners();
+        let (x, y) = container.get_size();
+        let size = (width.min(x), 4.min(y));
+        let pos = ((x1 + x / 2).saturating_sub(size.0 / 2), (y1 + y / 2).saturating_sub(size.1 / 2));
+
+        let mut popup = PaneContainer::new(container.get_size(), size, pane, self.settings.clone());
+        popup.set_position(pos);
+        popup.set_size(size);
+
+        self.sender.send(Message::CreatePopup(popup, true)).expect("Failed to send message");
+        self.pending = Some((action, recv));
+    }
+
+    fn ask_name(&mut self, action: PendingAction, title: &str, name: String, container: &PaneContainer) {
+        self.ask(action, title.to_string(), PromptType::Text(name, None, false), container);
+    }
+
+    fn ask_delete(&mut self, path: PathBuf, container: &PaneContainer) {
+        let mut buttons = Vec::new();
+        for (label, answer) in [("Yes", "y"), ("No", "n")] {
+            let function: ButtonFunction = Box::new(move |_| answer.to_string());
+            buttons.push((label.to_string(), function));
+        }
+
+        let title = format!("Delete {}?", path.display());
+        self.ask(PendingAction::Delete(path), title, PromptType::Button(buttons, 1), container);
+    }
+
+    /// Does what the popup asked about once it has been answered.
+    fn check_popup(&mut self) {
+        let answer = match &self.pending {
+            None => return,
+            Some((_, receiver)) => receiver.try_recv(),
+        };
+        let answer = match answer {
+            Err(TryRecvError::Empty) => return,
+            Ok(PaneMessage::String(answer)) => answer,
+            Ok(PaneMessage::PreviewString(_)) => return,
+            _ => {
+                self.pending = None;
+                return;
+            },
+        };
+        let action = match self.pending.take() {
+            None => return,
+            Some((action, _)) => action,
+        };
+
+        let answer = answer.trim();
+        match action {
+            PendingAction::Delete(path) => {
+                if answer != "y" {
+                    return;
+                }
+                let result = if path.is_dir() { fs::remove_dir_all(&path) } else { fs::remove_file(&path) };
+                match result {
+                    Err(err) => self.show_error("delete", err),
+                    Ok(()) => self.update_tree(None),
+                }
+            },
+            _ if answer.is_empty() => {},
+            PendingAction::CreateFile(dir) => {
+                let path = child_path(&dir, answer);
+                let result = path.parent()
+                    .map_or(Ok(()), fs::create_dir_all)
+                    .and_then(|_| fs::OpenOptions::new().write(true).create_new(true).open(&path).map(|_| ()));
+                match result {
+                    Err(err) => self.show_error("create the file", err),
+                    Ok(()) => {
+                        self.expanded.insert(dir);
+                        self.update_tree(Some(&path));
+                    },
+                }
+            },
+            PendingAction::CreateDir(dir) => {
+                let path = child_path(&dir, answer);
+                match fs::create_dir_all(&path) {
+                    Err(err) => self.show_error("create the directory", err),
+                    Ok(()) => {
+                        self.expanded.insert(dir);
+                        self.update_tree(Some(&path));
+                    },
+                }
+            },
+            PendingAction::Rename(path) => {
+                let new_path = match path.parent() {
+                    Some(parent) if !parent.as_os_str().is_empty() => parent.join(answer),
+                    _ => PathBuf::from(answer),
+                };
+                if new_path.exists() {
+                    self.show_error("rename", io::Error::new(io::ErrorKind::AlreadyExists, format!("{} already exists", new_path.display())));
+                    return;
+                }
+                match fs::rename(&path, &new_path) {
+                    Err(err) => self.show_error("rename", err),
+                    Ok(()) => {
+                        if self.expanded.remove(&path) {
+                            self.expanded.insert(new_path.clone());
+                        }
+                        self.update_tree(Some(&new_path));
+                    },
+                }
+            },
+        }
+    }
+
+    /// The key after `ctrl-w` moves to another pane or splits like it does in the text panes.
+    fn window_command(&self, key: KeyEvent) {
+        let message = match key.code {
+            KeyCode::Char('h') => Message::PaneLeft,
+            KeyCode::Char('j') => Message::PaneDown,
+            KeyCode::Char('k') => Message::PaneUp,
+            KeyCode::Char('l') => Message::PaneRight,
+            KeyCode::Char('s') => Message::HorizontalSplit,
+            KeyCode::Char('v') => Message::VerticalSplit,
+            KeyCode::Char('q') => Message::ClosePane(false, None),
+            _ => return,
+        };
+        self.sender.send(message).expect("Failed to send message");
+    }
+}
+
+
+impl Pane for ExplorerPane {
+    fn draw_row(&self, mut index: usize, container: &PaneContainer, output: &mut TextRow) {
+        let (mut cols, rows) = container.get_size();
+        if cols == 0 || rows == 0 {
+            return;
+        }
+
+        let settings = self.settings.borrow();
+        let ((x1, y1), _) = container.get_corners();
+
+        if container.has_border() {
+            if y1 != 0 {
+                if index == 0 {
+                    for _ in 0..cols {
+                        output.push(Some(Some(StyledChar::new('-', settings.colors.ui.clone()))));
+                    }
+                    return;
+                }
+                index -= 1;
+            }
+
+            if x1 != 0 {
+                output.push(Some(Some(StyledChar::new('|', settings.colors.ui.clone()))));
+                cols = cols.saturating_sub(1);
+            }
+        }
+
+        let start = output.len();
+        let (row_offset, cursor_row) = {
+            let cursor = self.cursor.borrow();
+            (cursor.row_offset, cursor.get_cursor().1)
+        };
+        let row = row_offset + index;
+
+        let mut text = match self.contents.get_row(row, 0, usize::MAX) {
+            None => Vec::new(),
+            Some(line) => line.chars().filter(|c| *c != '\n').collect::<Vec<char>>(),
+        };
+        if text.len() > cols {
+            text.truncate(cols.saturating_sub(1));
+            text.push('…');
+        }
+
+        // Directories stand out from files
+        let color = match self.rows.get(row) {
+            Some(tree_row) if tree_row.is_dir => settings.colors.ui.clone(),
+            _ => settings.colors.pane.clone(),
+        };
+        for c in text.iter() {
+            output.push(Some(Some(StyledChar::new(*c, color.clone()))));
+        }
+        for _ in text.len()..cols {
+            output.push(Some(Some(StyledChar::new(' ', settings.colors.pane.clone()))));
+        }
+
+        if row == cursor_row && !self.rows.is_empty() {
+            for i in start..start + cols {
+                output.set_background(i, settings.colors.selection.background_color);
+            }
+        }
+    }
+
+    fn refresh(&mut self, _container: &mut PaneContainer) {
+        self.check_popup();
+    }
+
+    fn process_keypress(&mut self, key: KeyEvent, container: &mut PaneContainer) -> io::Result<bool> {
+        if self.window_key {
+            self.window_key = false;
+            self.window_command(key);
+            return Ok(true);
+        }
+
+        let cursor = self.cursor.clone();
+        match key.code {
+            KeyCode::Char('w') if key.modifiers.contains(KeyModifiers::CONTROL) => self.window_key = true,
+            KeyCode::Char('j') | KeyCode::Down => cursor.borrow_mut().move_cursor(Direction::Down, 1, self),
+            KeyCode::Char('k') | KeyCode::Up => cursor.borrow_mut().move_cursor(Direction::Up, 1, self),
+            KeyCode::Char('g') | KeyCode::Home => cursor.borrow_mut().move_cursor(Direction::FileTop, 1, self),
+            KeyCode::Char('G') | KeyCode::End => cursor.borrow_mut().move_cursor(Direction::FileBottom, 1, self),
+            KeyCode::Enter => self.activate(None),
+            KeyCode::Char('l') | KeyCode::Right => self.activate(Some(true)),
+            KeyCode::Char('h') | KeyCode::Left => self.collapse(),
+            KeyCode::Char('-') | KeyCode::Backspace => self.go_up(),
+            KeyCode::Char('v') | KeyCode::Char('s') => {
+                if let Some(row) = self.selected().filter(|row| !row.is_dir) {
+                    self.open_path(&row.path, Some(key.code == KeyCode::Char('v')));
+                }
+            },
+            KeyCode::Char('a') => {
+                let dir = self.target_dir();
+                self.ask_name(PendingAction::CreateFile(dir), "New file", String::new(), container);
+            },
+            KeyCode::Char('A') => {
+                let dir = self.target_dir();
+                self.ask_name(PendingAction::CreateDir(dir), "New directory", String::new(), container);
+            },
+            KeyCode::Char('r') => {
+                if let Some(row) = self.selected() {
+                    let name = row.path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+                    self.ask_name(PendingAction::Rename(row.path), "Rename to", name, container);
+                }
+            },
+            KeyCode::Char('d') => {
+                if let Some(row) = self.selected() {
+                    self.ask_delete(row.path, container);
+                }
+            },
+            KeyCode::Char('.') => {
+                self.hidden = !self.hidden;
+                self.update_tree(None);
+            },
+            KeyCode::Char('R') => self.update_tree(None),
+            KeyCode::Char('q') => self.sender.send(Message::ClosePane(false, None)).expect("Failed to send message"),
+            _ => {},
+        }
+        Ok(true)
+    }
+
+    fn scroll_cursor(&mut self, container: &PaneContainer) {
+        let cursor = self.cursor.clone();
+        cursor.borrow_mut().scroll(self, container);
+    }
+
+    fn get_status(&self, _container: &PaneContainer) -> (String, String, String) {
+        let hidden = if self.hidden { "hidden shown" } else { "" };
+        (self.root.display().to_string(), hidden.to_string(), String::new())
+    }
+
+    fn run_command(&mut self, command: &str, _container: &PaneContainer) {
+        match command.trim() {
+            "q" | "q!" => self.sender.send(Message::ClosePane(false, None)).expect("Failed to send message"),
+            "refresh" => self.update_tree(None),
+            _ => {},
+        }
+    }
+
+    fn execute_command(&mut self, command: &str, container: &mut PaneContainer) {
+        self.run_command(command, container);
+    }
+
+    fn change_mode(&mut self, _mode_name: &str) {}
+
+    fn get_settings(&self) -> Rc<RefCell<Settings>> {
+        self.settings.clone()
+    }
+
+    fn set_sender(&mut self, sender: Sender<Message>) {
+        self.sender = sender;
+    }
+
+    fn reset(&mut self) {
+        self.cursor.borrow_mut().reset_move();
+    }
+
+    fn changed(&mut self) {
+        self.cursor.borrow_mut().set_moved();
+    }
+
+    fn get_cursor(&self) -> Rc<RefCell<Cursor>> {
+        self.cursor.clone()
+    }
+
+    fn save_buffer(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn open_file(&mut self, _filename: &PathBuf) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn insert_newline(&mut self) {}
+
+    fn insert_char(&mut self, _c: char) {}
+
+    fn insert_str(&mut self, _s: &str) {}
+
+    fn delete_char(&mut self) {}
+
+    fn backspace_char(&mut self) {}
+
+    fn get_line_count(&self) -> usize {
+        self.contents.get_line_count()
+    }
+
+    fn buffer_to_string(&self) -> String {
+        self.contents.to_string()
+    }
+
+    fn get_row_len(&self, row: usize) -> Option<usize> {
+        self.contents.line_len(row)
+    }
+
+    fn get_filename(&self) -> &Option<PathBuf> {
+        &None
+    }
+
+    fn get_name(&self) -> String {
+        format!("[{}]", self.root.display())
+    }
+
+    fn is_waiting(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    fn resize_cursor(&mut self, size: (usize, usize)) {
+        self.cursor.borrow_mut().resize(size);
+    }
+
+    fn set_cursor_size(&mut self, size: (usize, usize)) {
+        self.cursor.borrow_mut().set_size(size);
+    }
+
+    fn set_location(&mut self, (x, y): (usize, usize)) {
+        let cursor = self.cursor.clone();
+        cursor.borrow_mut().jump_to(x, y, self);
+    }
+
+    fn borrow_buffer(&self) -> &Buffer {
+        &self.contents
+    }
+
+    fn borrow_mut_buffer(&mut self) -> &mut Buffer {
+        &mut self.contents
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tree_rows() {
+        let dir = std::env::temp_dir().join("vi_explorer_test");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::write(dir.join("src").join("main.rs"), "").unwrap();
+        fs::write(dir.join("Cargo.toml"), "").unwrap();
+        fs::write(dir.join(".gitignore"), "").unwrap();
+
+        let row = |path: PathBuf, depth, is_dir| TreeRow { path, depth, is_dir };
+
+        // Directories come first and closed ones hide what is in them
+        let mut expanded = HashSet::new();
+        assert_eq!(tree_rows(&dir, 0, &expanded, false), vec![
+            row(dir.join("src"), 0, true),
+            row(dir.join("Cargo.toml"), 0, false),
+        ]);
+
+        expanded.insert(dir.join("src"));
+        assert_eq!(tree_rows(&dir, 0, &expanded, true), vec![
+            row(dir.join("src"), 0, true),
+            row(dir.join("src").join("main.rs"), 1, false),
+            row(dir.join(".gitignore"), 0, false),
+            row(dir.join("Cargo.toml"), 0, false),
+        ]);
+
+        assert_eq!(child_path(Path::new("."), "src"), PathBuf::from("src"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,470 @@
+use std::{rc::Rc, cell::RefCell, sync::mpsc::{self, Sender, Receiver, TryRecvError}, path::{Path, PathBuf}, io, thread};
+
+use crossterm::event::{KeyEvent, KeyCode, KeyModifiers};
+use ignore::WalkBuilder;
+
+use crate::{cursor::Cursor, window::{StyledChar, Message, TextRow}, settings::Settings, buffer::Buffer};
+use super::{PaneContainer, Pane};
+
+
+/// How the picked file gets opened.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpenIn {
+    Current,
+    VerticalSplit,
+    HorizontalSplit,
+}
+
+/// Characters that start a new part of a path, where a match counts for more.
+fn is_separator(c: char) -> bool {
+    matches!(c, '/' | '_' | '-' | '.' | ' ')
+}
+
+/// How well the query matches the path, or `None` if the path doesn't hold the letters of the query in order.
+/// Letters next to each other, letters at the start of a part of the path
+/// and letters in the file name rather than the directories score higher.
+/// Case is only matched when the query has a capital letter.
+pub fn fuzzy_score(query: &str, path: &str) -> Option<i64> {
+    if query.is_empty() {
+        return Some(0);
+    }
+    let ignore_case = !query.chars().any(char::is_uppercase);
+    let fold = |c: char| if ignore_case { c.to_ascii_lowercase() } else { c };
+
+    let chars = path.chars().collect::<Vec<_>>();
+    let name_start = chars.iter().rposition(|c| *c == '/').map_or(0, |slash| slash + 1);
+
+    let mut score: i64 = 0;
+    let mut next = 0;
+    let mut last_match: Option<usize> = None;
+    for q in query.chars().map(fold) {
+        let found = chars[next..].iter().position(|c| fold(*c) == q)? + next;
+
+        score += 1;
+        if last_match.is_some_and(|last| last + 1 == found) {
+            score += 5;
+        }
+        if found == 0 || is_separator(chars[found - 1]) {
+            score += 8;
+        }
+        if found >= name_start {
+            score += 2;
+        }
+        if let Some(last) = last_match {
+            score -= (found - last - 1).min(10) as i64;
+        }
+
+        last_match = Some(found);
+        next = found + 1;
+    }
+
+    // Shorter paths win a tie
+    Some(score * 10 - chars.len() as i64)
+}
+
+/// Walks the files under the root on another thread, skipping what git ignores.
+/// The paths come back relative to the root as they are found.
+fn walk_files(root: PathBuf, hidden: bool) -> Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+
+    thread::spawn(move || {
+        let walker = WalkBuilder::new(&root)
+            .hidden(!hidden)
+            .filter_entry(|entry| entry.file_name() != ".git")
+            .build();
+        for entry in walker.flatten() {
+            if !entry.file_type().is_some_and(|file_type| file_type.is_file()) {
+                continue;
+            }
+            let path = entry.path().strip_prefix(&root).unwrap_or(entry.path());
+            // The finder was closed so nobody wants the rest
+            if sender.send(path.to_string_lossy().to_string()).is_err() {
+                return;
+            }
+        }
+    });
+
+    receiver
+}
+
+/// A popup that finds the files of a project by typing parts of their paths.
+/// The files are listed while they are still being found.
+/// The picked file goes back to the window with how to open it.
+pub struct FinderPane {
+    root: PathBuf,
+    query: String,
+    /// Every file found so far, relative to the root.
+    paths: Vec<String>,
+    /// The index in `paths` of the files that match the query, best first.
+    matches: Vec<usize>,
+    selected: usize,
+    /// The first match shown in the list.
+    offset: usize,
+    receiver: Option<Receiver<String>>,
+    contents: Buffer,
+    cursor: Rc<RefCell<Cursor>>,
+    settings: Rc<RefCell<Settings>>,
+    sender: Sender<Message>,
+    pane_sender: Sender<(String, OpenIn)>,
+}
+
+impl FinderPane {
+    pub fn new(settings: Rc<RefCell<Settings>>,
+               root: PathBuf,
+               sender: Sender<Message>,
+               pane_sender: Sender<(String, OpenIn)>) -> Self {
+        let hidden = settings.borrow().editor_settings.show_hidden_files;
+        let receiver = walk_files(root.clone(), hidden);
+
+        Self {
+            root,
+            query: String::new(),
+            paths: Vec::new(),
+            matches: Vec::new(),
+            selected: 0,
+            offset: 0,
+            receiver: Some(receiver),
+            contents: Buffer::new(settings.clone()),
+            cursor: Rc::new(RefCell::new(Cursor::new((0, 0)))),
+            settings,
+            sender,
+            pane_sender,
+        }
+    }
+
+    /// Moves the files found since the last call into the list.
+    fn receive_paths(&mut self) {
+        let receiver = match &self.receiver {
+            None => return,
+            Some(receiver) => receiver,
+        };
+
+        let start = self.paths.len();
+        loop {
+            match receiver.try_recv() {
+                Ok(path) => self.paths.push(path),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.receiver = None;
+                    break;
+                },
+            }
+        }
+
+        if self.paths.len() > start {
+            self.match_paths(start);
+        }
+    }
+
+    /// Matches the query against the files from `start` on and sorts them in with the earlier matches.
+    fn match_paths(&mut self, start: usize) {
+        if start == 0 {
+            self.matches.clear();
+        }
+
+        let mut scored = self.matches.drain(..)
+            .chain(start..self.paths.len())
+            .filter_map(|index| fuzzy_score(&self.query, &self.paths[index]).map(|score| (score, index)))
+            .collect::<Vec<_>>();
+        scored.sort_by(|(a, a_index), (b, b_index)| b.cmp(a).then(a_index.cmp(b_index)));
+        self.matches = scored.into_iter().map(|(_, index)| index).collect();
+
+        self.selected = self.selected.min(self.matches.len().saturating_sub(1));
+    }
+
+    fn query_changed(&mut self) {
+        self.selected = 0;
+        self.offset = 0;
+        self.match_paths(0);
+    }
+
+    /// The rows that the list of files gets, below the query.
+    fn list_rows(container: &PaneContainer) -> usize {
+        container.get_size().1.saturating_sub(2)
+    }
+
+    /// Scrolls the list so that the selected file can be seen.
+    fn keep_visible(&mut self, rows: usize) {
+        if self.selected < self.offset {
+            self.offset = self.selected;
+        }
+        else if rows > 0 && self.selected >= self.offset + rows {
+            self.offset = self.selected + 1 - rows;
+        }
+    }
+
+    fn move_selection(&mut self, down: bool) {
+        if self.matches.is_empty() {
+            return;
+        }
+        self.selected = if down {
+            (self.selected + 1) % self.matches.len()
+        }
+        else {
+            self.selected.checked_sub(1).unwrap_or(self.matches.len() - 1)
+        };
+    }
+
+    fn open(&mut self, open_in: OpenIn) {
+        let path = match self.matches.get(self.selected) {
+            None => return,
+            Some(index) => self.root.join(&self.paths[*index]),
+        };
+        // Files under the current directory keep the relative names that they are opened with elsewhere
+        let path = match std::env::current_dir() {
+            Ok(dir) => path.strip_prefix(&dir).map(Path::to_path_buf).unwrap_or(path),
+            Err(_) => path,
+        };
+
+        self.close();
+        // The window might have stopped listening already
+        self.pane_sender.send((path.to_string_lossy().to_string(), open_in)).ok();
+    }
+
+    fn close(&mut self) {
+        self.receiver = None;
+        self.sender.send(Message::ClosePane(true, None)).expect("Failed to send message");
+    }
+
+    /// Pushes the text as one row of the popup between the borders, cut off or padded to the width.
+    fn push_row(text: &str, width: usize, settings: &Settings, output: &mut TextRow) {
+        let color = settings.colors.popup.clone();
+        output.push(Some(Some(StyledChar::new('│', color.clone()))));
+
+        let inner = width.saturating_sub(2);
+        let mut chars = text.chars().collect::<Vec<_>>();
+        if chars.len() > inner {
+            // The end of a path says more than its start
+            let cut = chars.len() + 1 - inner;
+            chars.drain(..cut);
+            chars.insert(0, '…');
+        }
+        for c in chars.iter() {
+            output.push(Some(Some(StyledChar::new(*c, color.clone()))));
+        }
+        for _ in chars.len()..inner {
+            output.push(Some(Some(StyledChar::new(' ', color.clone()))));
+        }
+
+        output.push(Some(Some(StyledChar::new('│', color))));
+    }
+}
+
+
+impl Pane for FinderPane {
+    fn draw_row(&self, index: usize, container: &PaneContainer, output: &mut TextRow) {
+        let (width, height) = container.get_size();
+        if width < 2 || height < 2 {
+            return;
+        }
+
+        let settings = self.settings.borrow();
+        let color = settings.colors.popup.clone();
+
+        if index == 0 || index == height {
+            let (left, right) = if index == 0 { ('┌', '┐') } else { ('└', '┘') };
+            let title = if index == 0 { " Find File " } else { "" };
+
+            output.push(Some(Some(StyledChar::new(left, color.clone()))));
+            let mut title = title.chars();
+            for _ in 0..width - 2 {
+                let c = title.next().unwrap_or('─');
+                output.push(Some(Some(StyledChar::new(c, color.clone()))));
+            }
+            output.push(Some(Some(StyledChar::new(right, color))));
+            return;
+        }
+
+        if index == 1 {
+            let searching = if self.receiver.is_some() { "…" } else { "" };
+            let count = format!("{}/{}{}", self.matches.len(), self.paths.len(), searching);
+            let prompt = format!("> {}", self.query);
+            let gap = width.saturating_sub(2 + prompt.chars().count() + count.chars().count()).max(1);
+            let start = output.len();
+            Self::push_row(&format!("{}{}{}", prompt, " ".repeat(gap), count), width, &settings, output);
+
+            // There is no terminal cursor in a popup, so the end of the query is marked instead
+            let end = prompt.chars().count();
+            if end < width - 2 {
+                output.set_background(start + 1 + end, settings.colors.selection.background_color);
+            }
+            return;
+        }
+
+        let row = self.offset + index - 2;
+        let text = match self.matches.get(row) {
+            None => "",
+            Some(path) => self.paths[*path].as_str(),
+        };
+        let start = output.len();
+        Self::push_row(text, width, &settings, output);
+
+        if row == self.selected && !self.matches.is_empty() {
+            for i in start + 1..start + width - 1 {
+                output.set_background(i, settings.colors.selection.background_color);
+            }
+        }
+    }
+
+    fn refresh(&mut self, container: &mut PaneContainer) {
+        self.receive_paths();
+        self.keep_visible(Self::list_rows(container));
+    }
+
+    fn process_keypress(&mut self, key: KeyEvent, container: &mut PaneContainer) -> io::Result<bool> {
+        let control = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Esc => self.close(),
+            KeyCode::Char('c') if control => self.close(),
+            KeyCode::Enter => self.open(OpenIn::Current),
+            KeyCode::Char('v') if control => self.open(OpenIn::VerticalSplit),
+            KeyCode::Char('s') if control => self.open(OpenIn::HorizontalSplit),
+            KeyCode::Up | KeyCode::BackTab => self.move_selection(false),
+            KeyCode::Char('p') | KeyCode::Char('k') if control => self.move_selection(false),
+            KeyCode::Down | KeyCode::Tab => self.move_selection(true),
+            KeyCode::Char('n') | KeyCode::Char('j') if control => self.move_selection(true),
+            KeyCode::Backspace if self.query.pop().is_some() => self.query_changed(),
+            KeyCode::Char('u') if control => {
+                self.query.clear();
+                self.query_changed();
+            },
+            KeyCode::Char(c) if !control => {
+                self.query.push(c);
+                self.query_changed();
+            },
+            _ => {},
+        }
+        self.keep_visible(Self::list_rows(container));
+        Ok(true)
+    }
+
+    fn scroll_cursor(&mut self, _container: &PaneContainer) {}
+
+    fn get_status(&self, _container: &PaneContainer) -> (String, String, String) {
+        let position = if self.matches.is_empty() {
+            String::new()
+        }
+        else {
+            format!("{} of {}", self.selected + 1, self.matches.len())
+        };
+        ("Find File".to_string(), position, String::new())
+    }
+
+    fn run_command(&mut self, command: &str, _container: &PaneContainer) {
+        if let "q" | "q!" = command.trim() {
+            self.close();
+        }
+    }
+
+    fn execute_command(&mut self, command: &str, container: &mut PaneContainer) {
+        self.run_command(command, container);
+    }
+
+    fn change_mode(&mut self, _mode_name: &str) {}
+
+    fn get_settings(&self) -> Rc<RefCell<Settings>> {
+        self.settings.clone()
+    }
+
+    fn set_sender(&mut self, sender: Sender<Message>) {
+        self.sender = sender;
+    }
+
+    fn reset(&mut self) {
+        self.cursor.borrow_mut().reset_move();
+    }
+
+    fn changed(&mut self) {
+        self.cursor.borrow_mut().set_moved();
+    }
+
+    fn get_cursor(&self) -> Rc<RefCell<Cursor>> {
+        self.cursor.clone()
+    }
+
+    fn save_buffer(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn open_file(&mut self, _filename: &PathBuf) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn insert_newline(&mut self) {}
+
+    fn insert_char(&mut self, _c: char) {}
+
+    fn insert_str(&mut self, _s: &str) {}
+
+    fn delete_char(&mut self) {}
+
+    fn backspace_char(&mut self) {}
+
+    fn get_line_count(&self) -> usize {
+        self.contents.get_line_count()
+    }
+
+    fn buffer_to_string(&self) -> String {
+        self.contents.to_string()
+    }
+
+    fn get_row_len(&self, row: usize) -> Option<usize> {
+        self.contents.line_len(row)
+    }
+
+    fn get_filename(&self) -> &Option<PathBuf> {
+        &None
+    }
+
+    fn get_name(&self) -> String {
+        "[Find File]".to_string()
+    }
+
+    fn is_waiting(&self) -> bool {
+        self.receiver.is_some()
+    }
+
+    fn resize_cursor(&mut self, size: (usize, usize)) {
+        self.cursor.borrow_mut().resize(size);
+    }
+
+    fn set_cursor_size(&mut self, size: (usize, usize)) {
+        self.cursor.borrow_mut().set_size(size);
+    }
+
+    fn set_location(&mut self, _location: (usize, usize)) {}
+
+    fn borrow_buffer(&self) -> &Buffer {
+        &self.contents
+    }
+
+    fn borrow_mut_buffer(&mut self) -> &mut Buffer {
+        &mut self.contents
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fuzzy_score() {
+        assert_eq!(fuzzy_score("xyz", "src/main.rs"), None);
+        assert_eq!(fuzzy_score("", "src/main.rs"), Some(0));
+        assert!(fuzzy_score("mr", "src/main.rs").is_some());
+
+        // Matches in the file name and at the start of words win
+        let name = fuzzy_score("win", "src/window.rs").unwrap();
+        let scattered = fuzzy_score("win", "src/pane/which_key_index.rs").unwrap();
+        assert!(name > scattered);
+
+        let boundary = fuzzy_score("pt", "src/pane/text.rs").unwrap();
+        let inside = fuzzy_score("pt", "src/prompt.rs").unwrap();
+        assert!(boundary > inside);
+
+        // Capitals in the query make it match case
+        assert!(fuzzy_score("readme", "README.md").is_some());
+        assert_eq!(fuzzy_score("Readme", "README.md"), None);
+    }
+}
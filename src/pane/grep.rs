@@ -0,0 +1,323 @@
+use std::{rc::Rc, cell::RefCell, sync::mpsc::Sender, path::PathBuf, io};
+
+use crossterm::event::{KeyEvent, KeyCode};
+
+use crate::{cursor::{Cursor, Direction}, window::{StyledChar, Message, TextRow}, settings::Settings, buffer::Buffer, quickfix::QuickFix};
+use super::{PaneContainer, Pane};
+
+
+/// The name of the pane, which the window looks for to only show one list of results.
+pub const GREP_PANE_NAME: &str = "[Grep]";
+
+/// Lists what a search of the project found, one match per row.
+/// Matches show up while the search is still running.
+/// Enter opens the file of the match under the cursor in the pane above.
+pub struct GrepPane {
+    quickfix: Rc<RefCell<QuickFix>>,
+    /// How many matches the rows were built from and whether the search was still running then.
+    shown: usize,
+    searching: bool,
+    /// The current entry of the list the last time we looked, so the cursor can follow `cn` and `cp`.
+    current: usize,
+    contents: Buffer,
+    cursor: Rc<RefCell<Cursor>>,
+    settings: Rc<RefCell<Settings>>,
+    sender: Sender<Message>,
+}
+
+impl GrepPane {
+    pub fn new(settings: Rc<RefCell<Settings>>,
+               quickfix: Rc<RefCell<QuickFix>>,
+               sender: Sender<Message>) -> Self {
+        let mut pane = Self {
+            quickfix,
+            shown: 0,
+            searching: false,
+            current: 0,
+            contents: Buffer::new(settings.clone()),
+            cursor: Rc::new(RefCell::new(Cursor::new((0, 0)))),
+            settings,
+            sender,
+        };
+        pane.update_list(true);
+        pane
+    }
+
+    /// Rebuilds the rows when the search found more matches or finished.
+    fn update_list(&mut self, force: bool) {
+        let (text, count, searching, current) = {
+            let quickfix = self.quickfix.borrow();
+            let count = quickfix.entries().len();
+            let searching = quickfix.is_searching();
+            if !force && count == self.shown && searching == self.searching && quickfix.current_index() == self.current {
+                return;
+            }
+
+            let text = if quickfix.is_empty() {
+                if searching { "Searching…" } else { "No matches" }.to_string()
+            }
+            else {
+                quickfix.entries().iter()
+                    .map(|entry| format!("{}:{}: {}", entry.path.display(), entry.line + 1, entry.text))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            };
+            (text, count, searching, quickfix.current_index())
+        };
+
+        if force || count != self.shown || searching != self.searching {
+            self.contents = Buffer::from(text);
+            self.contents.set_settings(self.settings.clone());
+            self.shown = count;
+            self.searching = searching;
+        }
+
+        let cursor = self.cursor.clone();
+        if current != self.current {
+            self.current = current;
+            cursor.borrow_mut().jump_to(0, current, self);
+        }
+        else {
+            let (_, y) = cursor.borrow().get_cursor();
+            cursor.borrow_mut().jump_to(0, y.min(count.saturating_sub(1)), self);
+        }
+    }
+
+    fn select(&mut self) {
+        let (_, y) = self.cursor.borrow().get_cursor();
+        let entry = match self.quickfix.borrow_mut().select(y) {
+            None => return,
+            Some(entry) => entry.clone(),
+        };
+        self.current = y;
+
+        let path = entry.path.to_string_lossy().to_string();
+        self.sender.send(Message::OpenFileAbove(path, Some((entry.col, entry.line)))).expect("Failed to send message");
+    }
+
+    /// Stops the search if it is still going, otherwise closes the list.
+    fn cancel(&mut self) {
+        let searching = self.quickfix.borrow().is_searching();
+        if searching {
+            self.quickfix.borrow_mut().cancel();
+            self.sender.send(Message::SetStatus("Search stopped".to_string())).expect("Failed to send message");
+        }
+        else {
+            self.sender.send(Message::ClosePane(false, None)).expect("Failed to send message");
+        }
+    }
+}
+
+
+impl Pane for GrepPane {
+    fn draw_row(&self, mut index: usize, container: &PaneContainer, output: &mut TextRow) {
+        let (mut cols, rows) = container.get_size();
+        if cols == 0 || rows == 0 {
+            return;
+        }
+
+        let settings = self.settings.borrow();
+        let ((x1, y1), _) = container.get_corners();
+
+        if container.has_border() {
+            if y1 != 0 {
+                if index == 0 {
+                    for _ in 0..cols {
+                        output.push(Some(Some(StyledChar::new('-', settings.colors.ui.clone()))));
+                    }
+                    return;
+                }
+                index -= 1;
+            }
+
+            if x1 != 0 {
+                output.push(Some(Some(StyledChar::new('|', settings.colors.ui.clone()))));
+                cols = cols.saturating_sub(1);
+            }
+        }
+
+        let start = output.len();
+        let (row_offset, cursor_row) = {
+            let cursor = self.cursor.borrow();
+            (cursor.row_offset, cursor.get_cursor().1)
+        };
+        let row = row_offset + index;
+
+        let line = match self.contents.get_row(row, 0, usize::MAX) {
+            None => String::new(),
+            Some(line) => line.chars().filter(|c| *c != '\n').collect::<String>(),
+        };
+        let mut text = line.chars().collect::<Vec<char>>();
+        let visible = if text.len() > cols {
+            text.truncate(cols.saturating_sub(1));
+            text.push('…');
+            cols.saturating_sub(1)
+        }
+        else {
+            text.len()
+        };
+
+        for c in text.iter() {
+            output.push(Some(Some(StyledChar::new(*c, settings.colors.pane.clone()))));
+        }
+        for _ in text.len()..cols {
+            output.push(Some(Some(StyledChar::new(' ', settings.colors.pane.clone()))));
+        }
+
+        if row == cursor_row && self.shown > 0 {
+            for i in start..start + cols {
+                output.set_background(i, settings.colors.selection.background_color);
+            }
+        }
+
+        // The match is in the text after the file and line number
+        let quickfix = self.quickfix.borrow();
+        let pattern = quickfix.pattern();
+        let found = quickfix.entries().get(row)
+            .and_then(|entry| Some(line.len().checked_sub(entry.text.len())? + entry.text.find(pattern)?));
+        if let (Some(byte), false) = (found, pattern.is_empty()) {
+            let col = line[..byte].chars().count();
+            let len = pattern.chars().count();
+            for i in col..(col + len).min(visible) {
+                output.set_background(start + i, settings.colors.search.background_color);
+            }
+        }
+    }
+
+    fn refresh(&mut self, _container: &mut PaneContainer) {
+        self.update_list(false);
+    }
+
+    fn process_keypress(&mut self, key: KeyEvent, _container: &mut PaneContainer) -> io::Result<bool> {
+        let cursor = self.cursor.clone();
+        match key.code {
+            KeyCode::Char('j') | KeyCode::Down => cursor.borrow_mut().move_cursor(Direction::Down, 1, self),
+            KeyCode::Char('k') | KeyCode::Up => cursor.borrow_mut().move_cursor(Direction::Up, 1, self),
+            KeyCode::Char('g') | KeyCode::Home => cursor.borrow_mut().move_cursor(Direction::FileTop, 1, self),
+            KeyCode::Char('G') | KeyCode::End => cursor.borrow_mut().move_cursor(Direction::FileBottom, 1, self),
+            KeyCode::Enter => self.select(),
+            KeyCode::Esc => self.cancel(),
+            KeyCode::Char('q') => self.sender.send(Message::ClosePane(false, None)).expect("Failed to send message"),
+            _ => {},
+        }
+        Ok(true)
+    }
+
+    fn scroll_cursor(&mut self, container: &PaneContainer) {
+        let cursor = self.cursor.clone();
+        cursor.borrow_mut().scroll(self, container);
+    }
+
+    fn get_status(&self, _container: &PaneContainer) -> (String, String, String) {
+        let quickfix = self.quickfix.borrow();
+        let (_, y) = self.cursor.borrow().get_cursor();
+        let mut position = if self.shown == 0 {
+            String::new()
+        }
+        else {
+            format!("{} of {}", y + 1, self.shown)
+        };
+        if quickfix.is_searching() {
+            position.push_str(" searching");
+        }
+        ("Grep".to_string(), position, quickfix.pattern().to_string())
+    }
+
+    fn run_command(&mut self, command: &str, _container: &PaneContainer) {
+        match command.trim() {
+            "select" => self.select(),
+            "grep!" => self.quickfix.borrow_mut().cancel(),
+            "q" | "q!" => self.sender.send(Message::ClosePane(false, None)).expect("Failed to send message"),
+            _ => {},
+        }
+    }
+
+    fn execute_command(&mut self, command: &str, container: &mut PaneContainer) {
+        self.run_command(command, container);
+    }
+
+    fn change_mode(&mut self, _mode_name: &str) {}
+
+    fn get_settings(&self) -> Rc<RefCell<Settings>> {
+        self.settings.clone()
+    }
+
+    fn set_sender(&mut self, sender: Sender<Message>) {
+        self.sender = sender;
+    }
+
+    fn reset(&mut self) {
+        self.cursor.borrow_mut().reset_move();
+    }
+
+    fn changed(&mut self) {
+        self.cursor.borrow_mut().set_moved();
+    }
+
+    fn get_cursor(&self) -> Rc<RefCell<Cursor>> {
+        self.cursor.clone()
+    }
+
+    fn save_buffer(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn open_file(&mut self, _filename: &PathBuf) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn insert_newline(&mut self) {}
+
+    fn insert_char(&mut self, _c: char) {}
+
+    fn insert_str(&mut self, _s: &str) {}
+
+    fn delete_char(&mut self) {}
+
+    fn backspace_char(&mut self) {}
+
+    fn get_line_count(&self) -> usize {
+        self.contents.get_line_count()
+    }
+
+    fn buffer_to_string(&self) -> String {
+        self.contents.to_string()
+    }
+
+    fn get_row_len(&self, row: usize) -> Option<usize> {
+        self.contents.line_len(row)
+    }
+
+    fn get_filename(&self) -> &Option<PathBuf> {
+        &None
+    }
+
+    fn get_name(&self) -> String {
+        GREP_PANE_NAME.to_string()
+    }
+
+    fn is_waiting(&self) -> bool {
+        self.quickfix.borrow().is_searching()
+    }
+
+    fn resize_cursor(&mut self, size: (usize, usize)) {
+        self.cursor.borrow_mut().resize(size);
+    }
+
+    fn set_cursor_size(&mut self, size: (usize, usize)) {
+        self.cursor.borrow_mut().set_size(size);
+    }
+
+    fn set_location(&mut self, (x, y): (usize, usize)) {
+        let cursor = self.cursor.clone();
+        cursor.borrow_mut().jump_to(x, y, self);
+    }
+
+    fn borrow_buffer(&self) -> &Buffer {
+        &self.contents
+    }
+
+    fn borrow_mut_buffer(&mut self) -> &mut Buffer {
+        &mut self.contents
+    }
+}
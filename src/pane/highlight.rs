@@ -0,0 +1,785 @@
+use std::{collections::HashMap, fs, io, iter, ops::{Range, RangeInclusive}, path::Path, rc::Rc};
+
+use tree_sitter::{Language, Node, Query, QueryCursor, Tree};
+
+use crate::settings::{ColorScheme, SyntaxHighlight};
+
+
+/// The highlights of every language, which `:reload_config` swaps out for new ones.
+pub type Highlights = Rc<HashMap<String, HashMap<String, SyntaxHighlight>>>;
+
+/// The length of the node and the pattern that painted a byte, along with the color it was painted.
+type Paint<'a> = ((usize, usize), Option<&'a ColorScheme>);
+
+/// The highlight queries that come with the editor.
+fn default_query(lang: &str) -> Option<&'static str> {
+    match lang {
+        "rust" => Some(include_str!("../../queries/rust/highlights.scm")),
+        "c" => Some(include_str!("../../queries/c/highlights.scm")),
+        "python" => Some(include_str!("../../queries/python/highlights.scm")),
+        "go" => Some(include_str!("../../queries/go/highlights.scm")),
+        "javascript" => Some(include_str!("../../queries/javascript/highlights.scm")),
+        "java" => Some(include_str!("../../queries/java/highlights.scm")),
+        "csharp" => Some(include_str!("../../queries/csharp/highlights.scm")),
+        _ => None,
+    }
+}
+
+/// Reads the highlight query of a language from `queries/<language>/highlights.scm` in the config directory,
+/// or uses the one that comes with the editor when there isn't one there.
+/// `None` means the language has no query, so it gets highlighted by the kinds of its nodes.
+pub fn load_query(language: Language, lang: &str, config_dir: Option<&Path>) -> Result<Option<Query>, String> {
+    let mut source = None;
+    if let Some(dir) = config_dir {
+        let path = dir.join("queries").join(lang).join("highlights.scm");
+        match fs::read_to_string(&path) {
+            Ok(text) => source = Some(text),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {},
+            Err(e) => return Err(format!("highlights for {}: {}: {}", lang, path.display(), e)),
+        }
+    }
+
+    let source = match source.or_else(|| default_query(lang).map(str::to_string)) {
+        None => return Ok(None),
+        Some(source) => source,
+    };
+    Query::new(language, &source)
+        .map(Some)
+        .map_err(|e| format!("highlights for {}: {}", lang, e))
+}
+
+/// The color of a capture name, or of the name it is a part of like `function` for `function.method`.
+fn capture_color<'a>(name: &str, captures: &'a HashMap<String, ColorScheme>) -> Option<&'a ColorScheme> {
+    let mut name = name;
+    loop {
+        if let Some(color) = captures.get(name) {
+            return Some(color);
+        }
+        match name.rfind('.') {
+            None => return None,
+            Some(dot) => name = &name[..dot],
+        }
+    }
+}
+
+/// The colors of one line, worked out from the syntax tree.
+#[derive(Debug, Default)]
+pub struct LineHighlight {
+    /// The byte ranges of the line that aren't drawn in the color of the pane, in order.
+    spans: Vec<(Range<usize>, ColorScheme)>,
+    /// The bytes of the line with a rainbow delimiter and whether it opens or closes.
+    delimiters: Vec<(usize, bool)>,
+}
+
+impl LineHighlight {
+    /// Works out the colors of a line by walking down the nodes of the tree that cover it once.
+    /// `start` is the byte of the text that the line starts at.
+    pub fn new(tree: &Tree, line: &str, start: usize, syntax_highlighting: &HashMap<String, SyntaxHighlight>, color_settings: &ColorScheme) -> Self {
+        let owners = line_nodes(tree, line, start);
+
+        let mut parents = HashMap::new();
+        let mut highlight = Self::default();
+
+        for (byte, c) in line.char_indices() {
+            let node = owners[byte];
+            let parent = *parents.entry(node.id()).or_insert_with(|| node.parent());
+
+            if let Some(opens) = rainbow_delimiter(c, node, parent) {
+                highlight.delimiters.push((byte, opens));
+            }
+
+            let color = syntax_color(node, parent, c, syntax_highlighting, color_settings);
+            if std::ptr::eq(color, color_settings) {
+                continue;
+            }
+            highlight.push_span(byte..byte + c.len_utf8(), color);
+        }
+
+        highlight
+    }
+
+    /// Works out the colors of a line from the captures of a highlight query.
+    /// The smallest node captured over a byte gives its color, and the first pattern when a node is captured more than once.
+    /// Predicates only see the part of a node's text that is on the line.
+    pub fn from_query(tree: &Tree, line: &str, start: usize, query: &Query, captures: &HashMap<String, ColorScheme>) -> Self {
+        let colors = query.capture_names().iter()
+            .map(|name| capture_color(name, captures))
+            .collect::<Vec<_>>();
+
+        let end = start + line.len();
+        let bytes = line.as_bytes();
+        let text = move |node: Node| {
+            let from = node.start_byte().clamp(start, end) - start;
+            let to = node.end_byte().clamp(start, end) - start;
+            iter::once(&bytes[from..to])
+        };
+
+        let mut painted: Vec<Option<Paint>> = vec![None; line.len()];
+        let mut cursor = QueryCursor::new();
+        cursor.set_byte_range(start..end);
+        for (query_match, index) in cursor.captures(query, tree.root_node(), text) {
+            let capture = query_match.captures[index];
+            let node = capture.node;
+            let rank = (node.end_byte() - node.start_byte(), query_match.pattern_index);
+            let from = node.start_byte().clamp(start, end) - start;
+            let to = node.end_byte().clamp(start, end) - start;
+
+            for byte in painted[from..to].iter_mut() {
+                if byte.is_none_or(|(other, _)| rank < other) {
+                    *byte = Some((rank, colors[capture.index as usize]));
+                }
+            }
+        }
+
+        let owners = line_nodes(tree, line, start);
+        let mut parents = HashMap::new();
+        let mut highlight = Self::default();
+
+        for (byte, c) in line.char_indices() {
+            let node = owners[byte];
+            let parent = *parents.entry(node.id()).or_insert_with(|| node.parent());
+
+            if let Some(opens) = rainbow_delimiter(c, node, parent) {
+                highlight.delimiters.push((byte, opens));
+            }
+
+            if let Some((_, Some(color))) = painted[byte] {
+                highlight.push_span(byte..byte + c.len_utf8(), color);
+            }
+        }
+
+        highlight
+    }
+
+    /// Adds the color of a character, joining it to the span before it when they are the same.
+    fn push_span(&mut self, range: Range<usize>, color: &ColorScheme) {
+        match self.spans.last_mut() {
+            Some((last_range, last)) if last_range.end == range.start && *last == *color => last_range.end = range.end,
+            _ => self.spans.push((range, color.clone())),
+        }
+    }
+
+    /// The color of the character at a byte of the line, `None` when it is drawn in the color of the pane.
+    pub fn color_at(&self, byte: usize) -> Option<&ColorScheme> {
+        let index = self.spans.partition_point(|(range, _)| range.end <= byte);
+        match self.spans.get(index) {
+            Some((range, color)) if range.contains(&byte) => Some(color),
+            _ => None,
+        }
+    }
+
+    /// Follows the rainbow delimiters of the line that come before a byte, starting from a depth.
+    pub fn rainbow_depth(&self, mut depth: usize, end: usize) -> usize {
+        for (_, opens) in self.delimiters.iter().take_while(|(byte, _)| *byte < end) {
+            if *opens {
+                depth += 1;
+            }
+            else {
+                depth = depth.saturating_sub(1);
+            }
+        }
+        depth
+    }
+
+    /// Whether the character at a byte of the line opens a rainbow delimiter or closes one.
+    pub fn delimiter_at(&self, byte: usize) -> Option<bool> {
+        self.delimiters.binary_search_by_key(&byte, |(byte, _)| *byte)
+            .ok()
+            .map(|index| self.delimiters[index].1)
+    }
+}
+
+/// The smallest node over each byte of a line.
+fn line_nodes<'a>(tree: &'a Tree, line: &str, start: usize) -> Vec<Node<'a>> {
+    let root = tree.root_node();
+    let mut owners = vec![root; line.len()];
+    paint_nodes(root, start..start + line.len(), &mut owners);
+    owners
+}
+
+/// Marks each byte of a line with the smallest node that covers it, the same one `descendant_for_point_range` would find.
+fn paint_nodes<'a>(node: Node<'a>, line: Range<usize>, owners: &mut [Node<'a>]) {
+    let from = node.start_byte().max(line.start);
+    let to = node.end_byte().min(line.end);
+    if from >= to {
+        return;
+    }
+    for owner in owners[from - line.start..to - line.start].iter_mut() {
+        *owner = node;
+    }
+
+    // Skipping to the first child that reaches the line keeps big files from walking every top level item
+    let mut cursor = node.walk();
+    if cursor.goto_first_child_for_byte(line.start).is_none() {
+        return;
+    }
+    loop {
+        let child = cursor.node();
+        if child.start_byte() >= line.end {
+            break;
+        }
+        paint_nodes(child, line.clone(), owners);
+        if !cursor.goto_next_sibling() {
+            break;
+        }
+    }
+}
+
+/// Whether a character opens a rainbow delimiter or closes one, `None` when it is neither.
+/// Angle brackets only count around type arguments and system includes.
+pub fn rainbow_delimiter(c: char, node: Node, parent: Option<Node>) -> Option<bool> {
+    let angle = || {
+        parent.is_some_and(|parent| matches!(parent.kind(), "type_arguments" | "system_lib_string"))
+            || node.kind() == "system_lib_string"
+    };
+
+    match c {
+        '(' | '{' | '[' => Some(true),
+        ')' | '}' | ']' => Some(false),
+        '<' if angle() => Some(true),
+        '>' if angle() => Some(false),
+        _ => None,
+    }
+}
+
+/// Picks the color of a character from the highlight of its node, or of the nodes around it.
+fn syntax_color<'a>(node: Node, parent_node: Option<Node>, c: char, syntax_highlighting: &'a HashMap<String, SyntaxHighlight>, color_settings: &'a ColorScheme) -> &'a ColorScheme {
+    if let Some(settings) = syntax_highlighting.get(&node.kind().to_string()) {
+        match settings {
+            SyntaxHighlight::Child(settings) => {
+                settings
+            }
+            SyntaxHighlight::ChildExclude(settings, exclude) => {
+                if exclude.contains(&c) {
+                    color_settings
+                }
+                else {
+                    settings
+                }
+            }
+            _ => {
+                color_settings
+            }
+        }
+    }
+    else if let Some(mut parent) = parent_node {
+        let mut colors = color_settings;
+
+        if let Some(settings) = syntax_highlighting.get(&parent.kind().to_string()) {
+            match settings {
+                SyntaxHighlight::Child(settings) => {
+                    colors = settings;
+                }
+                SyntaxHighlight::ChildExclude(settings, exclude) => {
+                    if exclude.contains(&c) {
+                        colors = color_settings;
+                    }
+                    else {
+                        colors = settings;
+                    }
+                }
+                SyntaxHighlight::Parent(color_map) => {
+                    let mut cursor = parent.walk();
+
+                    let mut index = 0;
+
+                    for child_node in parent.children(&mut cursor) {
+                        if child_node.id() == node.id() {
+                            break;
+                        }
+                        index += 1;
+                    }
+
+                    if let Some(field) = parent.field_name_for_child(index) {
+                        if let Some(highlight) = color_map.get(field) {
+                            colors = match highlight {
+                                SyntaxHighlight::Child(settings) => settings,
+                                SyntaxHighlight::ChildExclude(settings, exclude) => {
+                                    if exclude.contains(&c) {
+                                        color_settings
+                                    }
+                                    else {
+                                        settings
+                                    }
+                                }
+                                _ => {
+                                    color_settings
+                                }
+                            };
+                        }
+                    }
+                },
+                SyntaxHighlight::ParentExclude(color_map, exclude) => {
+                    if exclude.contains(&c) {
+                        colors = color_settings;
+                    }
+                    else {
+                        let mut cursor = parent.walk();
+
+                        let mut index = 0;
+
+                        for child_node in parent.children(&mut cursor) {
+                            if child_node.id() == node.id() {
+                                break;
+                            }
+                            index += 1;
+                        }
+
+                        if let Some(field) = parent.field_name_for_child(index) {
+                            if let Some(highlight) = color_map.get(field) {
+                                colors = match highlight {
+                                    SyntaxHighlight::Child(settings) => settings,
+                                    SyntaxHighlight::ChildExclude(settings, exclude) => {
+                                        if exclude.contains(&c) {
+                                            color_settings
+                                        }
+                                        else {
+                                            settings
+                                        }
+                                    }
+                                    _ => {
+                                        color_settings
+                                    }
+                                };
+                            }
+                        }
+                    }
+                },
+                SyntaxHighlight::GrandParent(grandparents) => {
+                    if let Some(highlight) = grandparents.get(&parent.kind().to_string()) {
+                        match highlight {
+                            SyntaxHighlight::Child(ref settings) => {
+                                colors = settings;
+                            }
+                            SyntaxHighlight::ChildExclude(ref settings, exclude) => {
+                                if exclude.contains(&c) {
+                                    colors = color_settings;
+                                }
+                                else {
+                                    colors = settings;
+                                }
+                            }
+                            SyntaxHighlight::Parent(color_map) => {
+                                let mut cursor = parent.walk();
+
+                                let mut index = 0;
+
+                                for child_node in parent.children(&mut cursor) {
+                                    if child_node.id() == node.id() {
+                                        break;
+                                    }
+                                    index += 1;
+                                }
+
+                                if let Some(field) = parent.field_name_for_child(index) {
+                                    if let Some(highlight) = color_map.get(field) {
+                                        colors = match highlight {
+                                            SyntaxHighlight::Child(settings) => settings,
+                                            SyntaxHighlight::ChildExclude(settings, exclude) => {
+                                                if exclude.contains(&c) {
+                                                    color_settings
+                                                }
+                                                else {
+                                                    settings
+                                                }
+                                            }
+                                            _ => {
+                                                color_settings
+                                            }
+                                        };
+                                    }
+                                }
+                            },
+                            SyntaxHighlight::ParentExclude(color_map, exclude) => {
+                                if exclude.contains(&c) {
+                                    colors = color_settings;
+                                }
+                                else {
+                                    let mut cursor = parent.walk();
+
+                                    let mut index = 0;
+
+                                    for child_node in parent.children(&mut cursor) {
+                                        if child_node.id() == node.id() {
+                                            break;
+                                        }
+                                        index += 1;
+                                    }
+
+                                    if let Some(field) = parent.field_name_for_child(index) {
+                                        if let Some(highlight) = color_map.get(field) {
+                                            colors = match highlight {
+                                                SyntaxHighlight::Child(settings) => settings,
+                                                SyntaxHighlight::ChildExclude(settings, exclude) => {
+                                                    if exclude.contains(&c) {
+                                                        color_settings
+                                                    }
+                                                    else {
+                                                        settings
+                                                    }
+                                                }
+                                                _ => {
+                                                    color_settings
+                                                }
+                                            };
+                                        }
+                                    }
+                                }
+                            },
+                            _ => {
+                                colors = color_settings;
+                            }
+                        }
+                    }
+                    else {
+                        let old_parent = parent;
+                        while let Some(new_parent) = parent.parent() {
+                            parent = new_parent;
+                            if let Some(highlight) = grandparents.get(&parent.kind().to_string()) {
+
+                                match highlight {
+                                    SyntaxHighlight::Child(ref settings) => {
+                                        colors = settings;
+                                    }
+                                    SyntaxHighlight::ChildExclude(ref settings, exclude) => {
+                                        if exclude.contains(&c) {
+                                            colors = color_settings;
+                                        }
+                                        else {
+                                            colors = settings;
+                                        }
+                                    }
+                                    SyntaxHighlight::Parent(color_map) => {
+                                        let mut cursor = old_parent.walk();
+
+                                        let mut index = 0;
+
+                                        for child_node in old_parent.children(&mut cursor) {
+                                            if child_node.id() == node.id() {
+                                                break;
+                                            }
+                                            index += 1;
+                                        }
+
+                                        if let Some(field) = old_parent.field_name_for_child(index) {
+                                            if let Some(highlight) = color_map.get(field) {
+                                                colors = match highlight {
+                                                    SyntaxHighlight::Child(settings) => settings,
+                                                    SyntaxHighlight::ChildExclude(settings, exclude) => {
+                                                        if exclude.contains(&c) {
+                                                            color_settings
+                                                        }
+                                                        else {
+                                                            settings
+                                                        }
+                                                    }
+                                                    _ => {
+                                                        color_settings
+                                                    }
+                                                };
+                                            }
+                                        }
+                                    },
+                                    SyntaxHighlight::ParentExclude(color_map, exclude) => {
+                                        if exclude.contains(&c) {
+                                            colors = color_settings;
+                                        }
+                                        else {
+                                            let mut cursor = parent.walk();
+
+                                            let mut index = 0;
+
+                                            for child_node in old_parent.children(&mut cursor) {
+                                                if child_node.id() == node.id() {
+                                                    break;
+                                                }
+                                                index += 1;
+                                            }
+
+                                            if let Some(field) = old_parent.field_name_for_child(index) {
+                                                if let Some(highlight) = color_map.get(field) {
+                                                    colors = match highlight {
+                                                        SyntaxHighlight::Child(settings) => settings,
+                                                        SyntaxHighlight::ChildExclude(settings, exclude) => {
+                                                            if exclude.contains(&c) {
+                                                                color_settings
+                                                            }
+                                                            else {
+                                                                settings
+                                                            }
+                                                        }
+                                                        _ => {
+                                                            color_settings
+                                                        }
+                                                    };
+                                                }
+                                            }
+                                        }
+                                    },
+                                    _ => {
+                                        colors = color_settings;
+                                    }
+                                }
+                                break;
+                            }
+
+                        }
+
+                    }
+
+                }
+
+            }
+
+
+        }
+
+
+
+        colors
+    }
+    else {
+        color_settings
+    }
+}
+
+
+/// The colors of the lines that were drawn, kept until the text around them changes.
+pub struct HighlightCache {
+    lines: HashMap<usize, Rc<LineHighlight>>,
+    /// The revision of the text the lines are for and how many lines it had.
+    revision: Option<(usize, usize)>,
+    line_count: usize,
+    highlights: Option<Highlights>,
+}
+
+impl Default for HighlightCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HighlightCache {
+    pub fn new() -> Self {
+        Self {
+            lines: HashMap::new(),
+            revision: None,
+            line_count: 0,
+            highlights: None,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.lines.clear();
+        self.revision = None;
+    }
+
+    /// Gets the colors of a line if they are still good for the text and the highlights.
+    /// Text that changed without the cache hearing about it throws away every line.
+    pub fn get(&mut self, row: usize, revision: (usize, usize), highlights: &Highlights) -> Option<Rc<LineHighlight>> {
+        let current = self.revision == Some(revision)
+            && self.highlights.as_ref().is_some_and(|cached| Rc::ptr_eq(cached, highlights));
+        if !current {
+            self.lines.clear();
+            return None;
+        }
+        self.lines.get(&row).cloned()
+    }
+
+    pub fn insert(&mut self, row: usize, revision: (usize, usize), line_count: usize, highlights: &Highlights, line: LineHighlight) -> Rc<LineHighlight> {
+        if self.revision != Some(revision) {
+            self.lines.clear();
+            self.revision = Some(revision);
+            self.line_count = line_count;
+        }
+        self.highlights = Some(highlights.clone());
+
+        let line = Rc::new(line);
+        self.lines.insert(row, line.clone());
+        line
+    }
+
+    /// Forgets the lines an edit went over and moves the ones after it to where they are now.
+    /// `rows` goes from the first row of the edit to its last row in the text after it.
+    pub fn edit(&mut self, rows: RangeInclusive<usize>, revision: (usize, usize), line_count: usize) {
+        if self.revision.is_none() {
+            return;
+        }
+
+        let (start, new_end) = rows.into_inner();
+        let added = line_count as isize - self.line_count as isize;
+        let old_end = (new_end as isize - added).max(start as isize) as usize;
+
+        self.lines = self.lines.drain()
+            .filter_map(|(row, line)| {
+                if row < start {
+                    Some((row, line))
+                }
+                else if row <= old_end {
+                    None
+                }
+                else {
+                    Some(((row as isize + added) as usize, line))
+                }
+            })
+            .collect();
+        self.revision = Some(revision);
+        self.line_count = line_count;
+    }
+
+    /// Forgets lines whose nodes changed without being edited, like the lines after a string that was opened.
+    pub fn forget(&mut self, rows: RangeInclusive<usize>) {
+        self.lines.retain(|row, _| !rows.contains(row));
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use tree_sitter::{Parser, Point};
+
+    use crate::settings::Settings;
+
+    use super::*;
+
+    #[test]
+    fn test_line_highlight_matches_the_nodes() {
+        let settings = Settings::default();
+        let syntax_highlighting = settings.colors.treesitter.get("rust").unwrap();
+        let color_settings = &settings.colors.pane;
+
+        let text = "fn main() {\n    let a: Vec<u8> = \"(\".into();\n}\n";
+        let mut parser = Parser::new();
+        parser.set_language(tree_sitter_rust::language()).unwrap();
+        let tree = parser.parse(text, None).unwrap();
+
+        let mut start = 0;
+        for (row, line) in text.split_inclusive('\n').enumerate() {
+            let highlight = LineHighlight::new(&tree, line, start, syntax_highlighting, color_settings);
+
+            for (byte, c) in line.char_indices() {
+                let node = tree.root_node()
+                    .descendant_for_point_range(Point::new(row, byte), Point::new(row, byte + 1))
+                    .unwrap();
+                let expected = syntax_color(node, node.parent(), c, syntax_highlighting, color_settings);
+                assert_eq!(highlight.color_at(byte).unwrap_or(color_settings), expected, "{:?} on row {}", c, row);
+                assert_eq!(highlight.delimiter_at(byte), rainbow_delimiter(c, node, node.parent()));
+            }
+            start += line.len();
+        }
+    }
+
+    #[test]
+    fn test_default_queries_load() {
+        let languages = [
+            ("rust", tree_sitter_rust::language()),
+            ("c", tree_sitter_c::language()),
+            ("python", tree_sitter_python::language()),
+            ("go", tree_sitter_go::language()),
+            ("javascript", tree_sitter_javascript::language()),
+            ("java", tree_sitter_java::language()),
+            ("csharp", tree_sitter_c_sharp::language()),
+        ];
+        for (lang, language) in languages {
+            assert!(load_query(language, lang, None).unwrap().is_some(), "{}", lang);
+        }
+        assert!(load_query(tree_sitter_cpp::language(), "cpp", None).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_query_from_config_dir() {
+        let dir = std::env::temp_dir().join("vi_highlight_query_test");
+        let queries = dir.join("queries").join("rust");
+        fs::create_dir_all(&queries).unwrap();
+
+        fs::write(queries.join("highlights.scm"), "(line_comment) @keyword").unwrap();
+        let query = load_query(tree_sitter_rust::language(), "rust", Some(&dir)).unwrap().unwrap();
+        assert_eq!(query.capture_names(), &["keyword".to_string()]);
+
+        // A query that doesn't fit the language is an error instead of no highlights
+        fs::write(queries.join("highlights.scm"), "(not_a_node) @keyword").unwrap();
+        assert!(load_query(tree_sitter_rust::language(), "rust", Some(&dir)).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_capture_color() {
+        let captures = Settings::default().colors.captures;
+        let function = captures.get("function");
+        assert!(function.is_some());
+        assert_eq!(capture_color("function", &captures), function);
+        assert_eq!(capture_color("function.method.builtin", &captures), function);
+        assert_eq!(capture_color("function.macro", &captures), captures.get("function.macro"));
+        assert_eq!(capture_color("punctuation.bracket", &captures), None);
+    }
+
+    #[test]
+    fn test_line_highlight_from_query() {
+        let settings = Settings::default();
+        let captures = &settings.colors.captures;
+        let color = |name: &str| captures.get(name);
+
+        let text = "fn main() {\n    let s = \"a\\n\"; // hi\n}\n";
+        let mut parser = Parser::new();
+        parser.set_language(tree_sitter_rust::language()).unwrap();
+        let tree = parser.parse(text, None).unwrap();
+        let query = load_query(tree_sitter_rust::language(), "rust", None).unwrap().unwrap();
+
+        let highlight = LineHighlight::from_query(&tree, "fn main() {\n", 0, &query, captures);
+        assert_eq!(highlight.color_at(0), color("keyword"));
+        assert_eq!(highlight.color_at(3), color("function"));
+        assert_eq!(highlight.color_at(7), None);
+        assert_eq!(highlight.delimiter_at(7), Some(true));
+
+        let highlight = LineHighlight::from_query(&tree, "    let s = \"a\\n\"; // hi\n", 12, &query, captures);
+        assert_eq!(highlight.color_at(4), color("keyword"));
+        assert_eq!(highlight.color_at(8), None);
+        assert_eq!(highlight.color_at(12), color("string"));
+        // The escape is inside the string, so its color goes over the string's
+        assert_eq!(highlight.color_at(14), color("escape"));
+        assert_eq!(highlight.color_at(19), color("comment"));
+    }
+
+    #[test]
+    fn test_rainbow_depth() {
+        let line = LineHighlight {
+            spans: Vec::new(),
+            delimiters: vec![(0, true), (2, true), (4, false)],
+        };
+        assert_eq!(line.rainbow_depth(1, 0), 1);
+        assert_eq!(line.rainbow_depth(1, 3), 3);
+        assert_eq!(line.rainbow_depth(0, usize::MAX), 1);
+    }
+
+    #[test]
+    fn test_cache_edit() {
+        let highlights: Highlights = Rc::new(HashMap::new());
+        let mut cache = HighlightCache::new();
+        for row in 0..5 {
+            cache.insert(row, (1, 0), 5, &highlights, LineHighlight::default());
+        }
+        let cached = |cache: &mut HighlightCache, revision| {
+            (0..7).filter(|row| cache.get(*row, revision, &highlights).is_some()).collect::<Vec<_>>()
+        };
+        assert_eq!(cached(&mut cache, (1, 0)), vec![0, 1, 2, 3, 4]);
+
+        // A line was added after row 1, so the rows after it move down
+        cache.edit(1..=2, (2, 0), 6);
+        assert_eq!(cached(&mut cache, (2, 0)), vec![0, 3, 4, 5]);
+
+        // Joining rows 3 and 4 moves the rows after them back up
+        cache.edit(3..=3, (3, 0), 5);
+        assert_eq!(cached(&mut cache, (3, 0)), vec![0, 4]);
+
+        cache.forget(0..=0);
+        assert_eq!(cached(&mut cache, (3, 0)), vec![4]);
+
+        // Text that changed behind the cache's back, or new highlights, start over
+        assert_eq!(cached(&mut cache, (4, 0)), Vec::<usize>::new());
+        cache.insert(0, (4, 0), 5, &highlights, LineHighlight::default());
+        assert_eq!(cached(&mut cache, (4, 0)), vec![0]);
+        let reloaded: Highlights = Rc::new(HashMap::new());
+        assert!(cache.get(0, (4, 0), &reloaded).is_none());
+    }
+}
@@ -2,20 +2,56 @@
 pub(crate) mod text;
 pub mod popup;
 pub mod treesitter;
-
-use std::{rc::Rc, cell::RefCell, path::PathBuf, io, cmp, fmt::Debug, sync::mpsc::Sender};
+pub mod highlight;
+pub mod parse;
+pub mod which_key;
+pub mod repeat;
+pub mod diagnostics;
+pub mod completion;
+pub mod finder;
+pub mod grep;
+pub mod explorer;
+
+use std::{rc::Rc, cell::RefCell, path::{Path, PathBuf}, io, cmp, fmt::Debug, sync::mpsc::Sender, ops::Range, time::Instant};
 
 use crossterm::event::KeyEvent;
+use encoding_rs::Encoding;
 use uuid::Uuid;
 
-use crate::{settings::Settings, window::{StyledChar, Message, TextRow}, cursor::Cursor, buffer::Buffer};
+use crate::{settings::{Settings, ColorScheme}, mode::base::VisualKind, window::{StyledChar, Message, MessageKind, TextRow}, cursor::Cursor, buffer::Buffer, registers::RegisterValue, search::Pattern, indent::{is_bad_indent, leading_whitespace}, lsp::{lsp_utils::TextEdit, SharedInfo, SharedReceiver, ControllerMessage}};
 
 
 pub enum PaneMessage {
     String(String),
+    /// The text of a prompt as it is being typed.
+    PreviewString(String),
+    /// The prompt was closed without an answer.
+    Cancel,
     Close,
 }
 
+/// The commands of every text pane that are worth typing in the command prompt.
+pub const TEXT_COMMANDS: [&str; 71] = [
+    "q", "q!", "qa", "qa!", "w", "w!", "wq", "e", "e!", "file_info", "local_definition", "local_declaration",
+    "record_macro", "play_macro", "fixindent", "registers", "reg", "recent", "find_file", "ls",
+    "b", "buffer", "bd", "bd!", "alternate_buffer",
+    "completion", "reopen", "grep", "grep!", "cnext", "cprevious", "checkhealth", "lsp", "set",
+    "setlocal", "first_non_blank", "smart_home", "reselect",
+    "word_start_forward", "word_start_backward", "word_end_forward", "line_center", "line_top", "line_bottom",
+    "horizontal_split", "vertical_split", "rotate_panes", "swap_pane", "pane_up",
+    "pane_down", "pane_left", "pane_right", "jump_match", "format_selection", "trim_whitespace", "open_line", "undo",
+    "redo", "open_tab", "open_tab_with_pane", "search_next", "search_previous", "repeat_change",
+    "nohlsearch", "delete_line", "delete_selection", "reload_config", "map", "noremap", "unmap", "maps",
+];
+
+/// The commands that panes with a language have as well.
+pub const LANGUAGE_COMMANDS: [&str; 19] = [
+    "show_diagnostic", "info", "hover", "format", "code_action", "rename", "diagnostics",
+    "goto_declaration", "goto_definition", "goto_type_definition", "goto_implementation",
+    "find_references", "diagnostic_next", "diagnostic_prev", "spell_next", "spell_prev",
+    "spell_suggest", "snippet_next", "snippet_previous",
+];
+
 
 impl Clone for PaneContainer {
     fn clone(&self) -> Self {
@@ -29,6 +65,7 @@ impl Clone for PaneContainer {
             close: false,
             identifier: Uuid::new_v4(),
             move_not_resize: self.move_not_resize,
+            border: self.border,
         }
     }
 }
@@ -50,6 +87,8 @@ pub struct PaneContainer {
     close: bool,
     identifier: Uuid,
     pub move_not_resize: bool,
+    /// Overrides the border setting for just this pane.
+    border: Option<bool>,
 }
 
 impl PaneContainer {
@@ -64,6 +103,7 @@ impl PaneContainer {
             close: false,
             identifier: Uuid::new_v4(),
             move_not_resize: false,
+            border: None,
         };
 
         container.shrink();
@@ -238,6 +278,32 @@ impl PaneContainer {
         return false;
     }
     
+    /// Whether this pane draws a border, falling back on the editor setting.
+    pub fn has_border(&self) -> bool {
+        self.border.unwrap_or_else(|| self.settings.borrow().editor_settings.border)
+    }
+
+    pub fn set_border(&mut self, border: Option<bool>) {
+        self.border = border;
+    }
+
+    /// How many columns and rows the border takes up on the left and top of the pane.
+    /// Panes along the edge of the window don't draw a border on that side.
+    pub fn border_offset(&self) -> (usize, usize) {
+        if !self.has_border() {
+            return (0, 0);
+        }
+        let ((x, y), _) = self.get_corners();
+
+        ((x != 0) as usize, (y != 0) as usize)
+    }
+
+    /// The size of the pane without its border.
+    pub fn get_text_size(&self) -> (usize, usize) {
+        let (x, y) = self.border_offset();
+        (self.size.0.saturating_sub(x), self.size.1.saturating_sub(y))
+    }
+
     pub fn get_size(&self) -> (usize, usize) {
         self.size
     }
@@ -311,6 +377,22 @@ impl PaneContainer {
         
     }
 
+    /// The position, size and maximum size of the pane.
+    pub fn get_geometry(&self) -> ((usize, usize), (usize, usize), (usize, usize)) {
+        (self.position, self.size, self.max_size)
+    }
+
+    /// Moves the pane into the place another pane had, keeping its buffer and cursor.
+    pub fn set_geometry(&mut self, (position, size, max_size): ((usize, usize), (usize, usize), (usize, usize))) {
+        self.position = position;
+        self.size = size;
+        self.max_size = max_size;
+
+        let mut pane = self.pane.borrow_mut();
+        pane.resize_cursor(self.size);
+        pane.changed();
+    }
+
     pub fn set_position(&mut self, position: (usize, usize)) {
         self.position = position;
         self.shrink();
@@ -350,6 +432,22 @@ impl PaneContainer {
         self.pane.borrow().get_cursor()
     }
 
+    /// Where the cursor is in the buffer as a column and row.
+    pub fn get_cursor_location(&self) -> (usize, usize) {
+        self.get_cursor().borrow().get_cursor()
+    }
+
+    /// Moves the cursor to a column and row in the buffer.
+    /// Locations past the end of a line or the buffer get clamped to the last valid place.
+    pub fn set_cursor_location(&mut self, location: (usize, usize)) {
+        self.pane.borrow_mut().set_location(location);
+    }
+
+    pub fn complete_words(&mut self, others: Vec<String>) {
+        let pane = self.pane.clone();
+        pane.borrow_mut().complete_words(others, self);
+    }
+
     pub fn close(&mut self) {
         self.close = true;
     }
@@ -358,14 +456,32 @@ impl PaneContainer {
         self.close
     }
 
-    pub fn backup(&mut self) {
-        self.pane.borrow_mut().backup_buffer();
-    }
-
     pub fn execute_command(&mut self, command: &str) {
         let pane = self.pane.clone();
         pane.borrow_mut().execute_command(command, self);
     }
+
+    /// Whether a cell of the window is inside the pane.
+    pub fn contains(&self, (col, row): (usize, usize)) -> bool {
+        let ((x1, y1), (x2, y2)) = self.get_corners();
+        // Hidden panes have no room to click on
+        self.size != (0, 0) && x1 <= col && col <= x2 && y1 <= row && row <= y2
+    }
+
+    /// Passes a left click on a cell of the window to the pane.
+    pub fn click(&mut self, (col, row): (usize, usize)) {
+        let (x, y) = self.position;
+        let pane = self.pane.clone();
+        pane.borrow_mut().click((col.saturating_sub(x), row.saturating_sub(y)), self);
+    }
+
+    /// Scrolls the pane by some rows, keeping the cursor where it is when it is still showing.
+    pub fn scroll_view(&mut self, delta: isize) {
+        let rows = self.get_text_size().1;
+        let cursor = self.get_cursor();
+        let pane = self.pane.borrow();
+        cursor.borrow_mut().scroll_view(delta, rows, &*pane);
+    }
     
 }
 
@@ -406,11 +522,12 @@ pub trait Pane {
 
     fn save_buffer(&mut self) -> io::Result<()>;
     fn open_file(&mut self, filename: &PathBuf) -> io::Result<()>;
-    fn backup_buffer(&mut self);
 
     fn insert_newline(&mut self) {
         self.insert_char('\n');
     }
+    /// Replaces the indentation at the start of a row, keeping the cursor on the same text.
+    fn set_indent(&mut self, _row: usize, _indent: &str) {}
     fn insert_char(&mut self, c: char);
     fn insert_str(&mut self, s: &str);
     fn delete_char(&mut self);
@@ -425,13 +542,551 @@ pub trait Pane {
 
     fn get_filename(&self) -> &Option<PathBuf>;
 
+    /// Lets the pane show what a command would do while it is still being typed.
+    /// `None` means the command was run or cancelled and any preview should go away.
+    fn preview_command(&mut self, _command: Option<&str>) {}
+
+    /// The language of the buffer if the pane knows it.
+    fn get_language(&self) -> Option<&str> {
+        None
+    }
+
+    /// Whether the pane is waiting on something besides the user, like an answer from a language server.
+    /// The window keeps refreshing the panes while one is waiting, even when no keys are pressed.
+    fn is_waiting(&self) -> bool {
+        false
+    }
+
+    /// Whether the buffer has changes that weren't saved.
+    fn is_modified(&self) -> bool {
+        false
+    }
+
+    /// When the buffer was last changed, if it has changes that auto-save hasn't tried to save yet.
+    fn last_edit(&self) -> Option<Instant> {
+        None
+    }
+
+    /// Whether text is being typed in, which auto-save waits for unless told not to.
+    fn is_inserting(&self) -> bool {
+        false
+    }
+
+    /// Whether a block is being selected, which lets the cursor past the end of lines with `virtualedit=block`.
+    fn selecting_block(&self) -> bool {
+        false
+    }
+
+    /// Saves the buffer the same way `:w` does, giving back whether it worked.
+    fn auto_save(&mut self, _container: &PaneContainer) -> bool {
+        false
+    }
+
+    /// Picks up the keybindings and key timeout after the settings were read again.
+    fn settings_changed(&mut self) {}
+
+    /// Shows a message on the message line, for the modes that have no way to send one.
+    fn show_message(&self, _message: String, _kind: MessageKind) {}
+
+    /// Handles a left click on a cell of the pane, counted from its top left corner.
+    /// Panes with a cursor move it to the text under the click, past the border and line numbers.
+    fn click(&mut self, (col, row): (usize, usize), container: &mut PaneContainer) {
+        let (border_x, border_y) = container.border_offset();
+        let location = {
+            let cursor = self.get_cursor();
+            let cursor = cursor.borrow();
+            // The cursor is down in the command line while a command is typed
+            if cursor.ignore_offset {
+                return;
+            }
+            (col.saturating_sub(border_x + cursor.number_line_size) + cursor.col_offset,
+             row.saturating_sub(border_y) + cursor.row_offset)
+        };
+        self.set_location(location);
+    }
+
+    /// Gives the pane the language server of its language after the server was restarted.
+    fn lsp_restarted(&mut self, _lsp: (Sender<ControllerMessage>, SharedReceiver)) {}
+
+    /// Gives the pane what the language server of its language can do.
+    fn set_lsp_info(&mut self, _info: SharedInfo) {}
+
+    /// Offers the words of the buffer and of the `others` open buffers that complete the word at the cursor.
+    fn complete_words(&mut self, _others: Vec<String>, _container: &PaneContainer) {}
+
+    /// Whether Tab and Shift-Tab go through the tabstops of a snippet instead of indenting.
+    fn in_snippet(&self) -> bool {
+        false
+    }
+
+    /// The names of the commands the pane runs, which Tab completes in the command prompt.
+    fn command_names(&self) -> Vec<&'static str> {
+        Vec::new()
+    }
+
+    /// Puts the text of a register into the buffer.
+    /// Whole lines go below the cursor's line when `after` is set and above it otherwise.
+    fn paste(&mut self, value: &RegisterValue, _after: bool) {
+        self.insert_str(&value.text);
+    }
+
+    /// Reads the file of the pane again with a different encoding.
+    fn reload_with_encoding(&mut self, _encoding: &'static Encoding) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Makes edits from a language server to the buffer as a single undo step.
+    fn apply_text_edits(&mut self, _edits: &[TextEdit]) {}
+
+    /// The name of the file to show to the user.
+    fn get_name(&self) -> String {
+        display_name(self.get_filename().as_deref())
+    }
+
     fn resize_cursor(&mut self, size: (usize, usize));
     fn set_cursor_size(&mut self, size: (usize, usize));
+    /// Jumps the cursor to a column and row in the buffer, clamping it to the text.
+    fn set_location(&mut self, location: (usize, usize));
     fn borrow_buffer(&self) -> &Buffer;
     fn borrow_mut_buffer(&mut self) -> &mut Buffer;
     
 }
 
+/// Turns `:map <mode> <keys> <command>`, `:unmap <mode> <keys>` and `:maps [mode]` into the message that does it.
+/// The keys are everything between the mode and the command, like `C-w s`.
+/// Gives back how to use the command when the arguments don't fit.
+pub fn map_message(command: &str, args: &[&str]) -> Result<Message, String> {
+    match (command, args) {
+        ("map" | "noremap", [mode, keys @ .., bound]) if !keys.is_empty() => {
+            Ok(Message::MapKeys(mode.to_string(), keys.join(" "), Some(bound.to_string())))
+        },
+        ("unmap", [mode, keys @ ..]) if !keys.is_empty() => {
+            Ok(Message::MapKeys(mode.to_string(), keys.join(" "), None))
+        },
+        ("maps", []) => Ok(Message::ListMaps("normal".to_string())),
+        ("maps", [mode]) => Ok(Message::ListMaps(mode.to_string())),
+        ("unmap", _) => Err("Usage: unmap <mode> <keys>".to_string()),
+        ("maps", _) => Err("Usage: maps [mode]".to_string()),
+        _ => Err(format!("Usage: {} <mode> <keys> <command>", command)),
+    }
+}
+
+/// Gets a printable name for a file.
+/// Names that aren't valid UTF-8 are converted lossily and paths without a final component fall back to the whole path.
+pub fn display_name(path: Option<&Path>) -> String {
+    match path {
+        None => "[No Name]".to_string(),
+        Some(path) => match path.file_name() {
+            Some(name) => name.to_string_lossy().to_string(),
+            None if path.as_os_str().is_empty() => "[No Name]".to_string(),
+            None => path.to_string_lossy().to_string(),
+        },
+    }
+}
+
+/// Tints the row with the cursor under everything that gets drawn on top of it.
+pub fn draw_cursor_line(settings: &Settings, text_start: usize, width: usize, output: &mut TextRow) {
+    for index in text_start..text_start + width {
+        output.set_background(index, settings.colors.cursor_line.background_color);
+    }
+}
+
+/// Tints the column that shows the line length limit.
+/// `text_start` is where the text of the row starts in the output, after any border or line numbers.
+pub fn draw_color_column(settings: &Settings, text_start: usize, col_offset: usize, width: usize, output: &mut TextRow) {
+    let column = match settings.editor_settings.color_column() {
+        None => return,
+        Some(column) => column - 1,
+    };
+
+    let column = match column.checked_sub(col_offset) {
+        None => return,
+        Some(column) => column,
+    };
+
+    if column < width {
+        output.set_background(text_start + column, settings.colors.color_column.background_color);
+    }
+}
+
+/// Tints the indentation of a line if it uses the wrong kind of whitespace.
+pub fn draw_bad_indent(settings: &Settings, line: &str, text_start: usize, col_offset: usize, width: usize, output: &mut TextRow) {
+    let editor_settings = &settings.editor_settings;
+    if !is_bad_indent(line, editor_settings.use_spaces, editor_settings.tab_size) {
+        return;
+    }
+
+    // Tabs get drawn as tab_size spaces
+    let cells = leading_whitespace(line).chars()
+        .map(|c| if c == '\t' { editor_settings.tab_size } else { 1 })
+        .sum::<usize>();
+
+    for column in col_offset..cells.min(col_offset + width) {
+        output.set_background(text_start + column - col_offset, settings.colors.bad_indent.background_color);
+    }
+}
+
+/// Draws the whitespace at the end of a line as dots.
+pub fn draw_trailing_whitespace(settings: &Settings, line: &str, text_start: usize, col_offset: usize, width: usize, output: &mut TextRow) {
+    let tab_size = settings.editor_settings.tab_size;
+    let cells = |text: &str| text.chars().map(|c| if c == '\t' { tab_size } else { 1 }).sum::<usize>();
+
+    let line = line.trim_end_matches(['\n', '\r']);
+    let text = line.trim_end_matches([' ', '\t']);
+
+    let start = cells(text);
+    let end = start + cells(&line[text.len()..]);
+
+    for column in start.max(col_offset)..end.min(col_offset + width) {
+        output.set_char(text_start + column - col_offset, '·', settings.colors.trailing_whitespace.clone());
+    }
+}
+
+/// Draws tabs as an arrow and non-breaking spaces as a box so they can be told apart from spaces.
+pub fn draw_whitespace(settings: &Settings, line: &str, text_start: usize, col_offset: usize, width: usize, output: &mut TextRow) {
+    let tab_size = settings.editor_settings.tab_size;
+    let scheme = &settings.colors.whitespace;
+
+    // The rest of a tab is left as the spaces it was drawn with
+    let mut column = 0;
+    for c in line.chars() {
+        if column >= col_offset + width {
+            break;
+        }
+        let symbol = match c {
+            '\t' => Some('→'),
+            '\u{a0}' => Some('␣'),
+            _ => None,
+        };
+        if let (Some(symbol), true) = (symbol, column >= col_offset) {
+            output.set_char(text_start + column - col_offset, symbol, scheme.clone());
+        }
+        column += if c == '\t' { tab_size } else { 1 };
+    }
+}
+
+/// The two ends of a visual selection as (x, y) positions, first end first.
+pub type Selection = ((usize, usize), (usize, usize));
+
+/// The anchor and cursor of the last visual selection and its kind, for gv.
+pub type LastSelection = ((usize, usize), (usize, usize), VisualKind);
+
+/// The bracket that matches the one under the cursor.
+/// It is only looked for again once the cursor moves or the text changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BracketMatch {
+    pub cursor: (usize, usize),
+    pub revision: (usize, usize),
+    pub found: Option<(usize, usize)>,
+}
+
+impl BracketMatch {
+    pub fn is_current(&self, cursor: (usize, usize), revision: (usize, usize)) -> bool {
+        self.cursor == cursor && self.revision == revision
+    }
+
+    /// Both brackets as (x, y) positions if there is a match.
+    pub fn brackets(&self) -> Option<[(usize, usize); 2]> {
+        self.found.map(|found| [self.cursor, found])
+    }
+}
+
+/// Orders the anchor and the cursor so the selection works when the cursor is before the anchor.
+pub fn order_selection(anchor: (usize, usize), cursor: (usize, usize)) -> Selection {
+    if (anchor.1, anchor.0) <= (cursor.1, cursor.0) {
+        (anchor, cursor)
+    }
+    else {
+        (cursor, anchor)
+    }
+}
+
+/// Puts the corners of a block selection in order, top left first.
+pub fn order_block(anchor: (usize, usize), cursor: (usize, usize)) -> Selection {
+    ((anchor.0.min(cursor.0), anchor.1.min(cursor.1)), (anchor.0.max(cursor.0), anchor.1.max(cursor.1)))
+}
+
+/// Finds the byte of the character at a position in the text.
+/// Positions past the end of a line give the end of the line, including its line break.
+fn position_to_byte(text: &str, (x, y): (usize, usize)) -> Option<usize> {
+    let mut line_start = 0;
+    for (row, line) in text.split_inclusive('\n').enumerate() {
+        if row == y {
+            let col = line.char_indices().nth(x).map(|(byte, _)| byte).unwrap_or(line.len());
+            return Some(line_start + col);
+        }
+        line_start += line.len();
+    }
+    None
+}
+
+/// Finds where to put whole lines that are pasted above or below a row.
+/// Returns the byte to insert at and the text to insert there.
+pub fn line_paste(text: &str, row: usize, after: bool, lines: &str) -> (usize, String) {
+    let target = if after { row + 1 } else { row };
+
+    match position_to_byte(text, (0, target)) {
+        Some(byte) => (byte, lines.to_string()),
+        // The last line has no line break to paste after
+        None if !text.is_empty() && !text.ends_with('\n') => {
+            (text.len(), format!("\n{}", lines.strip_suffix('\n').unwrap_or(lines)))
+        },
+        None => (text.len(), lines.to_string()),
+    }
+}
+
+/// Finds the bytes of the text that a selection covers.
+/// Both ends are included so the character under the cursor is part of the selection.
+pub fn selection_byte_range(text: &str, (start, end): Selection) -> Option<Range<usize>> {
+    let start = position_to_byte(text, start)?;
+    let end = position_to_byte(text, (end.0.saturating_add(1), end.1)).unwrap_or(text.len());
+
+    if start >= end {
+        return None;
+    }
+    Some(start..end)
+}
+
+/// Finds the bytes of each line that a block selection covers, from the top line down.
+/// Lines that end before the block give an empty range at their end.
+pub fn block_byte_ranges(text: &str, ((left, top), (right, bottom)): Selection) -> Vec<Range<usize>> {
+    (top..=bottom).filter_map(|row| {
+        let line_start = position_to_byte(text, (0, row))?;
+        let line = text[line_start..].split('\n').next().unwrap_or("");
+        let byte = |col: usize| line.char_indices().nth(col).map(|(byte, _)| byte).unwrap_or(line.len());
+        Some(line_start + byte(left)..line_start + byte(right.saturating_add(1)))
+    }).collect()
+}
+
+/// Finds the bytes of whole lines to delete.
+/// Deleting the last lines of a file takes the line break before them so no empty line is left behind.
+pub fn line_delete_range(text: &str, first: usize, last: usize) -> Option<Range<usize>> {
+    let range = selection_byte_range(text, ((0, first), (usize::MAX, last)))?;
+
+    if range.end == text.len() && !text.ends_with('\n') && range.start > 0 {
+        return Some(range.start - 1..range.end);
+    }
+    Some(range)
+}
+
+/// Finds the cells on screen that each character of a line is drawn in.
+/// Tabs get drawn as tab_size spaces and the line break as one cell.
+fn char_cells(line: &str, tab_size: usize) -> Vec<Range<usize>> {
+    let mut cell = 0;
+    line.chars().map(|c| {
+        let size = if c == '\t' { tab_size } else { 1 };
+        cell += size;
+        cell - size..cell
+    }).collect()
+}
+
+/// Tints the matches of the last search in a line.
+pub fn draw_search_matches(settings: &Settings, pattern: &str, line: &str, text_start: usize, col_offset: usize, width: usize, output: &mut TextRow) {
+    // A bad pattern was already reported when it was searched for
+    let pattern = match Pattern::compile(pattern) {
+        Err(_) => return,
+        Ok(pattern) => pattern,
+    };
+    let cells = char_cells(line, settings.editor_settings.tab_size);
+
+    for found in pattern.find_all(line) {
+        let columns = cells.iter()
+            .skip(found.col)
+            .take(found.len)
+            .flat_map(|cells| cells.clone());
+
+        for column in columns {
+            if column >= col_offset && column < col_offset + width {
+                output.set_background(text_start + column - col_offset, settings.colors.search.background_color);
+            }
+        }
+    }
+}
+
+/// Tints the part of a line that is selected.
+pub fn draw_selection(settings: &Settings, (start, end): Selection, (row, line): (usize, &str), text_start: usize, col_offset: usize, width: usize, output: &mut TextRow) {
+    if row < start.1 || row > end.1 {
+        return;
+    }
+
+    let first = if row == start.1 { start.0 } else { 0 };
+    let last = if row == end.1 { end.0 } else { usize::MAX };
+
+    let mut cells = char_cells(line, settings.editor_settings.tab_size);
+    // An empty line still shows that it is selected
+    if cells.is_empty() {
+        cells.push(0..1);
+    }
+
+    let selected = cells.iter()
+        .enumerate()
+        .filter(|(index, _)| *index >= first && *index <= last)
+        .flat_map(|(_, cells)| cells.clone());
+
+    for column in selected {
+        if column >= col_offset && column < col_offset + width {
+            output.set_background(text_start + column - col_offset, settings.colors.selection.background_color);
+        }
+    }
+}
+
+/// Tints the columns of a block selection on a row.
+pub fn draw_block_selection(settings: &Settings, ((left, top), (right, bottom)): Selection, (row, line): (usize, &str), text_start: usize, col_offset: usize, width: usize, output: &mut TextRow) {
+    if row < top || row > bottom {
+        return;
+    }
+    draw_selection(settings, ((left, row), (right, row)), (row, line), text_start, col_offset, width, output);
+}
+
+/// Tints the bracket under the cursor and the one that matches it.
+pub fn draw_matching_brackets(settings: &Settings, brackets: [(usize, usize); 2], (row, line): (usize, &str), text_start: usize, col_offset: usize, width: usize, output: &mut TextRow) {
+    let cells = char_cells(line, settings.editor_settings.tab_size);
+
+    for (col, bracket_row) in brackets {
+        if bracket_row != row {
+            continue;
+        }
+
+        for column in cells.get(col).cloned().unwrap_or_default() {
+            if column >= col_offset && column < col_offset + width {
+                output.set_background(text_start + column - col_offset, settings.colors.matching_bracket.background_color);
+            }
+        }
+    }
+}
+
+/// Draws a row from a command preview, skipping the columns that are scrolled off.
+/// The characters marked as changed get drawn with the preview colors.
+pub fn draw_preview_row(row: &[(char, bool)],
+                        col_offset: usize,
+                        width: usize,
+                        tab_size: usize,
+                        colors: (&ColorScheme, &ColorScheme),
+                        output: &mut TextRow) {
+    let (normal, preview) = colors;
+    let mut count = 0;
+
+    for (c, changed) in row.iter().skip(col_offset) {
+        let color = if *changed { preview } else { normal };
+        let string = match c {
+            '\t' => " ".repeat(tab_size),
+            c => c.to_string(),
+        };
+
+        for c in string.chars() {
+            if count == width {
+                return;
+            }
+            output.push(Some(Some(StyledChar::new(c, color.clone()))));
+            count += 1;
+        }
+    }
+
+    for _ in count..width {
+        output.push(Some(Some(StyledChar::new(' ', normal.clone()))));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+
+    #[test]
+    fn test_map_message() {
+        assert!(matches!(map_message("map", &["normal", "C-w", "s", "horizontal_split"]),
+                         Ok(Message::MapKeys(mode, keys, Some(command))) if mode == "normal" && keys == "C-w s" && command == "horizontal_split"));
+        assert!(matches!(map_message("unmap", &["insert", "C-k"]),
+                         Ok(Message::MapKeys(mode, keys, None)) if mode == "insert" && keys == "C-k"));
+        assert!(matches!(map_message("maps", &[]), Ok(Message::ListMaps(mode)) if mode == "normal"));
+        assert!(map_message("map", &["normal", "undo"]).is_err());
+        assert!(map_message("unmap", &["normal"]).is_err());
+        assert!(map_message("maps", &["normal", "visual"]).is_err());
+    }
+
+    #[test]
+    fn test_display_name() {
+        assert_eq!(display_name(None), "[No Name]");
+        assert_eq!(display_name(Some(Path::new("src/main.rs"))), "main.rs");
+        assert_eq!(display_name(Some(Path::new("src/.."))), "src/..");
+        assert_eq!(display_name(Some(Path::new(""))), "[No Name]");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_display_name_not_utf8() {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+        let name = OsStr::from_bytes(b"bad\xffname.txt");
+        let path = Path::new("dir").join(name);
+
+        assert_eq!(display_name(Some(&path)), "bad\u{FFFD}name.txt");
+    }
+
+    #[test]
+    fn test_selection_byte_range() {
+        let text = "one\ntwo\nthree\n";
+
+        assert_eq!(selection_byte_range(text, order_selection((1, 0), (1, 1))), Some(1..6));
+        // The cursor went up past the anchor
+        assert_eq!(selection_byte_range(text, order_selection((1, 1), (1, 0))), Some(1..6));
+        assert_eq!(selection_byte_range(text, order_selection((0, 2), (0, 2))), Some(8..9));
+        // Selecting past the end of a line takes the line break too
+        assert_eq!(selection_byte_range(text, order_selection((3, 0), (3, 0))), Some(3..4));
+        assert_eq!(selection_byte_range(text, order_selection((0, 5), (0, 5))), None);
+        // Line-wise selections reach past the end of the last line
+        assert_eq!(selection_byte_range(text, ((0, 1), (usize::MAX, 2))), Some(4..14));
+    }
+
+    #[test]
+    fn test_block_byte_ranges() {
+        let text = "one\nt\nthree\n";
+        let block = order_block((2, 2), (1, 0));
+
+        assert_eq!(block, ((1, 0), (2, 2)));
+        // The short line in the middle has nothing in the block
+        assert_eq!(block_byte_ranges(text, block), vec![1..3, 5..5, 7..9]);
+        assert_eq!(block_byte_ranges(text, ((0, 3), (1, 4))), Vec::<Range<usize>>::new());
+    }
+
+    #[test]
+    fn test_line_delete_range() {
+        assert_eq!(line_delete_range("one\ntwo\nthree\n", 0, 1), Some(0..8));
+        // Counts past the end of the file stop at the last line
+        assert_eq!(line_delete_range("one\ntwo\nthree\n", 1, 9), Some(4..14));
+        assert_eq!(line_delete_range("one\ntwo", 1, 1), Some(3..7));
+        assert_eq!(line_delete_range("one\ntwo", 5, 5), None);
+    }
+
+    #[test]
+    fn test_line_paste() {
+        let text = "one\ntwo\n";
+
+        assert_eq!(line_paste(text, 0, true, "new\n"), (4, "new\n".to_string()));
+        assert_eq!(line_paste(text, 0, false, "new\n"), (0, "new\n".to_string()));
+        assert_eq!(line_paste(text, 1, true, "new\n"), (8, "new\n".to_string()));
+        assert_eq!(line_paste("one\ntwo", 1, true, "new\n"), (7, "\nnew".to_string()));
+        assert_eq!(line_paste("", 0, true, "new\n"), (0, "new\n".to_string()));
+    }
 
+    #[test]
+    fn test_draw_zero_size_pane() {
+        use std::sync::mpsc::channel;
 
+        use crate::pane::text::PlainTextPane;
 
+        let settings = Rc::new(RefCell::new(Settings::default()));
+        let (sender, _receiver) = channel();
+        let pane = Rc::new(RefCell::new(PlainTextPane::new(settings.clone(), sender)));
+
+        for size in [(0, 10), (10, 0), (0, 0)] {
+            let container = PaneContainer::new((80, 24), size, pane.clone(), settings.clone());
+            let mut output = TextRow::new();
+
+            container.draw_row(0, &mut output);
+
+            assert_eq!(output.index, 0);
+        }
+    }
+}
@@ -0,0 +1,248 @@
+use std::{sync::mpsc::{self, Receiver, Sender}, thread};
+
+use crop::Rope;
+use tree_sitter::{InputEdit, Language, Parser, Tree};
+
+
+/// The text to parse and the tree it came from, already edited to fit the text.
+struct ParseJob {
+    generation: usize,
+    text: Rope,
+    old_tree: Option<Tree>,
+}
+
+/// A tree parsed on the thread and the first row whose nodes changed, `None` when none did.
+struct ParsedTree {
+    generation: usize,
+    tree: Tree,
+    changed_from: Option<usize>,
+}
+
+/// Parses the text of a pane on another thread so big files don't hold up drawing and typing.
+///
+/// Every job gets the next generation and the trees that come back for a generation the pane
+/// has already moved past are thrown away, so a slow parse can't go over a newer tree.
+pub struct BackgroundParser {
+    sender: Sender<ParseJob>,
+    receiver: Receiver<ParsedTree>,
+    generation: usize,
+    /// The generation of the last tree the pane took.
+    parsed: usize,
+    /// The edits made since that tree with the generation of their job and the row they start on.
+    /// `None` is a parse from scratch.
+    pending: Vec<(usize, usize, Option<InputEdit>)>,
+}
+
+impl BackgroundParser {
+    pub fn new(language: Language) -> Self {
+        let (sender, jobs) = mpsc::channel::<ParseJob>();
+        let (trees, receiver) = mpsc::channel();
+
+        thread::spawn(move || {
+            let mut parser = Parser::new();
+            parser.set_language(language).expect("Failed to set the language");
+
+            // The pane hung up when it was closed
+            while let Ok(mut job) = jobs.recv() {
+                // Each job has every edit before it in its tree, so only the newest one needs parsing
+                while let Ok(newer) = jobs.try_recv() {
+                    job = newer;
+                }
+
+                let text = job.text.to_string();
+                let tree = parser.parse(&text, job.old_tree.as_ref()).expect("Failed to parse");
+                let changed_from = match &job.old_tree {
+                    None => Some(0),
+                    Some(old_tree) => old_tree.changed_ranges(&tree)
+                        .map(|range| range.start_point.row)
+                        .min(),
+                };
+
+                let parsed = ParsedTree {
+                    generation: job.generation,
+                    tree,
+                    changed_from,
+                };
+                if trees.send(parsed).is_err() {
+                    return;
+                }
+            }
+        });
+
+        Self {
+            sender,
+            receiver,
+            generation: 0,
+            parsed: 0,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Parses the text again after an edit that `tree` was already told about.
+    /// `row` is the row the edit starts on.
+    pub fn edit(&mut self, edit: InputEdit, row: usize, text: Rope, tree: Option<&Tree>) {
+        self.send(text, tree.cloned(), row, Some(edit));
+    }
+
+    /// Parses the text without an old tree, for changes too big to tell the tree about.
+    pub fn parse_from_scratch(&mut self, text: Rope) {
+        self.send(text, None, 0, None);
+    }
+
+    fn send(&mut self, text: Rope, old_tree: Option<Tree>, row: usize, edit: Option<InputEdit>) {
+        self.generation += 1;
+        self.pending.push((self.generation, row, edit));
+
+        let job = ParseJob {
+            generation: self.generation,
+            text,
+            old_tree,
+        };
+        self.sender.send(job).expect("The parser thread stopped");
+    }
+
+    /// Forgets the trees still being parsed, like when the pane parsed the text itself.
+    pub fn cancel(&mut self) {
+        self.parsed = self.generation;
+        self.pending.clear();
+    }
+
+    /// Whether a tree is still coming for the latest text.
+    pub fn is_parsing(&self) -> bool {
+        self.parsed < self.generation
+    }
+
+    /// Takes the newest tree that came back, edited to fit the text as it is now.
+    /// The row is the first one whose highlighting might be wrong since the last tree.
+    pub fn receive(&mut self) -> Option<(Tree, usize)> {
+        let mut newest: Option<ParsedTree> = None;
+        let mut changed_from = None;
+        for parsed in self.receiver.try_iter() {
+            if parsed.generation <= self.parsed {
+                continue;
+            }
+            changed_from = min_row(changed_from, parsed.changed_from);
+            if newest.as_ref().is_none_or(|newest| newest.generation <= parsed.generation) {
+                newest = Some(parsed);
+            }
+        }
+        let ParsedTree { generation, mut tree, .. } = newest?;
+        self.parsed = generation;
+
+        // The rows of the edits in the tree were drawn with the tree before them
+        for (_, row, _) in self.pending.iter().filter(|(edit_generation, _, _)| *edit_generation <= generation) {
+            changed_from = min_row(changed_from, Some(*row));
+        }
+        self.pending.retain(|(edit_generation, _, _)| *edit_generation > generation);
+
+        // The edits made while it was being parsed aren't in it yet
+        for (_, _, edit) in self.pending.iter() {
+            if let Some(edit) = edit {
+                tree.edit(edit);
+            }
+        }
+
+        Some((tree, changed_from.unwrap_or(usize::MAX)))
+    }
+}
+
+fn min_row(a: Option<usize>, b: Option<usize>) -> Option<usize> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use tree_sitter::Point;
+
+    use super::*;
+
+    fn insert_edit(start_byte: usize, len: usize, row: usize, column: usize) -> InputEdit {
+        InputEdit {
+            start_byte,
+            old_end_byte: start_byte,
+            new_end_byte: start_byte + len,
+            start_position: Point::new(row, column),
+            old_end_position: Point::new(row, column),
+            new_end_position: Point::new(row, column + len),
+        }
+    }
+
+    /// Waits for the tree of the latest text.
+    fn wait(parser: &mut BackgroundParser) -> (Tree, usize) {
+        loop {
+            match parser.receive() {
+                Some(parsed) if !parser.is_parsing() => return parsed,
+                _ => std::thread::sleep(std::time::Duration::from_millis(10)),
+            }
+        }
+    }
+
+    #[test]
+    fn test_parses_on_another_thread() {
+        let mut parser = BackgroundParser::new(tree_sitter_rust::language());
+        assert!(!parser.is_parsing());
+
+        parser.parse_from_scratch(Rope::from("fn a() {}\n"));
+        assert!(parser.is_parsing());
+        let (tree, changed_from) = wait(&mut parser);
+        assert!(!parser.is_parsing());
+        assert_eq!(changed_from, 0);
+        assert_eq!(tree.root_node().child(0).unwrap().kind(), "function_item");
+        assert!(parser.receive().is_none());
+
+        // An edit is parsed with the tree it was made to
+        let mut old_tree = tree.clone();
+        let edit = insert_edit(8, 5, 0, 8);
+        old_tree.edit(&edit);
+        parser.edit(edit, 0, Rope::from("fn a() {1 + 1}\n"), Some(&old_tree));
+        let (tree, changed_from) = wait(&mut parser);
+        assert_eq!(changed_from, 0);
+        assert!(tree.root_node().to_sexp().contains("binary_expression"));
+    }
+
+    #[test]
+    fn test_old_trees_are_thrown_away() {
+        let mut parser = BackgroundParser::new(tree_sitter_rust::language());
+        parser.parse_from_scratch(Rope::from("fn a() {}\n"));
+        parser.parse_from_scratch(Rope::from("struct A;\n"));
+        let (tree, _) = wait(&mut parser);
+        assert_eq!(tree.root_node().child(0).unwrap().kind(), "struct_item");
+
+        // A tree for text the pane parsed itself never gets used
+        parser.parse_from_scratch(Rope::from("fn a() {}\n"));
+        parser.cancel();
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        assert!(parser.receive().is_none());
+        assert!(!parser.is_parsing());
+    }
+
+    #[test]
+    fn test_edits_after_a_job_are_applied_to_its_tree() {
+        let mut parser = BackgroundParser::new(tree_sitter_rust::language());
+        parser.parse_from_scratch(Rope::from("fn a() {}\n"));
+        let (mut tree, _) = wait(&mut parser);
+
+        let first = insert_edit(0, 1, 0, 0);
+        tree.edit(&first);
+        parser.edit(first, 0, Rope::from(" fn a() {}\n"), Some(&tree));
+
+        // Another edit whose tree hasn't come back yet
+        let second = insert_edit(0, 1, 0, 0);
+        parser.generation += 1;
+        parser.pending.push((parser.generation, 0, Some(second)));
+
+        let (tree, changed_from) = loop {
+            match parser.receive() {
+                None => std::thread::sleep(std::time::Duration::from_millis(10)),
+                Some(parsed) => break parsed,
+            }
+        };
+        assert_eq!(changed_from, 0);
+        assert_eq!(tree.root_node().child(0).unwrap().start_byte(), 2);
+        assert!(parser.is_parsing());
+    }
+}
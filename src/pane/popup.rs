@@ -3,7 +3,7 @@ use std::{rc::Rc, cell::RefCell, sync::mpsc::{Sender, Receiver}, path::PathBuf,
 
 use uuid::Uuid;
 
-use crate::{mode::{Mode, PromptType, Promptable}, cursor::Cursor, window::{StyledChar, Message, TextRow}, settings::Settings, buffer::Buffer};
+use crate::{mode::{Mode, PromptType, Promptable, drop_down::SidePanel}, cursor::Cursor, window::{StyledChar, Message, TextRow}, settings::Settings, buffer::Buffer};
 use super::{PaneMessage, PaneContainer, Pane};
 
 
@@ -83,8 +83,21 @@ impl PopUpPane {
                         pane_receiver: Receiver<PaneMessage>,
                         buttons: PromptType,
                         border: bool) -> PopUpPane {
+        Self::new_dropdown_with_panel(settings, prompt, window_sender, pane_sender, pane_receiver, buttons, None, border)
+    }
+
+    /// A dropdown that shows more about the selected button in a panel beside the list.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new_dropdown_with_panel(settings: Rc<RefCell<Settings>>,
+                                   prompt: Vec<String>,
+                                   window_sender: Sender<Message>,
+                                   pane_sender: Sender<PaneMessage>,
+                                   pane_receiver: Receiver<PaneMessage>,
+                                   buttons: PromptType,
+                                   side_panel: Option<SidePanel>,
+                                   border: bool) -> PopUpPane {
 
-        let mode = Rc::new(RefCell::new(crate::mode::drop_down::DropDown::new(buttons)));
+        let mode = Rc::new(RefCell::new(crate::mode::drop_down::DropDown::new(buttons, side_panel)));
 
         mode.borrow_mut().add_keybindings(settings.borrow().mode_keybindings.get("Drop Down").unwrap().clone());
 
@@ -109,6 +122,7 @@ impl PopUpPane {
                 match message {
                     PaneMessage::String(_string) => {
                     },
+                    PaneMessage::PreviewString(_) | PaneMessage::Cancel => {},
                     PaneMessage::Close => self.run_command(&format!("close {}", container.get_uuid()), container),
                 }
             },
@@ -304,13 +318,21 @@ impl Pane for PopUpPane {
         match command {
             "cancel" => {
                 self.window_sender.send(Message::ClosePane(true, None)).unwrap();
+                // Whoever opened the prompt might be gone already
+                self.pane_sender.send(PaneMessage::Cancel).ok();
+            },
+            "preview" => {
+                let _result_type = command_args.next();
+                let value = command_args.collect::<Vec<&str>>().join(" ");
+                self.pane_sender.send(PaneMessage::PreviewString(value)).ok();
             },
             "submit" => {
                 let result_type = command_args.next().unwrap();
 
                 match result_type {
                     "text" => {
-                        let value = command_args.next().unwrap();
+                        // The text can have spaces in it
+                        let value = command_args.collect::<Vec<&str>>().join(" ");
                         self.window_sender.send(Message::ClosePane(true, None)).unwrap();
                         self.pane_sender.send(PaneMessage::String(value.to_string())).unwrap();
                     },
@@ -397,11 +419,22 @@ impl Pane for PopUpPane {
     fn resize_cursor(&mut self, _size: (usize, usize)) {}
 
     fn set_cursor_size(&mut self, _size: (usize, usize)) {}
-        
 
-    fn backup_buffer(&mut self) {
+    fn set_location(&mut self, _location: (usize, usize)) {}
+
+    fn click(&mut self, (_, row): (usize, usize), container: &mut PaneContainer) {
+        // The top row is the border, then come the lines of the prompt with a blank line after them
+        let title = self.prompt.len() + usize::from(!self.prompt.is_empty());
+        let row = match row.checked_sub(1 + title) {
+            None => return,
+            Some(row) => row,
+        };
+
+        let mode = self.mode.clone();
+        mode.borrow_mut().click(row, self, container);
     }
-    
+        
+
     fn get_settings(&self) -> Rc<RefCell<Settings>> {
         self.settings.clone()
     }
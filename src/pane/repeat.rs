@@ -0,0 +1,67 @@
+use crossterm::event::KeyEvent;
+
+use crate::mode::Mode;
+
+
+
+/// Remembers the keys of the last change so that `.` can play them again.
+///
+/// Keys are collected from the moment Normal mode has nothing pending until it gets back to that point.
+/// If the text was edited in between, those keys were a change, otherwise they were a motion or some other command.
+pub struct ChangeRecorder {
+    keys: Vec<KeyEvent>,
+    /// The edit count of the buffer when the keys started.
+    edits: usize,
+    /// Set when the keys went through Command mode, whose commands don't get repeated.
+    skip: bool,
+    last_change: Vec<KeyEvent>,
+}
+
+impl Default for ChangeRecorder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ChangeRecorder {
+    pub fn new() -> Self {
+        Self {
+            keys: Vec::new(),
+            edits: 0,
+            skip: false,
+            last_change: Vec::new(),
+        }
+    }
+
+    fn is_idle(mode: &dyn Mode) -> bool {
+        mode.get_name() == "Normal" && mode.get_pending_keys().is_empty()
+    }
+
+    /// This should get called before the mode handles the key.
+    pub fn before_key(&mut self, key: KeyEvent, mode: &dyn Mode, edits: usize) {
+        if Self::is_idle(mode) {
+            self.keys.clear();
+            self.edits = edits;
+            self.skip = false;
+        }
+        self.keys.push(key);
+    }
+
+    /// This should get called after the mode handled the key, with the mode the pane is in now.
+    pub fn after_key(&mut self, mode: &dyn Mode, edits: usize) {
+        if mode.get_name() == "Command" {
+            self.skip = true;
+        }
+
+        if Self::is_idle(mode) {
+            if !self.skip && edits != self.edits {
+                self.last_change = std::mem::take(&mut self.keys);
+            }
+            self.keys.clear();
+        }
+    }
+
+    pub fn last_change(&self) -> &[KeyEvent] {
+        &self.last_change
+    }
+}
@@ -1,18 +1,30 @@
+use crate::definition;
+use crate::indent;
+use crate::word;
+use crate::undo;
+use crate::brackets;
+use crate::encoding::{FileEncoding, parse_encoding};
+use crate::disk::{DiskWatch, EditTimer, SaveOptions, diff_lines, save_file};
+use crate::lines::{LineCommand, LineOperation};
+use crate::substitute::{Substitute, SubstituteRun};
 use crate::editor::RegisterType;
-use crate::mode::PromptType;
-use crate::registers::Registers;
-use crate::window::TextRow;
+use crate::mode::{PromptType, ButtonFunction};
+use crate::registers::{RegisterValue, RegisterKind};
+use crate::search::{SearchMatch, Pattern, next_match};
+use crate::window::{TextRow, MessageKind};
+use crate::lsp::lsp_utils::{TextEdit, CompletionList, edits_from_bottom};
 use crate::{pane::Pane, window::StyledChar, cursor::CursorMove, buffer::Buffer};
-use std::{io::Write, sync::mpsc::Receiver};
+use std::sync::mpsc::Receiver;
 
-use std::{collections::HashMap, rc::Rc, cell::RefCell, path::PathBuf, sync::mpsc::Sender, io};
+use std::{collections::HashMap, rc::Rc, cell::RefCell, path::{Path, PathBuf}, sync::mpsc::Sender, io, ops::Bound, time::Instant};
 
 use crop::{RopeSlice, Rope};
+use encoding_rs::Encoding;
 use crossterm::event::KeyEvent;
 
-use crate::{cursor::{Cursor, Direction}, mode::{Mode, base::{Normal, Insert, Command}}, settings::Settings, window::Message};
+use crate::{cursor::{Cursor, Direction, ScreenLine}, mode::{Mode, base::{Normal, Insert, Command, Visual, VisualKind}, reload_keybindings}, settings::Settings, window::Message};
 
-use super::{PaneContainer, PaneMessage, popup::PopUpPane};
+use super::{PaneContainer, PaneMessage, popup::PopUpPane, completion::{completion_popup, completion_edit, word_prefix, buffer_words, path_completions}, which_key::WhichKey, repeat::ChangeRecorder, draw_preview_row, draw_cursor_line, draw_color_column, draw_bad_indent, draw_trailing_whitespace, draw_whitespace, draw_selection, draw_block_selection, order_selection, order_block, selection_byte_range, block_byte_ranges, line_paste, line_delete_range, draw_search_matches, draw_matching_brackets, Selection, LastSelection, BracketMatch, map_message, TEXT_COMMANDS};
 
 
 #[derive(Debug, Clone)]
@@ -107,6 +119,21 @@ pub enum Waiting {
     JumpPosition,
     Completion,
     Goto,
+    Spelling,
+    Search,
+    Substitute,
+    Rename,
+    CodeAction,
+    /// The file changed on disk while the buffer had changes of its own.
+    DiskChange,
+    /// The rest are for answers from a language server, which only the tree-sitter pane asks for.
+    CompletionResponse,
+    GotoResponse,
+    ReferencesResponse,
+    HoverResponse,
+    RenameResponse,
+    FormattingResponse,
+    CodeActionResponse,
     None,
 }
 
@@ -124,6 +151,30 @@ pub struct PlainTextPane {
     sender: Sender<Message>,
     popup_channels: Option<(Sender<PaneMessage>, Receiver<PaneMessage>)>,
     waiting: Waiting,
+    /// The words in the open completion list.
+    completion: Option<CompletionList>,
+    which_key: WhichKey,
+    change_recorder: ChangeRecorder,
+    bracket_match: Option<BracketMatch>,
+    substitute_preview: Option<Substitute>,
+    /// Where the cursor was when visual mode started.
+    selection_anchor: Option<(usize, usize)>,
+    /// What the selection covers between the anchor and the cursor.
+    selection_kind: VisualKind,
+    /// The anchor, cursor and kind of the last selection, for `gv` to select again.
+    last_selection: Option<LastSelection>,
+    search_pattern: Option<String>,
+    search_matches: Vec<SearchMatch>,
+    /// Whether the matches of the last search are drawn, which `noh` turns off until the next search.
+    search_highlight: bool,
+    /// The search being typed in the prompt and where the cursor was before it moved to the first match.
+    search_preview: Option<String>,
+    search_start: Option<Cursor>,
+    /// The substitute waiting on an answer to its replace prompt.
+    substitute_run: Option<SubstituteRun>,
+    encoding: FileEncoding,
+    disk_watch: DiskWatch,
+    edit_timer: EditTimer,
 }
 
 impl PlainTextPane {
@@ -141,9 +192,24 @@ impl PlainTextPane {
         command.borrow_mut().add_keybindings(settings.borrow().mode_keybindings.get("Command").unwrap().clone());
         command.borrow_mut().set_key_timeout(settings.borrow().editor_settings.key_timeout);
 
+        let visual = Rc::new(RefCell::new(Visual::new(VisualKind::Chars)));
+        visual.borrow_mut().add_keybindings(settings.borrow().mode_keybindings.get("Visual").unwrap().clone());
+        visual.borrow_mut().set_key_timeout(settings.borrow().editor_settings.key_timeout);
+
+        let visual_line = Rc::new(RefCell::new(Visual::new(VisualKind::Lines)));
+        visual_line.borrow_mut().add_keybindings(settings.borrow().mode_keybindings.get("Visual").unwrap().clone());
+        visual_line.borrow_mut().set_key_timeout(settings.borrow().editor_settings.key_timeout);
+
+        let visual_block = Rc::new(RefCell::new(Visual::new(VisualKind::Block)));
+        visual_block.borrow_mut().add_keybindings(settings.borrow().mode_keybindings.get("Visual").unwrap().clone());
+        visual_block.borrow_mut().set_key_timeout(settings.borrow().editor_settings.key_timeout);
+
         modes.insert("Normal".to_string(), normal.clone());
         modes.insert("Insert".to_string(), insert.clone());
         modes.insert("Command".to_string(), command.clone());
+        modes.insert("Visual".to_string(), visual.clone());
+        modes.insert("Visual Line".to_string(), visual_line.clone());
+        modes.insert("Visual Block".to_string(), visual_block.clone());
 
         
         Self {
@@ -158,6 +224,23 @@ impl PlainTextPane {
             sender,
             popup_channels: None,
             waiting: Waiting::None,
+            completion: None,
+            which_key: WhichKey::new(),
+            change_recorder: ChangeRecorder::new(),
+            bracket_match: None,
+            substitute_preview: None,
+            selection_anchor: None,
+            selection_kind: VisualKind::Chars,
+            last_selection: None,
+            search_pattern: None,
+            search_matches: Vec::new(),
+            search_highlight: false,
+            search_preview: None,
+            search_start: None,
+            substitute_run: None,
+            encoding: FileEncoding::default(),
+            disk_watch: DiskWatch::new(),
+            edit_timer: EditTimer::new(),
         }
     }
 
@@ -166,12 +249,677 @@ impl PlainTextPane {
         self.changed = changed;
     }
 
+    /// The selected text, if visual mode is on.
+    fn get_selection(&self) -> Option<Selection> {
+        let anchor = self.selection_anchor?;
+        let cursor = self.cursor.borrow().get_cursor();
+
+        match self.selection_kind {
+            VisualKind::Chars => Some(order_selection(anchor, cursor)),
+            VisualKind::Lines => {
+                let (start, end) = order_selection(anchor, cursor);
+                Some(((0, start.1), (usize::MAX, end.1)))
+            },
+            VisualKind::Block => Some(order_block(anchor, cursor)),
+        }
+    }
+
+    /// Puts the selected text in a register and moves the cursor to the start of the selection.
+    /// Returns the bytes that were selected, one range for each line of a block.
+    fn copy_selection(&mut self, reg: RegisterType) -> Option<Vec<std::ops::Range<usize>>> {
+        let selection = self.get_selection()?;
+        let text = self.contents.to_string();
+        let ranges = match self.selection_kind {
+            VisualKind::Block => block_byte_ranges(&text, selection),
+            _ => vec![selection_byte_range(&text, selection)?],
+        };
+
+        let copied = ranges.iter().map(|range| &text[range.clone()]).collect::<Vec<_>>().join("\n");
+        let value = match self.selection_kind {
+            VisualKind::Lines => RegisterValue::line_wise(copied),
+            _ => RegisterValue::char_wise(copied),
+        };
+        self.sender.send(Message::Copy(reg, value)).expect("Failed to send message");
+
+        let ((x, y), _) = selection;
+        let cursor = self.cursor.clone();
+        cursor.borrow_mut().jump_to(x, y, self);
+
+        Some(ranges)
+    }
+
+    /// Puts the cursor's line and the lines after it in a register.
+    fn copy_lines(&self, count: usize, reg: RegisterType) {
+        let (_, y) = self.cursor.borrow().get_cursor();
+        let text = self.contents.to_string();
+        let range = match selection_byte_range(&text, ((0, y), (usize::MAX, y + count - 1))) {
+            None => return,
+            Some(range) => range,
+        };
+
+        let message = Message::Copy(reg, RegisterValue::line_wise(text[range].to_string()));
+        self.sender.send(message).expect("Failed to send message");
+    }
+
+    /// Replaces the text between two (column, row) positions, the end being exclusive.
+    fn replace_text_at(&mut self, start: (usize, usize), end: (usize, usize), text: &str) {
+        let (start_byte, end_byte) = match (self.contents.get_byte_offset(start.0, start.1), self.contents.get_byte_offset(end.0, end.1)) {
+            (Some(start_byte), Some(end_byte)) => (start_byte, end_byte),
+            _ => return,
+        };
+        self.set_changed(true);
+
+        self.contents.replace(start_byte..end_byte, text);
+    }
+
+    /// Deletes the columns of a line, the end being exclusive, and puts what was there in a register.
+    fn delete_columns(&mut self, row: usize, (start, end): (usize, usize), reg: RegisterType) {
+        if start >= end {
+            return;
+        }
+        let line = match self.contents.get_row(row, 0, usize::MAX) {
+            None => return,
+            Some(line) => line.to_string(),
+        };
+
+        let deleted = line.chars().skip(start).take(end - start).collect::<String>();
+        self.sender.send(Message::Copy(reg, RegisterValue::char_wise(deleted))).expect("Failed to send message");
+
+        self.replace_text_at((start, row), (end, row), "");
+
+        let cursor = self.cursor.clone();
+        cursor.borrow_mut().jump_to(start, row, self);
+    }
+
+    /// Returns the columns of the word under the cursor for `iw`.
+    fn inner_word_at(&self, (x, y): (usize, usize)) -> Option<(usize, usize)> {
+        let line = self.contents.get_row(y, 0, usize::MAX)?.to_string();
+        word::inner_word(&line, x)
+    }
+
+    /// Starts a substitute from the command line.
+    fn start_substitute(&mut self, substitute: Substitute, container: &PaneContainer) {
+        let (_, y) = self.cursor.borrow().get_cursor();
+        let run = match SubstituteRun::new(substitute, y, self.contents.get_line_count()) {
+            Err(message) => {
+                self.sender.send(Message::SetMessage(message, MessageKind::Error)).expect("Failed to send message");
+                return;
+            },
+            Ok(run) => run,
+        };
+
+        if run.next_match(&self.contents).is_none() {
+            let message = format!("Pattern not found: {}", run.substitute.pattern);
+            self.sender.send(Message::SetMessage(message, MessageKind::Error)).expect("Failed to send message");
+            return;
+        }
+
+        self.substitute_run = Some(run);
+        self.continue_substitute(container);
+    }
+
+    /// Replaces matches until one has to be confirmed or there are none left.
+    /// Everything replaced without asking is undone together.
+    fn continue_substitute(&mut self, container: &PaneContainer) {
+        let mut grouped = false;
+
+        while let Some(mut run) = self.substitute_run.take() {
+            let found = match run.next_match(&self.contents) {
+                None => {
+                    self.finish_substitute(run);
+                    break;
+                },
+                Some(found) => found,
+            };
+
+            if run.ask {
+                let cursor = self.cursor.clone();
+                cursor.borrow_mut().jump_to(found.col, found.line, self);
+                // Keeps command mode from putting the cursor back before the prompt shows up
+                let current = *self.cursor.borrow();
+                self.cursor.borrow_mut().prepare_jump(&current);
+
+                self.substitute_run = Some(run);
+                self.confirm_substitute(container);
+                break;
+            }
+
+            if !grouped {
+                self.contents.begin_group();
+                grouped = true;
+            }
+            let replacement = run.replacement(&self.contents, found);
+            self.replace_text_at((found.col, found.line), (found.col + found.len, found.line), &replacement);
+            run.advance(found, Some(&replacement));
+            self.substitute_run = Some(run);
+        }
+
+        if grouped {
+            self.contents.end_group();
+        }
+    }
+
+    /// Handles the answer to the replace prompt, where "q" or anything else stops the substitute.
+    fn answer_substitute(&mut self, answer: &str, container: &PaneContainer) {
+        let mut run = match self.substitute_run.take() {
+            None => return,
+            Some(run) => run,
+        };
+        let found = match run.next_match(&self.contents) {
+            None => {
+                self.finish_substitute(run);
+                return;
+            },
+            Some(found) => found,
+        };
+
+        match answer {
+            "y" => {
+                let replacement = run.replacement(&self.contents, found);
+                self.replace_text_at((found.col, found.line), (found.col + found.len, found.line), &replacement);
+                run.advance(found, Some(&replacement));
+            },
+            "n" => run.advance(found, None),
+            "a" => run.ask = false,
+            _ => {
+                self.finish_substitute(run);
+                return;
+            },
+        }
+
+        self.substitute_run = Some(run);
+        self.continue_substitute(container);
+    }
+
+    /// Puts the cursor on the last line that was changed and reports how many replacements were made.
+    fn finish_substitute(&mut self, run: SubstituteRun) {
+        let cursor = self.cursor.clone();
+        match run.last_replaced {
+            Some(row) => {
+                cursor.borrow_mut().jump_to(0, row, self);
+                self.move_to_first_non_blank(false);
+            },
+            None => {
+                // The line the cursor was on might be shorter now
+                let (x, y) = cursor.borrow().get_cursor();
+                cursor.borrow_mut().jump_to(x, y, self);
+            },
+        }
+
+        let message = match run.replaced {
+            1 => "1 substitution".to_string(),
+            replaced => format!("{} substitutions", replaced),
+        };
+        self.sender.send(Message::SetMessage(message, MessageKind::Info)).expect("Failed to send message");
+    }
+
+    /// Asks whether the match under the cursor should be replaced.
+    fn confirm_substitute(&mut self, container: &PaneContainer) {
+        let (send, recv) = std::sync::mpsc::channel();
+        let (send2, recv2) = std::sync::mpsc::channel();
+
+        self.popup_channels = Some((send2, recv));
+
+        let mut buttons = Vec::new();
+        for (label, answer) in [("Yes", "y"), ("No", "n"), ("All", "a"), ("Quit", "q")] {
+            let function: ButtonFunction = Box::new(move |_| answer.to_string());
+            buttons.push((label.to_string(), function));
+        }
+        let buttons = PromptType::Button(buttons, 0);
+
+        let replacement = self.substitute_run.as_ref()
+            .map(|run| run.substitute.replacement.replace('\n', "↵"))
+            .unwrap_or_default();
+        let prompt = vec![format!("Replace with {}?", replacement)];
+        let width = prompt[0].chars().count().max(24) + 2;
+
+        let pane = PopUpPane::new_prompt(
+            self.settings.clone(),
+            prompt,
+            self.sender.clone(),
+            send,
+            recv2,
+            vec![buttons],
+            true
+        );
+
+        let pane = Rc::new(RefCell::new(pane));
+
+        let ((x1, y1), _) = container.get_corners();
+        let (x, y) = container.get_size();
+        let size = (width.min(x), 4.min(y));
+
+        let pos = ((x1 + x / 2).saturating_sub(size.0 / 2), (y1 + y / 2).saturating_sub(size.1 / 2));
+
+        let max_size = container.get_size();
+
+        let mut container = PaneContainer::new(max_size, size, pane, self.settings.clone());
+        container.set_position(pos);
+        container.set_size(size);
+
+        self.sender.send(Message::CreatePopup(container, true)).expect("Failed to send message");
+        self.waiting = Waiting::Substitute;
+    }
+
+    /// Moves the cursor to the first match of the search being typed without adding a jump.
+    fn preview_search(&mut self, pattern: &str) {
+        let start = *self.search_start.get_or_insert(*self.cursor.borrow());
+        *self.cursor.borrow_mut() = start;
+        self.search_preview = None;
+
+        if pattern.is_empty() {
+            return;
+        }
+
+        // A pattern that is still being typed might not compile yet
+        let matches = match Pattern::compile(pattern) {
+            Err(_) => return,
+            Ok(pattern) => pattern.find_all(&self.contents.to_string()),
+        };
+        let wrapscan = self.settings.borrow().editor_settings.wrapscan;
+
+        if let Some((found, _)) = next_match(&matches, start.get_cursor(), true, wrapscan) {
+            let cursor = self.cursor.clone();
+            cursor.borrow_mut().jump_to(found.col, found.line, self);
+        }
+        self.search_preview = Some(pattern.to_string());
+    }
+
+    /// Puts the cursor back where it was before the search being typed moved it.
+    fn end_search_preview(&mut self) {
+        self.search_preview = None;
+        if let Some(start) = self.search_start.take() {
+            *self.cursor.borrow_mut() = start;
+        }
+    }
+
+    /// Searches for a pattern and moves to the first match after the cursor.
+    /// An empty pattern searches for the last pattern again.
+    fn search(&mut self, pattern: &str) {
+        if !pattern.is_empty() {
+            self.search_pattern = Some(pattern.to_string());
+        }
+        self.search_highlight = true;
+        self.search_step(true);
+    }
+
+    /// Moves the cursor to the next or previous match of the last search.
+    fn search_step(&mut self, forward: bool) {
+        let pattern = match &self.search_pattern {
+            None => {
+                let message = Message::SetMessage("No previous search pattern".to_string(), MessageKind::Error);
+                self.sender.send(message).expect("Failed to send message");
+                return;
+            },
+            Some(pattern) => pattern.clone(),
+        };
+
+        let compiled = match Pattern::compile(&pattern) {
+            Err(message) => {
+                self.sender.send(Message::SetMessage(message, MessageKind::Error)).expect("Failed to send message");
+                return;
+            },
+            Ok(compiled) => compiled,
+        };
+        // The buffer might have changed since the last search
+        self.search_matches = compiled.find_all(&self.contents.to_string());
+
+        let wrapscan = self.settings.borrow().editor_settings.wrapscan;
+        let position = self.cursor.borrow().get_cursor();
+
+        match next_match(&self.search_matches, position, forward, wrapscan) {
+            None if self.search_matches.is_empty() => {
+                let message = Message::SetMessage(format!("Pattern not found: {}", pattern), MessageKind::Error);
+                self.sender.send(message).expect("Failed to send message");
+            },
+            None => {
+                let end = if forward { "BOTTOM" } else { "TOP" };
+                let message = Message::SetMessage(format!("search hit {} without match for: {}", end, pattern), MessageKind::Error);
+                self.sender.send(message).expect("Failed to send message");
+            },
+            Some((found, wrapped)) => {
+                if wrapped {
+                    let message = if forward {
+                        "search hit BOTTOM, continuing at TOP"
+                    }
+                    else {
+                        "search hit TOP, continuing at BOTTOM"
+                    };
+                    self.sender.send(Message::SetMessage(message.to_string(), MessageKind::Warning)).expect("Failed to send message");
+                }
+
+                self.jump_table.add(*self.cursor.borrow());
+                let cursor = self.cursor.clone();
+                cursor.borrow_mut().jump_to(found.col, found.line, self);
+            },
+        }
+    }
+
+    /// Moves the cursor to the first character of the line that isn't blank.
+    /// With `toggle` a cursor that is already there goes to the start of the line instead.
+    fn move_to_first_non_blank(&mut self, toggle: bool) {
+        let (x, y) = self.cursor.borrow().get_cursor();
+        let line = match self.contents.get_row(y, 0, usize::MAX) {
+            None => return,
+            Some(line) => line.to_string(),
+        };
+        let first = indent::leading_whitespace(&line).chars().count();
+
+        let x = if toggle && x == first { 0 } else { first };
+
+        let cursor = self.cursor.clone();
+        cursor.borrow_mut().jump_to(x, y, self);
+    }
+
+    /// Moves the cursor to the start of the next or previous word or to the end of the next one, like `w`, `b` and `e`.
+    /// The word can be on the lines after or before the cursor's.
+    fn move_by_word(&mut self, motion: &str) {
+        let (x, y) = self.cursor.borrow().get_cursor();
+        let lines = self.contents.get_line_count();
+
+        let found = match motion {
+            "word_start_backward" => word::previous_word(&self.cursor_line(y), x)
+                .map(|col| (col, y))
+                .or_else(|| (0..y).rev().find_map(|row| word::previous_word(&self.cursor_line(row), usize::MAX).map(|col| (col, row)))),
+            "word_end_forward" => word::next_word_end(&self.cursor_line(y), Some(x))
+                .map(|col| (col, y))
+                .or_else(|| (y + 1..lines).find_map(|row| word::next_word_end(&self.cursor_line(row), None).map(|col| (col, row)))),
+            _ => word::next_word(&self.cursor_line(y), x)
+                .map(|col| (col, y))
+                .or_else(|| (y + 1..lines).find_map(|row| word::first_word(&self.cursor_line(row)).map(|col| (col, row)))),
+        };
+
+        if let Some((x, y)) = found {
+            let cursor = self.cursor.clone();
+            cursor.borrow_mut().jump_to(x, y, self);
+        }
+    }
+
+    /// Indents or dedents every line from `first` to `last` by a level as one undo step.
+    /// Blank lines are left alone and the cursor goes to the first non-blank of the first line.
+    fn shift_lines(&mut self, first: usize, last: usize, right: bool) {
+        let (use_spaces, tab_size) = {
+            let settings = self.settings.borrow();
+            (settings.editor_settings.use_spaces, settings.editor_settings.tab_size)
+        };
+        let last = last.min(self.contents.get_line_count().saturating_sub(1));
+
+        self.contents.begin_group();
+        for row in first..=last {
+            let line = self.cursor_line(row);
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let old = indent::leading_whitespace(&line);
+            let new = if right {
+                indent::indent(old, use_spaces, tab_size)
+            }
+            else {
+                indent::dedent(old, use_spaces, tab_size)
+            };
+
+            if new != old {
+                self.set_indent(row, &new);
+            }
+        }
+        self.contents.end_group();
+
+        let cursor = self.cursor.clone();
+        cursor.borrow_mut().jump_to(0, first, self);
+        self.move_to_first_non_blank(false);
+    }
+
+    /// Gives every line from `first` to `last` the indentation of the closest line above it with text, as one undo step.
+    fn reindent_lines(&mut self, first: usize, last: usize) {
+        let (use_spaces, tab_size) = {
+            let settings = self.settings.borrow();
+            (settings.editor_settings.use_spaces, settings.editor_settings.tab_size)
+        };
+        let last = last.min(self.contents.get_line_count().saturating_sub(1));
+
+        self.contents.begin_group();
+        for row in first..=last {
+            let line = self.cursor_line(row);
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            // Plain text has no blocks so only the indentation is carried down
+            let new = match (0..row).rev().map(|above| self.cursor_line(above)).find(|above| !above.trim().is_empty()) {
+                None => String::new(),
+                Some(above) => indent::line_indent(&above, false, false, use_spaces, tab_size),
+            };
+
+            if new != indent::leading_whitespace(&line) {
+                self.set_indent(row, &new);
+            }
+        }
+        self.contents.end_group();
+
+        let cursor = self.cursor.clone();
+        cursor.borrow_mut().jump_to(0, first, self);
+        self.move_to_first_non_blank(false);
+    }
+
+    /// Finds the bracket that matches the one at a position.
+    fn find_matching_bracket(&self, position: (usize, usize)) -> Option<(usize, usize)> {
+        brackets::find_match(&self.contents, position)
+    }
+
+    /// The text of a line without its line break.
+    fn cursor_line(&self, row: usize) -> String {
+        match self.contents.get_row(row, 0, usize::MAX) {
+            None => String::new(),
+            Some(line) => line.to_string().trim_end_matches(['\n', '\r']).to_string(),
+        }
+    }
+
+    /// Lets the user know that the file had bytes that didn't fit the encoding.
+    fn report_decode_errors(&self, had_errors: bool) {
+        if had_errors {
+            let message = format!("Some characters could not be read as {}", self.encoding.name());
+            self.sender.send(Message::SetStatus(message)).expect("Failed to send message");
+        }
+    }
+
+    /// Loads the undo history saved with the file when `persistent_undo` is on.
+    fn load_undo_history(&mut self, filename: &Path) {
+        let settings = self.settings.borrow();
+        if settings.editor_settings.persistent_undo {
+            undo::load_history(settings.undo_dir.as_deref(), filename, &mut self.contents);
+        }
+    }
+
+    /// Writes the buffer to its file.
+    /// Forcing it writes over read only files, like `:w!`.
+    fn write_buffer(&mut self, force: bool) -> io::Result<()> {
+        if let Some(file_name) = &self.file_name {
+            let text = self.settings.borrow().editor_settings.prepare_save(self.contents.to_string());
+
+            let (bytes, had_errors) = self.encoding.encode(&text);
+
+            let options = SaveOptions::new(&self.settings.borrow(), force);
+            if let Some(warning) = save_file(file_name, &bytes, &options)? {
+                self.sender.send(Message::SetMessage(warning, MessageKind::Warning)).expect("Failed to send message");
+            }
+            self.changed = false;
+            self.disk_watch.update(file_name);
+
+            if had_errors {
+                let message = format!("Some characters could not be written as {}", self.encoding.name());
+                self.sender.send(Message::SetStatus(message)).expect("Failed to send message");
+            }
+
+            self.sender.send(Message::AddRecentFile(file_name.clone())).expect("Failed to send message");
+
+            let settings = self.settings.borrow();
+            if settings.editor_settings.persistent_undo {
+                if let Err(error) = undo::save_history(settings.undo_dir.as_deref(), file_name, &self.contents) {
+                    let message = format!("Failed to save undo history: {}", error);
+                    self.sender.send(Message::SetStatus(message)).expect("Failed to send message");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn report_save_error(&self, error: io::Error) {
+        let message = format!("Failed to save: {}", error);
+        self.sender.send(Message::SetMessage(message, MessageKind::Error)).expect("Failed to send message");
+    }
+
+    /// Whether another program wrote to the file since we read it, telling the user that saving needs a `!`.
+    fn changed_on_disk(&self) -> bool {
+        let changed = match &self.file_name {
+            None => false,
+            Some(file_name) => self.disk_watch.changed(file_name),
+        };
+        if changed {
+            let message = "The file changed on disk since it was read (add ! to override)".to_string();
+            self.sender.send(Message::SetMessage(message, MessageKind::Error)).expect("Failed to send message");
+        }
+        changed
+    }
+
+    /// Reloads the file when another program changed it and the buffer has nothing to lose.
+    /// Otherwise the user picks between the two versions.
+    fn check_disk(&mut self, container: &PaneContainer) {
+        if !matches!(self.waiting, Waiting::None) {
+            return;
+        }
+        let file_name = match &self.file_name {
+            None => return,
+            Some(file_name) => file_name.clone(),
+        };
+        if !self.disk_watch.poll(&file_name) {
+            return;
+        }
+
+        if self.changed {
+            self.ask_disk_change(container);
+        }
+        else {
+            self.run_command("e!", container);
+        }
+    }
+
+    fn ask_disk_change(&mut self, container: &PaneContainer) {
+        let (send, recv) = std::sync::mpsc::channel();
+        let (send2, recv2) = std::sync::mpsc::channel();
+
+        self.popup_channels = Some((send2, recv));
+
+        let mut buttons = Vec::new();
+        for (label, answer) in [("Reload", "reload"), ("Keep", "keep"), ("Diff", "diff")] {
+            let function: ButtonFunction = Box::new(move |_| answer.to_string());
+            buttons.push((label.to_string(), function));
+        }
+        let buttons = PromptType::Button(buttons, 1);
+
+        let prompt = vec!["File changed on disk".to_string()];
+        let width = 30;
+
+        let pane = PopUpPane::new_prompt(
+            self.settings.clone(),
+            prompt,
+            self.sender.clone(),
+            send,
+            recv2,
+            vec![buttons],
+            true
+        );
+        let pane = Rc::new(RefCell::new(pane));
+
+        let ((x1, y1), _) = container.get_corners();
+        let (x, y) = container.get_size();
+        let size = (width.min(x), 4.min(y));
+        let pos = ((x1 + x / 2).saturating_sub(size.0 / 2), (y1 + y / 2).saturating_sub(size.1 / 2));
+
+        let mut container = PaneContainer::new(container.get_size(), size, pane, self.settings.clone());
+        container.set_position(pos);
+        container.set_size(size);
+
+        self.sender.send(Message::CreatePopup(container, true)).expect("Failed to send message");
+        self.waiting = Waiting::DiskChange;
+    }
+
+    fn answer_disk_change(&mut self, answer: &str, container: &PaneContainer) {
+        match answer {
+            "reload" => self.run_command("e!", container),
+            "diff" => self.show_disk_diff(),
+            _ => {},
+        }
+    }
+
+    /// Shows how the file on disk differs from the buffer in a pane below.
+    fn show_disk_diff(&mut self) {
+        let file_name = match &self.file_name {
+            None => return,
+            Some(file_name) => file_name.clone(),
+        };
+        let disk = match FileEncoding::read(&file_name, Some(self.encoding.encoding)) {
+            Ok((disk, _, _)) => disk,
+            Err(error) => {
+                self.sender.send(Message::SetMessage(error.to_string(), MessageKind::Error)).expect("Failed to send message");
+                return;
+            },
+        };
+
+        let mut pane = PlainTextPane::new(self.settings.clone(), self.sender.clone());
+        let mut diff = Buffer::from(diff_lines(&self.contents.to_string(), &disk).join("\n"));
+        diff.set_settings(self.settings.clone());
+        *pane.borrow_mut_buffer() = diff;
+
+        self.sender.send(Message::HorizontalSplitWithPane(Rc::new(RefCell::new(pane)))).expect("Failed to send message");
+    }
+
+
+    /// Builds the row as the substitute being typed would leave it.
+    fn preview_row(&self, row: usize) -> Option<Vec<(char, bool)>> {
+        let substitute = self.substitute_preview.as_ref()?;
+        if !substitute.applies_to(row, self.cursor.borrow().get_cursor().1) {
+            return None;
+        }
+
+        let line = self.contents.get_row(row, 0, usize::MAX)?.to_string();
+        let line = line.trim_end_matches('\n');
+
+        if substitute.matches(line).is_empty() {
+            return None;
+        }
+        Some(substitute.preview(line))
+    }
 
     fn get_row(&self, row: usize, offset: usize, col: usize) -> Option<RopeSlice> {
 
         self.contents.get_row(row, offset, col)
     }
 
+    /// Runs a command like `sort` or `uniq` that rewrites whole lines.
+    fn run_line_command(&mut self, line_command: LineCommand) {
+        let text = self.contents.to_string();
+        let (range, replacement, removed) = match line_command.apply(&text) {
+            None => return,
+            Some(edit) => edit,
+        };
+
+        if text[range.clone()] != replacement {
+            self.set_changed(true);
+            self.contents.replace(range, replacement);
+        }
+
+        // The cursor's line might not be there anymore
+        let (x, y) = self.cursor.borrow().get_cursor();
+        let cursor = self.cursor.clone();
+        cursor.borrow_mut().jump_to(x, y, self);
+
+        if removed > 0 {
+            let message = format!("{} fewer lines", removed);
+            self.sender.send(Message::SetStatus(message)).expect("Failed to send message");
+        }
+    }
+
     pub fn borrow_buffer(&self) -> &Buffer {
         &self.contents
     }
@@ -191,6 +939,21 @@ impl PlainTextPane {
         self.contents.get_byte_offset(x, y)
     }
 
+    /// Shows the completions in a dropdown at the cursor, replacing any popup that is open.
+    fn show_completions(&mut self, completion: CompletionList, container: &PaneContainer) {
+        if let Some((send, _)) = self.popup_channels.take() {
+            let _ = send.send(PaneMessage::Close);
+        }
+
+        let cursor = self.cursor.borrow().get_real_cursor();
+        let (popup, channels) = completion_popup(&completion, cursor, false, container, &self.settings, &self.sender);
+        self.popup_channels = Some(channels);
+        self.completion = Some(completion);
+
+        self.sender.send(Message::CreatePopup(popup, true)).expect("Failed to send message");
+        self.waiting = Waiting::Completion;
+    }
+
     fn check_messages(&mut self, container: &PaneContainer) {
         match self.popup_channels.as_ref() {
             None => {},
@@ -211,12 +974,58 @@ impl PlainTextPane {
                                         let command = format!("set_jump {}", string);
                                         self.run_command(&command, container);
                                     },
-                                    Waiting::Completion => {},
+                                    Waiting::Completion => {
+                                        self.waiting = Waiting::None;
+                                        let command = format!("insert {}", string);
+                                        self.run_command(&command, container);
+                                    },
                                     Waiting::Goto => {},
+                                    Waiting::Spelling => {},
+                                    Waiting::Rename => {},
+                                    Waiting::CodeAction => {},
+                                    Waiting::DiskChange => {
+                                        self.waiting = Waiting::None;
+                                        self.answer_disk_change(&string, container);
+                                    },
+                                    Waiting::CompletionResponse | Waiting::GotoResponse |
+                                    Waiting::ReferencesResponse | Waiting::HoverResponse |
+                                    Waiting::RenameResponse | Waiting::FormattingResponse |
+                                    Waiting::CodeActionResponse => {},
+                                    Waiting::Search => {
+                                        self.waiting = Waiting::None;
+                                        // The jump starts from before the preview moved the cursor
+                                        self.end_search_preview();
+                                        self.search(&string);
+                                    },
+                                    Waiting::Substitute => {
+                                        self.waiting = Waiting::None;
+                                        self.answer_substitute(&string, container);
+                                    },
                                     Waiting::None => {
                                     },
                                 }
                             },
+                            PaneMessage::PreviewString(string) => {
+                                if let Waiting::Search = self.waiting {
+                                    self.preview_search(&string);
+                                }
+                            },
+                            PaneMessage::Cancel => {
+                                match self.waiting {
+                                    Waiting::DiskChange => self.waiting = Waiting::None,
+                                    Waiting::Search => {
+                                        self.waiting = Waiting::None;
+                                        self.end_search_preview();
+                                    },
+                                    Waiting::Substitute => {
+                                        self.waiting = Waiting::None;
+                                        if let Some(run) = self.substitute_run.take() {
+                                            self.finish_substitute(run);
+                                        }
+                                    },
+                                    _ => {},
+                                }
+                            },
                             PaneMessage::Close => self.run_command("q!", container),
                         }
                     },
@@ -243,13 +1052,18 @@ impl Pane for PlainTextPane {
     }
 
     fn draw_row(&self, mut index: usize, container: &PaneContainer, output: &mut TextRow) {
-        //let rows = container.get_size().1;
-        let cols = container.get_size().0;
+
+        let (mut cols, rows) = container.get_size();
+
+        // The resize math can leave a pane without any room to draw in
+        if cols == 0 || rows == 0 {
+            return;
+        }
 
 
         let ((x1, y1), _) = container.get_corners();
 
-        if self.settings.borrow().editor_settings.border {
+        if container.has_border() {
 
             let color_settings = &self.settings.borrow().colors.ui;
             
@@ -288,7 +1102,7 @@ impl Pane for PlainTextPane {
                     output.push(Some(Some(StyledChar::new(c, color_settings.clone()))));
                 }
                 
-                //cols = cols.saturating_sub(1);
+                cols = cols.saturating_sub(1);
             }
         }
 
@@ -312,6 +1126,7 @@ impl Pane for PlainTextPane {
                     places *= 10;
                     num_width += 1;
                 }
+                num_width = num_width.min(cols);
 
                 if real_row + 1 <= number_of_lines {
                     if !self.cursor.borrow().get_scrolled() {
@@ -324,7 +1139,7 @@ impl Pane for PlainTextPane {
 
                         let string = format!("{:width$}", real_row + 1, width = num_width);
 
-                        for c in string.chars() {
+                        for c in string.chars().take(num_width) {
                             output.push(Some(Some(StyledChar::new(c, color_settings.clone()))));
                         }
                     }
@@ -339,10 +1154,11 @@ impl Pane for PlainTextPane {
                     places *= 10;
                     num_width += 1;
                 }
+                num_width = num_width.min(cols);
                 if real_row == self.cursor.borrow().get_cursor().1 && real_row + 1 <= number_of_lines {
                     let string = format!("{:<width$}", real_row + 1 , width = num_width);
 
-                    for c in string.chars() {
+                    for c in string.chars().take(num_width) {
                         output.push(Some(Some(StyledChar::new(c, color_settings.clone()))));
                     }
                 }
@@ -351,7 +1167,7 @@ impl Pane for PlainTextPane {
                                             ((real_row) as isize - (self.cursor.borrow().get_cursor().1 as isize)).abs() as usize,
                                             width = num_width);
 
-                    for c in string.chars() {
+                    for c in string.chars().take(num_width) {
                         output.push(Some(Some(StyledChar::new(c, color_settings.clone()))));
                     }
                 }
@@ -361,6 +1177,17 @@ impl Pane for PlainTextPane {
 
         self.cursor.borrow_mut().number_line_size = num_width;
 
+        if let Some(row) = self.preview_row(real_row) {
+            let settings = self.settings.borrow();
+            draw_preview_row(&row,
+                             col_offset,
+                             cols.saturating_sub(num_width),
+                             settings.editor_settings.tab_size,
+                             (&settings.colors.pane, &settings.colors.preview),
+                             output);
+            return;
+        }
+
         let color_settings = &self.settings.borrow().colors.pane;
 
 
@@ -373,9 +1200,11 @@ impl Pane for PlainTextPane {
             return;
         }
 
+        let text_start = output.index;
+
         if let Some(row) = self.get_row(real_row, col_offset, cols) {
             let mut count = 0;
-            row.chars().for_each(|c| if count != (cols - num_width) {
+            row.chars().for_each(|c| if count < (cols - num_width) {
                 match c {
                     '\t' => {
 
@@ -427,39 +1256,148 @@ impl Pane for PlainTextPane {
                 output.push(Some(Some(StyledChar::new(c, color_settings.clone()))));
             }
         }
+
+        if real_row < number_of_lines && self.settings.borrow().editor_settings.indentcheck {
+            if let Some(line) = self.contents.get_row(real_row, 0, usize::MAX) {
+                draw_bad_indent(&self.settings.borrow(), &line.to_string(), text_start, col_offset, cols.saturating_sub(num_width), output);
+            }
+        }
+
+        let editor_settings = self.settings.borrow().editor_settings;
+        let is_cursor_line = real_row == self.cursor.borrow().get_cursor().1;
+        if real_row < number_of_lines && editor_settings.show_whitespace {
+            if let Some(line) = self.contents.get_row(real_row, 0, usize::MAX) {
+                draw_whitespace(&self.settings.borrow(), &line.to_string(), text_start, col_offset, cols.saturating_sub(num_width), output);
+            }
+        }
+        let list = editor_settings.list || editor_settings.show_whitespace;
+        if real_row < number_of_lines && list && editor_settings.cursorlineopt.shows_trailing(is_cursor_line) {
+            if let Some(line) = self.contents.get_row(real_row, 0, usize::MAX) {
+                draw_trailing_whitespace(&self.settings.borrow(), &line.to_string(), text_start, col_offset, cols.saturating_sub(num_width), output);
+            }
+        }
+
+        if real_row < number_of_lines && editor_settings.cursorline && is_cursor_line {
+            draw_cursor_line(&self.settings.borrow(), text_start, cols.saturating_sub(num_width), output);
+        }
+
+        if real_row < number_of_lines {
+            draw_color_column(&self.settings.borrow(), text_start, col_offset, cols.saturating_sub(num_width), output);
+        }
+
+        let pattern = self.search_preview.as_ref()
+            .or(self.search_pattern.as_ref().filter(|_| self.search_highlight));
+        if let Some(pattern) = pattern {
+            if let Some(line) = self.contents.get_row(real_row, 0, usize::MAX) {
+                draw_search_matches(&self.settings.borrow(), pattern, &line.to_string(), text_start, col_offset, cols.saturating_sub(num_width), output);
+            }
+        }
+
+        if let Some(selection) = self.get_selection() {
+            if let Some(line) = self.contents.get_row(real_row, 0, usize::MAX) {
+                let draw = if self.selection_kind == VisualKind::Block { draw_block_selection } else { draw_selection };
+                draw(&self.settings.borrow(), selection, (real_row, &line.to_string()), text_start, col_offset, cols.saturating_sub(num_width), output);
+            }
+        }
+
+        if let Some(brackets) = self.bracket_match.and_then(|bracket_match| bracket_match.brackets()) {
+            if let Some(line) = self.contents.get_row(real_row, 0, usize::MAX) {
+                draw_matching_brackets(&self.settings.borrow(), brackets, (real_row, &line.to_string()), text_start, col_offset, cols.saturating_sub(num_width), output);
+            }
+        }
     }
 
     fn refresh(&mut self, container: &mut PaneContainer) {
-        self.mode.borrow_mut().refresh();
+        let mode = self.mode.clone();
+        mode.borrow_mut().refresh(self, container);
         self.check_messages(container);
+        self.check_disk(container);
+        self.which_key.refresh(&*self.mode.borrow(), &self.settings, &self.sender, container);
+
+        let cursor = self.cursor.borrow().get_cursor();
+        let revision = self.contents.revision();
+        self.edit_timer.update(revision, self.changed, Instant::now());
+        if !self.bracket_match.is_some_and(|bracket_match| bracket_match.is_current(cursor, revision)) {
+            let found = self.find_matching_bracket(cursor);
+            self.bracket_match = Some(BracketMatch { cursor, revision, found });
+        }
     }
 
-
-    fn save_buffer(&mut self) -> io::Result<()> {
-        if let Some(file_name) = &self.file_name {
-            let mut file = std::fs::File::create(file_name)?;
-            file.write_all(self.contents.to_string().as_bytes())?;
-        }
-        Ok(())
+
+    fn save_buffer(&mut self) -> io::Result<()> {
+        self.write_buffer(false)
     }
 
     fn open_file(&mut self, filename: &PathBuf) -> io::Result<()> {
-        let file = std::fs::read_to_string(filename)?;
+        let encoding = self.settings.borrow().editor_settings.fileencoding;
+        let (file, encoding, had_errors) = FileEncoding::read(filename, encoding)?;
+        self.encoding = encoding;
         self.contents = Buffer::from(file);
         self.file_name = Some(PathBuf::from(filename));
+        self.disk_watch.update(filename);
+        self.load_undo_history(filename);
+        self.report_decode_errors(had_errors);
+        Ok(())
+    }
+
+    fn reload_with_encoding(&mut self, encoding: &'static Encoding) -> io::Result<()> {
+        let file_name = match &self.file_name {
+            None => return Ok(()),
+            Some(file_name) => file_name.clone(),
+        };
+
+        let (file, encoding, had_errors) = FileEncoding::read(&file_name, Some(encoding))?;
+        self.encoding = encoding;
+        self.disk_watch.update(&file_name);
+
+        let end = self.contents.get_byte_count();
+        self.contents.replace(0..end, file);
+        self.set_changed(false);
+
+        let (x, y) = self.cursor.borrow().get_cursor();
+        let cursor = self.cursor.clone();
+        cursor.borrow_mut().jump_to(x, y, self);
+
+        self.report_decode_errors(had_errors);
         Ok(())
     }
 
+    fn apply_text_edits(&mut self, edits: &[TextEdit]) {
+        if edits.is_empty() {
+            return;
+        }
+        self.contents.begin_group();
+        for edit in edits_from_bottom(edits) {
+            let (start, end) = edit.get_range();
+            self.replace_text_at(start, end, &edit.newText);
+        }
+        self.contents.end_group();
+
+        // The edits might have removed the text the cursor was on
+        let (x, y) = self.cursor.borrow().get_cursor();
+        let cursor = self.cursor.clone();
+        cursor.borrow_mut().jump_to(x, y, self);
+    }
+
     fn process_keypress(&mut self, key: KeyEvent, container: &mut PaneContainer) -> io::Result<bool> {
         let mode = self.mode.clone();
+        // A binding that timed out runs first so it doesn't end up in the recorded change
+        mode.borrow_mut().refresh(self, container);
+        let mode = self.mode.clone();
+        self.change_recorder.before_key(key, &*mode.borrow(), self.contents.edit_count());
+
         let result = mode.borrow_mut().process_keypress(key, self, container);
+
+        let mode = self.mode.clone();
+        self.change_recorder.after_key(&*mode.borrow(), self.contents.edit_count());
+        self.which_key.keypress();
         result
     }
 
     fn scroll_cursor(&mut self, container: &PaneContainer) {
         let cursor = self.cursor.clone();
 
-        cursor.borrow_mut().scroll(container);
+        cursor.borrow_mut().scroll(self, container);
         
     }
 
@@ -469,38 +1407,280 @@ impl Pane for PlainTextPane {
     }
 
     fn run_command(&mut self, command: &str, container: &PaneContainer) {
+        if let Some(substitute) = Substitute::parse(command.trim()) {
+            self.start_substitute(substitute, container);
+            return;
+        }
+
+        if let Some(line_command) = LineCommand::parse(command) {
+            self.run_line_command(line_command);
+            return;
+        }
+
         let mut command_args = command.split_whitespace();
         let command = command_args.next().unwrap_or("");
         match command {
             "q" => {
                 if self.changed {
-                } else {
-                    self.sender.send(Message::ClosePane(false, None)).unwrap();
+                    let message = "No write since last change (add ! to override)".to_string();
+                    self.sender.send(Message::SetMessage(message, MessageKind::Error)).expect("Failed to send message");
+                    return;
                 }
+                self.sender.send(Message::ClosePane(false, None)).unwrap();
             },
             "w" => {
+                if command_args.clone().next().is_none() && self.changed_on_disk() {
+                    return;
+                }
                 if let Some(file_name) = command_args.next() {
                     self.file_name = Some(PathBuf::from(file_name));
                 }
 
-                self.save_buffer().expect("Failed to save file");
-                self.contents.add_new_rope();
+                if let Err(error) = self.write_buffer(false) {
+                    self.report_save_error(error);
+                }
             },
             "w!" => {
                 if let Some(file_name) = command_args.next() {
                     self.file_name = Some(PathBuf::from(file_name));
                 }
 
-                self.save_buffer().expect("Failed to save file");
-                self.contents.add_new_rope();
+                if let Err(error) = self.write_buffer(true) {
+                    self.report_save_error(error);
+                }
             },
             "wq" => {
-                self.save_buffer().expect("Failed to save file");
+                if self.changed_on_disk() {
+                    return;
+                }
+                if let Err(error) = self.write_buffer(false) {
+                    self.report_save_error(error);
+                    return;
+                }
                 self.sender.send(Message::ClosePane(false, None)).unwrap();
             },
+            "file_info" => {
+                let (x, y) = self.cursor.borrow().get_cursor();
+                let offset = self.contents.get_byte_offset(x, y).unwrap_or(self.contents.get_byte_count());
+
+                let message = format!("{} lines, {} chars, {} bytes, byte {}, {}",
+                                      self.contents.get_line_count(),
+                                      self.contents.get_char_count(),
+                                      self.contents.get_byte_count(),
+                                      offset,
+                                      self.encoding.name());
+
+                self.sender.send(Message::SetStatus(message)).expect("Failed to send message");
+            },
+            "local_definition" | "local_declaration" => {
+                let (x, y) = self.cursor.borrow().get_cursor();
+                let text = self.contents.to_string();
+
+                let word = text.lines().nth(y).and_then(|line| definition::word_at(line, x));
+                let target = word.and_then(|(start, word)| {
+                    let candidates = definition::find_candidates(&text, &word);
+                    definition::pick_candidate(candidates, (start, y), command == "local_declaration")
+                });
+
+                match target {
+                    None => {
+                        self.sender.send(Message::SetStatus("Definition not found".to_string())).expect("Failed to send message");
+                    },
+                    Some((x, y)) => {
+                        self.jump_table.add(*self.cursor.borrow());
+                        self.cursor.borrow_mut().jump_to(x, y, self);
+                    },
+                }
+            },
+            "record_macro" => {
+                self.sender.send(Message::RecordMacro).expect("Failed to send message");
+            },
+            "play_macro" => {
+                let count = command_args.next()
+                    .and_then(|count| count.parse::<usize>().ok())
+                    .unwrap_or(1);
+
+                self.sender.send(Message::PlayMacro(count)).expect("Failed to send message");
+            },
+            "fixindent" => {
+                let (use_spaces, tab_size) = {
+                    let settings = self.settings.borrow();
+                    (settings.editor_settings.use_spaces, settings.editor_settings.tab_size)
+                };
+                let edits = indent::fix_indent(&self.contents.to_string(), use_spaces, tab_size);
+
+                let message = format!("Fixed the indentation of {} lines", edits.len());
+                if !edits.is_empty() {
+                    self.set_changed(true);
+                    self.contents.replace_chain(edits.into_boxed_slice());
+                }
+
+                self.sender.send(Message::SetStatus(message)).expect("Failed to send message");
+            },
+            "registers" | "reg" => {
+                self.sender.send(Message::RequestRegisters).expect("Failed to send message");
+            },
+            "recent" => {
+                self.sender.send(Message::RequestRecentFiles).expect("Failed to send message");
+            },
+            "find_file" => {
+                self.sender.send(Message::FindFile).expect("Failed to send message");
+            },
+            "ls" | "buffers" => {
+                self.sender.send(Message::ListBuffers).expect("Failed to send message");
+            },
+            "b" | "buffer" => {
+                let query = command_args.collect::<Vec<&str>>().join(" ");
+                if query.is_empty() {
+                    self.sender.send(Message::ListBuffers).expect("Failed to send message");
+                }
+                else {
+                    self.sender.send(Message::SwitchBuffer(query)).expect("Failed to send message");
+                }
+            },
+            "bd" | "bdelete" | "bd!" | "bdelete!" => {
+                let query = command_args.collect::<Vec<&str>>().join(" ");
+                let query = if query.is_empty() { None } else { Some(query) };
+                self.sender.send(Message::DeleteBuffer(query, command.ends_with('!'))).expect("Failed to send message");
+            },
+            "alternate_buffer" => {
+                self.sender.send(Message::AlternateBuffer).expect("Failed to send message");
+            },
+            "completion" => {
+                let (x, y) = self.cursor.borrow().get_cursor();
+                let hidden = self.settings.borrow().editor_settings.show_hidden_files;
+                match path_completions(&self.cursor_line(y), (x, y), hidden) {
+                    None => self.sender.send(Message::CompleteWords).expect("Failed to send message"),
+                    Some(completion) if completion.items.is_empty() => {
+                        self.sender.send(Message::SetMessage("No files to complete".to_string(), MessageKind::Info))
+                            .expect("Failed to send message");
+                    },
+                    Some(completion) => self.show_completions(completion, container),
+                }
+            },
+            "insert" => {
+                let completion = command_args.next()
+                    .and_then(|index| index.parse::<usize>().ok())
+                    .and_then(|index| self.completion.as_ref()?.get_completion(index).cloned());
+                if let Some(completion) = completion {
+                    let (x, y) = self.cursor.borrow().get_cursor();
+                    let (start, end, text) = completion_edit(&completion, &self.cursor_line(y), (x, y));
+                    if let Some(start_byte) = self.contents.get_byte_offset(start.0, start.1) {
+                        self.replace_text_at(start, end, &text);
+                        let (x, y) = self.contents.position_of_byte(start_byte + text.len());
+                        let cursor = self.cursor.clone();
+                        cursor.borrow_mut().jump_to(x, y, self);
+                    }
+                }
+                self.completion = None;
+            },
+            "reopen" => {
+                self.sender.send(Message::Reopen).expect("Failed to send message");
+            },
+            "grep" => {
+                let pattern = command_args.collect::<Vec<&str>>().join(" ");
+                if pattern.is_empty() {
+                    self.sender.send(Message::SetStatus("Nothing to search for".to_string())).expect("Failed to send message");
+                }
+                else {
+                    self.sender.send(Message::Grep(pattern)).expect("Failed to send message");
+                }
+            },
+            "grep!" => {
+                self.sender.send(Message::CancelGrep).expect("Failed to send message");
+            },
+            "cn" | "cnext" => {
+                self.sender.send(Message::NextQuickFix).expect("Failed to send message");
+            },
+            "cp" | "cprevious" => {
+                self.sender.send(Message::PreviousQuickFix).expect("Failed to send message");
+            },
+            "checkhealth" => {
+                self.sender.send(Message::CheckHealth).expect("Failed to send message");
+            },
+            "lsp" => {
+                // A plain text pane has no language to fall back on
+                let message = match (command_args.next().unwrap_or("status"), command_args.next()) {
+                    ("restart", Some(lang)) => Message::RestartLsp(lang.to_string()),
+                    ("stop", Some(lang)) => Message::StopLsp(lang.to_string()),
+                    ("restart" | "stop", None) => Message::SetStatus("No language given".to_string()),
+                    ("status", _) => Message::LspStatus,
+                    ("log", _) => Message::LspLog,
+                    (action, _) => Message::SetStatus(format!("Unknown lsp command: {}", action)),
+                };
+                self.sender.send(message).expect("Failed to send message");
+            },
             "q!" => {
                 self.sender.send(Message::ClosePane(false, None)).unwrap();
             },
+            "set" => {
+                let mut settings = self.settings.borrow_mut();
+                for option in command_args {
+                    match option {
+                        "scrollbind" | "scb" => {
+                            self.sender.send(Message::ScrollBind(true)).expect("Failed to send message");
+                        },
+                        "noscrollbind" | "noscb" => {
+                            self.sender.send(Message::ScrollBind(false)).expect("Failed to send message");
+                        },
+                        option => {
+                            if let Err(error) = settings.editor_settings.set_option(option) {
+                                self.sender.send(Message::SetMessage(error, MessageKind::Error)).expect("Failed to send message");
+                            }
+                        },
+                    }
+                }
+                self.sender.send(Message::ForceRedraw).expect("Failed to send message");
+            },
+            "setlocal" => {
+                for option in command_args {
+                    let border = match option {
+                        "border" => Some(true),
+                        "noborder" => Some(false),
+                        "border&" => None,
+                        option => {
+                            let message = Message::SetMessage(format!("Unknown local option: {}", option), MessageKind::Error);
+                            self.sender.send(message).expect("Failed to send message");
+                            continue;
+                        },
+                    };
+                    self.sender.send(Message::PaneBorder(border)).expect("Failed to send message");
+                }
+            },
+            "first_non_blank" => {
+                self.move_to_first_non_blank(false);
+            },
+            "word_start_forward" | "word_start_backward" | "word_end_forward" => {
+                let count = command_args.next()
+                    .and_then(|count| count.parse::<usize>().ok())
+                    .unwrap_or(1)
+                    .max(1);
+                for _ in 0..count {
+                    self.move_by_word(command);
+                }
+            },
+            "reselect" => {
+                if let Some(((anchor_x, anchor_y), (x, y), kind)) = self.last_selection {
+                    // Edits since then can leave the ends past the buffer
+                    let cursor = self.cursor.clone();
+                    cursor.borrow_mut().jump_to(anchor_x, anchor_y, self);
+                    self.selection_anchor = None;
+                    self.change_mode(kind.mode_name());
+                    cursor.borrow_mut().jump_to(x, y, self);
+                }
+            },
+            "smart_home" => {
+                self.move_to_first_non_blank(true);
+            },
+            "line_center" | "line_top" | "line_bottom" => {
+                let place = match command {
+                    "line_top" => ScreenLine::Top,
+                    "line_bottom" => ScreenLine::Bottom,
+                    _ => ScreenLine::Center,
+                };
+                let (_, rows) = container.get_text_size();
+                self.cursor.borrow_mut().place_line(place, rows, self);
+            },
             "move" => {
                 let direction = command_args.next();
                 let direction = match direction {
@@ -532,7 +1712,6 @@ impl Pane for PlainTextPane {
             "mode" => {
                 let mode = command_args.next().unwrap_or("Normal");
                 self.change_mode(mode);
-                self.contents.add_new_rope();
             },
             "jump" => {
                 if let Some(jump) = command_args.next() {
@@ -582,36 +1761,89 @@ impl Pane for PlainTextPane {
             },
             "horizontal_split" => {
                 self.sender.send(Message::HorizontalSplit).expect("Failed to send message");
-                self.contents.add_new_rope();
             },
             "vertical_split" => {
                 self.sender.send(Message::VerticalSplit).expect("Failed to send message");
-                self.contents.add_new_rope();
+            },
+            "map" | "noremap" | "unmap" | "maps" => {
+                let args = command_args.collect::<Vec<_>>();
+                let message = match map_message(command, &args) {
+                    Ok(message) => message,
+                    Err(usage) => Message::SetMessage(usage, MessageKind::Error),
+                };
+                self.sender.send(message).expect("Failed to send message");
+            },
+            "reload_config" => {
+                self.sender.send(Message::ReloadConfig).expect("Failed to send message");
+            },
+            "qa" => {
+                self.sender.send(Message::QuitAll).expect("Failed to send message");
             },
             "qa!" => {
                 self.sender.send(Message::ForceQuitAll).expect("Failed to send message");
             },
+            "rotate_panes" => {
+                self.sender.send(Message::RotatePanes).expect("Failed to send message");
+            },
+            "swap_pane" => {
+                self.sender.send(Message::SwapPane).expect("Failed to send message");
+            },
             "pane_up" => {
                 self.sender.send(Message::PaneUp).expect("Failed to send message");
-                self.contents.add_new_rope();
             },
             "pane_down" => {
                 self.sender.send(Message::PaneDown).expect("Failed to send message");
-                self.contents.add_new_rope();
             },
             "pane_left" => {
                 self.sender.send(Message::PaneLeft).expect("Failed to send message");
-                self.contents.add_new_rope();
             },
             "pane_right" => {
                 self.sender.send(Message::PaneRight).expect("Failed to send message");
-                self.contents.add_new_rope();
+            },
+            "e!" => {
+                if self.file_name.is_none() {
+                    self.sender.send(Message::SetMessage("No file name".to_string(), MessageKind::Error)).expect("Failed to send message");
+                    return;
+                }
+                let encoding = self.encoding.encoding;
+                match self.reload_with_encoding(encoding) {
+                    Ok(()) => self.sender.send(Message::SetStatus("Reloaded from disk".to_string())).expect("Failed to send message"),
+                    Err(error) => self.sender.send(Message::SetMessage(error.to_string(), MessageKind::Error)).expect("Failed to send message"),
+                }
             },
             "e" => {
-                if let Some(file_name) = command_args.next() {
-                    self.sender.send(Message::OpenFile(file_name.to_string(), None)).expect("Failed to send message");
+                let mut encoding = None;
+                let mut file_name = None;
+                for arg in command_args {
+                    match arg.strip_prefix("++enc=") {
+                        Some(label) => encoding = Some(label),
+                        None => file_name = Some(arg),
+                    }
+                }
+
+                let encoding = match encoding.map(parse_encoding) {
+                    None => None,
+                    Some(Ok(encoding)) => Some(encoding),
+                    Some(Err(error)) => {
+                        self.sender.send(Message::SetStatus(error)).expect("Failed to send message");
+                        return;
+                    },
+                };
+
+                match (file_name, encoding) {
+                    (Some(file_name), None) => {
+                        self.sender.send(Message::OpenFile(file_name.to_string(), None)).expect("Failed to send message");
+                    },
+                    (Some(file_name), Some(encoding)) => {
+                        self.sender.send(Message::OpenFileWithEncoding(file_name.to_string(), encoding)).expect("Failed to send message");
+                    },
+                    (None, Some(encoding)) => {
+                        if let Err(error) = self.reload_with_encoding(encoding) {
+                            self.sender.send(Message::SetStatus(error.to_string())).expect("Failed to send message");
+                        }
+                    },
+                    (None, None) => {},
                 }
-                self.contents.add_new_rope();
             },
             "prompt_jump" => {
                 let (send, recv) = std::sync::mpsc::channel();
@@ -654,8 +1886,6 @@ impl Pane for PlainTextPane {
 
                 self.sender.send(Message::CreatePopup(container, true)).expect("Failed to send message");
                 self.waiting = Waiting::JumpTarget;
-
-                self.contents.add_new_rope();
             },
             "prompt_set_jump" => {
                 let (send, recv) = std::sync::mpsc::channel();
@@ -698,21 +1928,96 @@ impl Pane for PlainTextPane {
 
                 self.sender.send(Message::CreatePopup(container, true)).expect("Failed to send message");
                 self.waiting = Waiting::JumpPosition;
+            },
+            "jump_match" => {
+                let (x, y) = self.cursor.borrow().get_cursor();
+                let found = brackets::bracket_from(&self.contents, (x, y))
+                    .and_then(|x| self.find_matching_bracket((x, y)));
+
+                if let Some((x, y)) = found {
+                    // Far jumps can be gone back from like other jumps
+                    let current = *self.cursor.borrow();
+                    if y.abs_diff(current.get_cursor().1) > container.get_text_size().1 {
+                        self.jump_table.add(current);
+                    }
+
+                    let cursor = self.cursor.clone();
+                    cursor.borrow_mut().jump_to(x, y, self);
+                }
+            },
+            "shift_lines" => {
+                let right = command_args.next() == Some("right");
+                let (_, y) = self.cursor.borrow().get_cursor();
+
+                let (first, last) = match command_args.next() {
+                    Some("selection") => match self.get_selection() {
+                        None => return,
+                        Some((start, end)) => (start.1, end.1),
+                    },
+                    count => {
+                        let count = count.and_then(|count| count.parse::<usize>().ok()).unwrap_or(1).max(1);
+                        (y, y + count - 1)
+                    },
+                };
 
-                self.contents.add_new_rope();
+                self.shift_lines(first, last, right);
+            },
+            "trim_whitespace" => {
+                // Only reached with `selection`, the whole buffer is a line command
+                if let Some((start, end)) = self.get_selection() {
+                    self.run_line_command(LineCommand {
+                        range: Some((start.1 + 1, end.1 + 1)),
+                        operation: LineOperation::TrimWhitespace,
+                    });
+                }
+            },
+            "format_selection" => {
+                if let Some((start, end)) = self.get_selection() {
+                    self.reindent_lines(start.1, end.1);
+                }
+            },
+            "open_line" => {
+                let (_, y) = self.cursor.borrow().get_cursor();
+                let line = self.cursor_line(y);
+                let cursor = self.cursor.clone();
+
+                match command_args.next() {
+                    Some("above") => {
+                        // The new line gets the indentation of the line it goes above
+                        let indent = indent::leading_whitespace(&line).to_string();
+                        cursor.borrow_mut().jump_to(0, y, self);
+                        self.insert_str(&format!("{}\n", indent));
+                        cursor.borrow_mut().jump_to(indent.chars().count(), y, self);
+                    },
+                    _ => {
+                        cursor.borrow_mut().jump_to(line.chars().count(), y, self);
+                        self.insert_newline();
+                    },
+                }
             },
             "undo" => {
-                self.contents.undo();
+                let change = self.contents.undo();
 
                 self.cursor.borrow_mut().number_line_size = self.contents.get_line_count();
 
-                self.cursor.borrow_mut().set_cursor(CursorMove::Nothing, CursorMove::Amount(self.contents.get_line_count()), self, (0,0));
+                // The cursor goes to where the undone change was
+                if let Some(change) = change {
+                    let (x, y) = change.start;
+                    let cursor = self.cursor.clone();
+                    cursor.borrow_mut().jump_to(x, y, self);
+                }
                 
             },
             "redo" => {
-                self.contents.redo();
+                let change = self.contents.redo();
                 self.cursor.borrow_mut().number_line_size = self.contents.get_line_count();
 
+                if let Some(change) = change {
+                    let (x, y) = change.new_end;
+                    let cursor = self.cursor.clone();
+                    cursor.borrow_mut().jump_to(x, y, self);
+                }
+
             },
             "change_tab" => {
                 if let Some(tab) = command_args.next() {
@@ -738,37 +2043,176 @@ impl Pane for PlainTextPane {
             "open_tab_with_pane" => {
                 self.sender.send(Message::OpenNewTabWithPane).expect("Failed to send message");
             },
-            "paste" => {
-                
-                if let Some(arg) = command_args.next() {
-                    if let Ok(number) = arg.parse::<usize>() {
-                        let message = Message::Paste(RegisterType::Number(number));
+            "start_search" => {
+                let (send, recv) = std::sync::mpsc::channel();
+                let (send2, recv2) = std::sync::mpsc::channel();
 
-                        self.sender.send(message).expect("Failed to send message");
-                    } else {
-                        let message = Message::Paste(RegisterType::Name(arg.to_string()));
+                self.popup_channels = Some((send2, recv));
+
+                let txt_prompt = PromptType::Text(String::new(), None, false);
+                let prompt = vec!["Search for".to_string()];
+
+                let pane = PopUpPane::new_prompt(
+                    self.settings.clone(),
+                    prompt,
+                    self.sender.clone(),
+                    send,
+                    recv2,
+                    vec![txt_prompt],
+                    true
+                );
+
+                let pane = Rc::new(RefCell::new(pane));
+
+                let ((x1, y1), _) = container.get_corners();
+                let (x, y) = container.get_size();
+                let size = (30.min(x), 4.min(y));
+
+                let pos = ((x1 + x / 2).saturating_sub(size.0 / 2), (y1 + y / 2).saturating_sub(size.1 / 2));
+
+                let max_size = container.get_size();
+
+                let mut container = PaneContainer::new(max_size, size, pane, self.settings.clone());
+                container.set_position(pos);
+                container.set_size(size);
+
+                self.sender.send(Message::CreatePopup(container, true)).expect("Failed to send message");
+                self.waiting = Waiting::Search;
+            },
+            "search" => {
+                let pattern = command_args.collect::<Vec<&str>>().join(" ");
+                self.search(&pattern);
+            },
+            "search_next" => {
+                self.search_step(true);
+            },
+            "search_previous" => {
+                self.search_step(false);
+            },
+            "repeat_change" => {
+                let count = command_args.next()
+                    .and_then(|count| count.parse::<usize>().ok())
+                    .unwrap_or(1)
+                    .max(1);
+
+                let keys = self.change_recorder.last_change().to_vec();
+                if !keys.is_empty() {
+                    self.sender.send(Message::MacroKeys(keys, count)).expect("Failed to send message");
+                }
+            },
+            "noh" | "nohlsearch" => {
+                self.search_highlight = false;
+            },
+            "delete_word" | "change_word" => {
+                let count = command_args.next()
+                    .and_then(|count| count.parse::<usize>().ok())
+                    .unwrap_or(1)
+                    .max(1);
+                let reg = match command_args.next() {
+                    Some(arg) => RegisterType::from_name(arg),
+                    None => RegisterType::None,
+                };
+
+                let (x, y) = self.cursor.borrow().get_cursor();
+                let line = match self.contents.get_row(y, 0, usize::MAX) {
+                    None => return,
+                    Some(line) => line.to_string(),
+                };
 
-                        self.sender.send(message).expect("Failed to send message");
+                let mut end = x;
+                for i in 0..count {
+                    // `cw` leaves the blanks after the last word alone
+                    end = if command == "change_word" && i + 1 == count {
+                        word::word_end(&line, end)
                     }
-                } else {
-                    let message = Message::Paste(RegisterType::None);
+                    else {
+                        word::next_word_start(&line, end)
+                    };
+                }
+
+                self.delete_columns(y, (x, end), reg);
+            },
+            "delete_inner_word" => {
+                let reg = match command_args.next() {
+                    Some(arg) => RegisterType::from_name(arg),
+                    None => RegisterType::None,
+                };
 
-                    self.sender.send(message).expect("Failed to send message");
+                let (x, y) = self.cursor.borrow().get_cursor();
+                if let Some(range) = self.inner_word_at((x, y)) {
+                    self.delete_columns(y, range, reg);
                 }
-                
+            },
+            "delete_line" => {
+                let count = command_args.next()
+                    .and_then(|count| count.parse::<usize>().ok())
+                    .unwrap_or(1)
+                    .max(1);
+                let reg = match command_args.next() {
+                    Some(arg) => RegisterType::from_name(arg),
+                    None => RegisterType::None,
+                };
+
+                let (_, y) = self.cursor.borrow().get_cursor();
+                let range = match line_delete_range(&self.contents.to_string(), y, y + count - 1) {
+                    None => return,
+                    Some(range) => range,
+                };
+
+                self.copy_lines(count, reg);
+                self.set_changed(true);
+                self.contents.delete(range);
 
+                let cursor = self.cursor.clone();
+                cursor.borrow_mut().jump_to(0, y, self);
+                self.move_to_first_non_blank(false);
+            },
+            "delete_selection" => {
+                let reg = match command_args.next() {
+                    Some(arg) => RegisterType::from_name(arg),
+                    None => RegisterType::None,
+                };
+
+                let ranges = match self.copy_selection(reg) {
+                    None => return,
+                    Some(ranges) => ranges,
+                };
+
+                self.set_changed(true);
+                // The lines of a block go in one undo step
+                let bounds = ranges.iter()
+                    .map(|range| (Bound::Included(range.start), Bound::Excluded(range.end)))
+                    .collect::<Box<[_]>>();
+                self.contents.delete_chain(bounds);
+
+                // The start of the selection might be past the end of the file now
+                let (x, y) = self.cursor.borrow().get_cursor();
+                let cursor = self.cursor.clone();
+                cursor.borrow_mut().jump_to(x, y, self);
+            },
+            "paste" => {
+                let after = command_args.next() != Some("before");
+                let reg = match command_args.next() {
+                    Some(arg) => RegisterType::from_name(arg),
+                    None => RegisterType::None,
+                };
+
+                let message = Message::Paste(reg, after);
+
+                self.sender.send(message).expect("Failed to send message");
             },
             "copy" => {
                 eprintln!("Copy");
                 if let Some(way) = command_args.next() {
 
+                    // Copying lines takes a count before the register
+                    let count = match way {
+                        "line" => command_args.next().and_then(|count| count.parse::<usize>().ok()).unwrap_or(1).max(1),
+                        _ => 1,
+                    };
+
                     let reg = if let Some(arg) = command_args.next() {
-                        let reg = if let Ok(number) = arg.parse::<usize>() {
-                            RegisterType::Number(number)
-                        } else {
-                            RegisterType::Name(arg.to_string())
-                        };
-                        reg
+                        RegisterType::from_name(arg)
                     } else {
                         RegisterType::None
                     };
@@ -777,20 +2221,10 @@ impl Pane for PlainTextPane {
 
                     match way {
                         "line" => {
-                            let (_, y) = self.cursor.borrow().get_cursor();
-                            let (width, _) = container.get_size();
-                            
-                            let row = self.contents.get_row(y, 0, width);
-
-                            let row = match row {
-                                Some(row) => row.to_string(),
-                                None => String::new(),
-                            };
-
-                            let message = Message::Copy(reg, row);
-
-                            self.sender.send(message).expect("Failed to send message");
-                                    
+                            self.copy_lines(count, reg);
+                        },
+                        "selection" => {
+                            self.copy_selection(reg);
                         },
                         _ => {},
 
@@ -809,16 +2243,71 @@ impl Pane for PlainTextPane {
 
     fn change_mode(&mut self, name: &str) {
         if let Some(mode) = self.get_mode(name) {
+            let was_block = self.selecting_block();
+            let visual = matches!(name, "Visual" | "Visual Line" | "Visual Block");
+            if let (Some(anchor), false) = (self.selection_anchor, visual) {
+                self.last_selection = Some((anchor, self.cursor.borrow().get_cursor(), self.selection_kind));
+            }
+            self.selection_anchor = match name {
+                // Switching between the visual modes keeps the selection where it started
+                "Visual" | "Visual Line" | "Visual Block" => {
+                    self.selection_anchor.or(Some(self.cursor.borrow().get_cursor()))
+                },
+                _ => None,
+            };
+            self.selection_kind = match name {
+                "Visual Line" => VisualKind::Lines,
+                "Visual Block" => VisualKind::Block,
+                _ => VisualKind::Chars,
+            };
+            // The cursor goes back on the text when it was only past the end of a line for the block
+            if was_block && !self.settings.borrow().editor_settings.virtualedit.allows(self.selecting_block()) {
+                let (x, y) = self.cursor.borrow().get_cursor();
+                let cursor = self.cursor.clone();
+                cursor.borrow_mut().jump_to(x, y, self);
+            }
+            // Everything typed in one insert session is undone together
+            if name == "Insert" {
+                self.contents.begin_group();
+            }
+            else {
+                self.contents.end_group();
+            }
             self.mode = mode;
         }
     }
 
     fn insert_newline(&mut self) {
-        self.insert_char('\n');
-        let mut cursor = self.cursor.borrow_mut();
+        let (x, y) = self.cursor.borrow().get_cursor();
+        let line = self.cursor_line(y);
+        let before = line.chars().take(x).collect::<String>();
+        let after = line.chars().skip(x).collect::<String>();
+
+        // Plain text has no blocks so the new line only keeps the indentation
+        let opener = None;
+        let (use_spaces, tab_size) = {
+            let settings = self.settings.borrow();
+            (settings.editor_settings.use_spaces, settings.editor_settings.tab_size)
+        };
+        let (text, column) = indent::split_line(&before, &after, opener, use_spaces, tab_size);
+
+        self.insert_str(&text);
+
+        let cursor = self.cursor.clone();
+        cursor.borrow_mut().jump_to(column, y + 1, self);
+    }
+
+    fn set_indent(&mut self, row: usize, indent: &str) {
+        let line = self.cursor_line(row);
+        let old = indent::leading_whitespace(&line).chars().count();
+        self.replace_text_at((0, row), (old, row), indent);
 
-        cursor.move_cursor(Direction::Down, 1, self);
-        cursor.set_cursor(CursorMove::ToStart, CursorMove::Nothing, self, (0,0));
+        let (x, y) = self.cursor.borrow().get_cursor();
+        if y == row {
+            let x = (x + indent.chars().count()).saturating_sub(old);
+            let cursor = self.cursor.clone();
+            cursor.borrow_mut().jump_to(x, y, self);
+        }
     }
 
     fn insert_char(&mut self, c: char) {
@@ -837,6 +2326,24 @@ impl Pane for PlainTextPane {
         self.contents.insert_current(byte_pos, c);
     }
 
+    fn paste(&mut self, value: &RegisterValue, after: bool) {
+        match value.kind {
+            RegisterKind::CharWise => self.insert_str(&value.text),
+            RegisterKind::LineWise => {
+                let (_, y) = self.cursor.borrow().get_cursor();
+                let (byte, lines) = line_paste(&self.contents.to_string(), y, after, &value.text);
+
+                self.set_changed(true);
+                self.contents.insert(byte, lines);
+
+                let y = if after { y + 1 } else { y };
+                let cursor = self.cursor.clone();
+                cursor.borrow_mut().jump_to(0, y, self);
+                self.move_to_first_non_blank(false);
+            },
+        }
+    }
+
     fn insert_str(&mut self, s: &str) {
         self.set_changed(true);
         let byte_pos = self.get_byte_offset();
@@ -919,6 +2426,42 @@ impl Pane for PlainTextPane {
         &self.file_name
     }
 
+    fn is_modified(&self) -> bool {
+        self.changed
+    }
+
+    fn settings_changed(&mut self) {
+        reload_keybindings(&self.modes, &self.settings.borrow());
+    }
+
+    fn show_message(&self, message: String, kind: MessageKind) {
+        self.sender.send(Message::SetMessage(message, kind)).expect("Failed to send message");
+    }
+
+    fn last_edit(&self) -> Option<Instant> {
+        self.edit_timer.edited()
+    }
+
+    fn is_inserting(&self) -> bool {
+        self.mode.borrow().get_name() == "Insert"
+    }
+
+    fn selecting_block(&self) -> bool {
+        self.selection_anchor.is_some() && self.selection_kind == VisualKind::Block
+    }
+
+    fn auto_save(&mut self, container: &PaneContainer) -> bool {
+        self.edit_timer.saved();
+        self.run_command("w", container);
+        !self.changed
+    }
+
+    fn preview_command(&mut self, command: Option<&str>) {
+        let enabled = self.settings.borrow().editor_settings.inccommand;
+        self.substitute_preview = command.filter(|_| enabled).and_then(Substitute::parse);
+        self.changed();
+    }
+
     fn resize_cursor(&mut self, size: (usize, usize)) {
         let mut cursor = self.cursor.borrow_mut();
         cursor.resize(size);
@@ -929,10 +2472,31 @@ impl Pane for PlainTextPane {
         cursor.set_size(size);
     }
 
-    fn backup_buffer(&mut self) {
-        self.contents.add_new_rope();
+    fn command_names(&self) -> Vec<&'static str> {
+        TEXT_COMMANDS.to_vec()
+    }
+
+    fn complete_words(&mut self, others: Vec<String>, container: &PaneContainer) {
+        let (x, y) = self.cursor.borrow().get_cursor();
+        let prefix = word_prefix(&self.cursor_line(y), x);
+        let cursor = self.get_byte_offset().unwrap_or(self.contents.get_byte_count());
+        let words = buffer_words(&prefix, &self.contents.to_string(), cursor, &others);
+        if words.is_empty() {
+            self.sender.send(Message::SetMessage("No words to complete".to_string(), MessageKind::Info))
+                .expect("Failed to send message");
+            return;
+        }
+
+        self.show_completions(CompletionList::from_words(words), container);
     }
 
+    fn set_location(&mut self, (x, y): (usize, usize)) {
+        self.jump_table.add(*self.cursor.borrow());
+        let cursor = self.cursor.clone();
+        cursor.borrow_mut().jump_to(x, y, self);
+    }
+
+
 
     fn get_settings(&self) -> Rc<RefCell<Settings>> {
         self.settings.clone()
@@ -1,25 +1,66 @@
-use std::{sync::{mpsc::{Sender, Receiver}, Arc}, cell::RefCell, rc::Rc, path::PathBuf, collections::{HashMap, HashSet}, io::{self, Write}};
+use std::{sync::mpsc::{Sender, Receiver}, cell::RefCell, rc::Rc, path::{Path, PathBuf}, collections::HashMap, io, ops::Bound, time::{Duration, Instant}};
 
 use crop::RopeSlice;
-use crossterm::{event::KeyEvent, style::{Attribute, Color}};
-use tree_sitter::{Parser, Tree, Point, Language, InputEdit};
+use crossterm::{event::{KeyCode, KeyEvent}, style::{Attribute, Color}};
+use encoding_rs::Encoding;
+use tree_sitter::{Parser, Tree, Point, Language, InputEdit, Query};
 
-use crate::{window::{Message, StyledChar, TextRow}, cursor::{Cursor, Direction, CursorMove}, mode::{Mode, base::{Normal, Insert, Command},  PromptType, Promptable}, buffer::Buffer, settings::{Settings, SyntaxHighlight, ColorScheme},  lsp::{ControllerMessage, LspNotification, lsp_utils::{Diagnostic, Diagnostics, CompletionList, TextEditType, LocationResponse}, LspResponse, LspRequest}, editor::RegisterType};
+use crate::{window::{Message, MessageKind, StyledChar, TextRow}, cursor::{Cursor, Direction, CursorMove, ScreenLine}, mode::{Mode, base::{Normal, Insert, Command, Visual, VisualKind},  PromptType, ButtonFunction, reload_keybindings}, buffer::{Buffer, Change}, settings::{Settings, config_dir}, spell::{SpellChecker, Misspelling}, definition, indent, word, undo, brackets, lines::{LineCommand, LineOperation}, substitute::{Substitute, SubstituteRun}, encoding::{FileEncoding, parse_encoding}, disk::{DiskWatch, EditTimer, SaveOptions, diff_lines, save_file}, lsp::{ControllerMessage, LspNotification, SharedReceiver, SharedInfo, lsp_utils::{Diagnostics, parse_severity, wrap_text, CompletionList, CompletionItem, SNIPPET_FORMAT, TextEdit, Location, LocationResponse, WorkspaceEdit, FormattingOptions, Position, CodeAction, edits_from_bottom, position_after_edits, path_to_uri, uri_to_path, find_root, TextChange}, LspResponse, LspRequest}, editor::RegisterType, registers::{RegisterValue, RegisterKind}, search::{SearchMatch, Pattern, next_match}, snippet::{Snippet, SnippetSession}};
 
-use super::{text::{JumpTable, Waiting}, PaneMessage, Pane, PaneContainer, popup::PopUpPane};
+use super::{text::{JumpTable, Waiting, PlainTextPane}, highlight::{HighlightCache, LineHighlight, load_query}, parse::BackgroundParser, PaneMessage, Pane, PaneContainer, popup::PopUpPane, diagnostics::DiagnosticsPane, completion::{completion_popup, completion_edit, word_prefix, buffer_words, path_completions}, display_name, which_key::WhichKey, repeat::ChangeRecorder, draw_preview_row, draw_cursor_line, draw_color_column, draw_bad_indent, draw_trailing_whitespace, draw_whitespace, draw_selection, draw_block_selection, order_selection, order_block, selection_byte_range, block_byte_ranges, line_paste, line_delete_range, draw_search_matches, draw_matching_brackets, Selection, LastSelection, BracketMatch, map_message, TEXT_COMMANDS, LANGUAGE_COMMANDS};
 
 
+/// Parts of tree-sitter node kinds that mean the node defines the name inside it.
+const DEFINING_NODES: [&str; 7] = ["declaration", "definition", "declarator", "item", "assignment", "let", "parameter"];
+/// How wide the text of diagnostic and hover popups can get.
+const INFO_WIDTH: usize = 78;
+
+/// The rainbow delimiter depth at the start of each line and the revision of the text it was worked out for.
+type RainbowDepths = (Option<(usize, usize)>, Vec<usize>);
 
 pub struct TreesitterPane {
     parser: Parser,
-    tree: Tree,
+    /// The tree of the text, `None` while the first tree of a big file is being parsed.
+    tree: Option<Tree>,
+    /// Parses big files on another thread, made the first time one is parsed.
+    background_parser: Option<BackgroundParser>,
     lang: String,
-    lsp_client: Option<(Sender<ControllerMessage>, Arc<Receiver<ControllerMessage>>)>,
+    lsp_client: Option<(Sender<ControllerMessage>, SharedReceiver)>,
+    /// What the server can do, filled in once it has started.
+    lsp_info: SharedInfo,
     file_version: usize,
-    lsp_diagnostics: Diagnostics,
-    sent_diagnostics: HashSet<Diagnostic>,
+    /// Shared with the diagnostics list so it sees what the server publishes.
+    lsp_diagnostics: Rc<RefCell<Diagnostics>>,
+    /// Where the diagnostics list sends the diagnostic to jump to.
+    diagnostics_list: Option<Receiver<PaneMessage>>,
+    /// The range of the diagnostic that was just jumped to, highlighted until the next key.
+    diagnostic_flash: Option<Selection>,
+    /// Where the cursor was when the diagnostic or hover popup was opened.
+    info_popup: Option<(usize, usize)>,
     lsp_completion: Option<CompletionList>,
+    /// Whether the completions were asked for by typing, so their list doesn't take the focus.
+    auto_completion: bool,
+    /// The character typed in Insert mode since the last refresh.
+    typed: Option<char>,
     lsp_location: Option<LocationResponse>,
+    lsp_hover: Option<String>,
+    lsp_workspace_edit: Option<WorkspaceEdit>,
+    lsp_formatting: Option<Option<Vec<TextEdit>>>,
+    lsp_code_actions: Option<Vec<CodeAction>>,
+    /// The code actions in the open popup, picked by their index.
+    code_actions: Vec<CodeAction>,
+    /// When the request the pane is waiting on was sent.
+    lsp_request_time: Option<Instant>,
+    /// The first line and the end of the range being formatted, `None` for the whole buffer.
+    format_range: Option<(usize, (usize, usize))>,
+    /// The edits the server hasn't been told about yet.
+    pending_changes: Vec<TextChange>,
+    /// Set when an edit replaced too much to send as a range, so the whole text gets sent.
+    whole_text_changed: bool,
+    /// When the first of the edits the server hasn't been told about was made.
+    changed_since: Option<Instant>,
+    /// The snippet whose tabstops Tab goes through in Insert mode.
+    snippet: Option<SnippetSession>,
 
     cursor: Rc<RefCell<Cursor>>,
     file_name: Option<PathBuf>,
@@ -32,7 +73,37 @@ pub struct TreesitterPane {
     sender: Sender<Message>,
     popup_channels: Option<(Sender<PaneMessage>, Receiver<PaneMessage>)>,
     waiting: Waiting,
-    rainbow_delimiters: RefCell<Vec<(char, ColorScheme)>>,
+    which_key: WhichKey,
+    change_recorder: ChangeRecorder,
+    bracket_match: Option<BracketMatch>,
+    substitute_preview: Option<Substitute>,
+    /// Where the cursor was when visual mode started.
+    selection_anchor: Option<(usize, usize)>,
+    /// What the selection covers between the anchor and the cursor.
+    selection_kind: VisualKind,
+    /// The anchor, cursor and kind of the last selection, for `gv` to select again.
+    last_selection: Option<LastSelection>,
+    search_pattern: Option<String>,
+    search_matches: Vec<SearchMatch>,
+    /// Whether the matches of the last search are drawn, which `noh` turns off until the next search.
+    search_highlight: bool,
+    /// The search being typed in the prompt and where the cursor was before it moved to the first match.
+    search_preview: Option<String>,
+    search_start: Option<Cursor>,
+    /// The substitute waiting on an answer to its replace prompt.
+    substitute_run: Option<SubstituteRun>,
+    /// The highlight query of the language, `None` when the language only has the highlights of its node kinds.
+    highlight_query: Option<Query>,
+    highlight_cache: RefCell<HighlightCache>,
+    rainbow_depths: RefCell<RainbowDepths>,
+    spell_checker: Option<Rc<SpellChecker>>,
+    misspellings: Vec<Misspelling>,
+    /// The revision of the text the misspellings were found in.
+    spelled_revision: Option<(usize, usize)>,
+    spell_target: Option<Misspelling>,
+    encoding: FileEncoding,
+    disk_watch: DiskWatch,
+    edit_timer: EditTimer,
 }
 
 impl TreesitterPane {
@@ -40,7 +111,7 @@ impl TreesitterPane {
                sender: Sender<Message>,
                lang: Language,
                lang_string: &str,
-               lsp: Option<(Sender<ControllerMessage>, Arc<Receiver<ControllerMessage>>)>)
+               lsp: Option<(Sender<ControllerMessage>, SharedReceiver)>)
                -> Self {
         let mut modes: HashMap<String, Rc<RefCell<dyn Mode>>> = HashMap::new();
         let normal = Rc::new(RefCell::new(Normal::new()));
@@ -55,9 +126,24 @@ impl TreesitterPane {
         command.borrow_mut().add_keybindings(settings.borrow().mode_keybindings.get("Command").unwrap().clone());
         command.borrow_mut().set_key_timeout(settings.borrow().editor_settings.key_timeout);
 
+        let visual = Rc::new(RefCell::new(Visual::new(VisualKind::Chars)));
+        visual.borrow_mut().add_keybindings(settings.borrow().mode_keybindings.get("Visual").unwrap().clone());
+        visual.borrow_mut().set_key_timeout(settings.borrow().editor_settings.key_timeout);
+
+        let visual_line = Rc::new(RefCell::new(Visual::new(VisualKind::Lines)));
+        visual_line.borrow_mut().add_keybindings(settings.borrow().mode_keybindings.get("Visual").unwrap().clone());
+        visual_line.borrow_mut().set_key_timeout(settings.borrow().editor_settings.key_timeout);
+
+        let visual_block = Rc::new(RefCell::new(Visual::new(VisualKind::Block)));
+        visual_block.borrow_mut().add_keybindings(settings.borrow().mode_keybindings.get("Visual").unwrap().clone());
+        visual_block.borrow_mut().set_key_timeout(settings.borrow().editor_settings.key_timeout);
+
         modes.insert("Normal".to_string(), normal.clone());
         modes.insert("Insert".to_string(), insert.clone());
         modes.insert("Command".to_string(), command.clone());
+        modes.insert("Visual".to_string(), visual.clone());
+        modes.insert("Visual Line".to_string(), visual_line.clone());
+        modes.insert("Visual Block".to_string(), visual_block.clone());
 
         let mut parser = Parser::new();
 
@@ -67,15 +153,32 @@ impl TreesitterPane {
                 
 
         
-        Self {
+        let mut pane = Self {
             parser,
-            tree,
+            tree: Some(tree),
+            background_parser: None,
             lsp_client: lsp,
+            lsp_info: SharedInfo::default(),
             file_version: 0,
-            lsp_diagnostics: Diagnostics::new(),
-            sent_diagnostics: HashSet::new(),
+            lsp_diagnostics: Rc::new(RefCell::new(Diagnostics::new())),
+            diagnostics_list: None,
+            diagnostic_flash: None,
+            info_popup: None,
             lsp_completion: None,
+            auto_completion: false,
+            typed: None,
             lsp_location: None,
+            lsp_hover: None,
+            lsp_workspace_edit: None,
+            lsp_formatting: None,
+            lsp_code_actions: None,
+            code_actions: Vec::new(),
+            lsp_request_time: None,
+            format_range: None,
+            pending_changes: Vec::new(),
+            whole_text_changed: false,
+            changed_since: None,
+            snippet: None,
             lang: lang_string.to_string(),
             cursor: Rc::new(RefCell::new(Cursor::new((0,0)))),
             file_name: None,
@@ -88,8 +191,47 @@ impl TreesitterPane {
             sender,
             popup_channels: None,
             waiting: Waiting::None,
-            rainbow_delimiters: RefCell::new(Vec::new()),
-        }
+            which_key: WhichKey::new(),
+            change_recorder: ChangeRecorder::new(),
+            bracket_match: None,
+            substitute_preview: None,
+            selection_anchor: None,
+            selection_kind: VisualKind::Chars,
+            last_selection: None,
+            search_pattern: None,
+            search_matches: Vec::new(),
+            search_highlight: false,
+            search_preview: None,
+            search_start: None,
+            substitute_run: None,
+            highlight_query: None,
+            highlight_cache: RefCell::new(HighlightCache::new()),
+            rainbow_depths: RefCell::new((None, Vec::new())),
+            spell_checker: None,
+            misspellings: Vec::new(),
+            spelled_revision: None,
+            spell_target: None,
+            encoding: FileEncoding::default(),
+            disk_watch: DiskWatch::new(),
+            edit_timer: EditTimer::new(),
+        };
+        pane.load_highlight_query();
+        pane
+    }
+
+    /// Reads the highlight query of the language again, so a query changed in the config directory gets used.
+    /// A query that doesn't work leaves the language with the highlights of its node kinds.
+    fn load_highlight_query(&mut self) {
+        let language = self.parser.language().expect("parser has no language");
+        self.highlight_query = match load_query(language, &self.lang, config_dir().as_deref()) {
+            Ok(query) => query,
+            Err(e) => {
+                let message = format!("Failed to load the {}", e);
+                self.sender.send(Message::SetMessage(message, MessageKind::Error)).expect("Failed to send message");
+                None
+            },
+        };
+        self.highlight_cache.borrow_mut().clear();
     }
 
 
@@ -97,1293 +239,3075 @@ impl TreesitterPane {
         self.changed = changed;
     }
 
+    /// The selected text, if visual mode is on.
+    fn get_selection(&self) -> Option<Selection> {
+        let anchor = self.selection_anchor?;
+        let cursor = self.cursor.borrow().get_cursor();
 
-    fn get_row(&self, row: usize, offset: usize, col: usize) -> Option<RopeSlice> {
+        match self.selection_kind {
+            VisualKind::Chars => Some(order_selection(anchor, cursor)),
+            VisualKind::Lines => {
+                let (start, end) = order_selection(anchor, cursor);
+                Some(((0, start.1), (usize::MAX, end.1)))
+            },
+            VisualKind::Block => Some(order_block(anchor, cursor)),
+        }
+    }
 
-        self.contents.get_row(row, offset, col)
+    /// Puts the selected text in a register and moves the cursor to the start of the selection.
+    /// Returns the bytes that were selected, one range for each line of a block.
+    fn copy_selection(&mut self, reg: RegisterType) -> Option<Vec<std::ops::Range<usize>>> {
+        let selection = self.get_selection()?;
+        let text = self.contents.to_string();
+        let ranges = match self.selection_kind {
+            VisualKind::Block => block_byte_ranges(&text, selection),
+            _ => vec![selection_byte_range(&text, selection)?],
+        };
+
+        let copied = ranges.iter().map(|range| &text[range.clone()]).collect::<Vec<_>>().join("\n");
+        let value = match self.selection_kind {
+            VisualKind::Lines => RegisterValue::line_wise(copied),
+            _ => RegisterValue::char_wise(copied),
+        };
+        self.sender.send(Message::Copy(reg, value)).expect("Failed to send message");
+
+        let ((x, y), _) = selection;
+        let cursor = self.cursor.clone();
+        cursor.borrow_mut().jump_to(x, y, self);
+
+        Some(ranges)
     }
 
-    pub fn borrow_buffer(&self) -> &Buffer {
-        &self.contents
+    /// Puts the cursor's line and the lines after it in a register.
+    fn copy_lines(&self, count: usize, reg: RegisterType) {
+        let (_, y) = self.cursor.borrow().get_cursor();
+        let text = self.contents.to_string();
+        let range = match selection_byte_range(&text, ((0, y), (usize::MAX, y + count - 1))) {
+            None => return,
+            Some(range) => range,
+        };
+
+        let message = Message::Copy(reg, RegisterValue::line_wise(text[range].to_string()));
+        self.sender.send(message).expect("Failed to send message");
     }
 
-    pub fn borrow_buffer_mut(&mut self) -> &mut Buffer {
-        &mut self.contents
+    /// Deletes the columns of a line, the end being exclusive, and puts what was there in a register.
+    fn delete_columns(&mut self, row: usize, (start, end): (usize, usize), reg: RegisterType) {
+        if start >= end {
+            return;
+        }
+        let line = match self.contents.get_row(row, 0, usize::MAX) {
+            None => return,
+            Some(line) => line.to_string(),
+        };
+
+        let deleted = line.chars().skip(start).take(end - start).collect::<String>();
+        self.sender.send(Message::Copy(reg, RegisterValue::char_wise(deleted))).expect("Failed to send message");
+
+        self.replace_text_at((start, row), (end, row), "");
+
+        let cursor = self.cursor.clone();
+        cursor.borrow_mut().jump_to(start, row, self);
     }
 
-    pub fn get_mode(&self, name: &str) -> Option<Rc<RefCell<dyn Mode>>> {
-        self.modes.get(name).map(|m| m.clone())
+    /// Returns the columns of the word under the cursor for `iw`.
+    /// An identifier in the syntax tree is taken whole even if the word rules would split it.
+    fn inner_word_at(&self, (x, y): (usize, usize)) -> Option<(usize, usize)> {
+        let line = self.contents.get_row(y, 0, usize::MAX)?.to_string();
+        let byte = line.char_indices().nth(x)?.0;
+
+        let point = Point::new(y, byte);
+        let identifier = self.tree.as_ref()
+            .and_then(|tree| tree.root_node().descendant_for_point_range(point, point))
+            .filter(|node| node.kind().ends_with("identifier"))
+            .filter(|node| node.start_position().row == y && node.end_position().row == y)
+            .and_then(|node| {
+                let start = line.get(..node.start_position().column)?.chars().count();
+                let end = line.get(..node.end_position().column)?.chars().count();
+                Some((start, end))
+            });
+
+        identifier.or_else(|| word::inner_word(&line, x))
     }
 
+    /// Starts a substitute from the command line.
+    fn start_substitute(&mut self, substitute: Substitute, container: &PaneContainer) {
+        let (_, y) = self.cursor.borrow().get_cursor();
+        let run = match SubstituteRun::new(substitute, y, self.contents.get_line_count()) {
+            Err(message) => {
+                self.sender.send(Message::SetMessage(message, MessageKind::Error)).expect("Failed to send message");
+                return;
+            },
+            Ok(run) => run,
+        };
 
-    fn get_byte_offset(&self) -> Option<usize> {
-        let (x, y) = self.cursor.borrow().get_cursor();
+        if run.next_match(&self.contents).is_none() {
+            let message = format!("Pattern not found: {}", run.substitute.pattern);
+            self.sender.send(Message::SetMessage(message, MessageKind::Error)).expect("Failed to send message");
+            return;
+        }
 
-        self.contents.get_byte_offset(x, y)
+        self.substitute_run = Some(run);
+        self.continue_substitute(container);
     }
 
-    fn check_messages(&mut self, container: &PaneContainer) {
-        match self.popup_channels.as_ref() {
-            None => {},
-            Some((_, receiver)) => {
-                match receiver.try_recv() {
-                    Ok(message) => {
-                        match message {
-                            PaneMessage::String(string) => {
+    /// Replaces matches until one has to be confirmed or there are none left.
+    /// Everything replaced without asking is undone together.
+    fn continue_substitute(&mut self, container: &PaneContainer) {
+        let mut grouped = false;
+
+        while let Some(mut run) = self.substitute_run.take() {
+            let found = match run.next_match(&self.contents) {
+                None => {
+                    self.finish_substitute(run);
+                    break;
+                },
+                Some(found) => found,
+            };
 
-                                match self.waiting {
-                                    Waiting::JumpTarget => {
-                                        self.waiting = Waiting::JumpPosition;
-                                        let command = format!("jump {}", string);
-                                        self.run_command(&command, container);
-                                    },
-                                    Waiting::JumpPosition => {
-                                        self.waiting = Waiting::None;
-                                        let command = format!("set_jump {}", string);
-                                        self.run_command(&command, container);
-                                    },
-                                    Waiting::Completion => {
-                                        self.waiting = Waiting::None;
-                                        let command = format!("insert {}", string);
-                                        self.run_command(&command, container);
-                                    },
-                                    Waiting::Goto => {
-                                        self.waiting = Waiting::None;
-                                        let command = format!("goto {}", string);
-                                        self.run_command(&command, container);
-                                    },
-                                    Waiting::None => {
-                                    },
-                                }
-                            },
-                            PaneMessage::Close => {
-                                eprintln!("Closing treesitter");
-                                self.run_command("q!", container)
-                            },
-                        }
-                    },
-                    Err(_) => {},
-                }
+            if run.ask {
+                let cursor = self.cursor.clone();
+                cursor.borrow_mut().jump_to(found.col, found.line, self);
+                // Keeps command mode from putting the cursor back before the prompt shows up
+                let current = *self.cursor.borrow();
+                self.cursor.borrow_mut().prepare_jump(&current);
+
+                self.substitute_run = Some(run);
+                self.confirm_substitute(container);
+                break;
             }
-        }
-    }
 
-    fn generate_uri(& self) -> String {
-        let working_dir = std::env::current_dir().unwrap();
-        match &self.file_name {
-            None => format!("untitled://{}", working_dir.display()),
-            Some(file_name) => {
-                let uri = format!("file://{}/{}", working_dir.display(), file_name.display());
+            if !grouped {
+                self.contents.begin_group();
+                grouped = true;
+            }
+            let replacement = run.replacement(&self.contents, found);
+            self.replace_text_at((found.col, found.line), (found.col + found.len, found.line), &replacement);
+            run.advance(found, Some(&replacement));
+            self.substitute_run = Some(run);
+        }
 
-                uri
-            },
+        if grouped {
+            self.contents.end_group();
         }
     }
 
-    fn read_lsp_messages(&mut self) {
-        match self.lsp_client.as_ref() {
-            None => {},
-            Some((sender, receiver)) => {
-                let mut other_uri_count = 0;
-                loop {
-                    match receiver.try_recv() {
-                        Ok(ControllerMessage::Response(resp)) => {
-                            match resp {
-                                LspResponse::PublishDiagnostics(diags) => {
-                                    if diags.uri == self.generate_uri() {
-                                        self.lsp_diagnostics.merge(diags);
-                                    }
-                                    else {
-                                        if other_uri_count == 4 {
-                                            break;
-                                        }
-                                        other_uri_count += 1;
-                                        sender.send(ControllerMessage::Resend(
-                                            self.lang.clone().into(),
-                                            LspResponse::PublishDiagnostics(diags)
-                                        )).unwrap();
-                                    }
-                                },
-                                LspResponse::Completion(completions) => {
-                                    self.lsp_completion = Some(completions);
-                                },
-                                LspResponse::Location(location) => {
-                                    self.lsp_location = Some(location);
-                                },
-                            }
+    /// Handles the answer to the replace prompt, where "q" or anything else stops the substitute.
+    fn answer_substitute(&mut self, answer: &str, container: &PaneContainer) {
+        let mut run = match self.substitute_run.take() {
+            None => return,
+            Some(run) => run,
+        };
+        let found = match run.next_match(&self.contents) {
+            None => {
+                self.finish_substitute(run);
+                return;
+            },
+            Some(found) => found,
+        };
 
-                        },
-                        Ok(_) => {
-                        },
-                        Err(_) => {
-                            break;
-                        },
-                    }
-                }
+        match answer {
+            "y" => {
+                let replacement = run.replacement(&self.contents, found);
+                self.replace_text_at((found.col, found.line), (found.col + found.len, found.line), &replacement);
+                run.advance(found, Some(&replacement));
+            },
+            "n" => run.advance(found, None),
+            "a" => run.ask = false,
+            _ => {
+                self.finish_substitute(run);
+                return;
             },
         }
-    }
 
-    /// This function is to create an informational popup that will display
-    /// the diagnostics for the current cursor position.
-    /// Therefore, it should get called whenever something might have changed
-    /// the diagnostics for the current cursor position.
-    fn open_info(&mut self, container: &PaneContainer) {
+        self.substitute_run = Some(run);
+        self.continue_substitute(container);
+    }
 
-        if self.lsp_completion.is_some() {
-            return;
+    /// Puts the cursor on the last line that was changed and reports how many replacements were made.
+    fn finish_substitute(&mut self, run: SubstituteRun) {
+        let cursor = self.cursor.clone();
+        match run.last_replaced {
+            Some(row) => {
+                cursor.borrow_mut().jump_to(0, row, self);
+                self.move_to_first_non_blank(false);
+            },
+            None => {
+                // The line the cursor was on might be shorter now
+                let (x, y) = cursor.borrow().get_cursor();
+                cursor.borrow_mut().jump_to(x, y, self);
+            },
         }
 
-        match &self.popup_channels {
-            Some((send,_)) => {
-                match self.waiting {
-                    Waiting::None => {
-                        eprintln!("Sending close");
-
-                        match send.send(PaneMessage::Close) {
-                            Ok(_) => {},
-                            Err(_) => {},
-                        }
+        let message = match run.replaced {
+            1 => "1 substitution".to_string(),
+            replaced => format!("{} substitutions", replaced),
+        };
+        self.sender.send(Message::SetMessage(message, MessageKind::Info)).expect("Failed to send message");
+    }
 
-                        self.popup_channels = None;
-                    },
-                    _ => {},
-                }
-            },
-            None => {},
-        }
+    /// Asks whether the match under the cursor should be replaced.
+    fn confirm_substitute(&mut self, container: &PaneContainer) {
+        let (send, recv) = std::sync::mpsc::channel();
+        let (send2, recv2) = std::sync::mpsc::channel();
 
+        self.popup_channels = Some((send2, recv));
 
-        
-        let cursor = self.cursor.borrow().get_cursor();
+        let mut buttons = Vec::new();
+        for (label, answer) in [("Yes", "y"), ("No", "n"), ("All", "a"), ("Quit", "q")] {
+            let function: ButtonFunction = Box::new(move |_| answer.to_string());
+            buttons.push((label.to_string(), function));
+        }
+        let buttons = PromptType::Button(buttons, 0);
+
+        let replacement = self.substitute_run.as_ref()
+            .map(|run| run.substitute.replacement.replace('\n', "↵"))
+            .unwrap_or_default();
+        let prompt = vec![format!("Replace with {}?", replacement)];
+        let width = prompt[0].chars().count().max(24) + 2;
+
+        let pane = PopUpPane::new_prompt(
+            self.settings.clone(),
+            prompt,
+            self.sender.clone(),
+            send,
+            recv2,
+            vec![buttons],
+            true
+        );
+
+        let pane = Rc::new(RefCell::new(pane));
 
-        let diagnostic = self.lsp_diagnostics.get_diagnostic(cursor.1, cursor.0);
-        match diagnostic {
-            Some(diagnostic) => {
+        let ((x1, y1), _) = container.get_corners();
+        let (x, y) = container.get_size();
+        let size = (width.min(x), 4.min(y));
 
-                if self.sent_diagnostics.contains(diagnostic) {
-                    self.sent_diagnostics.remove(diagnostic);
-                    return;
-                }
-                
-                let (send, recv) = std::sync::mpsc::channel();
-                let (send2, recv2) = std::sync::mpsc::channel();
+        let pos = ((x1 + x / 2).saturating_sub(size.0 / 2), (y1 + y / 2).saturating_sub(size.1 / 2));
 
-                let prompt = match &diagnostic.code {
-                    Some(code) => {
-                        let split = code.split('_');
-
-                        let mut words = Vec::new();
-                        for word in split {
-                            let mut chars = word.chars();
-                            match chars.next() {
-                                None => {},
-                                Some(c) => {
-                                    let mut word = String::new();
-                                    word.push(c.to_ascii_uppercase());
-                                    for c in chars {
-                                        word.push(c);
-                                    }
-                                    words.push(word);
-                                },
-                            }
-                        }
+        let max_size = container.get_size();
 
-                        let prompt = words.join(" ");
-                        
+        let mut container = PaneContainer::new(max_size, size, pane, self.settings.clone());
+        container.set_position(pos);
+        container.set_size(size);
 
-                        vec![prompt]
-                    },
-                    None => Vec::new(),
-                };
+        self.sender.send(Message::CreatePopup(container, true)).expect("Failed to send message");
+        self.waiting = Waiting::Substitute;
+    }
 
-                let mut max = 0;
+    /// Moves the cursor to the first match of the search being typed without adding a jump.
+    fn preview_search(&mut self, pattern: &str) {
+        let start = *self.search_start.get_or_insert(*self.cursor.borrow());
+        *self.cursor.borrow_mut() = start;
+        self.search_preview = None;
 
-                let body = diagnostic.message.split("\n").map(|s| if s.len() == 0 {
-                    None
-                } else {
-                    if s.len() > max {
-                        max = s.len();
-                    }
-                    eprintln!("{}", s);
-                    Some(s.to_string())
-                }).collect::<Vec<Option<String>>>();
+        if pattern.is_empty() {
+            return;
+        }
 
-                
-                let size = (max + 1, body.len() + 2 + prompt.len());
-                
+        // A pattern that is still being typed might not compile yet
+        let matches = match Pattern::compile(pattern) {
+            Err(_) => return,
+            Ok(pattern) => pattern.find_all(&self.contents.to_string()),
+        };
+        let wrapscan = self.settings.borrow().editor_settings.wrapscan;
 
-                let pane = PopUpPane::new_info(self.settings.clone(),
-                                               prompt,
-                                               self.sender.clone(),
-                                               send,
-                                               recv2,
-                                               body,
-                                               false);
-                let pane = Rc::new(RefCell::new(pane));
+        if let Some((found, _)) = next_match(&matches, start.get_cursor(), true, wrapscan) {
+            let cursor = self.cursor.clone();
+            cursor.borrow_mut().jump_to(found.col, found.line, self);
+        }
+        self.search_preview = Some(pattern.to_string());
+    }
 
-                let (_, (x2, y2)) = container.get_corners();
-                let (x, y) = container.get_size();
+    /// Puts the cursor back where it was before the search being typed moved it.
+    fn end_search_preview(&mut self) {
+        self.search_preview = None;
+        if let Some(start) = self.search_start.take() {
+            *self.cursor.borrow_mut() = start;
+        }
+    }
 
-                let pos = self.cursor.borrow().get_real_cursor();
-                eprintln!("pos: {:?}", pos);
+    /// Searches for a pattern and moves to the first match after the cursor.
+    /// An empty pattern searches for the last pattern again.
+    fn search(&mut self, pattern: &str) {
+        if !pattern.is_empty() {
+            self.search_pattern = Some(pattern.to_string());
+        }
+        self.search_highlight = true;
+        self.search_step(true);
+    }
 
-                let max_size = container.get_size();
+    /// Moves the cursor to the next or previous match of the last search.
+    fn search_step(&mut self, forward: bool) {
+        let pattern = match &self.search_pattern {
+            None => {
+                let message = Message::SetMessage("No previous search pattern".to_string(), MessageKind::Error);
+                self.sender.send(message).expect("Failed to send message");
+                return;
+            },
+            Some(pattern) => pattern.clone(),
+        };
 
-                let mut container = PaneContainer::new(max_size, size, pane, self.settings.clone());
-                container.set_position(pos);
-                container.set_size(size);
+        let compiled = match Pattern::compile(&pattern) {
+            Err(message) => {
+                self.sender.send(Message::SetMessage(message, MessageKind::Error)).expect("Failed to send message");
+                return;
+            },
+            Ok(compiled) => compiled,
+        };
+        // The buffer might have changed since the last search
+        self.search_matches = compiled.find_all(&self.contents.to_string());
 
-                self.sender.send(Message::CreatePopup(container, false)).expect("Failed to send message");
-                self.waiting = Waiting::None;
+        let wrapscan = self.settings.borrow().editor_settings.wrapscan;
+        let position = self.cursor.borrow().get_cursor();
 
-                self.popup_channels = Some((send2, recv));
-                self.sent_diagnostics.insert(diagnostic.clone());
+        match next_match(&self.search_matches, position, forward, wrapscan) {
+            None if self.search_matches.is_empty() => {
+                let message = Message::SetMessage(format!("Pattern not found: {}", pattern), MessageKind::Error);
+                self.sender.send(message).expect("Failed to send message");
             },
             None => {
-                match &self.popup_channels {
-                    Some((send,_)) => {
-                        match self.waiting {
-                            Waiting::None => {
-                                eprintln!("Sending close");
-
-                                match send.send(PaneMessage::Close) {
-                                    Ok(_) => {},
-                                    Err(_) => {},
-                                }
-                                self.sent_diagnostics.clear();
-                                self.popup_channels = None;
-                            },
-                            _ => {},
-                        }
-                    },
-                    None => {},
+                let end = if forward { "BOTTOM" } else { "TOP" };
+                let message = Message::SetMessage(format!("search hit {} without match for: {}", end, pattern), MessageKind::Error);
+                self.sender.send(message).expect("Failed to send message");
+            },
+            Some((found, wrapped)) => {
+                if wrapped {
+                    let message = if forward {
+                        "search hit BOTTOM, continuing at TOP"
+                    }
+                    else {
+                        "search hit TOP, continuing at BOTTOM"
+                    };
+                    self.sender.send(Message::SetMessage(message.to_string(), MessageKind::Warning)).expect("Failed to send message");
                 }
 
+                self.jump_table.add(*self.cursor.borrow());
+                let cursor = self.cursor.clone();
+                cursor.borrow_mut().jump_to(found.col, found.line, self);
             },
         }
-            
-        
     }
 
-    fn insert_str_at(&mut self, pos: (usize, usize), s: &str) {
-        self.set_changed(true);
-
-        let start_byte;
-        let new_end_byte;
-        
-        let byte_pos = self.get_byte_offset_pos(pos);
-        if self.contents.get_char_count() == 0 {
-            self.contents.insert(0, s);
-            start_byte = 0;
-            new_end_byte = self.contents.get_byte_count();
-        }
-        else {
-            let byte_pos = match byte_pos {
-                None => self.contents.get_byte_count(),
-                Some(byte_pos) => byte_pos,
-            };
-            self.contents.insert(byte_pos, s);
-            start_byte = byte_pos;
-            new_end_byte = self.contents.get_byte_count();
-        }
-
-        let (x, y) = pos;
-
-        let new_char_len = self.contents.get_char_count();
+    /// Moves the cursor to the first character of the line that isn't blank.
+    /// With `toggle` a cursor that is already there goes to the start of the line instead.
+    fn move_to_first_non_blank(&mut self, toggle: bool) {
+        let (x, y) = self.cursor.borrow().get_cursor();
+        let line = match self.contents.get_row(y, 0, usize::MAX) {
+            None => return,
+            Some(line) => line.to_string(),
+        };
+        let first = indent::leading_whitespace(&line).chars().count();
 
-        let insert_char_count = s.chars().count();
+        let x = if toggle && x == first { 0 } else { first };
 
+        let cursor = self.cursor.clone();
+        cursor.borrow_mut().jump_to(x, y, self);
+    }
 
-        let edit = InputEdit {
-            start_byte,
-            old_end_byte: start_byte,
-            new_end_byte,
-            start_position: Point::new(y, x),
-            old_end_position: Point::new(y, x + insert_char_count),
-            new_end_position: Point::new(y, new_char_len),
+    /// Moves the cursor to the start of the next or previous word or to the end of the next one, like `w`, `b` and `e`.
+    /// The word can be on the lines after or before the cursor's.
+    fn move_by_word(&mut self, motion: &str) {
+        let (x, y) = self.cursor.borrow().get_cursor();
+        let lines = self.contents.get_line_count();
+
+        let found = match motion {
+            "word_start_backward" => word::previous_word(&self.cursor_line(y), x)
+                .map(|col| (col, y))
+                .or_else(|| (0..y).rev().find_map(|row| word::previous_word(&self.cursor_line(row), usize::MAX).map(|col| (col, row)))),
+            "word_end_forward" => word::next_word_end(&self.cursor_line(y), Some(x))
+                .map(|col| (col, y))
+                .or_else(|| (y + 1..lines).find_map(|row| word::next_word_end(&self.cursor_line(row), None).map(|col| (col, row)))),
+            _ => word::next_word(&self.cursor_line(y), x)
+                .map(|col| (col, y))
+                .or_else(|| (y + 1..lines).find_map(|row| word::first_word(&self.cursor_line(row)).map(|col| (col, row)))),
         };
 
-        self.tree.edit(&edit);
-        self.tree = self.parser.parse(&self.contents.to_string(), Some(&self.tree)).unwrap();
-        
+        if let Some((x, y)) = found {
+            let cursor = self.cursor.clone();
+            cursor.borrow_mut().jump_to(x, y, self);
+        }
     }
 
-    fn get_byte_offset_pos(&self, (x, y): (usize, usize)) -> Option<usize> {
+    /// Indents or dedents every line from `first` to `last` by a level as one undo step.
+    /// Blank lines are left alone and the cursor goes to the first non-blank of the first line.
+    fn shift_lines(&mut self, first: usize, last: usize, right: bool) {
+        let (use_spaces, tab_size) = {
+            let settings = self.settings.borrow();
+            (settings.editor_settings.use_spaces, settings.editor_settings.tab_size)
+        };
+        let last = last.min(self.contents.get_line_count().saturating_sub(1));
 
-        self.contents.get_byte_offset(x, y)
-    }
+        self.contents.begin_group();
+        for row in first..=last {
+            let line = self.cursor_line(row);
+            if line.trim().is_empty() {
+                continue;
+            }
 
+            let old = indent::leading_whitespace(&line);
+            let new = if right {
+                indent::indent(old, use_spaces, tab_size)
+            }
+            else {
+                indent::dedent(old, use_spaces, tab_size)
+            };
 
-    fn get_file_path(uri: &str) -> String {
-        
-        let chars = uri.chars();
-        // Here we skip the `file://` part of the uri
-        let chars = chars.skip(7);
-        // We will need to skip the port number if there is one
-        //TODO: Handle port numbers
+            if new != old {
+                self.set_indent(row, &new);
+            }
+        }
+        self.contents.end_group();
 
-        chars.collect()
+        let cursor = self.cursor.clone();
+        cursor.borrow_mut().jump_to(0, first, self);
+        self.move_to_first_non_blank(false);
     }
 
+    /// Gives every line from `first` to `last` the indentation that the line above it calls for, as one undo step.
+    /// This is what formatting falls back to without a language server.
+    fn reindent_lines(&mut self, first: usize, last: usize) {
+        let (use_spaces, tab_size) = {
+            let settings = self.settings.borrow();
+            (settings.editor_settings.use_spaces, settings.editor_settings.tab_size)
+        };
+        let last = last.min(self.contents.get_line_count().saturating_sub(1));
 
-}
-
+        self.contents.begin_group();
+        for row in first..=last {
+            let line = self.cursor_line(row);
+            if line.trim().is_empty() {
+                continue;
+            }
 
-impl Pane for TreesitterPane {
+            let new = match (0..row).rev().find(|above| !self.cursor_line(*above).trim().is_empty()) {
+                None => String::new(),
+                Some(above) => {
+                    let above_line = self.cursor_line(above);
+                    let opened = self.block_opener(above_line.trim_end(), above).is_some();
+                    indent::line_indent(&above_line, opened, indent::starts_with_closer(&line), use_spaces, tab_size)
+                },
+            };
 
-    fn execute_command(&mut self, command: &str, container: &mut PaneContainer) {
-        let mode = self.mode.clone();
-        mode.borrow_mut().execute_command(command, self, container);
-    }
+            if new != indent::leading_whitespace(&line) {
+                self.set_indent(row, &new);
+            }
+        }
+        self.contents.end_group();
 
-    fn changed(&mut self) {
-        self.cursor.borrow_mut().set_moved();
-    }
-    
-    fn reset(&mut self) {
-        self.cursor.borrow_mut().reset_move();
+        let cursor = self.cursor.clone();
+        cursor.borrow_mut().jump_to(0, first, self);
+        self.move_to_first_non_blank(false);
     }
-    
-    fn draw_row(&self, mut index: usize, container: &PaneContainer, output: &mut TextRow) {
-
-        let cols = container.get_size().0;
-        //eprintln!("Cols: {}", cols);
-
-        //eprintln!("Changed");
 
-        let ((x1, y1), _) = container.get_corners();
+    /// How deep in rainbow delimiters the text of a row is before a column.
+    /// The depth at the start of each line is kept until the text changes, so only the lines after the last one asked for get scanned.
+    fn rainbow_depth(&self, row: usize, col: usize) -> usize {
+        let revision = self.contents.revision();
+        let mut depths = self.rainbow_depths.borrow_mut();
+        if depths.0 != Some(revision) {
+            *depths = (Some(revision), vec![0]);
+        }
 
+        let number_of_lines = self.contents.get_line_count();
+        while depths.1.len() <= row.min(number_of_lines) {
+            let line = depths.1.len() - 1;
+            let depth = self.scan_rainbow(line, usize::MAX, depths.1[line]);
+            depths.1.push(depth);
+        }
 
-        if self.settings.borrow().editor_settings.border {
+        match depths.1.get(row) {
+            None => 0,
+            Some(depth) => self.scan_rainbow(row, col, *depth),
+        }
+    }
 
-            let color_settings = &self.settings.borrow().colors.ui;
-            
-            if index == 0 && y1 != 0 {
+    /// Follows the rainbow delimiters of a row up to a column, starting from a depth.
+    fn scan_rainbow(&self, row: usize, col: usize, depth: usize) -> usize {
+        let end = match self.contents.get_row(row, 0, usize::MAX) {
+            None => return depth,
+            Some(line) => line.chars().take(col).map(char::len_utf8).sum::<usize>(),
+        };
+        self.line_highlight(row).rainbow_depth(depth, end)
+    }
 
-                if !self.cursor.borrow().get_moved() {
-                    //eprintln!("Not Changed");
-                    for _ in 0..cols {
-                        output.push(None);
-                    }
-                    return;
-                }
+    /// The colors of a line, which only get worked out again once the text around it changes.
+    /// Lines are drawn without colors while there is no tree.
+    fn line_highlight(&self, row: usize) -> Rc<LineHighlight> {
+        let tree = match &self.tree {
+            None => return Rc::new(LineHighlight::default()),
+            Some(tree) => tree,
+        };
+        let revision = self.contents.revision();
+        let highlights = self.settings.borrow().colors.treesitter.clone();
+        if let Some(line) = self.highlight_cache.borrow_mut().get(row, revision, &highlights) {
+            return line;
+        }
 
-                
-                let string = "-".repeat(cols);
+        let line = match self.contents.get_row(row, 0, usize::MAX) {
+            None => String::new(),
+            Some(line) => line.to_string(),
+        };
+        let start = self.contents.get_byte_offset(0, row).unwrap_or(0);
+        let line = match &self.highlight_query {
+            Some(query) => {
+                let captures = self.settings.borrow().colors.captures.clone();
+                LineHighlight::from_query(tree, &line, start, query, &captures)
+            },
+            None => {
+                let default = HashMap::new();
+                let syntax_highlighting = highlights.get(&self.lang).unwrap_or(&default);
+                let color_settings = self.settings.borrow().colors.pane.clone();
+                LineHighlight::new(tree, &line, start, syntax_highlighting, &color_settings)
+            },
+        };
+        self.highlight_cache.borrow_mut().insert(row, revision, self.contents.get_line_count(), &highlights, line)
+    }
 
-                for c in string.chars() {
-                    output.push(Some(Some(StyledChar::new(c, color_settings.clone()))));
-                }
-                
-                //output.push_str(apply_colors!("-".repeat(cols), color_settings));
-                return;
-            }
-            else {
-                let ((_, y), _) = container.get_corners();
+    /// Tells the syntax tree about an edit the buffer just went through and parses the text again around it.
+    /// The highlighting of the lines the edit touched, or whose nodes it changed, gets thrown away.
+    fn apply_edit(&mut self, edit: InputEdit) {
+        if let Some(tree) = &mut self.tree {
+            tree.edit(&edit);
+        }
 
-                if y != 0 {
-                    index = index.saturating_sub(1);
-                }
-            }
+        let start = self.contents.position_of_byte(edit.start_byte).1;
+        let end = self.contents.position_of_byte(edit.new_end_byte).1.max(start);
+        self.highlight_cache.borrow_mut().edit(start..=end, self.contents.revision(), self.contents.get_line_count());
 
-            if x1 != 0 {
-                let string = "|".to_string();
+        // The edited tree is drawn until the new one comes back
+        if self.parses_in_background() {
+            let language = self.parser.language().expect("parser has no language");
+            self.background_parser.get_or_insert_with(|| BackgroundParser::new(language))
+                .edit(edit, start, self.contents.rope(), self.tree.as_ref());
+            return;
+        }
+        if let Some(background_parser) = &mut self.background_parser {
+            background_parser.cancel();
+        }
 
-                for c in string.chars() {
-                    output.push(Some(Some(StyledChar::new(c, color_settings.clone()))));
-                }
-                
-                //cols = cols.saturating_sub(1);
+        let tree = self.parser.parse(self.contents.to_string(), self.tree.as_ref()).unwrap();
+        if let Some(old_tree) = &self.tree {
+            let mut cache = self.highlight_cache.borrow_mut();
+            for range in old_tree.changed_ranges(&tree) {
+                cache.forget(range.start_point.row..=range.end_point.row);
             }
         }
 
-        let real_row = self.cursor.borrow().row_offset + index;
-        let col_offset = self.cursor.borrow().col_offset;
-        //eprintln!("Col offset: {}", col_offset);
+        self.tree = Some(tree);
+    }
 
-        let number_of_lines = self.contents.get_line_count();
+    /// Parses the whole text again, for changes that are too big to tell the tree about.
+    fn parse_from_scratch(&mut self) {
+        self.highlight_cache.borrow_mut().clear();
+        self.spelled_revision = None;
+
+        if self.parses_in_background() {
+            let language = self.parser.language().expect("parser has no language");
+            self.background_parser.get_or_insert_with(|| BackgroundParser::new(language))
+                .parse_from_scratch(self.contents.rope());
+            // The old tree doesn't fit the text anymore
+            self.tree = None;
+            return;
+        }
+        if let Some(background_parser) = &mut self.background_parser {
+            background_parser.cancel();
+        }
 
-        let mut num_width = 0;
+        self.tree = Some(self.parser.parse(self.contents.to_string(), None).unwrap());
+    }
 
-        if self.settings.borrow().editor_settings.line_number {
+    /// Whether the text is big enough that parsing it would hold up typing.
+    fn parses_in_background(&self) -> bool {
+        self.contents.get_byte_count() > self.settings.borrow().editor_settings.background_parse_size
+    }
 
-            let color_settings = &self.settings.borrow().colors.ui;
-            
+    /// Takes the tree the parser thread finished, if there is one.
+    /// The highlighting drawn with the tree before it is thrown away from the first row that changed.
+    fn receive_tree(&mut self) {
+        let (tree, changed_from) = match self.background_parser.as_mut().and_then(BackgroundParser::receive) {
+            None => return,
+            Some(parsed) => parsed,
+        };
+        self.tree = Some(tree);
+        self.spelled_revision = None;
 
-            if !self.settings.borrow().editor_settings.relative_line_number {
+        self.highlight_cache.borrow_mut().forget(changed_from..=usize::MAX);
+        // The depth a row starts at only depends on the rows above it
+        self.rainbow_depths.borrow_mut().1.truncate(changed_from.saturating_add(1));
+    }
 
+    /// Finds the bracket that matches the one at a position.
+    /// The syntax tree knows which brackets belong together, so counting is only done when the tree can't tell.
+    fn find_matching_bracket(&self, (x, y): (usize, usize)) -> Option<(usize, usize)> {
+        let line = self.cursor_line(y);
+        let c = line.chars().nth(x)?;
+        if !brackets::is_bracket(c) {
+            return None;
+        }
 
-                let mut places = 1;
-                while places <= number_of_lines {
-                    places *= 10;
-                    num_width += 1;
+        let byte = self.contents.get_byte_offset(x, y)?;
+        let partner = self.tree.as_ref()
+            .and_then(|tree| tree.root_node().descendant_for_byte_range(byte, byte + c.len_utf8()))
+            .filter(|node| node.start_byte() == byte && node.kind().len() == 1)
+            .and_then(|node| {
+                let parent = node.parent()?;
+                let first = parent.child(0)?;
+                let last = parent.child(parent.child_count() - 1)?;
+
+                let partner = if node == first { last } else if node == last { first } else { return None };
+                let kind = partner.kind();
+                if kind.len() == 1 && kind.chars().all(brackets::is_bracket) && partner != node {
+                    Some(partner)
+                }
+                else {
+                    None
                 }
+            });
+
+        match partner {
+            None => brackets::find_match(&self.contents, (x, y)),
+            Some(partner) => {
+                let point = partner.start_position();
+                let line = self.cursor_line(point.row);
+                let col = line.char_indices().take_while(|(index, _)| *index < point.column).count();
+                Some((col, point.row))
+            },
+        }
+    }
 
-                if real_row + 1 <= number_of_lines {
+    /// The text of a line without its line break.
+    fn cursor_line(&self, row: usize) -> String {
+        match self.contents.get_row(row, 0, usize::MAX) {
+            None => String::new(),
+            Some(line) => line.to_string().trim_end_matches(['\n', '\r']).to_string(),
+        }
+    }
 
+    /// The bracket that the text before the cursor opens a block with.
+    /// Brackets in strings and comments don't count.
+    fn block_opener(&self, before: &str, row: usize) -> Option<char> {
+        let (index, c) = indent::block_opener(before, Some(&self.lang))?;
+        let byte = self.contents.get_byte_offset(before[..index].chars().count(), row)?;
+
+        let mut node = self.tree.as_ref()
+            .and_then(|tree| tree.root_node().descendant_for_byte_range(byte, byte + c.len_utf8()));
+        while let Some(current) = node {
+            if current.kind().contains("string") || current.kind().contains("comment") {
+                return None;
+            }
+            node = current.parent();
+        }
+        Some(c)
+    }
 
-                    if !self.cursor.borrow().get_scrolled() {
-                        for _ in 0..num_width {
-                            output.push(None);
-                        }
-                    }
-                    else {
-                        
+    /// Lets the user know that the file had bytes that didn't fit the encoding.
+    fn report_decode_errors(&self, had_errors: bool) {
+        if had_errors {
+            let message = format!("Some characters could not be read as {}", self.encoding.name());
+            self.sender.send(Message::SetStatus(message)).expect("Failed to send message");
+        }
+    }
 
-                        let string = format!("{:width$}", real_row + 1, width = num_width);
+    /// Loads the undo history saved with the file when `persistent_undo` is on.
+    fn load_undo_history(&mut self, filename: &Path) {
+        let settings = self.settings.borrow();
+        if settings.editor_settings.persistent_undo {
+            undo::load_history(settings.undo_dir.as_deref(), filename, &mut self.contents);
+        }
+    }
 
-                        for c in string.chars() {
-                            output.push(Some(Some(StyledChar::new(c, color_settings.clone()))));
-                        }
-                    }
-                }
+    /// Writes the buffer to its file.
+    /// Forcing it writes over read only files, like `:w!`.
+    fn write_buffer(&mut self, force: bool) -> io::Result<()> {
+        self.flush_changes();
+        if let Some(file_name) = &self.file_name {
+            let text = self.settings.borrow().editor_settings.prepare_save(self.contents.to_string());
 
+            let (bytes, had_errors) = self.encoding.encode(&text);
+
+            let options = SaveOptions::new(&self.settings.borrow(), force);
+            if let Some(warning) = save_file(file_name, &bytes, &options)? {
+                self.sender.send(Message::SetMessage(warning, MessageKind::Warning)).expect("Failed to send message");
             }
-            else if self.settings.borrow().editor_settings.relative_line_number {
+            self.changed = false;
+            self.disk_watch.update(file_name);
 
-                let mut places = 1;
-                num_width = 3;
-                while places <= number_of_lines {
-                    places *= 10;
-                    num_width += 1;
-                }
-                
-                if real_row == self.cursor.borrow().get_cursor().1 && real_row + 1 <= number_of_lines {
-                    let string = format!("{:<width$}", real_row + 1 , width = num_width);
+            if had_errors {
+                let message = format!("Some characters could not be written as {}", self.encoding.name());
+                self.sender.send(Message::SetStatus(message)).expect("Failed to send message");
+            }
 
-                    for c in string.chars() {
-                        output.push(Some(Some(StyledChar::new(c, color_settings.clone()))));
-                    }
-                }
-                else if real_row + 1 <= number_of_lines {
-                    let string = format!("{:width$}",
-                                            ((real_row) as isize - (self.cursor.borrow().get_cursor().1 as isize)).abs() as usize,
-                                            width = num_width);
+            self.sender.send(Message::AddRecentFile(file_name.clone())).expect("Failed to send message");
 
-                    for c in string.chars() {
-                        output.push(Some(Some(StyledChar::new(c, color_settings.clone()))));
-                    }
+            let settings = self.settings.borrow();
+            if settings.editor_settings.persistent_undo {
+                if let Err(error) = undo::save_history(settings.undo_dir.as_deref(), file_name, &self.contents) {
+                    let message = format!("Failed to save undo history: {}", error);
+                    self.sender.send(Message::SetStatus(message)).expect("Failed to send message");
                 }
             }
-
         }
+        Ok(())
+    }
 
-        self.cursor.borrow_mut().number_line_size = num_width;
+    fn report_save_error(&self, error: io::Error) {
+        let message = format!("Failed to save: {}", error);
+        self.sender.send(Message::SetMessage(message, MessageKind::Error)).expect("Failed to send message");
+    }
 
-        let color_settings = &self.settings.borrow().colors.pane;
-        let syntax_highlighting = self.settings.borrow().colors.treesitter.clone();
-        let default = HashMap::new();
-        let syntax_highlighting = syntax_highlighting.get(&self.lang).unwrap_or(&default);
+    /// Whether another program wrote to the file since we read it, telling the user that saving needs a `!`.
+    fn changed_on_disk(&self) -> bool {
+        let changed = match &self.file_name {
+            None => false,
+            Some(file_name) => self.disk_watch.changed(file_name),
+        };
+        if changed {
+            let message = "The file changed on disk since it was read (add ! to override)".to_string();
+            self.sender.send(Message::SetMessage(message, MessageKind::Error)).expect("Failed to send message");
+        }
+        changed
+    }
 
-        if !self.cursor.borrow().get_scrolled() {
-            //eprintln!("Not Changed");
-            for _ in 0..num_width {
-                output.push(None);
-            }
+    /// Reads the file again, throwing away the changes in the buffer.
+    /// The server is told about the file as if it was opened for the first time.
+    fn reload_from_disk(&mut self) -> io::Result<()> {
+        let encoding = self.encoding.encoding;
+        self.reload_with_encoding(encoding)?;
+
+        if let Some((sender, _)) = &self.lsp_client {
+            sender.send(ControllerMessage::Notification(
+                self.lang.clone().into(),
+                LspNotification::Close(self.generate_uri().into())
+            )).expect("Failed to send message");
         }
+        self.send_open();
+        Ok(())
+    }
 
+    /// Reloads the file when another program changed it and the buffer has nothing to lose.
+    /// Otherwise the user picks between the two versions.
+    fn check_disk(&mut self, container: &PaneContainer) {
+        if !matches!(self.waiting, Waiting::None) {
+            return;
+        }
+        let file_name = match &self.file_name {
+            None => return,
+            Some(file_name) => file_name.clone(),
+        };
+        if !self.disk_watch.poll(&file_name) {
+            return;
+        }
 
-        if !self.cursor.borrow().get_scrolled() {
-            //eprintln!("Not Changed");
-            for _ in 0..(cols - num_width) {
-                output.push(None);
-            }
+        if self.changed {
+            self.ask_disk_change(container);
         }
         else {
+            self.run_command("e!", container);
+        }
+    }
 
-            if let Some(row) = self.get_row(real_row, col_offset, cols - num_width) {
-                //eprintln!("Row: {}", row);
-                let mut count = 0;
+    fn ask_disk_change(&mut self, container: &PaneContainer) {
+        let (send, recv) = std::sync::mpsc::channel();
+        let (send2, recv2) = std::sync::mpsc::channel();
 
-                row.chars().for_each(|c| if count != (cols - num_width) {
-                    let point1 = Point::new(real_row, count);
-                    let point2 = Point::new(real_row, count + 1);
-                    let node = self.tree.root_node().descendant_for_point_range(point1, point2).unwrap();
-                    let parent_node = node.parent();
+        self.popup_channels = Some((send2, recv));
 
-                    match c {
-                        '\t' => {
-                            let string = " ".repeat(self.settings.borrow().editor_settings.tab_size);
+        let mut buttons = Vec::new();
+        for (label, answer) in [("Reload", "reload"), ("Keep", "keep"), ("Diff", "diff")] {
+            let function: ButtonFunction = Box::new(move |_| answer.to_string());
+            buttons.push((label.to_string(), function));
+        }
+        let buttons = PromptType::Button(buttons, 1);
+
+        let prompt = vec!["File changed on disk".to_string()];
+        let width = 30;
+
+        let pane = PopUpPane::new_prompt(
+            self.settings.clone(),
+            prompt,
+            self.sender.clone(),
+            send,
+            recv2,
+            vec![buttons],
+            true
+        );
+        let pane = Rc::new(RefCell::new(pane));
 
-                            count += self.settings.borrow().editor_settings.tab_size;
+        let ((x1, y1), _) = container.get_corners();
+        let (x, y) = container.get_size();
+        let size = (width.min(x), 4.min(y));
+        let pos = ((x1 + x / 2).saturating_sub(size.0 / 2), (y1 + y / 2).saturating_sub(size.1 / 2));
 
-                            for c in string.chars() {
-                                output.push(Some(Some(StyledChar::new(c, color_settings.clone()))));
-                            }
-                        },
-                        '\n' => {
-                            count += 1;
-                            let string = " ".to_string();
+        let mut container = PaneContainer::new(container.get_size(), size, pane, self.settings.clone());
+        container.set_position(pos);
+        container.set_size(size);
 
-                            for c in string.chars() {
-                                output.push(Some(Some(StyledChar::new(c, color_settings.clone()))));
-                            }
-                        },
-                        ' ' => {
-                            count += 1;
-                            let string = " ".to_string();
+        self.sender.send(Message::CreatePopup(container, true)).expect("Failed to send message");
+        self.waiting = Waiting::DiskChange;
+    }
 
-                            for c in string.chars() {
-                                output.push(Some(Some(StyledChar::new(c, color_settings.clone()))));
-                            }
-                        },
-                        c => {
-                            count += 1;
-                            let string = c.to_string();
+    fn answer_disk_change(&mut self, answer: &str, container: &PaneContainer) {
+        match answer {
+            "reload" => self.run_command("e!", container),
+            "diff" => self.show_disk_diff(),
+            _ => {},
+        }
+    }
 
-                            for c in string.chars() {
+    /// Shows how the file on disk differs from the buffer in a pane below.
+    fn show_disk_diff(&mut self) {
+        let file_name = match &self.file_name {
+            None => return,
+            Some(file_name) => file_name.clone(),
+        };
+        let disk = match FileEncoding::read(&file_name, Some(self.encoding.encoding)) {
+            Ok((disk, _, _)) => disk,
+            Err(error) => {
+                self.sender.send(Message::SetMessage(error.to_string(), MessageKind::Error)).expect("Failed to send message");
+                return;
+            },
+        };
 
-                                let color_settings = if let Some(settings) = syntax_highlighting.get(&node.kind().to_string()) {
-                                    match settings {
-                                        SyntaxHighlight::Child(settings) => {
-                                            settings
-                                        }
-                                        SyntaxHighlight::ChildExclude(settings, exclude) => {
-                                            if exclude.contains(&c) {
-                                                color_settings
-                                            }
-                                            else {
-                                                settings
-                                            }
-                                        }
-                                        _ => {
-                                            color_settings
-                                        }
-                                    }
-                                }
-                                else if let Some(mut parent) = parent_node {
-                                    let mut colors = color_settings;
+        let mut pane = PlainTextPane::new(self.settings.clone(), self.sender.clone());
+        let mut diff = Buffer::from(diff_lines(&self.contents.to_string(), &disk).join("\n"));
+        diff.set_settings(self.settings.clone());
+        *pane.borrow_mut_buffer() = diff;
 
-                                    if let Some(settings) = syntax_highlighting.get(&parent.kind().to_string()) {
-                                        match settings {
-                                            SyntaxHighlight::Child(settings) => {
-                                                colors = settings;
-                                            }
-                                            SyntaxHighlight::ChildExclude(settings, exclude) => {
-                                                if exclude.contains(&c) {
-                                                    colors = color_settings;
-                                                }
-                                                else {
-                                                    colors = settings;
-                                                }
-                                            }
-                                            SyntaxHighlight::Parent(color_map) => {
-                                                let mut cursor = parent.walk();
+        self.sender.send(Message::HorizontalSplitWithPane(Rc::new(RefCell::new(pane)))).expect("Failed to send message");
+    }
 
-                                                let mut index = 0;
 
-                                                for child_node in parent.children(&mut cursor) {
-                                                    if child_node.id() == node.id() {
-                                                        break;
-                                                    }
-                                                    index += 1;
-                                                }
+    fn get_row(&self, row: usize, offset: usize, col: usize) -> Option<RopeSlice> {
 
-                                                if let Some(field) = parent.field_name_for_child(index) {
-                                                    if let Some(highlight) = color_map.get(field) {
-                                                        colors = match highlight {
-                                                            SyntaxHighlight::Child(settings) => settings,
-                                                            SyntaxHighlight::ChildExclude(settings, exclude) => {
-                                                                if exclude.contains(&c) {
-                                                                    color_settings
-                                                                }
-                                                                else {
-                                                                    settings
-                                                                }
-                                                            }
-                                                            _ => {
-                                                                color_settings
-                                                            }
-                                                        };
-                                                    }
-                                                }
-                                            },
-                                            SyntaxHighlight::ParentExclude(color_map, exclude) => {
-                                                if exclude.contains(&c) {
-                                                    colors = color_settings;
-                                                }
-                                                else {
-                                                    let mut cursor = parent.walk();
+        self.contents.get_row(row, offset, col)
+    }
 
-                                                    let mut index = 0;
+    pub fn borrow_buffer(&self) -> &Buffer {
+        &self.contents
+    }
 
-                                                    for child_node in parent.children(&mut cursor) {
-                                                        if child_node.id() == node.id() {
-                                                            break;
-                                                        }
-                                                        index += 1;
-                                                    }
+    pub fn borrow_buffer_mut(&mut self) -> &mut Buffer {
+        &mut self.contents
+    }
 
-                                                    if let Some(field) = parent.field_name_for_child(index) {
-                                                        if let Some(highlight) = color_map.get(field) {
-                                                            colors = match highlight {
-                                                                SyntaxHighlight::Child(settings) => settings,
-                                                                SyntaxHighlight::ChildExclude(settings, exclude) => {
-                                                                    if exclude.contains(&c) {
-                                                                        color_settings
-                                                                    }
-                                                                    else {
-                                                                        settings
-                                                                    }
-                                                                }
-                                                                _ => {
-                                                                    color_settings
-                                                                }
-                                                            };
-                                                        }
-                                                    }
-                                                }
-                                            },
-                                            SyntaxHighlight::GrandParent(grandparents) => {
-                                                if let Some(highlight) = grandparents.get(&parent.kind().to_string()) {
-                                                    match highlight {
-                                                        SyntaxHighlight::Child(ref settings) => {
-                                                            colors = settings;
-                                                        }
-                                                        SyntaxHighlight::ChildExclude(ref settings, exclude) => {
-                                                            if exclude.contains(&c) {
-                                                                colors = color_settings;
-                                                            }
-                                                            else {
-                                                                colors = settings;
-                                                            }
-                                                        }
-                                                        SyntaxHighlight::Parent(color_map) => {
-                                                            let mut cursor = parent.walk();
-
-                                                            let mut index = 0;
-
-                                                            for child_node in parent.children(&mut cursor) {
-                                                                if child_node.id() == node.id() {
-                                                                    break;
-                                                                }
-                                                                index += 1;
-                                                            }
-
-                                                            if let Some(field) = parent.field_name_for_child(index) {
-                                                                if let Some(highlight) = color_map.get(field) {
-                                                                    colors = match highlight {
-                                                                        SyntaxHighlight::Child(settings) => settings,
-                                                                        SyntaxHighlight::ChildExclude(settings, exclude) => {
-                                                                            if exclude.contains(&c) {
-                                                                                color_settings
-                                                                            }
-                                                                            else {
-                                                                                settings
-                                                                            }
-                                                                        }
-                                                                        _ => {
-                                                                            color_settings
-                                                                        }
-                                                                    };
-                                                                }
-                                                            }
-                                                        },
-                                                        SyntaxHighlight::ParentExclude(color_map, exclude) => {
-                                                            if exclude.contains(&c) {
-                                                                colors = color_settings;
-                                                            }
-                                                            else {
-                                                                let mut cursor = parent.walk();
-
-                                                                let mut index = 0;
-
-                                                                for child_node in parent.children(&mut cursor) {
-                                                                    if child_node.id() == node.id() {
-                                                                        break;
-                                                                    }
-                                                                    index += 1;
-                                                                }
-
-                                                                if let Some(field) = parent.field_name_for_child(index) {
-                                                                    if let Some(highlight) = color_map.get(field) {
-                                                                        colors = match highlight {
-                                                                            SyntaxHighlight::Child(settings) => settings,
-                                                                            SyntaxHighlight::ChildExclude(settings, exclude) => {
-                                                                                if exclude.contains(&c) {
-                                                                                    color_settings
-                                                                                }
-                                                                                else {
-                                                                                    settings
-                                                                                }
-                                                                            }
-                                                                            _ => {
-                                                                                color_settings
-                                                                            }
-                                                                        };
-                                                                    }
-                                                                }
-                                                            }
-                                                        },
-                                                        _ => {
-                                                            colors = color_settings;
-                                                        }
-                                                    }
-                                                }
-                                                else {
-                                                    let old_parent = parent;
-                                                    while let Some(new_parent) = parent.parent() {
-                                                        parent = new_parent;
-                                                        if let Some(highlight) = grandparents.get(&parent.kind().to_string()) {
-
-                                                            match highlight {
-                                                                SyntaxHighlight::Child(ref settings) => {
-                                                                    colors = settings;
-                                                                }
-                                                                SyntaxHighlight::ChildExclude(ref settings, exclude) => {
-                                                                    if exclude.contains(&c) {
-                                                                        colors = color_settings;
-                                                                    }
-                                                                    else {
-                                                                        colors = settings;
-                                                                    }
-                                                                }
-                                                                SyntaxHighlight::Parent(color_map) => {
-                                                                    let mut cursor = old_parent.walk();
-
-                                                                    let mut index = 0;
-
-                                                                    for child_node in old_parent.children(&mut cursor) {
-                                                                        if child_node.id() == node.id() {
-                                                                            break;
-                                                                        }
-                                                                        index += 1;
-                                                                    }
-
-                                                                    if let Some(field) = old_parent.field_name_for_child(index) {
-                                                                        if let Some(highlight) = color_map.get(field) {
-                                                                            colors = match highlight {
-                                                                                SyntaxHighlight::Child(settings) => settings,
-                                                                                SyntaxHighlight::ChildExclude(settings, exclude) => {
-                                                                                    if exclude.contains(&c) {
-                                                                                        color_settings
-                                                                                    }
-                                                                                    else {
-                                                                                        settings
-                                                                                    }
-                                                                                }
-                                                                                _ => {
-                                                                                    color_settings
-                                                                                }
-                                                                            };
-                                                                        }
-                                                                    }
-                                                                },
-                                                                SyntaxHighlight::ParentExclude(color_map, exclude) => {
-                                                                    if exclude.contains(&c) {
-                                                                        colors = color_settings;
-                                                                    }
-                                                                    else {
-                                                                        let mut cursor = parent.walk();
-
-                                                                        let mut index = 0;
-
-                                                                        for child_node in old_parent.children(&mut cursor) {
-                                                                            if child_node.id() == node.id() {
-                                                                                break;
-                                                                            }
-                                                                            index += 1;
-                                                                        }
-
-                                                                        if let Some(field) = old_parent.field_name_for_child(index) {
-                                                                            if let Some(highlight) = color_map.get(field) {
-                                                                                colors = match highlight {
-                                                                                    SyntaxHighlight::Child(settings) => settings,
-                                                                                    SyntaxHighlight::ChildExclude(settings, exclude) => {
-                                                                                        if exclude.contains(&c) {
-                                                                                            color_settings
-                                                                                        }
-                                                                                        else {
-                                                                                            settings
-                                                                                        }
-                                                                                    }
-                                                                                    _ => {
-                                                                                        color_settings
-                                                                                    }
-                                                                                };
-                                                                            }
-                                                                        }
-                                                                    }
-                                                                },
-                                                                _ => {
-                                                                    colors = color_settings;
-                                                                }
-                                                            }
-                                                            break;
-                                                        }
+    pub fn get_mode(&self, name: &str) -> Option<Rc<RefCell<dyn Mode>>> {
+        self.modes.get(name).map(|m| m.clone())
+    }
 
-                                                    }
 
-                                                }
+    fn get_byte_offset(&self) -> Option<usize> {
+        let (x, y) = self.cursor.borrow().get_cursor();
 
-                                            }
+        self.contents.get_byte_offset(x, y)
+    }
+
+    fn check_messages(&mut self, container: &PaneContainer) {
+        match self.popup_channels.as_ref() {
+            None => {},
+            Some((_, receiver)) => {
+                match receiver.try_recv() {
+                    Ok(message) => {
+                        match message {
+                            PaneMessage::String(string) => {
 
+                                match self.waiting {
+                                    Waiting::JumpTarget => {
+                                        self.waiting = Waiting::JumpPosition;
+                                        let command = format!("jump {}", string);
+                                        self.run_command(&command, container);
+                                    },
+                                    Waiting::JumpPosition => {
+                                        self.waiting = Waiting::None;
+                                        let command = format!("set_jump {}", string);
+                                        self.run_command(&command, container);
+                                    },
+                                    Waiting::Completion => {
+                                        self.waiting = Waiting::None;
+                                        let command = format!("insert {}", string);
+                                        self.run_command(&command, container);
+                                    },
+                                    Waiting::Goto => {
+                                        self.waiting = Waiting::None;
+                                        let command = format!("goto {}", string);
+                                        self.run_command(&command, container);
+                                    },
+                                    Waiting::Spelling => {
+                                        self.waiting = Waiting::None;
+                                        let command = format!("spell_replace {}", string);
+                                        self.run_command(&command, container);
+                                    },
+                                    Waiting::Search => {
+                                        self.waiting = Waiting::None;
+                                        // The jump starts from before the preview moved the cursor
+                                        self.end_search_preview();
+                                        self.search(&string);
+                                    },
+                                    Waiting::Substitute => {
+                                        self.waiting = Waiting::None;
+                                        self.answer_substitute(&string, container);
+                                    },
+                                    Waiting::Rename => {
+                                        self.waiting = Waiting::None;
+                                        let command = format!("rename {}", string);
+                                        self.run_command(&command, container);
+                                    },
+                                    Waiting::CodeAction => {
+                                        self.waiting = Waiting::None;
+                                        let command = format!("apply_code_action {}", string);
+                                        self.run_command(&command, container);
+                                    },
+                                    Waiting::DiskChange => {
+                                        self.waiting = Waiting::None;
+                                        self.answer_disk_change(&string, container);
+                                    },
+                                    // Nothing is asked of the user while waiting on the server
+                                    Waiting::CompletionResponse | Waiting::GotoResponse |
+                                    Waiting::ReferencesResponse | Waiting::HoverResponse |
+                                    Waiting::RenameResponse | Waiting::FormattingResponse |
+                                    Waiting::CodeActionResponse => {},
+                                    Waiting::None => {
+                                    },
+                                }
+                            },
+                            PaneMessage::PreviewString(string) => {
+                                if let Waiting::Search = self.waiting {
+                                    self.preview_search(&string);
+                                }
+                            },
+                            PaneMessage::Cancel => {
+                                match self.waiting {
+                                    Waiting::DiskChange => self.waiting = Waiting::None,
+                                    Waiting::Search => {
+                                        self.waiting = Waiting::None;
+                                        self.end_search_preview();
+                                    },
+                                    Waiting::Substitute => {
+                                        self.waiting = Waiting::None;
+                                        if let Some(run) = self.substitute_run.take() {
+                                            self.finish_substitute(run);
                                         }
+                                    },
+                                    _ => {},
+                                }
+                            },
+                            PaneMessage::Close => {
+                                eprintln!("Closing treesitter");
+                                self.run_command("q!", container)
+                            },
+                        }
+                    },
+                    Err(_) => {},
+                }
+            }
+        }
+    }
 
+    fn generate_uri(& self) -> String {
+        match &self.file_name {
+            None => {
+                let working_dir = std::env::current_dir().unwrap();
+                format!("untitled://{}", working_dir.display())
+            },
+            Some(file_name) => path_to_uri(file_name),
+        }
+    }
 
+    fn read_lsp_messages(&mut self) {
+        let mut edits = Vec::new();
+        match self.lsp_client.as_ref() {
+            None => {},
+            Some((sender, receiver)) => {
+                let mut other_uri_count = 0;
+                loop {
+                    let message = receiver.lock().expect("Lsp receiver poisoned").try_recv();
+                    match message {
+                        Ok(ControllerMessage::Response(resp)) => {
+                            match resp {
+                                LspResponse::PublishDiagnostics(diags) => {
+                                    if diags.uri == self.generate_uri() {
+                                        // Every publish has all the diagnostics of the file
+                                        *self.lsp_diagnostics.borrow_mut() = diags;
+                                    }
+                                    else {
+                                        if other_uri_count == 4 {
+                                            break;
+                                        }
+                                        other_uri_count += 1;
+                                        sender.send(ControllerMessage::Resend(
+                                            self.lang.clone().into(),
+                                            LspResponse::PublishDiagnostics(diags)
+                                        )).unwrap();
+                                    }
+                                },
+                                LspResponse::ApplyEdit(edit, id) => {
+                                    // Whichever pane of the language gets it makes the edit
+                                    edits.push((edit, id));
+                                },
+                                resp if !self.is_waiting_for(&resp) => {
+                                    // Another pane of the same language asked for this
+                                    other_uri_count += 1;
+                                    sender.send(ControllerMessage::Resend(
+                                        self.lang.clone().into(),
+                                        resp
+                                    )).unwrap();
+                                    if other_uri_count == 4 {
+                                        break;
                                     }
+                                },
+                                LspResponse::Completion(completions) => {
+                                    self.lsp_completion = Some(completions);
+                                },
+                                LspResponse::Location(location) => {
+                                    self.lsp_location = Some(location);
+                                },
+                                LspResponse::Hover(text) => {
+                                    self.lsp_hover = Some(text);
+                                },
+                                LspResponse::WorkspaceEdit(edit) => {
+                                    self.lsp_workspace_edit = Some(edit);
+                                },
+                                LspResponse::Formatting(edits) => {
+                                    self.lsp_formatting = Some(edits);
+                                },
+                                LspResponse::CodeActions(actions) => {
+                                    self.lsp_code_actions = Some(actions);
+                                },
+                            }
 
+                        },
+                        Ok(_) => {
+                        },
+                        Err(_) => {
+                            break;
+                        },
+                    }
+                }
+            },
+        }
 
+        for (edit, id) in edits {
+            self.apply_workspace_edit(edit, Some(id));
+        }
+    }
 
-                                    colors
-                                }
-                                else {
-                                    color_settings
-                                };
+    /// Sends a request to the server without waiting for the answer.
+    /// `check_lsp_response` finishes what was asked for once the answer arrives.
+    fn send_lsp_request(&mut self, request: LspRequest, waiting: Waiting) {
+        self.flush_changes();
+        let sender = match &self.lsp_client {
+            None => return,
+            Some((sender, _)) => sender,
+        };
 
+        sender.send(ControllerMessage::Request(
+            self.lang.clone().into(),
+            request
+        )).expect("Failed to send message");
 
+        self.waiting = waiting;
+        self.lsp_request_time = Some(Instant::now());
+    }
 
+    /// Whether an answer from the server is the one the pane is waiting on.
+    fn is_waiting_for(&self, response: &LspResponse) -> bool {
+        matches!((&self.waiting, response),
+                 (Waiting::CompletionResponse, LspResponse::Completion(_)) |
+                 (Waiting::GotoResponse | Waiting::ReferencesResponse, LspResponse::Location(_)) |
+                 (Waiting::HoverResponse, LspResponse::Hover(_)) |
+                 (Waiting::RenameResponse, LspResponse::WorkspaceEdit(_)) |
+                 (Waiting::FormattingResponse, LspResponse::Formatting(_)) |
+                 (Waiting::CodeActionResponse, LspResponse::CodeActions(_)))
+    }
 
-                                match c {
-                                    '(' | ')' | '{' | '}' | '[' | ']' | '<' | '>' => {
-                                        if self.settings.borrow().editor_settings.rainbow_delimiters {
-                                            let colors = &self.settings.borrow().colors.rainbow_delimiters;
-                                            match c {
-                                                '(' => {
-                                                    let index = self.rainbow_delimiters.borrow().len() % colors.len();
-                                                    self.rainbow_delimiters.borrow_mut().push((c, colors[index].clone()));
-                                                    output.push(Some(Some(StyledChar::new(c, colors[index].clone()))));
-                                                },
-                                                '{' => {
-                                                    let index = self.rainbow_delimiters.borrow().len() % colors.len();
-                                                    self.rainbow_delimiters.borrow_mut().push((c, colors[index].clone()));
-                                                    output.push(Some(Some(StyledChar::new(c, colors[index].clone()))));
-                                                },
-                                                '[' => {
-                                                    let index = self.rainbow_delimiters.borrow().len() % colors.len();
-                                                    self.rainbow_delimiters.borrow_mut().push((c, colors[index].clone()));
-                                                    output.push(Some(Some(StyledChar::new(c, colors[index].clone()))));
-                                                },
-                                                ')' => {
-                                                    let (_, color) = self.rainbow_delimiters.borrow_mut().pop().unwrap_or((c, color_settings.clone()));
-                                                    output.push(Some(Some(StyledChar::new(c, color))));
-                                                },
-                                                '}' => {
-                                                    let (_, color) = self.rainbow_delimiters.borrow_mut().pop().unwrap_or((c, color_settings.clone()));
-                                                    output.push(Some(Some(StyledChar::new(c, color))));
-                                                },
-                                                ']' => {
-                                                    let (_, color) = self.rainbow_delimiters.borrow_mut().pop().unwrap_or((c, color_settings.clone()));
-                                                    output.push(Some(Some(StyledChar::new(c, color))));
-                                                },
-                                                '<' => {
-                                                    if let Some(parent) = parent_node {
-
-                                                        match parent.kind() {
-                                                            "type_arguments" | "system_lib_string" => {
-                                                                let index = self.rainbow_delimiters.borrow().len() % colors.len();
-                                                                self.rainbow_delimiters.borrow_mut().push((c, colors[index].clone()));
-                                                                output.push(Some(Some(StyledChar::new(c, colors[index].clone()))));
-                                                            },
-                                                            _ => {
-                                                                match node.kind() {
-                                                                    "system_lib_string" => {
-                                                                        let index = self.rainbow_delimiters.borrow().len() % colors.len();
-                                                                        self.rainbow_delimiters.borrow_mut().push((c, colors[index].clone()));
-                                                                        output.push(Some(Some(StyledChar::new(c, colors[index].clone()))));
-                                                                    },
-                                                                    _ => {
-                                                                        output.push(Some(Some(StyledChar::new(c, color_settings.clone()))));
-                                                                    },
-                                                                }
-                                                            },
-                                                        }
-                                                    }
-                                                    else {
-                                                        match node.kind() {
-                                                            "system_lib_string" => {
-                                                                let index = self.rainbow_delimiters.borrow().len() % colors.len();
-                                                                self.rainbow_delimiters.borrow_mut().push((c, colors[index].clone()));
-                                                                output.push(Some(Some(StyledChar::new(c, colors[index].clone()))));
-                                                            },
-                                                            _ => {
-                                                                output.push(Some(Some(StyledChar::new(c, color_settings.clone()))));
-                                                            },
-                                                        }
-                                                    }
-                                                },
-                                                '>' => {
-                                                    if let Some(parent) = parent_node {
-
-                                                        match parent.kind() {
-                                                            "type_arguments" | "system_lib_string" => {
-                                                                let (_, color) = self.rainbow_delimiters.borrow_mut().pop().unwrap_or((c, color_settings.clone()));
-                                                                output.push(Some(Some(StyledChar::new(c, color))));
-                                                            },
-                                                            _ => {
-                                                                match node.kind() {
-                                                                    "system_lib_string" => {
-                                                                        let (_, color) = self.rainbow_delimiters.borrow_mut().pop().unwrap_or((c, color_settings.clone()));
-                                                                        output.push(Some(Some(StyledChar::new(c, color))));
-                                                                    },
-                                                                    _ => {
-                                                                        output.push(Some(Some(StyledChar::new(c, color_settings.clone()))));
-                                                                    },
-                                                                }
-                                                            },
-                                                        }
-                                                    }
-                                                    else {
-                                                        match node.kind() {
-                                                            "system_lib_string" => {
-                                                                let (_, color) = self.rainbow_delimiters.borrow_mut().pop().unwrap_or((c, color_settings.clone()));
-                                                                output.push(Some(Some(StyledChar::new(c, color))));
-                                                            },
-                                                            _ => {
-                                                                output.push(Some(Some(StyledChar::new(c, color_settings.clone()))));
-                                                            },
-                                                        }
-                                                    }
-                                                },
-                                                _ => {
-                                                },
-                                            }
-                                        }
-                                        else {
-                                            output.push(Some(Some(StyledChar::new(c, color_settings.clone()))));
-                                        }
-                                    },
-                                    _ => {
+    /// Finishes the request the pane is waiting on once the answer is in, or gives up on it after the timeout.
+    fn check_lsp_response(&mut self, container: &PaneContainer) {
+        let sent = match self.lsp_request_time {
+            None => return,
+            Some(sent) => sent,
+        };
 
-                                        let diagnostic = self.lsp_diagnostics.get_diagnostic(real_row, count);
-                                        //eprintln!("Diagnostic: {:?}", diagnostic);
-                                        match diagnostic {
-                                            None => output.push(Some(Some(StyledChar::new(c, color_settings.clone())))),
-                                            Some(diagnostic) => {
-                                                match diagnostic.severity {
-                                                    3 => {
-                                                        let mut color_settings = color_settings.add_attribute(Attribute::Undercurled);
-                                                        color_settings.underline_color = Color::DarkRed;
-                                                        output.push(Some(Some(StyledChar::new(c, color_settings))));
-                                                    },
-                                                    2 => {
-                                                        let mut color_settings = color_settings.add_attribute(Attribute::Undercurled);
-                                                        color_settings.underline_color = Color::DarkYellow;
-                                                        output.push(Some(Some(StyledChar::new(c, color_settings))));
-                                                    },
-                                                    1 | _ => {
-                                                        let mut color_settings = color_settings.add_attribute(Attribute::Undercurled);
-                                                        color_settings.underline_color = Color::Yellow;
-                                                        output.push(Some(Some(StyledChar::new(c, color_settings))));
-                                                    },
-                                                }
-                                            }
+        let answered = match self.waiting {
+            Waiting::CompletionResponse => self.lsp_completion.is_some(),
+            Waiting::GotoResponse | Waiting::ReferencesResponse => self.lsp_location.is_some(),
+            Waiting::HoverResponse => self.lsp_hover.is_some(),
+            Waiting::RenameResponse => self.lsp_workspace_edit.is_some(),
+            Waiting::FormattingResponse => self.lsp_formatting.is_some(),
+            Waiting::CodeActionResponse => self.lsp_code_actions.is_some(),
+            // A popup took over so the answer isn't wanted anymore
+            _ => {
+                self.lsp_request_time = None;
+                return;
+            },
+        };
 
-                                        }
+        if !answered {
+            let timeout = Duration::from_millis(self.settings.borrow().editor_settings.lsp_timeout);
+            if sent.elapsed() >= timeout {
+                let message = match std::mem::replace(&mut self.waiting, Waiting::None) {
+                    // Completions the user asked for come from the buffers instead
+                    Waiting::CompletionResponse if !self.auto_completion => Message::CompleteWords,
+                    _ => Message::SetMessage("The language server didn't answer in time".to_string(), MessageKind::Warning),
+                };
+                self.lsp_request_time = None;
+                self.format_range = None;
+                self.sender.send(message).expect("Failed to send message");
+            }
+            return;
+        }
 
-                                        //output.push(Some(StyledChar::new(c, color_settings.clone())));
-                                    }
-                                }
-                            }
-                        },
-                    }
-                }
-                                    else {
-                });
+        self.lsp_request_time = None;
+        match std::mem::replace(&mut self.waiting, Waiting::None) {
+            Waiting::CompletionResponse => self.show_completions(container),
+            Waiting::GotoResponse => self.finish_goto(container),
+            Waiting::ReferencesResponse => self.finish_references(container),
+            Waiting::HoverResponse => self.show_hover(container),
+            Waiting::RenameResponse => self.finish_rename(),
+            Waiting::FormattingResponse => self.finish_formatting(),
+            Waiting::CodeActionResponse => self.show_code_actions(container),
+            _ => {},
+        }
+    }
 
-                let string = " ".repeat(cols.saturating_sub(count + num_width));
+    /// Jumps to the diagnostic that was picked in the diagnostics list.
+    fn check_diagnostics_list(&mut self) {
+        let message = match &self.diagnostics_list {
+            None => return,
+            Some(receiver) => receiver.try_recv(),
+        };
 
-                for c in string.chars() {
-                    output.push(Some(Some(StyledChar::new(c, color_settings.clone()))));
-                }
+        if let Ok(PaneMessage::String(position)) = message {
+            let mut position = position.split_whitespace().filter_map(|n| n.parse::<usize>().ok());
+            if let (Some(col), Some(row)) = (position.next(), position.next()) {
+                self.set_location((col, row));
             }
-            else if real_row >= number_of_lines {
-                let string = " ".repeat(cols);
+        }
+    }
 
-                for c in string.chars() {
-                    output.push(Some(Some(StyledChar::new(c, color_settings.clone()))));
-                }
-            }
-            else {
-                let string = " ".repeat(cols.saturating_sub(num_width));
+    /// Opens a popup under the cursor with every diagnostic at the cursor position.
+    fn show_diagnostic(&mut self, container: &PaneContainer) {
+        let cursor = self.cursor.borrow().get_cursor();
+        let wrap_width = container.get_size().0.saturating_sub(2).clamp(1, INFO_WIDTH);
 
-                for c in string.chars() {
-                    output.push(Some(Some(StyledChar::new(c, color_settings.clone()))));
-                }
+        let mut body = Vec::new();
+        for diagnostic in self.lsp_diagnostics.borrow().get_diagnostics(cursor.1, cursor.0) {
+            if !body.is_empty() {
+                body.push(None);
             }
+            body.extend(diagnostic.popup_lines(wrap_width).into_iter().map(Some));
         }
-    }
 
-    fn refresh(&mut self, container: &mut PaneContainer) {
-        self.mode.borrow_mut().refresh();
-        self.check_messages(container);
-        if self.rainbow_delimiters.borrow().len() > 0 {
-            self.rainbow_delimiters.borrow_mut().clear();
+        if body.is_empty() {
+            self.sender.send(Message::SetMessage("No diagnostics under the cursor".to_string(), MessageKind::Info))
+                .expect("Failed to send message");
+            return;
         }
 
-        self.read_lsp_messages();
+        self.open_info_popup(body, container);
+    }
+
+    /// Shows the completions in a dropdown at the cursor.
+    fn show_completions(&mut self, container: &PaneContainer) {
+        let completion_list = match &self.lsp_completion {
+            None => return,
+            Some(completion_list) => completion_list,
+        };
+        if completion_list.items.is_empty() {
+            // The words of the buffers are better than nothing
+            if !self.auto_completion {
+                self.sender.send(Message::CompleteWords).expect("Failed to send message");
+            }
+            return;
+        }
 
+        let cursor = self.cursor.borrow().get_real_cursor();
+        let (popup, channels) = completion_popup(completion_list, cursor, self.auto_completion, container, &self.settings, &self.sender);
+        self.popup_channels = Some(channels);
 
+        self.sender.send(Message::CreatePopup(popup, !self.auto_completion)).expect("Failed to send message");
+        // The list stays around until one of the completions is inserted
+        // A list that opened while typing only gets picked from once `completion` focuses it
+        self.waiting = if self.auto_completion { Waiting::None } else { Waiting::Completion };
     }
 
+    /// Offers the files that complete the path before the cursor.
+    /// Returns false when the text before the cursor doesn't look like a path.
+    fn complete_path(&mut self, container: &PaneContainer) -> bool {
+        let (x, y) = self.cursor.borrow().get_cursor();
+        let hidden = self.settings.borrow().editor_settings.show_hidden_files;
+        let completion_list = match path_completions(&self.cursor_line(y), (x, y), hidden) {
+            None => return false,
+            Some(completion_list) => completion_list,
+        };
+        if completion_list.items.is_empty() {
+            self.sender.send(Message::SetMessage("No files to complete".to_string(), MessageKind::Info))
+                .expect("Failed to send message");
+            return true;
+        }
 
-    fn save_buffer(&mut self) -> io::Result<()> {
-        if let Some(file_name) = &self.file_name {
-            let mut file = std::fs::File::create(file_name)?;
-            file.write_all(self.contents.to_string().as_bytes())?;
+        if let Some((send, _)) = self.popup_channels.take() {
+            let _ = send.send(PaneMessage::Close);
         }
-        Ok(())
+        self.auto_completion = false;
+        self.lsp_completion = Some(completion_list);
+        self.show_completions(container);
+        true
     }
 
-    fn open_file(&mut self, filename: &PathBuf) -> io::Result<()> {
-        let file = std::fs::read_to_string(filename)?;
-        self.contents = Buffer::from(file);
-        self.contents.set_settings(self.settings.clone());
-        self.file_name = Some(PathBuf::from(filename));
+    /// Asks the server for completions at the cursor, replacing any list that is open.
+    /// `auto` is for when the request comes from typing rather than from the user asking.
+    fn request_completion(&mut self, trigger: &str, auto: bool) {
+        if let Some((send, _)) = self.popup_channels.take() {
+            let _ = send.send(PaneMessage::Close);
+        }
 
-        self.tree = self.parser.parse(self.contents.to_string().as_bytes(), None).unwrap();
-        //eprintln!("{}", self.contents.to_string());
+        let uri = self.generate_uri();
+        let position = self.cursor.borrow().get_cursor();
+
+        self.lsp_completion = None;
+        self.auto_completion = auto;
+        self.send_lsp_request(
+            LspRequest::RequestCompletion(uri.into(), position, trigger.into()),
+            Waiting::CompletionResponse
+        );
+    }
 
-        //eprintln!("{}", self.tree.root_node().to_sexp());
+    /// Asks for completions after a character was typed in Insert mode.
+    /// Trigger characters of the server always ask, other characters once enough of an identifier is typed.
+    fn auto_complete(&mut self, c: char) {
+        let (enabled, min_prefix) = {
+            let settings = self.settings.borrow();
+            (settings.editor_settings.auto_completion, settings.editor_settings.completion_min_prefix)
+        };
+        // Nothing is asked while another request or popup is going on
+        let idle = matches!(self.waiting, Waiting::None | Waiting::CompletionResponse);
+        if self.lsp_client.is_none() || !enabled || !idle || (self.lsp_completion.is_some() && !self.auto_completion) {
+            return;
+        }
 
-        let uri = self.generate_uri();
+        let (x, y) = self.cursor.borrow().get_cursor();
+        let before = self.cursor_line(y).chars().take(x).collect::<String>();
 
-        
-        
-        match &self.lsp_client {
-            None => {},
-            Some((sender, _)) => {
-                sender.send(ControllerMessage::Notification(
-                    self.lang.clone().into(),
-                    LspNotification::Open(uri.clone().into(),
-                                          self.contents.to_string().into()))
-                ).expect("Failed to send message");
-
-                sender.send(ControllerMessage::Request(
-                    self.lang.clone().into(),
-                    LspRequest::RequestDiagnostic(uri.into())
-                )).expect("Failed to send message");
-            },
+        let triggered = self.lsp_info.lock().expect("Lsp info poisoned").trigger_characters.iter()
+            .any(|trigger| before.ends_with(trigger.as_str()));
+        if triggered {
+            self.request_completion("triggerCharacter", true);
+            return;
         }
 
-
-        
-        Ok(())
-    }
-
-    fn process_keypress(&mut self, key: KeyEvent, container: &mut PaneContainer) -> io::Result<bool> {
-        let mode = self.mode.clone();
-        let result = mode.borrow_mut().process_keypress(key, self, container);
-        
-        self.open_info(container);
-        result
+        let prefix = word_prefix(&before, x).chars().count();
+        if definition::is_identifier_char(c) && min_prefix > 0 && prefix >= min_prefix {
+            self.request_completion("invoked", true);
+        }
+        else {
+            self.close_auto_completion();
+        }
     }
 
-    fn scroll_cursor(&mut self, container: &PaneContainer) {
-        let cursor = self.cursor.clone();
+    /// Closes the list of completions that opened while typing.
+    fn close_auto_completion(&mut self) {
+        if !self.auto_completion {
+            return;
+        }
+        self.auto_completion = false;
+        self.lsp_completion = None;
 
-        cursor.borrow_mut().scroll(container);
-        
+        if let Waiting::CompletionResponse = self.waiting {
+            self.waiting = Waiting::None;
+            self.lsp_request_time = None;
+        }
+        else if let Some((send, _)) = self.popup_channels.take() {
+            let _ = send.send(PaneMessage::Close);
+        }
     }
 
+    /// Asks the server what is under the cursor so it can be shown in a popup.
+    fn request_hover(&mut self) {
+        let uri = self.generate_uri();
+        let position = self.cursor.borrow().get_cursor();
 
-    fn get_status(&self, container: &PaneContainer) -> (String, String, String) {
-        self.mode.borrow_mut().update_status(self, container)
+        self.send_lsp_request(LspRequest::Hover(uri.into(), position), Waiting::HoverResponse);
     }
 
-    fn run_command(&mut self, command: &str, container: &PaneContainer) {
-        let mut command_args = command.split_whitespace();
-        let command = command_args.next().unwrap_or("");
-        match command {
-            "q" => {
-                if self.changed {
-                } else {
-                    self.sender.send(Message::ClosePane(false, None)).unwrap();
-                }
+    fn show_hover(&mut self, container: &PaneContainer) {
+        let text = self.lsp_hover.take().expect("LSP hover was none");
+        if text.trim().is_empty() {
+            return;
+        }
 
-                let uri = self.generate_uri();
-                match &self.lsp_client {
-                    None => {},
-                    Some((sender,_)) => {
+        let wrap_width = container.get_size().0.saturating_sub(2).clamp(1, INFO_WIDTH);
 
-                        sender.send(ControllerMessage::Notification(
-                            self.lang.clone().into(),
-                            LspNotification::Close(uri.into())
-                        )).expect("Failed to send message");
-                    },
-                }
-               
-                
-            },
-            "w" => {
+        let mut body = Vec::new();
+        for line in text.lines() {
+            if line.trim().is_empty() {
+                body.push(None);
+            }
+            // Short lines keep their indentation since they are often code
+            else if line.chars().count() <= wrap_width {
+                body.push(Some(line.to_string()));
+            }
+            else {
+                body.extend(wrap_text(line, wrap_width).into_iter().map(Some));
+            }
+        }
 
-                self.file_version += 1;
+        self.open_info_popup(body, container);
+    }
 
-                let uri = self.generate_uri();
-                match &self.lsp_client {
-                    None => {},
-                    Some((sender, _)) => {
-                        //TODO replace filename with URI
+    /// Opens a read only popup under the cursor.
+    /// The popup stays until the cursor moves or Esc is pressed.
+    fn open_info_popup(&mut self, body: Vec<Option<String>>, container: &PaneContainer) {
+        if self.lsp_completion.is_some() {
+            return;
+        }
 
-                        sender.send(ControllerMessage::Notification(
-                            self.lang.clone().into(),
-                            LspNotification::WillSave(uri.clone().into(), "manual".into())
-                        )).expect("Failed to send message");
+        self.close_info_popup();
 
-                    },
-                }
+        let width = body.iter()
+            .flatten()
+            .map(|line| line.chars().count())
+            .max()
+            .unwrap_or(0);
+        let size = (width + 2, body.len() + 2);
 
-                
-                if let Some(file_name) = command_args.next() {
-                    self.file_name = Some(PathBuf::from(file_name));
-                }
+        let (send, recv) = std::sync::mpsc::channel();
+        let (send2, recv2) = std::sync::mpsc::channel();
 
-                self.save_buffer().expect("Failed to save file");
-                self.contents.add_new_rope();
+        let pane = PopUpPane::new_info(self.settings.clone(),
+                                       Vec::new(),
+                                       self.sender.clone(),
+                                       send,
+                                       recv2,
+                                       body,
+                                       false);
+        let pane = Rc::new(RefCell::new(pane));
 
-                match &self.lsp_client {
-                    None => {},
-                    Some((sender, _)) => {
+        // Below the cursor so the text it is about stays visible
+        let (x, y) = self.cursor.borrow().get_real_cursor();
 
-                        let text = self.contents.to_string();
+        let mut popup = PaneContainer::new(container.get_size(), size, pane, self.settings.clone());
+        popup.set_position((x, y + 1));
+        popup.set_size(size);
 
-                        sender.send(ControllerMessage::Notification(
-                            self.lang.clone().into(),
-                            LspNotification::Save(uri.into(), text.into())
-                        )).expect("Failed to send message");
-                    },
-                }
-            },
-            "w!" => {
+        self.sender.send(Message::CreatePopup(popup, false)).expect("Failed to send message");
+        self.waiting = Waiting::None;
 
-                self.file_version += 1;
+        self.popup_channels = Some((send2, recv));
+        self.info_popup = Some(self.cursor.borrow().get_cursor());
+    }
 
-                let uri = self.generate_uri();
-                match &self.lsp_client {
-                    None => {},
-                    Some((sender, _)) => {
-                        //TODO replace filename with URI
+    /// Closes the popup of a diagnostic or hover if there is one.
+    fn close_info_popup(&mut self) {
+        if self.info_popup.take().is_none() {
+            return;
+        }
 
-                        sender.send(ControllerMessage::Notification(
-                            self.lang.clone().into(),
-                            LspNotification::WillSave(uri.clone().into(), "manual".into())
-                        )).expect("Failed to send message");
+        if let Waiting::None = self.waiting {
+            if let Some((send, _)) = self.popup_channels.take() {
+                let _ = send.send(PaneMessage::Close);
+            }
+        }
+    }
 
-                    },
-                }
+    fn get_byte_offset_pos(&self, (x, y): (usize, usize)) -> Option<usize> {
 
-                
-                if let Some(file_name) = command_args.next() {
-                    self.file_name = Some(PathBuf::from(file_name));
-                }
+        self.contents.get_byte_offset(x, y)
+    }
 
-                self.save_buffer().expect("Failed to save file");
-                self.contents.add_new_rope();
+    /// Replaces the text between two (column, row) positions, the end being exclusive.
+    fn replace_text_at(&mut self, start: (usize, usize), end: (usize, usize), text: &str) {
+        let (start_byte, old_end_byte) = match (self.get_byte_offset_pos(start), self.get_byte_offset_pos(end)) {
+            (Some(start_byte), Some(old_end_byte)) => (start_byte, old_end_byte),
+            _ => return,
+        };
+        self.set_changed(true);
 
-                match &self.lsp_client {
-                    None => {},
-                    Some((sender, _)) => {
+        self.record_change(start_byte, old_end_byte, text);
+        self.contents.replace(start_byte..old_end_byte, text);
 
-                        let text = self.contents.to_string();
+        let new_end_position = match text.rsplit_once('\n') {
+            None => Point::new(start.1, start.0 + text.chars().count()),
+            Some((_, last)) => Point::new(start.1 + text.matches('\n').count(), last.chars().count()),
+        };
 
-                        sender.send(ControllerMessage::Notification(
-                            self.lang.clone().into(),
-                            LspNotification::Save(uri.into(), text.into())
-                        )).expect("Failed to send message");
-                    },
-                }
+        let edit = InputEdit {
+            start_byte,
+            old_end_byte,
+            new_end_byte: start_byte + text.len(),
+            start_position: Point::new(start.1, start.0),
+            old_end_position: Point::new(end.1, end.0),
+            new_end_position,
+        };
 
-            },
-            "wq" => {
+        self.apply_edit(edit);
+    }
 
+    /// Puts `text` in place of a range of bytes and reparses only around it.
+    fn replace_bytes(&mut self, range: std::ops::Range<usize>, text: &str) {
+        let (start_x, start_y) = self.contents.position_of_byte(range.start);
+        let (old_end_x, old_end_y) = self.contents.position_of_byte(range.end);
+        self.set_changed(true);
 
-                self.file_version += 1;
+        self.record_change(range.start, range.end, text);
+        self.contents.replace(range.clone(), text);
 
-                let uri = self.generate_uri();
-                match &self.lsp_client {
-                    None => {},
-                    Some((sender, _)) => {
-                        //TODO replace filename with URI
+        let new_end_byte = range.start + text.len();
+        let (new_end_x, new_end_y) = self.contents.position_of_byte(new_end_byte);
+        let edit = InputEdit {
+            start_byte: range.start,
+            old_end_byte: range.end,
+            new_end_byte,
+            start_position: Point::new(start_y, start_x),
+            old_end_position: Point::new(old_end_y, old_end_x),
+            new_end_position: Point::new(new_end_y, new_end_x),
+        };
 
-                        sender.send(ControllerMessage::Notification(
-                            self.lang.clone().into(),
-                            LspNotification::WillSave(uri.clone().into(), "manual".into())
-                        )).expect("Failed to send message");
+        self.apply_edit(edit);
+    }
 
-                    },
-                }
+    fn jump_to_byte(&mut self, byte: usize) {
+        let (x, y) = self.contents.position_of_byte(byte);
+        let cursor = self.cursor.clone();
+        cursor.borrow_mut().jump_to(x, y, self);
+    }
 
-                
-                self.save_buffer().expect("Failed to save file");
-                self.sender.send(Message::ClosePane(false, None)).unwrap();
+    /// Puts a completion in place of the text it replaces.
+    /// Snippets are expanded and the cursor goes to their first tabstop.
+    fn insert_completion(&mut self, completion: &CompletionItem) {
+        let (x, y) = self.cursor.borrow().get_cursor();
+        let (start, end, text) = completion_edit(completion, &self.cursor_line(y), (x, y));
 
-                match &self.lsp_client {
-                    None => {},
-                    Some((sender, _)) => {
+        self.snippet = None;
+        let start_byte = match self.get_byte_offset_pos(start) {
+            None => return,
+            Some(start_byte) => start_byte,
+        };
 
-                        let text = self.contents.to_string();
+        if completion.insertTextFormat == Some(SNIPPET_FORMAT) {
+            let snippet = Snippet::parse(&text);
+            self.replace_text_at(start, end, &snippet.text);
 
-                        sender.send(ControllerMessage::Notification(
-                            self.lang.clone().into(),
-                            LspNotification::Save(uri.clone().into(), text.into())
-                        )).expect("Failed to send message");
-                    },
+            let session = SnippetSession::new(&snippet, start_byte);
+            let (first, _) = session.current()[0];
+            // A snippet that only ends somewhere has no tabstops to go through
+            if !session.is_last() {
+                self.snippet = Some(session);
+            }
+            self.jump_to_byte(first);
+        }
+        else {
+            self.replace_text_at(start, end, &text);
+            self.jump_to_byte(start_byte + text.len());
+        }
+    }
+
+    /// Moves the cursor to the next or previous tabstop of the snippet.
+    /// The snippet is done once the cursor gets to where it ends.
+    fn jump_to_tabstop(&mut self, forward: bool) {
+        let first = match &mut self.snippet {
+            None => return,
+            Some(session) => {
+                if forward {
+                    session.next();
+                }
+                else {
+                    session.previous();
                 }
+                session.current()[0].0
+            },
+        };
+        if self.snippet.as_ref().is_some_and(|session| session.is_last()) {
+            self.snippet = None;
+        }
+        self.jump_to_byte(first);
+    }
 
+    /// Typing at the start of a tabstop that still has its placeholder replaces the placeholder.
+    fn replace_placeholder(&mut self) {
+        let (start, end) = match &self.snippet {
+            Some(session) if session.replace_placeholder => session.current()[0],
+            _ => return,
+        };
+        if start == end || self.get_byte_offset() != Some(start) {
+            return;
+        }
+        let start = self.contents.position_of_byte(start);
+        let end = self.contents.position_of_byte(end);
+        self.replace_text_at(start, end, "");
+    }
 
-                match &self.lsp_client {
-                    None => {},
-                    Some((sender,_)) => {
+    /// Copies the text of the tabstop that was just edited to the other places it is used.
+    fn sync_snippet(&mut self) {
+        let edited = match self.snippet.as_mut().and_then(|session| session.take_edited()) {
+            None => return,
+            Some(edited) => edited,
+        };
+        let ranges = |pane: &Self| pane.snippet.as_ref().map(|session| session.current().to_vec()).unwrap_or_default();
 
-                        sender.send(ControllerMessage::Notification(
-                            self.lang.clone().into(),
-                            LspNotification::Close(uri.into())
-                        )).expect("Failed to send message");
-                    },
-                }
-                
+        let (start, end) = ranges(self)[edited];
+        let text = match self.contents.to_string().get(start..end) {
+            // The tabstops lost track of the text
+            None => {
+                self.snippet = None;
+                return;
             },
-            "q!" => {
-                self.sender.send(Message::ClosePane(false, None)).unwrap();
-                let uri = self.generate_uri();
-
-                match &self.lsp_client {
-                    None => {},
-                    Some((sender,_)) => {
+            Some(text) => text.to_string(),
+        };
+        let cursor = self.get_byte_offset().unwrap_or(end).saturating_sub(start);
 
-                        sender.send(ControllerMessage::Notification(
-                            self.lang.clone().into(),
-                            LspNotification::Close(uri.into())
-                        )).expect("Failed to send message");
-                    },
-                }
+        for occurrence in 0..ranges(self).len() {
+            let (start, end) = match ranges(self).get(occurrence) {
+                None => return,
+                Some(range) => *range,
+            };
+            if occurrence == edited || self.contents.to_string().get(start..end) == Some(text.as_str()) {
+                continue;
+            }
+            let start = self.contents.position_of_byte(start);
+            let end = self.contents.position_of_byte(end);
+            self.replace_text_at(start, end, &text);
+        }
 
+        // The copies before the edited place move it
+        let start = match &mut self.snippet {
+            None => return,
+            Some(session) => {
+                session.take_edited();
+                session.current()[edited].0
             },
-            "move" => {
-                let direction = command_args.next();
-                let direction = match direction {
-                    Some("up") => Direction::Up,
-                    Some("down") => Direction::Down,
-                    Some("left") => Direction::Left,
-                    Some("right") => Direction::Right,
-                    Some("line_start") => Direction::LineStart,
-                    Some("line_end") => Direction::LineEnd,
-                    Some("file_top") => Direction::FileTop,
-                    Some("file_bottom") => Direction::FileBottom,
-                    Some("page_up") => Direction::PageUp,
-                    Some("page_down") => Direction::PageDown,
-                    _ => panic!("Invalid direction"),
+        };
+        self.jump_to_byte(start + cursor);
+    }
+
+    /// The tabstop the cursor is on, as selections to draw.
+    fn snippet_selections(&self) -> Vec<Selection> {
+        let session = match &self.snippet {
+            None => return Vec::new(),
+            Some(session) => session,
+        };
+        session.current().iter()
+            .filter(|(start, end)| start < end)
+            .map(|(start, end)| {
+                let start = self.contents.position_of_byte(*start);
+                let end = self.contents.position_of_byte(*end);
+                // The end of the range is exclusive but selections include their last column
+                let end = match end.0.checked_sub(1) {
+                    Some(col) => (col, end.1),
+                    None => (usize::MAX, end.1.saturating_sub(1)),
                 };
+                (start, end)
+            })
+            .collect()
+    }
 
-                let amount = command_args.next().unwrap_or("1").parse::<usize>().unwrap_or(1);
+    /// Brings the syntax tree up to date after the buffer changed underneath it, like on an undo.
+    /// Only the part around the change gets parsed again.
+    fn edit_tree(&mut self, change: &Change) {
+        let edit = InputEdit {
+            start_byte: change.start_byte,
+            old_end_byte: change.old_end_byte,
+            new_end_byte: change.new_end_byte,
+            start_position: Point::new(change.start.1, change.start.0),
+            old_end_position: Point::new(change.old_end.1, change.old_end.0),
+            new_end_position: Point::new(change.new_end.1, change.new_end.0),
+        };
 
-                match direction {
-                    Direction::FileBottom | Direction::FileTop | Direction::PageUp | Direction::PageDown => {
-                        let cursor = self.cursor.borrow();
-                        self.jump_table.add(*cursor);
-                    },
-                    _ => {},
-                }
+        self.apply_edit(edit);
 
-                self.cursor.borrow_mut().move_cursor(direction, amount, self);
-            },
-            "mode" => {
-                let mode = command_args.next().unwrap_or("Normal");
-                self.change_mode(mode);
-                self.contents.add_new_rope();
-            },
-            "jump" => {
+        // The tabstops can't follow an undo
+        self.snippet = None;
+        self.send_change_text();
+    }
+
+    /// Gives the pane a language server to talk to, like after the server was started again.
+    /// The server gets the whole buffer since it doesn't know about the edits made before.
+    pub fn attach_lsp_client(&mut self, lsp: (Sender<ControllerMessage>, SharedReceiver)) {
+        self.lsp_client = Some(lsp);
+        self.send_open();
+    }
+
+    /// Sends the whole buffer to the server as a newly opened file.
+    /// The version starts over so every change after this has a newer version than the open.
+    fn send_open(&mut self) {
+        self.file_version = 0;
+        // The server gets all of the text so the edits before this are in it
+        self.pending_changes.clear();
+        self.whole_text_changed = false;
+        self.changed_since = None;
+
+        let uri = self.generate_uri();
+        match &self.lsp_client {
+            None => {},
+            Some((sender, _)) => {
+                sender.send(ControllerMessage::Notification(
+                    self.lang.clone().into(),
+                    LspNotification::Open(uri.clone().into(),
+                                          self.file_version,
+                                          self.contents.to_string().into()))
+                ).expect("Failed to send message");
+
+                sender.send(ControllerMessage::Request(
+                    self.lang.clone().into(),
+                    LspRequest::RequestDiagnostic(uri.into())
+                )).expect("Failed to send message");
+            },
+        }
+    }
+
+    /// Marks the whole buffer as changed for the next time the server is told about the changes.
+    fn send_change_text(&mut self) {
+        if self.lsp_client.is_none() {
+            return;
+        }
+        self.whole_text_changed = true;
+        self.pending_changes.clear();
+        self.changed_since.get_or_insert_with(Instant::now);
+    }
+
+    /// Remembers an edit to send to the server, before it is made to the buffer.
+    /// The bytes are the range being replaced by the text.
+    fn record_change(&mut self, start_byte: usize, old_end_byte: usize, text: &str) {
+        if let Some(session) = &mut self.snippet {
+            if !session.edit(start_byte, old_end_byte, text.len()) {
+                self.snippet = None;
+            }
+        }
+        if self.lsp_client.is_none() {
+            return;
+        }
+        if !self.whole_text_changed {
+            let start = self.contents.position_of_byte(start_byte);
+            let end = self.contents.position_of_byte(old_end_byte);
+            self.pending_changes.push(TextChange {
+                range: Some((start, end)),
+                text: text.to_string(),
+            });
+        }
+        self.changed_since.get_or_insert_with(Instant::now);
+    }
+
+    /// Tells the server about the edits made since the last time.
+    /// Requests and saves call this first so the server sees the same text as the pane.
+    fn flush_changes(&mut self) {
+        if self.changed_since.take().is_none() {
+            return;
+        }
+        let changes = std::mem::take(&mut self.pending_changes);
+        self.whole_text_changed = false;
+
+        self.file_version += 1;
+
+        match &self.lsp_client {
+            None => {},
+            Some((sender, _)) => {
+                let message = ControllerMessage::Notification(
+                    self.lang.clone().into(),
+                    LspNotification::ChangeText(
+                        self.generate_uri().into(),
+                        self.file_version,
+                        changes,
+                        self.contents.to_string().into(),
+                    )
+                );
+
+                sender.send(message).expect("Failed to send message");
+            },
+        }
+    }
+
+    /// This walks the syntax tree and spell checks every comment and string.
+    /// We don't look at anything else so that identifiers don't get flagged.
+    fn check_spelling(&mut self) {
+        let revision = self.contents.revision();
+        if self.spelled_revision == Some(revision) {
+            return;
+        }
+        let checker = self.spell_checker.get_or_insert_with(|| Rc::new(SpellChecker::new())).clone();
+        let tree = match &self.tree {
+            // The misspellings found before stay until there is a tree to look in
+            None => return,
+            Some(tree) => tree,
+        };
+        let text = self.contents.to_string();
+        let mut misspellings = Vec::new();
+
+        let mut cursor = tree.walk();
+        'walk: loop {
+            let node = cursor.node();
+            let checkable = node.kind().contains("comment") || node.kind().contains("string");
+
+            if checkable {
+                if let Some(slice) = text.get(node.byte_range()) {
+                    let start = node.start_position();
+                    let line_start = node.start_byte().saturating_sub(start.column);
+                    let col = text.get(line_start..node.start_byte())
+                        .map(|prefix| prefix.chars().count())
+                        .unwrap_or(start.column);
+
+                    checker.check_text(slice, start.row, col, &mut misspellings);
+                }
+            }
+
+            if !checkable && cursor.goto_first_child() {
+                continue;
+            }
+
+            while !cursor.goto_next_sibling() {
+                if !cursor.goto_parent() {
+                    break 'walk;
+                }
+            }
+        }
+
+        self.misspellings = misspellings;
+        self.spelled_revision = Some(revision);
+    }
+
+    /// Runs a command like `sort` or `uniq` that rewrites whole lines.
+    fn run_line_command(&mut self, line_command: LineCommand) {
+        let text = self.contents.to_string();
+        let (range, replacement, removed) = match line_command.apply(&text) {
+            None => return,
+            Some(edit) => edit,
+        };
+
+        if text[range.clone()] != replacement {
+            self.set_changed(true);
+            self.contents.replace(range, replacement);
+
+            // The lines moved around so we parse from scratch
+            self.parse_from_scratch();
+            self.send_change_text();
+        }
+
+        // The cursor's line might not be there anymore
+        let (x, y) = self.cursor.borrow().get_cursor();
+        let cursor = self.cursor.clone();
+        cursor.borrow_mut().jump_to(x, y, self);
+
+        if removed > 0 {
+            let message = format!("{} fewer lines", removed);
+            self.sender.send(Message::SetStatus(message)).expect("Failed to send message");
+        }
+    }
+
+    /// Builds the row as the substitute being typed would leave it.
+    fn preview_row(&self, row: usize) -> Option<Vec<(char, bool)>> {
+        let substitute = self.substitute_preview.as_ref()?;
+        if !substitute.applies_to(row, self.cursor.borrow().get_cursor().1) {
+            return None;
+        }
+
+        let line = self.contents.get_row(row, 0, usize::MAX)?.to_string();
+        let line = line.trim_end_matches('\n');
+
+        if substitute.matches(line).is_empty() {
+            return None;
+        }
+        Some(substitute.preview(line))
+    }
+
+    /// Finds identifiers with the given name that sit in a node that looks like it defines something.
+    /// The positions are in characters so that they can be handed straight to the cursor.
+    fn find_definitions(&self, text: &str, name: &str) -> Vec<(usize, usize)> {
+        let mut candidates = Vec::new();
+
+        let tree = match &self.tree {
+            None => return candidates,
+            Some(tree) => tree,
+        };
+        let mut cursor = tree.walk();
+        'walk: loop {
+            let node = cursor.node();
+
+            if node.kind().contains("identifier") && text.get(node.byte_range()) == Some(name) {
+                let defining = node.parent()
+                    .map(|parent| {
+                        let kind = parent.kind();
+                        DEFINING_NODES.iter().any(|defining| kind.contains(defining))
+                    })
+                    .unwrap_or(false);
+
+                if defining {
+                    let start = node.start_position();
+                    let line_start = node.start_byte().saturating_sub(start.column);
+                    let col = text.get(line_start..node.start_byte())
+                        .map(|prefix| prefix.chars().count())
+                        .unwrap_or(start.column);
+
+                    candidates.push((col, start.row));
+                }
+            }
+
+            if cursor.goto_first_child() {
+                continue;
+            }
+
+            while !cursor.goto_next_sibling() {
+                if !cursor.goto_parent() {
+                    break 'walk;
+                }
+            }
+        }
+
+        candidates
+    }
+
+    /// Moves the cursor to a location from the server, opening its file if it is in another one.
+    fn jump_to_location(&mut self, location: &Location) {
+        let (pos, _) = location.range.get_positions();
+
+        if location.uri == self.generate_uri() {
+            self.jump_table.add(*self.cursor.borrow());
+
+            let mut cursor = self.cursor.borrow_mut();
+            cursor.set_cursor(CursorMove::Amount(pos.0), CursorMove::Amount(pos.1), self, (0,0));
+        }
+        else {
+            let file_name = Self::get_file_path(&location.uri);
+            self.sender.send(Message::OpenFile(file_name, Some(pos))).expect("Failed to send message");
+        }
+    }
+
+    /// Opens a dropdown in the middle of the pane to pick one of several locations to go to.
+    fn pick_location(&mut self, prompt: Vec<String>, locations: &[Location], container: &PaneContainer) {
+        let (send, recv) = std::sync::mpsc::channel();
+        let (send2, recv2) = std::sync::mpsc::channel();
+
+        self.popup_channels = Some((send2, recv));
+
+        let mut buttons = Vec::new();
+        let mut width = prompt.iter().map(|line| line.chars().count()).max().unwrap_or(0);
+
+        for location in locations.iter() {
+            let pathbuf = PathBuf::from(Self::get_file_path(&location.uri));
+            let label = format!("{}:{}:{}",
+                                display_name(Some(&pathbuf)),
+                                location.range.start.line + 1,
+                                location.range.start.character + 1);
+            width = width.max(label.chars().count());
+
+            let location = location.clone();
+            let function: ButtonFunction = Box::new(move |_| {
+                format!("{} {},{}", Self::get_file_path(&location.uri), location.range.start.character, location.range.start.line)
+            });
+
+            buttons.push((label, function));
+        }
+
+        let buttons = PromptType::Button(buttons, 0);
+
+        let pane = PopUpPane::new_dropdown(
+            self.settings.clone(),
+            prompt.clone(),
+            self.sender.clone(),
+            send,
+            recv2,
+            buttons,
+            true
+        );
+
+        let pane = Rc::new(RefCell::new(pane));
+
+        let max_size = container.get_size();
+        let size = ((width + 4).max(20).min(max_size.0), (locations.len() + prompt.len() + 2).min(max_size.1));
+
+        let ((x1, y1), _) = container.get_corners();
+        let pos = (x1 + (max_size.0 - size.0) / 2, y1 + (max_size.1 - size.1) / 2);
+
+        let mut popup = PaneContainer::new(max_size, size, pane, self.settings.clone());
+        popup.set_position(pos);
+        popup.set_size(size);
+
+        self.sender.send(Message::CreatePopup(popup, true)).expect("Failed to send message");
+        self.waiting = Waiting::Goto;
+    }
+
+    /// Asks the server to rename the symbol under the cursor everywhere it is used.
+    fn rename_symbol(&mut self, new_name: &str) {
+        let uri = self.generate_uri();
+        let position = self.cursor.borrow().get_cursor();
+
+        self.send_lsp_request(LspRequest::Rename(uri.into(), position, new_name.into()), Waiting::RenameResponse);
+    }
+
+    fn finish_rename(&mut self) {
+        let edit = self.lsp_workspace_edit.take().expect("LSP workspace edit was none");
+        if edit.changes.iter().all(|(_, edits)| edits.is_empty()) {
+            self.sender.send(Message::SetMessage("Nothing to rename".to_string(), MessageKind::Info))
+                .expect("Failed to send message");
+            return;
+        }
+
+        self.apply_workspace_edit(edit, None);
+    }
+
+    /// Makes the edits to this buffer and hands the edits to other files to the window.
+    /// `request_id` is set when the server asked for the edit, so it gets told once the edit is made.
+    fn apply_workspace_edit(&mut self, edit: WorkspaceEdit, request_id: Option<serde_json::Value>) {
+        let uri = self.generate_uri();
+
+        let mut other_files = Vec::new();
+        for (file_uri, edits) in edit.changes {
+            if file_uri == uri {
+                self.apply_text_edits(&edits);
+            }
+            else if !edits.is_empty() {
+                other_files.push((PathBuf::from(Self::get_file_path(&file_uri)), edits));
+            }
+        }
+
+        let reply = request_id.map(|id| (self.lang.clone(), id));
+        if !other_files.is_empty() {
+            self.sender.send(Message::ApplyEdits(other_files, reply)).expect("Failed to send message");
+        }
+        else if let Some((lang, id)) = reply {
+            // The server should hear about the edit before it hears that it was made
+            self.flush_changes();
+            if let Some((sender, _)) = &self.lsp_client {
+                sender.send(ControllerMessage::Notification(
+                    lang.into(),
+                    LspNotification::EditApplied(id, true)
+                )).expect("Failed to send message");
+            }
+        }
+    }
+
+    /// Asks the server what it can do at the cursor, passing along the diagnostics there.
+    fn request_code_actions(&mut self) {
+        let uri = self.generate_uri();
+        let (x, y) = self.cursor.borrow().get_cursor();
+        let diagnostics = self.lsp_diagnostics.borrow()
+            .get_diagnostics(y, x)
+            .into_iter()
+            .cloned()
+            .collect::<Vec<_>>();
+
+        self.send_lsp_request(LspRequest::CodeAction(uri.into(), ((x, y), (x, y)), diagnostics), Waiting::CodeActionResponse);
+    }
+
+    /// Offers the code actions from the server in a dropdown.
+    fn show_code_actions(&mut self, container: &PaneContainer) {
+        let actions = self.lsp_code_actions.take().expect("LSP code actions were none");
+        if actions.is_empty() {
+            self.sender.send(Message::SetMessage("No code actions".to_string(), MessageKind::Info))
+                .expect("Failed to send message");
+            return;
+        }
+
+        let max_size = container.get_size();
+        let width = actions.iter()
+            .map(|action| action.title.chars().count())
+            .max()
+            .unwrap_or(0)
+            .min(max_size.0.saturating_sub(2));
+
+        let mut buttons = Vec::new();
+        for (index, action) in actions.iter().enumerate() {
+            let label = action.title.chars().take(width).collect::<String>();
+            let function: ButtonFunction = Box::new(move |_| index.to_string());
+            buttons.push((label, function));
+        }
+        let buttons = PromptType::Button(buttons, 0);
+
+        let (send, recv) = std::sync::mpsc::channel();
+        let (send2, recv2) = std::sync::mpsc::channel();
+
+        self.popup_channels = Some((send2, recv));
+
+        let pane = PopUpPane::new_dropdown(
+            self.settings.clone(),
+            Vec::new(),
+            self.sender.clone(),
+            send,
+            recv2,
+            buttons,
+            false,
+        );
+        let pane = Rc::new(RefCell::new(pane));
+
+        let size = (width + 2, actions.len() + 2);
+        let (x, y) = self.cursor.borrow().get_real_cursor();
+
+        let mut popup = PaneContainer::new(max_size, size, pane, self.settings.clone());
+        popup.set_position((x, y + 1));
+        popup.set_size(size);
+
+        self.sender.send(Message::CreatePopup(popup, true)).expect("Failed to send message");
+        self.code_actions = actions;
+        self.waiting = Waiting::CodeAction;
+    }
+
+    /// Makes the edit of a code action and then has the server run its command.
+    fn apply_code_action(&mut self, action: CodeAction) {
+        if let Some(edit) = action.edit {
+            self.apply_workspace_edit(edit, None);
+        }
+
+        self.flush_changes();
+        if let (Some(command), Some((sender, _))) = (action.command, &self.lsp_client) {
+            sender.send(ControllerMessage::Request(
+                self.lang.clone().into(),
+                LspRequest::ExecuteCommand(command)
+            )).expect("Failed to send message");
+        }
+    }
+
+    fn formatting_options(&self) -> FormattingOptions {
+        let settings = self.settings.borrow();
+        FormattingOptions {
+            tab_size: settings.editor_settings.tab_size,
+            insert_spaces: settings.editor_settings.use_spaces,
+        }
+    }
+
+    /// Asks the server to format the whole buffer.
+    fn format_buffer(&mut self) {
+        let uri = self.generate_uri();
+        let options = self.formatting_options();
+
+        self.format_range = None;
+        self.send_lsp_request(LspRequest::Formatting(uri.into(), options), Waiting::FormattingResponse);
+    }
+
+    fn finish_formatting(&mut self) {
+        let edits = match self.lsp_formatting.take().expect("LSP formatting was none") {
+            None => {
+                self.sender.send(Message::SetMessage("No formatting available".to_string(), MessageKind::Info))
+                    .expect("Failed to send message");
+                return;
+            },
+            Some(edits) => edits,
+        };
+
+        let edits = match self.format_range.take() {
+            None => edits,
+            // Servers are allowed to format more than they were asked to
+            Some((first, end)) => edits.into_iter()
+                .filter(|edit| edit.range.start.line >= first)
+                .filter(|edit| (edit.range.end.line, edit.range.end.character) <= (end.1, end.0))
+                .collect::<Vec<_>>(),
+        };
+        self.apply_formatting(&edits);
+    }
+
+    /// Asks the server to format whole lines, only keeping the edits that stay within them.
+    /// Without a server the lines are indented instead.
+    fn format_lines(&mut self, first: usize, last: usize) {
+        let last = last.min(self.contents.get_line_count().saturating_sub(1));
+        if self.lsp_client.is_none() {
+            self.reindent_lines(first, last);
+            return;
+        }
+
+        let uri = self.generate_uri();
+        let end = (self.cursor_line(last).chars().count(), last);
+        let options = self.formatting_options();
+
+        self.format_range = Some((first, end));
+        self.send_lsp_request(LspRequest::RangeFormatting(uri.into(), ((0, first), end), options), Waiting::FormattingResponse);
+    }
+
+    /// Makes the edits of a formatting and keeps the cursor on the text it was on.
+    fn apply_formatting(&mut self, edits: &[TextEdit]) {
+        let (x, y) = self.cursor.borrow().get_cursor();
+        let position = position_after_edits(Position { line: y, character: x }, edits);
+
+        self.apply_text_edits(edits);
+        self.set_location((position.character, position.line));
+    }
+
+    /// Jumps to where a goto request led, letting the user pick when there is more than one place.
+    fn finish_goto(&mut self, container: &PaneContainer) {
+        let lsp_location = self.lsp_location.take().expect("LSP location was none");
+
+        match lsp_location {
+            LocationResponse::Location(location) => self.jump_to_location(&location),
+            LocationResponse::Locations(locations) => {
+                if locations.len() == 1 {
+                    self.jump_to_location(&locations[0]);
+                }
+                else {
+                    self.pick_location(vec!["Locations".to_string()], &locations, container);
+                }
+            },
+            LocationResponse::LocationLink(location_link) => {
+                eprintln!("Got location link {:?}", location_link);
+
+            },
+            LocationResponse::Null => {},
+        }
+    }
+
+    fn finish_references(&mut self, container: &PaneContainer) {
+        let locations = match self.lsp_location.take().expect("LSP location was none") {
+            LocationResponse::Locations(locations) => locations,
+            LocationResponse::Location(location) => vec![location],
+            _ => Vec::new(),
+        };
+
+        match locations.len() {
+            0 => {
+                self.sender.send(Message::SetMessage("No references found".to_string(), MessageKind::Info))
+                    .expect("Failed to send message");
+            },
+            1 => self.jump_to_location(&locations[0]),
+            count => {
+                let title = format!("{} References", count);
+                self.pick_location(vec![title], &locations, container);
+            },
+        }
+    }
+
+    fn get_file_path(uri: &str) -> String {
+        uri_to_path(uri).to_string_lossy().to_string()
+    }
+
+
+}
+
+
+impl Pane for TreesitterPane {
+
+    fn execute_command(&mut self, command: &str, container: &mut PaneContainer) {
+        let mode = self.mode.clone();
+        mode.borrow_mut().execute_command(command, self, container);
+    }
+
+    fn changed(&mut self) {
+        self.cursor.borrow_mut().set_moved();
+    }
+    
+    fn reset(&mut self) {
+        self.cursor.borrow_mut().reset_move();
+    }
+    
+    fn draw_row(&self, mut index: usize, container: &PaneContainer, output: &mut TextRow) {
+
+        let (mut cols, rows) = container.get_size();
+
+        // The resize math can leave a pane without any room to draw in
+        if cols == 0 || rows == 0 {
+            return;
+        }
+        //eprintln!("Cols: {}", cols);
+
+        //eprintln!("Changed");
+
+        let ((x1, y1), _) = container.get_corners();
+
+
+        if container.has_border() {
+
+            let color_settings = &self.settings.borrow().colors.ui;
+            
+            if index == 0 && y1 != 0 {
+
+                if !self.cursor.borrow().get_moved() {
+                    //eprintln!("Not Changed");
+                    for _ in 0..cols {
+                        output.push(None);
+                    }
+                    return;
+                }
+
+                
+                let string = "-".repeat(cols);
+
+                for c in string.chars() {
+                    output.push(Some(Some(StyledChar::new(c, color_settings.clone()))));
+                }
+                
+                //output.push_str(apply_colors!("-".repeat(cols), color_settings));
+                return;
+            }
+            else {
+                let ((_, y), _) = container.get_corners();
+
+                if y != 0 {
+                    index = index.saturating_sub(1);
+                }
+            }
+
+            if x1 != 0 {
+                let string = "|".to_string();
+
+                for c in string.chars() {
+                    output.push(Some(Some(StyledChar::new(c, color_settings.clone()))));
+                }
+                
+                cols = cols.saturating_sub(1);
+            }
+        }
+
+        let real_row = self.cursor.borrow().row_offset + index;
+        let col_offset = self.cursor.borrow().col_offset;
+        //eprintln!("Col offset: {}", col_offset);
+
+        let number_of_lines = self.contents.get_line_count();
+
+        let mut num_width = 0;
+
+        if self.settings.borrow().editor_settings.line_number {
+
+            let color_settings = &self.settings.borrow().colors.ui;
+            
+
+            if !self.settings.borrow().editor_settings.relative_line_number {
+
+
+                let mut places = 1;
+                while places <= number_of_lines {
+                    places *= 10;
+                    num_width += 1;
+                }
+                num_width = num_width.min(cols);
+
+                if real_row + 1 <= number_of_lines {
+
+
+                    if !self.cursor.borrow().get_scrolled() {
+                        for _ in 0..num_width {
+                            output.push(None);
+                        }
+                    }
+                    else {
+                        
+
+                        let string = format!("{:width$}", real_row + 1, width = num_width);
+
+                        for c in string.chars().take(num_width) {
+                            output.push(Some(Some(StyledChar::new(c, color_settings.clone()))));
+                        }
+                    }
+                }
+
+            }
+            else if self.settings.borrow().editor_settings.relative_line_number {
+
+                let mut places = 1;
+                num_width = 3;
+                while places <= number_of_lines {
+                    places *= 10;
+                    num_width += 1;
+                }
+                num_width = num_width.min(cols);
+                
+                if real_row == self.cursor.borrow().get_cursor().1 && real_row + 1 <= number_of_lines {
+                    let string = format!("{:<width$}", real_row + 1 , width = num_width);
+
+                    for c in string.chars().take(num_width) {
+                        output.push(Some(Some(StyledChar::new(c, color_settings.clone()))));
+                    }
+                }
+                else if real_row + 1 <= number_of_lines {
+                    let string = format!("{:width$}",
+                                            ((real_row) as isize - (self.cursor.borrow().get_cursor().1 as isize)).abs() as usize,
+                                            width = num_width);
+
+                    for c in string.chars().take(num_width) {
+                        output.push(Some(Some(StyledChar::new(c, color_settings.clone()))));
+                    }
+                }
+            }
+
+        }
+
+        self.cursor.borrow_mut().number_line_size = num_width;
+
+        if let Some(row) = self.preview_row(real_row) {
+            let settings = self.settings.borrow();
+            draw_preview_row(&row,
+                             col_offset,
+                             cols.saturating_sub(num_width),
+                             settings.editor_settings.tab_size,
+                             (&settings.colors.pane, &settings.colors.preview),
+                             output);
+            return;
+        }
+
+        let color_settings = &self.settings.borrow().colors.pane;
+
+        if !self.cursor.borrow().get_scrolled() {
+            //eprintln!("Not Changed");
+            for _ in 0..num_width {
+                output.push(None);
+            }
+        }
+
+
+        if !self.cursor.borrow().get_scrolled() {
+            //eprintln!("Not Changed");
+            for _ in 0..(cols - num_width) {
+                output.push(None);
+            }
+        }
+        else {
+            let text_start = output.index;
+
+            if let Some(row) = self.get_row(real_row, col_offset, cols - num_width) {
+                //eprintln!("Row: {}", row);
+                let mut count = 0;
+
+                // Delimiters that were scrolled past still count towards the nesting
+                let rainbow = self.settings.borrow().editor_settings.rainbow_delimiters;
+                let mut depth = if rainbow { self.rainbow_depth(real_row, col_offset) } else { 0 };
+
+                let highlight = self.line_highlight(real_row);
+                let mut byte = self.contents.get_row(real_row, 0, usize::MAX)
+                    .map(|line| line.chars().take(col_offset).map(char::len_utf8).sum::<usize>())
+                    .unwrap_or(0);
+
+                row.chars().for_each(|c| if count < (cols - num_width) {
+                    let char_byte = byte;
+                    byte += c.len_utf8();
+
+                    match c {
+                        '\t' => {
+                            let string = " ".repeat(self.settings.borrow().editor_settings.tab_size);
+
+                            count += self.settings.borrow().editor_settings.tab_size;
+
+                            for c in string.chars() {
+                                output.push(Some(Some(StyledChar::new(c, color_settings.clone()))));
+                            }
+                        },
+                        '\n' => {
+                            count += 1;
+                            let string = " ".to_string();
+
+                            for c in string.chars() {
+                                output.push(Some(Some(StyledChar::new(c, color_settings.clone()))));
+                            }
+                        },
+                        ' ' => {
+                            count += 1;
+                            let string = " ".to_string();
+
+                            for c in string.chars() {
+                                output.push(Some(Some(StyledChar::new(c, color_settings.clone()))));
+                            }
+                        },
+                        c => {
+                            count += 1;
+                            let string = c.to_string();
+
+                            for c in string.chars() {
+                                let color_settings = highlight.color_at(char_byte).unwrap_or(color_settings);
+
+                                match c {
+                                    '(' | ')' | '{' | '}' | '[' | ']' | '<' | '>' => {
+                                        let colors = &self.settings.borrow().colors.rainbow_delimiters;
+                                        let delimiter = if rainbow && !colors.is_empty() {
+                                            highlight.delimiter_at(char_byte)
+                                        }
+                                        else {
+                                            None
+                                        };
+
+                                        match delimiter {
+                                            Some(true) => {
+                                                output.push(Some(Some(StyledChar::new(c, colors[depth % colors.len()].clone()))));
+                                                depth += 1;
+                                            },
+                                            Some(false) if depth > 0 => {
+                                                depth -= 1;
+                                                output.push(Some(Some(StyledChar::new(c, colors[depth % colors.len()].clone()))));
+                                            },
+                                            _ => {
+                                                output.push(Some(Some(StyledChar::new(c, color_settings.clone()))));
+                                            },
+                                        }
+                                    },
+                                    _ => {
+
+                                        let diagnostics = self.lsp_diagnostics.borrow();
+                                        let diagnostic = diagnostics.get_diagnostic(real_row, count);
+                                        //eprintln!("Diagnostic: {:?}", diagnostic);
+                                        match diagnostic {
+                                            None => {
+                                                let misspelled = self.misspellings.iter()
+                                                    .any(|misspelling| misspelling.contains(real_row, col_offset + count - 1));
+
+                                                if misspelled {
+                                                    let spell_colors = &self.settings.borrow().colors.spell;
+                                                    let mut color_settings = color_settings.clone();
+                                                    color_settings.underline_color = spell_colors.underline_color;
+                                                    for attribute in spell_colors.attributes.iter() {
+                                                        color_settings = color_settings.add_attribute(*attribute);
+                                                    }
+                                                    output.push(Some(Some(StyledChar::new(c, color_settings))));
+                                                }
+                                                else {
+                                                    output.push(Some(Some(StyledChar::new(c, color_settings.clone()))));
+                                                }
+                                            },
+                                            Some(diagnostic) => {
+                                                match diagnostic.severity {
+                                                    3 => {
+                                                        let mut color_settings = color_settings.add_attribute(Attribute::Undercurled);
+                                                        color_settings.underline_color = Color::DarkRed;
+                                                        output.push(Some(Some(StyledChar::new(c, color_settings))));
+                                                    },
+                                                    2 => {
+                                                        let mut color_settings = color_settings.add_attribute(Attribute::Undercurled);
+                                                        color_settings.underline_color = Color::DarkYellow;
+                                                        output.push(Some(Some(StyledChar::new(c, color_settings))));
+                                                    },
+                                                    1 | _ => {
+                                                        let mut color_settings = color_settings.add_attribute(Attribute::Undercurled);
+                                                        color_settings.underline_color = Color::Yellow;
+                                                        output.push(Some(Some(StyledChar::new(c, color_settings))));
+                                                    },
+                                                }
+                                            }
+
+                                        }
+
+                                        //output.push(Some(StyledChar::new(c, color_settings.clone())));
+                                    }
+                                }
+                            }
+                        },
+                    }
+                }
+                                    else {
+                });
+
+                let string = " ".repeat(cols.saturating_sub(count + num_width));
+
+                for c in string.chars() {
+                    output.push(Some(Some(StyledChar::new(c, color_settings.clone()))));
+                }
+            }
+            else if real_row >= number_of_lines {
+                let string = " ".repeat(cols);
+
+                for c in string.chars() {
+                    output.push(Some(Some(StyledChar::new(c, color_settings.clone()))));
+                }
+            }
+            else {
+                let string = " ".repeat(cols.saturating_sub(num_width));
+
+                for c in string.chars() {
+                    output.push(Some(Some(StyledChar::new(c, color_settings.clone()))));
+                }
+            }
+
+            if real_row < number_of_lines && self.settings.borrow().editor_settings.indentcheck {
+                if let Some(line) = self.contents.get_row(real_row, 0, usize::MAX) {
+                    draw_bad_indent(&self.settings.borrow(), &line.to_string(), text_start, col_offset, cols.saturating_sub(num_width), output);
+                }
+            }
+
+            let editor_settings = self.settings.borrow().editor_settings;
+            let is_cursor_line = real_row == self.cursor.borrow().get_cursor().1;
+            if real_row < number_of_lines && editor_settings.show_whitespace {
+                if let Some(line) = self.contents.get_row(real_row, 0, usize::MAX) {
+                    draw_whitespace(&self.settings.borrow(), &line.to_string(), text_start, col_offset, cols.saturating_sub(num_width), output);
+                }
+            }
+            let list = editor_settings.list || editor_settings.show_whitespace;
+            if real_row < number_of_lines && list && editor_settings.cursorlineopt.shows_trailing(is_cursor_line) {
+                if let Some(line) = self.contents.get_row(real_row, 0, usize::MAX) {
+                    draw_trailing_whitespace(&self.settings.borrow(), &line.to_string(), text_start, col_offset, cols.saturating_sub(num_width), output);
+                }
+            }
+
+            if real_row < number_of_lines && editor_settings.cursorline && is_cursor_line {
+                draw_cursor_line(&self.settings.borrow(), text_start, cols.saturating_sub(num_width), output);
+            }
+
+            if real_row < number_of_lines {
+                draw_color_column(&self.settings.borrow(), text_start, col_offset, cols.saturating_sub(num_width), output);
+            }
+
+            let pattern = self.search_preview.as_ref()
+                .or(self.search_pattern.as_ref().filter(|_| self.search_highlight));
+            if let Some(pattern) = pattern {
+                if let Some(line) = self.contents.get_row(real_row, 0, usize::MAX) {
+                    draw_search_matches(&self.settings.borrow(), pattern, &line.to_string(), text_start, col_offset, cols.saturating_sub(num_width), output);
+                }
+            }
+
+            if let Some(selection) = self.get_selection() {
+                if let Some(line) = self.contents.get_row(real_row, 0, usize::MAX) {
+                    let draw = if self.selection_kind == VisualKind::Block { draw_block_selection } else { draw_selection };
+                    draw(&self.settings.borrow(), selection, (real_row, &line.to_string()), text_start, col_offset, cols.saturating_sub(num_width), output);
+                }
+            }
+
+            if let Some(range) = self.diagnostic_flash {
+                if let Some(line) = self.contents.get_row(real_row, 0, usize::MAX) {
+                    draw_selection(&self.settings.borrow(), range, (real_row, &line.to_string()), text_start, col_offset, cols.saturating_sub(num_width), output);
+                }
+            }
+
+            for range in self.snippet_selections() {
+                if let Some(line) = self.contents.get_row(real_row, 0, usize::MAX) {
+                    draw_selection(&self.settings.borrow(), range, (real_row, &line.to_string()), text_start, col_offset, cols.saturating_sub(num_width), output);
+                }
+            }
+
+            if let Some(brackets) = self.bracket_match.and_then(|bracket_match| bracket_match.brackets()) {
+                if let Some(line) = self.contents.get_row(real_row, 0, usize::MAX) {
+                    draw_matching_brackets(&self.settings.borrow(), brackets, (real_row, &line.to_string()), text_start, col_offset, cols.saturating_sub(num_width), output);
+                }
+            }
+        }
+    }
+
+    fn refresh(&mut self, container: &mut PaneContainer) {
+        self.receive_tree();
+        let mode = self.mode.clone();
+        mode.borrow_mut().refresh(self, container);
+        self.check_messages(container);
+        self.check_disk(container);
+        self.check_diagnostics_list();
+        self.which_key.refresh(&*self.mode.borrow(), &self.settings, &self.sender, container);
+        self.sync_snippet();
+
+        let cursor = self.cursor.borrow().get_cursor();
+        let revision = self.contents.revision();
+        self.edit_timer.update(revision, self.changed, Instant::now());
+        if !self.bracket_match.is_some_and(|bracket_match| bracket_match.is_current(cursor, revision)) {
+            let found = self.find_matching_bracket(cursor);
+            self.bracket_match = Some(BracketMatch { cursor, revision, found });
+        }
+
+        let delay = Duration::from_millis(self.settings.borrow().editor_settings.lsp_change_delay);
+        if self.changed_since.is_some_and(|since| since.elapsed() >= delay) {
+            self.flush_changes();
+        }
+
+        // The cursor has moved past what was typed by now
+        if let Some(c) = self.typed.take() {
+            if self.mode.borrow().get_name() == "Insert" {
+                self.auto_complete(c);
+            }
+        }
+
+        self.read_lsp_messages();
+        self.check_lsp_response(container);
+
+        if self.settings.borrow().editor_settings.spell {
+            self.check_spelling();
+        }
+        else if !self.misspellings.is_empty() {
+            self.misspellings.clear();
+            self.spelled_revision = None;
+        }
+    }
+
+
+    fn save_buffer(&mut self) -> io::Result<()> {
+        self.write_buffer(false)
+    }
+
+    fn open_file(&mut self, filename: &PathBuf) -> io::Result<()> {
+        let encoding = self.settings.borrow().editor_settings.fileencoding;
+        let (file, encoding, had_errors) = FileEncoding::read(filename, encoding)?;
+        self.encoding = encoding;
+        self.report_decode_errors(had_errors);
+
+        self.contents = Buffer::from(file);
+        self.contents.set_settings(self.settings.clone());
+        self.file_name = Some(PathBuf::from(filename));
+        self.disk_watch.update(filename);
+        self.load_undo_history(filename);
+
+        self.parse_from_scratch();
+        //eprintln!("{}", self.contents.to_string());
+
+        //eprintln!("{}", self.tree.root_node().to_sexp());
+
+        self.send_open();
+
+        Ok(())
+    }
+
+    fn reload_with_encoding(&mut self, encoding: &'static Encoding) -> io::Result<()> {
+        let file_name = match &self.file_name {
+            None => return Ok(()),
+            Some(file_name) => file_name.clone(),
+        };
+
+        let (file, encoding, had_errors) = FileEncoding::read(&file_name, Some(encoding))?;
+        self.encoding = encoding;
+        self.disk_watch.update(&file_name);
+
+        let end = self.contents.get_byte_count();
+        self.contents.replace(0..end, file);
+        self.set_changed(false);
+
+        self.parse_from_scratch();
+        self.send_change_text();
+
+        let (x, y) = self.cursor.borrow().get_cursor();
+        let cursor = self.cursor.clone();
+        cursor.borrow_mut().jump_to(x, y, self);
+
+        self.report_decode_errors(had_errors);
+        Ok(())
+    }
+
+    fn apply_text_edits(&mut self, edits: &[TextEdit]) {
+        if edits.is_empty() {
+            return;
+        }
+        self.contents.begin_group();
+        for edit in edits_from_bottom(edits) {
+            let (start, end) = edit.get_range();
+            self.replace_text_at(start, end, &edit.newText);
+        }
+        self.contents.end_group();
+
+        // The edits might have removed the text the cursor was on
+        let (x, y) = self.cursor.borrow().get_cursor();
+        let cursor = self.cursor.clone();
+        cursor.borrow_mut().jump_to(x, y, self);
+    }
+
+    fn process_keypress(&mut self, key: KeyEvent, container: &mut PaneContainer) -> io::Result<bool> {
+        let mode = self.mode.clone();
+        // A binding that timed out runs first so it doesn't end up in the recorded change
+        mode.borrow_mut().refresh(self, container);
+        let mode = self.mode.clone();
+        self.change_recorder.before_key(key, &*mode.borrow(), self.contents.edit_count());
+        self.diagnostic_flash = None;
+
+        let result = mode.borrow_mut().process_keypress(key, self, container);
+
+        let mode = self.mode.clone();
+        self.change_recorder.after_key(&*mode.borrow(), self.contents.edit_count());
+        self.which_key.keypress();
+
+        if self.info_popup.is_some() {
+            let moved = self.info_popup != Some(self.cursor.borrow().get_cursor());
+            if moved || key.code == KeyCode::Esc {
+                self.close_info_popup();
+            }
+        }
+        result
+    }
+
+    fn scroll_cursor(&mut self, container: &PaneContainer) {
+        let cursor = self.cursor.clone();
+
+        cursor.borrow_mut().scroll(self, container);
+        
+    }
+
+
+    fn get_status(&self, container: &PaneContainer) -> (String, String, String) {
+        self.mode.borrow_mut().update_status(self, container)
+    }
+
+    fn run_command(&mut self, command: &str, container: &PaneContainer) {
+        if let Some(substitute) = Substitute::parse(command.trim()) {
+            self.start_substitute(substitute, container);
+            return;
+        }
+
+        if let Some(line_command) = LineCommand::parse(command) {
+            self.run_line_command(line_command);
+            return;
+        }
+
+        let mut command_args = command.split_whitespace();
+        let command = command_args.next().unwrap_or("");
+        match command {
+            "q" => {
+                if self.changed {
+                    let message = "No write since last change (add ! to override)".to_string();
+                    self.sender.send(Message::SetMessage(message, MessageKind::Error)).expect("Failed to send message");
+                    return;
+                }
+                self.sender.send(Message::ClosePane(false, None)).unwrap();
+
+                let uri = self.generate_uri();
+                match &self.lsp_client {
+                    None => {},
+                    Some((sender,_)) => {
+
+                        sender.send(ControllerMessage::Notification(
+                            self.lang.clone().into(),
+                            LspNotification::Close(uri.into())
+                        )).expect("Failed to send message");
+                    },
+                }
+               
+                
+            },
+            "w" => {
+                if command_args.clone().next().is_none() && self.changed_on_disk() {
+                    return;
+                }
+
+                self.flush_changes();
+                self.file_version += 1;
+
+                let uri = self.generate_uri();
+                match &self.lsp_client {
+                    None => {},
+                    Some((sender, _)) => {
+                        //TODO replace filename with URI
+
+                        sender.send(ControllerMessage::Notification(
+                            self.lang.clone().into(),
+                            LspNotification::WillSave(uri.clone().into(), "manual".into())
+                        )).expect("Failed to send message");
+
+                    },
+                }
+
+                
+                if let Some(file_name) = command_args.next() {
+                    self.file_name = Some(PathBuf::from(file_name));
+                }
+
+                if let Err(error) = self.write_buffer(false) {
+                    self.report_save_error(error);
+                    return;
+                }
+
+                match &self.lsp_client {
+                    None => {},
+                    Some((sender, _)) => {
+
+                        let text = self.settings.borrow().editor_settings.prepare_save(self.contents.to_string());
+
+                        sender.send(ControllerMessage::Notification(
+                            self.lang.clone().into(),
+                            LspNotification::Save(uri.into(), text.into())
+                        )).expect("Failed to send message");
+                    },
+                }
+            },
+            "w!" => {
+
+                self.flush_changes();
+                self.file_version += 1;
+
+                let uri = self.generate_uri();
+                match &self.lsp_client {
+                    None => {},
+                    Some((sender, _)) => {
+                        //TODO replace filename with URI
+
+                        sender.send(ControllerMessage::Notification(
+                            self.lang.clone().into(),
+                            LspNotification::WillSave(uri.clone().into(), "manual".into())
+                        )).expect("Failed to send message");
+
+                    },
+                }
+
+                
+                if let Some(file_name) = command_args.next() {
+                    self.file_name = Some(PathBuf::from(file_name));
+                }
+
+                if let Err(error) = self.write_buffer(true) {
+                    self.report_save_error(error);
+                    return;
+                }
+
+                match &self.lsp_client {
+                    None => {},
+                    Some((sender, _)) => {
+
+                        let text = self.settings.borrow().editor_settings.prepare_save(self.contents.to_string());
+
+                        sender.send(ControllerMessage::Notification(
+                            self.lang.clone().into(),
+                            LspNotification::Save(uri.into(), text.into())
+                        )).expect("Failed to send message");
+                    },
+                }
+
+            },
+            "wq" => {
+                if self.changed_on_disk() {
+                    return;
+                }
+
+                self.flush_changes();
+                self.file_version += 1;
+
+                let uri = self.generate_uri();
+                match &self.lsp_client {
+                    None => {},
+                    Some((sender, _)) => {
+                        //TODO replace filename with URI
+
+                        sender.send(ControllerMessage::Notification(
+                            self.lang.clone().into(),
+                            LspNotification::WillSave(uri.clone().into(), "manual".into())
+                        )).expect("Failed to send message");
+
+                    },
+                }
+
+                
+                if let Err(error) = self.write_buffer(false) {
+                    self.report_save_error(error);
+                    return;
+                }
+                self.sender.send(Message::ClosePane(false, None)).unwrap();
+
+                match &self.lsp_client {
+                    None => {},
+                    Some((sender, _)) => {
+
+                        let text = self.settings.borrow().editor_settings.prepare_save(self.contents.to_string());
+
+                        sender.send(ControllerMessage::Notification(
+                            self.lang.clone().into(),
+                            LspNotification::Save(uri.clone().into(), text.into())
+                        )).expect("Failed to send message");
+                    },
+                }
+
+
+                match &self.lsp_client {
+                    None => {},
+                    Some((sender,_)) => {
+
+                        sender.send(ControllerMessage::Notification(
+                            self.lang.clone().into(),
+                            LspNotification::Close(uri.into())
+                        )).expect("Failed to send message");
+                    },
+                }
+                
+            },
+            "file_info" => {
+                let (x, y) = self.cursor.borrow().get_cursor();
+                let offset = self.contents.get_byte_offset(x, y).unwrap_or(self.contents.get_byte_count());
+
+                let message = format!("{} lines, {} chars, {} bytes, byte {}, {}",
+                                      self.contents.get_line_count(),
+                                      self.contents.get_char_count(),
+                                      self.contents.get_byte_count(),
+                                      offset,
+                                      self.encoding.name());
+
+                self.sender.send(Message::SetStatus(message)).expect("Failed to send message");
+            },
+            "local_definition" | "local_declaration" if self.lsp_client.is_some() => {
+                let command = if command == "local_definition" {
+                    "goto_definition"
+                }
+                else {
+                    "goto_declaration"
+                };
+                self.run_command(command, container);
+            },
+            "local_definition" | "local_declaration" => {
+                let (x, y) = self.cursor.borrow().get_cursor();
+                let text = self.contents.to_string();
+
+                let word = text.lines().nth(y).and_then(|line| definition::word_at(line, x));
+                let target = word.and_then(|(start, word)| {
+                    let mut candidates = self.find_definitions(&text, &word);
+                    if candidates.is_empty() {
+                        candidates = definition::find_candidates(&text, &word);
+                    }
+                    definition::pick_candidate(candidates, (start, y), command == "local_declaration")
+                });
+
+                match target {
+                    None => {
+                        self.sender.send(Message::SetStatus("Definition not found".to_string())).expect("Failed to send message");
+                    },
+                    Some((x, y)) => {
+                        self.jump_table.add(*self.cursor.borrow());
+                        self.cursor.borrow_mut().jump_to(x, y, self);
+                    },
+                }
+            },
+            "record_macro" => {
+                self.sender.send(Message::RecordMacro).expect("Failed to send message");
+            },
+            "play_macro" => {
+                let count = command_args.next()
+                    .and_then(|count| count.parse::<usize>().ok())
+                    .unwrap_or(1);
+
+                self.sender.send(Message::PlayMacro(count)).expect("Failed to send message");
+            },
+            "fixindent" => {
+                let (use_spaces, tab_size) = {
+                    let settings = self.settings.borrow();
+                    (settings.editor_settings.use_spaces, settings.editor_settings.tab_size)
+                };
+                let edits = indent::fix_indent(&self.contents.to_string(), use_spaces, tab_size);
+
+                let message = format!("Fixed the indentation of {} lines", edits.len());
+                if !edits.is_empty() {
+                    self.set_changed(true);
+                    self.contents.replace_chain(edits.into_boxed_slice());
+
+                    // Every line could have changed so we parse from scratch
+                    self.parse_from_scratch();
+                    self.send_change_text();
+                }
+
+                self.sender.send(Message::SetStatus(message)).expect("Failed to send message");
+            },
+            "registers" | "reg" => {
+                self.sender.send(Message::RequestRegisters).expect("Failed to send message");
+            },
+            "recent" => {
+                self.sender.send(Message::RequestRecentFiles).expect("Failed to send message");
+            },
+            "find_file" => {
+                self.sender.send(Message::FindFile).expect("Failed to send message");
+            },
+            "ls" | "buffers" => {
+                self.sender.send(Message::ListBuffers).expect("Failed to send message");
+            },
+            "b" | "buffer" => {
+                let query = command_args.collect::<Vec<&str>>().join(" ");
+                if query.is_empty() {
+                    self.sender.send(Message::ListBuffers).expect("Failed to send message");
+                }
+                else {
+                    self.sender.send(Message::SwitchBuffer(query)).expect("Failed to send message");
+                }
+            },
+            "bd" | "bdelete" | "bd!" | "bdelete!" => {
+                let query = command_args.collect::<Vec<&str>>().join(" ");
+                let query = if query.is_empty() { None } else { Some(query) };
+                self.sender.send(Message::DeleteBuffer(query, command.ends_with('!'))).expect("Failed to send message");
+            },
+            "alternate_buffer" => {
+                self.sender.send(Message::AlternateBuffer).expect("Failed to send message");
+            },
+            "reopen" => {
+                self.sender.send(Message::Reopen).expect("Failed to send message");
+            },
+            "grep" => {
+                let pattern = command_args.collect::<Vec<&str>>().join(" ");
+                if pattern.is_empty() {
+                    self.sender.send(Message::SetStatus("Nothing to search for".to_string())).expect("Failed to send message");
+                }
+                else {
+                    self.sender.send(Message::Grep(pattern)).expect("Failed to send message");
+                }
+            },
+            "grep!" => {
+                self.sender.send(Message::CancelGrep).expect("Failed to send message");
+            },
+            "cn" | "cnext" => {
+                self.sender.send(Message::NextQuickFix).expect("Failed to send message");
+            },
+            "cp" | "cprevious" => {
+                self.sender.send(Message::PreviousQuickFix).expect("Failed to send message");
+            },
+            "checkhealth" => {
+                self.sender.send(Message::CheckHealth).expect("Failed to send message");
+            },
+            "lsp" => {
+                let action = command_args.next().unwrap_or("status");
+                let lang = command_args.next().unwrap_or(self.lang.as_str()).to_string();
+                let message = match action {
+                    "restart" => Message::RestartLsp(lang),
+                    "stop" => Message::StopLsp(lang),
+                    "status" => Message::LspStatus,
+                    "log" => Message::LspLog,
+                    "root" => match &self.file_name {
+                        Some(file_name) => Message::SetStatus(format!("Project root: {}", find_root(file_name).display())),
+                        None => Message::SetStatus("The buffer has no file".to_string()),
+                    },
+                    _ => Message::SetStatus(format!("Unknown lsp command: {}", action)),
+                };
+                self.sender.send(message).expect("Failed to send message");
+            },
+            "q!" => {
+                self.sender.send(Message::ClosePane(false, None)).unwrap();
+                let uri = self.generate_uri();
+
+                match &self.lsp_client {
+                    None => {},
+                    Some((sender,_)) => {
+
+                        sender.send(ControllerMessage::Notification(
+                            self.lang.clone().into(),
+                            LspNotification::Close(uri.into())
+                        )).expect("Failed to send message");
+                    },
+                }
+
+            },
+            "first_non_blank" => {
+                self.move_to_first_non_blank(false);
+            },
+            "word_start_forward" | "word_start_backward" | "word_end_forward" => {
+                let count = command_args.next()
+                    .and_then(|count| count.parse::<usize>().ok())
+                    .unwrap_or(1)
+                    .max(1);
+                for _ in 0..count {
+                    self.move_by_word(command);
+                }
+            },
+            "reselect" => {
+                if let Some(((anchor_x, anchor_y), (x, y), kind)) = self.last_selection {
+                    // Edits since then can leave the ends past the buffer
+                    let cursor = self.cursor.clone();
+                    cursor.borrow_mut().jump_to(anchor_x, anchor_y, self);
+                    self.selection_anchor = None;
+                    self.change_mode(kind.mode_name());
+                    cursor.borrow_mut().jump_to(x, y, self);
+                }
+            },
+            "smart_home" => {
+                self.move_to_first_non_blank(true);
+            },
+            "line_center" | "line_top" | "line_bottom" => {
+                let place = match command {
+                    "line_top" => ScreenLine::Top,
+                    "line_bottom" => ScreenLine::Bottom,
+                    _ => ScreenLine::Center,
+                };
+                let (_, rows) = container.get_text_size();
+                self.cursor.borrow_mut().place_line(place, rows, self);
+            },
+            "move" => {
+                let direction = command_args.next();
+                let direction = match direction {
+                    Some("up") => Direction::Up,
+                    Some("down") => Direction::Down,
+                    Some("left") => Direction::Left,
+                    Some("right") => Direction::Right,
+                    Some("line_start") => Direction::LineStart,
+                    Some("line_end") => Direction::LineEnd,
+                    Some("file_top") => Direction::FileTop,
+                    Some("file_bottom") => Direction::FileBottom,
+                    Some("page_up") => Direction::PageUp,
+                    Some("page_down") => Direction::PageDown,
+                    _ => panic!("Invalid direction"),
+                };
+
+                let amount = command_args.next().unwrap_or("1").parse::<usize>().unwrap_or(1);
+
+                match direction {
+                    Direction::FileBottom | Direction::FileTop | Direction::PageUp | Direction::PageDown => {
+                        let cursor = self.cursor.borrow();
+                        self.jump_table.add(*cursor);
+                    },
+                    _ => {},
+                }
+
+                self.cursor.borrow_mut().move_cursor(direction, amount, self);
+            },
+            "mode" => {
+                let mode = command_args.next().unwrap_or("Normal");
+                self.change_mode(mode);
+            },
+            "jump" => {
                 if let Some(jump) = command_args.next() {
                     match jump {
                         "next" => {
@@ -1413,112 +3337,629 @@ impl Pane for TreesitterPane {
                                     *cursor = new_cursor;
                                 }
 
-                            }
+                            }
+
+                        }
+
+                    }
+                }
+
+            },
+            "set_jump" => {
+                //eprintln!("Setting jump");
+                let cursor = self.cursor.borrow();
+                if let Some(jump) = command_args.next() {
+                    self.jump_table.add_named(jump, *cursor);
+                }
+                else {
+                    self.jump_table.add(*cursor);
+                }
+                
+            },
+            "horizontal_split" => {
+                self.sender.send(Message::HorizontalSplit).expect("Failed to send message");
+            },
+            "vertical_split" => {
+                self.sender.send(Message::VerticalSplit).expect("Failed to send message");
+            },
+            "map" | "noremap" | "unmap" | "maps" => {
+                let args = command_args.collect::<Vec<_>>();
+                let message = match map_message(command, &args) {
+                    Ok(message) => message,
+                    Err(usage) => Message::SetMessage(usage, MessageKind::Error),
+                };
+                self.sender.send(message).expect("Failed to send message");
+            },
+            "reload_config" => {
+                self.sender.send(Message::ReloadConfig).expect("Failed to send message");
+            },
+            "qa" => {
+                self.sender.send(Message::QuitAll).expect("Failed to send message");
+            },
+            "qa!" => {
+                self.sender.send(Message::ForceQuitAll).expect("Failed to send message");
+            },
+            "rotate_panes" => {
+                self.sender.send(Message::RotatePanes).expect("Failed to send message");
+            },
+            "swap_pane" => {
+                self.sender.send(Message::SwapPane).expect("Failed to send message");
+            },
+            "pane_up" => {
+                self.sender.send(Message::PaneUp).expect("Failed to send message");
+            },
+            "pane_down" => {
+                self.sender.send(Message::PaneDown).expect("Failed to send message");
+            },
+            "pane_left" => {
+                self.sender.send(Message::PaneLeft).expect("Failed to send message");
+            },
+            "pane_right" => {
+                self.sender.send(Message::PaneRight).expect("Failed to send message");
+            },
+            "e!" => {
+                if self.file_name.is_none() {
+                    self.sender.send(Message::SetMessage("No file name".to_string(), MessageKind::Error)).expect("Failed to send message");
+                    return;
+                }
+                match self.reload_from_disk() {
+                    Ok(()) => self.sender.send(Message::SetStatus("Reloaded from disk".to_string())).expect("Failed to send message"),
+                    Err(error) => self.sender.send(Message::SetMessage(error.to_string(), MessageKind::Error)).expect("Failed to send message"),
+                }
+            },
+            "e" => {
+                let mut encoding = None;
+                let mut file_name = None;
+                for arg in command_args {
+                    match arg.strip_prefix("++enc=") {
+                        Some(label) => encoding = Some(label),
+                        None => file_name = Some(arg),
+                    }
+                }
+
+                let encoding = match encoding.map(parse_encoding) {
+                    None => None,
+                    Some(Ok(encoding)) => Some(encoding),
+                    Some(Err(error)) => {
+                        self.sender.send(Message::SetStatus(error)).expect("Failed to send message");
+                        return;
+                    },
+                };
+
+                match (file_name, encoding) {
+                    (Some(file_name), None) => {
+                        self.sender.send(Message::OpenFile(file_name.to_string(), None)).expect("Failed to send message");
+                    },
+                    (Some(file_name), Some(encoding)) => {
+                        self.sender.send(Message::OpenFileWithEncoding(file_name.to_string(), encoding)).expect("Failed to send message");
+                    },
+                    (None, Some(encoding)) => {
+                        if let Err(error) = self.reload_with_encoding(encoding) {
+                            self.sender.send(Message::SetStatus(error.to_string())).expect("Failed to send message");
+                        }
+                    },
+                    (None, None) => {},
+                }
+            },
+            "prompt_jump" => {
+                let (send, recv) = std::sync::mpsc::channel();
+                let (send2, recv2) = std::sync::mpsc::channel();
+
+                self.popup_channels = Some((send2, recv));
 
-                        }
+                let txt_prompt = PromptType::Text(String::new(), None, false);
+                let prompt = vec!["Enter Jump".to_string(), "Target".to_string()];
+
+                let pane = PopUpPane::new_prompt(
+                    self.settings.clone(),
+                    prompt,
+                    self.sender.clone(),
+                    send,
+                    recv2,
+                    vec![txt_prompt],
+                    true
+                );
+
+                let pane = Rc::new(RefCell::new(pane));
+
+                let (_, (x2, y2)) = container.get_corners();
+                let (x, y) = container.get_size();
+
+                let (x, y) = (x / 2, y / 2);
+
+                let pos = (x2 - 14 - x, y2 - 6 - y);
+
+
+                let max_size = container.get_size();
+                
+                let mut container = PaneContainer::new(max_size, (14, 5), pane, self.settings.clone());
+
+
+                container.set_position(pos);
+                container.set_size((14, 5));
+
+
+
+                self.sender.send(Message::CreatePopup(container, true)).expect("Failed to send message");
+                self.waiting = Waiting::JumpTarget;
+            },
+            "prompt_set_jump" => {
+                let (send, recv) = std::sync::mpsc::channel();
+                let (send2, recv2) = std::sync::mpsc::channel();
+
+                self.popup_channels = Some((send2, recv));
+
+                let txt_prompt = PromptType::Text(String::new(), None, false);
+                let prompt = vec!["Name the".to_string(), "Target".to_string()];
+
+                let pane = PopUpPane::new_prompt(
+                    self.settings.clone(),
+                    prompt,
+                    self.sender.clone(),
+                    send,
+                    recv2,
+                    vec![txt_prompt],
+                    true
+                );
 
+                let pane = Rc::new(RefCell::new(pane));
+
+                let (_, (x2, y2)) = container.get_corners();
+                let (x, y) = container.get_size();
+
+                let (x, y) = (x / 2, y / 2);
+
+                let pos = (x2 - 14 - x, y2 - 6 - y);
+
+
+                let max_size = container.get_size();
+                
+                let mut container = PaneContainer::new(max_size, (14, 5), pane, self.settings.clone());
+
+
+                container.set_position(pos);
+                container.set_size((14, 5));
+
+
+
+                self.sender.send(Message::CreatePopup(container, true)).expect("Failed to send message");
+                self.waiting = Waiting::JumpPosition;
+            },
+            "jump_match" => {
+                let (x, y) = self.cursor.borrow().get_cursor();
+                let found = brackets::bracket_from(&self.contents, (x, y))
+                    .and_then(|x| self.find_matching_bracket((x, y)));
+
+                if let Some((x, y)) = found {
+                    // Far jumps can be gone back from like other jumps
+                    let current = *self.cursor.borrow();
+                    if y.abs_diff(current.get_cursor().1) > container.get_text_size().1 {
+                        self.jump_table.add(current);
                     }
 
-                    self.open_info(container);
+                    let cursor = self.cursor.clone();
+                    cursor.borrow_mut().jump_to(x, y, self);
                 }
+            },
+            "shift_lines" => {
+                let right = command_args.next() == Some("right");
+                let (_, y) = self.cursor.borrow().get_cursor();
+
+                let (first, last) = match command_args.next() {
+                    Some("selection") => match self.get_selection() {
+                        None => return,
+                        Some((start, end)) => (start.1, end.1),
+                    },
+                    count => {
+                        let count = count.and_then(|count| count.parse::<usize>().ok()).unwrap_or(1).max(1);
+                        (y, y + count - 1)
+                    },
+                };
 
+                self.shift_lines(first, last, right);
             },
-            "set_jump" => {
-                //eprintln!("Setting jump");
-                let cursor = self.cursor.borrow();
-                if let Some(jump) = command_args.next() {
-                    self.jump_table.add_named(jump, *cursor);
+            "trim_whitespace" => {
+                // Only reached with `selection`, the whole buffer is a line command
+                if let Some((start, end)) = self.get_selection() {
+                    self.run_line_command(LineCommand {
+                        range: Some((start.1 + 1, end.1 + 1)),
+                        operation: LineOperation::TrimWhitespace,
+                    });
+                }
+            },
+            "format_selection" => {
+                if let Some((start, end)) = self.get_selection() {
+                    self.format_lines(start.1, end.1);
+                }
+            },
+            "open_line" => {
+                let (_, y) = self.cursor.borrow().get_cursor();
+                let line = self.cursor_line(y);
+                let cursor = self.cursor.clone();
+
+                match command_args.next() {
+                    Some("above") => {
+                        // The new line gets the indentation of the line it goes above
+                        let indent = indent::leading_whitespace(&line).to_string();
+                        cursor.borrow_mut().jump_to(0, y, self);
+                        self.insert_str(&format!("{}\n", indent));
+                        cursor.borrow_mut().jump_to(indent.chars().count(), y, self);
+                    },
+                    _ => {
+                        cursor.borrow_mut().jump_to(line.chars().count(), y, self);
+                        self.insert_newline();
+                    },
+                }
+            },
+            "undo" => {
+                let change = self.contents.undo();
+
+                self.cursor.borrow_mut().number_line_size = self.contents.get_line_count();
+
+                // The cursor goes to where the undone change was
+                if let Some(change) = change {
+                    self.edit_tree(&change);
+
+                    let (x, y) = change.start;
+                    let cursor = self.cursor.clone();
+                    cursor.borrow_mut().jump_to(x, y, self);
+                }
+
+            },
+            "redo" => {
+                let change = self.contents.redo();
+                self.cursor.borrow_mut().number_line_size = self.contents.get_line_count();
+
+                if let Some(change) = change {
+                    self.edit_tree(&change);
+
+                    let (x, y) = change.new_end;
+                    let cursor = self.cursor.clone();
+                    cursor.borrow_mut().jump_to(x, y, self);
+                }
+            },
+            "change_tab" => {
+                if let Some(tab) = command_args.next() {
+                    if let Ok(tab) = tab.parse::<usize>() {
+                        self.sender.send(Message::NthTab(tab)).expect("Failed to send message");
+                    }
+                    else {
+                        match tab {
+                            "prev" => {
+                                self.sender.send(Message::PreviousTab).expect("Failed to send message");
+                            },
+                            "next" => {
+                                self.sender.send(Message::NextTab).expect("Failed to send message");
+                            },
+                            _ => {}
+                        }
+                    }
+                }
+            },
+            "open_tab" => {
+                self.sender.send(Message::OpenNewTab).expect("Failed to send message");
+            },
+            "open_tab_with_pane" => {
+                self.sender.send(Message::OpenNewTabWithPane).expect("Failed to send message");
+            },
+            "show_diagnostic" | "info" => {
+                self.show_diagnostic(container);
+            },
+            "hover" => {
+                self.request_hover();
+            },
+            "format" => {
+                self.format_buffer();
+            },
+            "code_action" => {
+                self.request_code_actions();
+            },
+            "apply_code_action" => {
+                let actions = std::mem::take(&mut self.code_actions);
+                let action = command_args.next()
+                    .and_then(|index| index.parse::<usize>().ok())
+                    .and_then(|index| actions.into_iter().nth(index));
+
+                if let Some(action) = action {
+                    self.apply_code_action(action);
+                }
+            },
+            "rename" => {
+                let new_name = command_args.collect::<Vec<&str>>().join(" ");
+                if !new_name.is_empty() {
+                    self.rename_symbol(&new_name);
+                    return;
+                }
+                if self.lsp_client.is_none() {
+                    return;
+                }
+
+                let (send, recv) = std::sync::mpsc::channel();
+                let (send2, recv2) = std::sync::mpsc::channel();
+
+                self.popup_channels = Some((send2, recv));
+
+                let txt_prompt = PromptType::Text(String::new(), None, false);
+                let prompt = vec!["Rename to".to_string()];
+
+                let pane = PopUpPane::new_prompt(
+                    self.settings.clone(),
+                    prompt,
+                    self.sender.clone(),
+                    send,
+                    recv2,
+                    vec![txt_prompt],
+                    true
+                );
+
+                let pane = Rc::new(RefCell::new(pane));
+
+                let ((x1, y1), _) = container.get_corners();
+                let (x, y) = container.get_size();
+                let size = (30.min(x), 4.min(y));
+
+                let pos = ((x1 + x / 2).saturating_sub(size.0 / 2), (y1 + y / 2).saturating_sub(size.1 / 2));
+
+                let max_size = container.get_size();
+
+                let mut container = PaneContainer::new(max_size, size, pane, self.settings.clone());
+                container.set_position(pos);
+                container.set_size(size);
+
+                self.sender.send(Message::CreatePopup(container, true)).expect("Failed to send message");
+                self.waiting = Waiting::Rename;
+            },
+            "diagnostics" => {
+                let (send, recv) = std::sync::mpsc::channel();
+                let pane = DiagnosticsPane::new(self.settings.clone(),
+                                                self.lsp_diagnostics.clone(),
+                                                self.sender.clone(),
+                                                send);
+
+                self.diagnostics_list = Some(recv);
+                self.sender.send(Message::HorizontalSplitWithPane(Rc::new(RefCell::new(pane))))
+                    .expect("Failed to send message");
+            },
+            "completion" => {
+                // A list that opened while typing gets the focus instead of being asked for again
+                if self.complete_path(container) {}
+                else if self.lsp_client.is_none() {
+                    self.sender.send(Message::CompleteWords).expect("Failed to send message");
+                }
+                else if self.auto_completion && self.lsp_completion.is_some() {
+                    if let Some((send, _)) = self.popup_channels.take() {
+                        let _ = send.send(PaneMessage::Close);
+                    }
+                    self.auto_completion = false;
+                    self.show_completions(container);
                 }
                 else {
-                    self.jump_table.add(*cursor);
+                    // Any completion popup that is still open is replaced by the new one
+                    self.request_completion("invoked", false);
                 }
-                
             },
-            "horizontal_split" => {
-                self.sender.send(Message::HorizontalSplit).expect("Failed to send message");
-                self.contents.add_new_rope();
+            "snippet_next" => self.jump_to_tabstop(true),
+            "snippet_previous" => self.jump_to_tabstop(false),
+            "insert" => {
+                let completion = command_args.next()
+                    .and_then(|index| index.parse::<usize>().ok())
+                    .and_then(|index| self.lsp_completion.as_ref()?.get_completion(index).cloned());
+                if let Some(completion) = completion {
+                    self.insert_completion(&completion);
+                }
+                self.lsp_completion = None;
             },
-            "vertical_split" => {
-                self.sender.send(Message::VerticalSplit).expect("Failed to send message");
-                self.contents.add_new_rope();
+            "goto_declaration" | "goto_definition" |
+            "goto_type_definition" | "goto_implementation" => {
+                let uri = self.generate_uri();
+
+                let position = self.cursor.borrow().get_cursor();
+
+                let request = match command {
+                    "goto_declaration" => LspRequest::GotoDeclaration(uri.into(), position),
+                    "goto_definition" => LspRequest::GotoDefinition(uri.into(), position),
+                    "goto_type_definition" => LspRequest::GotoTypeDefinition(uri.into(), position),
+                    "goto_implementation" => LspRequest::GotoImplementation(uri.into(), position),
+                    _ => unreachable!(),
+                };
+
+                self.send_lsp_request(request, Waiting::GotoResponse);
             },
-            "qa!" => {
-                self.sender.send(Message::ForceQuitAll).expect("Failed to send message");
+            "find_references" => {
+                let uri = self.generate_uri();
+                let position = self.cursor.borrow().get_cursor();
+                let include_declaration = command_args.next() != Some("nodecl");
+
+                self.send_lsp_request(
+                    LspRequest::References(uri.into(), position, include_declaration),
+                    Waiting::ReferencesResponse
+                );
             },
-            "pane_up" => {
-                self.sender.send(Message::PaneUp).expect("Failed to send message");
-                self.contents.add_new_rope();
+            "goto" => {
+                if let Some(path) = command_args.next() {
+                    let pos = if let Some(pos) = command_args.next() {
+                        let split = pos.split(",").collect::<Vec<_>>();
+                        let x = split[0].parse::<usize>().expect("Failed to parse x");
+                        let y = split[1].parse::<usize>().expect("Failed to parse y");
+                        Some((x, y))
+                    }
+                    else {
+                        None
+                    };
+
+                    let message = Message::OpenFile(path.to_string(), pos);
+
+                    self.sender.send(message).expect("Failed to send message");
+                    
+                }
             },
-            "pane_down" => {
-                self.sender.send(Message::PaneDown).expect("Failed to send message");
-                self.contents.add_new_rope();
+            "set" => {
+                let mut settings = self.settings.borrow_mut();
+                for option in command_args {
+                    match option {
+                        "scrollbind" | "scb" => {
+                            self.sender.send(Message::ScrollBind(true)).expect("Failed to send message");
+                        },
+                        "noscrollbind" | "noscb" => {
+                            self.sender.send(Message::ScrollBind(false)).expect("Failed to send message");
+                        },
+                        option => {
+                            if let Err(error) = settings.editor_settings.set_option(option) {
+                                self.sender.send(Message::SetMessage(error, MessageKind::Error)).expect("Failed to send message");
+                            }
+                        },
+                    }
+                }
+                self.sender.send(Message::ForceRedraw).expect("Failed to send message");
             },
-            "pane_left" => {
-                self.sender.send(Message::PaneLeft).expect("Failed to send message");
-                self.contents.add_new_rope();
+            "setlocal" => {
+                for option in command_args {
+                    let border = match option {
+                        "border" => Some(true),
+                        "noborder" => Some(false),
+                        "border&" => None,
+                        option => {
+                            let message = Message::SetMessage(format!("Unknown local option: {}", option), MessageKind::Error);
+                            self.sender.send(message).expect("Failed to send message");
+                            continue;
+                        },
+                    };
+                    self.sender.send(Message::PaneBorder(border)).expect("Failed to send message");
+                }
             },
-            "pane_right" => {
-                self.sender.send(Message::PaneRight).expect("Failed to send message");
-                self.contents.add_new_rope();
+            "diagnostic_next" | "diagnostic_prev" => {
+                let severity = match command_args.next() {
+                    None => None,
+                    Some(name) => match parse_severity(name) {
+                        Some(severity) => Some(severity),
+                        None => {
+                            let message = format!("Unknown severity: {}", name);
+                            self.sender.send(Message::SetMessage(message, MessageKind::Error)).expect("Failed to send message");
+                            return;
+                        },
+                    },
+                };
+
+                let (x, y) = self.cursor.borrow().get_cursor();
+                let range = self.lsp_diagnostics.borrow()
+                    .next_diagnostic((y, x), command == "diagnostic_next", severity)
+                    .map(|diagnostic| diagnostic.range.get_positions());
+
+                match range {
+                    None => {
+                        self.sender.send(Message::SetMessage("No diagnostics".to_string(), MessageKind::Info))
+                            .expect("Failed to send message");
+                    },
+                    Some((start, end)) => {
+                        self.jump_table.add(*self.cursor.borrow());
+                        self.cursor.borrow_mut().jump_to(start.0, start.1, self);
+
+                        // The end of the range is exclusive but selections include their last column
+                        let end = match end.0.checked_sub(1) {
+                            Some(col) => (col, end.1),
+                            None if end.1 > start.1 => (usize::MAX, end.1 - 1),
+                            None => end,
+                        };
+                        self.diagnostic_flash = Some((start, end));
+                    },
+                }
             },
-            "e" => {
-                if let Some(file_name) = command_args.next() {
-                    self.sender.send(Message::OpenFile(file_name.to_string(), None)).expect("Failed to send message");
+            "spell_next" | "spell_prev" => {
+                let (x, y) = self.cursor.borrow().get_cursor();
+
+                let target = if command == "spell_next" {
+                    self.misspellings.iter()
+                        .find(|misspelling| (misspelling.row, misspelling.start) > (y, x))
+                        .or(self.misspellings.first())
+                }
+                else {
+                    self.misspellings.iter().rev()
+                        .find(|misspelling| (misspelling.row, misspelling.start) < (y, x))
+                        .or(self.misspellings.last())
+                };
+
+                if let Some(misspelling) = target {
+                    let (x, y) = (misspelling.start, misspelling.row);
+                    self.jump_table.add(*self.cursor.borrow());
+                    self.cursor.borrow_mut().jump_to(x, y, self);
                 }
-                self.contents.add_new_rope();
             },
-            "prompt_jump" => {
+            "spell_suggest" => {
+                let (x, y) = self.cursor.borrow().get_cursor();
+
+                let misspelling = match self.misspellings.iter().find(|misspelling| misspelling.contains(y, x)) {
+                    None => return,
+                    Some(misspelling) => misspelling.clone(),
+                };
+                let suggestions = match &self.spell_checker {
+                    None => return,
+                    Some(checker) => checker.suggest(&misspelling.word, 10),
+                };
+                if suggestions.is_empty() {
+                    return;
+                }
+
+                if let Some((send, _)) = self.popup_channels.take() {
+                    let _ = send.send(PaneMessage::Close);
+                }
+
                 let (send, recv) = std::sync::mpsc::channel();
                 let (send2, recv2) = std::sync::mpsc::channel();
 
                 self.popup_channels = Some((send2, recv));
 
-                let txt_prompt = PromptType::Text(String::new(), None, false);
-                let prompt = vec!["Enter Jump".to_string(), "Target".to_string()];
+                let width = suggestions.iter().map(|word| word.chars().count()).max().unwrap_or(0).max(11) + 2;
+                let size = (width, suggestions.len() + 3);
 
-                let pane = PopUpPane::new_prompt(
+                let mut buttons = Vec::new();
+                for suggestion in suggestions {
+                    let word = suggestion.clone();
+                    let function: ButtonFunction = Box::new(move |_| word.clone());
+                    buttons.push((suggestion, function));
+                }
+
+                let buttons = PromptType::Button(buttons, 0);
+                let prompt = vec!["Suggestions".to_string()];
+
+                let pane = PopUpPane::new_dropdown(
                     self.settings.clone(),
                     prompt,
                     self.sender.clone(),
                     send,
                     recv2,
-                    vec![txt_prompt],
+                    buttons,
                     true
                 );
 
                 let pane = Rc::new(RefCell::new(pane));
 
-                let (_, (x2, y2)) = container.get_corners();
-                let (x, y) = container.get_size();
-
-                let (x, y) = (x / 2, y / 2);
-
-                let pos = (x2 - 14 - x, y2 - 6 - y);
-
+                let pos = self.cursor.borrow().get_real_cursor();
 
                 let max_size = container.get_size();
-                
-                let mut container = PaneContainer::new(max_size, (14, 5), pane, self.settings.clone());
 
+                let mut container = PaneContainer::new(max_size, size, pane, self.settings.clone());
 
                 container.set_position(pos);
-                container.set_size((14, 5));
-
-
+                container.set_size(size);
 
                 self.sender.send(Message::CreatePopup(container, true)).expect("Failed to send message");
-                self.waiting = Waiting::JumpTarget;
-
-                self.contents.add_new_rope();
+                self.waiting = Waiting::Spelling;
+                self.spell_target = Some(misspelling);
             },
-            "prompt_set_jump" => {
+            "spell_replace" => {
+                if let (Some(word), Some(target)) = (command_args.next(), self.spell_target.take()) {
+                    self.replace_text_at((target.start, target.row), (target.end, target.row), word);
+                    self.cursor.borrow_mut().jump_to(target.start, target.row, self);
+                }
+            },
+            "start_search" => {
                 let (send, recv) = std::sync::mpsc::channel();
                 let (send2, recv2) = std::sync::mpsc::channel();
 
                 self.popup_channels = Some((send2, recv));
 
                 let txt_prompt = PromptType::Text(String::new(), None, false);
-                let prompt = vec!["Name the".to_string(), "Target".to_string()];
+                let prompt = vec!["Search for".to_string()];
 
                 let pane = PopUpPane::new_prompt(
                     self.settings.clone(),
@@ -1532,404 +3973,178 @@ impl Pane for TreesitterPane {
 
                 let pane = Rc::new(RefCell::new(pane));
 
-                let (_, (x2, y2)) = container.get_corners();
+                let ((x1, y1), _) = container.get_corners();
                 let (x, y) = container.get_size();
+                let size = (30.min(x), 4.min(y));
 
-                let (x, y) = (x / 2, y / 2);
-
-                let pos = (x2 - 14 - x, y2 - 6 - y);
-
+                let pos = ((x1 + x / 2).saturating_sub(size.0 / 2), (y1 + y / 2).saturating_sub(size.1 / 2));
 
                 let max_size = container.get_size();
-                
-                let mut container = PaneContainer::new(max_size, (14, 5), pane, self.settings.clone());
-
 
+                let mut container = PaneContainer::new(max_size, size, pane, self.settings.clone());
                 container.set_position(pos);
-                container.set_size((14, 5));
-
-
+                container.set_size(size);
 
                 self.sender.send(Message::CreatePopup(container, true)).expect("Failed to send message");
-                self.waiting = Waiting::JumpPosition;
-
-                self.contents.add_new_rope();
+                self.waiting = Waiting::Search;
             },
-            "undo" => {
-                self.contents.undo();
-
-                self.cursor.borrow_mut().number_line_size = self.contents.get_line_count();
-
-                self.cursor.borrow_mut().set_cursor(CursorMove::Nothing, CursorMove::Amount(self.contents.get_line_count()), self, (0,0));
-
-                self.tree = self.parser.parse(&self.contents.to_string(),None).unwrap();//TODO: replace this with an incremental parse
-
-            },
-            "redo" => {
-                self.contents.redo();
-                self.cursor.borrow_mut().number_line_size = self.contents.get_line_count();
-
-                self.tree = self.parser.parse(&self.contents.to_string(),None).unwrap();//TODO: replace this with an incremental parse
+            "search" => {
+                let pattern = command_args.collect::<Vec<&str>>().join(" ");
+                self.search(&pattern);
             },
-            "change_tab" => {
-                if let Some(tab) = command_args.next() {
-                    if let Ok(tab) = tab.parse::<usize>() {
-                        self.sender.send(Message::NthTab(tab)).expect("Failed to send message");
-                    }
-                    else {
-                        match tab {
-                            "prev" => {
-                                self.sender.send(Message::PreviousTab).expect("Failed to send message");
-                            },
-                            "next" => {
-                                self.sender.send(Message::NextTab).expect("Failed to send message");
-                            },
-                            _ => {}
-                        }
-                    }
-                }
+            "search_next" => {
+                self.search_step(true);
             },
-            "open_tab" => {
-                self.sender.send(Message::OpenNewTab).expect("Failed to send message");
+            "search_previous" => {
+                self.search_step(false);
             },
-            "open_tab_with_pane" => {
-                self.sender.send(Message::OpenNewTabWithPane).expect("Failed to send message");
+            "repeat_change" => {
+                let count = command_args.next()
+                    .and_then(|count| count.parse::<usize>().ok())
+                    .unwrap_or(1)
+                    .max(1);
+
+                let keys = self.change_recorder.last_change().to_vec();
+                if !keys.is_empty() {
+                    self.sender.send(Message::MacroKeys(keys, count)).expect("Failed to send message");
+                }
             },
-            "info" => {
-                self.open_info(container);
+            "noh" | "nohlsearch" => {
+                self.search_highlight = false;
             },
-            "completion" => {
-                match &self.lsp_client {
-                    None => {},
-                    Some((sender, _)) => {
-
-                        match self.popup_channels.take() {
-                            None => {},
-                            Some((send, _)) => {
-                                match send.send(PaneMessage::Close) {
-                                    Ok(_) => {},
-                                    Err(_) => {},
-                                }
-                            }
-                        }
-
-
-                        let uri = self.generate_uri();
-
-                        let position = self.cursor.borrow().get_cursor();
-
-                        sender.send(ControllerMessage::Request(
-                             self.lang.clone().into(),
-                            LspRequest::RequestCompletion(uri.into(), position, "invoked".into())
-                        )).expect("Failed to send message");
-
-                        //eprintln!("Sent completion request");
-                        while self.lsp_completion.is_none() {
-                            self.read_lsp_messages();
-                        }
-
-                        let completion_list = match self.lsp_completion.take() {
-                            None => return,
-                            Some(list) => list,
-                        };
-
-                        let buttons = completion_list.generate_buttons(70);
-
-
-                        
-                        let (send, recv) = std::sync::mpsc::channel();
-                        let (send2, recv2) = std::sync::mpsc::channel();
-
-                        self.popup_channels = Some((send2, recv));
-
-                        let prompt = Vec::new();
-
-                        let size = (70, buttons.button_len().expect("Buttons were not buttons") + 2);
-
-                        
-                        let pane = PopUpPane::new_dropdown(
-                            self.settings.clone(),
-                            prompt,
-                            self.sender.clone(),
-                            send,
-                            recv2,
-                            buttons,
-                            false,
-                        );
-
-                        let pane = Rc::new(RefCell::new(pane));
-
-                        let pos = self.cursor.borrow().get_real_cursor();
-                        
-
-                        let max_size = container.get_size();
-
-                        let mut container = PaneContainer::new(max_size, size, pane, self.settings.clone());
-
-
-                        container.set_position(pos);
-                        container.set_size(size);
-
-
-
-                        self.sender.send(Message::CreatePopup(container, true)).expect("Failed to send message");
-                        self.waiting = Waiting::Completion;
-
-                        self.contents.add_new_rope();
+            "delete_word" | "change_word" => {
+                let count = command_args.next()
+                    .and_then(|count| count.parse::<usize>().ok())
+                    .unwrap_or(1)
+                    .max(1);
+                let reg = match command_args.next() {
+                    Some(arg) => RegisterType::from_name(arg),
+                    None => RegisterType::None,
+                };
 
-                        self.lsp_completion = Some(completion_list);
-                    },
-                }
-                
-            },
-            "insert" => {
-                if let Some(index) = command_args.next() {
-                    //eprintln!("Inserting {}", index);
-
-                    if let Some(index) = index.parse::<usize>().ok() {
-                        match &self.lsp_client {
-                            None => {},
-                            Some((_, _)) => {
-                                match self.lsp_completion {
-                                    None => {},
-                                    Some(ref mut list) => {
-                                        //eprintln!("Inserting {}", index);
-                                        if let Some(completion) = list.get_completion(index) {
-                                            //eprintln!("Inserting {}", index);
-                                            if let Some(text_edit) = completion.get_edit_text() {
-                                                //eprintln!("Inserting {}", index);
-                                                match text_edit {
-                                                    TextEditType::TextEdit(text_edit) => {
-                                                        let (pos, _) = text_edit.get_range();
-
-                                                        self.insert_str_at(pos, &text_edit.newText);
-                                                        //eprintln!("Inserting {} at {:?}", &text_edit.newText, pos);
-                                                    },
-                                                    TextEditType::InsertReplaceEdit(text_edit) => {
-                                                        unimplemented!();
-                                                    },
-                                                }
-                                            }
-                                        }
-                                    },
-                                }
-                                
-                                self.lsp_completion = None;
-                            },
-                        }
+                let (x, y) = self.cursor.borrow().get_cursor();
+                let line = match self.contents.get_row(y, 0, usize::MAX) {
+                    None => return,
+                    Some(line) => line.to_string(),
+                };
 
+                let mut end = x;
+                for i in 0..count {
+                    // `cw` leaves the blanks after the last word alone
+                    end = if command == "change_word" && i + 1 == count {
+                        word::word_end(&line, end)
                     }
+                    else {
+                        word::next_word_start(&line, end)
+                    };
                 }
 
-                self.lsp_completion = None;
+                self.delete_columns(y, (x, end), reg);
             },
-            "goto_declaration" | "goto_definition" |
-            "goto_type_definition" | "goto_implementation" => {
-                match &self.lsp_client {
-                    None => {},
-                    Some((sender, _)) => {
-                        let uri = self.generate_uri();
-
-                        let position = self.cursor.borrow().get_cursor();
-
-                        let request = match command {
-                            "goto_declaration" => LspRequest::GotoDeclaration(uri.clone().into(), position),
-                            "goto_definition" => LspRequest::GotoDefinition(uri.clone().into(), position),
-                            "goto_type_definition" => LspRequest::GotoTypeDefinition(uri.clone().into(), position),
-                            "goto_implementation" => LspRequest::GotoImplementation(uri.clone().into(), position),
-                            _ => unreachable!(),
-                        };
-
-
-                        sender.send(ControllerMessage::Request(
-                             self.lang.clone().into(),
-                            request
-                        )).expect("Failed to send message");
-
-                        while self.lsp_location.is_none() {
-                            self.read_lsp_messages();
-                        }
-
-                        let lsp_location = self.lsp_location.take().expect("LSP location was none");
-
-                        match lsp_location {
-                            LocationResponse::Location(location) => {
-                                //eprintln!("Got location {:?}", location);
-                                
-                                if location.uri.as_str() == uri.as_str() {
-                                    //eprintln!("Jumping to {:?}", location.range);
-                                    self.jump_table.add(*self.cursor.borrow());
-
-                                    let ((x, y), _) = location.range.get_positions();
-
-                                    let mut cursor = self.cursor.borrow_mut();
-
-                                    cursor.set_cursor(CursorMove::Amount(x), CursorMove::Amount(y), self, (0,0));
-                                }
-                                else {
-
-                                    let file_name = Self::get_file_path(&location.uri);
-                                    let (pos, _) = location.range.get_positions();
-
-                                    let message = Message::OpenFile(file_name, Some(pos));
-
-                                    self.sender.send(message).expect("Failed to send message");
-
-                                    self.contents.add_new_rope();
-                                }
-                            },
-                            LocationResponse::Locations(locations) => {
-
-                                if locations.len() == 1 {
-                                    
-                                    if locations[0].uri.as_str() == uri.as_str() {
-                                        //eprintln!("Jumping to {:?}", locations[0].range);
-                                        self.jump_table.add(*self.cursor.borrow());
-
-                                        let ((x, y), _) = locations[0].range.get_positions();
-
-                                        let mut cursor = self.cursor.borrow_mut();
-
-                                        cursor.set_cursor(CursorMove::Amount(x), CursorMove::Amount(y), self, (0,0));
-                                    }
-                                    else {
-
-                                        let file_name = Self::get_file_path(&locations[0].uri);
-                                        let (pos, _) = locations[0].range.get_positions();
-
-                                        let message = Message::OpenFile(file_name, Some(pos));
-
-                                        self.sender.send(message).expect("Failed to send message");
-                                        
-                                        self.contents.add_new_rope();
-                                    }
-                                }
-                                else {
-                                    let (send, recv) = std::sync::mpsc::channel();
-                                    let (send2, recv2) = std::sync::mpsc::channel();
-
-                                    self.popup_channels = Some((send2, recv));
-
-                                    let mut buttons = Vec::new();
-                                    
-                                    for location in locations.iter() {
-                                        let pathbuf = PathBuf::from(location.uri.clone());
-                                        let file_name = pathbuf.file_name().expect("Failed to get file name").to_str().expect("Failed to convert to str").to_string();
-                                        let location = location.clone();
-                                        
-                                        let function: Box<dyn Fn(&dyn Promptable) -> String> = Box::new(move |_| {
-                                            format!("{} {},{}", Self::get_file_path(&location.uri), location.range.start.character, location.range.start.line)
-                                        });
-                                        
-                                        buttons.push((file_name, function));
-                                        
-
-                                    }
-
-                                    let buttons = PromptType::Button(buttons, 0);
-                                    let prompt = vec!["Locations".to_string()];
-
-                                    let pane = PopUpPane::new_dropdown(
-                                        self.settings.clone(),
-                                        prompt,
-                                        self.sender.clone(),
-                                        send,
-                                        recv2,
-                                        buttons,
-                                        true
-                                    );
-
-                                    let pane = Rc::new(RefCell::new(pane));
-
-                                    let (_, (x2, y2)) = container.get_corners();
-                                    let (x, y) = container.get_size();
-
-                                    let (x, y) = (x / 2, y / 2);
-
-                                    let pos = (x2 - 20 - x, y2 - (locations.len() + 3) - y);
-
-
-                                    let max_size = container.get_size();
-                                    
-                                    let mut container = PaneContainer::new(max_size, (20, locations.len() + 3), pane, self.settings.clone());
-
-
-                                    container.set_position(pos);
-                                    container.set_size((20, locations.len() + 3));
-
-
-
-                                    self.sender.send(Message::CreatePopup(container, true)).expect("Failed to send message");
-                                    self.waiting = Waiting::Goto;
-
-                                    self.contents.add_new_rope();
-                                    
-                                }
-
-                            },
-                            LocationResponse::LocationLink(location_link) => {
-                                eprintln!("Got location link {:?}", location_link);
-
-                            },
-                            LocationResponse::Null => {},
-                            
-                            
-                        }
-                        
+            "delete_inner_word" => {
+                let reg = match command_args.next() {
+                    Some(arg) => RegisterType::from_name(arg),
+                    None => RegisterType::None,
+                };
 
-                    }
+                let (x, y) = self.cursor.borrow().get_cursor();
+                if let Some(range) = self.inner_word_at((x, y)) {
+                    self.delete_columns(y, range, reg);
                 }
+            },
+            "delete_line" => {
+                let count = command_args.next()
+                    .and_then(|count| count.parse::<usize>().ok())
+                    .unwrap_or(1)
+                    .max(1);
+                let reg = match command_args.next() {
+                    Some(arg) => RegisterType::from_name(arg),
+                    None => RegisterType::None,
+                };
+
+                let (_, y) = self.cursor.borrow().get_cursor();
+                let range = match line_delete_range(&self.contents.to_string(), y, y + count - 1) {
+                    None => return,
+                    Some(range) => range,
+                };
 
+                self.copy_lines(count, reg);
+                self.replace_bytes(range, "");
 
+                let cursor = self.cursor.clone();
+                cursor.borrow_mut().jump_to(0, y, self);
+                self.move_to_first_non_blank(false);
             },
-            "goto" => {
-                if let Some(path) = command_args.next() {
-                    let pos = if let Some(pos) = command_args.next() {
-                        let split = pos.split(",").collect::<Vec<_>>();
-                        let x = split[0].parse::<usize>().expect("Failed to parse x");
-                        let y = split[1].parse::<usize>().expect("Failed to parse y");
-                        Some((x, y))
-                    }
-                    else {
-                        None
-                    };
+            "delete_selection" => {
+                let reg = match command_args.next() {
+                    Some(arg) => RegisterType::from_name(arg),
+                    None => RegisterType::None,
+                };
 
-                    let message = Message::OpenFile(path.to_string(), pos);
+                let ranges = match self.copy_selection(reg) {
+                    None => return,
+                    Some(ranges) => ranges,
+                };
 
-                    self.sender.send(message).expect("Failed to send message");
-                    
+                let (start_byte, old_end_byte) = match (ranges.first(), ranges.last()) {
+                    (Some(first), Some(last)) => (first.start, last.end),
+                    _ => return,
+                };
+                let deleted = ranges.iter().map(|range| range.len()).sum::<usize>();
+                let (start_x, start_y) = self.contents.position_of_byte(start_byte);
+                let (old_end_x, old_end_y) = self.contents.position_of_byte(old_end_byte);
+
+                self.set_changed(true);
+                // The server deletes from the bottom up so the ranges above stay where they were
+                for range in ranges.iter().rev() {
+                    self.record_change(range.start, range.end, "");
                 }
+                let bounds = ranges.iter()
+                    .map(|range| (Bound::Included(range.start), Bound::Excluded(range.end)))
+                    .collect::<Box<[_]>>();
+                self.contents.delete_chain(bounds);
+
+                // The lines of a block are edited as one span from the first to the last
+                let new_end_byte = old_end_byte - deleted;
+                let (new_end_x, new_end_y) = self.contents.position_of_byte(new_end_byte);
+                let edit = InputEdit {
+                    start_byte,
+                    old_end_byte,
+                    new_end_byte,
+                    start_position: Point::new(start_y, start_x),
+                    old_end_position: Point::new(old_end_y, old_end_x),
+                    new_end_position: Point::new(new_end_y, new_end_x),
+                };
+                self.apply_edit(edit);
+
+                // The start of the selection might be past the end of the file now
+                let (x, y) = self.cursor.borrow().get_cursor();
+                let cursor = self.cursor.clone();
+                cursor.borrow_mut().jump_to(x, y, self);
             },
             "paste" => {
-                if let Some(arg) = command_args.next() {
-                    if let Ok(number) = arg.parse::<usize>() {
-                        let message = Message::Paste(RegisterType::Number(number));
-
-                        self.sender.send(message).expect("Failed to send message");
-                    } else {
-                        let message = Message::Paste(RegisterType::Name(arg.to_string()));
-
-                        self.sender.send(message).expect("Failed to send message");
-                    }
-                } else {
-                    let message = Message::Paste(RegisterType::None);
+                let after = command_args.next() != Some("before");
+                let reg = match command_args.next() {
+                    Some(arg) => RegisterType::from_name(arg),
+                    None => RegisterType::None,
+                };
 
-                    self.sender.send(message).expect("Failed to send message");
-                }
-                
+                let message = Message::Paste(reg, after);
 
+                self.sender.send(message).expect("Failed to send message");
             },
             "copy" => {
                 eprintln!("Copy");
                 if let Some(way) = command_args.next() {
 
+                    // Copying lines takes a count before the register
+                    let count = match way {
+                        "line" => command_args.next().and_then(|count| count.parse::<usize>().ok()).unwrap_or(1).max(1),
+                        _ => 1,
+                    };
+
                     let reg = if let Some(arg) = command_args.next() {
-                        let reg = if let Ok(number) = arg.parse::<usize>() {
-                            RegisterType::Number(number)
-                        } else {
-                            RegisterType::Name(arg.to_string())
-                        };
-                        reg
+                        RegisterType::from_name(arg)
                     } else {
                         RegisterType::None
                     };
@@ -1938,20 +4153,10 @@ impl Pane for TreesitterPane {
 
                     match way {
                         "line" => {
-                            let (_, y) = self.cursor.borrow().get_cursor();
-                            let (width, _) = container.get_size();
-                            
-                            let row = self.contents.get_row(y, 0, width);
-
-                            let row = match row {
-                                Some(row) => row.to_string(),
-                                None => String::new(),
-                            };
-
-                            let message = Message::Copy(reg, row);
-
-                            self.sender.send(message).expect("Failed to send message");
-                                    
+                            self.copy_lines(count, reg);
+                        },
+                        "selection" => {
+                            self.copy_selection(reg);
                         },
                         _ => {},
 
@@ -1971,20 +4176,78 @@ impl Pane for TreesitterPane {
 
     fn change_mode(&mut self, name: &str) {
         if let Some(mode) = self.get_mode(name) {
+            let was_block = self.selecting_block();
+            let visual = matches!(name, "Visual" | "Visual Line" | "Visual Block");
+            if let (Some(anchor), false) = (self.selection_anchor, visual) {
+                self.last_selection = Some((anchor, self.cursor.borrow().get_cursor(), self.selection_kind));
+            }
+            self.selection_anchor = match name {
+                // Switching between the visual modes keeps the selection where it started
+                "Visual" | "Visual Line" | "Visual Block" => {
+                    self.selection_anchor.or(Some(self.cursor.borrow().get_cursor()))
+                },
+                _ => None,
+            };
+            self.selection_kind = match name {
+                "Visual Line" => VisualKind::Lines,
+                "Visual Block" => VisualKind::Block,
+                _ => VisualKind::Chars,
+            };
+            // The cursor goes back on the text when it was only past the end of a line for the block
+            if was_block && !self.settings.borrow().editor_settings.virtualedit.allows(self.selecting_block()) {
+                let (x, y) = self.cursor.borrow().get_cursor();
+                let cursor = self.cursor.clone();
+                cursor.borrow_mut().jump_to(x, y, self);
+            }
+            // Everything typed in one insert session is undone together
+            if name == "Insert" {
+                self.contents.begin_group();
+            }
+            else {
+                self.contents.end_group();
+                self.snippet = None;
+                self.close_auto_completion();
+            }
             self.mode = mode;
         }
     }
 
-    fn insert_newline(&mut self) {
-        self.insert_char('\n');
-        let mut cursor = self.cursor.borrow_mut();
+    fn insert_newline(&mut self) {
+        let (x, y) = self.cursor.borrow().get_cursor();
+        let line = self.cursor_line(y);
+        let before = line.chars().take(x).collect::<String>();
+        let after = line.chars().skip(x).collect::<String>();
+
+        let opener = self.block_opener(&before, y);
+        let (use_spaces, tab_size) = {
+            let settings = self.settings.borrow();
+            (settings.editor_settings.use_spaces, settings.editor_settings.tab_size)
+        };
+        let (text, column) = indent::split_line(&before, &after, opener, use_spaces, tab_size);
+
+        self.insert_str(&text);
+
+        let cursor = self.cursor.clone();
+        cursor.borrow_mut().jump_to(column, y + 1, self);
+    }
+
+    fn set_indent(&mut self, row: usize, indent: &str) {
+        let line = self.cursor_line(row);
+        let old = indent::leading_whitespace(&line).chars().count();
+        self.replace_text_at((0, row), (old, row), indent);
 
-        cursor.move_cursor(Direction::Down, 1, self);
-        cursor.set_cursor(CursorMove::ToStart, CursorMove::Nothing, self, (0,0));
+        let (x, y) = self.cursor.borrow().get_cursor();
+        if y == row {
+            let x = (x + indent.chars().count()).saturating_sub(old);
+            let cursor = self.cursor.clone();
+            cursor.borrow_mut().jump_to(x, y, self);
+        }
     }
 
     fn insert_char(&mut self, c: char) {
+        self.replace_placeholder();
         self.set_changed(true);
+        self.typed = Some(c);
 
         let start_byte;
         //let old_end_byte = self.contents.get_byte_count();
@@ -1993,6 +4256,7 @@ impl Pane for TreesitterPane {
         let byte_pos = self.get_byte_offset();
         let c = c.to_string();
         if self.contents.get_char_count() == 0 {
+            self.record_change(0, 0, &c);
             self.contents.insert_current(0, c);
             new_end_byte = self.contents.get_byte_count();
             start_byte = 0;
@@ -2003,6 +4267,7 @@ impl Pane for TreesitterPane {
                 Some(byte_pos) => byte_pos,
             };
 
+            self.record_change(byte_pos, byte_pos, &c);
             self.contents.insert_current(byte_pos, c);
 
             new_end_byte = self.contents.get_byte_count();
@@ -2021,31 +4286,28 @@ impl Pane for TreesitterPane {
             new_end_position: Point::new(y, new_char_len),
         };
 
-        self.tree.edit(&edit);
-        self.tree = self.parser.parse(&self.contents.to_string(), Some(&self.tree)).unwrap();
+        self.apply_edit(edit);
+    }
 
-        self.file_version += 1;
+    fn paste(&mut self, value: &RegisterValue, after: bool) {
+        match value.kind {
+            RegisterKind::CharWise => self.insert_str(&value.text),
+            RegisterKind::LineWise => {
+                let (_, y) = self.cursor.borrow().get_cursor();
+                let (byte, lines) = line_paste(&self.contents.to_string(), y, after, &value.text);
 
-        match &self.lsp_client {
-            None => {},
-            Some((sender, _)) => {
-                let message = ControllerMessage::Notification(
-                    self.lang.clone().into(),
-                    LspNotification::ChangeText(
-                        self.generate_uri().into(),
-                        self.file_version,
-                        self.contents.to_string().into(),
-                    )
-                );
+                self.replace_bytes(byte..byte, &lines);
 
-                sender.send(message).expect("Failed to send message");
-                        
+                let y = if after { y + 1 } else { y };
+                let cursor = self.cursor.clone();
+                cursor.borrow_mut().jump_to(0, y, self);
+                self.move_to_first_non_blank(false);
             },
         }
-
     }
 
     fn insert_str(&mut self, s: &str) {
+        self.replace_placeholder();
         self.set_changed(true);
 
         let start_byte;
@@ -2053,6 +4315,7 @@ impl Pane for TreesitterPane {
         
         let byte_pos = self.get_byte_offset();
         if self.contents.get_char_count() == 0 {
+            self.record_change(0, 0, s);
             self.contents.insert(0, s);
             start_byte = 0;
             new_end_byte = self.contents.get_byte_count();
@@ -2062,6 +4325,7 @@ impl Pane for TreesitterPane {
                 None => self.contents.get_byte_count(),
                 Some(byte_pos) => byte_pos,
             };
+            self.record_change(byte_pos, byte_pos, s);
             self.contents.insert(byte_pos, s);
             start_byte = byte_pos;
             new_end_byte = self.contents.get_byte_count();
@@ -2083,28 +4347,7 @@ impl Pane for TreesitterPane {
             new_end_position: Point::new(y, new_char_len),
         };
 
-        self.tree.edit(&edit);
-        self.tree = self.parser.parse(&self.contents.to_string(), Some(&self.tree)).unwrap();
-
-        self.file_version += 1;
-        
-        match &self.lsp_client {
-            None => {},
-            Some((sender, _)) => {
-                let message = ControllerMessage::Notification(
-                    self.lang.clone().into(),
-                    LspNotification::ChangeText(
-                        self.generate_uri().into(),
-                        self.file_version,
-                        self.contents.to_string().into(),
-                    )
-                );
-
-                sender.send(message).expect("Failed to send message");
-                        
-            },
-        }
-        
+        self.apply_edit(edit);
     }
 
     ///TODO: add check to make sure we have a valid byte range
@@ -2122,6 +4365,7 @@ impl Pane for TreesitterPane {
 
         let old_end_byte = self.contents.get_byte_count();
 
+        self.record_change(byte_pos, byte_pos.saturating_add(1), "");
         self.contents.delete(byte_pos..byte_pos.saturating_add(1));
 
         let new_end_byte = self.contents.get_byte_count();
@@ -2133,33 +4377,11 @@ impl Pane for TreesitterPane {
             old_end_byte,
             new_end_byte,
             start_position: Point::new(y, x),
-            old_end_position: Point::new(y, x - 1),
+            old_end_position: Point::new(y, x.saturating_sub(1)),
             new_end_position: Point::new(y, x),
         };
 
-        self.tree.edit(&edit);
-
-        self.tree = self.parser.parse(&self.contents.to_string(), Some(&self.tree)).unwrap();
-
-        self.file_version += 1;
-        
-        match &self.lsp_client {
-            None => {},
-            Some((sender, _)) => {
-                let message = ControllerMessage::Notification(
-                    self.lang.clone().into(),
-                    LspNotification::ChangeText(
-                        self.generate_uri().into(),
-                        self.file_version,
-                        self.contents.to_string().into(),
-                    )
-                );
-
-                sender.send(message).expect("Failed to send message");
-                        
-            },
-        }
-
+        self.apply_edit(edit);
     }
 
     ///TODO: add check to make sure we have a valid byte range
@@ -2192,11 +4414,13 @@ impl Pane for TreesitterPane {
         else {
             cursor.move_cursor(Direction::Left, 1, self);
         }
+        drop(cursor);
 
         let start_byte = byte_pos.saturating_sub(1);
         let old_end_byte = self.contents.get_byte_count();
         
 
+        self.record_change(byte_pos.saturating_sub(1), byte_pos, "");
         self.contents.delete(byte_pos.saturating_sub(1)..byte_pos);
 
         let new_end_byte = self.contents.get_byte_count();
@@ -2210,29 +4434,7 @@ impl Pane for TreesitterPane {
             new_end_position: Point::new(y, x),
         };
 
-        self.tree.edit(&edit);
-
-        self.tree = self.parser.parse(&self.contents.to_string(), Some(&self.tree)).unwrap();
-
-        self.file_version += 1;
-        
-        match &self.lsp_client {
-            None => {},
-            Some((sender, _)) => {
-                let message = ControllerMessage::Notification(
-                    self.lang.clone().into(),
-                    LspNotification::ChangeText(
-                        self.generate_uri().into(),
-                        self.file_version,
-                        self.contents.to_string().into(),
-                    )
-                );
-
-                sender.send(message).expect("Failed to send message");
-                        
-            },
-        }
-
+        self.apply_edit(edit);
     }
 
     fn get_cursor(&self) -> Rc<RefCell<Cursor>> {
@@ -2257,6 +4459,43 @@ impl Pane for TreesitterPane {
         &self.file_name
     }
 
+    fn is_modified(&self) -> bool {
+        self.changed
+    }
+
+    fn settings_changed(&mut self) {
+        reload_keybindings(&self.modes, &self.settings.borrow());
+        self.load_highlight_query();
+    }
+
+    fn show_message(&self, message: String, kind: MessageKind) {
+        self.sender.send(Message::SetMessage(message, kind)).expect("Failed to send message");
+    }
+
+    fn last_edit(&self) -> Option<Instant> {
+        self.edit_timer.edited()
+    }
+
+    fn is_inserting(&self) -> bool {
+        self.mode.borrow().get_name() == "Insert"
+    }
+
+    fn selecting_block(&self) -> bool {
+        self.selection_anchor.is_some() && self.selection_kind == VisualKind::Block
+    }
+
+    fn auto_save(&mut self, container: &PaneContainer) -> bool {
+        self.edit_timer.saved();
+        self.run_command("w", container);
+        !self.changed
+    }
+
+    fn preview_command(&mut self, command: Option<&str>) {
+        let enabled = self.settings.borrow().editor_settings.inccommand;
+        self.substitute_preview = command.filter(|_| enabled).and_then(Substitute::parse);
+        self.changed();
+    }
+
     fn resize_cursor(&mut self, size: (usize, usize)) {
         let mut cursor = self.cursor.borrow_mut();
         cursor.resize(size);
@@ -2267,10 +4506,62 @@ impl Pane for TreesitterPane {
         cursor.set_size(size);
     }
 
-    fn backup_buffer(&mut self) {
-        self.contents.add_new_rope();
+    fn get_language(&self) -> Option<&str> {
+        Some(&self.lang)
+    }
+
+    fn is_waiting(&self) -> bool {
+        self.lsp_request_time.is_some()
+            || self.changed_since.is_some()
+            || self.background_parser.as_ref().is_some_and(BackgroundParser::is_parsing)
+    }
+
+    fn in_snippet(&self) -> bool {
+        self.snippet.is_some()
+    }
+
+    fn lsp_restarted(&mut self, lsp: (Sender<ControllerMessage>, SharedReceiver)) {
+        // The old server is gone so its answer isn't coming
+        if self.lsp_request_time.take().is_some() {
+            self.waiting = Waiting::None;
+        }
+        self.attach_lsp_client(lsp);
+    }
+
+    fn set_lsp_info(&mut self, info: SharedInfo) {
+        self.lsp_info = info;
+    }
+
+    fn command_names(&self) -> Vec<&'static str> {
+        TEXT_COMMANDS.iter().chain(LANGUAGE_COMMANDS.iter()).copied().collect()
+    }
+
+    fn complete_words(&mut self, others: Vec<String>, container: &PaneContainer) {
+        let (x, y) = self.cursor.borrow().get_cursor();
+        let prefix = word_prefix(&self.cursor_line(y), x);
+        let cursor = self.get_byte_offset().unwrap_or(self.contents.get_byte_count());
+        let words = buffer_words(&prefix, &self.contents.to_string(), cursor, &others);
+        if words.is_empty() {
+            self.sender.send(Message::SetMessage("No words to complete".to_string(), MessageKind::Info))
+                .expect("Failed to send message");
+            return;
+        }
+
+        if let Some((send, _)) = self.popup_channels.take() {
+            let _ = send.send(PaneMessage::Close);
+        }
+        self.auto_completion = false;
+        self.lsp_completion = Some(CompletionList::from_words(words));
+        self.show_completions(container);
+    }
+
+    fn set_location(&mut self, (x, y): (usize, usize)) {
+        self.jump_table.add(*self.cursor.borrow());
+        let cursor = self.cursor.clone();
+        cursor.borrow_mut().jump_to(x, y, self);
     }
 
+
     fn get_settings(&self) -> Rc<RefCell<Settings>> {
         self.settings.clone()
     }
@@ -2286,3 +4577,935 @@ impl Pane for TreesitterPane {
         self.sender = sender;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{mpsc::{channel, Receiver}, Arc, Mutex};
+
+    use crate::lsp::LspInfo;
+
+    use super::*;
+
+    /// A rust pane with its container, the messages it sends and its settings.
+    type RustPane = (Rc<RefCell<TreesitterPane>>, PaneContainer, Receiver<Message>, Rc<RefCell<Settings>>);
+
+    /// A rust pane holding `text`.
+    fn rust_pane(text: &str) -> RustPane {
+        let settings = Rc::new(RefCell::new(Settings::default()));
+        let (sender, receiver) = channel();
+        let pane = Rc::new(RefCell::new(TreesitterPane::new(settings.clone(), sender, tree_sitter_rust::language(), "rust", None)));
+        let container = PaneContainer::new((80, 24), (80, 24), pane.clone(), settings.clone());
+        if !text.is_empty() {
+            pane.borrow_mut().insert_str(text);
+        }
+        (pane, container, receiver, settings)
+    }
+
+    /// The opens and changes the pane sent with their versions and text.
+    fn sent_versions(receiver: &Receiver<ControllerMessage>) -> Vec<(&'static str, usize, String)> {
+        receiver.try_iter().filter_map(|message| match message {
+            ControllerMessage::Notification(_, LspNotification::Open(_, version, text)) => {
+                Some(("open", version, text.to_string()))
+            },
+            ControllerMessage::Notification(_, LspNotification::ChangeText(_, version, _, text)) => {
+                Some(("change", version, text.to_string()))
+            },
+            _ => None,
+        }).collect()
+    }
+
+    #[test]
+    fn test_attach_after_edit() {
+        let settings = Rc::new(RefCell::new(Settings::default()));
+        let (sender, _receiver) = channel();
+        let mut pane = TreesitterPane::new(settings, sender, tree_sitter_rust::language(), "rust", None);
+
+        pane.insert_str("fn main() {}\n");
+        pane.insert_str("// edited\n");
+        let text = pane.buffer_to_string();
+
+        let (lsp_sender, lsp_receiver) = channel();
+        let (_, responses) = channel();
+        pane.attach_lsp_client((lsp_sender, Arc::new(Mutex::new(responses))));
+
+        pane.insert_str("// again\n");
+        pane.flush_changes();
+
+        let sent = sent_versions(&lsp_receiver);
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent[0], ("open", 0, text));
+        assert_eq!(sent[1].0, "change");
+        assert!(sent[1].1 > sent[0].1);
+        assert_eq!(sent[1].2, pane.buffer_to_string());
+    }
+
+    #[test]
+    fn test_changes_are_batched() {
+        let (pane, mut container, _receiver, settings) = rust_pane("fn main() {}\n");
+        let mut pane = pane.borrow_mut();
+
+        let (lsp_sender, lsp_receiver) = channel();
+        let (_, responses) = channel();
+        pane.attach_lsp_client((lsp_sender, Arc::new(Mutex::new(responses))));
+        lsp_receiver.try_iter().for_each(drop);
+        settings.borrow_mut().editor_settings.lsp_change_delay = 60_000;
+
+        let cursor = pane.get_cursor();
+        cursor.borrow_mut().jump_to(0, 0, &*pane);
+        pane.insert_char('x');
+        cursor.borrow_mut().jump_to(3, 0, &*pane);
+        pane.delete_char();
+        pane.refresh(&mut container);
+        assert!(lsp_receiver.try_recv().is_err());
+        assert!(pane.is_waiting());
+
+        settings.borrow_mut().editor_settings.lsp_change_delay = 0;
+        pane.refresh(&mut container);
+        assert!(!pane.is_waiting());
+
+        let sent = lsp_receiver.try_iter().filter_map(|message| match message {
+            ControllerMessage::Notification(_, LspNotification::ChangeText(_, _, changes, text)) => Some((changes, text.to_string())),
+            _ => None,
+        }).collect::<Vec<_>>();
+        assert_eq!(sent, vec![(
+            vec![
+                TextChange { range: Some(((0, 0), (0, 0))), text: "x".to_string() },
+                TextChange { range: Some(((3, 0), (4, 0))), text: String::new() },
+            ],
+            "xfnmain() {}\n".to_string(),
+        )]);
+    }
+
+    #[test]
+    fn test_apply_edit_from_server() {
+        let (pane, mut container, receiver, _) = rust_pane("let foo = 1;\n");
+        let mut pane = pane.borrow_mut();
+
+        let (lsp_sender, lsp_receiver) = channel();
+        let (responses, lsp_responses) = channel();
+        pane.attach_lsp_client((lsp_sender, Arc::new(Mutex::new(lsp_responses))));
+
+        let edit = |uri: String| WorkspaceEdit::from_value(&serde_json::json!({
+            "changes": {
+                (uri): [{
+                    "range": { "start": { "line": 0, "character": 4 }, "end": { "line": 0, "character": 7 } },
+                    "newText": "bar",
+                }],
+            },
+        }));
+
+        // The server isn't answering anything the pane asked, but the edit is still made and answered
+        responses.send(ControllerMessage::Response(LspResponse::ApplyEdit(edit(pane.generate_uri()), serde_json::json!(4)))).unwrap();
+        pane.refresh(&mut container);
+        assert_eq!(pane.buffer_to_string(), "let bar = 1;\n");
+        let answers = lsp_receiver.try_iter().filter_map(|message| match message {
+            ControllerMessage::Notification(_, LspNotification::EditApplied(id, applied)) => Some((id, applied)),
+            _ => None,
+        }).collect::<Vec<_>>();
+        assert_eq!(answers, vec![(serde_json::json!(4), true)]);
+
+        // Edits to other files go to the window, which answers once they are made
+        responses.send(ControllerMessage::Response(LspResponse::ApplyEdit(edit("file:///tmp/other.rs".to_string()), serde_json::json!(5)))).unwrap();
+        pane.refresh(&mut container);
+        let forwarded = receiver.try_iter().find_map(|message| match message {
+            Message::ApplyEdits(changes, reply) => Some((changes.len(), reply)),
+            _ => None,
+        });
+        assert_eq!(forwarded, Some((1, Some(("rust".to_string(), serde_json::json!(5))))));
+    }
+
+    #[test]
+    fn test_snippet_completion() {
+        let (pane, mut container, _receiver, _) = rust_pane("");
+        let mut pane = pane.borrow_mut();
+
+        pane.change_mode("Insert");
+        pane.insert_str("let x = ma;\nlet y = 1;\n");
+        let (lsp_sender, _lsp_receiver) = channel();
+        let (_responses, lsp_responses) = channel();
+        pane.attach_lsp_client((lsp_sender, Arc::new(Mutex::new(lsp_responses))));
+        pane.set_location((10, 0));
+
+        pane.lsp_completion = serde_json::from_value(serde_json::json!({
+            "isIncomplete": false,
+            "items": [{
+                "label": "max",
+                "kind": 3,
+                "insertText": "max(${1:a}, ${2:a})$0",
+                "insertTextFormat": 2,
+            }],
+        })).ok();
+        pane.run_command("insert 0", &container);
+        assert_eq!(pane.buffer_to_string(), "let x = max(a, a);\nlet y = 1;\n");
+        assert_eq!(pane.cursor.borrow().get_cursor(), (12, 0));
+        assert!(pane.in_snippet());
+
+        // Typing replaces the placeholder
+        pane.insert_char('v');
+        pane.set_location((13, 0));
+        pane.insert_char('1');
+        pane.refresh(&mut container);
+        assert_eq!(pane.buffer_to_string(), "let x = max(v1, a);\nlet y = 1;\n");
+
+        pane.run_command("snippet_next", &container);
+        assert_eq!(pane.cursor.borrow().get_cursor(), (16, 0));
+        pane.run_command("snippet_previous", &container);
+        assert_eq!(pane.cursor.borrow().get_cursor(), (12, 0));
+
+        // Going past the last tabstop ends the snippet where it says to
+        pane.run_command("snippet_next", &container);
+        pane.run_command("snippet_next", &container);
+        assert_eq!(pane.cursor.borrow().get_cursor(), (18, 0));
+        assert!(!pane.in_snippet());
+    }
+
+    #[test]
+    fn test_mirrored_tabstops() {
+        let (pane, mut container, _receiver, _) = rust_pane("");
+        let mut pane = pane.borrow_mut();
+
+        pane.change_mode("Insert");
+        pane.insert_str("\n");
+        let (lsp_sender, _lsp_receiver) = channel();
+        let (_responses, lsp_responses) = channel();
+        pane.attach_lsp_client((lsp_sender, Arc::new(Mutex::new(lsp_responses))));
+
+        pane.lsp_completion = serde_json::from_value(serde_json::json!({
+            "isIncomplete": false,
+            "items": [{
+                "label": "for",
+                "kind": 15,
+                "insertText": "for ${1:i} in $2 { $1 }",
+                "insertTextFormat": 2,
+            }],
+        })).ok();
+        pane.run_command("insert 0", &container);
+        assert_eq!(pane.buffer_to_string(), "for i in  { i }\n");
+
+        pane.insert_char('n');
+        pane.refresh(&mut container);
+        assert_eq!(pane.buffer_to_string(), "for n in  { n }\n");
+
+        // Leaving Insert mode is the end of the snippet
+        pane.change_mode("Normal");
+        assert!(!pane.in_snippet());
+    }
+
+    #[test]
+    fn test_word_completion() {
+        let (pane, container, receiver, _) = rust_pane("");
+        let mut pane = pane.borrow_mut();
+
+        pane.change_mode("Insert");
+        pane.insert_str("let total = 1;\nlet x = to\n");
+        pane.set_location((10, 1));
+
+        // Without a server the window is asked for the other buffers
+        pane.run_command("completion", &container);
+        assert!(receiver.try_iter().any(|message| matches!(message, Message::CompleteWords)));
+
+        pane.complete_words(vec!["tomato".to_string()], &container);
+        assert!(receiver.try_iter().any(|message| matches!(message, Message::CreatePopup(_, true))));
+        let labels = pane.lsp_completion.as_ref().unwrap().items.iter().map(|item| item.label.clone()).collect::<Vec<_>>();
+        assert_eq!(labels, vec!["total", "tomato"]);
+
+        pane.run_command("insert 1", &container);
+        assert_eq!(pane.buffer_to_string(), "let total = 1;\nlet x = tomato\n");
+        assert_eq!(pane.cursor.borrow().get_cursor(), (14, 1));
+    }
+
+    #[test]
+    fn test_auto_completion() {
+        let (pane, mut container, receiver, settings) = rust_pane("");
+        let mut pane = pane.borrow_mut();
+
+        pane.change_mode("Insert");
+        pane.insert_str("point\n");
+        let (lsp_sender, lsp_receiver) = channel();
+        let (responses, lsp_responses) = channel();
+        pane.attach_lsp_client((lsp_sender, Arc::new(Mutex::new(lsp_responses))));
+        pane.set_lsp_info(Arc::new(Mutex::new(LspInfo { trigger_characters: vec![".".to_string()] })));
+
+        let triggers = |receiver: &Receiver<ControllerMessage>| receiver.try_iter().filter_map(|message| match message {
+            ControllerMessage::Request(_, LspRequest::RequestCompletion(_, position, trigger)) => Some((position, trigger.to_string())),
+            _ => None,
+        }).collect::<Vec<_>>();
+
+        // The mode moves the cursor past the character before the refresh
+        pane.set_location((5, 0));
+        pane.insert_char('.');
+        pane.set_location((6, 0));
+        pane.refresh(&mut container);
+        assert_eq!(triggers(&lsp_receiver), vec![((6, 0), "triggerCharacter".to_string())]);
+
+        // The list opens without taking the focus from the text
+        responses.send(ControllerMessage::Response(LspResponse::Completion(serde_json::from_value(serde_json::json!({
+            "isIncomplete": false,
+            "items": [{ "label": "x" }],
+        })).unwrap()))).unwrap();
+        pane.refresh(&mut container);
+        let popups = receiver.try_iter().filter_map(|message| match message {
+            Message::CreatePopup(_, make_active) => Some(make_active),
+            _ => None,
+        }).collect::<Vec<_>>();
+        assert_eq!(popups, vec![false]);
+        assert!(!pane.is_waiting());
+
+        // Short identifiers don't ask
+        pane.insert_char('x');
+        pane.set_location((7, 0));
+        pane.refresh(&mut container);
+        assert!(triggers(&lsp_receiver).is_empty());
+        assert!(pane.lsp_completion.is_none());
+
+        settings.borrow_mut().editor_settings.auto_completion = false;
+        pane.insert_char('.');
+        pane.set_location((8, 0));
+        pane.refresh(&mut container);
+        assert!(triggers(&lsp_receiver).is_empty());
+    }
+
+    #[test]
+    fn test_lsp_request_does_not_block() {
+        let (pane, mut container, receiver, settings) = rust_pane("fn main() {}\n");
+        let mut pane = pane.borrow_mut();
+
+        let (lsp_sender, _lsp_receiver) = channel();
+        let (responses, lsp_responses) = channel();
+        pane.attach_lsp_client((lsp_sender, Arc::new(Mutex::new(lsp_responses))));
+
+        let messages = |receiver: &Receiver<Message>| receiver.try_iter().filter_map(|message| match message {
+            Message::SetMessage(text, _) => Some(text),
+            _ => None,
+        }).collect::<Vec<_>>();
+
+        pane.run_command("find_references", &container);
+        assert!(pane.is_waiting());
+
+        // Keys still work while the answer is on its way
+        pane.run_command("move down 1", &container);
+        pane.refresh(&mut container);
+        assert!(pane.is_waiting());
+
+        responses.send(ControllerMessage::Response(LspResponse::Location(LocationResponse::Null))).unwrap();
+        pane.refresh(&mut container);
+        assert!(!pane.is_waiting());
+        assert_eq!(messages(&receiver), vec!["No references found".to_string()]);
+
+        // A server that never answers is given up on
+        settings.borrow_mut().editor_settings.lsp_timeout = 0;
+        pane.run_command("find_references", &container);
+        pane.refresh(&mut container);
+        assert!(!pane.is_waiting());
+        assert_eq!(messages(&receiver), vec!["The language server didn't answer in time".to_string()]);
+    }
+
+    #[test]
+    fn test_delete_selection_before_anchor() {
+        let (pane, container, receiver, _) = rust_pane("one\ntwo\nthree\n");
+        let mut pane = pane.borrow_mut();
+
+        let cursor = pane.get_cursor();
+        cursor.borrow_mut().jump_to(1, 1, &*pane);
+        pane.change_mode("Visual");
+        // The selection has to work when the cursor goes before the anchor
+        cursor.borrow_mut().jump_to(1, 0, &*pane);
+
+        pane.run_command("delete_selection", &container);
+
+        assert_eq!(pane.buffer_to_string(), "oo\nthree\n");
+        assert_eq!(cursor.borrow().get_cursor(), (1, 0));
+        let copied = receiver.try_iter().find_map(|message| match message {
+            Message::Copy(_, value) => Some(value.text),
+            _ => None,
+        });
+        assert_eq!(copied.as_deref(), Some("ne\ntw"));
+
+        pane.change_mode("Normal");
+        assert!(pane.get_selection().is_none());
+    }
+
+    #[test]
+    fn test_line_wise_delete_and_paste() {
+        let (pane, container, receiver, _) = rust_pane("one\ntwo\nthree\n");
+        let mut pane = pane.borrow_mut();
+
+        let cursor = pane.get_cursor();
+        cursor.borrow_mut().jump_to(2, 0, &*pane);
+        pane.change_mode("Visual Line");
+        cursor.borrow_mut().jump_to(1, 1, &*pane);
+
+        pane.run_command("delete_selection", &container);
+        pane.change_mode("Normal");
+        assert_eq!(pane.buffer_to_string(), "three\n");
+
+        let copied = receiver.try_iter().find_map(|message| match message {
+            Message::Copy(_, value) => Some(value),
+            _ => None,
+        }).unwrap();
+        assert_eq!(copied, RegisterValue::line_wise("one\ntwo\n".to_string()));
+
+        pane.paste(&copied, true);
+        assert_eq!(pane.buffer_to_string(), "three\none\ntwo\n");
+        assert_eq!(cursor.borrow().get_cursor(), (0, 1));
+
+        pane.paste(&copied, false);
+        assert_eq!(pane.buffer_to_string(), "three\none\ntwo\none\ntwo\n");
+    }
+
+    #[test]
+    fn test_reselect_last_selection() {
+        let (pane, container, _receiver, _) = rust_pane("one\ntwo\nthree\n");
+        let mut pane = pane.borrow_mut();
+
+        let cursor = pane.get_cursor();
+        cursor.borrow_mut().jump_to(2, 1, &*pane);
+        pane.change_mode("Visual Line");
+        cursor.borrow_mut().jump_to(1, 0, &*pane);
+        let selection = pane.get_selection();
+        assert!(selection.is_some());
+
+        pane.change_mode("Normal");
+        cursor.borrow_mut().jump_to(0, 2, &*pane);
+        assert!(pane.get_selection().is_none());
+
+        pane.run_command("reselect", &container);
+        assert_eq!(pane.selection_kind, VisualKind::Lines);
+        assert_eq!(pane.get_selection(), selection);
+        assert_eq!(cursor.borrow().get_cursor(), (1, 0));
+    }
+
+    #[test]
+    fn test_block_selection_virtual_columns() {
+        let (pane, container, _receiver, settings) = rust_pane("ab\nabcdef\n");
+        settings.borrow_mut().editor_settings.virtualedit = crate::settings::VirtualEdit::Block;
+        let mut pane = pane.borrow_mut();
+
+        let cursor = pane.get_cursor();
+        cursor.borrow_mut().jump_to(0, 0, &*pane);
+
+        pane.run_command("move right 5", &container);
+        assert_eq!(cursor.borrow().get_cursor(), (2, 0));
+
+        cursor.borrow_mut().jump_to(0, 0, &*pane);
+        pane.change_mode("Visual Block");
+        pane.run_command("move right 4", &container);
+        assert_eq!(cursor.borrow().get_cursor(), (4, 0));
+
+        pane.change_mode("Normal");
+        assert_eq!(cursor.borrow().get_cursor(), (2, 0));
+
+        cursor.borrow_mut().jump_to(0, 0, &*pane);
+        pane.change_mode("Visual Block");
+        pane.run_command("move right 4", &container);
+        pane.run_command("move down 1", &container);
+        assert_eq!(cursor.borrow().get_cursor(), (4, 1));
+
+        pane.run_command("delete_selection", &container);
+        pane.change_mode("Normal");
+        assert_eq!(pane.buffer_to_string(), "\nf\n");
+    }
+
+    #[test]
+    fn test_delete_block_is_one_edit() {
+        let (pane, container, _receiver, _) = rust_pane("let a = 1;\nlet b = 2;\nlet c = 3;\n");
+        let mut pane = pane.borrow_mut();
+        let (lsp_sender, _lsp_receiver) = channel();
+        let (_, responses) = channel();
+        pane.attach_lsp_client((lsp_sender, Arc::new(Mutex::new(responses))));
+
+        let cursor = pane.get_cursor();
+        cursor.borrow_mut().jump_to(4, 0, &*pane);
+        pane.change_mode("Visual Block");
+        cursor.borrow_mut().jump_to(4, 2, &*pane);
+        pane.run_command("delete_selection", &container);
+        pane.change_mode("Normal");
+        let text = "let  = 1;\nlet  = 2;\nlet  = 3;\n";
+        assert_eq!(pane.buffer_to_string(), text);
+
+        // Only the deleted columns go to the server
+        assert_eq!(pane.pending_changes.len(), 3);
+        assert!(pane.pending_changes.iter().all(|change| change.range.is_some() && change.text.is_empty()));
+        let reparsed = pane.parser.parse(text, None).unwrap();
+        assert_eq!(pane.tree.as_ref().unwrap().root_node().to_sexp(), reparsed.root_node().to_sexp());
+
+        pane.run_command("undo", &container);
+        assert_eq!(pane.buffer_to_string(), "let a = 1;\nlet b = 2;\nlet c = 3;\n");
+    }
+
+    #[test]
+    fn test_visual_word_motions() {
+        let (pane, container, _receiver, _) = rust_pane("let foo = bar;\n\n  baz(1);\n");
+        let mut pane = pane.borrow_mut();
+        let cursor = pane.get_cursor();
+        cursor.borrow_mut().jump_to(0, 0, &*pane);
+        pane.change_mode("Visual");
+
+        pane.run_command("word_start_forward 2", &container);
+        assert_eq!(cursor.borrow().get_cursor(), (8, 0));
+        pane.run_command("word_end_forward", &container);
+        assert_eq!(cursor.borrow().get_cursor(), (12, 0));
+        pane.run_command("word_end_forward", &container);
+        assert_eq!(cursor.borrow().get_cursor(), (13, 0));
+        // The empty line in between is skipped
+        pane.run_command("word_start_forward", &container);
+        assert_eq!(cursor.borrow().get_cursor(), (2, 2));
+        pane.run_command("word_start_backward", &container);
+        assert_eq!(cursor.borrow().get_cursor(), (13, 0));
+        pane.run_command("word_start_backward 2", &container);
+        assert_eq!(cursor.borrow().get_cursor(), (8, 0));
+
+        assert_eq!(pane.get_selection(), Some(((0, 0), (8, 0))));
+    }
+
+    #[test]
+    fn test_line_paste_is_incremental() {
+        let (pane, _container, _receiver, _) = rust_pane("fn a() {}\nfn b() {}\n");
+        let mut pane = pane.borrow_mut();
+        let (lsp_sender, _lsp_receiver) = channel();
+        let (_, responses) = channel();
+        pane.attach_lsp_client((lsp_sender, Arc::new(Mutex::new(responses))));
+
+        let cursor = pane.get_cursor();
+        cursor.borrow_mut().jump_to(0, 0, &*pane);
+        pane.paste(&RegisterValue::line_wise("fn c() {}\n".to_string()), true);
+        let text = "fn a() {}\nfn c() {}\nfn b() {}\n";
+        assert_eq!(pane.buffer_to_string(), text);
+
+        // Only the new line goes to the server
+        assert_eq!(pane.pending_changes.len(), 1);
+        assert_eq!(pane.pending_changes[0].range, Some(((0, 1), (0, 1))));
+        assert_eq!(pane.pending_changes[0].text, "fn c() {}\n");
+        let reparsed = pane.parser.parse(text, None).unwrap();
+        assert_eq!(pane.tree.as_ref().unwrap().root_node().to_sexp(), reparsed.root_node().to_sexp());
+    }
+
+    #[test]
+    fn test_delete_line_is_incremental() {
+        let (pane, container, _receiver, _) = rust_pane("fn a() {}\nfn b() {}\nfn c() {}\n");
+        let mut pane = pane.borrow_mut();
+        let (lsp_sender, _lsp_receiver) = channel();
+        let (_, responses) = channel();
+        pane.attach_lsp_client((lsp_sender, Arc::new(Mutex::new(responses))));
+
+        let cursor = pane.get_cursor();
+        cursor.borrow_mut().jump_to(0, 1, &*pane);
+        pane.run_command("delete_line 1", &container);
+        let text = "fn a() {}\nfn c() {}\n";
+        assert_eq!(pane.buffer_to_string(), text);
+
+        // Only the deleted line goes to the server
+        assert_eq!(pane.pending_changes.len(), 1);
+        assert_eq!(pane.pending_changes[0].range, Some(((0, 1), (0, 2))));
+        assert!(pane.pending_changes[0].text.is_empty());
+        let reparsed = pane.parser.parse(text, None).unwrap();
+        assert_eq!(pane.tree.as_ref().unwrap().root_node().to_sexp(), reparsed.root_node().to_sexp());
+    }
+
+    #[test]
+    fn test_delete_lines_with_count() {
+        let (pane, container, _receiver, _) = rust_pane("one\n  two\nthree\nfour");
+        let mut pane = pane.borrow_mut();
+
+        let cursor = pane.get_cursor();
+        cursor.borrow_mut().jump_to(0, 1, &*pane);
+        pane.run_command("delete_line 2", &container);
+
+        assert_eq!(pane.buffer_to_string(), "one\nfour");
+        assert_eq!(cursor.borrow().get_cursor(), (0, 1));
+
+        // The count can't take more lines than there are
+        pane.run_command("delete_line 5", &container);
+        assert_eq!(pane.buffer_to_string(), "one");
+    }
+
+    #[test]
+    fn test_search_wraps() {
+        let (pane, container, receiver, settings) = rust_pane("let a = 1;\nlet b = a;\n");
+        let mut pane = pane.borrow_mut();
+
+        let cursor = pane.get_cursor();
+
+        pane.run_command("search a", &container);
+        assert_eq!(cursor.borrow().get_cursor(), (4, 0));
+        pane.run_command("search_next", &container);
+        assert_eq!(cursor.borrow().get_cursor(), (8, 1));
+
+        pane.run_command("search_next", &container);
+        assert_eq!(cursor.borrow().get_cursor(), (4, 0));
+        let wrapped = receiver.try_iter().any(|message| matches!(message, Message::SetMessage(text, MessageKind::Warning) if text.contains("continuing at TOP")));
+        assert!(wrapped);
+
+        settings.borrow_mut().editor_settings.wrapscan = false;
+        pane.run_command("search_previous", &container);
+        assert_eq!(cursor.borrow().get_cursor(), (4, 0));
+    }
+
+    #[test]
+    fn test_substitute() {
+        let (pane, container, _receiver, _) = rust_pane("let a = 1;\nlet b = a;\n");
+        let mut pane = pane.borrow_mut();
+
+        let cursor = pane.get_cursor();
+
+        pane.run_command("%s/a/x\\/y/g", &container);
+        assert_eq!(pane.contents.to_string(), "let x/y = 1;\nlet b = x/y;\n");
+        assert_eq!(cursor.borrow().get_cursor(), (0, 1));
+
+        pane.run_command("s/x\\/y//", &container);
+        assert_eq!(pane.contents.to_string(), "let x/y = 1;\nlet b = ;\n");
+
+        pane.run_command("%s/ = /\\r/c", &container);
+        assert_eq!(cursor.borrow().get_cursor(), (7, 0));
+        pane.answer_substitute("n", &container);
+        assert_eq!(cursor.borrow().get_cursor(), (5, 1));
+        pane.answer_substitute("y", &container);
+        assert_eq!(pane.contents.to_string(), "let x/y = 1;\nlet b\n;\n");
+        assert_eq!(cursor.borrow().get_cursor(), (0, 2));
+        assert!(pane.substitute_run.is_none());
+    }
+
+    #[test]
+    fn test_regex_substitute() {
+        let (pane, container, receiver, _) = rust_pane("foo(1, 22);\nbar(3, 4);\n");
+        let mut pane = pane.borrow_mut();
+
+
+        pane.run_command("%s/(\\d+), (\\d+)/$2, \\1/", &container);
+        assert_eq!(pane.contents.to_string(), "foo(22, 1);\nbar(4, 3);\n");
+
+        pane.run_command("%s/(/x/", &container);
+        assert_eq!(pane.contents.to_string(), "foo(22, 1);\nbar(4, 3);\n");
+        let invalid = receiver.try_iter().any(|message| matches!(message, Message::SetMessage(text, MessageKind::Error) if text == "Invalid pattern: ("));
+        assert!(invalid);
+    }
+
+    /// The ids of what is inside of the first items of a tree, which stay the same while the items are reused.
+    /// The items themselves get new ids when the list holding them is built again.
+    fn item_ids(tree: &Tree) -> Vec<usize> {
+        let root = tree.root_node();
+        (0..500).flat_map(|i| {
+            let item = root.child(i).unwrap();
+            (0..item.child_count()).map(move |j| item.child(j).unwrap().id())
+        }).collect()
+    }
+
+    #[test]
+    fn test_undo_reparses_incrementally() {
+        let text = "fn f() { let x = 1; }\n".repeat(1000);
+        let (pane, container, _receiver, settings) = rust_pane(&text);
+        // The tree is looked at right after the undo, so it can't come from the parser thread
+        settings.borrow_mut().editor_settings.background_parse_size = usize::MAX;
+        let mut pane = pane.borrow_mut();
+
+        let cursor = pane.get_cursor();
+        cursor.borrow_mut().jump_to(0, 1000, &*pane);
+        pane.insert_str("fn g() {}\n");
+        pane.change_mode("Normal");
+        let edited = pane.tree.clone().unwrap();
+        let edited_ids = item_ids(&edited);
+
+        pane.run_command("undo", &container);
+        assert_eq!(pane.contents.to_string(), text);
+
+        // The items before the change are reused instead of parsed again
+        let tree = pane.tree.clone().unwrap();
+        assert_eq!(item_ids(&tree), edited_ids);
+
+        let reparsed = pane.parser.parse(&text, None).unwrap();
+        assert_eq!(tree.root_node().to_sexp(), reparsed.root_node().to_sexp());
+    }
+
+    #[test]
+    fn test_big_files_parse_in_background() {
+        let settings = Rc::new(RefCell::new(Settings::default()));
+        settings.borrow_mut().editor_settings.background_parse_size = 0;
+        let (sender, _receiver) = channel();
+        let mut pane = TreesitterPane::new(settings.clone(), sender, tree_sitter_rust::language(), "rust", None);
+
+        let wait_for_tree = |pane: &mut TreesitterPane| {
+            while pane.is_waiting() {
+                std::thread::sleep(Duration::from_millis(10));
+                pane.receive_tree();
+            }
+        };
+
+        pane.insert_str("fn a() {}\n");
+        assert!(pane.is_waiting());
+        wait_for_tree(&mut pane);
+        let tree = pane.tree.as_ref().unwrap();
+        assert_eq!(tree.root_node().child(0).unwrap().kind(), "function_item");
+
+        // There is no tree to draw with until the new one comes back, so the lines go without colors
+        pane.parse_from_scratch();
+        assert!(pane.tree.is_none());
+        assert!(pane.line_highlight(0).color_at(0).is_none());
+        wait_for_tree(&mut pane);
+        assert!(pane.line_highlight(0).color_at(0).is_some());
+
+        // The highlighting drawn with the old tree goes once the new one is in
+        let cursor = pane.get_cursor();
+        cursor.borrow_mut().jump_to(0, 1, &pane);
+        pane.insert_str("// b\n");
+        let stale = pane.line_highlight(1);
+        wait_for_tree(&mut pane);
+        assert!(!Rc::ptr_eq(&stale, &pane.line_highlight(1)));
+        assert!(pane.line_highlight(1).color_at(0).is_some());
+    }
+
+    #[test]
+    fn test_auto_indent() {
+        let (pane, container, _receiver, _) = rust_pane("fn main() {}\n");
+        let mut pane = pane.borrow_mut();
+
+        let cursor = pane.get_cursor();
+        cursor.borrow_mut().jump_to(11, 0, &*pane);
+        pane.insert_newline();
+        assert_eq!(pane.buffer_to_string(), "fn main() {\n    \n}\n");
+        assert_eq!(cursor.borrow().get_cursor(), (4, 1));
+
+        // A bracket in a comment doesn't open a block
+        pane.insert_str("// {");
+        cursor.borrow_mut().jump_to(8, 1, &*pane);
+        pane.insert_newline();
+        assert_eq!(pane.buffer_to_string(), "fn main() {\n    // {\n    \n}\n");
+        assert_eq!(cursor.borrow().get_cursor(), (4, 2));
+
+        pane.run_command("open_line above", &container);
+        assert_eq!(pane.buffer_to_string(), "fn main() {\n    // {\n    \n    \n}\n");
+        assert_eq!(cursor.borrow().get_cursor(), (4, 2));
+
+        pane.set_indent(2, "");
+        assert_eq!(pane.buffer_to_string(), "fn main() {\n    // {\n\n    \n}\n");
+        assert_eq!(cursor.borrow().get_cursor(), (0, 2));
+    }
+
+    #[test]
+    fn test_shift_lines() {
+        let (pane, container, _receiver, _) = rust_pane("");
+        let mut pane = pane.borrow_mut();
+
+        let text = "fn f() {\nlet x = 1;\n\n  let y = 2;\n}\n";
+        pane.insert_str(text);
+        let cursor = pane.get_cursor();
+        cursor.borrow_mut().jump_to(0, 1, &*pane);
+
+        pane.run_command("shift_lines right 3", &container);
+        assert_eq!(pane.buffer_to_string(), "fn f() {\n    let x = 1;\n\n    let y = 2;\n}\n");
+        assert_eq!(cursor.borrow().get_cursor(), (4, 1));
+
+        pane.run_command("shift_lines left", &container);
+        assert_eq!(pane.buffer_to_string(), "fn f() {\nlet x = 1;\n\n    let y = 2;\n}\n");
+
+        pane.run_command("undo", &container);
+        pane.run_command("undo", &container);
+        assert_eq!(pane.buffer_to_string(), text);
+    }
+
+    #[test]
+    fn test_format_selection_without_lsp() {
+        let (pane, container, _receiver, _) = rust_pane("");
+        let mut pane = pane.borrow_mut();
+
+        let text = "fn f() {\nlet x = 1; // {\n\n        let y = 2;\n  }\n";
+        pane.insert_str(text);
+        let cursor = pane.get_cursor();
+        cursor.borrow_mut().jump_to(0, 1, &*pane);
+        pane.change_mode("Visual Line");
+        cursor.borrow_mut().jump_to(0, 4, &*pane);
+
+        pane.run_command("format_selection", &container);
+        // The bracket in the comment doesn't open a block
+        assert_eq!(pane.buffer_to_string(), "fn f() {\n    let x = 1; // {\n\n    let y = 2;\n}\n");
+        assert_eq!(cursor.borrow().get_cursor(), (4, 1));
+
+        pane.run_command("undo", &container);
+        assert_eq!(pane.buffer_to_string(), text);
+    }
+
+    #[test]
+    fn test_jump_match() {
+        let (pane, container, _receiver, _) = rust_pane("fn f() {\n    let s = \"}\";\n}\n");
+        let mut pane = pane.borrow_mut();
+
+        let cursor = pane.get_cursor();
+        cursor.borrow_mut().jump_to(7, 0, &*pane);
+
+        // The bracket in the string isn't the match
+        pane.run_command("jump_match", &container);
+        assert_eq!(cursor.borrow().get_cursor(), (0, 2));
+        pane.run_command("jump_match", &container);
+        assert_eq!(cursor.borrow().get_cursor(), (7, 0));
+
+        cursor.borrow_mut().jump_to(0, 0, &*pane);
+        pane.run_command("jump_match", &container);
+        assert_eq!(cursor.borrow().get_cursor(), (5, 0));
+    }
+
+    #[test]
+    fn test_word_operators() {
+        let (pane, container, receiver, _) = rust_pane("let foo_bar = baz.qux;\n");
+        let mut pane = pane.borrow_mut();
+
+        let cursor = pane.get_cursor();
+        cursor.borrow_mut().jump_to(4, 0, &*pane);
+
+        pane.run_command("delete_word 1", &container);
+        assert_eq!(pane.buffer_to_string(), "let = baz.qux;\n");
+
+        pane.run_command("change_word 1", &container);
+        assert_eq!(pane.buffer_to_string(), "let  baz.qux;\n");
+
+        cursor.borrow_mut().jump_to(10, 0, &*pane);
+        pane.run_command("delete_inner_word", &container);
+        assert_eq!(pane.buffer_to_string(), "let  baz.;\n");
+        assert_eq!(cursor.borrow().get_cursor(), (9, 0));
+
+        let copied = receiver.try_iter().filter_map(|message| match message {
+            Message::Copy(_, value) => Some(value.text),
+            _ => None,
+        }).collect::<Vec<_>>();
+        assert_eq!(copied, vec!["foo_bar ", "=", "qux"]);
+    }
+
+    #[test]
+    fn test_repeat_change() {
+        use crossterm::event::{KeyCode, KeyModifiers};
+
+        let (pane, mut container, receiver, _) = rust_pane("abcdef\n");
+        let mut pane = pane.borrow_mut();
+
+        let cursor = pane.get_cursor();
+        cursor.borrow_mut().jump_to(0, 0, &*pane);
+
+        let key = |c| KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE);
+        // Only the `x` changes the text so the motion after it isn't what gets repeated
+        for c in ['x', 'l', '2', '.'] {
+            pane.process_keypress(key(c), &mut container).unwrap();
+        }
+        assert_eq!(pane.buffer_to_string(), "bcdef\n");
+
+        let repeated = receiver.try_iter().find_map(|message| match message {
+            Message::MacroKeys(keys, count) => Some((keys, count)),
+            _ => None,
+        });
+        assert_eq!(repeated, Some((vec![key('x')], 2)));
+    }
+
+    #[test]
+    fn test_rainbow_delimiters_when_scrolled() {
+        let settings = Rc::new(RefCell::new(Settings::default()));
+        let (sender, _receiver) = channel();
+        let pane = Rc::new(RefCell::new(TreesitterPane::new(settings.clone(), sender, tree_sitter_rust::language(), "rust", None)));
+        let container = PaneContainer::new((80, 24), (80, 3), pane.clone(), settings.clone());
+        let mut pane = pane.borrow_mut();
+
+        pane.insert_str("fn main() {\n    if a {\n        if b {\n            let c = (d, [e]);\n        }\n    }\n}\n");
+
+        // A row in the middle doesn't need the rows above it drawn first
+        assert_eq!(pane.rainbow_depth(4, 0), 3);
+        let depths = (0..8).map(|row| pane.rainbow_depth(row, 0)).collect::<Vec<_>>();
+        assert_eq!(depths, vec![0, 1, 2, 3, 3, 2, 1, 0]);
+        assert_eq!(pane.rainbow_depth(3, 20), 3);
+        assert_eq!(pane.rainbow_depth(3, 21), 4);
+        assert_eq!(pane.rainbow_depth(3, 25), 5);
+
+        // A closing brace at the top of the view gets the color of the one that opened it
+        let colors = settings.borrow().colors.rainbow_delimiters.clone();
+        let first_brace = |pane: &TreesitterPane, row_offset: usize| {
+            pane.get_cursor().borrow_mut().row_offset = row_offset;
+            let mut output = TextRow::new();
+            pane.draw_row(0, &container, &mut output);
+            (0..output.len())
+                .filter_map(|i| output[i].borrow().clone())
+                .find(|chr| chr.chr == '}')
+                .map(|chr| chr.color)
+        };
+        for row_offset in [4, 5, 6, 4] {
+            assert_eq!(first_brace(&pane, row_offset), Some(colors[(6 - row_offset) % colors.len()].clone()));
+        }
+
+        // Editing the text starts the depths over
+        let cursor = pane.get_cursor();
+        cursor.borrow_mut().jump_to(0, 0, &*pane);
+        pane.insert_str("mod m {\n");
+        assert_eq!(pane.rainbow_depth(5, 0), 4);
+    }
+
+    #[test]
+    fn test_highlights_are_cached() {
+        let settings = Rc::new(RefCell::new(Settings::default()));
+        let (sender, _receiver) = channel();
+        let mut pane = TreesitterPane::new(settings.clone(), sender, tree_sitter_rust::language(), "rust", None);
+
+        pane.insert_str("fn a() {}\nfn b() {}\n");
+        let first = pane.line_highlight(0);
+        let second = pane.line_highlight(1);
+        assert!(Rc::ptr_eq(&first, &pane.line_highlight(0)));
+
+        // Only the lines from the edit on get worked out again
+        let cursor = pane.get_cursor();
+        cursor.borrow_mut().jump_to(0, 1, &pane);
+        pane.insert_str("const C: u8 = 1;\n");
+        assert!(Rc::ptr_eq(&first, &pane.line_highlight(0)));
+        assert!(!Rc::ptr_eq(&second, &pane.line_highlight(1)));
+
+        // Reloading the config brings new colors
+        let colors = settings.borrow().colors.treesitter.as_ref().clone();
+        settings.borrow_mut().colors.treesitter = Rc::new(colors);
+        assert!(!Rc::ptr_eq(&first, &pane.line_highlight(0)));
+    }
+
+    #[test]
+    fn test_search_preview_restores_cursor() {
+        let settings = Rc::new(RefCell::new(Settings::default()));
+        let (sender, _receiver) = channel();
+        let pane = Rc::new(RefCell::new(TreesitterPane::new(settings.clone(), sender, tree_sitter_rust::language(), "rust", None)));
+        let mut pane = pane.borrow_mut();
+
+        pane.insert_str("let a = 1;\nlet b = a;\n");
+        let cursor = pane.get_cursor();
+        cursor.borrow_mut().jump_to(0, 0, &*pane);
+
+        pane.preview_search("b");
+        assert_eq!(cursor.borrow().get_cursor(), (4, 1));
+        pane.preview_search("a");
+        assert_eq!(cursor.borrow().get_cursor(), (4, 0));
+
+        pane.end_search_preview();
+        assert_eq!(cursor.borrow().get_cursor(), (0, 0));
+        assert!(pane.search_preview.is_none());
+    }
+
+    #[test]
+    fn test_spelling_is_checked_after_edits() {
+        let (pane, mut container, _receiver, settings) = rust_pane("");
+        settings.borrow_mut().editor_settings.spell = true;
+        let mut pane = pane.borrow_mut();
+        pane.spell_checker = Some(Rc::new(SpellChecker::from_words(["spelling", "word"])));
+
+        pane.insert_str("// speling\n");
+        pane.refresh(&mut container);
+        assert_eq!(pane.misspellings.len(), 1);
+
+        // Nothing changed so the tree isn't walked again
+        pane.misspellings.clear();
+        pane.refresh(&mut container);
+        assert!(pane.misspellings.is_empty());
+
+        pane.insert_str("// wrod\n");
+        pane.refresh(&mut container);
+        assert_eq!(pane.misspellings.len(), 2);
+    }
+
+    #[test]
+    fn test_setlocal_unknown_option() {
+        let (pane, container, receiver, _) = rust_pane("");
+        let mut pane = pane.borrow_mut();
+
+        pane.run_command("setlocal wrap border", &container);
+        let messages = receiver.try_iter().collect::<Vec<_>>();
+        assert!(matches!(&messages[0], Message::SetMessage(text, MessageKind::Error) if text == "Unknown local option: wrap"));
+        assert!(matches!(&messages[1], Message::PaneBorder(Some(true))));
+    }
+}
@@ -0,0 +1,104 @@
+use std::{rc::Rc, cell::RefCell, sync::mpsc::Sender, time::{Instant, Duration}};
+
+use crate::{mode::Mode, settings::Settings, window::Message};
+
+use super::{PaneMessage, PaneContainer, popup::PopUpPane};
+
+
+
+/// Shows the keybindings that can still be finished once part of a multi-key binding has been typed.
+pub struct WhichKey {
+    closer: Option<Sender<PaneMessage>>,
+    time: Instant,
+}
+
+impl Default for WhichKey {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl WhichKey {
+    pub fn new() -> Self {
+        Self {
+            closer: None,
+            time: Instant::now(),
+        }
+    }
+
+    /// This should get called after every keypress.
+    /// Any open popup gets closed and the delay starts over.
+    pub fn keypress(&mut self) {
+        self.time = Instant::now();
+        self.close();
+    }
+
+    pub fn close(&mut self) {
+        if let Some(closer) = self.closer.take() {
+            let _ = closer.send(PaneMessage::Close);
+        }
+    }
+
+    /// Opens the popup once the delay has passed if the mode has a partial binding.
+    pub fn refresh(&mut self, mode: &dyn Mode, settings: &Rc<RefCell<Settings>>, sender: &Sender<Message>, container: &PaneContainer) {
+        if self.closer.is_some() {
+            return;
+        }
+
+        let (enabled, delay) = {
+            let settings = settings.borrow();
+            (settings.editor_settings.which_key, settings.editor_settings.which_key_delay)
+        };
+        if !enabled || self.time.elapsed() < Duration::from_millis(delay) {
+            return;
+        }
+
+        let (typed, bindings) = match mode.get_pending_bindings() {
+            None => return,
+            Some(pending) => pending,
+        };
+
+        let mut body = bindings.iter()
+            .map(|(keys, command)| {
+                let keys = keys.iter().map(|key| key.to_string()).collect::<Vec<String>>().join(" ");
+                format!("{}  {}", keys, command)
+            })
+            .collect::<Vec<String>>();
+        body.sort();
+
+        let prompt = typed.iter().map(|key| key.to_string()).collect::<Vec<String>>().join(" ");
+
+        let width = body.iter()
+            .map(|line| line.chars().count())
+            .chain(std::iter::once(prompt.chars().count()))
+            .max()
+            .unwrap_or(0) + 2;
+        let size = (width, body.len() + 3);
+
+        // Nothing gets sent back from an info popup
+        let (send, _) = std::sync::mpsc::channel();
+        let (send2, recv2) = std::sync::mpsc::channel();
+
+        let pane = PopUpPane::new_info(settings.clone(),
+                                       vec![prompt],
+                                       sender.clone(),
+                                       send,
+                                       recv2,
+                                       body.into_iter().map(Some).collect(),
+                                       true);
+        let pane = Rc::new(RefCell::new(pane));
+
+        let (_, (x2, y2)) = container.get_corners();
+        let pos = (x2.saturating_sub(size.0), y2.saturating_sub(size.1));
+
+        let max_size = container.get_size();
+
+        let mut container = PaneContainer::new(max_size, size, pane, settings.clone());
+        container.set_position(pos);
+        container.set_size(size);
+
+        sender.send(Message::CreatePopup(container, false)).expect("Failed to send message");
+
+        self.closer = Some(send2);
+    }
+}
@@ -0,0 +1,117 @@
+use std::{fs, path::PathBuf};
+
+
+/// What a path has to start with for it to be completed while typing text.
+const PATH_STARTS: [&str; 4] = ["./", "../", "/", "~/"];
+
+/// Characters that end a path when going back from the cursor.
+fn is_path_boundary(c: char) -> bool {
+    c.is_whitespace() || matches!(c, '"' | '\'' | '`' | '(' | ')' | '[' | ']' | '<' | '>' | '=' | ',')
+}
+
+/// The path that `text` ends with, if there is something there that looks like one.
+pub fn path_before(text: &str) -> Option<&str> {
+    let start = text.char_indices()
+        .rev()
+        .find(|(_, c)| is_path_boundary(*c))
+        .map_or(0, |(i, c)| i + c.len_utf8());
+    let path = &text[start..];
+    PATH_STARTS.iter().any(|prefix| path.starts_with(prefix)).then_some(path)
+}
+
+/// The directory a partial path lists and the start of the name being typed in it.
+fn split_partial(partial: &str) -> (&str, &str) {
+    match partial.rfind('/') {
+        None => ("", partial),
+        Some(slash) => (&partial[..=slash], &partial[slash + 1..]),
+    }
+}
+
+/// The directory on disk that a directory of a partial path means, with `~` as the home directory.
+fn resolve_dir(dir: &str) -> PathBuf {
+    if dir.is_empty() {
+        return PathBuf::from(".");
+    }
+    match dir.strip_prefix("~/") {
+        Some(rest) => match std::env::var_os("HOME") {
+            Some(home) => PathBuf::from(home).join(rest),
+            None => PathBuf::from(dir),
+        },
+        None => PathBuf::from(dir),
+    }
+}
+
+/// The names in the directory of a partial path that complete the name being typed, sorted.
+/// Directories end with `/` so that completing can carry on into them.
+/// Hidden files are only listed with `hidden` or when the name being typed starts with a dot.
+pub fn complete_names(partial: &str, hidden: bool) -> Vec<String> {
+    let (dir, name) = split_partial(partial);
+    let entries = match fs::read_dir(resolve_dir(dir)) {
+        Err(_) => return Vec::new(),
+        Ok(entries) => entries,
+    };
+
+    let mut names = entries.filter_map(|entry| {
+        let entry = entry.ok()?;
+        let file_name = entry.file_name().into_string().ok()?;
+        if !file_name.starts_with(name) || (file_name.starts_with('.') && !hidden && !name.starts_with('.')) {
+            return None;
+        }
+        // Links to directories are followed like directories
+        let is_dir = fs::metadata(entry.path()).is_ok_and(|metadata| metadata.is_dir());
+        Some(if is_dir { format!("{}/", file_name) } else { file_name })
+    })
+    .collect::<Vec<_>>();
+    names.sort();
+    names
+}
+
+/// The whole paths that complete a partial path.
+pub fn complete_path(partial: &str, hidden: bool) -> Vec<String> {
+    let (dir, _) = split_partial(partial);
+    complete_names(partial, hidden).into_iter()
+        .map(|name| format!("{}{}", dir, name))
+        .collect()
+}
+
+/// How much of the end of a partial path completing it replaces, in characters.
+pub fn name_len(partial: &str) -> usize {
+    split_partial(partial).1.chars().count()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_path_before() {
+        assert_eq!(path_before("let file = \"./src/ma"), Some("./src/ma"));
+        assert_eq!(path_before("see ~/notes"), Some("~/notes"));
+        assert_eq!(path_before("/usr"), Some("/usr"));
+        assert_eq!(path_before("a / b"), None);
+        assert_eq!(path_before("self.value"), None);
+    }
+
+    #[test]
+    fn test_complete_path() {
+        let dir = std::env::temp_dir().join("vi_path_completion_test");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::write(dir.join("setup.py"), "").unwrap();
+        fs::write(dir.join(".secret"), "").unwrap();
+
+        let partial = format!("{}/s", dir.display());
+        assert_eq!(complete_names(&partial, false), vec!["setup.py".to_string(), "src/".to_string()]);
+        assert_eq!(complete_path(&partial, false)[1], format!("{}/src/", dir.display()));
+        assert_eq!(name_len(&partial), 1);
+
+        // Hidden files need the setting or a dot
+        let partial = format!("{}/", dir.display());
+        assert_eq!(complete_names(&partial, false).len(), 2);
+        assert_eq!(complete_names(&partial, true).len(), 3);
+        assert_eq!(complete_names(&format!("{}.", partial), false), vec![".secret".to_string()]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
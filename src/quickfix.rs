@@ -0,0 +1,203 @@
+use std::{env, fs, path::PathBuf, sync::mpsc::{self, Receiver, TryRecvError}, thread};
+
+use ignore::WalkBuilder;
+
+
+/// A place in a file that a command like `grep` found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuickFixEntry {
+    pub path: PathBuf,
+    /// The column of the match, counting from 0.
+    pub col: usize,
+    /// The line of the match, counting from 0.
+    pub line: usize,
+    pub text: String,
+}
+
+/// How much of the start of a file is checked for a null byte, which makes it binary.
+const BINARY_CHECK_LEN: usize = 8000;
+
+/// A list of places to step through with `cn` and `cp`.
+pub struct QuickFix {
+    entries: Vec<QuickFixEntry>,
+    current: usize,
+    /// What was searched for to find the entries.
+    pattern: String,
+    /// Where the entries of a search that is still running come from.
+    receiver: Option<Receiver<QuickFixEntry>>,
+}
+
+impl Default for QuickFix {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl QuickFix {
+    pub fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+            current: 0,
+            pattern: String::new(),
+            receiver: None,
+        }
+    }
+
+    /// Throws away the list and starts searching the files under the directory on another thread.
+    /// Files ignored by git and binary files are skipped.
+    /// The paths of files under the current directory are kept relative to it.
+    pub fn grep(&mut self, pattern: String, root: PathBuf) {
+        let (sender, receiver) = mpsc::channel();
+        let current_dir = env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+
+        self.pattern = pattern.clone();
+        thread::spawn(move || {
+            for entry in WalkBuilder::new(&root).build().flatten() {
+                if !entry.file_type().is_some_and(|file_type| file_type.is_file()) {
+                    continue;
+                }
+                // Files that aren't text can't match
+                let text = match fs::read(entry.path()) {
+                    Ok(bytes) if !is_binary(&bytes) => match String::from_utf8(bytes) {
+                        Ok(text) => text,
+                        Err(_) => continue,
+                    },
+                    _ => continue,
+                };
+                let path = entry.path();
+                let path = path.strip_prefix(&current_dir)
+                    .or_else(|_| path.strip_prefix("."))
+                    .unwrap_or(path)
+                    .to_path_buf();
+
+                for (col, line, text) in grep_text(&text, &pattern) {
+                    let entry = QuickFixEntry {
+                        path: path.clone(),
+                        col,
+                        line,
+                        text,
+                    };
+                    // The list was replaced so nobody wants the rest
+                    if sender.send(entry).is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+
+        self.entries.clear();
+        self.current = 0;
+        self.receiver = Some(receiver);
+    }
+
+    pub fn is_searching(&self) -> bool {
+        self.receiver.is_some()
+    }
+
+    /// Stops a search that is still running, keeping what it found so far.
+    pub fn cancel(&mut self) {
+        // The search stops when it has nowhere to send what it finds
+        self.receiver = None;
+    }
+
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+
+    pub fn entries(&self) -> &[QuickFixEntry] {
+        &self.entries
+    }
+
+    /// Moves what the search found since the last call into the list.
+    /// Returns whether the search is done.
+    pub fn poll(&mut self) -> bool {
+        let receiver = match &self.receiver {
+            None => return true,
+            Some(receiver) => receiver,
+        };
+
+        loop {
+            match receiver.try_recv() {
+                Ok(entry) => self.entries.push(entry),
+                Err(TryRecvError::Empty) => return false,
+                Err(TryRecvError::Disconnected) => {
+                    self.receiver = None;
+                    return true;
+                },
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn current(&self) -> Option<&QuickFixEntry> {
+        self.entries.get(self.current)
+    }
+
+    pub fn current_index(&self) -> usize {
+        self.current
+    }
+
+    /// Makes an entry the current one, so that stepping carries on from there.
+    pub fn select(&mut self, index: usize) -> Option<&QuickFixEntry> {
+        if index >= self.entries.len() {
+            return None;
+        }
+        self.current = index;
+        self.current()
+    }
+
+    pub fn next_entry(&mut self) -> Option<&QuickFixEntry> {
+        if self.current + 1 >= self.entries.len() {
+            return None;
+        }
+        self.current += 1;
+        self.current()
+    }
+
+    pub fn previous_entry(&mut self) -> Option<&QuickFixEntry> {
+        if self.current == 0 || self.entries.is_empty() {
+            return None;
+        }
+        self.current -= 1;
+        self.current()
+    }
+}
+
+/// Whether the start of a file holds a null byte, which text files don't.
+fn is_binary(bytes: &[u8]) -> bool {
+    bytes.iter().take(BINARY_CHECK_LEN).any(|byte| *byte == 0)
+}
+
+/// Finds the lines that contain the pattern.
+/// Returns the column and line of the first match on each line along with the line.
+fn grep_text(text: &str, pattern: &str) -> Vec<(usize, usize, String)> {
+    text.lines().enumerate().filter_map(|(line, content)| {
+        let byte = content.find(pattern)?;
+        let col = content[..byte].chars().count();
+        Some((col, line, content.trim().to_string()))
+    }).collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_grep_text() {
+        let text = "fn main() {\n    let todo = 1;\n}\n// todo: more";
+        assert_eq!(grep_text(text, "todo"), vec![
+            (8, 1, "let todo = 1;".to_string()),
+            (3, 3, "// todo: more".to_string()),
+        ]);
+        assert!(is_binary(b"ELF\0\x01todo"));
+        assert!(!is_binary("todo ✓".as_bytes()));
+    }
+}
@@ -0,0 +1,84 @@
+use std::{env, fs, io, path::PathBuf};
+
+
+/// How many files we remember.
+const MAX_RECENT_FILES: usize = 32;
+
+
+/// A most recently used list of files that is kept between sessions.
+pub struct RecentFiles {
+    files: Vec<PathBuf>,
+    save_path: Option<PathBuf>,
+}
+
+impl Default for RecentFiles {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RecentFiles {
+    pub fn new() -> Self {
+        let save_path = Self::data_path();
+
+        let files = match &save_path {
+            None => Vec::new(),
+            Some(path) => match fs::read_to_string(path) {
+                Ok(contents) => contents.lines()
+                    .filter(|line| !line.is_empty())
+                    .map(PathBuf::from)
+                    .take(MAX_RECENT_FILES)
+                    .collect(),
+                Err(_) => Vec::new(),
+            },
+        };
+
+        Self {
+            files,
+            save_path,
+        }
+    }
+
+    /// The file lives in `$XDG_DATA_HOME/vi` or `~/.local/share/vi` if that isn't set.
+    fn data_path() -> Option<PathBuf> {
+        let data_dir = match env::var_os("XDG_DATA_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(env::var_os("HOME")?).join(".local").join("share"),
+        };
+
+        Some(data_dir.join("vi").join("recent_files"))
+    }
+
+    /// Moves a file to the front of the list and saves the list.
+    pub fn push(&mut self, path: PathBuf) -> io::Result<()> {
+        let path = fs::canonicalize(&path).unwrap_or(path);
+
+        self.files.retain(|file| file != &path);
+        self.files.insert(0, path);
+        self.files.truncate(MAX_RECENT_FILES);
+
+        self.save()
+    }
+
+    pub fn get_files(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let path = match &self.save_path {
+            None => return Ok(()),
+            Some(path) => path,
+        };
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let contents = self.files.iter()
+            .map(|file| file.to_string_lossy().to_string())
+            .collect::<Vec<String>>()
+            .join("\n");
+
+        fs::write(path, contents)
+    }
+}
@@ -1,20 +1,83 @@
 
 use std::{collections::HashMap, cell::RefCell};
 use arboard::Clipboard;
+use crossterm::event::KeyEvent;
+
+
+/// Whether a register holds whole lines or a piece of text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegisterKind {
+    CharWise,
+    /// Pasting puts the text on its own lines instead of at the cursor.
+    LineWise,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegisterValue {
+    pub text: String,
+    pub kind: RegisterKind,
+}
+
+impl RegisterValue {
+    pub fn char_wise(text: String) -> Self {
+        Self {
+            text,
+            kind: RegisterKind::CharWise,
+        }
+    }
+
+    /// Whole lines always end with a line break so they can be pasted between other lines.
+    pub fn line_wise(mut text: String) -> Self {
+        if !text.ends_with('\n') {
+            text.push('\n');
+        }
+        Self {
+            text,
+            kind: RegisterKind::LineWise,
+        }
+    }
+
+    /// Adds text to the end of the register like yanking into `"A` does.
+    /// When either side holds whole lines the result holds whole lines too.
+    pub fn append(&mut self, other: RegisterValue) {
+        match (self.kind, other.kind) {
+            (RegisterKind::CharWise, RegisterKind::CharWise) => self.text.push_str(&other.text),
+            (kind, _) => {
+                if kind == RegisterKind::CharWise {
+                    self.text.push('\n');
+                }
+                self.text.push_str(&other.text);
+                *self = Self::line_wise(std::mem::take(&mut self.text));
+            },
+        }
+    }
+
+    /// Text from outside the editor holds whole lines when it ends with a line break.
+    pub fn from_text(text: String) -> Self {
+        if text.ends_with('\n') {
+            Self::line_wise(text)
+        }
+        else {
+            Self::char_wise(text)
+        }
+    }
+}
 
 
 pub trait RegisterUtils<T> {
     /// Get the value of a register.
-    fn get(&self, name: T) -> Option<&String>;
+    fn get(&self, name: T) -> Option<&RegisterValue>;
     /// Set the value of a register.
-    fn set(&mut self, name: T, value: String);
+    fn set(&mut self, name: T, value: RegisterValue);
 }
 
 
 pub struct Registers {
     clipboard: Result<RefCell<Clipboard>, Option<String>>,
-    named: HashMap<String, String>,
-    numbered: HashMap<usize, String>,
+    named: HashMap<String, RegisterValue>,
+    numbered: HashMap<usize, RegisterValue>,
+    /// The keys recorded with `q`, keyed by register.
+    macros: HashMap<char, Vec<KeyEvent>>,
 }
 
 
@@ -32,6 +95,7 @@ impl Registers {
             clipboard,//: ClipboardContext::new().map_err(|_| None).map(RefCell::new),
             named: HashMap::new(),
             numbered: HashMap::new(),
+            macros: HashMap::new(),
         }
     }
 
@@ -65,26 +129,66 @@ impl Registers {
             }
         }
     }
+
+    /// Stores a value in a named register, returning what the register holds now.
+    /// An uppercase name appends to the lowercase register instead of replacing it.
+    pub fn set_named(&mut self, name: &str, value: RegisterValue) -> RegisterValue {
+        let lower = name.to_lowercase();
+
+        let value = match self.named.get(&lower) {
+            Some(old) if lower != name => {
+                let mut old = old.clone();
+                old.append(value);
+                old
+            },
+            _ => value,
+        };
+        self.named.insert(lower, value.clone());
+        value
+    }
+
+    /// Every register that holds something, the numbered ones first.
+    pub fn list(&self) -> Vec<(String, RegisterValue)> {
+        let mut numbered = self.numbered.iter()
+            .map(|(name, value)| (name.to_string(), value.clone()))
+            .collect::<Vec<_>>();
+        numbered.sort_by_key(|(name, _)| name.parse::<usize>().unwrap_or(0));
+
+        let mut named = self.named.iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect::<Vec<_>>();
+        named.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        numbered.into_iter().chain(named).collect()
+    }
+
+    pub fn get_macro(&self, name: char) -> Option<&Vec<KeyEvent>> {
+        self.macros.get(&name)
+    }
+
+    pub fn set_macro(&mut self, name: char, keys: Vec<KeyEvent>) {
+        self.macros.insert(name, keys);
+    }
     
 }
 
 impl RegisterUtils<usize> for Registers {
-    fn get(&self, name: usize) -> Option<&String> {
+    fn get(&self, name: usize) -> Option<&RegisterValue> {
         self.numbered.get(&name)
     }
 
-    fn set(&mut self, name: usize, value: String) {
+    fn set(&mut self, name: usize, value: RegisterValue) {
         self.numbered.insert(name, value);
     }
 
 }
 
 impl RegisterUtils<String> for Registers {
-    fn get(&self, name: String) -> Option<&String> {
+    fn get(&self, name: String) -> Option<&RegisterValue> {
         self.named.get(&name)
     }
 
-    fn set(&mut self, name: String, value: String) {
+    fn set(&mut self, name: String, value: RegisterValue) {
         self.named.insert(name, value);
     }
 }
@@ -0,0 +1,151 @@
+use std::cell::RefCell;
+
+use regex::Regex;
+
+use crate::buffer::Buffer;
+
+/// A place in the buffer where the search pattern was found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SearchMatch {
+    /// The column of the first character of the match, counting from 0.
+    pub col: usize,
+    /// The line of the match, counting from 0.
+    pub line: usize,
+    /// How many characters the match covers.
+    pub len: usize,
+}
+
+thread_local! {
+    /// The last pattern that was compiled.
+    /// The same pattern gets used for every row that is drawn so it is only compiled once.
+    static LAST_PATTERN: RefCell<Option<Pattern>> = const { RefCell::new(None) };
+}
+
+/// A compiled regular expression to search with.
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    source: String,
+    regex: Regex,
+}
+
+impl Pattern {
+    /// Compiles the pattern, reusing the last one if it is the same.
+    /// The error is ready to be shown to the user.
+    pub fn compile(source: &str) -> Result<Self, String> {
+        LAST_PATTERN.with(|last| {
+            let mut last = last.borrow_mut();
+            if let Some(pattern) = last.as_ref().filter(|pattern| pattern.source == source) {
+                return Ok(pattern.clone());
+            }
+
+            let regex = Regex::new(source).map_err(|_| format!("Invalid pattern: {}", source))?;
+            let pattern = Self {
+                source: source.to_string(),
+                regex,
+            };
+            *last = Some(pattern.clone());
+            Ok(pattern)
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    /// Finds the first match in the line that starts at or after the column.
+    /// Returns the columns the match starts and ends at.
+    pub fn find_in_line(&self, line: &str, col: usize) -> Option<(usize, usize)> {
+        let line = trim_newline(line);
+        let byte = char_to_byte(line, col)?;
+        let found = self.regex.find_at(line, byte)?;
+
+        let start = line[..found.start()].chars().count();
+        Some((start, start + found.as_str().chars().count()))
+    }
+
+    /// Finds every place the pattern shows up in the text.
+    /// Matches don't overlap and can't span lines.
+    pub fn find_all(&self, text: &str) -> Vec<SearchMatch> {
+        text.lines().enumerate().flat_map(|(line, content)| {
+            self.regex.find_iter(content).map(move |found| SearchMatch {
+                col: content[..found.start()].chars().count(),
+                line,
+                len: found.as_str().chars().count(),
+            })
+        }).collect()
+    }
+
+    /// Finds the first match at or after the (column, row) position without going past the end of the buffer.
+    pub fn find_next(&self, buffer: &Buffer, (x, y): (usize, usize)) -> Option<SearchMatch> {
+        (y..buffer.get_line_count()).find_map(|row| {
+            let line = buffer.get_row(row, 0, usize::MAX)?.to_string();
+            let col = if row == y { x } else { 0 };
+
+            self.find_in_line(&line, col).map(|(start, end)| SearchMatch {
+                col: start,
+                line: row,
+                len: end - start,
+            })
+        })
+    }
+
+    /// Builds the replacement for the match that starts at the column.
+    /// Capture groups can be used as `\1` or `$1`.
+    pub fn expand(&self, line: &str, col: usize, replacement: &str) -> String {
+        let line = trim_newline(line);
+        let captures = match char_to_byte(line, col).and_then(|byte| self.regex.captures_at(line, byte)) {
+            None => return replacement.to_string(),
+            Some(captures) => captures,
+        };
+
+        let mut template = String::with_capacity(replacement.len());
+        let mut chars = replacement.chars().peekable();
+        while let Some(c) = chars.next() {
+            match (c, chars.peek().copied()) {
+                ('\\', Some(digit)) if digit.is_ascii_digit() => {
+                    template.push_str(&format!("${{{}}}", digit));
+                    chars.next();
+                },
+                (c, _) => template.push(c),
+            }
+        }
+
+        let mut output = String::new();
+        captures.expand(&template, &mut output);
+        output
+    }
+}
+
+fn trim_newline(line: &str) -> &str {
+    let line = line.strip_suffix('\n').unwrap_or(line);
+    line.strip_suffix('\r').unwrap_or(line)
+}
+
+/// The byte the column starts at, the end of the line being the last column.
+fn char_to_byte(line: &str, col: usize) -> Option<usize> {
+    line.char_indices()
+        .map(|(byte, _)| byte)
+        .chain(std::iter::once(line.len()))
+        .nth(col)
+}
+
+/// Picks the match after the cursor, or before it when going backward.
+/// With `wrap` the search continues from the other end of the file.
+/// Also returns whether the search went past the end of the file.
+pub fn next_match(matches: &[SearchMatch], (x, y): (usize, usize), forward: bool, wrap: bool) -> Option<(SearchMatch, bool)> {
+    let found = if forward {
+        matches.iter().find(|found| (found.line, found.col) > (y, x))
+    }
+    else {
+        matches.iter().rev().find(|found| (found.line, found.col) < (y, x))
+    };
+
+    match found {
+        Some(found) => Some((*found, false)),
+        None if wrap => {
+            let found = if forward { matches.first() } else { matches.last() };
+            found.map(|found| (*found, true))
+        },
+        None => None,
+    }
+}
@@ -1,9 +1,12 @@
 use core::fmt;
-use std::{collections::{HashMap, HashSet}, rc::Rc};
+use std::{collections::{HashMap, HashSet}, env, fs, io, path::PathBuf, rc::Rc};
 
 use crossterm::{event::{KeyCode, KeyModifiers, KeyEvent}, style::{Attribute, Color}};
+use encoding_rs::Encoding;
 use serde::Deserialize;
 
+use crate::encoding::parse_encoding;
+
 
 #[macro_export]
 macro_rules! apply_colors {
@@ -56,7 +59,7 @@ impl fmt::Display for KeyCodeWrapper {
     }
 }
 
-#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
 pub struct Key {
     pub key: KeyCode,
     pub modifier: KeyModifiers,
@@ -102,18 +105,133 @@ impl From<KeyEvent> for Key {
 pub type Mode = String;
 pub type Command = String;
 
+/// Two bindings in a mode where one starts with the whole of the other.
+/// Typing the shorter one makes the mode wait for the key timeout before running it, in case the longer one comes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BindingConflict {
+    pub mode: Mode,
+    pub shorter: (Keys, Command),
+    pub longer: (Keys, Command),
+}
+
+impl fmt::Display for BindingConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let keys = |keys: &Keys| keys.iter().map(|key| key.to_string()).collect::<Vec<String>>().join(" ");
+
+        write!(f, "{}: {} ({}) hides {} ({})",
+               self.mode,
+               keys(&self.shorter.0),
+               self.shorter.1,
+               keys(&self.longer.0),
+               self.longer.1)
+    }
+}
+
 pub type Keys = Vec<Key>;
 
+/// What the keys typed in a mode come to once they are taken out of the key buffer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyAction {
+    Run(Command),
+    /// A key that no binding starts with, which the mode can use on its own or drop.
+    Unbound(Key),
+}
+
+/// Takes the bindings the typed keys make up out of the key buffer, in the order they were typed.
+/// Keys that could still turn into a longer binding stay in the buffer, unless the key timeout ran out.
+/// When a key doesn't continue any binding, the longest binding at the start of the buffer is run
+/// and the keys after it are gone through again, like vim's timeoutlen.
+pub fn take_key_actions(bindings: &HashMap<Keys, Command>, buffer: &mut Keys, mut timed_out: bool) -> Vec<KeyAction> {
+    let mut actions = Vec::new();
+
+    while !buffer.is_empty() {
+        let continues = bindings.keys().any(|keys| keys.len() > buffer.len() && keys.starts_with(buffer));
+        if continues && !timed_out {
+            break;
+        }
+        // The keys left over get their own chance to be finished
+        timed_out = false;
+
+        let found = (1..=buffer.len()).rev()
+            .find_map(|length| bindings.get(&buffer[..length]).map(|command| (length, command)));
+        match found {
+            Some((length, command)) => {
+                actions.push(KeyAction::Run(command.clone()));
+                buffer.drain(..length);
+            },
+            None => actions.push(KeyAction::Unbound(buffer.remove(0))),
+        }
+    }
+
+    actions
+}
+
 #[derive(Debug, Clone)]
 pub struct Settings {
     pub editor_settings: EditorSettings,
     pub mode_keybindings: HashMap<Mode, HashMap<Keys, Command>>,
     pub colors: EditorColors,
-    
+    /// The pairs that get closed as they are typed for each language.
+    /// Languages without their own pairs use the ones under `default`.
+    pub auto_pairs: HashMap<String, Vec<(char, char)>>,
+    /// Where undo histories get saved when `persistent_undo` is on.
+    /// `None` uses the data directory.
+    pub undo_dir: Option<PathBuf>,
+    /// Where the copies of files made by `backup` go.
+    /// `None` puts them next to the files.
+    pub backup_dir: Option<PathBuf>,
+    /// The language servers to start for each language, in place of the built in ones.
+    pub lsp_servers: HashMap<String, LspServerConfig>,
+}
+
+/// How to start the language server for a language.
+#[derive(Debug, Deserialize, Clone, PartialEq, Default)]
+pub struct LspServerConfig {
+    /// Can be left out when connecting over tcp to a server that is already running.
+    #[serde(default)]
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Variables to set in the environment of the server.
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    /// Sent to the server as the `initializationOptions` when it starts.
+    pub initialization_options: Option<serde_json::Value>,
+    /// Handed to the server when it asks for its settings with `workspace/configuration`.
+    pub configuration: Option<serde_json::Value>,
+    /// How messages get to and from the server.
+    #[serde(default)]
+    pub transport: LspTransport,
+    /// The host to connect to over tcp, the local machine if left out.
+    pub host: Option<String>,
+    /// The port to connect to over tcp.
+    pub port: Option<u16>,
+}
+
+impl LspServerConfig {
+    /// A server needs a command to start it, or a port to connect to when it talks over tcp.
+    fn check(&self) -> Result<(), String> {
+        match self.transport {
+            LspTransport::Stdio if self.command.is_empty() => Err("missing command".to_string()),
+            LspTransport::Tcp if self.port.is_none() => Err("missing port".to_string()),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LspTransport {
+    /// The server is started by the editor and talks over its stdin and stdout.
+    #[default]
+    Stdio,
+    /// The server listens on a port. If there is a command it gets started first.
+    Tcp,
 }
 
 impl Settings {
-    fn generate_normal_keybindings(normal_keybindings: &mut HashMap<Keys, Command>) {
+    /// The bindings that start with the leader get built with the given leader key.
+    fn generate_normal_keybindings(normal_keybindings: &mut HashMap<Keys, Command>, leader: Key) {
         normal_keybindings.insert(vec![Key {
             key: KeyCode::Char('l'),
             modifier: KeyModifiers::NONE,
@@ -167,6 +285,10 @@ impl Settings {
             key: KeyCode::Char('$'),
             modifier: KeyModifiers::NONE,
         }], "line_end".to_string());
+        normal_keybindings.insert(vec![Key {
+            key: KeyCode::Char('^'),
+            modifier: KeyModifiers::NONE,
+        }], "first_non_blank".to_string());
         normal_keybindings.insert(vec![Key {
             key: KeyCode::Char('w'),
             modifier: KeyModifiers::NONE,
@@ -258,6 +380,10 @@ impl Settings {
             key: KeyCode::Char('x'),
             modifier: KeyModifiers::NONE,
         }], "delete_char".to_string());
+        normal_keybindings.insert(vec![Key {
+            key: KeyCode::Char('.'),
+            modifier: KeyModifiers::NONE,
+        }], "repeat_change".to_string());
         normal_keybindings.insert(vec![Key {
             key: KeyCode::Char('d'),
             modifier: KeyModifiers::NONE,
@@ -265,6 +391,51 @@ impl Settings {
             key: KeyCode::Char('w'),
             modifier: KeyModifiers::NONE,
         }], "delete_word".to_string());
+        normal_keybindings.insert(vec![Key {
+            key: KeyCode::Char('c'),
+            modifier: KeyModifiers::NONE,
+        }, Key {
+            key: KeyCode::Char('w'),
+            modifier: KeyModifiers::NONE,
+        }], "change_word".to_string());
+        normal_keybindings.insert(vec![Key {
+            key: KeyCode::Char('d'),
+            modifier: KeyModifiers::NONE,
+        }, Key {
+            key: KeyCode::Char('i'),
+            modifier: KeyModifiers::NONE,
+        }, Key {
+            key: KeyCode::Char('w'),
+            modifier: KeyModifiers::NONE,
+        }], "delete_inner_word".to_string());
+        normal_keybindings.insert(vec![Key {
+            key: KeyCode::Char('c'),
+            modifier: KeyModifiers::NONE,
+        }, Key {
+            key: KeyCode::Char('i'),
+            modifier: KeyModifiers::NONE,
+        }, Key {
+            key: KeyCode::Char('w'),
+            modifier: KeyModifiers::NONE,
+        }], "change_inner_word".to_string());
+        normal_keybindings.insert(vec![Key {
+            key: KeyCode::Char('%'),
+            modifier: KeyModifiers::NONE,
+        }], "jump_match".to_string());
+        normal_keybindings.insert(vec![Key {
+            key: KeyCode::Char('>'),
+            modifier: KeyModifiers::NONE,
+        }, Key {
+            key: KeyCode::Char('>'),
+            modifier: KeyModifiers::NONE,
+        }], "indent_line".to_string());
+        normal_keybindings.insert(vec![Key {
+            key: KeyCode::Char('<'),
+            modifier: KeyModifiers::NONE,
+        }, Key {
+            key: KeyCode::Char('<'),
+            modifier: KeyModifiers::NONE,
+        }], "dedent_line".to_string());
         normal_keybindings.insert(vec![Key {
             key: KeyCode::Char('d'),
             modifier: KeyModifiers::NONE,
@@ -295,6 +466,37 @@ impl Settings {
             key: KeyCode::Char(':'),
             modifier: KeyModifiers::NONE,
         }], "start_command".to_string());
+        normal_keybindings.insert(vec![Key {
+            key: KeyCode::Char('v'),
+            modifier: KeyModifiers::NONE,
+        }], "start_visual".to_string());
+        normal_keybindings.insert(vec![Key {
+            key: KeyCode::Char('V'),
+            modifier: KeyModifiers::NONE,
+        }], "start_visual_line".to_string());
+        normal_keybindings.insert(vec![Key {
+            key: KeyCode::Char('v'),
+            modifier: KeyModifiers::CONTROL,
+        }], "start_visual_block".to_string());
+        normal_keybindings.insert(vec![Key {
+            key: KeyCode::Char('g'),
+            modifier: KeyModifiers::NONE,
+        }, Key {
+            key: KeyCode::Char('v'),
+            modifier: KeyModifiers::NONE,
+        }], "reselect".to_string());
+        normal_keybindings.insert(vec![Key {
+            key: KeyCode::Char('/'),
+            modifier: KeyModifiers::NONE,
+        }], "start_search".to_string());
+        normal_keybindings.insert(vec![Key {
+            key: KeyCode::Char('n'),
+            modifier: KeyModifiers::NONE,
+        }], "search_next".to_string());
+        normal_keybindings.insert(vec![Key {
+            key: KeyCode::Char('N'),
+            modifier: KeyModifiers::NONE,
+        }], "search_previous".to_string());
         normal_keybindings.insert(vec![Key {
             key: KeyCode::Char('Z'),
             modifier: KeyModifiers::NONE,
@@ -358,21 +560,29 @@ impl Settings {
             key: KeyCode::Char('l'),
             modifier: KeyModifiers::NONE,
         }], "pane_right".to_string());
-
         normal_keybindings.insert(vec![Key {
-            key: KeyCode::Char('\\'),
+            key: KeyCode::Char('w'),
+            modifier: KeyModifiers::CONTROL,
+        }, Key {
+            key: KeyCode::Char('r'),
             modifier: KeyModifiers::NONE,
+        }], "rotate_panes".to_string());
+        normal_keybindings.insert(vec![Key {
+            key: KeyCode::Char('w'),
+            modifier: KeyModifiers::CONTROL,
         }, Key {
+            key: KeyCode::Char('x'),
+            modifier: KeyModifiers::NONE,
+        }], "swap_pane".to_string());
+
+        normal_keybindings.insert(vec![leader, Key {
             key: KeyCode::Char('j'),
             modifier: KeyModifiers::NONE,
         }, Key {
             key: KeyCode::Char('t'),
             modifier: KeyModifiers::NONE,
         }], "prompt_jump".to_string());
-        normal_keybindings.insert(vec![Key {
-            key: KeyCode::Char('\\'),
-            modifier: KeyModifiers::NONE,
-        }, Key {
+        normal_keybindings.insert(vec![leader, Key {
             key: KeyCode::Char('j'),
             modifier: KeyModifiers::NONE,
         }, Key {
@@ -420,18 +630,17 @@ impl Settings {
             modifier: KeyModifiers::NONE,
         }], "open_tab_with_pane".to_string());
 
-        normal_keybindings.insert(vec![Key {
-            key: KeyCode::Char('\\'),
-            modifier: KeyModifiers::NONE,
-        }, Key {
+        normal_keybindings.insert(vec![leader, Key {
             key: KeyCode::Char('i'),
             modifier: KeyModifiers::NONE,
         }], "info".to_string());
 
-        normal_keybindings.insert(vec![Key {
-            key: KeyCode::Char('\\'),
+        normal_keybindings.insert(vec![leader, Key {
+            key: KeyCode::Char('d'),
             modifier: KeyModifiers::NONE,
-        }, Key {
+        }], "show_diagnostic".to_string());
+
+        normal_keybindings.insert(vec![leader, Key {
             key: KeyCode::Char('g'),
             modifier: KeyModifiers::NONE,
         }, Key {
@@ -441,10 +650,7 @@ impl Settings {
             key: KeyCode::Char('d'),
             modifier: KeyModifiers::NONE,
         }], "goto_definition".to_string());
-        normal_keybindings.insert(vec![Key {
-            key: KeyCode::Char('\\'),
-            modifier: KeyModifiers::NONE,
-        }, Key {
+        normal_keybindings.insert(vec![leader, Key {
             key: KeyCode::Char('g'),
             modifier: KeyModifiers::NONE,
         }, Key {
@@ -454,10 +660,7 @@ impl Settings {
             key: KeyCode::Char('D'),
             modifier: KeyModifiers::NONE,
         }], "goto_declaration".to_string());
-        normal_keybindings.insert(vec![Key {
-            key: KeyCode::Char('\\'),
-            modifier: KeyModifiers::NONE,
-        }, Key {
+        normal_keybindings.insert(vec![leader, Key {
             key: KeyCode::Char('g'),
             modifier: KeyModifiers::NONE,
         }, Key {
@@ -467,10 +670,7 @@ impl Settings {
             key: KeyCode::Char('t'),
             modifier: KeyModifiers::NONE,
         }], "goto_type_definition".to_string());
-        normal_keybindings.insert(vec![Key {
-            key: KeyCode::Char('\\'),
-            modifier: KeyModifiers::NONE,
-        }, Key {
+        normal_keybindings.insert(vec![leader, Key {
             key: KeyCode::Char('g'),
             modifier: KeyModifiers::NONE,
         }, Key {
@@ -480,6 +680,41 @@ impl Settings {
             key: KeyCode::Char('i'),
             modifier: KeyModifiers::NONE,
         }], "goto_implementation".to_string());
+        normal_keybindings.insert(vec![leader, Key {
+            key: KeyCode::Char('g'),
+            modifier: KeyModifiers::NONE,
+        }, Key {
+            key: KeyCode::Char('r'),
+            modifier: KeyModifiers::NONE,
+        }], "find_references".to_string());
+        normal_keybindings.insert(vec![leader, Key {
+            key: KeyCode::Char('r'),
+            modifier: KeyModifiers::NONE,
+        }, Key {
+            key: KeyCode::Char('n'),
+            modifier: KeyModifiers::NONE,
+        }], "rename".to_string());
+        normal_keybindings.insert(vec![leader, Key {
+            key: KeyCode::Char('a'),
+            modifier: KeyModifiers::NONE,
+        }], "code_action".to_string());
+        normal_keybindings.insert(vec![leader, Key {
+            key: KeyCode::Char('f'),
+            modifier: KeyModifiers::NONE,
+        }], "find_file".to_string());
+        normal_keybindings.insert(vec![Key {
+            key: KeyCode::Char('p'),
+            modifier: KeyModifiers::CONTROL,
+        }], "find_file".to_string());
+        // Terminals send Ctrl-^ as Ctrl-6
+        normal_keybindings.insert(vec![Key {
+            key: KeyCode::Char('6'),
+            modifier: KeyModifiers::CONTROL,
+        }], "alternate_buffer".to_string());
+        normal_keybindings.insert(vec![Key {
+            key: KeyCode::Char('^'),
+            modifier: KeyModifiers::CONTROL,
+        }], "alternate_buffer".to_string());
 
         normal_keybindings.insert(vec![Key {
             key: KeyCode::Char('p'),
@@ -496,6 +731,110 @@ impl Settings {
             key: KeyCode::Char('y'),
             modifier: KeyModifiers::NONE,
         }], "copy_line".to_string());
+
+        normal_keybindings.insert(vec![Key {
+            key: KeyCode::Char(']'),
+            modifier: KeyModifiers::NONE,
+        }, Key {
+            key: KeyCode::Char('s'),
+            modifier: KeyModifiers::NONE,
+        }], "spell_next".to_string());
+        normal_keybindings.insert(vec![Key {
+            key: KeyCode::Char('['),
+            modifier: KeyModifiers::NONE,
+        }, Key {
+            key: KeyCode::Char('s'),
+            modifier: KeyModifiers::NONE,
+        }], "spell_prev".to_string());
+        normal_keybindings.insert(vec![Key {
+            key: KeyCode::Char(']'),
+            modifier: KeyModifiers::NONE,
+        }, Key {
+            key: KeyCode::Char('d'),
+            modifier: KeyModifiers::NONE,
+        }], "diagnostic_next".to_string());
+        normal_keybindings.insert(vec![Key {
+            key: KeyCode::Char('['),
+            modifier: KeyModifiers::NONE,
+        }, Key {
+            key: KeyCode::Char('d'),
+            modifier: KeyModifiers::NONE,
+        }], "diagnostic_prev".to_string());
+        normal_keybindings.insert(vec![Key {
+            key: KeyCode::Char(']'),
+            modifier: KeyModifiers::NONE,
+        }, Key {
+            key: KeyCode::Char('q'),
+            modifier: KeyModifiers::NONE,
+        }], "cnext".to_string());
+        normal_keybindings.insert(vec![Key {
+            key: KeyCode::Char('['),
+            modifier: KeyModifiers::NONE,
+        }, Key {
+            key: KeyCode::Char('q'),
+            modifier: KeyModifiers::NONE,
+        }], "cprevious".to_string());
+        normal_keybindings.insert(vec![Key {
+            key: KeyCode::Char('K'),
+            modifier: KeyModifiers::NONE,
+        }], "hover".to_string());
+        normal_keybindings.insert(vec![Key {
+            key: KeyCode::Char('z'),
+            modifier: KeyModifiers::NONE,
+        }, Key {
+            key: KeyCode::Char('='),
+            modifier: KeyModifiers::NONE,
+        }], "spell_suggest".to_string());
+        normal_keybindings.insert(vec![Key {
+            key: KeyCode::Char('z'),
+            modifier: KeyModifiers::NONE,
+        }, Key {
+            key: KeyCode::Char('z'),
+            modifier: KeyModifiers::NONE,
+        }], "line_center".to_string());
+        normal_keybindings.insert(vec![Key {
+            key: KeyCode::Char('z'),
+            modifier: KeyModifiers::NONE,
+        }, Key {
+            key: KeyCode::Char('t'),
+            modifier: KeyModifiers::NONE,
+        }], "line_top".to_string());
+        normal_keybindings.insert(vec![Key {
+            key: KeyCode::Char('z'),
+            modifier: KeyModifiers::NONE,
+        }, Key {
+            key: KeyCode::Char('b'),
+            modifier: KeyModifiers::NONE,
+        }], "line_bottom".to_string());
+        normal_keybindings.insert(vec![Key {
+            key: KeyCode::Char('g'),
+            modifier: KeyModifiers::NONE,
+        }, Key {
+            key: KeyCode::Char('g'),
+            modifier: KeyModifiers::CONTROL,
+        }], "file_info".to_string());
+        normal_keybindings.insert(vec![Key {
+            key: KeyCode::Char('g'),
+            modifier: KeyModifiers::NONE,
+        }, Key {
+            key: KeyCode::Char('d'),
+            modifier: KeyModifiers::NONE,
+        }], "local_definition".to_string());
+        normal_keybindings.insert(vec![Key {
+            key: KeyCode::Char('g'),
+            modifier: KeyModifiers::NONE,
+        }, Key {
+            key: KeyCode::Char('D'),
+            modifier: KeyModifiers::NONE,
+        }], "local_declaration".to_string());
+        normal_keybindings.insert(vec![Key {
+            key: KeyCode::Char('q'),
+            modifier: KeyModifiers::NONE,
+        }], "record_macro".to_string());
+        normal_keybindings.insert(vec![Key {
+            key: KeyCode::Char('@'),
+            modifier: KeyModifiers::NONE,
+        }], "play_macro".to_string());
         
                                        
 
@@ -626,12 +965,158 @@ impl Settings {
         command_keybindings.insert(vec![Key {
             key: KeyCode::Up,
             modifier: KeyModifiers::NONE,
-        }], "start".to_string());
+        }], "history_previous".to_string());
         command_keybindings.insert(vec![Key {
             key: KeyCode::Down,
             modifier: KeyModifiers::NONE,
+        }], "history_next".to_string());
+        command_keybindings.insert(vec![Key {
+            key: KeyCode::Home,
+            modifier: KeyModifiers::NONE,
+        }], "start".to_string());
+        command_keybindings.insert(vec![Key {
+            key: KeyCode::End,
+            modifier: KeyModifiers::NONE,
         }], "end".to_string());
+        command_keybindings.insert(vec![Key {
+            key: KeyCode::Tab,
+            modifier: KeyModifiers::NONE,
+        }], "complete_next".to_string());
+        command_keybindings.insert(vec![Key {
+            key: KeyCode::BackTab,
+            modifier: KeyModifiers::SHIFT,
+        }], "complete_previous".to_string());
+
+    }
 
+    fn generate_visual_keybindings(visual_keybindings: &mut HashMap<Keys, Command>) {
+        visual_keybindings.insert(vec![Key {
+            key: KeyCode::Esc,
+            modifier: KeyModifiers::NONE,
+        }], "leave".to_string());
+        visual_keybindings.insert(vec![Key {
+            key: KeyCode::Char('v'),
+            modifier: KeyModifiers::NONE,
+        }], "start_visual".to_string());
+        visual_keybindings.insert(vec![Key {
+            key: KeyCode::Char('V'),
+            modifier: KeyModifiers::NONE,
+        }], "start_visual_line".to_string());
+        visual_keybindings.insert(vec![Key {
+            key: KeyCode::Char('v'),
+            modifier: KeyModifiers::CONTROL,
+        }], "start_visual_block".to_string());
+        visual_keybindings.insert(vec![Key {
+            key: KeyCode::Char('l'),
+            modifier: KeyModifiers::NONE,
+        }], "right".to_string());
+        visual_keybindings.insert(vec![Key {
+            key: KeyCode::Right,
+            modifier: KeyModifiers::NONE,
+        }], "right".to_string());
+        visual_keybindings.insert(vec![Key {
+            key: KeyCode::Char('h'),
+            modifier: KeyModifiers::NONE,
+        }], "left".to_string());
+        visual_keybindings.insert(vec![Key {
+            key: KeyCode::Left,
+            modifier: KeyModifiers::NONE,
+        }], "left".to_string());
+        visual_keybindings.insert(vec![Key {
+            key: KeyCode::Char('j'),
+            modifier: KeyModifiers::NONE,
+        }], "down".to_string());
+        visual_keybindings.insert(vec![Key {
+            key: KeyCode::Down,
+            modifier: KeyModifiers::NONE,
+        }], "down".to_string());
+        visual_keybindings.insert(vec![Key {
+            key: KeyCode::Char('k'),
+            modifier: KeyModifiers::NONE,
+        }], "up".to_string());
+        visual_keybindings.insert(vec![Key {
+            key: KeyCode::Up,
+            modifier: KeyModifiers::NONE,
+        }], "up".to_string());
+        visual_keybindings.insert(vec![Key {
+            key: KeyCode::Char('0'),
+            modifier: KeyModifiers::NONE,
+        }], "line_start".to_string());
+        visual_keybindings.insert(vec![Key {
+            key: KeyCode::Char('$'),
+            modifier: KeyModifiers::NONE,
+        }], "line_end".to_string());
+        visual_keybindings.insert(vec![Key {
+            key: KeyCode::Home,
+            modifier: KeyModifiers::NONE,
+        }], "line_start".to_string());
+        visual_keybindings.insert(vec![Key {
+            key: KeyCode::End,
+            modifier: KeyModifiers::NONE,
+        }], "line_end".to_string());
+        visual_keybindings.insert(vec![Key {
+            key: KeyCode::Char('^'),
+            modifier: KeyModifiers::NONE,
+        }], "first_non_blank".to_string());
+        visual_keybindings.insert(vec![Key {
+            key: KeyCode::Char('w'),
+            modifier: KeyModifiers::NONE,
+        }], "word_start_forward".to_string());
+        visual_keybindings.insert(vec![Key {
+            key: KeyCode::Char('b'),
+            modifier: KeyModifiers::NONE,
+        }], "word_start_backward".to_string());
+        visual_keybindings.insert(vec![Key {
+            key: KeyCode::Char('e'),
+            modifier: KeyModifiers::NONE,
+        }], "word_end_forward".to_string());
+        visual_keybindings.insert(vec![Key {
+            key: KeyCode::Char('G'),
+            modifier: KeyModifiers::NONE,
+        }], "file_bottom".to_string());
+        visual_keybindings.insert(vec![Key {
+            key: KeyCode::PageUp,
+            modifier: KeyModifiers::NONE,
+        }], "page_up".to_string());
+        visual_keybindings.insert(vec![Key {
+            key: KeyCode::PageDown,
+            modifier: KeyModifiers::NONE,
+        }], "page_down".to_string());
+        visual_keybindings.insert(vec![Key {
+            key: KeyCode::Char('d'),
+            modifier: KeyModifiers::NONE,
+        }], "delete".to_string());
+        visual_keybindings.insert(vec![Key {
+            key: KeyCode::Char('x'),
+            modifier: KeyModifiers::NONE,
+        }], "delete".to_string());
+        visual_keybindings.insert(vec![Key {
+            key: KeyCode::Char('y'),
+            modifier: KeyModifiers::NONE,
+        }], "copy".to_string());
+        visual_keybindings.insert(vec![Key {
+            key: KeyCode::Char('c'),
+            modifier: KeyModifiers::NONE,
+        }], "change".to_string());
+        visual_keybindings.insert(vec![Key {
+            key: KeyCode::Char('>'),
+            modifier: KeyModifiers::NONE,
+        }], "indent".to_string());
+        visual_keybindings.insert(vec![Key {
+            key: KeyCode::Char('<'),
+            modifier: KeyModifiers::NONE,
+        }], "dedent".to_string());
+        visual_keybindings.insert(vec![Key {
+            key: KeyCode::Char('='),
+            modifier: KeyModifiers::NONE,
+        }], "format_selection".to_string());
+        visual_keybindings.insert(vec![Key {
+            key: KeyCode::Char('g'),
+            modifier: KeyModifiers::NONE,
+        }, Key {
+            key: KeyCode::Char('g'),
+            modifier: KeyModifiers::NONE,
+        }], "file_top".to_string());
     }
 
     fn generate_prompt_keybindings(prompt_keybindings: &mut HashMap<Keys, Command>) {
@@ -682,6 +1167,171 @@ impl Settings {
     }
 }
 
+impl Settings {
+    /// The pairs to close for a language, falling back on the default pairs.
+    pub fn get_auto_pairs(&self, language: Option<&str>) -> &[(char, char)] {
+        language.and_then(|language| self.auto_pairs.get(language))
+            .or_else(|| self.auto_pairs.get("default"))
+            .map(|pairs| pairs.as_slice())
+            .unwrap_or(&[])
+    }
+
+    /// Finds every binding that starts with another whole binding in the same mode.
+    pub fn find_conflicts(&self) -> Vec<BindingConflict> {
+        let mut conflicts = Vec::new();
+
+        for (mode, bindings) in self.mode_keybindings.iter() {
+            for (shorter, shorter_command) in bindings.iter() {
+                for (longer, longer_command) in bindings.iter() {
+                    if longer.len() > shorter.len() && longer.starts_with(shorter) {
+                        conflicts.push(BindingConflict {
+                            mode: mode.clone(),
+                            shorter: (shorter.clone(), shorter_command.clone()),
+                            longer: (longer.clone(), longer_command.clone()),
+                        });
+                    }
+                }
+            }
+        }
+
+        conflicts.sort_by_key(|conflict| conflict.to_string());
+        conflicts
+    }
+
+    /// Moves every Normal mode binding that starts with the current leader over to a new leader.
+    pub fn set_leader(&mut self, leader: Key) {
+        let old_leader = self.editor_settings.leader;
+        self.editor_settings.leader = leader;
+
+        if old_leader == leader {
+            return;
+        }
+
+        for (name, keybindings) in self.mode_keybindings.iter_mut() {
+            if !name.eq_ignore_ascii_case("normal") {
+                continue;
+            }
+
+            let moved = keybindings.keys()
+                .filter(|keys| keys.len() > 1 && keys[0] == old_leader)
+                .cloned()
+                .collect::<Vec<Keys>>();
+
+            for mut keys in moved {
+                let command = keybindings.remove(&keys).expect("binding was not found");
+                keys[0] = leader;
+                keybindings.insert(keys, command);
+            }
+            // The key on its own would run before any binding that starts with it could be typed
+            keybindings.remove(&vec![leader]);
+        }
+    }
+}
+
+/// The leader that the default bindings are written with.
+pub const DEFAULT_LEADER: Key = Key {
+    key: KeyCode::Char('\\'),
+    modifier: KeyModifiers::NONE,
+};
+
+/// Reads a leader key like `\\` or `space`.
+pub fn parse_leader(name: &str) -> Result<Key, String> {
+    let key = match name {
+        "space" | " " => KeyCode::Char(' '),
+        "tab" => KeyCode::Tab,
+        name => {
+            let mut chars = name.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => KeyCode::Char(c),
+                _ => return Err(format!("Invalid leader: {}", name)),
+            }
+        },
+    };
+
+    Ok(Key {
+        key,
+        modifier: KeyModifiers::NONE,
+    })
+}
+
+/// Reads keys written like `C-w s`, with spaces between the keys.
+/// A key can have `C-` for control, `M-` or `A-` for alt and `S-` for shift in front of it.
+/// Keys that aren't a single character go by their names, like `esc`, `space` or `leader` for the given leader.
+pub fn parse_key_notation(notation: &str, leader: Key) -> Result<Keys, String> {
+    let keys = notation.split_whitespace()
+        .map(|name| parse_key_name(name, leader))
+        .collect::<Result<Keys, String>>()?;
+
+    if keys.is_empty() {
+        return Err("No keys given".to_string());
+    }
+    Ok(keys)
+}
+
+fn parse_key_name(name: &str, leader: Key) -> Result<Key, String> {
+    let mut modifier = KeyModifiers::NONE;
+    let mut rest = name;
+    while rest.len() > 2 && rest.as_bytes()[1] == b'-' {
+        modifier |= match rest.as_bytes()[0].to_ascii_uppercase() {
+            b'C' => KeyModifiers::CONTROL,
+            b'M' | b'A' => KeyModifiers::ALT,
+            b'S' => KeyModifiers::SHIFT,
+            _ => break,
+        };
+        rest = &rest[2..];
+    }
+
+    let mut chars = rest.chars();
+    let key = match (chars.next(), chars.next()) {
+        (Some(c), None) => KeyCode::Char(c),
+        _ => match rest.to_ascii_lowercase().as_str() {
+            "leader" if modifier.is_empty() => return Ok(leader),
+            "space" => KeyCode::Char(' '),
+            "lt" => KeyCode::Char('<'),
+            "enter" | "cr" | "return" => KeyCode::Enter,
+            "esc" | "escape" => KeyCode::Esc,
+            "tab" => KeyCode::Tab,
+            "backtab" | "back-tab" => KeyCode::BackTab,
+            "backspace" | "bs" => KeyCode::Backspace,
+            "delete" | "del" => KeyCode::Delete,
+            "insert" => KeyCode::Insert,
+            "left" => KeyCode::Left,
+            "right" => KeyCode::Right,
+            "up" => KeyCode::Up,
+            "down" => KeyCode::Down,
+            "home" => KeyCode::Home,
+            "end" => KeyCode::End,
+            "pageup" | "page-up" => KeyCode::PageUp,
+            "pagedown" | "page-down" => KeyCode::PageDown,
+            lower => match lower.strip_prefix('f').and_then(|number| number.parse::<u8>().ok()) {
+                Some(number @ 1..=24) => KeyCode::F(number),
+                _ => return Err(format!("Unknown key: {}", name)),
+            },
+        },
+    };
+
+    // A shifted letter comes in as the capital letter without the shift
+    let key = match key {
+        KeyCode::Char(c) if modifier == KeyModifiers::SHIFT && c.is_ascii_lowercase() => {
+            modifier = KeyModifiers::NONE;
+            KeyCode::Char(c.to_ascii_uppercase())
+        },
+        key => key,
+    };
+
+    Ok(Key { key, modifier })
+}
+
+fn deserialize_leader<'de, D>(deserializer: D) -> Result<Key, D::Error> where D: serde::Deserializer<'de> {
+    let name = String::deserialize(deserializer)?;
+    parse_leader(&name).map_err(serde::de::Error::custom)
+}
+
+fn deserialize_encoding<'de, D>(deserializer: D) -> Result<Option<&'static Encoding>, D::Error> where D: serde::Deserializer<'de> {
+    let name = String::deserialize(deserializer)?;
+    parse_encoding(&name).map(Some).map_err(serde::de::Error::custom)
+}
+
 impl Default for Settings {
     fn default() -> Self {
         let editor_settings = EditorSettings::default();
@@ -689,7 +1339,7 @@ impl Default for Settings {
 
         let mut normal_keybindings = HashMap::new();
 
-        Self::generate_normal_keybindings(&mut normal_keybindings);
+        Self::generate_normal_keybindings(&mut normal_keybindings, editor_settings.leader);
 
         
         let mut insert_keybindings = HashMap::new();
@@ -706,6 +1356,12 @@ impl Default for Settings {
         mode_keybindings.insert("Insert".to_string(), insert_keybindings);
         mode_keybindings.insert("Command".to_string(), command_keybindings);
 
+        let mut visual_keybindings = HashMap::new();
+
+        Self::generate_visual_keybindings(&mut visual_keybindings);
+
+        mode_keybindings.insert("Visual".to_string(), visual_keybindings);
+
         let mut prompt_keybindings = HashMap::new();
 
         Self::generate_prompt_keybindings(&mut prompt_keybindings);
@@ -724,11 +1380,26 @@ impl Default for Settings {
             editor_settings,
             mode_keybindings,
             colors,
+            auto_pairs: default_auto_pairs(),
+            undo_dir: None,
+            backup_dir: None,
+            lsp_servers: HashMap::new(),
         }
     }
 }
 
+fn default_auto_pairs() -> HashMap<String, Vec<(char, char)>> {
+    let mut auto_pairs = HashMap::new();
+
+    auto_pairs.insert("default".to_string(), vec![('(', ')'), ('{', '}'), ('[', ']'), ('"', '"'), ('\'', '\'')]);
+    // Single quotes start lifetimes in Rust
+    auto_pairs.insert("rust".to_string(), vec![('(', ')'), ('{', '}'), ('[', ']'), ('"', '"')]);
+
+    auto_pairs
+}
+
 #[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(default)]
 pub struct EditorSettings {
     pub line_number: bool,
     pub relative_line_number: bool,
@@ -739,6 +1410,240 @@ pub struct EditorSettings {
     pub minimum_width: usize,
     pub minimum_height: usize,
     pub rainbow_delimiters: bool,
+    /// Whether typing an opening bracket or quote also types the closing one.
+    #[serde(alias = "auto_pairs")]
+    pub autopairs: bool,
+    /// Whether or not to check the spelling of comments and strings.
+    pub spell: bool,
+    /// Which register yanks and pastes use when no register is given.
+    pub clipboard: ClipboardSetting,
+    /// What to do with the newline at the end of the file when saving.
+    pub final_newline: FinalNewline,
+    /// Whether or not to show the possible continuations of a partly typed keybinding.
+    pub which_key: bool,
+    /// How long to wait in milliseconds before showing the continuations.
+    pub which_key_delay: u64,
+    /// Whether or not to show what a substitute will do while it is being typed.
+    pub inccommand: bool,
+    /// The column lines should be wrapped at, 0 turns it off.
+    pub textwidth: usize,
+    /// The column to draw a line down to show the line length limit.
+    #[serde(alias = "color_column")]
+    pub colorcolumn: Option<ColorColumn>,
+    /// Whether or not to tint the line with the cursor.
+    pub cursorline: bool,
+    /// Whether or not to highlight lines indented with tabs when using spaces or the other way around.
+    pub indentcheck: bool,
+    /// The key that starts most of the longer bindings.
+    #[serde(deserialize_with = "deserialize_leader")]
+    pub leader: Key,
+    /// Where the cursor can move past the end of a line.
+    pub virtualedit: VirtualEdit,
+    /// Whether or not to use the settings from `.editorconfig` files.
+    pub editorconfig: bool,
+    /// The line ending to write files with.
+    pub line_ending: LineEnding,
+    /// Whether or not to remove the whitespace at the end of lines when saving.
+    pub trim_trailing_whitespace: bool,
+    /// Whether or not to show the keys of a command that is still being typed.
+    pub showcmd: bool,
+    /// The encoding to read files without a byte order mark with. `None` means UTF-8.
+    /// Files are written back in the encoding they were read with.
+    #[serde(deserialize_with = "deserialize_encoding")]
+    pub fileencoding: Option<&'static Encoding>,
+    /// Whether or not `0` goes to the first character that isn't blank before the start of the line.
+    pub smarthome: bool,
+    /// Whether or not to show the whitespace at the end of lines.
+    pub list: bool,
+    /// Whether or not to show tabs, non-breaking spaces and the whitespace at the end of lines.
+    pub show_whitespace: bool,
+    /// How the line with the cursor gets drawn.
+    pub cursorlineopt: CursorLineOpt,
+    /// Whether or not searching past the last match starts over at the other end of the file.
+    pub wrapscan: bool,
+    /// How long in milliseconds a message stays on the message line.
+    pub message_timeout: u64,
+    /// Whether or not the undo history is saved with the file and loaded when it is opened again.
+    pub persistent_undo: bool,
+    /// Whether or not saving keeps what the file had before in a copy with a `~` after its name.
+    pub backup: bool,
+    /// How many seconds after the last change a file is saved by itself. `None` turns it off.
+    pub auto_save: Option<u64>,
+    /// Whether or not files are saved when the terminal loses focus.
+    pub auto_save_on_focus_lost: bool,
+    /// Whether or not auto-save also saves in the middle of typing in Insert mode.
+    pub auto_save_in_insert: bool,
+    /// How long in milliseconds to wait for the language server to answer before giving up.
+    pub lsp_timeout: u64,
+    /// How long in milliseconds to wait after an edit before telling the language server,
+    /// so that quick typing is sent as one change.
+    pub lsp_change_delay: u64,
+    /// Whether or not completions are asked for while typing in Insert mode,
+    /// after a trigger character of the language server or enough of an identifier.
+    pub auto_completion: bool,
+    /// How much of an identifier has to be typed before completions are asked for by themselves.
+    /// 0 only asks after trigger characters.
+    pub completion_min_prefix: usize,
+    /// Whether or not completing a path offers the files whose names start with a dot.
+    pub show_hidden_files: bool,
+    /// How many commands the command prompt remembers.
+    pub history_size: usize,
+    /// Whether or not the commands are saved for the next session.
+    pub save_history: bool,
+    /// Whether or not the mouse can click and scroll in the editor.
+    /// Turning it off leaves the mouse to the terminal, so text can be selected there.
+    pub mouse: bool,
+    /// How many lines a turn of the mouse wheel scrolls.
+    pub mouse_scroll: usize,
+    /// How many lines to keep visible above and below the cursor when scrolling.
+    pub scroll_off: usize,
+    /// Files with more bytes than this get parsed on another thread, so their highlighting can lag a little behind typing.
+    pub background_parse_size: usize,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LineEnding {
+    /// Write the line endings the way they are in the buffer.
+    Keep,
+    Lf,
+    Crlf,
+    Cr,
+}
+
+impl LineEnding {
+    pub fn apply(&self, text: String) -> String {
+        let ending = match self {
+            LineEnding::Keep => return text,
+            LineEnding::Lf => "\n",
+            LineEnding::Crlf => "\r\n",
+            LineEnding::Cr => "\r",
+        };
+
+        let text = text.replace("\r\n", "\n");
+        if ending == "\n" {
+            text
+        }
+        else {
+            text.replace('\n', ending)
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CursorLineOpt {
+    /// The line with the cursor is drawn like every other line.
+    Both,
+    /// The line with the cursor doesn't show its trailing whitespace so typing doesn't leave dots behind.
+    NoTrail,
+}
+
+impl CursorLineOpt {
+    /// Whether or not a line should show its trailing whitespace.
+    pub fn shows_trailing(&self, is_cursor_line: bool) -> bool {
+        !is_cursor_line || *self == CursorLineOpt::Both
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum VirtualEdit {
+    /// The cursor always stays on the text of a line.
+    None,
+    /// The cursor can go past the end of a line while selecting a block.
+    Block,
+    /// The cursor can go past the end of a line in every mode.
+    All,
+}
+
+impl VirtualEdit {
+    /// Whether the cursor can go past the end of a line, given whether a block is being selected.
+    pub fn allows(&self, selecting_block: bool) -> bool {
+        match self {
+            VirtualEdit::None => false,
+            VirtualEdit::Block => selecting_block,
+            VirtualEdit::All => true,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ClipboardSetting {
+    /// Use the editor's own unnamed register.
+    Unnamed,
+    /// Use the system clipboard.
+    UnnamedPlus,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FinalNewline {
+    /// Remove any newlines at the end of the file.
+    Trim,
+    /// Add a newline to the end of the file if there isn't one.
+    Ensure,
+    /// Leave the file as is.
+    Keep,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "ColorColumnValue")]
+pub enum ColorColumn {
+    /// A column counting from 1.
+    Column(usize),
+    /// A column relative to `textwidth`, written like `+1` or `-2`.
+    TextWidth(isize),
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ColorColumnValue {
+    Number(usize),
+    Text(String),
+}
+
+impl TryFrom<ColorColumnValue> for ColorColumn {
+    type Error = String;
+
+    fn try_from(value: ColorColumnValue) -> Result<Self, Self::Error> {
+        match value {
+            ColorColumnValue::Number(column) => Ok(ColorColumn::Column(column)),
+            ColorColumnValue::Text(text) => ColorColumn::parse(&text),
+        }
+    }
+}
+
+impl ColorColumn {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let invalid = |_| format!("Invalid colorcolumn: {}", value);
+
+        if value.starts_with('+') || value.starts_with('-') {
+            value.parse::<isize>().map(ColorColumn::TextWidth).map_err(invalid)
+        }
+        else {
+            value.parse::<usize>().map(ColorColumn::Column).map_err(invalid)
+        }
+    }
+}
+
+impl FinalNewline {
+    pub fn apply(&self, mut text: String) -> String {
+        match self {
+            FinalNewline::Trim => {
+                let len = text.trim_end_matches(['\n', '\r']).len();
+                text.truncate(len);
+            },
+            FinalNewline::Ensure => {
+                if !text.is_empty() && !text.ends_with('\n') {
+                    text.push('\n');
+                }
+            },
+            FinalNewline::Keep => {},
+        }
+        text
+    }
 }
 
 impl Default for EditorSettings {
@@ -753,7 +1658,193 @@ impl Default for EditorSettings {
             minimum_width: 24,
             minimum_height: 1,
             rainbow_delimiters: true,
+            autopairs: true,
+            spell: false,
+            clipboard: ClipboardSetting::Unnamed,
+            final_newline: FinalNewline::Keep,
+            which_key: true,
+            which_key_delay: 500,
+            inccommand: false,
+            textwidth: 0,
+            colorcolumn: None,
+            cursorline: false,
+            indentcheck: false,
+            leader: DEFAULT_LEADER,
+            virtualedit: VirtualEdit::None,
+            editorconfig: true,
+            line_ending: LineEnding::Keep,
+            trim_trailing_whitespace: false,
+            showcmd: true,
+            fileencoding: None,
+            smarthome: true,
+            list: false,
+            show_whitespace: false,
+            cursorlineopt: CursorLineOpt::Both,
+            wrapscan: true,
+            message_timeout: 5000,
+            persistent_undo: false,
+            backup: false,
+            auto_save: None,
+            auto_save_on_focus_lost: false,
+            auto_save_in_insert: false,
+            lsp_timeout: 5000,
+            lsp_change_delay: 150,
+            auto_completion: true,
+            completion_min_prefix: 3,
+            show_hidden_files: false,
+            history_size: 200,
+            save_history: true,
+            mouse: true,
+            mouse_scroll: 3,
+            scroll_off: 0,
+            background_parse_size: 512 * 1024,
+        }
+    }
+}
+
+impl EditorSettings {
+    /// Turns the text of a buffer into what gets written to its file.
+    pub fn prepare_save(&self, text: String) -> String {
+        let text = if self.trim_trailing_whitespace {
+            text.split_inclusive('\n')
+                .map(|line| {
+                    let content = line.trim_end_matches(['\n', '\r']);
+                    let ending = &line[content.len()..];
+                    format!("{}{}", content.trim_end_matches([' ', '\t']), ending)
+                })
+                .collect()
+        }
+        else {
+            text
+        };
+
+        self.line_ending.apply(self.final_newline.apply(text))
+    }
+
+    /// The column to draw the color column at, counting from 1.
+    /// A column relative to `textwidth` is only drawn when `textwidth` is set.
+    pub fn color_column(&self) -> Option<usize> {
+        match self.colorcolumn? {
+            ColorColumn::Column(0) => None,
+            ColorColumn::Column(column) => Some(column),
+            ColorColumn::TextWidth(_) if self.textwidth == 0 => None,
+            ColorColumn::TextWidth(offset) => {
+                let column = self.textwidth as isize + offset;
+                if column > 0 {
+                    Some(column as usize)
+                }
+                else {
+                    None
+                }
+            },
+        }
+    }
+
+    /// Changes a setting from a string like the ones given to `:set`.
+    /// Booleans can be turned on with `name` and turned off with `noname`.
+    /// Numbers are set with `name=value`.
+    pub fn set_option(&mut self, option: &str) -> Result<(), String> {
+        let option = option.trim();
+
+        if let Some((name, value)) = option.split_once('=') {
+            let value = value.trim();
+            let number = |value: &str| value.parse::<usize>().map_err(|_| format!("Invalid number: {}", value));
+            match name.trim() {
+                "tab_size" | "tabstop" | "ts" => self.tab_size = number(value)?,
+                "key_timeout" => self.key_timeout = number(value)? as u64,
+                "which_key_delay" => self.which_key_delay = number(value)? as u64,
+                "message_timeout" => self.message_timeout = number(value)? as u64,
+                "lsp_timeout" => self.lsp_timeout = number(value)? as u64,
+                "lsp_change_delay" => self.lsp_change_delay = number(value)? as u64,
+                "completion_min_prefix" => self.completion_min_prefix = number(value)?,
+                "mouse_scroll" => self.mouse_scroll = number(value)?,
+                "scroll_off" | "scrolloff" | "so" => self.scroll_off = number(value)?,
+                "background_parse_size" => self.background_parse_size = number(value)?,
+                "auto_save" => match number(value)? {
+                    0 => self.auto_save = None,
+                    seconds => self.auto_save = Some(seconds as u64),
+                },
+                "history_size" | "history" | "hi" => self.history_size = number(value)?,
+                "minimum_width" => self.minimum_width = number(value)?,
+                "minimum_height" => self.minimum_height = number(value)?,
+                "textwidth" | "tw" => self.textwidth = number(value)?,
+                "colorcolumn" | "color_column" | "cc" => match value {
+                    "" => self.colorcolumn = None,
+                    value => self.colorcolumn = Some(ColorColumn::parse(value)?),
+                },
+                "clipboard" => match value {
+                    "unnamed" | "" => self.clipboard = ClipboardSetting::Unnamed,
+                    "unnamedplus" => self.clipboard = ClipboardSetting::UnnamedPlus,
+                    value => return Err(format!("Invalid clipboard: {}", value)),
+                },
+                "final_newline" => match value {
+                    "trim" => self.final_newline = FinalNewline::Trim,
+                    "ensure" => self.final_newline = FinalNewline::Ensure,
+                    "keep" => self.final_newline = FinalNewline::Keep,
+                    value => return Err(format!("Invalid final_newline: {}", value)),
+                },
+                "line_ending" | "fileformat" | "ff" => match value {
+                    "keep" | "" => self.line_ending = LineEnding::Keep,
+                    "lf" | "unix" => self.line_ending = LineEnding::Lf,
+                    "crlf" | "dos" => self.line_ending = LineEnding::Crlf,
+                    "cr" | "mac" => self.line_ending = LineEnding::Cr,
+                    value => return Err(format!("Invalid line_ending: {}", value)),
+                },
+                "fileencoding" | "fenc" => match value {
+                    "" => self.fileencoding = None,
+                    value => self.fileencoding = Some(parse_encoding(value)?),
+                },
+                "cursorlineopt" | "culopt" => match value {
+                    "both" | "" => self.cursorlineopt = CursorLineOpt::Both,
+                    "notrail" => self.cursorlineopt = CursorLineOpt::NoTrail,
+                    value => return Err(format!("Invalid cursorlineopt: {}", value)),
+                },
+                "virtualedit" | "ve" => match value {
+                    "none" | "" => self.virtualedit = VirtualEdit::None,
+                    "block" => self.virtualedit = VirtualEdit::Block,
+                    "all" => self.virtualedit = VirtualEdit::All,
+                    value => return Err(format!("Invalid virtualedit: {}", value)),
+                },
+                name => return Err(format!("Unknown option: {}", name)),
+            }
+            return Ok(());
         }
+
+        let (name, value) = match option.strip_prefix("no") {
+            Some(name) => (name, false),
+            None => (option, true),
+        };
+
+        match name {
+            "spell" => self.spell = value,
+            "line_number" | "number" | "nu" => self.line_number = value,
+            "relative_line_number" | "relativenumber" | "rnu" => self.relative_line_number = value,
+            "use_spaces" | "expandtab" | "et" => self.use_spaces = value,
+            "border" => self.border = value,
+            "rainbow_delimiters" => self.rainbow_delimiters = value,
+            "autopairs" | "auto_pairs" => self.autopairs = value,
+            "which_key" => self.which_key = value,
+            "inccommand" => self.inccommand = value,
+            "indentcheck" => self.indentcheck = value,
+            "editorconfig" => self.editorconfig = value,
+            "trim_trailing_whitespace" => self.trim_trailing_whitespace = value,
+            "showcmd" | "sc" => self.showcmd = value,
+            "smarthome" => self.smarthome = value,
+            "list" => self.list = value,
+            "cursorline" | "cul" => self.cursorline = value,
+            "show_whitespace" => self.show_whitespace = value,
+            "wrapscan" | "ws" => self.wrapscan = value,
+            "persistent_undo" | "undofile" | "udf" => self.persistent_undo = value,
+            "backup" | "bk" => self.backup = value,
+            "auto_save_on_focus_lost" => self.auto_save_on_focus_lost = value,
+            "auto_save_in_insert" => self.auto_save_in_insert = value,
+            "auto_completion" => self.auto_completion = value,
+            "show_hidden_files" => self.show_hidden_files = value,
+            "save_history" => self.save_history = value,
+            "mouse" => self.mouse = value,
+            _ => return Err(format!("Unknown option: {}", option)),
+        }
+        Ok(())
     }
 }
 
@@ -804,7 +1895,39 @@ pub struct EditorColors {
     pub mode: HashMap<String, ColorScheme>,
     /// The color scheme for treesitter nodes.
     pub treesitter: Rc<HashMap<String,HashMap<String, SyntaxHighlight>>>,
+    /// The color scheme for each capture of the highlight queries, like `keyword` for `@keyword`.
+    /// A capture like `function.method` without a color of its own uses the one of `function`.
+    pub captures: Rc<HashMap<String, ColorScheme>>,
     pub rainbow_delimiters: Vec<ColorScheme>,
+    /// The color scheme for misspelled words.
+    pub spell: ColorScheme,
+    /// The color scheme for text that a command preview changed.
+    pub preview: ColorScheme,
+    /// The color scheme for the column that shows the line length limit.
+    /// Only the background is used so the text keeps its colors.
+    pub color_column: ColorScheme,
+    /// The color scheme for the line with the cursor when `cursorline` is on.
+    /// Only the background is used so the text keeps its colors.
+    pub cursor_line: ColorScheme,
+    /// The color scheme for indentation that uses the wrong kind of whitespace.
+    pub bad_indent: ColorScheme,
+    /// The color scheme for the whitespace at the end of lines when it is shown.
+    pub trailing_whitespace: ColorScheme,
+    /// The color scheme for the tabs and non-breaking spaces drawn by `show_whitespace`.
+    pub whitespace: ColorScheme,
+    /// The color scheme for the matches of the last search.
+    /// Only the background is used so the text keeps its colors.
+    pub search: ColorScheme,
+    /// The color scheme for the text selected in visual mode.
+    /// Only the background is used so the text keeps its colors.
+    pub selection: ColorScheme,
+    /// The color scheme for the bracket under the cursor and the one that matches it.
+    /// Only the background is used so the text keeps its colors.
+    pub matching_bracket: ColorScheme,
+    /// The color schemes for the message line, one for each kind of message.
+    pub message: ColorScheme,
+    pub warning: ColorScheme,
+    pub error: ColorScheme,
 }
 
 
@@ -833,6 +1956,20 @@ impl Default for EditorColors {
             attributes: Rc::new(vec![Attribute::Bold]),
         });
 
+        mode.insert("Visual".to_string(), ColorScheme {
+            foreground_color: Color::Black,
+            background_color: Color::DarkYellow,
+            underline_color: Color::Reset,
+            attributes: Rc::new(vec![Attribute::Bold]),
+        });
+
+        mode.insert("Visual Line".to_string(), ColorScheme {
+            foreground_color: Color::Black,
+            background_color: Color::DarkYellow,
+            underline_color: Color::Reset,
+            attributes: Rc::new(vec![Attribute::Bold]),
+        });
+
         let mut treesitter = HashMap::new();
 
         Self::generate_scheme_colors(&mut treesitter);
@@ -844,6 +1981,7 @@ impl Default for EditorColors {
 
         let treesitter = Rc::new(treesitter);
 
+        let captures = Rc::new(Self::generate_capture_colors());
 
         let mut rainbow_delimiters = Vec::new();
 
@@ -903,12 +2041,121 @@ impl Default for EditorColors {
             },
             mode,
             treesitter,
+            captures,
             rainbow_delimiters,
+            spell: ColorScheme {
+                foreground_color: Color::Reset,
+                background_color: Color::Reset,
+                underline_color: Color::Cyan,
+                attributes: Rc::new(vec![Attribute::Undercurled]),
+            },
+            preview: ColorScheme {
+                foreground_color: Color::Black,
+                background_color: Color::Yellow,
+                underline_color: Color::Reset,
+                attributes: Rc::new(Vec::new()),
+            },
+            color_column: ColorScheme {
+                foreground_color: Color::Reset,
+                background_color: Color::DarkGrey,
+                underline_color: Color::Reset,
+                attributes: Rc::new(Vec::new()),
+            },
+            cursor_line: ColorScheme {
+                foreground_color: Color::Reset,
+                background_color: Color::Rgb { r: 48, g: 48, b: 48 },
+                underline_color: Color::Reset,
+                attributes: Rc::new(Vec::new()),
+            },
+            bad_indent: ColorScheme {
+                foreground_color: Color::Reset,
+                background_color: Color::DarkRed,
+                underline_color: Color::Reset,
+                attributes: Rc::new(Vec::new()),
+            },
+            trailing_whitespace: ColorScheme {
+                foreground_color: Color::DarkGrey,
+                background_color: Color::Reset,
+                underline_color: Color::Reset,
+                attributes: Rc::new(Vec::new()),
+            },
+            whitespace: ColorScheme {
+                foreground_color: Color::DarkGrey,
+                background_color: Color::Reset,
+                underline_color: Color::Reset,
+                attributes: Rc::new(Vec::new()),
+            },
+            search: ColorScheme {
+                foreground_color: Color::Reset,
+                background_color: Color::DarkYellow,
+                underline_color: Color::Reset,
+                attributes: Rc::new(Vec::new()),
+            },
+            selection: ColorScheme {
+                foreground_color: Color::Reset,
+                background_color: Color::DarkBlue,
+                underline_color: Color::Reset,
+                attributes: Rc::new(Vec::new()),
+            },
+            matching_bracket: ColorScheme {
+                foreground_color: Color::Reset,
+                background_color: Color::DarkGrey,
+                underline_color: Color::Reset,
+                attributes: Rc::new(Vec::new()),
+            },
+            message: ColorScheme {
+                foreground_color: Color::Reset,
+                background_color: Color::Reset,
+                underline_color: Color::Reset,
+                attributes: Rc::new(Vec::new()),
+            },
+            warning: ColorScheme {
+                foreground_color: Color::Yellow,
+                background_color: Color::Reset,
+                underline_color: Color::Reset,
+                attributes: Rc::new(Vec::new()),
+            },
+            error: ColorScheme {
+                foreground_color: Color::Red,
+                background_color: Color::Reset,
+                underline_color: Color::Reset,
+                attributes: Rc::new(Vec::new()),
+            },
         }
     }
 }
 
 impl EditorColors {
+    fn generate_capture_colors() -> HashMap<String, ColorScheme> {
+        let color = |foreground_color| ColorScheme {
+            foreground_color,
+            background_color: Color::Reset,
+            underline_color: Color::Reset,
+            attributes: Rc::new(Vec::new()),
+        };
+
+        let mut captures = HashMap::new();
+
+        captures.insert("keyword".to_string(), color(Color::DarkBlue));
+        captures.insert("string".to_string(), color(Color::DarkGreen));
+        captures.insert("escape".to_string(), color(Color::DarkCyan));
+        captures.insert("number".to_string(), color(Color::DarkRed));
+        captures.insert("comment".to_string(), color(Color::DarkGrey));
+        captures.insert("function".to_string(), color(Color::DarkMagenta));
+        captures.insert("function.macro".to_string(), color(Color::DarkCyan));
+        captures.insert("type".to_string(), color(Color::DarkYellow));
+        captures.insert("constructor".to_string(), color(Color::DarkYellow));
+        captures.insert("constant".to_string(), color(Color::DarkRed));
+        captures.insert("constant.builtin".to_string(), color(Color::DarkBlue));
+        captures.insert("variable.builtin".to_string(), color(Color::DarkYellow));
+        captures.insert("property".to_string(), color(Color::DarkCyan));
+        captures.insert("attribute".to_string(), color(Color::DarkCyan));
+        captures.insert("label".to_string(), color(Color::DarkRed));
+        captures.insert("namespace".to_string(), color(Color::DarkCyan));
+
+        captures
+    }
+
     fn generate_rust_colors(treesitter: &mut HashMap<String, HashMap<String, SyntaxHighlight>>) {
 
         let mut rust = HashMap::new();
@@ -2244,7 +3491,8 @@ fn parse_keybindings(table: &toml::Value, commands: &[String]) -> HashMap<Keys,
 }
 
 
-pub fn read_settings(settings_file: &str, mode_info: HashMap<String,Vec<String>>) -> Settings {
+/// Also returns what was wrong with the file and the bindings that conflict, for the caller to report.
+pub fn read_settings(settings_file: &str, mode_info: HashMap<String,Vec<String>>) -> (Settings, Vec<String>) {
     println!("settings file: \n{}", settings_file);
     let table = settings_file.parse::<toml::Table>().unwrap();
 
@@ -2256,7 +3504,7 @@ pub fn read_settings(settings_file: &str, mode_info: HashMap<String,Vec<String>>
 
     let editor_string = editor_string.replace(",", "\n");
 
-    let editor_settings = toml::from_str(&editor_string).unwrap();
+    let editor_settings: EditorSettings = toml::from_str(&editor_string).unwrap();
 
     let mut mode_keybindings = HashMap::new();
     for name in mode_info.keys() {
@@ -2265,155 +3513,508 @@ pub fn read_settings(settings_file: &str, mode_info: HashMap<String,Vec<String>>
         mode_keybindings.insert(name.to_string(), keybindings);
     }
 
-    let colors = match table.get("color") {
-        None => EditorColors::default(),
-        Some(value) => parse_editor_colors(value),
+    let mut colors = EditorColors::default();
+    if let Some(value) = table.get("color") {
+        parse_editor_colors(value, &mut colors).expect("Invalid colors");
+    }
+
+    let mut auto_pairs = default_auto_pairs();
+    if let Some(value) = table.get("autopairs") {
+        parse_auto_pairs(value, &mut auto_pairs);
+    }
+
+    let undo_dir = table["editor"].get("undo_dir")
+        .and_then(|dir| dir.as_str())
+        .map(PathBuf::from);
+
+    let backup_dir = table["editor"].get("backup_dir")
+        .and_then(|dir| dir.as_str())
+        .map(PathBuf::from);
+
+    let mut errors = Vec::new();
+    let lsp_servers = match table.get("lsp_servers") {
+        None => HashMap::new(),
+        Some(value) => parse_lsp_servers(value, &mut errors),
     };
-    
-    Settings {
-        editor_settings,
+
+    let leader = editor_settings.leader;
+    let mut settings = Settings {
+        editor_settings: EditorSettings {
+            leader: DEFAULT_LEADER,
+            ..editor_settings
+        },
         mode_keybindings,
         colors,
+        auto_pairs,
+        undo_dir,
+        backup_dir,
+        lsp_servers,
+    };
+    // Bindings in the file are written with `\\` as the leader
+    settings.set_leader(leader);
+
+    errors.extend(settings.find_conflicts().iter().map(|conflict| conflict.to_string()));
+
+    (settings, errors)
+}
+
+/// Reads the `[autopairs]` table, where each language has a list of pairs written like `"()"`.
+fn parse_auto_pairs(value: &toml::Value, auto_pairs: &mut HashMap<String, Vec<(char, char)>>) {
+    let table = match value {
+        toml::Value::Table(table) => table,
+        _ => return,
+    };
+
+    for (language, pairs) in table {
+        let pairs = match pairs {
+            toml::Value::Array(pairs) => pairs,
+            _ => continue,
+        };
+
+        let pairs = pairs.iter()
+            .filter_map(|pair| pair.as_str())
+            .filter_map(|pair| {
+                let mut chars = pair.chars();
+                match (chars.next(), chars.next(), chars.next()) {
+                    (Some(open), Some(close), None) => Some((open, close)),
+                    _ => None,
+                }
+            })
+            .collect();
+
+        auto_pairs.insert(language.clone(), pairs);
+    }
+}
+
+/// The name of the config file a project can have in its directory, which goes over the user's.
+pub const PROJECT_CONFIG: &str = ".vi-rust.toml";
+
+/// The directory of the user's config, `$XDG_CONFIG_HOME/vi-rust` or `~/.config/vi-rust` if that isn't set.
+pub fn config_dir() -> Option<PathBuf> {
+    let config_dir = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+
+    Some(config_dir.join("vi-rust"))
+}
+
+fn user_config_path() -> Option<PathBuf> {
+    Some(config_dir()?.join("config.toml"))
+}
+
+/// The project config in the current directory or the closest directory above it that has one.
+fn project_config_path() -> Option<PathBuf> {
+    let dir = env::current_dir().ok()?;
+    dir.ancestors()
+        .map(|dir| dir.join(PROJECT_CONFIG))
+        .find(|path| path.is_file())
+}
+
+/// Puts the values of `over` into `base`, going into the tables that both have.
+fn merge_tables(base: &mut toml::Table, over: toml::Table) {
+    for (key, value) in over {
+        match value {
+            toml::Value::Table(over) if base.get(&key).is_some_and(|value| value.is_table()) => {
+                if let Some(toml::Value::Table(base)) = base.get_mut(&key) {
+                    merge_tables(base, over);
+                }
+            },
+            value => {
+                base.insert(key, value);
+            },
+        }
+    }
+}
+
+/// Errors from the toml crate can take up several lines, which don't fit on the message line.
+fn error_line(error: impl fmt::Display) -> String {
+    error.to_string()
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('|') && !line.chars().next().is_some_and(|c| c.is_ascii_digit()))
+        .collect::<Vec<_>>()
+        .join(": ")
+}
+
+/// The name a mode goes by in the settings.
+/// Modes can be written in lowercase and with `_` for spaces, like `drop_down`.
+fn find_mode_name(mode_keybindings: &HashMap<Mode, HashMap<Keys, Command>>, name: &str) -> Option<Mode> {
+    let name = name.replace('_', " ");
+    mode_keybindings.keys()
+        .find(|known| known.eq_ignore_ascii_case(&name))
+        .cloned()
+}
+
+/// Keys the way they are written in config files and commands.
+fn show_key_notation(keys: &Keys) -> String {
+    keys.iter().map(|key| key.to_string()).collect::<Vec<String>>().join(" ")
+}
+
+/// Reads the `[keybindings.<mode>]` tables over the bindings the mode already has.
+/// Each binding is written like `"C-w s" = "horizontal_split"`, and binding keys to `""` takes them away.
+fn parse_keybinding_overrides(value: &toml::Value, mode_keybindings: &mut HashMap<Mode, HashMap<Keys, Command>>, errors: &mut Vec<String>) {
+    let table = match value.as_table() {
+        None => {
+            errors.push("keybindings were not a table".to_string());
+            return;
+        },
+        Some(table) => table,
+    };
+
+    for (mode, bindings) in table {
+        let keybindings = match find_mode_name(mode_keybindings, mode) {
+            None => {
+                errors.push(format!("keybindings: unknown mode {}", mode));
+                continue;
+            },
+            Some(name) => mode_keybindings.get_mut(&name).expect("mode was not found"),
+        };
+
+        for (keys, command) in bindings.as_table().into_iter().flatten() {
+            let parsed = parse_key_notation(keys, DEFAULT_LEADER)
+                .and_then(|keys| match command.as_str() {
+                    None => Err("the command has to be a string".to_string()),
+                    Some(command) => Ok((keys, command)),
+                });
+            match parsed {
+                Ok((keys, "")) => {
+                    keybindings.remove(&keys);
+                },
+                Ok((keys, command)) => {
+                    keybindings.insert(keys, command.to_string());
+                },
+                Err(error) => errors.push(format!("keybindings.{} \"{}\": {}", mode, keys, error)),
+            }
+        }
+    }
+}
+
+impl Settings {
+    /// Binds keys to a command in a mode like `:map` does, or takes the binding away without a command.
+    /// Keys that start with another binding, or that another binding starts with, are refused
+    /// since the shorter binding would run before the longer one could be typed.
+    /// Gives back what was done to show the user.
+    pub fn map_keys(&mut self, mode: &str, notation: &str, command: Option<&str>) -> Result<String, String> {
+        let name = find_mode_name(&self.mode_keybindings, mode)
+            .ok_or_else(|| format!("Unknown mode: {}", mode))?;
+        let keys = parse_key_notation(notation, self.editor_settings.leader)?;
+        let shown = show_key_notation(&keys);
+        let bindings = self.mode_keybindings.get_mut(&name).expect("mode was not found");
+
+        let command = match command {
+            None => {
+                return match bindings.remove(&keys) {
+                    None => Err(format!("No mapping for {} in {} mode", shown, name)),
+                    Some(_) => Ok(format!("Unmapped {} in {} mode", shown, name)),
+                };
+            },
+            Some(command) => command,
+        };
+
+        let mut conflicts = bindings.iter()
+            .filter(|(other, _)| **other != keys && (other.starts_with(&keys) || keys.starts_with(other)))
+            .map(|(other, other_command)| (show_key_notation(other), other.len(), other_command.clone()))
+            .collect::<Vec<_>>();
+        conflicts.sort();
+        if let Some((other, length, other_command)) = conflicts.into_iter().next() {
+            let reason = if length > keys.len() { "would hide" } else { "would be hidden by" };
+            return Err(format!("{} {} {} ({}) in {} mode, unmap it first", shown, reason, other, other_command, name));
+        }
+
+        bindings.insert(keys, command.to_string());
+        Ok(format!("Mapped {} to {} in {} mode", shown, command, name))
+    }
+
+    /// The bindings of a mode as lines of their keys and commands, in order of their keys.
+    pub fn list_keys(&self, mode: &str) -> Result<(Mode, Vec<String>), String> {
+        let name = find_mode_name(&self.mode_keybindings, mode)
+            .ok_or_else(|| format!("Unknown mode: {}", mode))?;
+        let bindings = self.mode_keybindings.get(&name).expect("mode was not found");
+
+        let mut lines = bindings.iter()
+            .map(|(keys, command)| (show_key_notation(keys), command))
+            .collect::<Vec<_>>();
+        lines.sort();
+        let width = lines.iter().map(|(keys, _)| keys.chars().count()).max().unwrap_or(0);
+        let lines = lines.into_iter()
+            .map(|(keys, command)| format!("{:width$}  {}", keys, command, width = width))
+            .collect();
+        Ok((name, lines))
+    }
+
+    /// Reads the user's config file and then the project's over it.
+    /// What was wrong with them is given back, and the parts that were wrong keep their defaults.
+    pub fn load() -> (Settings, Vec<String>) {
+        let paths = [user_config_path(), project_config_path()].into_iter()
+            .flatten()
+            .collect::<Vec<_>>();
+        Settings::from_files(&paths)
+    }
+
+    /// Reads config files over the defaults, with the later files going over the earlier ones.
+    /// Files that don't exist are skipped.
+    pub fn from_files(paths: &[PathBuf]) -> (Settings, Vec<String>) {
+        let mut table = toml::Table::new();
+        let mut errors = Vec::new();
+
+        for path in paths {
+            let text = match fs::read_to_string(path) {
+                Ok(text) => text,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => {
+                    errors.push(format!("{}: {}", path.display(), e));
+                    continue;
+                },
+            };
+
+            match text.parse::<toml::Table>() {
+                Ok(file) => merge_tables(&mut table, file),
+                Err(e) => errors.push(format!("{}: {}", path.display(), error_line(e))),
+            }
+        }
+
+        let (settings, mut config_errors) = Settings::from_config(&table);
+        errors.append(&mut config_errors);
+        (settings, errors)
+    }
+
+    /// Builds the settings from the tables of a config file, with the defaults for what it leaves out.
+    pub fn from_config(table: &toml::Table) -> (Settings, Vec<String>) {
+        let mut settings = Settings::default();
+        let mut errors = Vec::new();
+
+        let mut leader = DEFAULT_LEADER;
+        if let Some(editor) = table.get("editor") {
+            match editor.clone().try_into::<EditorSettings>() {
+                Ok(editor_settings) => {
+                    leader = editor_settings.leader;
+                    settings.editor_settings = EditorSettings {
+                        leader: DEFAULT_LEADER,
+                        ..editor_settings
+                    };
+                },
+                Err(e) => errors.push(format!("editor: {}", error_line(e))),
+            }
+
+            settings.undo_dir = editor.get("undo_dir")
+                .and_then(|dir| dir.as_str())
+                .map(PathBuf::from);
+            settings.backup_dir = editor.get("backup_dir")
+                .and_then(|dir| dir.as_str())
+                .map(PathBuf::from);
+        }
+
+        if let Some(value) = table.get("color") {
+            if let Err(e) = parse_editor_colors(value, &mut settings.colors) {
+                errors.push(format!("color: {}", e));
+            }
+        }
+
+        if let Some(value) = table.get("autopairs") {
+            parse_auto_pairs(value, &mut settings.auto_pairs);
+        }
+
+        if let Some(value) = table.get("lsp_servers") {
+            settings.lsp_servers = parse_lsp_servers(value, &mut errors);
+        }
+
+        if let Some(value) = table.get("keybindings") {
+            parse_keybinding_overrides(value, &mut settings.mode_keybindings, &mut errors);
+        }
+
+        // Bindings in the file are written with `leader`, which is the default leader until now
+        settings.set_leader(leader);
+
+        // Both bindings are kept, the longer one only gets typed before the key timeout
+        for conflict in settings.find_conflicts() {
+            errors.push(conflict.to_string());
+        }
+
+        (settings, errors)
     }
 }
 
-fn parse_editor_colors(table: &toml::Value) -> EditorColors {
-    let table = table.as_table().expect("editor colors were not a table");
-    let mut editor_colors = EditorColors::default();
+/// Reads the `[lsp_servers.<language>]` tables.
+/// A server that can't be read is skipped so the built in one for the language is used instead.
+fn parse_lsp_servers(table: &toml::Value, errors: &mut Vec<String>) -> HashMap<String, LspServerConfig> {
+    let mut servers = HashMap::new();
+    for (language, server) in table.as_table().into_iter().flatten() {
+        let server = server.clone().try_into::<LspServerConfig>()
+            .map_err(|e| e.to_string())
+            .and_then(|server| server.check().map(|_| server));
+        match server {
+            Ok(server) => {
+                servers.insert(language.clone(), server);
+            },
+            Err(e) => errors.push(format!("invalid language server for {}: {}", language, e)),
+        }
+    }
+    servers
+}
 
-    match table.get("pane") {
-        None => editor_colors.pane = ColorScheme::default(),
-        Some(value) => editor_colors.pane = parse_color_scheme(value),
+/// Reads the `[color]` table over the colors that are already set.
+fn parse_editor_colors(table: &toml::Value, editor_colors: &mut EditorColors) -> Result<(), String> {
+    let table = table.as_table().ok_or_else(|| "colors were not a table".to_string())?;
+
+    for (name, value) in table {
+        let scheme = match name.as_str() {
+            "pane" => &mut editor_colors.pane,
+            "ui" => &mut editor_colors.ui,
+            "bar" => &mut editor_colors.bar,
+            "popup" => &mut editor_colors.popup,
+            "spell" => &mut editor_colors.spell,
+            "preview" => &mut editor_colors.preview,
+            "color_column" => &mut editor_colors.color_column,
+            "cursor_line" => &mut editor_colors.cursor_line,
+            "bad_indent" => &mut editor_colors.bad_indent,
+            "trailing_whitespace" => &mut editor_colors.trailing_whitespace,
+            "whitespace" => &mut editor_colors.whitespace,
+            "search" => &mut editor_colors.search,
+            "selection" => &mut editor_colors.selection,
+            "matching_bracket" => &mut editor_colors.matching_bracket,
+            "message" => &mut editor_colors.message,
+            "warning" => &mut editor_colors.warning,
+            "error" => &mut editor_colors.error,
+            "captures" => {
+                parse_capture_colors(value, Rc::make_mut(&mut editor_colors.captures))
+                    .map_err(|e| format!("captures: {}", e))?;
+                continue;
+            },
+            name => return Err(format!("unknown color {}", name)),
+        };
+        *scheme = parse_color_scheme(value).map_err(|e| format!("{}: {}", name, e))?;
     }
 
-    match table.get("ui") {
-        None => editor_colors.ui = ColorScheme::default(),
-        Some(value) => editor_colors.ui = parse_color_scheme(value),
+    Ok(())
+}
+
+/// Reads the `[color.captures]` table, whose keys are capture names without the `@`.
+fn parse_capture_colors(table: &toml::Value, captures: &mut HashMap<String, ColorScheme>) -> Result<(), String> {
+    let table = table.as_table().ok_or_else(|| "captures were not a table".to_string())?;
+
+    for (name, value) in table {
+        let scheme = parse_color_scheme(value).map_err(|e| format!("{}: {}", name, e))?;
+        captures.insert(name.clone(), scheme);
     }
 
-    editor_colors
+    Ok(())
 }
 
-fn parse_color_scheme(table: &toml::Value) -> ColorScheme {
-    let table = table.as_table().expect("color scheme was not a table");
+fn parse_color_scheme(table: &toml::Value) -> Result<ColorScheme, String> {
+    let table = table.as_table().ok_or_else(|| "color scheme was not a table".to_string())?;
     let mut color_scheme = ColorScheme::default();
     
     match table.get("foreground_color") {
         None => color_scheme.foreground_color = Color::Reset,
-        Some(value) => color_scheme.foreground_color = parse_color(value),
+        Some(value) => color_scheme.foreground_color = parse_color(value)?,
     }
 
     match table.get("background_color") {
         None => color_scheme.background_color = Color::Reset,
-        Some(value) => color_scheme.background_color = parse_color(value),
+        Some(value) => color_scheme.background_color = parse_color(value)?,
     }
 
     match table.get("underline_color") {
         None => color_scheme.underline_color = Color::Reset,
-        Some(value) => color_scheme.underline_color = parse_color(value),
+        Some(value) => color_scheme.underline_color = parse_color(value)?,
     }
 
     match table.get("attributes") {
         None => color_scheme.attributes = Rc::new(Vec::new()),
-        Some(value) => color_scheme.attributes = Rc::new(parse_attributes(value)),
+        Some(value) => color_scheme.attributes = Rc::new(parse_attributes(value)?),
     }
 
-    color_scheme
+    Ok(color_scheme)
 }
 
-fn parse_attributes(list: &toml::Value) -> Vec<Attribute> {
-    let list = list.as_array().expect("attributes were not an array");
+fn parse_attributes(list: &toml::Value) -> Result<Vec<Attribute>, String> {
+    let list = list.as_array().ok_or_else(|| "attributes were not an array".to_string())?;
 
     let mut attributes = Vec::new();
 
     for attribute in list {
-        let attribute = attribute.as_str().expect("attribute was not a string");
-
-        match attribute {
-            "reset" => attributes.push(Attribute::Reset),
-            "bold" => attributes.push(Attribute::Bold),
-            "dim" => attributes.push(Attribute::Dim),
-            "italic" => attributes.push(Attribute::Italic),
-            "underlined" => attributes.push(Attribute::Underlined),
-            "double_underlined" => attributes.push(Attribute::DoubleUnderlined),
-            "under_curled" => attributes.push(Attribute::Undercurled),
-            "under_dotted" => attributes.push(Attribute::Underdotted),
-            "under_dashed" => attributes.push(Attribute::Underdashed),
-            "slow_blink" => attributes.push(Attribute::SlowBlink),
-            "rapid_blink" => attributes.push(Attribute::RapidBlink),
-            "reverse" => attributes.push(Attribute::Reverse),
-            "hidden" => attributes.push(Attribute::Hidden),
-            "crossed_out" => attributes.push(Attribute::CrossedOut),
-            "fraktur" => attributes.push(Attribute::Fraktur),
-            "no_bold" => attributes.push(Attribute::NoBold),
-            "normal_intensity" => attributes.push(Attribute::NormalIntensity),
-            "no_italic" => attributes.push(Attribute::NoItalic),
-            "no_underline" => attributes.push(Attribute::NoUnderline),
-            "no_blink" => attributes.push(Attribute::NoBlink),
-            "no_reverse" => attributes.push(Attribute::NoReverse),
-            "no_hidden" => attributes.push(Attribute::NoHidden),
-            "not_crossed_out" => attributes.push(Attribute::NotCrossedOut),
-            "framed" => attributes.push(Attribute::Framed),
-            "encircled" => attributes.push(Attribute::Encircled),
-            "overlined" => attributes.push(Attribute::OverLined),
-            "not_framed_or_encircled" => attributes.push(Attribute::NotFramedOrEncircled),
-            "not_overlined" => attributes.push(Attribute::NotOverLined),
-            value => {
-                panic!("unknown attribute: {}", value);
-            }
-
-        }
-
+        let attribute = attribute.as_str().ok_or_else(|| "attribute was not a string".to_string())?;
+
+        let attribute = match attribute {
+            "reset" => Attribute::Reset,
+            "bold" => Attribute::Bold,
+            "dim" => Attribute::Dim,
+            "italic" => Attribute::Italic,
+            "underlined" => Attribute::Underlined,
+            "double_underlined" => Attribute::DoubleUnderlined,
+            "under_curled" => Attribute::Undercurled,
+            "under_dotted" => Attribute::Underdotted,
+            "under_dashed" => Attribute::Underdashed,
+            "slow_blink" => Attribute::SlowBlink,
+            "rapid_blink" => Attribute::RapidBlink,
+            "reverse" => Attribute::Reverse,
+            "hidden" => Attribute::Hidden,
+            "crossed_out" => Attribute::CrossedOut,
+            "fraktur" => Attribute::Fraktur,
+            "no_bold" => Attribute::NoBold,
+            "normal_intensity" => Attribute::NormalIntensity,
+            "no_italic" => Attribute::NoItalic,
+            "no_underline" => Attribute::NoUnderline,
+            "no_blink" => Attribute::NoBlink,
+            "no_reverse" => Attribute::NoReverse,
+            "no_hidden" => Attribute::NoHidden,
+            "not_crossed_out" => Attribute::NotCrossedOut,
+            "framed" => Attribute::Framed,
+            "encircled" => Attribute::Encircled,
+            "overlined" => Attribute::OverLined,
+            "not_framed_or_encircled" => Attribute::NotFramedOrEncircled,
+            "not_overlined" => Attribute::NotOverLined,
+            value => return Err(format!("unknown attribute {}", value)),
+        };
+        attributes.push(attribute);
     }
 
-    attributes
+    Ok(attributes)
 }
 
-fn parse_color(value: &toml::Value) -> Color {
-
-    if value.is_str() {
-        let value = value.as_str().expect("color was not a string");
-
-        match value {
-            "black" => Color::Black,
-            "red" => Color::Red,
-            "green" => Color::Green,
-            "yellow" => Color::Yellow,
-            "blue" => Color::Blue,
-            "magenta" => Color::Magenta,
-            "cyan" => Color::Cyan,
-            "white" => Color::White,
-            "dark-grey" => Color::DarkGrey,
-            "dark-red" => Color::DarkRed,
-            "dark-green" => Color::DarkGreen,
-            "dark-yellow" => Color::DarkYellow,
-            "dark-blue" => Color::DarkBlue,
-            "dark-magenta" => Color::DarkMagenta,
-            "dark-cyan" => Color::DarkCyan,
-            "grey" => Color::Grey,
-            _ => unreachable!(),
-        }
-    }
-    else if value.is_array() {
-        let value = value.as_array().expect("color was not an array");
-
-        if value.len() != 3 {
-            panic!("color array was not of length 3");
-        }
-
-        Color::Rgb {
-            r: value[0].as_integer().expect("color array value was not an integer") as u8,
-            g: value[1].as_integer().expect("color array value was not an integer") as u8,
-            b: value[2].as_integer().expect("color array value was not an integer") as u8,
-        }
-    }
-    else {
-        panic!("color was not a string or array");
+fn parse_color(value: &toml::Value) -> Result<Color, String> {
+    match value {
+        toml::Value::String(name) => {
+            let color = match name.as_str() {
+                "black" => Color::Black,
+                "red" => Color::Red,
+                "green" => Color::Green,
+                "yellow" => Color::Yellow,
+                "blue" => Color::Blue,
+                "magenta" => Color::Magenta,
+                "cyan" => Color::Cyan,
+                "white" => Color::White,
+                "dark-grey" => Color::DarkGrey,
+                "dark-red" => Color::DarkRed,
+                "dark-green" => Color::DarkGreen,
+                "dark-yellow" => Color::DarkYellow,
+                "dark-blue" => Color::DarkBlue,
+                "dark-magenta" => Color::DarkMagenta,
+                "dark-cyan" => Color::DarkCyan,
+                "grey" => Color::Grey,
+                name => return Err(format!("unknown color {}", name)),
+            };
+            Ok(color)
+        },
+        toml::Value::Array(values) => {
+            let component = |value: &toml::Value| value.as_integer()
+                .and_then(|value| u8::try_from(value).ok())
+                .ok_or_else(|| "color values have to be from 0 to 255".to_string());
+
+            match values.as_slice() {
+                [r, g, b] => Ok(Color::Rgb {
+                    r: component(r)?,
+                    g: component(g)?,
+                    b: component(b)?,
+                }),
+                _ => Err("a color needs 3 values".to_string()),
+            }
+        },
+        _ => Err("a color has to be a name or an array".to_string()),
     }
-    
 }
 
-
 #[cfg(test)]
 mod tests {
     use std::fs;
@@ -2431,6 +4032,7 @@ mod tests {
             "down".to_string(),
             "line_start".to_string(),
             "line_end".to_string(),
+            "first_non_blank".to_string(),
             "word_start_forward".to_string(),
             "word_start_backward".to_string(),
             "word_end_forward".to_string(),
@@ -2468,10 +4070,438 @@ mod tests {
             
         
 
-        let settings = read_settings(&settings, mode_settings);
+        let (settings, errors) = read_settings(&settings, mode_settings);
 
         println!("{:?}", settings);
+        println!("{:?}", errors);
+
+    }
+
+    #[test]
+    fn test_home_and_end_move_through_the_file() {
+        let settings = Settings::default();
+        let normal = &settings.mode_keybindings["Normal"];
+        let key = |key| vec![Key {
+            key,
+            modifier: KeyModifiers::NONE,
+        }];
+
+        assert_eq!(normal.get(&key(KeyCode::Home)), Some(&"file_top".to_string()));
+        assert_eq!(normal.get(&key(KeyCode::End)), Some(&"file_bottom".to_string()));
+        assert_eq!(normal.get(&key(KeyCode::Char('0'))), Some(&"line_start".to_string()));
+    }
+
+    #[test]
+    fn test_leader() {
+        let mut settings = Settings::default();
+        let space = parse_leader("space").unwrap();
+
+        let info = vec![DEFAULT_LEADER, Key {
+            key: KeyCode::Char('i'),
+            modifier: KeyModifiers::NONE,
+        }];
+        assert!(settings.mode_keybindings.get("Normal").unwrap().contains_key(&info));
+
+        settings.set_leader(space);
+
+        let moved = vec![space, info[1]];
+        let normal = settings.mode_keybindings.get("Normal").unwrap();
+        assert!(!normal.contains_key(&info));
+        assert_eq!(normal.get(&moved), Some(&"info".to_string()));
+        assert!(parse_leader("leader").is_err());
+    }
+
+    #[test]
+    fn test_binding_conflicts() {
+        let mut settings = Settings::default();
+        let key = |c| Key {
+            key: KeyCode::Char(c),
+            modifier: KeyModifiers::NONE,
+        };
+
+        let normal = settings.mode_keybindings.get_mut("Normal").unwrap();
+        normal.insert(vec![key('z'), key('q')], "first".to_string());
+        normal.insert(vec![key('z'), key('q'), key('q')], "second".to_string());
+
+        let conflicts = settings.find_conflicts();
+        assert!(conflicts.iter().any(|conflict| conflict.shorter.1 == "first" && conflict.longer.1 == "second"));
+
+        assert!(Settings::default().find_conflicts().is_empty());
+    }
+
+    #[test]
+    fn test_conflicts_keep_default_keymap() {
+        let dir = std::env::temp_dir().join("vi_config_conflicts_test");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let user = dir.join("config.toml");
+        fs::write(&user, "[keybindings.normal]\ng = \"left\"\n").unwrap();
+
+        let (settings, errors) = Settings::from_files(&[user]);
+        let defaults = Settings::default();
+        let g = parse_key_notation("g", DEFAULT_LEADER).unwrap();
+        let hidden = defaults.mode_keybindings["Normal"].keys()
+            .filter(|keys| keys.len() > 1 && keys.starts_with(&g))
+            .count();
+        assert!(hidden > 0);
+        assert_eq!(errors.len(), hidden);
+
+        for (mode, bindings) in defaults.mode_keybindings.iter() {
+            for (keys, command) in bindings.iter() {
+                assert_eq!(settings.mode_keybindings[mode].get(keys), Some(command));
+            }
+        }
+        assert_eq!(settings.mode_keybindings["Normal"].get(&g), Some(&"left".to_string()));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_take_key_actions() {
+        let key = |c| Key {
+            key: KeyCode::Char(c),
+            modifier: KeyModifiers::NONE,
+        };
+        let run = |command: &str| KeyAction::Run(command.to_string());
+
+        let mut bindings = HashMap::new();
+        bindings.insert(vec![key('d')], "delete".to_string());
+        bindings.insert(vec![key('d'), key('d')], "delete_line".to_string());
+        bindings.insert(vec![key('d'), key('w')], "delete_word".to_string());
+        bindings.insert(vec![key('g'), key('g')], "file_top".to_string());
+        bindings.insert(vec![key('j')], "down".to_string());
+
+        // `d` could still be `dd` or `dw`
+        let mut buffer = vec![key('d')];
+        assert!(take_key_actions(&bindings, &mut buffer, false).is_empty());
+        assert_eq!(buffer, vec![key('d')]);
+
+        let mut buffer = vec![key('d'), key('d')];
+        assert_eq!(take_key_actions(&bindings, &mut buffer, false), vec![run("delete_line")]);
+        assert!(buffer.is_empty());
+
+        let mut buffer = vec![key('d'), key('w')];
+        assert_eq!(take_key_actions(&bindings, &mut buffer, false), vec![run("delete_word")]);
+        assert!(buffer.is_empty());
+
+        // Running out of time falls back on the shorter binding
+        let mut buffer = vec![key('d')];
+        assert_eq!(take_key_actions(&bindings, &mut buffer, true), vec![run("delete")]);
+        assert!(buffer.is_empty());
+
+        // A key that doesn't continue any binding runs `d` and then goes through again on its own
+        let mut buffer = vec![key('d'), key('j')];
+        assert_eq!(take_key_actions(&bindings, &mut buffer, false), vec![run("delete"), run("down")]);
+        assert!(buffer.is_empty());
+
+        // The keys after the fallback can still wait for more
+        let mut buffer = vec![key('d'), key('g')];
+        assert_eq!(take_key_actions(&bindings, &mut buffer, false), vec![run("delete")]);
+        assert_eq!(buffer, vec![key('g')]);
+        assert_eq!(take_key_actions(&bindings, &mut buffer, false), vec![]);
+        buffer.push(key('g'));
+        assert_eq!(take_key_actions(&bindings, &mut buffer, false), vec![run("file_top")]);
+
+        // `g` on its own isn't bound, so the key is handed back
+        let mut buffer = vec![key('g')];
+        assert_eq!(take_key_actions(&bindings, &mut buffer, true), vec![KeyAction::Unbound(key('g'))]);
+        let mut buffer = vec![key('g'), key('j')];
+        assert_eq!(take_key_actions(&bindings, &mut buffer, false), vec![KeyAction::Unbound(key('g')), run("down")]);
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn test_auto_pairs_option() {
+        let editor_settings: EditorSettings = toml::from_str("auto_pairs = false").unwrap();
+        assert!(!editor_settings.autopairs);
+
+        let mut editor_settings = EditorSettings::default();
+        editor_settings.set_option("noauto_pairs").unwrap();
+        assert!(!editor_settings.autopairs);
+        editor_settings.set_option("autopairs").unwrap();
+        assert!(editor_settings.autopairs);
+    }
+
+    #[test]
+    fn test_auto_completion_options() {
+        let editor_settings: EditorSettings = toml::from_str("auto_completion = false\ncompletion_min_prefix = 2").unwrap();
+        assert!(!editor_settings.auto_completion);
+        assert_eq!(editor_settings.completion_min_prefix, 2);
+
+        let mut editor_settings = EditorSettings::default();
+        editor_settings.set_option("noauto_completion").unwrap();
+        assert!(!editor_settings.auto_completion);
+        editor_settings.set_option("completion_min_prefix=1").unwrap();
+        assert_eq!(editor_settings.completion_min_prefix, 1);
+    }
+
+    #[test]
+    fn test_key_notation() {
+        let key = |key, modifier| Key { key, modifier };
+
+        assert_eq!(parse_key_notation("C-w s", DEFAULT_LEADER), Ok(vec![
+            key(KeyCode::Char('w'), KeyModifiers::CONTROL),
+            key(KeyCode::Char('s'), KeyModifiers::NONE),
+        ]));
+        assert_eq!(parse_key_notation("leader  f", DEFAULT_LEADER), Ok(vec![DEFAULT_LEADER, key(KeyCode::Char('f'), KeyModifiers::NONE)]));
+        assert_eq!(parse_key_notation("S-a", DEFAULT_LEADER), Ok(vec![key(KeyCode::Char('A'), KeyModifiers::NONE)]));
+        assert_eq!(parse_key_notation("C-M-x", DEFAULT_LEADER), Ok(vec![key(KeyCode::Char('x'), KeyModifiers::CONTROL | KeyModifiers::ALT)]));
+        assert_eq!(parse_key_notation("esc C--", DEFAULT_LEADER), Ok(vec![
+            key(KeyCode::Esc, KeyModifiers::NONE),
+            key(KeyCode::Char('-'), KeyModifiers::CONTROL),
+        ]));
+        assert_eq!(parse_key_notation("F5", DEFAULT_LEADER), Ok(vec![key(KeyCode::F(5), KeyModifiers::NONE)]));
+        assert!(parse_key_notation("C-nothing", DEFAULT_LEADER).is_err());
+        assert!(parse_key_notation(" ", DEFAULT_LEADER).is_err());
+    }
+
+    #[test]
+    fn test_config_file() {
+        let table = r#"
+            [editor]
+            tab_size = 2
+            leader = "space"
+
+            [color.search]
+            background_color = [10, 20, 30]
+
+            [keybindings.normal]
+            "C-w s" = "horizontal_split"
+            "leader x" = "close"
+            "u" = ""
+            "C-nothing" = "undo"
+
+            [keybindings.drop_down]
+            "C-j" = "down"
+        "#.parse::<toml::Table>().unwrap();
+
+        let (settings, errors) = Settings::from_config(&table);
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains("C-nothing"));
+        assert_eq!(settings.editor_settings.tab_size, 2);
+        assert_eq!(settings.colors.search.background_color, Color::Rgb { r: 10, g: 20, b: 30 });
+
+        let normal = settings.mode_keybindings.get("Normal").unwrap();
+        let keys = |notation| parse_key_notation(notation, DEFAULT_LEADER).unwrap();
+        assert_eq!(normal.get(&keys("C-w s")), Some(&"horizontal_split".to_string()));
+        assert_eq!(normal.get(&keys("space x")), Some(&"close".to_string()));
+        assert_eq!(normal.get(&keys("u")), None);
+        let drop_down = settings.mode_keybindings.get("Drop Down").unwrap();
+        assert_eq!(drop_down.get(&keys("C-j")), Some(&"down".to_string()));
+
+        let bad = "[editor]\ntab_size = \"wide\"\n[color.search]\nbackground_color = \"plaid\"".parse::<toml::Table>().unwrap();
+        let (settings, errors) = Settings::from_config(&bad);
+        assert_eq!(errors.len(), 2);
+        assert_eq!(settings.editor_settings.tab_size, EditorSettings::default().tab_size);
+        assert_eq!(settings.colors.search, EditorColors::default().search);
+    }
+
+    #[test]
+    fn test_map_keys() {
+        let mut settings = Settings::default();
+        let keys = |notation| parse_key_notation(notation, DEFAULT_LEADER).unwrap();
+
+        assert!(settings.map_keys("normal", "C-k z", Some("undo")).is_ok());
+        assert_eq!(settings.mode_keybindings["Normal"].get(&keys("C-k z")), Some(&"undo".to_string()));
+
+        // `g g` goes to the top of the file so `g` on its own would hide it
+        assert!(settings.map_keys("normal", "g", Some("undo")).is_err());
+        assert!(settings.map_keys("normal", "C-k z z", Some("redo")).is_err());
+        assert!(settings.map_keys("nowhere", "x", Some("undo")).is_err());
+        assert!(settings.map_keys("normal", "C-k z", Some("redo")).is_ok());
+        assert_eq!(settings.mode_keybindings["Normal"].get(&keys("C-k z")), Some(&"redo".to_string()));
+
+        assert!(settings.map_keys("Normal", "C-k z", None).is_ok());
+        assert!(!settings.mode_keybindings["Normal"].contains_key(&keys("C-k z")));
+        assert!(settings.map_keys("normal", "C-k z", None).is_err());
+
+        let (name, lines) = settings.list_keys("drop_down").unwrap();
+        assert_eq!(name, "Drop Down");
+        assert_eq!(lines.len(), settings.mode_keybindings["Drop Down"].len());
+    }
+
+    #[test]
+    fn test_config_files() {
+        let dir = std::env::temp_dir().join("vi_config_files_test");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let user = dir.join("config.toml");
+        let project = dir.join(PROJECT_CONFIG);
+        let broken = dir.join("broken.toml");
+        fs::write(&user, "[editor]\ntab_size = 8\nborder = false\n").unwrap();
+        fs::write(&project, "[editor]\ntab_size = 3\n").unwrap();
+        fs::write(&broken, "[editor\n").unwrap();
+
+        let (settings, errors) = Settings::from_files(&[user.clone(), project.clone(), dir.join("missing.toml")]);
+        assert!(errors.is_empty());
+        assert_eq!(settings.editor_settings.tab_size, 3);
+        assert!(!settings.editor_settings.border);
+
+        let (settings, errors) = Settings::from_files(&[user, broken]);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(settings.editor_settings.tab_size, 8);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_auto_save_options() {
+        let editor_settings: EditorSettings = toml::from_str("auto_save = 30\nauto_save_on_focus_lost = true").unwrap();
+        assert_eq!(editor_settings.auto_save, Some(30));
+        assert!(editor_settings.auto_save_on_focus_lost);
+
+        let mut editor_settings = EditorSettings::default();
+        assert_eq!(editor_settings.auto_save, None);
+        editor_settings.set_option("auto_save=5").unwrap();
+        assert_eq!(editor_settings.auto_save, Some(5));
+        editor_settings.set_option("auto_save=0").unwrap();
+        assert_eq!(editor_settings.auto_save, None);
+        editor_settings.set_option("auto_save_in_insert").unwrap();
+        assert!(editor_settings.auto_save_in_insert);
+    }
+
+    #[test]
+    fn test_mouse_options() {
+        let mut editor_settings = EditorSettings::default();
+        assert!(editor_settings.mouse);
+        assert_eq!(editor_settings.mouse_scroll, 3);
+
+        editor_settings.set_option("nomouse").unwrap();
+        assert!(!editor_settings.mouse);
+        editor_settings.set_option("mouse_scroll=5").unwrap();
+        assert_eq!(editor_settings.mouse_scroll, 5);
+
+        let editor_settings: EditorSettings = toml::from_str("mouse = false").unwrap();
+        assert!(!editor_settings.mouse);
+    }
 
+    #[test]
+    fn test_scroll_off() {
+        let mut editor_settings = EditorSettings::default();
+        assert_eq!(editor_settings.scroll_off, 0);
+
+        editor_settings.set_option("scroll_off=4").unwrap();
+        assert_eq!(editor_settings.scroll_off, 4);
+        editor_settings.set_option("so=2").unwrap();
+        assert_eq!(editor_settings.scroll_off, 2);
+        assert!(editor_settings.set_option("so=x").is_err());
+
+        let editor_settings: EditorSettings = toml::from_str("scroll_off = 8").unwrap();
+        assert_eq!(editor_settings.scroll_off, 8);
+    }
+
+    #[test]
+    fn test_background_parse_size() {
+        let mut editor_settings = EditorSettings::default();
+        assert_eq!(editor_settings.background_parse_size, 512 * 1024);
+        editor_settings.set_option("background_parse_size=0").unwrap();
+        assert_eq!(editor_settings.background_parse_size, 0);
+
+        let editor_settings: EditorSettings = toml::from_str("background_parse_size = 1000").unwrap();
+        assert_eq!(editor_settings.background_parse_size, 1000);
+    }
+
+    #[test]
+    fn test_show_whitespace() {
+        let mut editor_settings = EditorSettings::default();
+        assert!(!editor_settings.show_whitespace);
+        editor_settings.set_option("show_whitespace").unwrap();
+        assert!(editor_settings.show_whitespace);
+        editor_settings.set_option("noshow_whitespace").unwrap();
+        assert!(!editor_settings.show_whitespace);
+
+        let mut colors = EditorColors::default();
+        let table: toml::Value = toml::from_str("whitespace = { foreground_color = \"dark-red\" }").unwrap();
+        parse_editor_colors(&table, &mut colors).unwrap();
+        assert_eq!(colors.whitespace.foreground_color, Color::DarkRed);
+    }
+
+    #[test]
+    fn test_capture_colors() {
+        let mut colors = EditorColors::default();
+        assert_eq!(colors.captures.get("keyword").unwrap().foreground_color, Color::DarkBlue);
+
+        let table: toml::Value = toml::from_str(r#"
+            [captures]
+            keyword = { foreground_color = "dark-red" }
+            "function.method" = { foreground_color = "dark-green" }
+        "#).unwrap();
+        parse_editor_colors(&table, &mut colors).unwrap();
+        assert_eq!(colors.captures.get("keyword").unwrap().foreground_color, Color::DarkRed);
+        assert_eq!(colors.captures.get("function.method").unwrap().foreground_color, Color::DarkGreen);
+        assert_eq!(colors.captures.get("string").unwrap().foreground_color, Color::DarkGreen);
+
+        let table: toml::Value = toml::from_str("captures = { keyword = 1 }").unwrap();
+        assert!(parse_editor_colors(&table, &mut colors).is_err());
+    }
+
+    #[test]
+    fn test_cursorline_and_color_column() {
+        let mut editor_settings = EditorSettings::default();
+        assert!(!editor_settings.cursorline);
+        editor_settings.set_option("cursorline").unwrap();
+        assert!(editor_settings.cursorline);
+        editor_settings.set_option("nocul").unwrap();
+        assert!(!editor_settings.cursorline);
+
+        editor_settings.set_option("color_column=100").unwrap();
+        assert_eq!(editor_settings.color_column(), Some(100));
+
+        let editor_settings: EditorSettings = toml::from_str("cursorline = true\ncolor_column = 100").unwrap();
+        assert!(editor_settings.cursorline);
+        assert_eq!(editor_settings.color_column(), Some(100));
+    }
+
+    #[test]
+    fn test_lsp_servers() {
+        let table: toml::Value = toml::from_str(r#"
+            [python]
+            command = "pylsp"
+            args = ["-v"]
+            initialization_options = { pylsp = { plugins = { pycodestyle = { enabled = false } } } }
+
+            [zig]
+            command = "zls"
+            env = { ZLS_LOG = "info" }
+            configuration = { zls = { enable_snippets = true } }
+
+            [java]
+            transport = "tcp"
+            port = 5036
+
+            [broken]
+            args = ["--stdio"]
+
+            [no_port]
+            command = "jdtls"
+            transport = "tcp"
+        "#).unwrap();
+        let mut errors = Vec::new();
+        let servers = parse_lsp_servers(&table, &mut errors);
+
+        let python = servers.get("python").unwrap();
+        assert_eq!(python.command, "pylsp");
+        assert_eq!(python.args, vec!["-v".to_string()]);
+        assert_eq!(python.initialization_options, Some(serde_json::json!({"pylsp": {"plugins": {"pycodestyle": {"enabled": false}}}})));
+
+        let zig = servers.get("zig").unwrap();
+        assert!(zig.args.is_empty());
+        assert_eq!(zig.env.get("ZLS_LOG"), Some(&"info".to_string()));
+        assert_eq!(zig.configuration, Some(serde_json::json!({"zls": {"enable_snippets": true}})));
+
+        let java = servers.get("java").unwrap();
+        assert_eq!(java.transport, LspTransport::Tcp);
+        assert_eq!(java.port, Some(5036));
+        assert!(java.command.is_empty());
+        assert_eq!(zig.transport, LspTransport::Stdio);
+
+        assert!(!servers.contains_key("broken"));
+        assert!(!servers.contains_key("no_port"));
+        assert_eq!(errors.len(), 2);
+        assert!(errors.iter().any(|error| error.starts_with("invalid language server for broken")));
+        assert!(errors.iter().any(|error| error.starts_with("invalid language server for no_port")));
     }
 
 }
@@ -0,0 +1,379 @@
+use std::collections::BTreeMap;
+
+/// A snippet from a language server with its placeholders filled in, like `foo(${1:arg}, $2)$0`.
+#[derive(Debug, PartialEq)]
+pub struct Snippet {
+    pub text: String,
+    /// The byte ranges in the text of each tabstop in the order Tab visits them, with `$0` last.
+    /// A tabstop that is used more than once has a range for every place it is in.
+    pub tabstops: Vec<Vec<(usize, usize)>>,
+}
+
+enum Part {
+    Text(String),
+    /// The number of the tabstop and its placeholder.
+    Tabstop(usize, Vec<Part>),
+    /// The default of a variable, which can have tabstops of its own.
+    Group(Vec<Part>),
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        }
+        else {
+            false
+        }
+    }
+
+    fn number(&mut self) -> Option<usize> {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        self.chars[start..self.pos].iter().collect::<String>().parse().ok()
+    }
+
+    fn name(&mut self) -> Option<String> {
+        let start = self.pos;
+        if !self.peek().is_some_and(|c| c == '_' || c.is_ascii_alphabetic()) {
+            return None;
+        }
+        while self.peek().is_some_and(|c| c == '_' || c.is_ascii_alphanumeric()) {
+            self.pos += 1;
+        }
+        Some(self.chars[start..self.pos].iter().collect())
+    }
+
+    /// Reads text and tabstops up to the end, or up to the `}` that closes a placeholder when `nested`.
+    fn parse(&mut self, nested: bool) -> Vec<Part> {
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        while let Some(c) = self.peek() {
+            match c {
+                '\\' => match self.chars.get(self.pos + 1).copied() {
+                    Some(escaped @ ('$' | '}' | '\\')) => {
+                        text.push(escaped);
+                        self.pos += 2;
+                    },
+                    _ => {
+                        text.push('\\');
+                        self.pos += 1;
+                    },
+                },
+                '}' if nested => break,
+                '$' => {
+                    let start = self.pos;
+                    match self.dollar() {
+                        Some(part) => {
+                            if !text.is_empty() {
+                                parts.push(Part::Text(std::mem::take(&mut text)));
+                            }
+                            parts.push(part);
+                        },
+                        // A `$` that doesn't start anything is just a `$`
+                        None => {
+                            self.pos = start + 1;
+                            text.push('$');
+                        },
+                    }
+                },
+                c => {
+                    text.push(c);
+                    self.pos += 1;
+                },
+            }
+        }
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+        parts
+    }
+
+    /// Reads what comes after a `$`.
+    fn dollar(&mut self) -> Option<Part> {
+        self.pos += 1;
+        if !self.eat('{') {
+            if let Some(number) = self.number() {
+                return Some(Part::Tabstop(number, Vec::new()));
+            }
+            // The editor doesn't know the values of any variables
+            return self.name().map(|_| Part::Text(String::new()));
+        }
+
+        if let Some(number) = self.number() {
+            if self.eat('}') {
+                return Some(Part::Tabstop(number, Vec::new()));
+            }
+            if self.eat(':') {
+                let placeholder = self.parse(true);
+                return self.eat('}').then_some(Part::Tabstop(number, placeholder));
+            }
+            if self.eat('|') {
+                // The first choice is the one that gets inserted
+                let choice = self.choice()?;
+                return Some(Part::Tabstop(number, vec![Part::Text(choice)]));
+            }
+            return None;
+        }
+
+        self.name()?;
+        if self.eat('}') {
+            return Some(Part::Text(String::new()));
+        }
+        if self.eat(':') {
+            let default = self.parse(true);
+            return self.eat('}').then_some(Part::Group(default));
+        }
+        if self.eat('/') {
+            // A transform of a variable without a value is empty
+            while let Some(c) = self.peek() {
+                self.pos += if c == '\\' { 2 } else { 1 };
+                if c == '}' {
+                    return Some(Part::Text(String::new()));
+                }
+            }
+        }
+        None
+    }
+
+    /// Reads the options of a choice up to its closing `|}` and gives back the first one.
+    fn choice(&mut self) -> Option<String> {
+        let mut options = vec![String::new()];
+        while let Some(c) = self.peek() {
+            self.pos += 1;
+            match c {
+                '\\' => {
+                    let escaped = self.peek()?;
+                    self.pos += 1;
+                    options.last_mut()?.push(escaped);
+                },
+                ',' => options.push(String::new()),
+                '|' => return self.eat('}').then(|| options.swap_remove(0)),
+                c => options.last_mut()?.push(c),
+            }
+        }
+        None
+    }
+}
+
+/// The placeholder of each tabstop is the first one it is given, the other places it is used copy it.
+fn find_placeholders<'a>(parts: &'a [Part], placeholders: &mut BTreeMap<usize, &'a [Part]>) {
+    for part in parts {
+        match part {
+            Part::Text(_) => {},
+            Part::Tabstop(number, placeholder) => {
+                if !placeholder.is_empty() {
+                    placeholders.entry(*number).or_insert(placeholder);
+                }
+                find_placeholders(placeholder, placeholders);
+            },
+            Part::Group(parts) => find_placeholders(parts, placeholders),
+        }
+    }
+}
+
+fn render(parts: &[Part], placeholders: &BTreeMap<usize, &[Part]>, text: &mut String, ranges: &mut BTreeMap<usize, Vec<(usize, usize)>>) {
+    for part in parts {
+        match part {
+            Part::Text(part) => text.push_str(part),
+            Part::Tabstop(number, placeholder) => {
+                let start = text.len();
+                if !placeholder.is_empty() {
+                    render(placeholder, placeholders, text, ranges);
+                }
+                else if let Some(placeholder) = placeholders.get(number) {
+                    // The tabstops inside of a copy aren't places to go to
+                    render(placeholder, placeholders, text, &mut BTreeMap::new());
+                }
+                ranges.entry(*number).or_default().push((start, text.len()));
+            },
+            Part::Group(parts) => render(parts, placeholders, text, ranges),
+        }
+    }
+}
+
+impl Snippet {
+    pub fn parse(snippet: &str) -> Self {
+        let mut parser = Parser {
+            chars: snippet.chars().collect(),
+            pos: 0,
+        };
+        let parts = parser.parse(false);
+
+        let mut placeholders = BTreeMap::new();
+        find_placeholders(&parts, &mut placeholders);
+
+        let mut text = String::new();
+        let mut ranges = BTreeMap::new();
+        render(&parts, &placeholders, &mut text, &mut ranges);
+
+        // Without a `$0` the snippet ends after its text
+        let last = ranges.remove(&0).unwrap_or_else(|| vec![(text.len(), text.len())]);
+        let mut tabstops = ranges.into_values().collect::<Vec<_>>();
+        tabstops.push(last);
+
+        Snippet {
+            text,
+            tabstops,
+        }
+    }
+}
+
+/// A snippet that was inserted into a buffer.
+/// Its tabstops move along with the edits made to the buffer.
+#[derive(Debug)]
+pub struct SnippetSession {
+    /// The byte ranges in the buffer of every tabstop, in the order they are visited.
+    tabstops: Vec<Vec<(usize, usize)>>,
+    current: usize,
+    /// The place of the current tabstop that was edited last, which the other places get copied from.
+    edited: Option<usize>,
+    /// Typing at the start of the current tabstop replaces its placeholder until something else is edited.
+    pub replace_placeholder: bool,
+}
+
+impl SnippetSession {
+    /// `offset` is the byte in the buffer the snippet was inserted at.
+    pub fn new(snippet: &Snippet, offset: usize) -> Self {
+        let tabstops = snippet.tabstops.iter()
+            .map(|ranges| ranges.iter().map(|(start, end)| (start + offset, end + offset)).collect())
+            .collect();
+        SnippetSession {
+            tabstops,
+            current: 0,
+            edited: None,
+            replace_placeholder: true,
+        }
+    }
+
+    /// The places of the tabstop the cursor is on.
+    pub fn current(&self) -> &[(usize, usize)] {
+        &self.tabstops[self.current]
+    }
+
+    /// Whether the cursor is on the tabstop the snippet ends at.
+    pub fn is_last(&self) -> bool {
+        self.current + 1 == self.tabstops.len()
+    }
+
+    pub fn next(&mut self) {
+        self.current = (self.current + 1).min(self.tabstops.len() - 1);
+        self.edited = None;
+        self.replace_placeholder = true;
+    }
+
+    pub fn previous(&mut self) {
+        self.current = self.current.saturating_sub(1);
+        self.edited = None;
+        self.replace_placeholder = true;
+    }
+
+    /// Moves the tabstops along with an edit that replaced the bytes from `start` to `old_end` with `new_len` bytes.
+    /// Returns false when the edit broke into a tabstop from outside of it, which ends the snippet.
+    pub fn edit(&mut self, start: usize, old_end: usize, new_len: usize) -> bool {
+        let grow = |end: usize| end + new_len - (old_end - start);
+        self.replace_placeholder = false;
+
+        for (index, ranges) in self.tabstops.iter_mut().enumerate() {
+            for (occurrence, range) in ranges.iter_mut().enumerate() {
+                if index == self.current && start >= range.0 && old_end <= range.1 {
+                    // Typing at either end of the current tabstop adds to it
+                    range.1 = grow(range.1);
+                    self.edited = Some(occurrence);
+                }
+                else if old_end <= range.0 {
+                    *range = (grow(range.0), grow(range.1));
+                }
+                else if start >= range.1 {
+                    // Edits after a tabstop don't move it
+                }
+                else if start >= range.0 && old_end <= range.1 {
+                    range.1 = grow(range.1);
+                }
+                else {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    /// Which place of the current tabstop the other places should copy, if it changed.
+    pub fn take_edited(&mut self) -> Option<usize> {
+        let occurrence = self.edited.take()?;
+        (self.current().len() > 1).then_some(occurrence)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let snippet = Snippet::parse("foo(${1:a}, ${2:b})$0");
+        assert_eq!(snippet.text, "foo(a, b)");
+        assert_eq!(snippet.tabstops, vec![vec![(4, 5)], vec![(7, 8)], vec![(9, 9)]]);
+
+        // The snippet ends after its text when there is no `$0`
+        let snippet = Snippet::parse("if $1 {\n\t$2\n}");
+        assert_eq!(snippet.text, "if  {\n\t\n}");
+        assert_eq!(snippet.tabstops, vec![vec![(3, 3)], vec![(7, 7)], vec![(9, 9)]]);
+
+        let snippet = Snippet::parse("${1:outer ${2:inner}} ${3|one,two|} \\$1 $ ${TM_FILENAME:name}");
+        assert_eq!(snippet.text, "outer inner one $1 $ name");
+        assert_eq!(snippet.tabstops, vec![vec![(0, 11)], vec![(6, 11)], vec![(12, 15)], vec![(25, 25)]]);
+    }
+
+    #[test]
+    fn test_mirrors() {
+        let snippet = Snippet::parse("let ${1:x} = $2; $1");
+        assert_eq!(snippet.text, "let x = ; x");
+        assert_eq!(snippet.tabstops[0], vec![(4, 5), (10, 11)]);
+
+        let mut session = SnippetSession::new(&snippet, 10);
+        // Typing over the placeholder in the first place
+        assert!(session.edit(14, 15, 3));
+        assert_eq!(session.take_edited(), Some(0));
+        assert_eq!(session.current(), &[(14, 17), (22, 23)]);
+        // Copying it to the second place
+        assert!(session.edit(22, 23, 3));
+        session.take_edited();
+        assert_eq!(session.current(), &[(14, 17), (22, 25)]);
+
+        session.next();
+        assert_eq!(session.current(), &[(20, 20)]);
+        session.next();
+        assert!(session.is_last());
+        assert_eq!(session.current(), &[(25, 25)]);
+    }
+
+    #[test]
+    fn test_edits_around_tabstops() {
+        let snippet = Snippet::parse("${1:a}${2:b}$0");
+        let mut session = SnippetSession::new(&snippet, 2);
+
+        // Typing at the end of the current tabstop grows it and pushes the next one along
+        assert!(session.edit(3, 3, 2));
+        assert_eq!(session.tabstops, vec![vec![(2, 5)], vec![(5, 6)], vec![(6, 6)]]);
+
+        // Edits before the snippet move all of it
+        assert!(session.edit(0, 0, 1));
+        assert_eq!(session.tabstops, vec![vec![(3, 6)], vec![(6, 7)], vec![(7, 7)]]);
+
+        // Deleting across two tabstops ends the snippet
+        assert!(!session.edit(5, 7, 0));
+    }
+}
@@ -0,0 +1,187 @@
+use std::{collections::HashSet, fs};
+
+
+/// The places we look for a system word list, in order.
+const DICTIONARY_PATHS: [&str; 3] = [
+    "/usr/share/dict/words",
+    "/usr/dict/words",
+    "/usr/share/dict/web2",
+];
+
+
+
+/// A word that wasn't found in the dictionary.
+/// The columns are in characters and the end is exclusive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Misspelling {
+    pub row: usize,
+    pub start: usize,
+    pub end: usize,
+    pub word: String,
+}
+
+impl Misspelling {
+    pub fn contains(&self, row: usize, col: usize) -> bool {
+        self.row == row && self.start <= col && col < self.end
+    }
+}
+
+
+pub struct SpellChecker {
+    words: HashSet<String>,
+}
+
+impl Default for SpellChecker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SpellChecker {
+    pub fn new() -> Self {
+        let mut words = HashSet::new();
+
+        for path in DICTIONARY_PATHS.iter() {
+            if let Ok(file) = fs::read_to_string(path) {
+                words.extend(file.lines().map(|word| word.trim().to_lowercase()));
+                break;
+            }
+        }
+
+        words.remove("");
+
+        Self {
+            words,
+        }
+    }
+
+    pub fn from_words<'a, I>(words: I) -> Self where I: IntoIterator<Item = &'a str> {
+        Self {
+            words: words.into_iter().map(|word| word.to_lowercase()).collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.is_empty()
+    }
+
+    /// Without a dictionary we can't tell what is misspelled so we don't flag anything.
+    pub fn check(&self, word: &str) -> bool {
+        if self.words.is_empty() {
+            return true;
+        }
+        let lower = word.to_lowercase();
+
+        if self.words.contains(&lower) {
+            return true;
+        }
+
+        // Possessives like "editor's"
+        match lower.strip_suffix("'s") {
+            Some(stem) => self.words.contains(stem),
+            None => false,
+        }
+    }
+
+    /// Scans a piece of text starting at the given position and records every misspelled word.
+    /// Anything that looks like an identifier (it has a digit or an underscore in it) gets skipped.
+    pub fn check_text(&self, text: &str, mut row: usize, mut col: usize, output: &mut Vec<Misspelling>) {
+        let mut word = String::new();
+        let mut start = (row, col);
+
+        let flush = |word: &mut String, start: (usize, usize), output: &mut Vec<Misspelling>| {
+            if word.is_empty() {
+                return;
+            }
+            let leading = word.chars().take_while(|c| *c == '\'').count();
+            let trimmed = word.trim_matches('\'');
+            let len = trimmed.chars().count();
+
+            if len > 1 && trimmed.chars().all(|c| c.is_alphabetic() || c == '\'') && !self.check(trimmed) {
+                output.push(Misspelling {
+                    row: start.0,
+                    start: start.1 + leading,
+                    end: start.1 + leading + len,
+                    word: trimmed.to_string(),
+                });
+            }
+            word.clear();
+        };
+
+        for c in text.chars() {
+            if c.is_alphanumeric() || c == '_' || c == '\'' {
+                if word.is_empty() {
+                    start = (row, col);
+                }
+                word.push(c);
+            }
+            else {
+                flush(&mut word, start, output);
+            }
+
+            if c == '\n' {
+                row += 1;
+                col = 0;
+            }
+            else {
+                col += 1;
+            }
+        }
+        flush(&mut word, start, output);
+    }
+
+    /// Returns up to `max` words that are a small edit away from the given word.
+    pub fn suggest(&self, word: &str, max: usize) -> Vec<String> {
+        let lower = word.to_lowercase();
+        let len = lower.chars().count();
+
+        let mut candidates = self.words.iter()
+            .filter(|candidate| {
+                let candidate_len = candidate.chars().count();
+                candidate_len + 2 >= len && candidate_len <= len + 2
+            })
+            .map(|candidate| (edit_distance(&lower, candidate), candidate))
+            .filter(|(distance, _)| *distance <= 2)
+            .collect::<Vec<_>>();
+
+        candidates.sort();
+
+        let capitalize = word.chars().next().map(|c| c.is_uppercase()).unwrap_or(false);
+
+        candidates.into_iter()
+            .take(max)
+            .map(|(_, candidate)| {
+                if capitalize {
+                    let mut chars = candidate.chars();
+                    match chars.next() {
+                        None => String::new(),
+                        Some(c) => c.to_uppercase().chain(chars).collect(),
+                    }
+                }
+                else {
+                    candidate.clone()
+                }
+            })
+            .collect()
+    }
+}
+
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<char>>();
+    let mut previous = (0..=b.len()).collect::<Vec<usize>>();
+    let mut current = vec![0; b.len() + 1];
+
+    for (i, ca) in a.chars().enumerate() {
+        current[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb { 0 } else { 1 };
+            current[j + 1] = (previous[j] + cost)
+                .min(previous[j + 1] + 1)
+                .min(current[j] + 1);
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+
+    previous[b.len()]
+}
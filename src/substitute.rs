@@ -0,0 +1,198 @@
+use crate::{buffer::Buffer, search::{Pattern, SearchMatch}};
+
+/// A substitute command like `s/old/new/g` or `%s/old/new/`.
+/// Only the pattern has to be there which lets us parse a command while it is still being typed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Substitute {
+    pub pattern: String,
+    pub replacement: String,
+    /// Set by `%s`, otherwise only the cursor's line is touched.
+    pub whole_file: bool,
+    /// Set by the `g` flag, otherwise only the first match of a line is replaced.
+    pub global: bool,
+    /// Set by the `c` flag, which asks before each replacement.
+    pub confirm: bool,
+}
+
+impl Substitute {
+    pub fn parse(command: &str) -> Option<Self> {
+        let (whole_file, rest) = match command.strip_prefix('%') {
+            Some(rest) => (true, rest),
+            None => (false, command),
+        };
+        let rest = rest.strip_prefix("s/")?;
+
+        let mut parts = vec![String::new()];
+        let mut chars = rest.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some('/') => parts.last_mut().unwrap().push('/'),
+                    Some('\\') => parts.last_mut().unwrap().push('\\'),
+                    // Like vim a `\r` in the replacement breaks the line
+                    Some('r') | Some('n') if parts.len() == 2 => parts.last_mut().unwrap().push('\n'),
+                    Some(c) => {
+                        parts.last_mut().unwrap().push('\\');
+                        parts.last_mut().unwrap().push(c);
+                    },
+                    None => parts.last_mut().unwrap().push('\\'),
+                },
+                '/' if parts.len() < 3 => parts.push(String::new()),
+                c => parts.last_mut().unwrap().push(c),
+            }
+        }
+
+        let mut parts = parts.into_iter();
+        let pattern = parts.next().unwrap_or_default();
+        let replacement = parts.next().unwrap_or_default();
+        let flags = parts.next().unwrap_or_default();
+
+        if pattern.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            pattern,
+            replacement,
+            whole_file,
+            global: flags.contains('g'),
+            confirm: flags.contains('c'),
+        })
+    }
+
+    /// Whether the substitute touches the given row.
+    pub fn applies_to(&self, row: usize, cursor_row: usize) -> bool {
+        self.whole_file || row == cursor_row
+    }
+
+    /// Returns the character ranges in the line that would get replaced along with what replaces them.
+    /// A pattern that doesn't compile has no matches.
+    pub fn matches(&self, line: &str) -> Vec<(usize, usize, String)> {
+        let pattern = match Pattern::compile(&self.pattern) {
+            Err(_) => return Vec::new(),
+            Ok(pattern) => pattern,
+        };
+
+        let line_len = line.chars().count();
+        let mut matches = Vec::new();
+        let mut col = 0;
+        while let Some((start, end)) = pattern.find_in_line(line, col) {
+            matches.push((start, end, pattern.expand(line, start, &self.replacement)));
+
+            if !self.global {
+                break;
+            }
+            // An empty match would be found again in the same place
+            col = if end == start { end + 1 } else { end };
+            if col > line_len {
+                break;
+            }
+        }
+
+        matches
+    }
+
+    /// Builds the line as it would look after the substitute.
+    /// Each character is paired with whether it came from the replacement.
+    pub fn preview(&self, line: &str) -> Vec<(char, bool)> {
+        let chars = line.chars().collect::<Vec<char>>();
+        let mut output = Vec::with_capacity(chars.len());
+
+        let mut last = 0;
+        for (start, end, replacement) in self.matches(line) {
+            output.extend(chars[last..start].iter().map(|c| (*c, false)));
+            // A line break can't be drawn inside of the row
+            output.extend(replacement.chars().map(|c| (if c == '\n' { '↵' } else { c }, true)));
+            last = end;
+        }
+        output.extend(chars[last..].iter().map(|c| (*c, false)));
+
+        output
+    }
+}
+
+/// A substitute that is worked through one match at a time so that each one can be confirmed.
+#[derive(Debug, Clone)]
+pub struct SubstituteRun {
+    pub substitute: Substitute,
+    pattern: Pattern,
+    /// Where to look for the next match as (column, row).
+    pub position: (usize, usize),
+    /// The last row to look at, which moves down when a replacement adds lines.
+    pub last_row: usize,
+    /// Whether to ask before replacing, answering "All" turns it off.
+    pub ask: bool,
+    pub replaced: usize,
+    /// The row the last replacement ended on.
+    pub last_replaced: Option<usize>,
+}
+
+impl SubstituteRun {
+    /// Fails with a message for the user when the pattern doesn't compile.
+    pub fn new(substitute: Substitute, cursor_row: usize, line_count: usize) -> Result<Self, String> {
+        let pattern = Pattern::compile(&substitute.pattern)?;
+        let (first_row, last_row) = if substitute.whole_file {
+            (0, line_count.saturating_sub(1))
+        }
+        else {
+            (cursor_row, cursor_row)
+        };
+        let ask = substitute.confirm;
+
+        Ok(Self {
+            substitute,
+            pattern,
+            position: (0, first_row),
+            last_row,
+            ask,
+            replaced: 0,
+            last_replaced: None,
+        })
+    }
+
+    /// Finds the next match that the substitute should touch.
+    pub fn next_match(&self, buffer: &Buffer) -> Option<SearchMatch> {
+        let (x, y) = self.position;
+        let line = buffer.get_row(y, 0, usize::MAX).map(|line| line.to_string()).unwrap_or_default();
+        // Past the end of the line the search starts on the next one
+        let position = if x > line.chars().count() { (0, y + 1) } else { (x, y) };
+
+        self.pattern.find_next(buffer, position).filter(|found| found.line <= self.last_row)
+    }
+
+    /// Builds the text that replaces the match.
+    pub fn replacement(&self, buffer: &Buffer, found: SearchMatch) -> String {
+        let line = buffer.get_row(found.line, 0, usize::MAX).map(|line| line.to_string()).unwrap_or_default();
+        self.pattern.expand(&line, found.col, &self.substitute.replacement)
+    }
+
+    /// Moves past a match, skipping over the replacement if one was made.
+    /// Without the `g` flag the rest of the line is skipped too.
+    pub fn advance(&mut self, found: SearchMatch, replacement: Option<&str>) {
+        let (col, row) = match replacement {
+            Some(replacement) => {
+                let added = replacement.matches('\n').count();
+                self.replaced += 1;
+                self.last_row += added;
+                self.last_replaced = Some(found.line + added);
+
+                match replacement.rsplit_once('\n') {
+                    None => (found.col + replacement.chars().count(), found.line),
+                    Some((_, last)) => (last.chars().count(), found.line + added),
+                }
+            },
+            None => (found.col + found.len, found.line),
+        };
+
+        self.position = if !self.substitute.global {
+            (0, row + 1)
+        }
+        else if found.len == 0 {
+            // An empty match would be found again in the same place
+            (col + 1, row)
+        }
+        else {
+            (col, row)
+        };
+    }
+}
@@ -0,0 +1,100 @@
+use std::{env, fs, io, path::{Path, PathBuf}};
+
+use serde::{Deserialize, Serialize};
+
+use crate::buffer::Buffer;
+
+
+/// Bumped whenever the layout of the undo file changes so old files get ignored.
+const UNDO_FILE_VERSION: u32 = 1;
+
+
+/// One step of the undo history as the text that got replaced at a byte offset.
+/// Keeping both sides lets the history be rebuilt going back and forward from the saved text.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UndoStep {
+    pub start: usize,
+    pub removed: String,
+    pub inserted: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct UndoFile {
+    version: u32,
+    /// The hash of the text the history was saved with.
+    hash: u64,
+    /// How many steps of the history were applied to get the saved text.
+    current: usize,
+    steps: Vec<UndoStep>,
+}
+
+
+/// FNV-1a, which unlike the std hasher is guaranteed to give the same hash every run.
+fn hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x100000001b3))
+}
+
+/// The undo files live in `$XDG_DATA_HOME/vi/undo` or `~/.local/share/vi/undo` if no directory was set.
+fn undo_dir(dir: Option<&Path>) -> Option<PathBuf> {
+    if let Some(dir) = dir {
+        return Some(dir.to_path_buf());
+    }
+
+    let data_dir = match env::var_os("XDG_DATA_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".local").join("share"),
+    };
+
+    Some(data_dir.join("vi").join("undo"))
+}
+
+/// The undo file of a file is named after the hash of its absolute path.
+pub fn undo_path(dir: Option<&Path>, file_name: &Path) -> Option<PathBuf> {
+    let path = fs::canonicalize(file_name).ok()?;
+    let name = format!("{:016x}", hash(path.to_string_lossy().as_bytes()));
+
+    Some(undo_dir(dir)?.join(name))
+}
+
+/// Writes the undo history of a buffer that was just saved to the file.
+pub fn save_history(dir: Option<&Path>, file_name: &Path, buffer: &Buffer) -> io::Result<()> {
+    let path = match undo_path(dir, file_name) {
+        None => return Ok(()),
+        Some(path) => path,
+    };
+
+    let (steps, current) = buffer.history();
+    let undo_file = UndoFile {
+        version: UNDO_FILE_VERSION,
+        hash: hash(buffer.to_string().as_bytes()),
+        current,
+        steps,
+    };
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let contents = serde_json::to_string(&undo_file).map_err(io::Error::other)?;
+    fs::write(path, contents)
+}
+
+/// Gives a freshly opened buffer the history that was saved with it.
+/// Nothing happens when there is no undo file or it doesn't belong to the text in the buffer.
+pub fn load_history(dir: Option<&Path>, file_name: &Path, buffer: &mut Buffer) {
+    let contents = match undo_path(dir, file_name).and_then(|path| fs::read_to_string(path).ok()) {
+        None => return,
+        Some(contents) => contents,
+    };
+
+    let undo_file = match serde_json::from_str::<UndoFile>(&contents) {
+        Ok(undo_file) => undo_file,
+        Err(_) => return,
+    };
+
+    if undo_file.version != UNDO_FILE_VERSION || undo_file.hash != hash(buffer.to_string().as_bytes()) {
+        return;
+    }
+
+    buffer.restore_history(&undo_file.steps, undo_file.current);
+}
@@ -1,40 +1,107 @@
 use core::fmt;
 use std::cell::RefCell;
 use std::cmp;
-use std::collections::{HashSet, HashMap};
+use std::collections::{HashSet, HashMap, VecDeque};
 use std::fmt::{Debug, Formatter};
 use std::ops::Index;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 use std::io;
+use std::fs;
 use std::io::Write;
 use std::sync::mpsc::{Sender, Receiver, self, TryRecvError};
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime};
 
-use crossterm::event::{KeyEvent, self, Event};
-use crossterm::style::{Stylize, StyledContent};
+use crossterm::event::{KeyEvent, KeyCode, self, Event, MouseEvent, MouseEventKind, MouseButton, EnableMouseCapture, DisableMouseCapture};
+use crossterm::style::{Stylize, StyledContent, Color};
 use crossterm::{terminal::{self, ClearType}, execute, cursor, queue};
+use encoding_rs::Encoding;
 use uuid::Uuid;
 
 use crate::editor::{EditorMessage, RegisterType};
-use crate::lsp::ControllerMessage;
+use crate::lsp::{ControllerMessage, LspNotification, SharedReceiver, SharedInfo};
+use crate::lsp::lsp_utils::{TextEdit, apply_text_edits, find_root};
+use crate::registers::RegisterValue;
+use crate::editorconfig::EditorConfig;
 use crate::pane::treesitter::TreesitterPane;
-use crate::settings::ColorScheme;
+use crate::settings::{ColorScheme, ClipboardSetting};
 use crate::{apply_colors, settings::Settings};
-use crate::pane::{Pane, PaneContainer};
+use crate::pane::{Pane, PaneContainer, PaneMessage, display_name};
+use crate::pane::popup::PopUpPane;
+use crate::pane::finder::{FinderPane, OpenIn};
+use crate::pane::grep::{GrepPane, GREP_PANE_NAME};
+use crate::pane::explorer::ExplorerPane;
+use crate::quickfix::QuickFix;
+use crate::buffer_list::BufferList;
+use crate::disk::clock_time;
+use crate::mode::{PromptType, ButtonFunction};
 use crate::pane::text::PlainTextPane;
 use crate::treesitter::tree_sitter_scheme;
 
 
+/// How serious a message on the message line is, which picks its color.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageKind {
+    Info,
+    Warning,
+    Error,
+}
+
+impl MessageKind {
+    /// The kind for the type of a message from a language server.
+    pub fn from_lsp(kind: u64) -> Self {
+        match kind {
+            1 => MessageKind::Error,
+            2 => MessageKind::Warning,
+            _ => MessageKind::Info,
+        }
+    }
+}
+
+/// Whether two paths lead to the same file, even when one is relative.
+fn same_file(a: &Path, b: &Path) -> bool {
+    if a == b {
+        return true;
+    }
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// The edits to make in each file, like the ones a rename comes back with.
+pub type FileEdits = Vec<(PathBuf, Vec<TextEdit>)>;
+
+/// The language and id of the server request that asked for some edits, so the server can be answered.
+pub type EditReply = Option<(String, serde_json::Value)>;
+
 pub enum Message {
     HorizontalSplit,
     VerticalSplit,
+    /// Split the active pane and show another pane in the new half.
+    HorizontalSplitWithPane(Rc<RefCell<dyn Pane>>),
+    /// Quit unless a pane in any window has unsaved changes.
+    QuitAll,
+    /// Ask what to do with the files that have unsaved changes before quitting.
+    ConfirmQuit(Vec<String>),
     ForceQuitAll,
     PaneUp,
     PaneDown,
     PaneLeft,
     PaneRight,
+    /// Move every pane into the place of the one after it.
+    RotatePanes,
+    /// Exchange the active pane with the one after it.
+    SwapPane,
     OpenFile(String, Option<(usize, usize)>),
+    /// Open the file given on the command line in place of the empty pane the window starts with.
+    OpenFileStart(String, Option<(usize, usize)>),
+    /// Open a file in the pane above the active one, for lists of places that stay open below it.
+    OpenFileAbove(String, Option<(usize, usize)>),
+    /// Split the active pane and open a file in the new half, side by side when the bool is true.
+    SplitOpenFile(String, bool),
+    /// Open a file and read it with the given encoding.
+    OpenFileWithEncoding(String, &'static Encoding),
     /// go down a layer
     ClosePane(bool, Option<Uuid>),
     CreatePopup(PaneContainer, bool),
@@ -43,11 +110,107 @@ pub enum Message {
     NextTab,
     PreviousTab,
     NthTab(usize),
-    PasteResponse(Option<Box<str>>),
-    Paste(RegisterType),
-    Copy(RegisterType, String),
+    /// The text of a register and whether to paste it after the cursor.
+    PasteResponse(Option<RegisterValue>, bool),
+    /// The bool is whether to paste after the cursor.
+    Paste(RegisterType, bool),
+    Copy(RegisterType, RegisterValue),
+    /// Remember a file that was opened or saved.
+    AddRecentFile(PathBuf),
+    /// Ask the editor for the recently used files.
+    RequestRecentFiles,
+    RecentFiles(Vec<PathBuf>),
+    /// Show an informational message on the message line.
+    SetStatus(String),
+    /// Show a message on the message line until the next keypress or until it times out.
+    SetMessage(String, MessageKind),
+    /// Turn the border of the active pane on or off, or go back to the editor setting with `None`.
+    PaneBorder(Option<bool>),
+    /// Redraw every pane, for when a setting changed how they look.
+    ForceRedraw,
+    /// Read the config files again and use them in every window.
+    ReloadConfig,
+    /// Bind keys in a mode to a command in every window, or take the binding away without a command.
+    MapKeys(String, String, Option<String>),
+    /// Show the bindings of a mode.
+    ListMaps(String),
+    /// Bind or unbind the scrolling of the active pane to the other bound panes.
+    ScrollBind(bool),
+    /// Stop recording a macro or start waiting for the register to record into.
+    RecordMacro,
+    /// Wait for the register of a macro and play it some number of times.
+    PlayMacro(usize),
+    MacroKeys(Vec<KeyEvent>, usize),
+    /// Move the cursor of the active pane to a column and row in its buffer.
+    SetCursorLocation((usize, usize)),
+    /// Open the last closed file in a new pane.
+    Reopen,
+    /// Show the problems found in the settings.
+    CheckHealth,
+    /// Search the files of the project for some text.
+    Grep(String),
+    /// Stop the search of the project that is running.
+    CancelGrep,
+    /// Show what the search of the project finds in a pane below the active one.
+    ShowQuickFix(Rc<RefCell<QuickFix>>),
+    NextQuickFix,
+    PreviousQuickFix,
+    /// Ask the editor for the contents of the registers.
+    RequestRegisters,
+    Registers(Vec<(String, RegisterValue)>),
+    /// Make edits from a language server to other files, whether they are open or not.
+    /// When the server asked for the edit, the language and id of its request are there to answer it.
+    ApplyEdits(FileEdits, EditReply),
+    /// Start the language server of a language again.
+    RestartLsp(String),
+    /// Stop the language server of a language.
+    StopLsp(String),
+    /// List the language servers that are running.
+    LspStatus,
+    /// Show what the language servers logged.
+    LspLog,
+    /// Complete the word at the cursor of the active pane with the words of the open buffers.
+    CompleteWords,
+    /// Open the popup that finds the files of the project by name.
+    FindFile,
+    /// Show the buffers of the window in a popup.
+    ListBuffers,
+    /// Show a buffer in the active pane, picked by its number or part of its name.
+    SwitchBuffer(String),
+    /// Show the buffer that was in the active pane before the current one.
+    AlternateBuffer,
+    /// Delete a buffer, or the one in the active pane with `None`.
+    /// The bool is whether to skip asking about unsaved changes.
+    DeleteBuffer(Option<String>, bool),
+}
+
+/// What to do with the next key after `q` or `@`.
+enum MacroPending {
+    Record,
+    Play(usize),
+}
+
+/// A border between panes that is being dragged with the mouse.
+#[derive(Debug, Clone)]
+struct BorderDrag {
+    /// Whether the border runs up and down, so it moves left and right.
+    vertical: bool,
+    /// The column or row where the panes after the border start.
+    line: usize,
+    /// The panes that end at the border and the ones that start at it.
+    before: Vec<Uuid>,
+    after: Vec<Uuid>,
 }
 
+/// How many keys macros can play in a row before we assume one is calling itself forever.
+const MAX_MACRO_KEYS: usize = 100000;
+
+/// The rows at the bottom of the window for the status bar and the message line.
+const STATUS_ROWS: usize = 2;
+
+/// How often to check on panes that are waiting, like for an answer from a language server.
+const WAITING_POLL: Duration = Duration::from_millis(50);
+
 
 
 pub struct Window{
@@ -68,7 +231,36 @@ pub struct Window{
     skip: bool,
     lsp_responder: Sender<ControllerMessage>,
     lsp_listener: Rc<Receiver<ControllerMessage>>,
-    
+    /// What the server of each language can do, for the panes of the language.
+    lsp_infos: HashMap<String, SharedInfo>,
+    /// The channel for the recent files popup.
+    recent_receiver: Option<Receiver<PaneMessage>>,
+    /// The channel for the file finder popup.
+    finder_receiver: Option<Receiver<(String, OpenIn)>>,
+    /// Every file opened in the window, even the ones no pane shows.
+    buffer_list: BufferList,
+    /// The channel for the buffer list popup.
+    buffer_receiver: Option<Receiver<PaneMessage>>,
+    /// The buffer with unsaved changes that is waiting for the user to confirm deleting it.
+    pending_delete: Option<(Receiver<PaneMessage>, usize)>,
+    /// The channel for the popup that asks about unsaved changes before quitting.
+    pending_quit: Option<Receiver<PaneMessage>>,
+    /// The edits to files that aren't open, waiting for the user to confirm them.
+    pending_edits: Option<(Receiver<PaneMessage>, FileEdits, EditReply)>,
+    /// The message on the message line and when it was shown.
+    message: Option<(String, MessageKind, Instant)>,
+    /// The panes that scroll together and the row offset each had when we last looked.
+    scroll_bound: HashMap<Uuid, usize>,
+    /// The register and keys of the macro being recorded.
+    recording: Option<(char, Vec<KeyEvent>)>,
+    macro_pending: Option<MacroPending>,
+    last_macro: Option<char>,
+    /// Keys from a macro that haven't been played yet.
+    macro_keys: VecDeque<KeyEvent>,
+    macro_keys_played: usize,
+    /// Whether the terminal is sending us mouse events.
+    mouse_capture: bool,
+    border_drag: Option<BorderDrag>,
 }
 
 impl Window {
@@ -81,9 +273,10 @@ impl Window {
 
         let channels = mpsc::channel();
         
+        // Without a terminal, like in tests, we pretend to have a small one
         let win_size = terminal::size()
-            .map(|(w, h)| (w as usize, h as usize - 1))// -1 for trailing newline and -1 for command bar
-            .unwrap();
+            .map(|(w, h)| (w as usize, (h as usize).saturating_sub(STATUS_ROWS)))
+            .unwrap_or((80, 24 - STATUS_ROWS));
         let pane: Rc<RefCell<dyn Pane>> = Rc::new(RefCell::new(PlainTextPane::new(settings.clone(), channels.0.clone())));
 
         pane.borrow_mut().set_cursor_size(win_size);
@@ -114,6 +307,23 @@ impl Window {
             skip: false,
             lsp_listener,
             lsp_responder,
+            lsp_infos: HashMap::new(),
+            recent_receiver: None,
+            finder_receiver: None,
+            buffer_list: BufferList::new(),
+            buffer_receiver: None,
+            pending_delete: None,
+            pending_quit: None,
+            pending_edits: None,
+            message: None,
+            scroll_bound: HashMap::new(),
+            recording: None,
+            macro_pending: None,
+            last_macro: None,
+            macro_keys: VecDeque::new(),
+            macro_keys_played: 0,
+            mouse_capture: false,
+            border_drag: None,
         }
     }
 
@@ -173,208 +383,372 @@ impl Window {
         }
     }
 
+    /// The settings for a file with the properties from any `.editorconfig` layered over the editor's settings.
+    /// Files without any get the editor's settings so that `set` changes them too.
+    fn settings_for(&self, filename: &Path) -> Rc<RefCell<Settings>> {
+        if !self.settings.borrow().editor_settings.editorconfig {
+            return self.settings.clone();
+        }
+
+        let config = EditorConfig::find(filename);
+        if config.is_empty() {
+            return self.settings.clone();
+        }
+
+        let mut settings = self.settings.borrow().clone();
+        config.apply(&mut settings.editor_settings);
+        Rc::new(RefCell::new(settings))
+    }
+
+    /// Uses settings that were read from the config files, in this window and the panes in it.
+    /// Panes with settings of their own from an `.editorconfig` get those put over the new settings again.
+    pub fn apply_settings(&mut self, settings: Settings) {
+        self.duration = Duration::from_millis(settings.editor_settings.key_timeout);
+        *self.settings.borrow_mut() = settings;
+
+        for pane in self.all_panes() {
+            let pane_settings = pane.borrow().get_settings();
+            let path = pane.borrow().get_filename().clone();
+            if let (Some(path), false) = (path, Rc::ptr_eq(&pane_settings, &self.settings)) {
+                let settings = self.settings_for(&path).borrow().clone();
+                *pane_settings.borrow_mut() = settings;
+            }
+            pane.borrow_mut().settings_changed();
+        }
+    }
+
+    /// Binds keys like `:map` does, or takes the binding away without a command, in this window and the panes in it.
+    pub fn map_keys(&mut self, mode: &str, keys: &str, command: Option<&str>) -> Result<String, String> {
+        let message = self.settings.borrow_mut().map_keys(mode, keys, command)?;
+
+        for pane in self.all_panes() {
+            let pane_settings = pane.borrow().get_settings();
+            if !Rc::ptr_eq(&pane_settings, &self.settings) {
+                pane_settings.borrow_mut().mode_keybindings = self.settings.borrow().mode_keybindings.clone();
+            }
+            pane.borrow_mut().settings_changed();
+        }
+        Ok(message)
+    }
+
+    /// Every pane in the window once, including the buffers that aren't shown.
+    fn all_panes(&self) -> Vec<Rc<RefCell<dyn Pane>>> {
+        let mut panes: Vec<Rc<RefCell<dyn Pane>>> = Vec::new();
+        let all = self.panes.iter()
+            .flatten()
+            .map(|container| container.get_pane())
+            .chain(self.buffer_list.entries().iter().map(|entry| entry.pane.clone()));
+        for pane in all {
+            if !panes.iter().any(|other| Rc::ptr_eq(other, &pane)) {
+                panes.push(pane);
+            }
+        }
+        panes
+    }
+
+    /// Asks the controller for the language server of the language.
+    /// Servers from the settings come first, then the built in ones.
+    /// The server gets told about the project `filename` is in.
+    fn lsp_client_for(&mut self, language: &str, filename: &Path) -> Option<(Sender<ControllerMessage>, SharedReceiver)> {
+        let config = self.settings.borrow().lsp_servers.get(language).cloned();
+        let root = find_root(filename);
+        self.lsp_responder.send(ControllerMessage::CreateClient(language.into(), config, Some(root), self.lsp_responder.clone())).ok()?;
+
+        match self.controller_answer() {
+            Some(ControllerMessage::ClientCreated(language_rcv, info)) => {
+                self.lsp_infos.insert(language.to_string(), info);
+                Some((self.lsp_responder.clone(), language_rcv))
+            },
+            Some(ControllerMessage::NoClient(Some(reason))) => {
+                self.show_message(reason, MessageKind::Error);
+                None
+            },
+            _ => None,
+        }
+    }
+
+    /// Waits for the controller to answer, skipping anything else it sends.
+    fn controller_answer(&self) -> Option<ControllerMessage> {
+        loop {
+            match self.lsp_listener.recv() {
+                Ok(ControllerMessage::Response(_)) | Ok(ControllerMessage::Resend(..)) => continue,
+                Ok(ControllerMessage::ShowMessage(language, kind, text)) => {
+                    let message = format!("{}: {}", language, text);
+                    let _ = self.editor_sender.send(EditorMessage::LspMessage(message, MessageKind::from_lsp(kind)));
+                },
+                Ok(message) => return Some(message),
+                Err(_) => return None,
+            }
+        }
+    }
+
+    fn restart_lsp(&mut self, language: &str) {
+        let config = self.settings.borrow().lsp_servers.get(language).cloned();
+        if self.lsp_responder.send(ControllerMessage::RestartClient(language.into(), config, self.lsp_responder.clone())).is_err() {
+            return;
+        }
+
+        match self.controller_answer() {
+            Some(ControllerMessage::ClientCreated(language_rcv, info)) => {
+                self.lsp_infos.insert(language.to_string(), info.clone());
+                for pane in self.all_panes() {
+                    let mut pane = pane.borrow_mut();
+                    if pane.get_language() == Some(language) {
+                        pane.set_lsp_info(info.clone());
+                        pane.lsp_restarted((self.lsp_responder.clone(), language_rcv.clone()));
+                    }
+                }
+                self.show_message(format!("Restarted the language server for {}", language), MessageKind::Info);
+            },
+            Some(ControllerMessage::NoClient(Some(reason))) => self.show_message(reason, MessageKind::Error),
+            _ => self.show_message(format!("No language server for {}", language), MessageKind::Warning),
+        }
+    }
+
+    /// Gives the active pane the text of the other open buffers to complete words from.
+    fn complete_words(&mut self) {
+        let active = self.panes[self.active_layer][self.active_panes[self.active_layer]].get_pane();
+
+        let mut panes: Vec<Rc<RefCell<dyn Pane>>> = vec![active];
+        for container in self.panes[0].iter() {
+            let pane = container.get_pane();
+            if !panes.iter().any(|seen| Rc::ptr_eq(seen, &pane)) {
+                panes.push(pane);
+            }
+        }
+        let others = panes[1..].iter()
+            .map(|pane| pane.borrow().buffer_to_string())
+            .collect();
+
+        self.panes[self.active_layer][self.active_panes[self.active_layer]].complete_words(others);
+    }
+
+    fn stop_lsp(&mut self, language: &str) {
+        if self.lsp_responder.send(ControllerMessage::StopClient(language.into())).is_err() {
+            return;
+        }
+
+        match self.controller_answer() {
+            Some(ControllerMessage::ClientStopped) => {
+                self.show_message(format!("Stopped the language server for {}", language), MessageKind::Info);
+            },
+            _ => self.show_message(format!("No language server is running for {}", language), MessageKind::Warning),
+        }
+    }
+
+    /// Lists the running language servers with their process ids.
+    fn show_lsp_status(&mut self) {
+        if self.lsp_responder.send(ControllerMessage::Status).is_err() {
+            return;
+        }
+        let servers = match self.controller_answer() {
+            Some(ControllerMessage::ClientStatus(servers)) => servers,
+            _ => Vec::new(),
+        };
+        if servers.is_empty() {
+            self.show_message("No language servers are running".to_string(), MessageKind::Info);
+            return;
+        }
+
+        // Nothing needs to come back from picking a line
+        let (send, _) = mpsc::channel();
+        let (_, recv2) = mpsc::channel();
+
+        let mut buttons = Vec::new();
+        let mut width = "Language Servers".len();
+        for (language, status) in servers.iter() {
+            let line = format!("{} ({})", language, status);
+            width = width.max(line.chars().count());
+
+            let function: ButtonFunction = Box::new(|_| String::new());
+            buttons.push((line, function));
+        }
+
+        let buttons = PromptType::Button(buttons, 0);
+        let prompt = vec!["Language Servers".to_string()];
+
+        let pane = PopUpPane::new_dropdown(
+            self.settings.clone(),
+            prompt,
+            self.channels.0.clone(),
+            send,
+            recv2,
+            buttons,
+            true
+        );
+        let pane = Rc::new(RefCell::new(pane));
+
+        let size = ((width + 2).min(self.size.0), (servers.len() + 3).min(self.size.1));
+        let pos = ((self.size.0 - size.0) / 2, (self.size.1 - size.1) / 2);
+
+        let mut container = PaneContainer::new(self.size, size, pane, self.settings.clone());
+        container.set_position(pos);
+        container.set_size(size);
+        container.set_move_not_resize(true);
+
+        self.create_popup(container, true);
+    }
+
+    /// Lists what the language servers logged with the newest line first.
+    fn show_lsp_log(&mut self) {
+        if self.lsp_responder.send(ControllerMessage::Log).is_err() {
+            return;
+        }
+        let lines = match self.controller_answer() {
+            Some(ControllerMessage::LogLines(lines)) => lines,
+            _ => Vec::new(),
+        };
+        if lines.is_empty() {
+            self.show_message("The language servers haven't logged anything".to_string(), MessageKind::Info);
+            return;
+        }
+
+        // Nothing needs to come back from picking a line
+        let (send, _) = mpsc::channel();
+        let (_, recv2) = mpsc::channel();
+
+        let max_width = self.size.0.saturating_sub(2);
+        let mut buttons = Vec::new();
+        let mut width = "Language Server Log".len();
+        for line in lines.iter().rev() {
+            let line = line.chars().take(max_width).collect::<String>();
+            width = width.max(line.chars().count());
+
+            let function: ButtonFunction = Box::new(|_| String::new());
+            buttons.push((line, function));
+        }
+
+        let buttons = PromptType::Button(buttons, 0);
+        let prompt = vec!["Language Server Log".to_string()];
+
+        let pane = PopUpPane::new_dropdown(
+            self.settings.clone(),
+            prompt,
+            self.channels.0.clone(),
+            send,
+            recv2,
+            buttons,
+            true
+        );
+        let pane = Rc::new(RefCell::new(pane));
+
+        let size = ((width + 2).min(self.size.0), (lines.len() + 3).min(self.size.1));
+        let pos = ((self.size.0 - size.0) / 2, (self.size.1 - size.1) / 2);
+
+        let mut container = PaneContainer::new(self.size, size, pane, self.settings.clone());
+        container.set_position(pos);
+        container.set_size(size);
+        container.set_move_not_resize(true);
+
+        self.create_popup(container, true);
+    }
+
     fn file_opener(&mut self, filename: PathBuf) -> io::Result<Rc<RefCell<dyn Pane>>> {
         //eprintln!("Opening file: {:?}", filename);
+        if filename.is_dir() {
+            return Ok(Rc::new(RefCell::new(ExplorerPane::new(self.settings.clone(), filename, self.channels.0.clone()))));
+        }
+        let settings = self.settings_for(&filename);
         let file_type = filename.extension().and_then(|s| s.to_str()).unwrap_or("txt").to_string();
 
         let pane: Rc<RefCell<dyn Pane>> = match file_type.as_str() {
             "scm" => {
+                let lsp_client = self.lsp_client_for("scheme", &filename);
                 let language = unsafe { tree_sitter_scheme() };
-                let mut pane = TreesitterPane::new(self.settings.clone(), self.channels.0.clone(), language, "scheme", None);
+                let mut pane = TreesitterPane::new(settings.clone(), self.channels.0.clone(), language, "scheme", lsp_client);
                 pane.open_file(&filename)?;
-                pane.backup_buffer();
                 Rc::new(RefCell::new(pane))
             },
             "rs" => {
-
-                self.lsp_responder.send(ControllerMessage::CreateClient("rust".to_string().into())).unwrap();
-
-                let lsp_client;
-
-                loop {
-                        
-                    match self.lsp_listener.try_recv() {
-                        Ok(ControllerMessage::ClientCreated(language_rcv)) => {
-                            lsp_client = language_rcv;
-                            break;
-                        },
-                        Ok(_) => {
-                            continue;
-                        },
-                        Err(TryRecvError::Empty) => {
-                            continue;
-                        },
-                        Err(TryRecvError::Disconnected) => {
-                            unreachable!();
-                        },
-                    }
-                }
-
-                let lsp_client = Some((self.lsp_responder.clone(), lsp_client));
-                
+                let lsp_client = self.lsp_client_for("rust", &filename);
                 let language = tree_sitter_rust::language();
-                let mut pane = TreesitterPane::new(self.settings.clone(), self.channels.0.clone(), language,"rust", lsp_client);
+                let mut pane = TreesitterPane::new(settings.clone(), self.channels.0.clone(), language, "rust", lsp_client);
                 pane.open_file(&filename)?;
-                pane.backup_buffer();
                 Rc::new(RefCell::new(pane))
             }
             //todo: move h to C++ since there is no easy way of knowing which lang it is
             "c" | "h" => {
-
-                self.lsp_responder.send(ControllerMessage::CreateClient("c".to_string().into())).unwrap();
-
-                let lsp_client;
-                
-                loop {
-
-                    match self.lsp_listener.try_recv() {
-                        Ok(ControllerMessage::ClientCreated(language_rcv)) => {
-                            lsp_client = language_rcv;
-                            break;
-                        },
-                        Ok(_) => {
-                            continue;
-                        },
-                        Err(TryRecvError::Empty) => {
-                            continue;
-                        },
-                        Err(TryRecvError::Disconnected) => {
-                            unreachable!();
-                        },
-                    }
-                }
-
-                let lsp_client = Some((self.lsp_responder.clone(), lsp_client));
-                
+                let lsp_client = self.lsp_client_for("c", &filename);
                 let language = tree_sitter_c::language();
-                let mut pane = TreesitterPane::new(self.settings.clone(), self.channels.0.clone(), language,"c", lsp_client);
+                let mut pane = TreesitterPane::new(settings.clone(), self.channels.0.clone(), language, "c", lsp_client);
                 pane.open_file(&filename)?;
-                pane.backup_buffer();
                 Rc::new(RefCell::new(pane))
             }
             "cpp" | "hpp" /*| "h"*/ => {
-
-                self.lsp_responder.send(ControllerMessage::CreateClient("cpp".to_string().into())).unwrap();
-
-                let lsp_client = match self.lsp_listener.recv().unwrap() {
-                    ControllerMessage::ClientCreated(language_rcv) => {
-                        language_rcv
-                    },
-                    _ => unreachable!(),
-                };
-
-                let lsp_client = Some((self.lsp_responder.clone(), lsp_client));
-                
+                let lsp_client = self.lsp_client_for("cpp", &filename);
                 let language = tree_sitter_cpp::language();
-                let mut pane = TreesitterPane::new(self.settings.clone(), self.channels.0.clone(), language,"cpp", lsp_client);
+                let mut pane = TreesitterPane::new(settings.clone(), self.channels.0.clone(), language, "cpp", lsp_client);
                 pane.open_file(&filename)?;
-                pane.backup_buffer();
                 Rc::new(RefCell::new(pane))
             }
             "py" => {
-
-                self.lsp_responder.send(ControllerMessage::CreateClient("python".to_string().into())).unwrap();
-
-                let lsp_client = match self.lsp_listener.recv().unwrap() {
-                    ControllerMessage::ClientCreated(language_rcv) => {
-                        language_rcv
-                    },
-                    _ => unreachable!(),
-                };
-
-                let lsp_client = Some((self.lsp_responder.clone(), lsp_client));
-                
+                let lsp_client = self.lsp_client_for("python", &filename);
                 let language = tree_sitter_python::language();
-                let mut pane = TreesitterPane::new(self.settings.clone(), self.channels.0.clone(), language,"python", lsp_client);
+                let mut pane = TreesitterPane::new(settings.clone(), self.channels.0.clone(), language, "python", lsp_client);
                 pane.open_file(&filename)?;
-                pane.backup_buffer();
                 Rc::new(RefCell::new(pane))
             }
             "lsp" => {
+                let lsp_client = self.lsp_client_for("commonlisp", &filename);
                 let language = tree_sitter_commonlisp::language();
-                let mut pane = TreesitterPane::new(self.settings.clone(), self.channels.0.clone(), language,"commonlisp", None);
+                let mut pane = TreesitterPane::new(settings.clone(), self.channels.0.clone(), language, "commonlisp", lsp_client);
                 pane.open_file(&filename)?;
-                pane.backup_buffer();
                 Rc::new(RefCell::new(pane))
             }
             "swift" => {
-
-                self.lsp_responder.send(ControllerMessage::CreateClient("swift".to_string().into())).unwrap();
-
-                let lsp_client = match self.lsp_listener.recv().unwrap() {
-                    ControllerMessage::ClientCreated(language_rcv) => {
-                        language_rcv
-                    },
-                    _ => unreachable!(),
-                };
-
-                let lsp_client = Some((self.lsp_responder.clone(), lsp_client));
-                
+                let lsp_client = self.lsp_client_for("swift", &filename);
                 let language = tree_sitter_swift::language();
-                let mut pane = TreesitterPane::new(self.settings.clone(), self.channels.0.clone(), language,"swift", lsp_client);
+                let mut pane = TreesitterPane::new(settings.clone(), self.channels.0.clone(), language, "swift", lsp_client);
                 pane.open_file(&filename)?;
-                pane.backup_buffer();
                 Rc::new(RefCell::new(pane))
             }
             "go" => {
-
-                self.lsp_responder.send(ControllerMessage::CreateClient("go".to_string().into())).unwrap();
-
-                let lsp_client = match self.lsp_listener.recv().unwrap() {
-                    ControllerMessage::ClientCreated(language_rcv) => {
-                        language_rcv
-                    },
-                    _ => unreachable!(),
-                };
-
-                let lsp_client = Some((self.lsp_responder.clone(), lsp_client));
-                
+                let lsp_client = self.lsp_client_for("go", &filename);
                 let language = tree_sitter_go::language();
-                let mut pane = TreesitterPane::new(self.settings.clone(), self.channels.0.clone(), language,"go", lsp_client);
+                let mut pane = TreesitterPane::new(settings.clone(), self.channels.0.clone(), language, "go", lsp_client);
                 pane.open_file(&filename)?;
-                pane.backup_buffer();
                 Rc::new(RefCell::new(pane))
             }
             "sh" => {
-
-                self.lsp_responder.send(ControllerMessage::CreateClient("bash".to_string().into())).unwrap();
-
-                let lsp_client = match self.lsp_listener.recv().unwrap() {
-                    ControllerMessage::ClientCreated(language_rcv) => {
-                        language_rcv
-                    },
-                    _ => unreachable!(),
-                };
-
-                let lsp_client = Some((self.lsp_responder.clone(), lsp_client));
-                
+                let lsp_client = self.lsp_client_for("bash", &filename);
                 let language = tree_sitter_bash::language();
-                let mut pane = TreesitterPane::new(self.settings.clone(), self.channels.0.clone(), language,"bash", lsp_client);
+                let mut pane = TreesitterPane::new(settings.clone(), self.channels.0.clone(), language, "bash", lsp_client);
                 pane.open_file(&filename)?;
-                pane.backup_buffer();
                 Rc::new(RefCell::new(pane))
             }
             "js" => {
+                let lsp_client = self.lsp_client_for("javascript", &filename);
                 let language = tree_sitter_javascript::language();
-                let mut pane = TreesitterPane::new(self.settings.clone(), self.channels.0.clone(), language,"javascript", None);
+                let mut pane = TreesitterPane::new(settings.clone(), self.channels.0.clone(), language, "javascript", lsp_client);
+                pane.open_file(&filename)?;
+                Rc::new(RefCell::new(pane))
+            }
+            "java" => {
+                let lsp_client = self.lsp_client_for("java", &filename);
+                let language = tree_sitter_java::language();
+                let mut pane = TreesitterPane::new(settings.clone(), self.channels.0.clone(), language, "java", lsp_client);
                 pane.open_file(&filename)?;
-                pane.backup_buffer();
                 Rc::new(RefCell::new(pane))
             }
             "cs" => {
+                let lsp_client = self.lsp_client_for("csharp", &filename);
                 let language = tree_sitter_c_sharp::language();
-                let mut pane = TreesitterPane::new(self.settings.clone(), self.channels.0.clone(), language,"csharp", None);
+                let mut pane = TreesitterPane::new(settings.clone(), self.channels.0.clone(), language, "csharp", lsp_client);
                 pane.open_file(&filename)?;
-                pane.backup_buffer();
                 Rc::new(RefCell::new(pane))
             }
             "txt" | _ => {
-                let mut pane = PlainTextPane::new(self.settings.clone(), self.channels.0.clone());
+                let mut pane = PlainTextPane::new(settings.clone(), self.channels.0.clone());
                 pane.open_file(&filename)?;
-                pane.backup_buffer();
                 Rc::new(RefCell::new(pane))
             }
         };
+        let language = pane.borrow().get_language().map(str::to_string);
+        if let Some(info) = language.and_then(|language| self.lsp_infos.get(&language)) {
+            pane.borrow_mut().set_lsp_info(info.clone());
+        }
+        self.editor_sender.send(EditorMessage::AddRecentFile(filename)).unwrap();
+        self.buffer_list.add(pane.clone());
         Ok(pane)
     }
 
@@ -385,6 +759,19 @@ impl Window {
         Ok(self.panes[self.active_layer].len() - 1)
     }
 
+    /// Replaces the pane the window starts with so quitting the file doesn't leave the empty pane behind.
+    fn open_file_start(&mut self, filename: String, pos: Option<(usize, usize)>) -> io::Result<()> {
+        let pane = self.file_opener(filename.into())?;
+
+        let container = &mut self.panes[0][self.active_panes[0]];
+        container.change_pane(pane);
+        if let Some(location) = pos {
+            container.set_cursor_location(location);
+        }
+        container.scroll_cursor();
+        Ok(())
+    }
+
     fn switch_pane(&mut self, filename: String, pos: Option<(usize, usize)>) -> io::Result<()> {
         let filename = PathBuf::from(filename);
         //eprintln!("switching to pane: {:?}", filename);
@@ -403,26 +790,26 @@ impl Window {
             self.open_file(filename)?
         };
 
+        self.swap_with_active(new_active_pane_index, pos)
+    }
+
+    /// Shows the pane of another container in the active one and leaves the pane that was there in its place.
+    fn swap_with_active(&mut self, new_active_pane_index: usize, pos: Option<(usize, usize)>) -> io::Result<()> {
         let active_pane = self.panes[self.active_layer][self.active_panes[self.active_layer]].get_pane().clone();
         let new_active_pane = self.panes[self.active_layer][new_active_pane_index].get_pane().clone();
 
         active_pane.borrow_mut().reset();
+        if !Rc::ptr_eq(&active_pane, &new_active_pane) {
+            self.buffer_list.set_alternate(&active_pane);
+        }
 
         self.panes[self.active_layer][self.active_panes[self.active_layer]].change_pane(new_active_pane);
         self.panes[self.active_layer][new_active_pane_index].change_pane(active_pane);
 
         self.panes[self.active_layer][self.active_panes[self.active_layer]].get_pane().borrow_mut().set_cursor_size(self.panes[self.active_layer][self.active_panes[self.active_layer]].get_size());
 
-        if let Some((x, y)) = pos {
-            let pane = self.panes[self.active_layer][self.active_panes[self.active_layer]].get_pane().clone();
-            
-            pane.borrow()
-                .get_cursor()
-                .borrow_mut()
-                .set_cursor(crate::cursor::CursorMove::Amount(x),
-                            crate::cursor::CursorMove::Amount(y),
-                            &*pane.borrow(),
-                            (0, 0));
+        if let Some(location) = pos {
+            self.panes[self.active_layer][self.active_panes[self.active_layer]].set_cursor_location(location);
         }
 
         self.panes[self.active_layer][self.active_panes[self.active_layer]].scroll_cursor();
@@ -578,6 +965,63 @@ impl Window {
     }
 
 
+    /// Cycles the panes through each other's places.
+    /// The last pane moves to where the first one was.
+    fn rotate_panes(&mut self) {
+        if self.panes[0].len() < 2 {
+            return;
+        }
+        let active = self.panes[0][self.active_panes[0]].get_uuid();
+
+        let geometry = self.panes[0].iter().map(|pane| pane.get_geometry()).collect::<Vec<_>>();
+        self.panes[0].rotate_right(1);
+
+        for (pane, geometry) in self.panes[0].iter_mut().zip(geometry) {
+            pane.set_geometry(geometry);
+        }
+
+        self.reindex_panes(active);
+    }
+
+    /// Exchanges the active pane with the next one, or the previous one if it is the last pane.
+    fn swap_pane(&mut self) {
+        if self.panes[0].len() < 2 {
+            return;
+        }
+        let index = self.active_panes[0];
+        let other = if index + 1 < self.panes[0].len() {
+            index + 1
+        }
+        else {
+            index - 1
+        };
+        let active = self.panes[0][index].get_uuid();
+
+        let index_geometry = self.panes[0][index].get_geometry();
+        let other_geometry = self.panes[0][other].get_geometry();
+
+        self.panes[0].swap(index, other);
+        self.panes[0][index].set_geometry(index_geometry);
+        self.panes[0][other].set_geometry(other_geometry);
+
+        self.reindex_panes(active);
+    }
+
+    /// Rebuilds the pane lookup after the panes were reordered and keeps the given pane active.
+    fn reindex_panes(&mut self, active: Uuid) {
+        self.id_to_pane = HashMap::new();
+
+        for (i, layer) in self.panes.iter().enumerate() {
+            for (j, pane) in layer.iter().enumerate() {
+                self.id_to_pane.insert(pane.get_uuid(), (i, j));
+            }
+        }
+
+        if let Some((_, index)) = self.id_to_pane.get(&active) {
+            self.active_panes[0] = *index;
+        }
+    }
+
     fn pane_up(&mut self) {
         let ((x1, y1), (x2, _)) = self.panes[self.active_layer][self.active_panes[self.active_layer]].get_corners();
 
@@ -661,181 +1105,1500 @@ impl Window {
 
         let pane_middle = (y1 + y2) / 2;
 
-        let mut pane_index = None;
-        // This loop tries to find the pane that is to the left of the current pane
-        for (i, pane) in self.panes[self.active_layer].iter().enumerate() {
-            let ((_, y1), (x2, y2)) = pane.get_corners();
-            if x2 == pane_left && y1 <= pane_middle && pane_middle <= y2 {
-                pane_index = Some(i);
-                break;
+        let mut pane_index = None;
+        // This loop tries to find the pane that is to the left of the current pane
+        for (i, pane) in self.panes[self.active_layer].iter().enumerate() {
+            let ((_, y1), (x2, y2)) = pane.get_corners();
+            if x2 == pane_left && y1 <= pane_middle && pane_middle <= y2 {
+                pane_index = Some(i);
+                break;
+            }
+        }
+
+        match pane_index {
+            Some(index) => {
+                self.active_panes[self.active_layer] = index;
+            }
+            None => {}
+        }
+    }
+
+    fn read_messages(&mut self) -> io::Result<()> {
+        match self.channels.1.try_recv() {
+            Ok(message) => {
+                match message {
+                    Message::HorizontalSplit => {
+                        self.horizontal_split();
+                        Ok(())
+                    }
+                    Message::VerticalSplit => {
+                        self.vertical_split();
+                        Ok(())
+                    }
+                    Message::HorizontalSplitWithPane(pane) => {
+                        self.split_with_pane(pane);
+                        self.force_refresh_screen()
+                    }
+                    Message::QuitAll => {
+                        self.skip = true;
+                        self.editor_sender.send(EditorMessage::QuitAll).unwrap();
+                        Ok(())
+                    }
+                    Message::ConfirmQuit(files) => {
+                        self.confirm_quit(files);
+                        self.force_refresh_screen()
+                    }
+                    Message::ForceQuitAll => {
+                        for layers in self.panes.iter_mut() {
+                            for pane in layers.iter_mut() {
+                                pane.close();
+                            }
+                        }
+                        self.editor_sender.send(EditorMessage::Quit).unwrap();
+                        Ok(())
+                    }
+                    Message::RotatePanes => {
+                        self.rotate_panes();
+                        self.force_refresh_screen()
+                    },
+                    Message::SwapPane => {
+                        self.swap_pane();
+                        self.force_refresh_screen()
+                    },
+                    Message::PaneUp => {
+                        self.pane_up();
+                        Ok(())
+                    },
+                    Message::PaneDown => {
+                        self.pane_down();
+                        Ok(())
+                    },
+                    Message::PaneLeft => {
+                        self.pane_left();
+                        Ok(())
+                    },
+                    Message::PaneRight => {
+                        self.pane_right();
+                        Ok(())
+                    },
+                    Message::OpenFile(path, pos) => {
+                        self.switch_pane(path, pos)?;
+                        self.force_refresh_screen()?;
+                        Ok(())
+                    }
+                    Message::OpenFileStart(path, pos) => self.open_file_start(path, pos),
+                    Message::OpenFileAbove(path, pos) => {
+                        self.pane_up();
+                        self.switch_pane(path, pos)?;
+                        self.force_refresh_screen()
+                    }
+                    Message::SplitOpenFile(path, vertical) => {
+                        if vertical {
+                            self.vertical_split();
+                        }
+                        else {
+                            self.horizontal_split();
+                        }
+                        self.switch_pane(path, None)?;
+                        self.force_refresh_screen()
+                    }
+                    Message::OpenFileWithEncoding(path, encoding) => {
+                        self.switch_pane(path, None)?;
+
+                        let pane = self.panes[self.active_layer][self.active_panes[self.active_layer]].get_pane().clone();
+                        pane.borrow_mut().reload_with_encoding(encoding)?;
+                        self.force_refresh_screen()?;
+                        Ok(())
+                    }
+                    Message::ClosePane(go_down, uuid) => {
+                        let (layer, index) = match uuid {
+                            None => (self.active_layer, self.active_panes[self.active_layer]),
+                            Some(uuid) => *self.id_to_pane.get(&uuid).unwrap(),
+                        };
+                        // Only the panes on the bottom layer hold files
+                        if layer == 0 {
+                            let container = &self.panes[layer][index];
+                            if let Some(path) = container.get_filename() {
+                                let location = container.get_cursor_location();
+                                self.editor_sender.send(EditorMessage::ClosedBuffer(path, location)).unwrap();
+                            }
+
+                            // A buffer with unsaved changes stays in the list after its last pane is gone
+                            let pane = container.get_pane();
+                            let shown = self.panes[0].iter()
+                                .enumerate()
+                                .any(|(i, other)| i != index && !other.can_close() && Rc::ptr_eq(&other.get_pane(), &pane));
+                            if !shown && !pane.borrow().is_modified() {
+                                if let Some(number) = self.buffer_list.number_of(&pane) {
+                                    self.buffer_list.remove(number);
+                                }
+                            }
+                        }
+
+                        match uuid {
+                            None => {
+                                //self.panes[self.active_layer].remove(self.active_panes[self.active_layer]);
+                                self.panes[self.active_layer][self.active_panes[self.active_layer]].close();
+                                self.active_panes[self.active_layer] = self.active_panes[self.active_layer].saturating_sub(1);
+
+                                if go_down {
+                                    self.active_layer = self.active_layer.saturating_sub(1);
+                                }
+                            },
+                            Some(uuid) => {
+                                let coords = self.id_to_pane.get(&uuid).unwrap();
+
+                                self.panes[coords.0][coords.1].close();
+
+                                if go_down {
+                                    self.active_layer = coords.0.saturating_sub(1);
+                                }
+                                self.force_refresh_screen()?;
+                            }
+                        }
+
+                        for buffer in self.buffers.iter_mut() {
+                            buffer.hard_clear();
+                        }
+                        
+                        Ok(())
+                    },
+                    Message::CreatePopup(mut container, make_active) => {
+                        container.set_move_not_resize(true);
+                        self.create_popup(container, make_active);
+                        self.force_refresh_screen()?;
+                        Ok(())
+                    },
+                    Message::OpenNewTab => {
+                        self.editor_sender.send(EditorMessage::NewWindow(None)).unwrap();
+                        self.skip = true;
+                        Ok(())
+                    },
+                    Message::OpenNewTabWithPane => {
+                        let pane = self.panes[self.active_layer][self.active_panes[self.active_layer]].get_pane();
+                        self.panes[self.active_layer][self.active_panes[self.active_layer]].close();
+
+                        self.active_panes[self.active_layer] = self.active_panes[self.active_layer].saturating_sub(1);
+
+                        self.editor_sender.send(EditorMessage::NewWindow(Some(pane))).unwrap();
+                        self.skip = true;
+                        Ok(())
+                    },
+                    Message::NextTab => {
+                        self.editor_sender.send(EditorMessage::NextWindow).unwrap();
+                        self.skip = true;
+                        Ok(())
+                    },
+                    Message::PreviousTab => {
+                        self.editor_sender.send(EditorMessage::PrevWindow).unwrap();
+                        self.skip = true;
+                        Ok(())
+                    },
+                    Message::NthTab(n) => {
+                        self.editor_sender.send(EditorMessage::NthWindow(n)).unwrap();
+                        self.skip = true;
+                        Ok(())
+                    },
+                    Message::PasteResponse(value, after) => {
+                        self.skip = true;
+
+                        if let Some(value) = value {
+                            let pane = self.panes[self.active_layer][self.active_panes[self.active_layer]].get_pane();
+                            pane.borrow_mut().paste(&value, after);
+                        }
+                        self.force_refresh_screen()?;
+                        
+                        Ok(())
+                    },
+                    Message::Paste(ty, after) => {
+                        self.skip = true;
+                        let ty = self.default_register(ty);
+                        self.editor_sender.send(EditorMessage::Paste(ty, after)).unwrap();
+                        Ok(())
+                    },
+                    Message::Copy(ty, value) => {
+                        self.skip = true;
+                        let ty = self.default_register(ty);
+                        self.editor_sender.send(EditorMessage::Copy(ty, value)).unwrap();
+                        Ok(())
+                    },
+                    Message::AddRecentFile(path) => {
+                        self.editor_sender.send(EditorMessage::AddRecentFile(path)).unwrap();
+                        Ok(())
+                    },
+                    Message::RequestRecentFiles => {
+                        self.skip = true;
+                        self.editor_sender.send(EditorMessage::RecentFiles).unwrap();
+                        Ok(())
+                    },
+                    Message::RecentFiles(files) => {
+                        self.open_recent_files(files);
+                        self.force_refresh_screen()?;
+                        Ok(())
+                    },
+                    Message::SetStatus(message) => {
+                        self.show_message(message, MessageKind::Info);
+                        Ok(())
+                    },
+                    Message::SetMessage(message, kind) => {
+                        self.show_message(message, kind);
+                        Ok(())
+                    },
+                    Message::PaneBorder(border) => {
+                        self.panes[0][self.active_panes[0]].set_border(border);
+                        self.force_refresh_screen()
+                    },
+                    Message::ReloadConfig => {
+                        self.skip = true;
+                        self.editor_sender.send(EditorMessage::ReloadConfig).unwrap();
+                        Ok(())
+                    },
+                    Message::MapKeys(mode, keys, command) => {
+                        self.skip = true;
+                        self.editor_sender.send(EditorMessage::MapKeys(mode, keys, command)).unwrap();
+                        Ok(())
+                    },
+                    Message::ListMaps(mode) => {
+                        self.show_maps(&mode);
+                        self.force_refresh_screen()
+                    },
+                    Message::ForceRedraw => {
+                        self.force_refresh_screen()
+                    },
+                    Message::CheckHealth => {
+                        self.check_health();
+                        self.force_refresh_screen()
+                    },
+                    Message::RequestRegisters => {
+                        self.skip = true;
+                        self.editor_sender.send(EditorMessage::Registers).unwrap();
+                        Ok(())
+                    },
+                    Message::Registers(registers) => {
+                        self.show_registers(registers);
+                        self.force_refresh_screen()
+                    },
+                    Message::ApplyEdits(changes, reply) => {
+                        self.apply_edits(changes, reply);
+                        self.force_refresh_screen()
+                    },
+                    Message::RestartLsp(language) => {
+                        self.restart_lsp(&language);
+                        self.force_refresh_screen()
+                    },
+                    Message::StopLsp(language) => {
+                        self.stop_lsp(&language);
+                        self.force_refresh_screen()
+                    },
+                    Message::LspStatus => {
+                        self.show_lsp_status();
+                        self.force_refresh_screen()
+                    },
+                    Message::LspLog => {
+                        self.show_lsp_log();
+                        self.force_refresh_screen()
+                    },
+                    Message::CompleteWords => {
+                        self.complete_words();
+                        self.force_refresh_screen()
+                    },
+                    Message::FindFile => {
+                        self.open_file_finder();
+                        self.force_refresh_screen()
+                    },
+                    Message::ListBuffers => {
+                        self.list_buffers();
+                        self.force_refresh_screen()
+                    },
+                    Message::SwitchBuffer(query) => {
+                        match self.buffer_list.find(&query) {
+                            Ok(number) => self.show_buffer(number)?,
+                            Err(error) => self.show_message(error, MessageKind::Error),
+                        }
+                        self.force_refresh_screen()
+                    },
+                    Message::AlternateBuffer => {
+                        match self.buffer_list.alternate() {
+                            Some(number) => self.show_buffer(number)?,
+                            None => self.show_message("No alternate file".to_string(), MessageKind::Error),
+                        }
+                        self.force_refresh_screen()
+                    },
+                    Message::DeleteBuffer(query, force) => {
+                        self.delete_buffer(query, force);
+                        self.force_refresh_screen()
+                    },
+                    Message::Reopen => {
+                        self.skip = true;
+                        self.editor_sender.send(EditorMessage::Reopen).unwrap();
+                        Ok(())
+                    },
+                    Message::Grep(pattern) => {
+                        self.skip = true;
+                        self.editor_sender.send(EditorMessage::Grep(pattern, self.project_root())).unwrap();
+                        Ok(())
+                    },
+                    Message::CancelGrep => {
+                        self.skip = true;
+                        self.editor_sender.send(EditorMessage::CancelGrep).unwrap();
+                        Ok(())
+                    },
+                    Message::ShowQuickFix(quickfix) => {
+                        self.show_quickfix(quickfix);
+                        self.force_refresh_screen()
+                    },
+                    Message::NextQuickFix => {
+                        self.skip = true;
+                        self.editor_sender.send(EditorMessage::NextQuickFix).unwrap();
+                        Ok(())
+                    },
+                    Message::PreviousQuickFix => {
+                        self.skip = true;
+                        self.editor_sender.send(EditorMessage::PreviousQuickFix).unwrap();
+                        Ok(())
+                    },
+                    Message::SetCursorLocation(location) => {
+                        self.panes[self.active_layer][self.active_panes[self.active_layer]].set_cursor_location(location);
+                        Ok(())
+                    },
+                    Message::RecordMacro => {
+                        match self.recording.take() {
+                            Some((name, mut keys)) => {
+                                // The last key is the one that stopped the recording
+                                keys.pop();
+                                self.editor_sender.send(EditorMessage::SetMacro(name, keys)).unwrap();
+                            },
+                            None => {
+                                self.macro_pending = Some(MacroPending::Record);
+                            },
+                        }
+                        Ok(())
+                    },
+                    Message::PlayMacro(count) => {
+                        self.macro_pending = Some(MacroPending::Play(count));
+                        Ok(())
+                    },
+                    Message::MacroKeys(keys, count) => {
+                        if self.macro_keys_played + self.macro_keys.len() + keys.len() * count > MAX_MACRO_KEYS {
+                            self.macro_keys.clear();
+                            self.show_message("Macro stopped, it looks like it calls itself".to_string(), MessageKind::Warning);
+                            return Ok(());
+                        }
+
+                        // Nested macros play before the rest of the macro that called them
+                        for _ in 0..count {
+                            for key in keys.iter().rev() {
+                                self.macro_keys.push_front(*key);
+                            }
+                        }
+                        Ok(())
+                    },
+                    Message::ScrollBind(bind) => {
+                        let container = &self.panes[self.active_layer][self.active_panes[self.active_layer]];
+                        let id = container.get_uuid();
+
+                        if bind {
+                            let offset = container.get_cursor().borrow().row_offset;
+                            self.scroll_bound.insert(id, offset);
+                        }
+                        else {
+                            self.scroll_bound.remove(&id);
+                        }
+                        Ok(())
+                    },
+                    
+                }
+            },
+            Err(_) => Ok(()),
+        }
+        
+    }
+
+    /// Opens a dropdown of the recently used files.
+    /// The file that gets picked comes back through `recent_receiver`.
+    fn open_recent_files(&mut self, files: Vec<PathBuf>) {
+        if files.is_empty() {
+            return;
+        }
+
+        let (send, recv) = mpsc::channel();
+        let (_, recv2) = mpsc::channel();
+
+        let mut buttons = Vec::new();
+        let mut width = "Recent Files".len();
+        for file in files.iter() {
+            let name = file.to_string_lossy().to_string();
+            width = width.max(name.chars().count());
+
+            let path = name.clone();
+            let function: ButtonFunction = Box::new(move |_| path.clone());
+
+            buttons.push((name, function));
+        }
+
+        let buttons = PromptType::Button(buttons, 0);
+        let prompt = vec!["Recent Files".to_string()];
+
+        let pane = PopUpPane::new_dropdown(
+            self.settings.clone(),
+            prompt,
+            self.channels.0.clone(),
+            send,
+            recv2,
+            buttons,
+            true
+        );
+        let pane = Rc::new(RefCell::new(pane));
+
+        let size = ((width + 2).min(self.size.0), (files.len() + 3).min(self.size.1));
+        let pos = ((self.size.0 - size.0) / 2, (self.size.1 - size.1) / 2);
+
+        let mut container = PaneContainer::new(self.size, size, pane, self.settings.clone());
+        container.set_position(pos);
+        container.set_size(size);
+        container.set_move_not_resize(true);
+
+        self.create_popup(container, true);
+        self.recent_receiver = Some(recv);
+    }
+
+    /// The project of the file in the active pane.
+    /// Without a file the project is the current directory.
+    fn project_root(&self) -> PathBuf {
+        match self.panes[0][self.active_panes[0]].get_filename() {
+            Some(filename) => find_root(&filename),
+            None => std::env::current_dir().unwrap_or_else(|_| PathBuf::from(".")),
+        }
+    }
+
+    /// Opens the popup for finding a file in the project of the active pane.
+    /// The file that gets picked comes back through `finder_receiver`.
+    fn open_file_finder(&mut self) {
+        let root = self.project_root();
+
+        let (send, recv) = mpsc::channel();
+        let pane = FinderPane::new(self.settings.clone(), root, self.channels.0.clone(), send);
+        let pane = Rc::new(RefCell::new(pane));
+
+        let size = ((self.size.0 * 3 / 4).max(20).min(self.size.0), (self.size.1 * 2 / 3).max(5).min(self.size.1.saturating_sub(1)));
+        let pos = ((self.size.0 - size.0) / 2, (self.size.1 - size.1) / 2);
+
+        let mut container = PaneContainer::new(self.size, size, pane, self.settings.clone());
+        container.set_position(pos);
+        container.set_size(size);
+        container.set_move_not_resize(true);
+
+        self.create_popup(container, true);
+        self.finder_receiver = Some(recv);
+    }
+
+    /// Shows a buffer in the active pane.
+    /// The buffer that was there is kept in a hidden container, like the ones `open_file` makes.
+    fn show_buffer(&mut self, number: usize) -> io::Result<()> {
+        let pane = match self.buffer_list.get(number) {
+            None => {
+                self.show_message(format!("No buffer {}", number), MessageKind::Error);
+                return Ok(());
+            },
+            Some(pane) => pane,
+        };
+
+        let layer = self.active_layer;
+        if Rc::ptr_eq(&self.panes[layer][self.active_panes[layer]].get_pane(), &pane) {
+            return Ok(());
+        }
+
+        let hidden = self.panes[layer].iter()
+            .position(|container| container.get_size() == (0, 0) && Rc::ptr_eq(&container.get_pane(), &pane));
+        let index = match hidden {
+            Some(index) => index,
+            None => {
+                self.panes[layer].push(PaneContainer::new((0, 0), (0, 0), pane, self.settings.clone()));
+                self.panes[layer].len() - 1
+            },
+        };
+
+        self.swap_with_active(index, None)
+    }
+
+    /// Opens a dropdown of the buffers, marked like `:ls` in vim.
+    /// `%` is the buffer of the active pane, `#` the alternate one, `a` one that is shown and `+` one with unsaved changes.
+    /// The buffer that gets picked comes back through `buffer_receiver`.
+    fn list_buffers(&mut self) {
+        let active = self.panes[0][self.active_panes[0]].get_pane();
+        let alternate = self.buffer_list.alternate();
+
+        let mut lines = Vec::new();
+        for entry in self.buffer_list.entries() {
+            let shown = self.panes[0].iter()
+                .any(|container| container.get_size() != (0, 0) && Rc::ptr_eq(&container.get_pane(), &entry.pane));
+            let current = if Rc::ptr_eq(&active, &entry.pane) {
+                '%'
+            }
+            else if alternate == Some(entry.number) {
+                '#'
+            }
+            else {
+                ' '
+            };
+            let pane = entry.pane.borrow();
+            let line = pane.get_cursor().borrow().get_cursor().1 + 1;
+
+            lines.push((entry.number, format!("{:>3} {}{}{} {}  line {}",
+                                              entry.number,
+                                              current,
+                                              if shown { 'a' } else { ' ' },
+                                              if pane.is_modified() { '+' } else { ' ' },
+                                              entry.name(),
+                                              line)));
+        }
+
+        if lines.is_empty() {
+            self.show_message("No buffers".to_string(), MessageKind::Info);
+            return;
+        }
+
+        let (send, recv) = mpsc::channel();
+        let (_, recv2) = mpsc::channel();
+
+        let mut buttons = Vec::new();
+        let mut width = "Buffers".len();
+        let count = lines.len();
+        for (number, line) in lines {
+            width = width.max(line.chars().count());
+
+            let function: ButtonFunction = Box::new(move |_| number.to_string());
+            buttons.push((line, function));
+        }
+
+        let buttons = PromptType::Button(buttons, 0);
+        let prompt = vec!["Buffers".to_string()];
+
+        let pane = PopUpPane::new_dropdown(
+            self.settings.clone(),
+            prompt,
+            self.channels.0.clone(),
+            send,
+            recv2,
+            buttons,
+            true
+        );
+        let pane = Rc::new(RefCell::new(pane));
+
+        let size = ((width + 2).min(self.size.0), (count + 3).min(self.size.1));
+        let pos = ((self.size.0 - size.0) / 2, (self.size.1 - size.1) / 2);
+
+        let mut container = PaneContainer::new(self.size, size, pane, self.settings.clone());
+        container.set_position(pos);
+        container.set_size(size);
+        container.set_move_not_resize(true);
+
+        self.create_popup(container, true);
+        self.buffer_receiver = Some(recv);
+    }
+
+    /// Deletes a buffer, asking first when it has unsaved changes unless `force` is set.
+    fn delete_buffer(&mut self, query: Option<String>, force: bool) {
+        let number = match query {
+            None => {
+                let active = self.panes[0][self.active_panes[0]].get_pane();
+                match self.buffer_list.number_of(&active) {
+                    None => {
+                        self.show_message("The pane has no buffer".to_string(), MessageKind::Error);
+                        return;
+                    },
+                    Some(number) => number,
+                }
+            },
+            Some(query) => match self.buffer_list.find(&query) {
+                Err(error) => {
+                    self.show_message(error, MessageKind::Error);
+                    return;
+                },
+                Ok(number) => number,
+            },
+        };
+
+        let pane = self.buffer_list.get(number).expect("Buffer was in the list");
+        if force || !pane.borrow().is_modified() {
+            self.remove_buffer(number);
+            return;
+        }
+
+        let (send, recv) = mpsc::channel();
+        let (_, recv2) = mpsc::channel();
+
+        let name = display_name(pane.borrow().get_filename().as_deref());
+        let prompt = vec![format!("{} has unsaved changes, delete it anyway?", name)];
+        let width = prompt[0].chars().count();
+
+        let delete: ButtonFunction = Box::new(|_| "delete".to_string());
+        let cancel: ButtonFunction = Box::new(|_| "cancel".to_string());
+        let buttons = PromptType::Button(vec![("Delete".to_string(), delete), ("Cancel".to_string(), cancel)], 1);
+
+        let pane = PopUpPane::new_dropdown(
+            self.settings.clone(),
+            prompt,
+            self.channels.0.clone(),
+            send,
+            recv2,
+            buttons,
+            true
+        );
+        let pane = Rc::new(RefCell::new(pane));
+
+        let size = ((width + 2).min(self.size.0), 5.min(self.size.1));
+        let pos = ((self.size.0 - size.0) / 2, (self.size.1 - size.1) / 2);
+
+        let mut container = PaneContainer::new(self.size, size, pane, self.settings.clone());
+        container.set_position(pos);
+        container.set_size(size);
+        container.set_move_not_resize(true);
+
+        self.create_popup(container, true);
+        self.pending_delete = Some((recv, number));
+    }
+
+    /// Takes a buffer out of the list and out of every pane.
+    /// Panes that showed it switch to another buffer or close when there are none left.
+    fn remove_buffer(&mut self, number: usize) {
+        let pane = match self.buffer_list.remove(number) {
+            None => return,
+            Some(pane) => pane,
+        };
+
+        if let Some(path) = pane.borrow().get_filename().clone() {
+            let location = pane.borrow().get_cursor().borrow().get_cursor();
+            self.editor_sender.send(EditorMessage::ClosedBuffer(path, location)).unwrap();
+        }
+
+        // The hidden containers are dropped right away since there is nothing to make room for
+        let active = self.panes[0][self.active_panes[0]].get_uuid();
+        self.panes[0].retain(|container| container.get_size() != (0, 0) || !Rc::ptr_eq(&container.get_pane(), &pane));
+        self.reindex_panes(active);
+
+        let other = self.buffer_list.alternate()
+            .or_else(|| self.buffer_list.entries().last().map(|entry| entry.number))
+            .and_then(|number| self.buffer_list.get(number));
+        for container in self.panes[0].iter_mut() {
+            if !Rc::ptr_eq(&container.get_pane(), &pane) {
+                continue;
+            }
+            match &other {
+                Some(other) => {
+                    container.change_pane(other.clone());
+                    let size = container.get_size();
+                    other.borrow_mut().set_cursor_size(size);
+                },
+                None => container.close(),
+            }
+        }
+
+        self.show_message(format!("Deleted buffer {}", number), MessageKind::Info);
+    }
+
+    /// The panes of the window with unsaved changes, including buffers no pane shows anymore.
+    fn modified_panes(&self) -> Vec<Rc<RefCell<dyn Pane>>> {
+        let mut modified: Vec<Rc<RefCell<dyn Pane>>> = Vec::new();
+        let panes = self.panes[0].iter()
+            .map(|container| container.get_pane())
+            .chain(self.buffer_list.entries().iter().map(|entry| entry.pane.clone()));
+        for pane in panes {
+            if pane.borrow().is_modified() && !modified.iter().any(|other| Rc::ptr_eq(other, &pane)) {
+                modified.push(pane);
+            }
+        }
+        modified
+    }
+
+    /// The names of the files with unsaved changes.
+    pub fn modified_files(&self) -> Vec<String> {
+        self.modified_panes().iter()
+            .map(|pane| match pane.borrow().get_filename() {
+                None => "[No Name]".to_string(),
+                Some(path) => path.to_string_lossy().to_string(),
+            })
+            .collect()
+    }
+
+    /// Saves every file with unsaved changes, giving back the ones that couldn't be saved and why.
+    pub fn save_all(&mut self) -> Vec<String> {
+        let mut failed = Vec::new();
+        for pane in self.modified_panes() {
+            let name = match pane.borrow().get_filename() {
+                None => {
+                    failed.push("[No Name]: no file name".to_string());
+                    continue;
+                },
+                Some(path) => path.to_string_lossy().to_string(),
+            };
+
+            if let Err(error) = pane.borrow_mut().save_buffer() {
+                failed.push(format!("{}: {}", name, error));
+            }
+        }
+        failed
+    }
+
+    /// Asks whether to save or throw away the unsaved changes before quitting.
+    /// The answer comes back through `pending_quit`.
+    fn confirm_quit(&mut self, files: Vec<String>) {
+        let (send, recv) = mpsc::channel();
+        let (_, recv2) = mpsc::channel();
+
+        let mut prompt = vec!["No write since last change in:".to_string()];
+        prompt.extend(files);
+        let width = prompt.iter().map(|line| line.chars().count()).max().unwrap_or(0);
+
+        let save: ButtonFunction = Box::new(|_| "save".to_string());
+        let discard: ButtonFunction = Box::new(|_| "discard".to_string());
+        let cancel: ButtonFunction = Box::new(|_| "cancel".to_string());
+        let buttons = PromptType::Button(vec![
+            ("Save All".to_string(), save),
+            ("Discard All".to_string(), discard),
+            ("Cancel".to_string(), cancel),
+        ], 2);
+
+        let pane = PopUpPane::new_dropdown(
+            self.settings.clone(),
+            prompt.clone(),
+            self.channels.0.clone(),
+            send,
+            recv2,
+            buttons,
+            true
+        );
+        let pane = Rc::new(RefCell::new(pane));
+
+        let size = ((width + 2).min(self.size.0), (prompt.len() + 5).min(self.size.1));
+        let pos = ((self.size.0 - size.0) / 2, (self.size.1 - size.1) / 2);
+
+        let mut container = PaneContainer::new(self.size, size, pane, self.settings.clone());
+        container.set_position(pos);
+        container.set_size(size);
+        container.set_move_not_resize(true);
+
+        self.create_popup(container, true);
+        self.pending_quit = Some(recv);
+    }
+
+    /// Splits the active pane and puts the pane in the new half, which becomes the active one.
+    fn split_with_pane(&mut self, pane: Rc<RefCell<dyn Pane>>) {
+        self.horizontal_split();
+
+        let container = &mut self.panes[self.active_layer][self.active_panes[self.active_layer]];
+        container.change_pane(pane);
+        let size = container.get_size();
+        container.get_pane().borrow_mut().set_cursor_size(size);
+    }
+
+    /// Shows the results of a search below the active pane.
+    /// A list that is already open shows the new results by itself.
+    fn show_quickfix(&mut self, quickfix: Rc<RefCell<QuickFix>>) {
+        let open = self.panes[0].iter()
+            .any(|container| !container.can_close() && container.get_pane().borrow().get_name() == GREP_PANE_NAME);
+        if open {
+            return;
+        }
+
+        let pane = GrepPane::new(self.settings.clone(), quickfix, self.channels.0.clone());
+        self.split_with_pane(Rc::new(RefCell::new(pane)));
+    }
+
+    /// Lists the problems with the settings, like bindings that can never be typed.
+    fn check_health(&mut self) {
+        let conflicts = self.settings.borrow().find_conflicts();
+        if conflicts.is_empty() {
+            self.show_message("No problems found".to_string(), MessageKind::Info);
+            return;
+        }
+
+        // Nothing needs to come back from picking a line
+        let (send, _) = mpsc::channel();
+        let (_, recv2) = mpsc::channel();
+
+        let mut buttons = Vec::new();
+        let mut width = "Binding Conflicts".len();
+        for conflict in conflicts.iter() {
+            let line = conflict.to_string();
+            width = width.max(line.chars().count());
+
+            let function: ButtonFunction = Box::new(|_| String::new());
+            buttons.push((line, function));
+        }
+
+        let buttons = PromptType::Button(buttons, 0);
+        let prompt = vec!["Binding Conflicts".to_string()];
+
+        let pane = PopUpPane::new_dropdown(
+            self.settings.clone(),
+            prompt,
+            self.channels.0.clone(),
+            send,
+            recv2,
+            buttons,
+            true
+        );
+        let pane = Rc::new(RefCell::new(pane));
+
+        let size = ((width + 2).min(self.size.0), (conflicts.len() + 3).min(self.size.1));
+        let pos = ((self.size.0 - size.0) / 2, (self.size.1 - size.1) / 2);
+
+        let mut container = PaneContainer::new(self.size, size, pane, self.settings.clone());
+        container.set_position(pos);
+        container.set_size(size);
+        container.set_move_not_resize(true);
+
+        self.create_popup(container, true);
+    }
+
+    /// Lists what every register holds, one register per line.
+    fn show_registers(&mut self, registers: Vec<(String, RegisterValue)>) {
+        if registers.is_empty() {
+            self.show_message("The registers are empty".to_string(), MessageKind::Info);
+            return;
+        }
+
+        let lines = registers.iter()
+            // Line breaks are shown like vim does so that each register stays on one line
+            .map(|(name, value)| format!("\"{}  {}", name, value.text.replace('\n', "^J")))
+            .collect();
+        self.show_list("Registers", lines);
+    }
+
+    /// Lists the bindings of a mode for `:maps`.
+    fn show_maps(&mut self, mode: &str) {
+        let result = self.settings.borrow().list_keys(mode);
+        match result {
+            Ok((_, lines)) if lines.is_empty() => {
+                self.show_message(format!("No mappings in {} mode", mode), MessageKind::Info);
+            },
+            Ok((name, lines)) => self.show_list(&format!("{} mappings", name), lines),
+            Err(error) => self.show_message(error, MessageKind::Error),
+        }
+    }
+
+    /// Shows lines in a popup that only has to be looked at.
+    fn show_list(&mut self, title: &str, lines: Vec<String>) {
+        // Nothing needs to come back from picking a line
+        let (send, _) = mpsc::channel();
+        let (_, recv2) = mpsc::channel();
+
+        let max_width = self.size.0.saturating_sub(2);
+        let mut buttons = Vec::new();
+        let mut width = title.chars().count();
+        for line in lines.iter() {
+            let line = line.chars().take(max_width).collect::<String>();
+            width = width.max(line.chars().count());
+
+            let function: ButtonFunction = Box::new(|_| String::new());
+            buttons.push((line, function));
+        }
+
+        let buttons = PromptType::Button(buttons, 0);
+        let prompt = vec![title.to_string()];
+
+        let pane = PopUpPane::new_dropdown(
+            self.settings.clone(),
+            prompt,
+            self.channels.0.clone(),
+            send,
+            recv2,
+            buttons,
+            true
+        );
+        let pane = Rc::new(RefCell::new(pane));
+
+        let size = ((width + 2).min(self.size.0), (lines.len() + 3).min(self.size.1));
+        let pos = ((self.size.0 - size.0) / 2, (self.size.1 - size.1) / 2);
+
+        let mut container = PaneContainer::new(self.size, size, pane, self.settings.clone());
+        container.set_position(pos);
+        container.set_size(size);
+        container.set_move_not_resize(true);
+
+        self.create_popup(container, true);
+    }
+
+    fn check_recent_files(&mut self) -> io::Result<()> {
+        let message = match &self.recent_receiver {
+            None => return Ok(()),
+            Some(receiver) => receiver.try_recv(),
+        };
+
+        match message {
+            Ok(PaneMessage::String(path)) => {
+                self.recent_receiver = None;
+                self.switch_pane(path, None)?;
+            },
+            Ok(PaneMessage::Close) | Ok(PaneMessage::Cancel) => {
+                self.recent_receiver = None;
+            },
+            Ok(PaneMessage::PreviewString(_)) => {},
+            Err(TryRecvError::Disconnected) => {
+                self.recent_receiver = None;
+            },
+            Err(TryRecvError::Empty) => {},
+        }
+        Ok(())
+    }
+
+    fn check_file_finder(&mut self) -> io::Result<()> {
+        let message = match &self.finder_receiver {
+            None => return Ok(()),
+            Some(receiver) => receiver.try_recv(),
+        };
+
+        match message {
+            Ok((path, open_in)) => {
+                self.finder_receiver = None;
+                match open_in {
+                    OpenIn::Current => {},
+                    OpenIn::VerticalSplit => self.vertical_split(),
+                    OpenIn::HorizontalSplit => self.horizontal_split(),
+                }
+                self.switch_pane(path, None)?;
+            },
+            Err(TryRecvError::Disconnected) => {
+                self.finder_receiver = None;
+            },
+            Err(TryRecvError::Empty) => {},
+        }
+        Ok(())
+    }
+
+    fn check_buffer_list(&mut self) -> io::Result<()> {
+        let message = match &self.buffer_receiver {
+            None => return Ok(()),
+            Some(receiver) => receiver.try_recv(),
+        };
+
+        match message {
+            Ok(PaneMessage::String(number)) => {
+                self.buffer_receiver = None;
+                if let Ok(number) = number.parse::<usize>() {
+                    self.show_buffer(number)?;
+                }
+            },
+            Ok(PaneMessage::Close) | Ok(PaneMessage::Cancel) => {
+                self.buffer_receiver = None;
+            },
+            Ok(PaneMessage::PreviewString(_)) => {},
+            Err(TryRecvError::Disconnected) => {
+                self.buffer_receiver = None;
+            },
+            Err(TryRecvError::Empty) => {},
+        }
+        Ok(())
+    }
+
+    /// Deletes the buffer with unsaved changes once the user answers the popup.
+    fn check_pending_delete(&mut self) {
+        let message = match &self.pending_delete {
+            None => return,
+            Some((receiver, _)) => receiver.try_recv(),
+        };
+
+        match message {
+            Ok(PaneMessage::String(answer)) => {
+                let (_, number) = self.pending_delete.take().expect("Pending delete was none");
+                if answer == "delete" {
+                    self.remove_buffer(number);
+                }
+            },
+            Ok(PaneMessage::Close) | Ok(PaneMessage::Cancel) | Err(TryRecvError::Disconnected) => {
+                self.pending_delete = None;
+            },
+            Ok(PaneMessage::PreviewString(_)) | Err(TryRecvError::Empty) => {},
+        }
+    }
+
+    /// Quits the way the user picked in the popup about unsaved changes.
+    fn check_pending_quit(&mut self) {
+        let message = match &self.pending_quit {
+            None => return,
+            Some(receiver) => receiver.try_recv(),
+        };
+
+        match message {
+            Ok(PaneMessage::String(answer)) => {
+                self.pending_quit = None;
+                match answer.as_str() {
+                    "save" => self.editor_sender.send(EditorMessage::SaveAllAndQuit).unwrap(),
+                    "discard" => self.editor_sender.send(EditorMessage::Quit).unwrap(),
+                    _ => {},
+                }
+            },
+            Ok(PaneMessage::Close) | Ok(PaneMessage::Cancel) | Err(TryRecvError::Disconnected) => {
+                self.pending_quit = None;
+            },
+            Ok(PaneMessage::PreviewString(_)) | Err(TryRecvError::Empty) => {},
+        }
+    }
+
+    /// Makes the edits to files open in a pane right away.
+    /// Files that aren't open are only written to after the user confirms it.
+    fn apply_edits(&mut self, changes: FileEdits, reply: EditReply) {
+        let mut unopened = Vec::new();
+        for (path, edits) in changes {
+            let pane = self.panes[0].iter()
+                .map(|container| container.get_pane())
+                .find(|pane| match pane.borrow().get_filename() {
+                    Some(file_name) => same_file(file_name, &path),
+                    None => false,
+                });
+
+            match pane {
+                Some(pane) => pane.borrow_mut().apply_text_edits(&edits),
+                None => unopened.push((path, edits)),
+            }
+        }
+
+        if unopened.is_empty() {
+            self.answer_edit(reply, true);
+            return;
+        }
+
+        let (send, recv) = mpsc::channel();
+        let (_, recv2) = mpsc::channel();
+
+        let mut prompt = vec![format!("Edit {} unopened files?", unopened.len())];
+        prompt.extend(unopened.iter().map(|(path, _)| path.to_string_lossy().to_string()));
+        let width = prompt.iter().map(|line| line.chars().count()).max().unwrap_or(0);
+
+        let apply: ButtonFunction = Box::new(|_| "apply".to_string());
+        let cancel: ButtonFunction = Box::new(|_| "cancel".to_string());
+        let buttons = PromptType::Button(vec![("Apply".to_string(), apply), ("Cancel".to_string(), cancel)], 0);
+
+        let pane = PopUpPane::new_dropdown(
+            self.settings.clone(),
+            prompt.clone(),
+            self.channels.0.clone(),
+            send,
+            recv2,
+            buttons,
+            true
+        );
+        let pane = Rc::new(RefCell::new(pane));
+
+        let size = ((width + 2).min(self.size.0), (prompt.len() + 4).min(self.size.1));
+        let pos = ((self.size.0 - size.0) / 2, (self.size.1 - size.1) / 2);
+
+        let mut container = PaneContainer::new(self.size, size, pane, self.settings.clone());
+        container.set_position(pos);
+        container.set_size(size);
+        container.set_move_not_resize(true);
+
+        self.create_popup(container, true);
+        self.pending_edits = Some((recv, unopened, reply));
+    }
+
+    /// Tells the server that asked for an edit whether it was made.
+    fn answer_edit(&self, reply: EditReply, applied: bool) {
+        if let Some((language, id)) = reply {
+            let _ = self.lsp_responder.send(ControllerMessage::Notification(language.into(), LspNotification::EditApplied(id, applied)));
+        }
+    }
+
+    /// Writes the edits to the files that aren't open once the user answers the popup.
+    fn check_pending_edits(&mut self) {
+        let message = match &self.pending_edits {
+            None => return,
+            Some((receiver, _, _)) => receiver.try_recv(),
+        };
+
+        match message {
+            Ok(PaneMessage::String(answer)) => {
+                let (_, changes, reply) = self.pending_edits.take().expect("Pending edits were none");
+                if answer != "apply" {
+                    self.answer_edit(reply, false);
+                    return;
+                }
+
+                let mut failed = Vec::new();
+                for (path, edits) in changes.iter() {
+                    let result = fs::read_to_string(path)
+                        .and_then(|text| fs::write(path, apply_text_edits(&text, edits)));
+                    if let Err(e) = result {
+                        failed.push(format!("{}: {}", path.display(), e));
+                    }
+                }
+
+                self.answer_edit(reply, failed.is_empty());
+                if failed.is_empty() {
+                    self.show_message(format!("Edited {} files", changes.len()), MessageKind::Info);
+                }
+                else {
+                    self.show_message(format!("Failed to edit {}", failed.join(", ")), MessageKind::Error);
+                }
+            },
+            Ok(PaneMessage::Close) | Ok(PaneMessage::Cancel) | Err(TryRecvError::Disconnected) => {
+                if let Some((_, _, reply)) = self.pending_edits.take() {
+                    self.answer_edit(reply, false);
+                }
+            },
+            Ok(PaneMessage::PreviewString(_)) | Err(TryRecvError::Empty) => {},
+        }
+    }
+
+    fn auto_save_delay(&self) -> Option<Duration> {
+        self.settings.borrow().editor_settings.auto_save.map(Duration::from_secs)
+    }
+
+    /// The panes with changes for auto-save to save, by the index of a container showing them.
+    /// With a `delay` the changes have to be that old, without one any changes will do.
+    /// Nothing is saved while a popup is open, or while typing in Insert mode unless `auto_save_in_insert` is set.
+    fn auto_save_targets(&self, delay: Option<Duration>) -> Vec<usize> {
+        if self.panes.iter().skip(1).any(|layer| !layer.is_empty()) {
+            return Vec::new();
+        }
+        let in_insert = self.settings.borrow().editor_settings.auto_save_in_insert;
+        let now = Instant::now();
+
+        let mut targets: Vec<usize> = Vec::new();
+        for (index, container) in self.panes[0].iter().enumerate() {
+            let pane = container.get_pane();
+            let due = {
+                let pane = pane.borrow();
+                let edited = match pane.last_edit() {
+                    None => continue,
+                    Some(edited) => edited,
+                };
+                delay.is_none_or(|delay| now.duration_since(edited) >= delay)
+                    && pane.get_filename().is_some()
+                    && (in_insert || !pane.is_inserting())
+            };
+            if due && !targets.iter().any(|target| Rc::ptr_eq(&self.panes[0][*target].get_pane(), &pane)) {
+                targets.push(index);
+            }
+        }
+        targets
+    }
+
+    /// Saves the panes auto-save is due for the same way `:w` does and says when it did.
+    fn auto_save(&mut self, delay: Option<Duration>) {
+        let mut saved = false;
+        for index in self.auto_save_targets(delay) {
+            let container = &self.panes[0][index];
+            let pane = container.get_pane();
+            saved |= pane.borrow_mut().auto_save(container);
+        }
+        if saved {
+            self.show_message(format!("autosaved {}", clock_time(SystemTime::now())), MessageKind::Info);
+        }
+    }
+
+    fn check_auto_save(&mut self) {
+        if let Some(delay) = self.auto_save_delay() {
+            self.auto_save(Some(delay));
+        }
+    }
+
+    /// Whether auto-save has something to save, so the window stops waiting for a key to do it.
+    fn auto_save_ready(&self) -> bool {
+        self.auto_save_delay().is_some_and(|delay| !self.auto_save_targets(Some(delay)).is_empty())
+    }
+
+    /// Asks the terminal for mouse events or gives the mouse back to it when the `mouse` setting changed.
+    fn check_mouse_capture(&mut self) -> io::Result<()> {
+        let mouse = self.settings.borrow().editor_settings.mouse;
+        if mouse == self.mouse_capture {
+            return Ok(());
+        }
+
+        self.mouse_capture = mouse;
+        if mouse {
+            execute!(io::stdout(), EnableMouseCapture)
+        }
+        else {
+            execute!(io::stdout(), DisableMouseCapture)
+        }
+    }
+
+    fn process_mouse(&mut self, mouse: MouseEvent) {
+        if !self.settings.borrow().editor_settings.mouse {
+            return;
+        }
+
+        let position = (mouse.column as usize, mouse.row as usize);
+        match mouse.kind {
+            MouseEventKind::Down(MouseButton::Left) => {
+                self.border_drag = self.border_at(position);
+                if self.border_drag.is_none() {
+                    self.click(position);
+                }
+            },
+            MouseEventKind::Drag(MouseButton::Left) => self.drag_border(position),
+            MouseEventKind::Up(MouseButton::Left) => self.drop_border(),
+            MouseEventKind::ScrollUp => self.scroll_at(position, -1),
+            MouseEventKind::ScrollDown => self.scroll_at(position, 1),
+            _ => {},
+        }
+    }
+
+    /// The pane under a cell of the window, leaving out popups.
+    fn pane_at(&self, position: (usize, usize)) -> Option<usize> {
+        if position.1 >= self.size.1 {
+            return None;
+        }
+        self.panes[0].iter().position(|container| container.contains(position))
+    }
+
+    /// The top layer that has a popup in it.
+    fn popup_layer(&self) -> Option<usize> {
+        (1..self.panes.len()).rev().find(|layer| !self.panes[*layer].is_empty())
+    }
+
+    /// Focuses the pane that was clicked and puts its cursor on the clicked text.
+    /// While a popup is open only clicks on it do anything.
+    fn click(&mut self, position: (usize, usize)) {
+        if let Some(layer) = self.popup_layer() {
+            if let Some(container) = self.panes[layer].iter_mut().find(|container| container.contains(position)) {
+                container.click(position);
+            }
+            return;
+        }
+
+        // Clicking away would leave the command being typed behind in the other pane
+        if self.panes[0][self.active_panes[0]].get_cursor().borrow().ignore_offset {
+            return;
+        }
+
+        let index = match self.pane_at(position) {
+            None => return,
+            Some(index) => index,
+        };
+        self.active_panes[0] = index;
+        self.panes[0][index].click(position);
+    }
+
+    /// The border drawn at the left or top of a pane under a cell of the window, if there is one.
+    fn border_at(&self, (col, row): (usize, usize)) -> Option<BorderDrag> {
+        if self.popup_layer().is_some() {
+            return None;
+        }
+
+        self.panes[0].iter()
+            .filter(|container| container.get_size() != (0, 0))
+            .find_map(|container| {
+                let ((x1, y1), (x2, y2)) = container.get_corners();
+                match container.border_offset() {
+                    (1, _) if col == x1 && y1 <= row && row <= y2 => Some(self.panes_along_border(true, x1, (y1, y2))),
+                    (_, 1) if row == y1 && x1 <= col && col <= x2 => Some(self.panes_along_border(false, y1, (x1, x2))),
+                    _ => None,
+                }
+            })
+    }
+
+    /// Finds the panes on both sides of a border, starting from the part of it that was grabbed.
+    /// Panes that share the border with those panes move with them so the panes keep fitting together.
+    fn panes_along_border(&self, vertical: bool, line: usize, span: (usize, usize)) -> BorderDrag {
+        let mut drag = BorderDrag { vertical, line, before: Vec::new(), after: Vec::new() };
+        let mut spans = vec![span];
+
+        loop {
+            let mut found = false;
+            for container in self.panes[0].iter().filter(|container| container.get_size() != (0, 0)) {
+                let ((x1, y1), (x2, y2)) = container.get_corners();
+                let (start, end, along) = if vertical { (x1, x2, (y1, y2)) } else { (y1, y2, (x1, x2)) };
+
+                let side = if start == line {
+                    &mut drag.after
+                }
+                else if end + 1 == line {
+                    &mut drag.before
+                }
+                else {
+                    continue;
+                };
+
+                let id = container.get_uuid();
+                if side.contains(&id) || !spans.iter().any(|(first, last)| along.0 <= *last && *first <= along.1) {
+                    continue;
+                }
+                side.push(id);
+                spans.push(along);
+                found = true;
+            }
+            if !found {
+                return drag;
+            }
+        }
+    }
+
+    /// Moves the border being dragged to the mouse, as far as the panes on each side can shrink.
+    fn drag_border(&mut self, (col, row): (usize, usize)) {
+        let drag = match &self.border_drag {
+            None => return,
+            Some(drag) => drag.clone(),
+        };
+
+        let minimum = {
+            let settings = self.settings.borrow();
+            if drag.vertical { settings.editor_settings.minimum_width } else { settings.editor_settings.minimum_height }
+        };
+        let length = |container: &PaneContainer| {
+            let (width, height) = container.get_size();
+            if drag.vertical { width } else { height }
+        };
+        let room = |ids: &[Uuid]| {
+            self.panes[0].iter()
+                .filter(|container| ids.contains(&container.get_uuid()))
+                .map(|container| length(container).saturating_sub(minimum))
+                .min()
+                .unwrap_or(0)
+        };
+
+        let target = if drag.vertical { col } else { row };
+        let delta = (target as isize - drag.line as isize).clamp(-(room(&drag.before) as isize), room(&drag.after) as isize);
+        if delta == 0 {
+            return;
+        }
+
+        let moved = |value: usize| (value as isize + delta) as usize;
+        for container in self.panes[0].iter_mut() {
+            let id = container.get_uuid();
+            let (mut position, mut size, max_size) = container.get_geometry();
+            if drag.before.contains(&id) {
+                if drag.vertical { size.0 = moved(size.0) } else { size.1 = moved(size.1) }
+            }
+            else if drag.after.contains(&id) {
+                if drag.vertical {
+                    position.0 = moved(position.0);
+                    size.0 = (size.0 as isize - delta) as usize;
+                }
+                else {
+                    position.1 = moved(position.1);
+                    size.1 = (size.1 as isize - delta) as usize;
+                }
+            }
+            else {
+                continue;
             }
+            container.set_geometry((position, size, max_size));
         }
 
-        match pane_index {
-            Some(index) => {
-                self.active_panes[self.active_layer] = index;
-            }
-            None => {}
+        if let Some(drag) = &mut self.border_drag {
+            drag.line = moved(drag.line);
         }
     }
 
-    fn read_messages(&mut self) -> io::Result<()> {
-        match self.channels.1.try_recv() {
-            Ok(message) => {
-                match message {
-                    Message::HorizontalSplit => {
-                        self.horizontal_split();
-                        Ok(())
-                    }
-                    Message::VerticalSplit => {
-                        self.vertical_split();
-                        Ok(())
-                    }
-                    Message::ForceQuitAll => {
-                        for layers in self.panes.iter_mut() {
-                            for pane in layers.iter_mut() {
-                                pane.close();
-                            }
-                        }
-                        self.editor_sender.send(EditorMessage::Quit).unwrap();
-                        Ok(())
-                    }
-                    Message::PaneUp => {
-                        self.pane_up();
-                        Ok(())
-                    },
-                    Message::PaneDown => {
-                        self.pane_down();
-                        Ok(())
-                    },
-                    Message::PaneLeft => {
-                        self.pane_left();
-                        Ok(())
-                    },
-                    Message::PaneRight => {
-                        self.pane_right();
-                        Ok(())
-                    },
-                    Message::OpenFile(path, pos) => {
-                        self.switch_pane(path, pos)?;
-                        self.force_refresh_screen()?;
-                        Ok(())
-                    }
-                    Message::ClosePane(go_down, uuid) => {
-
-                        match uuid {
-                            None => {
-                                //self.panes[self.active_layer].remove(self.active_panes[self.active_layer]);
-                                self.panes[self.active_layer][self.active_panes[self.active_layer]].close();
-                                self.active_panes[self.active_layer] = self.active_panes[self.active_layer].saturating_sub(1);
-
-                                if go_down {
-                                    self.active_layer = self.active_layer.saturating_sub(1);
-                                }
-                            },
-                            Some(uuid) => {
-                                let coords = self.id_to_pane.get(&uuid).unwrap();
+    /// Lets go of the border being dragged and redraws the panes around it.
+    fn drop_border(&mut self) {
+        let drag = match self.border_drag.take() {
+            None => return,
+            Some(drag) => drag,
+        };
 
-                                self.panes[coords.0][coords.1].close();
+        for container in self.panes[0].iter_mut() {
+            let id = container.get_uuid();
+            if drag.before.contains(&id) || drag.after.contains(&id) {
+                container.changed();
+            }
+        }
+    }
 
-                                if go_down {
-                                    self.active_layer = coords.0.saturating_sub(1);
-                                }
-                                self.force_refresh_screen()?;
-                            }
-                        }
+    /// Scrolls the pane under the mouse, or the active pane when the mouse isn't over one.
+    fn scroll_at(&mut self, position: (usize, usize), direction: isize) {
+        if self.popup_layer().is_some() {
+            return;
+        }
 
-                        for buffer in self.buffers.iter_mut() {
-                            buffer.hard_clear();
-                        }
-                        
-                        Ok(())
-                    },
-                    Message::CreatePopup(mut container, make_active) => {
-                        container.set_move_not_resize(true);
-                        self.create_popup(container, make_active);
-                        self.force_refresh_screen()?;
-                        Ok(())
-                    },
-                    Message::OpenNewTab => {
-                        self.editor_sender.send(EditorMessage::NewWindow(None)).unwrap();
-                        self.skip = true;
-                        Ok(())
-                    },
-                    Message::OpenNewTabWithPane => {
-                        let pane = self.panes[self.active_layer][self.active_panes[self.active_layer]].get_pane();
-                        self.panes[self.active_layer][self.active_panes[self.active_layer]].close();
+        let index = self.pane_at(position).unwrap_or(self.active_panes[0]);
+        let lines = self.settings.borrow().editor_settings.mouse_scroll as isize;
+        self.panes[0][index].scroll_view(direction * lines);
+    }
 
-                        self.active_panes[self.active_layer] = self.active_panes[self.active_layer].saturating_sub(1);
+    /// Scrolls the bound panes by as much as the active pane scrolled since last time.
+    /// We sync the change rather than the offset itself so that panes of different heights stay lined up.
+    fn sync_scroll_bind(&mut self) {
+        let active = &self.panes[0][self.active_panes[0]];
+        let id = active.get_uuid();
 
-                        self.editor_sender.send(EditorMessage::NewWindow(Some(pane))).unwrap();
-                        self.skip = true;
-                        Ok(())
-                    },
-                    Message::NextTab => {
-                        self.editor_sender.send(EditorMessage::NextWindow).unwrap();
-                        self.skip = true;
-                        Ok(())
-                    },
-                    Message::PreviousTab => {
-                        self.editor_sender.send(EditorMessage::PrevWindow).unwrap();
-                        self.skip = true;
-                        Ok(())
-                    },
-                    Message::NthTab(n) => {
-                        self.editor_sender.send(EditorMessage::NthWindow(n)).unwrap();
-                        self.skip = true;
-                        Ok(())
-                    },
-                    Message::PasteResponse(text) => {
-                        self.skip = true;
+        let last = match self.scroll_bound.get(&id) {
+            None => return,
+            Some(last) => *last,
+        };
+        let offset = active.get_cursor().borrow().row_offset;
+        if offset == last {
+            return;
+        }
+        let delta = offset as isize - last as isize;
+
+        for container in self.panes[0].iter_mut() {
+            let other = container.get_uuid();
+            let last = match self.scroll_bound.get_mut(&other) {
+                None => continue,
+                Some(last) => last,
+            };
+
+            if other != id {
+                let number_of_lines = container.get_pane().borrow().get_line_count();
+                container.get_cursor().borrow_mut().scroll_by(delta, number_of_lines);
+                container.changed();
+            }
 
-                        match text {
-                            None => {},
-                            Some(text) => {
-                                let command = format!("insert_text {}", text);
+            *last = container.get_cursor().borrow().row_offset;
+        }
+    }
 
-                                self.panes[self.active_layer]
-                                    [self.active_panes[self.active_layer]].execute_command(&command);
-                            }
-                        }
-                        self.force_refresh_screen()?;
-                        
-                        Ok(())
-                    },
-                    Message::Paste(ty) => {
-                        self.skip = true;
-                        self.editor_sender.send(EditorMessage::Paste(ty)).unwrap();
-                        Ok(())
-                    },
-                    Message::Copy(ty, string) => {
-                        self.skip = true;
-                        self.editor_sender.send(EditorMessage::Copy(ty, string)).unwrap();
-                        Ok(())
-                    },
-                    
-                }
+    /// Yanks and pastes without a register go to the system clipboard when `clipboard=unnamedplus` is set.
+    fn default_register(&self, ty: RegisterType) -> RegisterType {
+        match ty {
+            RegisterType::None if self.settings.borrow().editor_settings.clipboard == ClipboardSetting::UnnamedPlus => {
+                RegisterType::Clipboard
             },
-            Err(_) => Ok(()),
+            ty => ty,
         }
-        
     }
 
-    fn process_event(&mut self) -> io::Result<Event> {
+    /// Waits for the next event.
+    /// `None` means a pane is waiting on something and needs to be refreshed before there was an event.
+    fn process_event(&mut self) -> io::Result<Option<Event>> {
+        if let Some(key) = self.macro_keys.pop_front() {
+            self.macro_keys_played += 1;
+            return Ok(Some(Event::Key(key)));
+        }
+        self.macro_keys_played = 0;
+
         //self.refresh_screen()?;
+        let (which_key, delay) = {
+            let settings = self.settings.borrow();
+            (settings.editor_settings.which_key, settings.editor_settings.which_key_delay)
+        };
+        // Give the panes a chance to show keybinding hints if the user stops typing
+        if which_key && !event::poll(Duration::from_millis(delay))? {
+            self.refresh_screen()?;
+            self.read_messages()?;
+        }
         loop {
-            if event::poll(self.duration)? {
-                return event::read();
+            if event::poll(self.duration.min(WAITING_POLL))? {
+                return event::read().map(Some);
+            }
+            if self.panes_waiting() || self.auto_save_ready() {
+                return Ok(None);
             }
         }
     }
 
+    fn panes_waiting(&self) -> bool {
+        self.panes.iter()
+            .flatten()
+            .any(|container| container.get_pane().borrow().is_waiting())
+    }
+
 
     pub fn run(&mut self) -> io::Result<bool> {
         //eprintln!("Running");
         
         //self.refresh_screen()?;
         self.read_messages()?;
+        self.check_recent_files()?;
+        self.check_file_finder()?;
+        self.check_buffer_list()?;
+        self.check_pending_delete();
+        self.check_pending_quit();
+        self.check_pending_edits();
         self.remove_panes();
         if self.panes[0].len() == 0 {
             eprintln!("No panes left");
@@ -843,6 +2606,8 @@ impl Window {
             return Ok(false);
         }
 
+        self.check_auto_save();
+        self.check_mouse_capture()?;
         self.refresh_screen()?;
         let ((x1, y1), (x2, y2)) = self.panes[0][self.active_panes[0]].get_corners();
 
@@ -864,30 +2629,40 @@ impl Window {
         //eprintln!("Getting Event");
         let event = self.process_event()?;
         match event {
-            Event::Key(key) => {
+            Some(Event::Key(key)) => {
                 self.process_keypress(key)
             },
-            Event::Resize(width, height) => {
+            Some(Event::Resize(width, height)) => {
                 self.resize(width, height);
 
                 self.refresh_screen()?;
                 
                 Ok(true)
             }
+            Some(Event::Mouse(mouse)) => {
+                self.process_mouse(mouse);
+                Ok(true)
+            }
+            Some(Event::FocusLost) => {
+                if self.settings.borrow().editor_settings.auto_save_on_focus_lost {
+                    self.auto_save(None);
+                }
+                Ok(true)
+            }
             _ => {
                 Ok(true)},
         }
     }
 
     fn resize(&mut self, width: u16, height: u16) {
-        self.size = (width as usize, height as usize - 1);
+        self.size = (width as usize, (height as usize).saturating_sub(STATUS_ROWS));
         for pane in self.panes[self.active_layer].iter_mut() {
-            pane.resize((width as usize, height as usize));
+            pane.resize(self.size);
         }
         for buffer in self.buffers.iter_mut() {
-            buffer.resize((width as usize, height as usize - 1));
+            buffer.resize(self.size);
         }
-        self.compositor.resize((width as usize, height as usize - 1));
+        self.compositor.resize(self.size);
     }
 
     pub fn clear_screen() -> io::Result<()> {
@@ -997,6 +2772,10 @@ impl Window {
         let color_settings = &settings.colors.bar;
 
         let (name, first, second) = self.panes[0][self.active_panes[0]].get_status();
+        let first = match &self.recording {
+            Some((name, _)) => format!("{} recording @{}", first, name),
+            None => first,
+        };
         let total = name.len() + 1 + first.len() + second.len();// plus one for the space
 
         let mode_color = &settings.colors.mode.get(&name).unwrap_or(&color_settings);
@@ -1016,6 +2795,41 @@ impl Window {
         self.contents.push_str(apply_colors!(second, color_settings));
     }
 
+    fn show_message(&mut self, message: String, kind: MessageKind) {
+        self.message = Some((message, kind, Instant::now()));
+    }
+
+    /// Draws the line under the status bar that holds the latest message.
+    fn draw_message_line(&mut self) {
+        let timeout = Duration::from_millis(self.settings.borrow().editor_settings.message_timeout);
+        if let Some((_, _, shown)) = &self.message {
+            if shown.elapsed() >= timeout {
+                self.message = None;
+            }
+        }
+
+        self.contents.push_str("\r\n");
+        queue!(
+            self.contents,
+            terminal::Clear(ClearType::UntilNewLine),
+        ).unwrap();
+
+        let (message, kind) = match &self.message {
+            None => return,
+            Some((message, kind, _)) => (message, kind),
+        };
+
+        let settings = self.settings.borrow();
+        let color_settings = match kind {
+            MessageKind::Info => &settings.colors.message,
+            MessageKind::Warning => &settings.colors.warning,
+            MessageKind::Error => &settings.colors.error,
+        };
+
+        let message = message.chars().take(self.size.0).collect::<String>();
+        self.contents.push_str(apply_colors!(message, color_settings));
+    }
+
     pub fn force_refresh_screen(&mut self) -> io::Result<()> {
         //Self::clear_screen()?;
         for layer in self.panes.iter_mut() {
@@ -1055,6 +2869,8 @@ impl Window {
             }
         }//[self.active_panes[self.active_layer]].scroll_cursor();
 
+        self.sync_scroll_bind();
+
         queue!(
             self.contents,
             cursor::Hide,
@@ -1065,14 +2881,16 @@ impl Window {
         self.draw_rows();
         //eprintln!("drawing status bar");
         self.draw_status_bar();
+        self.draw_message_line();
 
         let cursor = self.panes[0][self.active_panes[self.active_layer]].get_cursor();
         let cursor = cursor.borrow();
 
         let (x, y) = cursor.get_real_cursor();
         //eprintln!("x: {} y: {}", x, y);
-        let x = x + self.panes[0][self.active_panes[self.active_layer]].get_position().0;
-        let y = y + self.panes[0][self.active_panes[self.active_layer]].get_position().1;
+        let (border_x, border_y) = self.panes[0][self.active_panes[self.active_layer]].border_offset();
+        let x = x + self.panes[0][self.active_panes[self.active_layer]].get_position().0 + border_x;
+        let y = y + self.panes[0][self.active_panes[self.active_layer]].get_position().1 + border_y;
         //eprintln!("x: {} y: {}", x, y);
 
         
@@ -1097,16 +2915,44 @@ impl Window {
         self.contents.flush()
     }
 
-    pub fn open_file_start(&mut self, filename: &str) -> io::Result<()> {
-        let pane = self.file_opener(filename.into())?;
+    pub fn process_keypress(&mut self, key: KeyEvent) -> io::Result<bool> {
+        self.message = None;
+
+        // Keys played back from a macro don't get recorded again
+        if self.macro_keys_played == 0 {
+            if let Some((_, keys)) = &mut self.recording {
+                keys.push(key);
+            }
+        }
 
-        self.panes[0][self.active_panes[0]].change_pane(pane);
+        if let Some(pending) = self.macro_pending.take() {
+            let name = match key.code {
+                KeyCode::Char(c) => c,
+                _ => return Ok(true),
+            };
 
-        Ok(())
-        //self.panes[self.active_layer][self.active_panes[self.active_layer]].open_file(&PathBuf::from(filename.to_owned()))
-    }
+            match pending {
+                MacroPending::Record => {
+                    self.recording = Some((name, Vec::new()));
+                },
+                MacroPending::Play(count) => {
+                    let name = if name == '@' {
+                        match self.last_macro {
+                            None => return Ok(true),
+                            Some(name) => name,
+                        }
+                    }
+                    else {
+                        name
+                    };
+                    self.last_macro = Some(name);
+
+                    self.editor_sender.send(EditorMessage::GetMacro(name, count.max(1))).unwrap();
+                },
+            }
+            return Ok(true);
+        }
 
-    pub fn process_keypress(&mut self, key: KeyEvent) -> io::Result<bool> {
         self.panes[self.active_layer][self.active_panes[self.active_layer]].process_keypress(key)
     }
 
@@ -1189,6 +3035,29 @@ impl TextRow {
         self.index += 1;
     }
 
+    /// Changes the background of a character that was already pushed.
+    pub fn set_background(&mut self, index: usize, color: Color) {
+        let chr = match self.contents.get(index) {
+            None => return,
+            Some(chr) => chr.borrow().clone(),
+        };
+
+        if let Some(mut chr) = chr {
+            chr.color.background_color = color;
+            chr.changed = true;
+            self.contents[index] = Rc::new(RefCell::new(Some(chr)));
+            self.changed = true;
+        }
+    }
+
+    /// Replaces a character that was already pushed.
+    pub fn set_char(&mut self, index: usize, chr: char, color: ColorScheme) {
+        if index < self.contents.len() {
+            self.contents[index] = Rc::new(RefCell::new(Some(StyledChar::new(chr, color))));
+            self.changed = true;
+        }
+    }
+
     pub fn extend(&mut self, mut other: Vec<Option<StyledChar>>) {
         let mut index = 0;
         while index < self.contents.len() {
@@ -1530,3 +3399,259 @@ impl io::Write for WindowContents {
 }
 
 
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::editor::parse_file_argument;
+
+    /// A window with the other ends of its channels to the editor and the language servers.
+    /// Nothing answers for the controller, so asking for a language server gets no answer.
+    fn test_window() -> (Window, Receiver<EditorMessage>, Receiver<ControllerMessage>) {
+        let (editor_sender, editor_receiver) = mpsc::channel();
+        let (lsp_sender, lsp_receiver) = mpsc::channel();
+        let (_, lsp_listener) = mpsc::channel();
+        let window = Window::new(editor_sender, lsp_sender, Rc::new(lsp_listener));
+        (window, editor_receiver, lsp_receiver)
+    }
+
+    /// A file in the temp directory that no other test or test run uses.
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("vi_{}_{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn test_startup_file_has_treesitter() {
+        let path = temp_path("startup_test.js");
+        fs::write(&path, "let x = 1;\n").unwrap();
+
+        let (mut window, _editor_receiver, _lsp_receiver) = test_window();
+
+        // This is what the editor sends for a file given on the command line
+        let (filename, position) = parse_file_argument(&format!("{}:1:5", path.display()));
+        window.get_sender().send(Message::OpenFileStart(filename, position)).unwrap();
+        window.read_messages().unwrap();
+
+        assert_eq!(window.panes[0].len(), 1);
+        let pane = window.panes[0][window.active_panes[0]].get_pane();
+        assert_eq!(pane.borrow().get_language(), Some("javascript"));
+        assert_eq!(pane.borrow().get_cursor().borrow().get_cursor(), (4, 0));
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_quit_startup_file() {
+        let path = temp_path("startup_quit_test.txt");
+        fs::write(&path, "one\n").unwrap();
+
+        let (mut window, _editor_receiver, _lsp_receiver) = test_window();
+
+        let sender = window.get_sender();
+        sender.send(Message::OpenFileStart(path.to_string_lossy().to_string(), None)).unwrap();
+        window.read_messages().unwrap();
+        assert_eq!(window.panes[0][window.active_panes[0]].get_filename(), Some(path.clone()));
+
+        // No empty pane is left behind to keep the editor open
+        sender.send(Message::ClosePane(false, None)).unwrap();
+        window.read_messages().unwrap();
+        window.remove_panes();
+        assert!(window.panes[0].is_empty());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_restart_attaches_panes_without_a_server() {
+        let path = temp_path("restart_lsp_test.rs");
+        fs::write(&path, "fn main() {}\n").unwrap();
+
+        let (mut window, _editor_receiver, lsp_receiver) = test_window();
+        let (controller, lsp_listener) = mpsc::channel();
+        window.lsp_listener = Rc::new(lsp_listener);
+
+        // The server isn't there when the file is opened
+        controller.send(ControllerMessage::NoClient(None)).unwrap();
+        window.open_file(path.clone()).unwrap();
+        lsp_receiver.try_iter().for_each(drop);
+
+        let (_, responses) = mpsc::channel();
+        let info = SharedInfo::default();
+        controller.send(ControllerMessage::ClientCreated(Arc::new(Mutex::new(responses)), info)).unwrap();
+        window.restart_lsp("rust");
+
+        let opened = lsp_receiver.try_iter().any(|message| matches!(message,
+            ControllerMessage::Notification(language, LspNotification::Open(..)) if &*language == "rust"));
+        assert!(opened);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_switch_buffers() {
+        let first = temp_path("buffer_test_first.txt");
+        let second = temp_path("buffer_test_second.txt");
+        fs::write(&first, "one\n").unwrap();
+        fs::write(&second, "two\nthree\n").unwrap();
+
+        let (mut window, _editor_receiver, _lsp_receiver) = test_window();
+
+        let sender = window.get_sender();
+        sender.send(Message::OpenFile(first.to_string_lossy().to_string(), None)).unwrap();
+        window.read_messages().unwrap();
+        sender.send(Message::OpenFile(second.to_string_lossy().to_string(), Some((0, 1)))).unwrap();
+        window.read_messages().unwrap();
+        assert_eq!(window.buffer_list.entries().len(), 2);
+
+        sender.send(Message::SwitchBuffer("test_first".to_string())).unwrap();
+        window.read_messages().unwrap();
+        assert_eq!(window.panes[0][window.active_panes[0]].get_filename(), Some(first.clone()));
+
+        // Going back keeps the cursor where it was
+        sender.send(Message::AlternateBuffer).unwrap();
+        window.read_messages().unwrap();
+        assert_eq!(window.panes[0][window.active_panes[0]].get_filename(), Some(second.clone()));
+        assert_eq!(window.panes[0][window.active_panes[0]].get_cursor_location(), (0, 1));
+
+        fs::remove_file(&first).unwrap();
+        fs::remove_file(&second).unwrap();
+    }
+
+    #[test]
+    fn test_save_all_modified() {
+        let path = temp_path("save_all_test.txt");
+        fs::write(&path, "one\n").unwrap();
+
+        let (mut window, _editor_receiver, _lsp_receiver) = test_window();
+
+        window.get_sender().send(Message::OpenFile(path.to_string_lossy().to_string(), None)).unwrap();
+        window.read_messages().unwrap();
+        assert!(window.modified_files().is_empty());
+
+        let pane = window.panes[0][window.active_panes[0]].get_pane();
+        pane.borrow_mut().insert_char('x');
+        assert_eq!(window.modified_files(), vec![path.to_string_lossy().to_string()]);
+
+        assert!(window.save_all().is_empty());
+        assert!(window.modified_files().is_empty());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "xone\n");
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_apply_settings() {
+        let (mut window, _editor_receiver, _lsp_receiver) = test_window();
+
+        let mut settings = Settings::default();
+        settings.editor_settings.tab_size = 3;
+        settings.editor_settings.key_timeout = 42;
+        window.apply_settings(settings);
+
+        let pane = window.panes[0][window.active_panes[0]].get_pane();
+        assert_eq!(pane.borrow().get_settings().borrow().editor_settings.tab_size, 3);
+        assert_eq!(window.duration, Duration::from_millis(42));
+    }
+
+    #[test]
+    fn test_auto_save() {
+        let path = temp_path("auto_save_test.txt");
+        fs::write(&path, "one\n").unwrap();
+
+        let (mut window, _editor_receiver, _lsp_receiver) = test_window();
+        window.settings.borrow_mut().editor_settings.auto_save = Some(0);
+
+        window.get_sender().send(Message::OpenFile(path.to_string_lossy().to_string(), None)).unwrap();
+        window.read_messages().unwrap();
+        let active = window.active_panes[0];
+        let pane = window.panes[0][active].get_pane();
+        pane.borrow_mut().insert_char('x');
+        assert!(!window.auto_save_ready());
+
+        // The pane notices the change when it is refreshed
+        window.panes[0][active].refresh();
+        assert!(window.auto_save_ready());
+        window.check_auto_save();
+        assert!(window.modified_files().is_empty());
+        assert!(!window.auto_save_ready());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "xone\n");
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_mouse() {
+        let path = temp_path("mouse_test.txt");
+        let text = (0..100).map(|i| format!("line {}\n", i)).collect::<String>();
+        fs::write(&path, text).unwrap();
+
+        let (mut window, _editor_receiver, _lsp_receiver) = test_window();
+        window.settings.borrow_mut().editor_settings.line_number = false;
+
+        window.get_sender().send(Message::OpenFile(path.to_string_lossy().to_string(), None)).unwrap();
+        window.read_messages().unwrap();
+        window.vertical_split();
+        window.active_panes[0] = 0;
+
+        // Clicking the right pane focuses it and goes past its border
+        let ((x, y), _) = window.panes[0][1].get_corners();
+        let (border_x, _) = window.panes[0][1].border_offset();
+        window.click((x + border_x + 3, y + 2));
+        assert_eq!(window.active_panes[0], 1);
+        assert_eq!(window.panes[0][1].get_cursor_location(), (3, 2));
+
+        window.click((1, 4));
+        assert_eq!(window.active_panes[0], 0);
+        assert_eq!(window.panes[0][0].get_cursor_location(), (1, 4));
+
+        // The wheel only takes the cursor along once it would scroll off
+        window.settings.borrow_mut().editor_settings.mouse_scroll = 3;
+        window.scroll_at((1, 1), 1);
+        assert_eq!(window.panes[0][0].get_cursor().borrow().row_offset, 3);
+        assert_eq!(window.panes[0][0].get_cursor_location(), (1, 4));
+        window.scroll_at((1, 1), 2);
+        assert_eq!(window.panes[0][0].get_cursor().borrow().row_offset, 9);
+        assert_eq!(window.panes[0][0].get_cursor_location(), (1, 9));
+        window.scroll_at((1, 1), -3);
+        assert_eq!(window.panes[0][0].get_cursor().borrow().row_offset, 0);
+        assert_eq!(window.panes[0][0].get_cursor_location(), (1, 9));
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_drag_border() {
+        let (mut window, _editor_receiver, _lsp_receiver) = test_window();
+        window.settings.borrow_mut().editor_settings.minimum_width = 5;
+        window.vertical_split();
+
+        let mouse = |kind, (column, row): (usize, usize)| MouseEvent {
+            kind,
+            column: column as u16,
+            row: row as u16,
+            modifiers: event::KeyModifiers::NONE,
+        };
+        let ((line, y), (right_end, _)) = window.panes[0][1].get_corners();
+        let left_width = window.panes[0][0].get_size().0;
+
+        window.process_mouse(mouse(MouseEventKind::Down(MouseButton::Left), (line, y + 2)));
+        assert!(window.border_drag.is_some());
+
+        window.process_mouse(mouse(MouseEventKind::Drag(MouseButton::Left), (line - 3, y + 2)));
+        assert_eq!(window.panes[0][0].get_size().0, left_width - 3);
+        assert_eq!(window.panes[0][1].get_corners().0, (line - 3, y));
+        assert_eq!(window.panes[0][1].get_corners().1.0, right_end);
+
+        // The left pane stops at the minimum width
+        window.process_mouse(mouse(MouseEventKind::Drag(MouseButton::Left), (0, y + 2)));
+        assert_eq!(window.panes[0][0].get_size().0, 5);
+        assert_eq!(window.panes[0][0].get_corners().1.0 + 1, window.panes[0][1].get_corners().0.0);
+        assert_eq!(window.panes[0][1].get_corners().1.0, right_end);
+
+        window.process_mouse(mouse(MouseEventKind::Up(MouseButton::Left), (0, y + 2)));
+        assert!(window.border_drag.is_none());
+        assert_eq!(window.active_panes[0], 1);
+    }
+}
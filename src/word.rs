@@ -0,0 +1,104 @@
+use crate::definition::is_identifier_char;
+
+
+/// Words are runs of characters of the same class.
+/// Identifiers and punctuation are separate words even when they touch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CharClass {
+    Blank,
+    Identifier,
+    Punctuation,
+}
+
+fn class(c: char) -> CharClass {
+    if c.is_whitespace() {
+        CharClass::Blank
+    }
+    else if is_identifier_char(c) {
+        CharClass::Identifier
+    }
+    else {
+        CharClass::Punctuation
+    }
+}
+
+fn line_chars(line: &str) -> Vec<char> {
+    line.trim_end_matches(['\n', '\r']).chars().collect()
+}
+
+/// Returns the column after the run of characters of the same class that the column is in.
+fn run_end(chars: &[char], col: usize) -> usize {
+    let start = class(chars[col]);
+    col + chars[col..].iter().take_while(|c| class(**c) == start).count()
+}
+
+/// Returns the column the next word starts at, which is where `dw` deletes to.
+/// The end of the line is used when there is no word after the cursor.
+pub fn next_word_start(line: &str, col: usize) -> usize {
+    let chars = line_chars(line);
+    if col >= chars.len() {
+        return chars.len();
+    }
+
+    let col = match class(chars[col]) {
+        CharClass::Blank => col,
+        _ => run_end(&chars, col),
+    };
+    col + chars[col..].iter().take_while(|c| class(**c) == CharClass::Blank).count()
+}
+
+/// Returns the column after the end of the word under the cursor.
+/// This is what `cw` changes, which unlike `dw` leaves the blanks after the word alone.
+pub fn word_end(line: &str, col: usize) -> usize {
+    let chars = line_chars(line);
+    if col >= chars.len() {
+        return chars.len();
+    }
+
+    run_end(&chars, col)
+}
+
+/// Returns the columns of the word under the cursor, the end being exclusive.
+/// Like vim's `iw` a run of blanks counts as a word too.
+pub fn inner_word(line: &str, col: usize) -> Option<(usize, usize)> {
+    let chars = line_chars(line);
+    let current = class(*chars.get(col)?);
+
+    let start = col - chars[..col].iter().rev().take_while(|c| class(**c) == current).count();
+    Some((start, run_end(&chars, col)))
+}
+
+/// Returns the column of the next word after the cursor for `w`, if it is on the same line.
+pub fn next_word(line: &str, col: usize) -> Option<usize> {
+    let col = next_word_start(line, col);
+    (col < line_chars(line).len()).then_some(col)
+}
+
+/// Returns the column of the first word of a line, which is where `w` goes from the end of the line above.
+pub fn first_word(line: &str) -> Option<usize> {
+    let chars = line_chars(line);
+    chars.iter().position(|c| class(*c) != CharClass::Blank)
+}
+
+/// Returns the column the word before the cursor starts at for `b`, if it is on the same line.
+pub fn previous_word(line: &str, col: usize) -> Option<usize> {
+    let chars = line_chars(line);
+    let col = col.min(chars.len());
+
+    let end = col - chars[..col].iter().rev().take_while(|c| class(**c) == CharClass::Blank).count();
+    let current = class(*chars.get(end.checked_sub(1)?)?);
+    Some(end - chars[..end].iter().rev().take_while(|c| class(**c) == current).count())
+}
+
+/// Returns the column of the last character of the next word that ends after the cursor for `e`.
+/// `None` starts looking from the start of the line, which is where `e` goes on from the line above.
+pub fn next_word_end(line: &str, col: Option<usize>) -> Option<usize> {
+    let chars = line_chars(line);
+    let start = col.map_or(0, |col| col + 1);
+    let start = start + chars.get(start..)?.iter().take_while(|c| class(**c) == CharClass::Blank).count();
+    if start >= chars.len() {
+        return None;
+    }
+
+    Some(run_end(&chars, start) - 1)
+}